@@ -1,29 +1,62 @@
 //! Core types and encoding operations used by the age file format.
 
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+use hmac::{Hmac, Mac};
+use once_cell::race::OnceBox;
+use secrecy::{zeroize::Zeroize, ExposeSecret};
+use sha2::Sha256;
+
+#[cfg(feature = "std")]
 use rand::{
     distributions::{Distribution, Uniform},
     thread_rng, RngCore,
 };
-use secrecy::{ExposeSecret, Secret};
+
+use crate::{primitives::hkdf, secret::SecretBuf};
+
+/// The prefix of the intro line of an age file, which is followed by the version.
+const AGE_INTRO_PREFIX: &str = "age-encryption.org/";
 
 /// The prefix identifying an age stanza.
 const STANZA_TAG: &str = "-> ";
 
+/// The version string of the age v1 format.
+const V1_VERSION: &str = "v1";
+
+/// The prefix of the MAC line of an age v1 header.
+const MAC_TAG: &str = "---";
+
+/// The label used to derive the header MAC key from the file key.
+const HEADER_KEY_LABEL: &[u8] = b"header";
+
 /// The length of an age file key.
 pub const FILE_KEY_BYTES: usize = 16;
 
 /// A file key for encrypting or decrypting an age file.
-pub struct FileKey(Secret<[u8; FILE_KEY_BYTES]>);
+///
+/// The key is held in a [`SecretBuf`], so it is locked into memory with the `mlock`
+/// feature flag.
+pub struct FileKey(SecretBuf);
 
 impl From<[u8; FILE_KEY_BYTES]> for FileKey {
-    fn from(file_key: [u8; FILE_KEY_BYTES]) -> Self {
-        FileKey(Secret::new(file_key))
+    fn from(mut file_key: [u8; FILE_KEY_BYTES]) -> Self {
+        let key = FileKey(SecretBuf::from_slice(&file_key));
+        file_key.zeroize();
+        key
     }
 }
 
 impl ExposeSecret<[u8; FILE_KEY_BYTES]> for FileKey {
     fn expose_secret(&self) -> &[u8; FILE_KEY_BYTES] {
-        self.0.expose_secret()
+        self.0[..]
+            .try_into()
+            .expect("file keys have the correct length")
     }
 }
 
@@ -48,6 +81,11 @@ pub struct AgeStanza<'a> {
 impl<'a> AgeStanza<'a> {
     /// Decodes and returns the body of this stanza.
     pub fn body(&self) -> Vec<u8> {
+        decode_body(&self.encoded_body())
+    }
+
+    /// Returns the Base64 encoding of the body of this stanza, without line breaks.
+    fn encoded_body(&self) -> Vec<u8> {
         // An AgeStanza will always contain at least one chunk.
         let (partial_chunk, full_chunks) = self.body.split_last().unwrap();
 
@@ -58,41 +96,189 @@ impl<'a> AgeStanza<'a> {
             data[i * 64..(i + 1) * 64].copy_from_slice(chunk);
         }
         data[full_chunks.len() * 64..].copy_from_slice(partial_chunk);
-
-        // The chunks are guaranteed to contain Base64 characters by construction.
-        base64::decode_config(&data, base64::STANDARD_NO_PAD).unwrap()
+        data
     }
 }
 
+/// Decodes a stanza body returned by [`AgeStanza::encoded_body`].
+fn decode_body(encoded: &[u8]) -> Vec<u8> {
+    // The chunks are guaranteed to contain canonical Base64 by construction.
+    base64::decode_config(encoded, base64::STANDARD_NO_PAD).unwrap()
+}
+
 /// A section of the age header that encapsulates the file key as encrypted to a specific
 /// recipient.
 ///
 /// This is the owned type; see [`AgeStanza`] for the reference type.
-#[derive(Debug, PartialEq)]
+///
+/// The body of a stanza parsed from an age file is only decoded from Base64 when it is
+/// first accessed with [`Stanza::body`], so stanzas that no identity tries to unwrap
+/// never pay the cost of decoding.
 pub struct Stanza {
     /// A tag identifying this stanza type.
     pub tag: String,
     /// Zero or more arguments.
     pub args: Vec<String>,
-    /// The body of the stanza, containing a wrapped [`FileKey`].
-    pub body: Vec<u8>,
+    /// The Base64 encoding of the body, if this stanza was parsed.
+    encoded_body: Option<Vec<u8>>,
+    /// The body, decoded from `encoded_body` on first access.
+    body: OnceBox<Vec<u8>>,
+}
+
+impl Stanza {
+    /// Constructs a stanza with the given tag, arguments, and body.
+    pub fn new(tag: String, args: Vec<String>, body: Vec<u8>) -> Self {
+        let decoded = OnceBox::new();
+        decoded
+            .set(Box::new(body))
+            .expect("OnceBox was just created");
+        Stanza {
+            tag,
+            args,
+            encoded_body: None,
+            body: decoded,
+        }
+    }
+
+    /// Returns the body of the stanza, containing a wrapped [`FileKey`].
+    pub fn body(&self) -> &[u8] {
+        self.body.get_or_init(|| {
+            Box::new(decode_body(
+                self.encoded_body
+                    .as_ref()
+                    .expect("Stanza bodies are either decoded or encoded"),
+            ))
+        })
+    }
+
+    /// Returns the length of the body of the stanza, without decoding it.
+    pub fn body_len(&self) -> usize {
+        match (self.body.get(), &self.encoded_body) {
+            (Some(body), _) => body.len(),
+            // Canonical unpadded Base64 encodes every 6 bits as a character, and has
+            // fewer than 8 trailing bits.
+            (None, Some(encoded)) => encoded.len() * 6 / 8,
+            (None, None) => unreachable!("Stanza bodies are either decoded or encoded"),
+        }
+    }
+
+    /// Consumes the stanza, returning its body.
+    pub fn into_body(self) -> Vec<u8> {
+        match (self.body.get(), self.encoded_body) {
+            (Some(body), _) => body.clone(),
+            (None, Some(encoded)) => decode_body(&encoded),
+            (None, None) => unreachable!("Stanza bodies are either decoded or encoded"),
+        }
+    }
+}
+
+impl fmt::Debug for Stanza {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stanza")
+            .field("tag", &self.tag)
+            .field("args", &self.args)
+            .field("body", &self.body())
+            .finish()
+    }
+}
+
+impl PartialEq for Stanza {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag == other.tag && self.args == other.args && self.body() == other.body()
+    }
+}
+
+/// Generates valid stanzas, with arbitrary tags, arguments, and bodies.
+#[cfg(feature = "fuzzing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fuzzing")))]
+impl<'a> arbitrary::Arbitrary<'a> for Stanza {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let tag = arbitrary_vchar_string(u)?;
+        let args = (0..u.int_in_range(0..=4)?)
+            .map(|_| arbitrary_vchar_string(u))
+            .collect::<arbitrary::Result<_>>()?;
+        let body = u.arbitrary()?;
+        Ok(Stanza::new(tag, args, body))
+    }
+}
+
+/// Generates a non-empty string of visible ASCII characters, which is valid as a stanza
+/// tag or argument, or as the version in an age file's intro line.
+#[cfg(feature = "fuzzing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fuzzing")))]
+pub fn arbitrary_vchar_string(u: &mut arbitrary::Unstructured<'_>) -> arbitrary::Result<String> {
+    (0..u.int_in_range(1..=16)?)
+        .map(|_| u.int_in_range(33..=126).map(char::from))
+        .collect()
 }
 
 impl From<AgeStanza<'_>> for Stanza {
     fn from(stanza: AgeStanza<'_>) -> Self {
-        let body = stanza.body();
         Stanza {
             tag: stanza.tag.to_string(),
-            args: stanza.args.into_iter().map(|s| s.to_string()).collect(),
-            body,
+            args: stanza.args.iter().map(|s| s.to_string()).collect(),
+            encoded_body: Some(stanza.encoded_body()),
+            body: OnceBox::new(),
         }
     }
 }
 
+/// The header of an age v1 file.
+///
+/// This is the type returned by [`read::age_header_v1`]. Parsing a header and verifying
+/// its MAC only require the `alloc` crate, so age headers can be handled in
+/// environments without `std`.
+#[derive(Debug)]
+pub struct AgeHeaderV1<'a> {
+    /// The recipient stanzas.
+    pub stanzas: Vec<AgeStanza<'a>>,
+    /// The header MAC.
+    pub mac: [u8; 32],
+    /// The encoded header, up to and including the `---` of the MAC line.
+    mac_input: &'a [u8],
+}
+
+impl<'a> AgeHeaderV1<'a> {
+    /// Returns the bytes of the encoded header that are covered by the MAC.
+    ///
+    /// These are taken from the parsed input rather than re-encoded, so they are correct
+    /// even for headers containing stanzas with the legacy body encoding.
+    pub fn mac_input(&self) -> &'a [u8] {
+        self.mac_input
+    }
+
+    /// Verifies the header MAC with the given file key, in constant time.
+    pub fn verify_mac(&self, file_key: &FileKey) -> bool {
+        header_v1_hmac(file_key, self.mac_input)
+            .verify_slice(&self.mac)
+            .is_ok()
+    }
+}
+
+/// Computes the MAC of an age v1 header.
+///
+/// `mac_input` is the encoded header up to and including the `---` that begins the MAC
+/// line, as written by [`write::age_header_v1_mac_input`].
+pub fn header_v1_mac(file_key: &FileKey, mac_input: &[u8]) -> [u8; 32] {
+    header_v1_hmac(file_key, mac_input)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+fn header_v1_hmac(file_key: &FileKey, mac_input: &[u8]) -> Hmac<Sha256> {
+    let mac_key = hkdf(&[], HEADER_KEY_LABEL, file_key.expose_secret());
+    let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key).expect("key is the correct length");
+    mac.update(mac_input);
+    mac
+}
+
 /// Creates a random recipient stanza that exercises the joint in the age v1 format.
 ///
 /// This function is guaranteed to return a valid stanza, but makes no other guarantees
 /// about the stanza's fields.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
 pub fn grease_the_joint() -> Stanza {
     // Generate arbitrary strings between 1 and 9 characters long.
     fn gen_arbitrary_string<R: RngCore>(rng: &mut R) -> String {
@@ -125,22 +311,26 @@ pub fn grease_the_joint() -> Stanza {
     let mut body = vec![0; Uniform::from(0..100).sample(&mut rng)];
     rng.fill_bytes(&mut body);
 
-    Stanza { tag, args, body }
+    Stanza::new(tag, args, body)
 }
 
 /// Decoding operations for age types.
 pub mod read {
+    use alloc::{vec, vec::Vec};
     use nom::{
         branch::alt,
-        bytes::streaming::{tag, take_while1, take_while_m_n},
+        bytes::streaming::{tag, take, take_while1, take_while_m_n},
         character::streaming::newline,
-        combinator::{map, map_opt, opt, verify},
-        multi::{many_till, separated_list1},
-        sequence::{pair, preceded, terminated},
+        combinator::{consumed, map, map_opt, opt, verify},
+        multi::{many1, many_till, separated_list1},
+        sequence::{pair, preceded, terminated, tuple},
         IResult,
     };
 
-    use super::{AgeStanza, STANZA_TAG};
+    use super::{AgeHeaderV1, AgeStanza, AGE_INTRO_PREFIX, MAC_TAG, STANZA_TAG, V1_VERSION};
+
+    /// The length of the Base64-encoded header MAC.
+    const ENCODED_MAC_LENGTH: usize = 43;
 
     fn is_base64_char(c: u8) -> bool {
         // Check against the ASCII values of the standard Base64 character set.
@@ -184,10 +374,22 @@ pub mod read {
     pub fn arbitrary_string(input: &[u8]) -> IResult<&[u8], &str> {
         map(take_while1(|c| (33..=126).contains(&c)), |bytes| {
             // Safety: ASCII bytes are valid UTF-8
-            unsafe { std::str::from_utf8_unchecked(bytes) }
+            unsafe { core::str::from_utf8_unchecked(bytes) }
         })(input)
     }
 
+    /// Reads the intro line of an age file, returning the version string.
+    ///
+    /// From the age specification:
+    /// ```text
+    /// The first line of the header is age-encryption.org/ followed by an arbitrary
+    /// version string. ... We describe version v1, other versions can change anything
+    /// after the first line.
+    /// ```
+    pub fn age_intro(input: &[u8]) -> IResult<&[u8], &str> {
+        preceded(tag(AGE_INTRO_PREFIX), terminated(arbitrary_string, newline))(input)
+    }
+
     fn wrapped_encoded_data(input: &[u8]) -> IResult<&[u8], Vec<&[u8]>> {
         map(
             many_till(
@@ -307,6 +509,50 @@ pub mod read {
         alt((age_stanza, legacy_age_stanza_inner))(input)
     }
 
+    /// Reads the header of an age v1 file, starting with its intro line.
+    ///
+    /// From the age specification:
+    /// ```text
+    /// The header ends with the following line
+    ///
+    /// --- encode(HMAC[HKDF["", "header"](file key)](header))
+    ///
+    /// where header is the whole header up to the --- mark included.
+    /// ```
+    ///
+    /// Stanzas with the legacy body encoding are accepted; see [`legacy_age_stanza`].
+    /// The MAC is not verified here; use [`AgeHeaderV1::verify_mac`] once the file key
+    /// is known. Like the other parsers in this module, this returns
+    /// [`nom::Err::Incomplete`] if `input` ends partway through the header.
+    pub fn age_header_v1(input: &[u8]) -> IResult<&[u8], AgeHeaderV1<'_>> {
+        map(
+            pair(
+                consumed(tuple((
+                    verify(age_intro, |version: &str| version == V1_VERSION),
+                    many1(legacy_age_stanza),
+                    tag(MAC_TAG),
+                ))),
+                terminated(
+                    preceded(
+                        tag(" "),
+                        map_opt(take(ENCODED_MAC_LENGTH), |encoded: &[u8]| {
+                            let mut mac = [0; 32];
+                            base64::decode_config_slice(encoded, base64::STANDARD_NO_PAD, &mut mac)
+                                .ok()
+                                .map(|_| mac)
+                        }),
+                    ),
+                    newline,
+                ),
+            ),
+            |((mac_input, (_, stanzas, _)), mac)| AgeHeaderV1 {
+                stanzas,
+                mac,
+                mac_input,
+            },
+        )(input)
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
@@ -323,17 +569,24 @@ pub mod read {
 }
 
 /// Encoding operations for age types.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
 pub mod write {
     use cookie_factory::{
         combinator::string,
-        multi::separated_list,
+        multi::{all, separated_list},
         sequence::{pair, tuple},
         SerializeFn, WriteContext,
     };
     use std::io::Write;
     use std::iter;
 
-    use super::STANZA_TAG;
+    use super::{Stanza, AGE_INTRO_PREFIX, MAC_TAG, STANZA_TAG, V1_VERSION};
+
+    /// Writes the intro line of an age file with the given version string.
+    pub fn age_intro<'a, W: 'a + Write>(version: &'a str) -> impl SerializeFn<W> + 'a {
+        tuple((string(AGE_INTRO_PREFIX), string(version), string("\n")))
+    }
 
     fn wrapped_encoded_data<'a, W: 'a + Write>(data: &[u8]) -> impl SerializeFn<W> + 'a {
         let encoded = base64::encode_config(data, base64::STANDARD_NO_PAD);
@@ -373,13 +626,65 @@ pub mod write {
             wrapped_encoded_data(body),
         )
     }
+
+    /// Writes the part of an age v1 header that is covered by its MAC: the intro line,
+    /// the recipient stanzas, and the `---` that begins the MAC line.
+    ///
+    /// The MAC can then be computed with [`header_v1_mac`].
+    ///
+    /// [`header_v1_mac`]: super::header_v1_mac
+    pub fn age_header_v1_mac_input<'a, W: 'a + Write>(
+        stanzas: &'a [Stanza],
+    ) -> impl SerializeFn<W> + 'a {
+        tuple((
+            age_intro(V1_VERSION),
+            all(stanzas
+                .iter()
+                .map(|s| age_stanza(&s.tag, &s.args, s.body()))),
+            string(MAC_TAG),
+        ))
+    }
+
+    /// Writes an age v1 header with the given MAC.
+    pub fn age_header_v1<'a, W: 'a + Write>(
+        stanzas: &'a [Stanza],
+        mac: &'a [u8; 32],
+    ) -> impl SerializeFn<W> + 'a {
+        tuple((
+            age_header_v1_mac_input(stanzas),
+            string(" "),
+            string(base64::encode_config(mac, base64::STANDARD_NO_PAD)),
+            string("\n"),
+        ))
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use nom::error::ErrorKind;
 
-    use super::{read, write};
+    use super::{header_v1_mac, read, write, FileKey, Stanza};
+
+    #[test]
+    fn parse_age_intro() {
+        let test_intro = "age-encryption.org/v1\n";
+
+        let (rest, version) = read::age_intro(test_intro.as_bytes()).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(version, "v1");
+
+        let mut buf = vec![];
+        cookie_factory::gen_simple(write::age_intro(version), &mut buf).unwrap();
+        assert_eq!(buf, test_intro.as_bytes());
+
+        // The intro line is only complete once we see the newline.
+        assert!(matches!(
+            read::age_intro(b"age-encryption.org/v1"),
+            Err(nom::Err::Incomplete(_))
+        ));
+        assert!(read::age_intro(b"age-encryption.org/ v1\n").is_err());
+        assert!(read::age_intro(b"rage-encryption.org/v1\n").is_err());
+    }
 
     #[test]
     fn parse_age_stanza() {
@@ -407,6 +712,71 @@ C3ZAeY64NXS4QFrksLm3EGz+uPRyI0eQsWw7LWbbYig
         assert_eq!(buf, test_stanza.as_bytes());
     }
 
+    #[test]
+    fn owned_stanza_body() {
+        // Cover empty, short, full, and multi-line bodies.
+        for len in 0..150 {
+            let test_body: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+            let mut buf = vec![];
+            cookie_factory::gen_simple(write::age_stanza("tag", &["arg"], &test_body), &mut buf)
+                .unwrap();
+            let (_, stanza) = read::age_stanza(&buf).unwrap();
+
+            let stanza = Stanza::from(stanza);
+            // The length is known without decoding the body.
+            assert_eq!(stanza.body_len(), len);
+            assert!(stanza.body.get().is_none());
+            assert_eq!(stanza.body(), test_body);
+            assert_eq!(
+                stanza,
+                Stanza::new("tag".to_owned(), vec!["arg".to_owned()], test_body.clone())
+            );
+            assert_eq!(stanza.into_body(), test_body);
+        }
+    }
+
+    #[test]
+    fn age_header_v1_round_trip() {
+        let file_key = FileKey::from([7; 16]);
+        let stanzas = vec![
+            Stanza::new("X25519".to_owned(), vec!["arg".to_owned()], vec![1; 32]),
+            Stanza::new("other".to_owned(), vec![], vec![2; 48]),
+        ];
+
+        let mut mac_input = vec![];
+        cookie_factory::gen_simple(write::age_header_v1_mac_input(&stanzas), &mut mac_input)
+            .unwrap();
+        let mac = header_v1_mac(&file_key, &mac_input);
+
+        let mut buf = vec![];
+        cookie_factory::gen_simple(write::age_header_v1(&stanzas, &mac), &mut buf).unwrap();
+
+        let (rest, header) = read::age_header_v1(&buf).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(header.mac_input(), &mac_input[..]);
+        assert_eq!(
+            header
+                .stanzas
+                .into_iter()
+                .map(Stanza::from)
+                .collect::<Vec<_>>(),
+            stanzas
+        );
+
+        let (_, header) = read::age_header_v1(&buf).unwrap();
+        assert!(header.verify_mac(&file_key));
+        assert!(!header.verify_mac(&FileKey::from([8; 16])));
+
+        // A truncated header asks for more input.
+        assert!(matches!(
+            read::age_header_v1(&buf[..buf.len() - 1]),
+            Err(nom::Err::Incomplete(_))
+        ));
+        // Other versions are not parsed as v1 headers.
+        assert!(read::age_header_v1(b"age-encryption.org/v2\n").is_err());
+    }
+
     #[test]
     fn age_stanza_with_empty_body() {
         let test_tag = "empty-body";
@@ -3,17 +3,33 @@
 //! You are probably looking for the [`age`](https://crates.io/crates/age) crate
 //! itself. You should only need to directly depend on this crate if you are
 //! implementing a custom recipient type.
+//!
+//! # Feature flags
+//!
+//! - `std` (enabled by default): enables the I/O helpers in [`io`], serialization of
+//!   age types, and [`format::grease_the_joint`]. Without it, this crate only
+//!   requires `alloc`, and provides the cryptographic primitives and header parsing
+//!   for use on embedded targets.
+//! - `mlock`: locks [`format::FileKey`]s and other [`secret::SecretBuf`]s into
+//!   physical memory on Unix, so that they are never written to swap.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 // Catch documentation errors caused by code changes.
 #![deny(rustdoc::broken_intra_doc_links)]
 
+extern crate alloc;
+
 // Re-export crates that are used in our public API.
 pub use secrecy;
 
 pub mod format;
-pub mod io;
 pub mod primitives;
+pub mod secret;
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub mod io;
 
 #[cfg(feature = "plugin")]
 #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
@@ -10,7 +10,8 @@ use std::fmt;
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::iter;
 use std::path::Path;
-use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
 
 use crate::{
     format::{grease_the_joint, read, write, Stanza},
@@ -62,9 +63,25 @@ pub struct Connection<R: Read, W: Write> {
     input: BufReader<R>,
     output: W,
     buffer: String,
+    process: Option<Terminator>,
     _working_dir: Option<tempfile::TempDir>,
 }
 
+/// A handle for terminating the plugin binary of a [`Connection`] from another thread,
+/// for example to give up on a plugin that is waiting for a hardware token.
+#[derive(Clone)]
+pub struct Terminator(Arc<Mutex<Child>>);
+
+impl Terminator {
+    /// Terminates the plugin binary. Any pending or future reads from its connection
+    /// will then fail.
+    pub fn terminate(&self) -> io::Result<()> {
+        let mut process = self.0.lock().expect("not poisoned");
+        process.kill()?;
+        process.wait().map(|_| ())
+    }
+}
+
 impl Connection<DebugReader<ChildStdout>, DebugWriter<ChildStdin>> {
     /// Starts a plugin binary with the given state machine.
     ///
@@ -74,7 +91,7 @@ impl Connection<DebugReader<ChildStdout>, DebugWriter<ChildStdin>> {
     pub fn open(binary: &Path, state_machine: &str) -> io::Result<Self> {
         let working_dir = tempfile::tempdir()?;
         let debug_enabled = env::var("AGEDEBUG").map(|s| s == "plugin").unwrap_or(false);
-        let process = Command::new(binary.canonicalize()?)
+        let mut process = Command::new(binary.canonicalize()?)
             .arg(format!("--age-plugin={}", state_machine))
             .current_dir(working_dir.path())
             .stdin(Stdio::piped())
@@ -86,17 +103,26 @@ impl Connection<DebugReader<ChildStdout>, DebugWriter<ChildStdin>> {
             })
             .spawn()?;
         let input = BufReader::new(DebugReader::new(
-            process.stdout.expect("could open stdout"),
+            process.stdout.take().expect("could open stdout"),
             debug_enabled,
         ));
-        let output = DebugWriter::new(process.stdin.expect("could open stdin"), debug_enabled);
+        let output = DebugWriter::new(
+            process.stdin.take().expect("could open stdin"),
+            debug_enabled,
+        );
         Ok(Connection {
             input,
             output,
             buffer: String::new(),
+            process: Some(Terminator(Arc::new(Mutex::new(process)))),
             _working_dir: Some(working_dir),
         })
     }
+
+    /// Returns a handle for terminating the plugin binary.
+    pub fn terminator(&self) -> Terminator {
+        self.process.clone().expect("opened with a plugin binary")
+    }
 }
 
 impl Connection<io::Stdin, io::Stdout> {
@@ -106,6 +132,7 @@ impl Connection<io::Stdin, io::Stdout> {
             input: BufReader::new(io::stdin()),
             output: io::stdout(),
             buffer: String::new(),
+            process: None,
             _working_dir: None,
         }
     }
@@ -141,7 +168,7 @@ impl<R: Read, W: Write> Connection<R, W> {
             .chain(stanza.args.iter().map(|s| s.as_str()))
             .collect();
 
-        self.send(command, &metadata, &stanza.body)
+        self.send(command, &metadata, stanza.body())
     }
 
     fn receive(&mut self) -> io::Result<Stanza> {
@@ -196,7 +223,7 @@ impl<R: Read, W: Write> Connection<R, W> {
     ) -> io::Result<()> {
         phase_steps(UnidirSend(self))?;
         for grease in self.grease_gun() {
-            self.send(&grease.tag, &grease.args, &grease.body)?;
+            self.send(&grease.tag, &grease.args, grease.body())?;
         }
         self.done()
     }
@@ -268,7 +295,7 @@ impl<R: Read, W: Write> Connection<R, W> {
     ) -> io::Result<()> {
         phase_steps(BidirSend(self))?;
         for grease in self.grease_gun() {
-            self.send(&grease.tag, &grease.args, &grease.body)?;
+            self.send(&grease.tag, &grease.args, grease.body())?;
             self.receive()?;
         }
         self.done()
@@ -299,7 +326,7 @@ impl<'a, R: Read, W: Write> UnidirSend<'a, R, W> {
     /// Send a command.
     pub fn send(&mut self, command: &str, metadata: &[&str], data: &[u8]) -> io::Result<()> {
         for grease in self.0.grease_gun() {
-            self.0.send(&grease.tag, &grease.args, &grease.body)?;
+            self.0.send(&grease.tag, &grease.args, grease.body())?;
         }
         self.0.send(command, metadata, data)
     }
@@ -312,7 +339,7 @@ impl<'a, R: Read, W: Write> UnidirSend<'a, R, W> {
         stanza: &Stanza,
     ) -> io::Result<()> {
         for grease in self.0.grease_gun() {
-            self.0.send(&grease.tag, &grease.args, &grease.body)?;
+            self.0.send(&grease.tag, &grease.args, grease.body())?;
         }
         self.0.send_stanza(command, metadata, stanza)
     }
@@ -327,7 +354,7 @@ impl<'a, R: Read, W: Write> BidirSend<'a, R, W> {
     /// Send a command and receive a response.
     pub fn send(&mut self, command: &str, metadata: &[&str], data: &[u8]) -> Result<Stanza> {
         for grease in self.0.grease_gun() {
-            self.0.send(&grease.tag, &grease.args, &grease.body)?;
+            self.0.send(&grease.tag, &grease.args, grease.body())?;
             self.0.receive()?;
         }
         self.0.send(command, metadata, data)?;
@@ -351,7 +378,7 @@ impl<'a, R: Read, W: Write> BidirSend<'a, R, W> {
         stanza: &Stanza,
     ) -> Result<Stanza> {
         for grease in self.0.grease_gun() {
-            self.0.send(&grease.tag, &grease.args, &grease.body)?;
+            self.0.send(&grease.tag, &grease.args, grease.body())?;
             self.0.receive()?;
         }
         self.0.send_stanza(command, metadata, stanza)?;
@@ -468,12 +495,14 @@ mod tests {
             input: BufReader::new(PipeReader::new(plugin_to_client.clone())),
             output: PipeWriter::new(client_to_plugin.clone()),
             buffer: String::new(),
+            process: None,
             _working_dir: None,
         };
         let mut plugin_conn = Connection {
             input: BufReader::new(PipeReader::new(client_to_plugin)),
             output: PipeWriter::new(plugin_to_client),
             buffer: String::new(),
+            process: None,
             _working_dir: None,
         };
 
@@ -490,11 +519,11 @@ mod tests {
         assert_eq!(
             stanza,
             (
-                Ok(vec![Stanza {
-                    tag: "test".to_owned(),
-                    args: vec!["foo".to_owned()],
-                    body: b"bar"[..].to_owned()
-                }]),
+                Ok(vec![Stanza::new(
+                    "test".to_owned(),
+                    vec!["foo".to_owned()],
+                    b"bar"[..].to_owned()
+                )]),
                 Ok(vec![]),
                 None
             )
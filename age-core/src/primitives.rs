@@ -1,5 +1,6 @@
 //! Primitive cryptographic operations used across various `age` components.
 
+use alloc::vec::Vec;
 use chacha20poly1305::{
     aead::{self, generic_array::typenum::Unsigned, Aead, AeadCore, KeyInit},
     ChaCha20Poly1305,
@@ -7,6 +8,8 @@ use chacha20poly1305::{
 use hkdf::Hkdf;
 use sha2::Sha256;
 
+pub mod stream;
+
 /// `encrypt[key](plaintext)` - encrypts a message with a one-time key.
 ///
 /// ChaCha20-Poly1305 from [RFC 7539] with a zero nonce.
@@ -0,0 +1,316 @@
+//! The STREAM construction used to encrypt the payload of an age file.
+//!
+//! This only handles the encryption and decryption of individual chunks; buffering
+//! the payload into chunks is left to the caller. The `age` crate wraps this in
+//! readers and writers.
+
+use chacha20poly1305::{
+    aead::{self, Aead, AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Tag,
+};
+use core::fmt;
+use secrecy::SecretVec;
+
+/// The size of a plaintext chunk. Every chunk except the last is exactly this size.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The size of the authentication tag appended to each chunk.
+pub const TAG_SIZE: usize = 16;
+
+/// The size of an encrypted chunk. Every chunk except the last is exactly this size.
+pub const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+
+/// The nonce used in STREAM.
+///
+/// Structured as an 11 bytes of big endian counter, and 1 byte of last block flag
+/// (`0x00 / 0x01`). We store this in the lower 12 bytes of a `u128`.
+#[derive(Clone, Copy, Default)]
+struct Nonce(u128);
+
+impl Nonce {
+    /// Unsets last-chunk flag.
+    fn set_counter(&mut self, val: u64) {
+        self.0 = u128::from(val) << 8;
+    }
+
+    fn counter(&self) -> u64 {
+        (self.0 >> 8) as u64
+    }
+
+    fn increment_counter(&mut self) {
+        // Increment the 11-byte counter
+        self.0 += 1 << 8;
+        if self.0 >> (8 * 12) != 0 {
+            panic!("We overflowed the nonce!");
+        }
+    }
+
+    fn is_last(&self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    fn set_last(&mut self, last: bool) -> Result<(), Error> {
+        if !self.is_last() {
+            self.0 |= if last { 1 } else { 0 };
+            Ok(())
+        } else {
+            Err(Error::LastChunkProcessed)
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 12] {
+        self.0.to_be_bytes()[4..]
+            .try_into()
+            .expect("slice is correct length")
+    }
+}
+
+/// The ways in which processing a STREAM chunk can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The last chunk of the stream has already been processed.
+    LastChunkProcessed,
+    /// The chunk could not be authenticated.
+    Decryption,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::LastChunkProcessed => write!(f, "last chunk has been processed"),
+            Error::Decryption => write!(f, "decryption error"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl std::error::Error for Error {}
+
+/// `STREAM[key](plaintext)`
+///
+/// The [STREAM] construction for online authenticated encryption, instantiated with
+/// ChaCha20-Poly1305 in 64KiB chunks, and a nonce structure of 11 bytes of big endian
+/// counter, and 1 byte of last block flag (0x00 / 0x01).
+///
+/// Chunks must be processed in order, unless the caller moves between them with
+/// [`Stream::seek_to_chunk`].
+///
+/// [STREAM]: https://eprint.iacr.org/2015/189.pdf
+pub struct Stream {
+    aead: ChaCha20Poly1305,
+    nonce: Nonce,
+}
+
+impl Stream {
+    /// Starts a stream under the given `key`.
+    ///
+    /// `key` must **never** be repeated across multiple streams. In `age` this is
+    /// achieved by deriving the key with [`hkdf`] from both a random file key and a
+    /// random nonce.
+    ///
+    /// [`hkdf`]: super::hkdf
+    pub fn new(key: &[u8; 32]) -> Self {
+        Stream {
+            aead: ChaCha20Poly1305::new(key.into()),
+            nonce: Nonce::default(),
+        }
+    }
+
+    /// Encrypts the chunk in `buf[..len]` in place, appending the tag.
+    ///
+    /// `buf` must have room for the tag after the chunk. Returns the length of the
+    /// encrypted chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is greater than [`CHUNK_SIZE`], or `buf` is too short.
+    pub fn encrypt_chunk(
+        &mut self,
+        buf: &mut [u8],
+        len: usize,
+        last: bool,
+    ) -> Result<usize, Error> {
+        assert!(len <= CHUNK_SIZE);
+        assert!(buf.len() >= len + TAG_SIZE);
+
+        self.nonce.set_last(last)?;
+
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(&self.nonce.to_bytes().into(), &[], &mut buf[..len])
+            .expect("we will never hit chacha20::MAX_BLOCKS because of the chunk size");
+        buf[len..len + TAG_SIZE].copy_from_slice(&tag);
+        self.nonce.increment_counter();
+
+        Ok(len + TAG_SIZE)
+    }
+
+    /// Decrypts an encrypted chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk` is longer than [`ENCRYPTED_CHUNK_SIZE`].
+    pub fn decrypt_chunk(&mut self, chunk: &[u8], last: bool) -> Result<SecretVec<u8>, Error> {
+        assert!(chunk.len() <= ENCRYPTED_CHUNK_SIZE);
+
+        let nonce = self.nonce;
+        self.nonce.set_last(last)?;
+
+        match self.aead.decrypt(&self.nonce.to_bytes().into(), chunk) {
+            Ok(decrypted) => {
+                self.nonce.increment_counter();
+                Ok(SecretVec::new(decrypted))
+            }
+            Err(_) => {
+                // A chunk that fails to authenticate as the last chunk must not complete
+                // the stream.
+                self.nonce = nonce;
+                Err(Error::Decryption)
+            }
+        }
+    }
+
+    /// Decrypts the encrypted chunk in `buf[..len]` in place.
+    ///
+    /// Returns the length of the plaintext, which is written to the start of `buf`. This
+    /// lets the caller decrypt into a buffer of its choosing, such as a
+    /// [`SecretBuf`](crate::secret::SecretBuf).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is greater than [`ENCRYPTED_CHUNK_SIZE`], or `buf` is too short.
+    pub fn decrypt_chunk_in_place(
+        &mut self,
+        buf: &mut [u8],
+        len: usize,
+        last: bool,
+    ) -> Result<usize, Error> {
+        assert!(len <= ENCRYPTED_CHUNK_SIZE);
+
+        let nonce = self.nonce;
+        self.nonce.set_last(last)?;
+
+        let decrypted = match len.checked_sub(TAG_SIZE) {
+            Some(plaintext_len) => {
+                let (chunk, tag) = buf[..len].split_at_mut(plaintext_len);
+                self.aead
+                    .decrypt_in_place_detached(
+                        &self.nonce.to_bytes().into(),
+                        &[],
+                        chunk,
+                        Tag::from_slice(tag),
+                    )
+                    .map(|()| plaintext_len)
+            }
+            None => Err(aead::Error),
+        };
+
+        match decrypted {
+            Ok(plaintext_len) => {
+                self.nonce.increment_counter();
+                Ok(plaintext_len)
+            }
+            Err(_) => {
+                // A chunk that fails to authenticate as the last chunk must not complete
+                // the stream.
+                self.nonce = nonce;
+                Err(Error::Decryption)
+            }
+        }
+    }
+
+    /// Skips a chunk that failed to decrypt, so that the next chunk can be decrypted.
+    pub fn skip_chunk(&mut self) {
+        // Setting the counter also clears the last-chunk flag.
+        let next = self.nonce.counter() + 1;
+        self.nonce.set_counter(next);
+    }
+
+    /// Returns the index of the next chunk to be processed.
+    pub fn chunk_index(&self) -> u64 {
+        self.nonce.counter()
+    }
+
+    /// Moves to the chunk with the given index, so that it is the next chunk to be
+    /// processed.
+    ///
+    /// This clears the completion state of the stream.
+    pub fn seek_to_chunk(&mut self, index: u64) {
+        self.nonce.set_counter(index);
+    }
+
+    /// Marks the stream as complete without processing another chunk.
+    ///
+    /// This is used when seeking to the end of a stream whose last chunk is full, which
+    /// has already been authenticated as the last chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stream is already complete.
+    pub fn mark_complete(&mut self) {
+        self.nonce
+            .set_last(true)
+            .expect("the last chunk has not been processed");
+    }
+
+    /// Returns `true` if the last chunk of the stream has been processed.
+    pub fn is_complete(&self) -> bool {
+        self.nonce.is_last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use secrecy::ExposeSecret;
+
+    use super::{Error, Stream, CHUNK_SIZE, TAG_SIZE};
+
+    #[test]
+    fn chunk_round_trip() {
+        let data = vec![42; CHUNK_SIZE];
+
+        let mut buf = data.clone();
+        buf.resize(CHUNK_SIZE + TAG_SIZE, 0);
+        let mut s = Stream::new(&[7; 32]);
+        assert_eq!(s.encrypt_chunk(&mut buf, CHUNK_SIZE, false), Ok(buf.len()));
+        assert_eq!(s.chunk_index(), 1);
+
+        let mut s = Stream::new(&[7; 32]);
+        // Decrypting as the last chunk fails, because the chunk was not the last chunk.
+        assert!(matches!(
+            s.decrypt_chunk(&buf, true),
+            Err(Error::Decryption)
+        ));
+        assert!(!s.is_complete());
+        s.seek_to_chunk(0);
+        assert_eq!(s.decrypt_chunk(&buf, false).unwrap().expose_secret(), &data);
+        assert!(!s.is_complete());
+
+        let mut s = Stream::new(&[7; 32]);
+        let len = buf.len();
+        assert_eq!(
+            s.decrypt_chunk_in_place(&mut buf, len, true),
+            Err(Error::Decryption)
+        );
+        assert_eq!(
+            s.decrypt_chunk_in_place(&mut buf, len, false),
+            Ok(CHUNK_SIZE)
+        );
+        assert_eq!(&buf[..CHUNK_SIZE], &data[..]);
+        assert_eq!(s.chunk_index(), 1);
+    }
+
+    #[test]
+    fn last_chunk_is_final() {
+        let mut buf = vec![0; 10 + TAG_SIZE];
+        let mut s = Stream::new(&[7; 32]);
+        s.encrypt_chunk(&mut buf, 10, true).unwrap();
+        assert!(s.is_complete());
+        assert_eq!(
+            s.encrypt_chunk(&mut buf, 10, false),
+            Err(Error::LastChunkProcessed)
+        );
+    }
+}
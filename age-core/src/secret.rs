@@ -0,0 +1,187 @@
+//! Heap buffers for secret material.
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::slice;
+use secrecy::zeroize::Zeroize;
+
+/// A fixed-size heap buffer for secret material, which is zeroized when dropped.
+///
+/// With the `mlock` feature flag on Unix, the buffer is also locked into physical
+/// memory, so that it is never written to swap. Each buffer then has pages of its own,
+/// because locks apply to whole pages and are not counted: unlocking one buffer must
+/// not unlock another that shares a page with it. If the buffer can't be locked (for
+/// example, because the process would exceed `RLIMIT_MEMLOCK`), it is still usable;
+/// check [`SecretBuf::is_locked`] if that matters.
+pub struct SecretBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+    locked: bool,
+}
+
+// SAFETY: `SecretBuf` uniquely owns its allocation, like `Box<[u8]>`.
+unsafe impl Send for SecretBuf {}
+unsafe impl Sync for SecretBuf {}
+
+impl SecretBuf {
+    /// Allocates a buffer of `len` zero bytes.
+    pub fn new(len: usize) -> Self {
+        let layout = layout(len);
+        // SAFETY: `layout` has a non-zero size.
+        let ptr = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+        let locked = lock(ptr, layout.size());
+
+        SecretBuf {
+            ptr,
+            len,
+            layout,
+            locked,
+        }
+    }
+
+    /// Allocates a buffer containing a copy of `data`.
+    pub fn from_slice(data: &[u8]) -> Self {
+        let mut buf = Self::new(data.len());
+        buf.copy_from_slice(data);
+        buf
+    }
+
+    /// Returns true if this buffer is locked into physical memory.
+    ///
+    /// This is always false without the `mlock` feature flag, or on platforms other
+    /// than Unix.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Deref for SecretBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the allocation is at least `len` bytes, and initialized.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for SecretBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the allocation is at least `len` bytes, and initialized.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl fmt::Debug for SecretBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretBuf")
+            .field("len", &self.len)
+            .field("locked", &self.locked)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for SecretBuf {
+    fn drop(&mut self) {
+        // SAFETY: we own the whole allocation, which is initialized.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }.zeroize();
+        if self.locked {
+            unlock(self.ptr, self.layout.size());
+        }
+        // SAFETY: `ptr` was allocated with `layout`.
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+/// Returns the layout of the allocation for a buffer of `len` bytes, which covers whole
+/// pages if the buffer will be locked.
+#[cfg(all(feature = "mlock", unix))]
+fn layout(len: usize) -> Layout {
+    // SAFETY: `sysconf` has no preconditions.
+    let page = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        page if page > 0 => page as usize,
+        _ => 4096,
+    };
+    let pages = (len.max(1) - 1) / page + 1;
+    let size = pages.checked_mul(page).expect("buffer is too large");
+    Layout::from_size_align(size, page).expect("page size is a power of two")
+}
+
+#[cfg(not(all(feature = "mlock", unix)))]
+fn layout(len: usize) -> Layout {
+    Layout::from_size_align(len.max(1), 1).expect("buffer is too large")
+}
+
+#[cfg(all(feature = "mlock", unix))]
+fn lock(ptr: NonNull<u8>, size: usize) -> bool {
+    // SAFETY: the range is a live allocation that we own.
+    unsafe { libc::mlock(ptr.as_ptr() as *const libc::c_void, size) == 0 }
+}
+
+#[cfg(not(all(feature = "mlock", unix)))]
+fn lock(_: NonNull<u8>, _: usize) -> bool {
+    false
+}
+
+#[cfg(all(feature = "mlock", unix))]
+fn unlock(ptr: NonNull<u8>, size: usize) {
+    // SAFETY: the range is a live allocation that we own, and locked.
+    unsafe { libc::munlock(ptr.as_ptr() as *const libc::c_void, size) };
+}
+
+#[cfg(not(all(feature = "mlock", unix)))]
+fn unlock(_: NonNull<u8>, _: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretBuf;
+
+    #[test]
+    fn zeroed_and_writable() {
+        let mut buf = SecretBuf::new(40);
+        assert_eq!(&buf[..], &[0; 40][..]);
+        buf[..3].copy_from_slice(b"key");
+        assert_eq!(&buf[..3], b"key");
+
+        assert_eq!(&SecretBuf::from_slice(b"secret")[..], b"secret");
+        assert!(SecretBuf::new(0).is_empty());
+    }
+
+    #[cfg(all(feature = "mlock", unix))]
+    #[test]
+    fn locked() {
+        // SAFETY: `sysconf` has no preconditions.
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+
+        let buf = SecretBuf::from_slice(&[7; 5000]);
+        assert_eq!(&buf[..], &[7; 5000][..]);
+
+        // The buffer has whole pages of its own.
+        assert_eq!(buf.ptr.as_ptr() as usize % page, 0);
+        assert_eq!(buf.layout.size() % page, 0);
+        assert!(buf.layout.size() >= 5000);
+
+        // Locking can only be expected to succeed if RLIMIT_MEMLOCK leaves room for
+        // this buffer alongside any others that concurrent tests have locked.
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `limit` is a valid `rlimit` to write to.
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) },
+            0
+        );
+        if limit.rlim_cur == libc::RLIM_INFINITY || limit.rlim_cur >= 1 << 20 {
+            assert!(buf.is_locked());
+        } else {
+            eprintln!(
+                "Skipping mlock check: RLIMIT_MEMLOCK is only {} bytes",
+                limit.rlim_cur
+            );
+        }
+    }
+}
@@ -80,11 +80,11 @@ impl RecipientPluginV1 for RecipientPlugin {
             .into_iter()
             .map(|file_key| {
                 // TODO: This should return one stanza per recipient and identity.
-                vec![Stanza {
-                    tag: RECIPIENT_TAG.to_owned(),
-                    args: vec!["does".to_owned(), "nothing".to_owned()],
-                    body: file_key.expose_secret().to_vec(),
-                }]
+                vec![Stanza::new(
+                    RECIPIENT_TAG.to_owned(),
+                    vec!["does".to_owned(), "nothing".to_owned()],
+                    file_key.expose_secret().to_vec(),
+                )]
             })
             .collect()))
     }
@@ -128,7 +128,7 @@ impl IdentityPluginV1 for IdentityPlugin {
                     let _ = callbacks.message("This identity does nothing!")?;
                     file_keys.entry(file_index).or_insert_with(|| {
                         Ok(FileKey::from(
-                            TryInto::<[u8; 16]>::try_into(&stanza.body[..]).unwrap(),
+                            TryInto::<[u8; 16]>::try_into(stanza.body()).unwrap(),
                         ))
                     });
                     break;
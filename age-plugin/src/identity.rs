@@ -48,6 +48,36 @@ pub trait IdentityPluginV1 {
     ) -> io::Result<HashMap<usize, Result<FileKey, Vec<Error>>>>;
 }
 
+/// An identity plugin that supports no identities.
+///
+/// Plugins that only implement [`RecipientPluginV1`] can pass `|| ()` as the
+/// `identity_v1` argument to [`run_state_machine`].
+///
+/// [`RecipientPluginV1`]: crate::recipient::RecipientPluginV1
+/// [`run_state_machine`]: crate::run_state_machine
+impl IdentityPluginV1 for () {
+    fn add_identity(
+        &mut self,
+        index: usize,
+        _plugin_name: &str,
+        _bytes: &[u8],
+    ) -> Result<(), Error> {
+        Err(Error::Identity {
+            index,
+            message: "This plugin does not support identities".to_owned(),
+        })
+    }
+
+    fn unwrap_file_keys(
+        &mut self,
+        _files: Vec<Vec<Stanza>>,
+        _callbacks: impl Callbacks<Error>,
+    ) -> io::Result<HashMap<usize, Result<FileKey, Vec<Error>>>> {
+        // With no identities, no file keys can be unwrapped.
+        Ok(HashMap::new())
+    }
+}
+
 /// The interface that age plugins can use to interact with an age implementation.
 struct BidirCallbacks<'a, 'b, R: io::Read, W: io::Write>(&'b mut BidirSend<'a, R, W>);
 
@@ -94,7 +124,7 @@ impl<'a, 'b, R: io::Read, W: io::Write> Callbacks<Error> for BidirCallbacks<'a,
         self.0
             .send("request-public", &[], message.as_bytes())
             .and_then(|res| match res {
-                Ok(s) => String::from_utf8(s.body)
+                Ok(s) => String::from_utf8(s.into_body())
                     .map_err(|_| {
                         io::Error::new(io::ErrorKind::InvalidData, "response is not UTF-8")
                     })
@@ -110,7 +140,7 @@ impl<'a, 'b, R: io::Read, W: io::Write> Callbacks<Error> for BidirCallbacks<'a,
         self.0
             .send("request-secret", &[], message.as_bytes())
             .and_then(|res| match res {
-                Ok(s) => String::from_utf8(s.body)
+                Ok(s) => String::from_utf8(s.into_body())
                     .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "secret is not UTF-8"))
                     .map(|s| Ok(SecretString::new(s))),
                 Err(e) => Ok(Err(e)),
@@ -200,7 +230,7 @@ pub(crate) fn run_v1<P: IdentityPluginV1>(mut plugin: P) -> io::Result<()> {
     // Phase 1: receive identities and stanzas
     let (identities, recipient_stanzas) = {
         let (identities, stanzas, _) = conn.unidir_receive(
-            (ADD_IDENTITY, |s| match (&s.args[..], &s.body[..]) {
+            (ADD_IDENTITY, |s| match (&s.args[..], s.body()) {
                 ([identity], []) => Ok(identity.clone()),
                 _ => Err(Error::Internal {
                     message: format!(
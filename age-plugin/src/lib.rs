@@ -211,6 +211,9 @@ pub fn print_new_identity(plugin_name: &str, identity: &[u8], recipient: &[u8])
 ///
 /// This should be triggered if the `--age-plugin=state_machine` flag is provided as an
 /// argument when starting the plugin.
+///
+/// Plugins that only support one of the state machines can pass `|| ()` for the other;
+/// the unit type rejects all recipients and identities given to it.
 pub fn run_state_machine<R: recipient::RecipientPluginV1, I: identity::IdentityPluginV1>(
     state_machine: &str,
     recipient_v1: impl FnOnce() -> R,
@@ -47,6 +47,50 @@ pub trait RecipientPluginV1 {
     ) -> io::Result<Result<Vec<Vec<Stanza>>, Vec<Error>>>;
 }
 
+/// A recipient plugin that supports no recipients or identities.
+///
+/// Plugins that only implement [`IdentityPluginV1`] can pass `|| ()` as the
+/// `recipient_v1` argument to [`run_state_machine`].
+///
+/// [`IdentityPluginV1`]: crate::identity::IdentityPluginV1
+/// [`run_state_machine`]: crate::run_state_machine
+impl RecipientPluginV1 for () {
+    fn add_recipient(
+        &mut self,
+        index: usize,
+        _plugin_name: &str,
+        _bytes: &[u8],
+    ) -> Result<(), Error> {
+        Err(Error::Recipient {
+            index,
+            message: "This plugin does not support recipients".to_owned(),
+        })
+    }
+
+    fn add_identity(
+        &mut self,
+        index: usize,
+        _plugin_name: &str,
+        _bytes: &[u8],
+    ) -> Result<(), Error> {
+        Err(Error::Identity {
+            index,
+            message: "This plugin does not support encrypting to identities".to_owned(),
+        })
+    }
+
+    fn wrap_file_keys(
+        &mut self,
+        _file_keys: Vec<FileKey>,
+        _callbacks: impl Callbacks<Error>,
+    ) -> io::Result<Result<Vec<Vec<Stanza>>, Vec<Error>>> {
+        // We reject every recipient and identity, so this is never reached.
+        Ok(Err(vec![Error::Internal {
+            message: "This plugin does not support recipients".to_owned(),
+        }]))
+    }
+}
+
 /// The interface that age plugins can use to interact with an age implementation.
 struct BidirCallbacks<'a, 'b, R: io::Read, W: io::Write>(&'b mut BidirSend<'a, R, W>);
 
@@ -93,7 +137,7 @@ impl<'a, 'b, R: io::Read, W: io::Write> Callbacks<Error> for BidirCallbacks<'a,
         self.0
             .send("request-public", &[], message.as_bytes())
             .and_then(|res| match res {
-                Ok(s) => String::from_utf8(s.body)
+                Ok(s) => String::from_utf8(s.into_body())
                     .map_err(|_| {
                         io::Error::new(io::ErrorKind::InvalidData, "response is not UTF-8")
                     })
@@ -109,7 +153,7 @@ impl<'a, 'b, R: io::Read, W: io::Write> Callbacks<Error> for BidirCallbacks<'a,
         self.0
             .send("request-secret", &[], message.as_bytes())
             .and_then(|res| match res {
-                Ok(s) => String::from_utf8(s.body)
+                Ok(s) => String::from_utf8(s.into_body())
                     .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "secret is not UTF-8"))
                     .map(|s| Ok(SecretString::new(s))),
                 Err(e) => Ok(Err(e)),
@@ -189,7 +233,7 @@ pub(crate) fn run_v1<P: RecipientPluginV1>(mut plugin: P) -> io::Result<()> {
     // Phase 1: collect recipients, and file keys to be wrapped
     let ((recipients, identities), file_keys) = {
         let (recipients, identities, file_keys) = conn.unidir_receive(
-            (ADD_RECIPIENT, |s| match (&s.args[..], &s.body[..]) {
+            (ADD_RECIPIENT, |s| match (&s.args[..], s.body()) {
                 ([recipient], []) => Ok(recipient.clone()),
                 _ => Err(Error::Internal {
                     message: format!(
@@ -198,7 +242,7 @@ pub(crate) fn run_v1<P: RecipientPluginV1>(mut plugin: P) -> io::Result<()> {
                     ),
                 }),
             }),
-            (ADD_IDENTITY, |s| match (&s.args[..], &s.body[..]) {
+            (ADD_IDENTITY, |s| match (&s.args[..], s.body()) {
                 ([identity], []) => Ok(identity.clone()),
                 _ => Err(Error::Internal {
                     message: format!(
@@ -209,7 +253,7 @@ pub(crate) fn run_v1<P: RecipientPluginV1>(mut plugin: P) -> io::Result<()> {
             }),
             (Some(WRAP_FILE_KEY), |s| {
                 // TODO: Should we ignore file key commands with unexpected metadata args?
-                TryInto::<[u8; FILE_KEY_BYTES]>::try_into(&s.body[..])
+                TryInto::<[u8; FILE_KEY_BYTES]>::try_into(s.body())
                     .map_err(|_| Error::Internal {
                         message: "invalid file key length".to_owned(),
                     })
@@ -0,0 +1,102 @@
+use age::{x25519, Decryptor, Encryptor};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use criterion_cycles_per_byte::CyclesPerByte;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+type Criterion_ = Criterion<CyclesPerByte>;
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+type Criterion_ = Criterion;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn setup_criterion() -> Criterion_ {
+    Criterion::default().with_measurement(CyclesPerByte)
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn setup_criterion() -> Criterion_ {
+    Criterion::default()
+}
+
+use std::io::{self, Read, Write};
+use std::iter;
+
+const MB: usize = 1024 * 1024;
+
+/// Returns the ChaCha20 backend selected at compile time with one of the
+/// `chacha20_force_*` configuration flags, or "auto" if `chacha20` picks one at runtime.
+#[allow(unknown_lints, unexpected_cfgs)]
+fn forced_backend() -> &'static str {
+    if cfg!(chacha20_force_soft) {
+        "soft"
+    } else if cfg!(chacha20_force_avx2) {
+        "avx2"
+    } else if cfg!(chacha20_force_sse2) {
+        "sse2"
+    } else if cfg!(chacha20_force_neon) {
+        "neon"
+    } else {
+        "auto"
+    }
+}
+
+fn bench(c: &mut Criterion_) {
+    let identity = x25519::Identity::generate();
+    let recipient = identity.to_public();
+
+    // Include the forced backend in the group name, so that results from different
+    // backends are not compared against each other.
+    let mut group = c.benchmark_group(format!("stream-{}", forced_backend()));
+
+    let pt_buf = vec![7u8; 16 * MB];
+    let mut ct_buf = vec![];
+    let mut out_buf = vec![0u8; 16 * MB];
+
+    for &size in &[MB, 4 * MB, 16 * MB] {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_function(BenchmarkId::new("encrypt", size), |b| {
+            b.iter(|| {
+                let mut output = Encryptor::with_recipients(vec![Box::new(recipient.clone())])
+                    .unwrap()
+                    .wrap_output(io::sink())
+                    .unwrap();
+                output.write_all(&pt_buf[..size]).unwrap();
+                output.finish().unwrap();
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("decrypt", size), |b| {
+            let mut output = Encryptor::with_recipients(vec![Box::new(recipient.clone())])
+                .unwrap()
+                .wrap_output(&mut ct_buf)
+                .unwrap();
+            output.write_all(&pt_buf[..size]).unwrap();
+            output.finish().unwrap();
+
+            b.iter(|| {
+                let decryptor = match Decryptor::new(&ct_buf[..]).unwrap() {
+                    Decryptor::Recipients(decryptor) => decryptor,
+                    _ => panic!(),
+                };
+                let mut input = decryptor
+                    .decrypt(iter::once(&identity as &dyn age::Identity))
+                    .unwrap();
+                input.read_exact(&mut out_buf[..size]).unwrap();
+            });
+
+            ct_buf.clear();
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = setup_criterion();
+    targets = bench
+);
+criterion_main!(benches);
@@ -0,0 +1,108 @@
+//! Serves the plaintext of an age file over HTTP, decrypting requested ranges on the fly.
+//!
+//! ```text
+//! cargo run --example serve-encrypted --features http-range -- key.txt video.mp4.age 127.0.0.1:8080
+//! ```
+//!
+//! This is a minimal single-threaded server, intended to show how to use
+//! `age::http_range`; it only understands `GET` and `HEAD` requests.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use age::http_range::{RangeServer, Status};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (identity_file, age_file, addr) = match &args[..] {
+        [identity_file, age_file, addr] => (identity_file, age_file, addr),
+        _ => {
+            eprintln!("Usage: serve-encrypted IDENTITY_FILE AGE_FILE ADDRESS");
+            std::process::exit(1);
+        }
+    };
+
+    let identities: Vec<_> = age::IdentityFile::from_file(identity_file.clone())?
+        .into_identities()
+        .into_iter()
+        .filter_map(|entry| match entry {
+            age::IdentityFileEntry::Native(identity) => Some(identity),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        })
+        .collect();
+
+    let reader = match age::Decryptor::new(File::open(age_file)?)? {
+        age::Decryptor::Recipients(d) => {
+            d.decrypt(identities.iter().map(|i| i as &dyn age::Identity))?
+        }
+        _ => return Err("passphrase-encrypted files are not supported".into()),
+    };
+    let mut server = RangeServer::new(reader)?;
+
+    let listener = TcpListener::bind(addr)?;
+    eprintln!(
+        "Serving {} bytes of plaintext on http://{}",
+        server.plaintext_len(),
+        listener.local_addr()?
+    );
+    for stream in listener.incoming() {
+        if let Err(e) = stream.and_then(|stream| handle(&mut server, stream)) {
+            eprintln!("Error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn handle(server: &mut RangeServer<File>, stream: TcpStream) -> io::Result<()> {
+    let mut request = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+
+    let mut line = String::new();
+    request.read_line(&mut line)?;
+    let method = line.split(' ').next().unwrap_or_default().to_owned();
+
+    let mut range = None;
+    loop {
+        line.clear();
+        if request.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    if method != "GET" && method != "HEAD" {
+        return stream.write_all(
+            b"HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+    }
+
+    let mut response = server.respond(range.as_deref())?;
+    let reason = match response.status() {
+        Status::Ok => "OK",
+        Status::PartialContent => "Partial Content",
+        Status::RangeNotSatisfiable => "Range Not Satisfiable",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nAccept-Ranges: bytes\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status().code(),
+        reason,
+        response.content_len(),
+    )?;
+    if let Some(content_range) = response.content_range() {
+        write!(stream, "Content-Range: {}\r\n", content_range)?;
+    }
+    stream.write_all(b"\r\n")?;
+
+    if method == "GET" {
+        io::copy(&mut response, &mut stream)?;
+    }
+    stream.flush()
+}
@@ -22,6 +22,17 @@ cli-passphrase-desc = Type passphrase (leave empty to autogenerate a secure one)
 cli-passphrase-prompt = Passphrase
 cli-passphrase-confirm = Confirm passphrase
 
+cli-batch-passphrase-empty = Passphrase is empty
+cli-batch-passphrase-not-utf8 = Passphrase is not valid UTF-8
+cli-batch-passphrase-too-long = Passphrase is longer than {$max} bytes
+
+warn-identity-file-readable =
+    Warning: the identity file '{$filename}' can be read by other users (mode {$mode}).
+    Make it private with: chmod 600 '{$filename}'
+
+err-secret-sink-failed = '{$program}' failed ({$status}).
+err-clipboard-not-found = No clipboard command was found. Install one of: {$commands}
+
 -flag-armor = -a/--armor
 -flag-output = -o/--output
 -output-stdout = -o -
@@ -34,6 +45,8 @@ rec-detected-binary = Force with '{-output-stdout}'.
 err-deny-binary-output = refusing to output binary to the terminal.
 rec-deny-binary-output = Did you mean to use {-flag-armor}? {rec-detected-binary}
 
+err-invalid-mode = Invalid file mode '{$mode}' (expected octal permissions, such as 600).
+
 ## Errors
 
 err-decryption-failed = Decryption failed
@@ -45,25 +58,93 @@ err-header-invalid = Header is invalid
 
 err-header-mac-invalid = Header MAC is invalid
 
+err-stanza-arg-count = Invalid {$tag} stanza: expected {$expected} arguments, found {$actual}
+err-stanza-base64-arg = Invalid {$tag} stanza: argument {$position} is not the base64 encoding of {$expected_len} bytes
+err-stanza-decimal-arg = Invalid {$tag} stanza: argument {$position} is not a number between 0 and 255 without leading zeroes
+err-stanza-body-length = Invalid {$tag} stanza: expected a {$expected}-byte body, found {$actual} bytes
+
 err-key-decryption = Failed to decrypt an encrypted key
 
 err-no-matching-keys = No matching keys found
 
+err-threshold-not-met = Only {$shares} of the {$threshold} shares needed to decrypt were found
+err-threshold-header = Recipients can't be added to a file that was encrypted to a threshold of recipients.
+
 err-unknown-format = Unknown {-age} format.
 rec-unknown-format = Have you tried upgrading to the latest version?
 
 err-missing-plugin = Could not find '{$plugin_name}' on the PATH.
 rec-missing-plugin = Have you installed the plugin?
+rec-missing-plugin-dir = Plugins are found on the PATH, or in {$plugin_dir}
 
 err-plugin-identity = '{$plugin_name}' couldn't use an identity: {$message}
 err-plugin-recipient = '{$plugin_name}' couldn't use recipient {$recipient}: {$message}
 err-plugin-multiple = Plugin returned multiple errors:
+err-plugin-not-allowed = Not running '{$plugin_name}' because it is not in the plugin allowlist.
+
+err-plugin-protocol = '{$plugin_name}' did not follow the plugin protocol: {$message}
+rec-plugin-protocol = This is a bug in the plugin; please report it to its authors.
+
+err-plugin-too-old = '{$plugin_name}' exited without responding to the {$state_machine} protocol.
+rec-plugin-too-old = The plugin may be too old to support it. Have you tried upgrading the plugin?
+
+err-plugin-timeout = '{$plugin_name}' did not finish within {$seconds} seconds, and was stopped.
+
+err-kms = {$service} request failed: {$message}
+err-kms-invalid-response = The response did not contain a valid '{$field}'.
+err-kms-aws-no-credentials =
+    No AWS credentials were found in the environment, or from the instance metadata service: {$err}
+err-kms-azure-no-credentials =
+    No Azure credentials were found in the environment, or from a managed identity: {$err}
+err-kms-gcp-no-credentials =
+    No Google Cloud access token was found in the environment, or from the metadata server: {$err}
+err-kms-vault-no-token = No Vault token or AppRole secret ID was provided.
 
 err-read-identity-encrypted-without-passphrase =
     Identity file '{$filename}' is encrypted with {-age} but not with a passphrase.
 err-read-identity-not-found = Identity file not found: {$filename}
 
+err-recipients-file-expired =
+    The recipient on line {$line_number} of recipients file {$filename} expired on {$expires}.
+err-recipients-file-expired-label =
+    Recipient '{$label}' in recipients file {$filename} expired on {$expires}.
+rec-recipients-file-expired =
+    Remove it from the recipients file, or replace it with the recipient's new key.
+
+err-recipients-file-invalid-annotation =
+    Recipients file {$filename} contains an invalid annotation on line {$line_number}.
+rec-recipients-file-invalid-annotation =
+    Annotations have the form "# label:NAME expires:YYYY-MM-DD".
+
+err-fanout-failed = Only {$healthy} outputs are still being written, but {$required} are required.
+err-fanout-sink = Output {$index}: {$err}
+
 err-stream-last-chunk-empty = Last STREAM chunk is empty. Please report this, and/or try an older {-rage} version.
+err-stream-truncated = The age file is truncated after {$plaintext} bytes of plaintext ({$ciphertext} bytes of the payload).
+err-stream-plaintext-too-long = The plaintext is longer than the maximum of {$max} bytes.
+
+err-rng-failed = The system's random number generator failed: {$err}
+err-rng-constant = The system's random number generator returned a constant output; refusing to generate keys with it.
+err-rng-repeated = The system's random number generator repeated its output; refusing to generate keys with it.
+
+err-fido2-ctap = The security key returned error {$status}.
+err-fido2-no-credential = The connected security key doesn't have the credential for this recipient.
+err-fido2-no-hmac-secret = The security key doesn't support the hmac-secret extension.
+err-fido2-pin-blocked = The security key's PIN is blocked. Remove and reinsert it, or reset it.
+err-fido2-pin-required = The security key's PIN is required to create a credential.
+
+err-yubikey-not-found = YubiKey {$serial} is not connected.
+err-yubikey-key-replaced =
+    The key in slot {$slot} of YubiKey {$serial} is not the key in the identity file.
+err-yubikey-pin-blocked = The PIN of YubiKey {$serial} is blocked. Use the PUK to unblock it.
+
+err-scan-invalid = The scanned string is not a valid recipient.
+err-scan-misread = The scanned recipient is invalid. Was character {$position} misread? The valid recipient closest to it is:
+    {$correction}
+
+err-mnemonic-length = The recovery phrase must have {$expected} words, but it has {$words}.
+err-mnemonic-unknown-word = Word {$position} of the recovery phrase ('{$word}') is not in the wordlist.
+err-mnemonic-checksum = The recovery phrase is invalid. Check that no words were mistyped or swapped.
 
 ## Encrypted identities
 
@@ -71,9 +152,38 @@ encrypted-passphrase-prompt = Type passphrase for encrypted identity '{$filename
 
 encrypted-warn-no-match = Warning: encrypted identity file '{$filename}' didn't match file's recipients
 
+## CNG identities
+
+cng-waiting = Waiting for Windows (authenticate if prompted)...
+
+## FIDO2 identities
+
+fido2-insert = Please insert your security key
+fido2-insert-retry = Continue
+fido2-insert-skip = Skip
+fido2-pin-prompt = Enter PIN for security key
+fido2-pin-incorrect = Incorrect PIN
+fido2-touch = Touch your security key...
+
+## Key management services
+
+vault-token-prompt = Type Vault token for {$address}
+vault-secret-id-prompt = Type Vault AppRole secret ID for role {$role_id}
+
 ## Plugin identities
 
 plugin-waiting-on-binary = Waiting for {$binary_name}...
+plugin-waiting-on-binary-timeout = Waiting for {$binary_name} (giving up in {$seconds} seconds)...
+
+plugin-confirm-run =
+    A recipient or identity requires the plugin '{$binary_name}' ({$path}),
+    which is not in the plugin allowlist. Run it?
+plugin-confirm-run-yes = Run
+plugin-confirm-run-no = Cancel
+
+## Secure Enclave identities
+
+secure-enclave-waiting = Waiting for the Secure Enclave (authenticate if prompted)...
 
 ## SSH identities
 
@@ -120,3 +230,31 @@ ssh-unsupported-key-type =
     subset of these for backwards compatibility, specifically the '{-ssh-rsa}'
     and '{-ssh-ed25519}' key types. This SSH key uses the unsupported key type
     '{$key_type}'.
+
+ssh-unsupported-rsa-key-size =
+    Unsupported SSH RSA Key Size
+    ----------------------------
+    {-rage} supports '{-ssh-rsa}' keys between {$min_bits} and {$max_bits} bits,
+    but this key is {$bits} bits. You can generate a new key with:
+
+        ssh-keygen -t rsa -b 4096
+
+    or switch to an '{-ssh-ed25519}' key.
+
+ssh-unsupported-dsa-key =
+    Unsupported SSH DSA Key
+    -----------------------
+    DSA ('ssh-dss') keys are limited to 1024 bits, which is too weak to protect
+    files, and OpenSSH has disabled them by default since version 7.0. {-rage}
+    does not support them. You can generate a new '{-ssh-ed25519}' key with:
+
+        {$gen_new}
+
+## YubiKey identities
+
+yubikey-insert = Please insert YubiKey {$serial}
+yubikey-insert-retry = Continue
+yubikey-insert-skip = Skip
+yubikey-pin-prompt = Enter PIN for YubiKey {$serial}
+yubikey-pin-incorrect = Incorrect PIN ({$retries} tries remaining)
+yubikey-touch = Touch your YubiKey...
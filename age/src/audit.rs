@@ -0,0 +1,89 @@
+//! Records of age file headers, for audit logging.
+//!
+//! A [`HeaderRecord`] is obtained from a [`Header`] whose MAC has been verified, so the
+//! recipient stanzas that it lists are exactly those that the file was encrypted to.
+//! With the `serde` feature flag, records can be serialized in a stable structure:
+//!
+//! ```text
+//! {
+//!   "version": "v1",
+//!   "recipients": [
+//!     { "tag": "X25519", "args": ["..."], "label": null }
+//!   ],
+//!   "mac": "<base64>",
+//!   "header": "<base64>"
+//! }
+//! ```
+//!
+//! Binary fields are encoded as standard base64 with padding. Stanza bodies, which
+//! contain the wrapped file keys, are omitted from `recipients` but included in
+//! `header`, which can be hashed or archived to show exactly what was verified.
+//!
+//! [`Header`]: crate::Header
+
+use age_core::format::Stanza;
+
+use crate::label;
+
+/// The public parts of a recipient stanza in a verified header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StanzaRecord {
+    /// The stanza's tag, which identifies its type (for example, `X25519`).
+    pub tag: String,
+    /// The stanza's arguments.
+    pub args: Vec<String>,
+    /// The label that the stanza was given with [`Labeled`], if any.
+    ///
+    /// [`Labeled`]: crate::Labeled
+    pub label: Option<String>,
+}
+
+/// A record of an age file header whose MAC has been verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeaderRecord {
+    /// The version of the age format (currently always `v1`).
+    pub version: String,
+    /// The recipient stanzas in the header, in order.
+    pub recipients: Vec<StanzaRecord>,
+    /// The header's MAC.
+    #[cfg_attr(feature = "serde", serde(with = "base64_bytes"))]
+    pub mac: Vec<u8>,
+    /// The exact serialized header, including the MAC line.
+    #[cfg_attr(feature = "serde", serde(with = "base64_bytes"))]
+    pub header: Vec<u8>,
+}
+
+impl HeaderRecord {
+    pub(crate) fn new(stanzas: &[Stanza], mac: &[u8], header: Vec<u8>) -> Self {
+        HeaderRecord {
+            version: "v1".to_owned(),
+            recipients: label::labeled_stanzas(stanzas)
+                .map(|(stanza, label)| StanzaRecord {
+                    tag: stanza.tag.clone(),
+                    args: stanza.args.clone(),
+                    label: label.map(|l| l.to_owned()),
+                })
+                .collect(),
+            mac: mac.to_vec(),
+            header,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod base64_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode(encoded).map_err(D::Error::custom)
+    }
+}
@@ -1,6 +1,7 @@
 //! Common helpers for CLI binaries.
 
 use age_core::secrecy::{ExposeSecret, SecretString};
+use i18n_embed::{unic_langid::LanguageIdentifier, LanguageLoader};
 use pinentry::{ConfirmationDialog, PassphraseInput};
 use rand::{
     distributions::{Distribution, Uniform},
@@ -8,19 +9,32 @@ use rand::{
     CryptoRng, RngCore,
 };
 use rpassword::prompt_password;
+use std::collections::HashSet;
+use std::env;
 use std::fmt;
 use std::fs::File;
 use std::io::{self, BufReader};
+use std::sync::Arc;
 use subtle::ConstantTimeEq;
+use unicode_normalization::UnicodeNormalization;
+use zeroize::Zeroize;
 
-use crate::{fl, identity::IdentityFile, wfl, Callbacks, Identity};
+use crate::{
+    fl,
+    identity::{IdentityFile, IdentityFileEntry},
+    util::BIP39_WORDLIST,
+    wfl, Callbacks, Identity,
+};
 
 #[cfg(feature = "armor")]
 use crate::armor::ArmoredReader;
 
-pub mod file_io;
+#[cfg(feature = "plugin")]
+use crate::plugin::Allowlist;
 
-const BIP39_WORDLIST: &str = include_str!("../assets/bip39-english.txt");
+pub mod file_io;
+pub mod recipients_file;
+pub mod secret_sink;
 
 /// Errors that can occur while reading identities.
 #[derive(Debug)]
@@ -38,6 +52,14 @@ pub enum ReadError {
         /// The plugin's binary name.
         binary_name: String,
     },
+    /// A plugin identity requires a plugin that is not in the plugin allowlist, and the
+    /// user did not approve running it.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    PluginNotAllowed {
+        /// The plugin's binary name.
+        binary_name: String,
+    },
     /// The given identity file contains an SSH key that we know how to parse, but that we
     /// do not support.
     #[cfg(feature = "ssh")]
@@ -88,6 +110,16 @@ impl fmt::Display for ReadError {
                 )?;
                 wfl!(f, "rec-missing-plugin")
             }
+            #[cfg(feature = "plugin")]
+            ReadError::PluginNotAllowed { binary_name } => write!(
+                f,
+                "{}",
+                i18n_embed_fl::fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-plugin-not-allowed",
+                    plugin_name = binary_name.as_str()
+                )
+            ),
             #[cfg(feature = "ssh")]
             ReadError::UnsupportedKey(filename, k) => k.display(f, Some(filename.as_str())),
         }
@@ -104,9 +136,42 @@ impl std::error::Error for ReadError {
 }
 
 /// Reads identities from the provided files.
+///
+/// If the files contain plugin identities, the user is asked before running their
+/// plugins. Use [`read_identities_with_allowlist`] to run some plugins without asking.
 pub fn read_identities(
     filenames: Vec<String>,
     max_work_factor: Option<u8>,
+) -> Result<Vec<Box<dyn Identity>>, ReadError> {
+    #[cfg(feature = "plugin")]
+    {
+        read_identities_with_allowlist(filenames, max_work_factor, &Allowlist::new())
+    }
+
+    #[cfg(not(feature = "plugin"))]
+    {
+        read_identities_inner(filenames, max_work_factor)
+    }
+}
+
+/// Reads identities from the provided files.
+///
+/// Plugin identities only run their plugin if it is in `allowlist`, or if the user
+/// approves it.
+#[cfg(feature = "plugin")]
+#[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+pub fn read_identities_with_allowlist(
+    filenames: Vec<String>,
+    max_work_factor: Option<u8>,
+    allowlist: &Allowlist,
+) -> Result<Vec<Box<dyn Identity>>, ReadError> {
+    read_identities_inner(filenames, max_work_factor, allowlist)
+}
+
+fn read_identities_inner(
+    filenames: Vec<String>,
+    max_work_factor: Option<u8>,
+    #[cfg(feature = "plugin")] allowlist: &Allowlist,
 ) -> Result<Vec<Box<dyn Identity>>, ReadError> {
     let mut identities: Vec<Box<dyn Identity>> = vec![];
 
@@ -120,6 +185,8 @@ pub fn read_identities(
             max_work_factor,
         ) {
             if let Some(identity) = identity {
+                #[cfg(feature = "plugin")]
+                let identity = identity.with_plugin_allowlist(allowlist.clone());
                 identities.push(Box::new(identity));
                 continue;
             } else {
@@ -137,37 +204,47 @@ pub fn read_identities(
                 return Err(ReadError::UnsupportedKey(filename, k))
             }
             Ok(identity) => {
+                if let crate::ssh::Identity::Unencrypted(_) = identity {
+                    warn_if_readable_by_others(&filename);
+                }
                 identities.push(Box::new(identity.with_callbacks(UiCallbacks)));
                 continue;
             }
             Err(_) => (),
         }
-        // IdentityFileEntry::into_identity will never return a MissingPlugin error
-        // when plugin feature is not enabled.
+        // IdentityFileEntry::into_identity can only fail when the plugin feature is
+        // enabled.
 
         // Try parsing as multiple single-line age identities.
         let identity_file =
             IdentityFile::from_file(filename.clone()).map_err(|e| match e.kind() {
-                io::ErrorKind::NotFound => ReadError::IdentityNotFound(filename),
+                io::ErrorKind::NotFound => ReadError::IdentityNotFound(filename.clone()),
                 _ => e.into(),
             })?;
 
-        for entry in identity_file.into_identities() {
-            let entry = entry.into_identity(UiCallbacks);
+        let entries = identity_file.into_identities();
+        if entries.iter().any(is_secret_key) {
+            warn_if_readable_by_others(&filename);
+        }
 
+        for entry in entries {
             #[cfg(feature = "plugin")]
-            let entry = entry.map_err(|e| match e {
-                #[cfg(feature = "plugin")]
-                crate::DecryptError::MissingPlugin { binary_name } => {
-                    ReadError::MissingPlugin { binary_name }
-                }
-                // DecryptError::MissingPlugin is the only possible error kind returned by
-                // IdentityFileEntry::into_identity.
-                _ => unreachable!(),
-            })?;
+            let entry = entry
+                .into_identity(UiCallbacks, allowlist)
+                .map_err(|e| match e {
+                    crate::DecryptError::MissingPlugin { binary_name } => {
+                        ReadError::MissingPlugin { binary_name }
+                    }
+                    crate::DecryptError::PluginNotAllowed { binary_name } => {
+                        ReadError::PluginNotAllowed { binary_name }
+                    }
+                    // These are the only possible error kinds returned by
+                    // IdentityFileEntry::into_identity.
+                    _ => unreachable!(),
+                })?;
 
             #[cfg(not(feature = "plugin"))]
-            let entry = entry.unwrap();
+            let entry = entry.into_identity(UiCallbacks).unwrap();
 
             identities.push(entry);
         }
@@ -176,6 +253,39 @@ pub fn read_identities(
     Ok(identities)
 }
 
+/// Returns true if `entry` is a secret key, rather than a reference to a key held
+/// elsewhere (such as by a plugin).
+fn is_secret_key(entry: &IdentityFileEntry) -> bool {
+    let secret = matches!(entry, IdentityFileEntry::Native(_));
+    #[cfg(feature = "pq")]
+    let secret = secret || matches!(entry, IdentityFileEntry::Pq(_));
+    secret
+}
+
+/// Warns if the identity file at `filename` can be read by other users, like `ssh`
+/// does for private keys.
+fn warn_if_readable_by_others(filename: &str) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Ok(metadata) = std::fs::metadata(filename) {
+            let mode = metadata.permissions().mode() & 0o777;
+            if metadata.is_file() && mode & 0o044 != 0 {
+                UiCallbacks.display_message(&i18n_embed_fl::fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "warn-identity-file-readable",
+                    filename = filename,
+                    mode = format!("{:o}", mode),
+                ));
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = filename;
+}
+
 fn confirm(query: &str, ok: &str, cancel: Option<&str>) -> pinentry::Result<bool> {
     if let Some(mut input) = ConfirmationDialog::with_default_binary() {
         // pinentry binary is available!
@@ -187,6 +297,10 @@ fn confirm(query: &str, ok: &str, cancel: Option<&str>) -> pinentry::Result<bool
     } else {
         // Fall back to CLI interface.
         let term = console::Term::stderr();
+        if !term.is_term() {
+            // We can't ask the user, and reading would return empty responses forever.
+            return Err(pinentry::Error::Cancelled);
+        }
         let initial = format!("{}: (y/n) ", query);
         loop {
             let response = term.read_line_initial_text(&initial)?.to_lowercase();
@@ -286,6 +400,134 @@ impl Callbacks for UiCallbacks {
     }
 }
 
+/// Implementation of age callbacks for non-interactive use, such as in scripts.
+///
+/// Every passphrase request is answered with the passphrase given to
+/// [`BatchCallbacks::new`], which can be read with [`read_passphrase_from_fd`] or
+/// [`read_passphrase_from_env`]. Messages are printed to stderr, and every other
+/// request fails, because there is no user to ask.
+#[derive(Clone)]
+pub struct BatchCallbacks {
+    passphrase: Arc<SecretString>,
+}
+
+impl BatchCallbacks {
+    /// Creates callbacks that answer passphrase requests with `passphrase`.
+    pub fn new(passphrase: SecretString) -> Self {
+        BatchCallbacks {
+            passphrase: Arc::new(passphrase),
+        }
+    }
+}
+
+impl Callbacks for BatchCallbacks {
+    fn display_message(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+
+    fn request_passphrase(&self, _description: &str) -> Option<SecretString> {
+        Some(SecretString::new(self.passphrase.expose_secret().clone()))
+    }
+}
+
+fn empty_passphrase_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        fl!("cli-batch-passphrase-empty"),
+    )
+}
+
+/// The longest passphrase that [`read_passphrase_from_fd`] accepts, in bytes.
+#[cfg(unix)]
+const MAX_FD_PASSPHRASE_LEN: usize = 1024;
+
+/// Reads a passphrase from the file descriptor `fd`, for non-interactive use.
+///
+/// Unlike a command-line argument or an environment variable, a pipe or file opened
+/// by the shell (such as `3< passphrase.txt`) is not visible to other processes. The
+/// passphrase is the first line read from `fd`, without its line ending, and must not
+/// be empty or longer than 1024 bytes.
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+pub fn read_passphrase_from_fd(fd: u32) -> io::Result<SecretString> {
+    use std::io::Read;
+
+    // Opening the descriptor through `/dev/fd` avoids needing `unsafe` code.
+    let mut file = File::open(format!("/dev/fd/{}", fd))?;
+
+    // Read one byte at a time, so that we don't consume anything after the first line,
+    // or leave copies of the passphrase in a read buffer. The passphrase is read into a
+    // fixed-size buffer (with room for its line ending), which is never reallocated.
+    let mut buf = zeroize::Zeroizing::new([0; MAX_FD_PASSPHRASE_LEN + 1]);
+    let mut len = 0;
+    loop {
+        match file.read(&mut buf[len..=len]) {
+            Ok(0) => break,
+            Ok(_) if buf[len] == b'\n' => break,
+            Ok(_) if len == MAX_FD_PASSPHRASE_LEN => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    i18n_embed_fl::fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "cli-batch-passphrase-too-long",
+                        max = MAX_FD_PASSPHRASE_LEN
+                    ),
+                ))
+            }
+            Ok(_) => len += 1,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    let mut passphrase = &buf[..len];
+    if passphrase.last() == Some(&b'\r') {
+        passphrase = &passphrase[..len - 1];
+    }
+    if passphrase.is_empty() {
+        return Err(empty_passphrase_error());
+    }
+
+    std::str::from_utf8(passphrase)
+        .map(|p| SecretString::new(p.to_owned()))
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                fl!("cli-batch-passphrase-not-utf8"),
+            )
+        })
+}
+
+/// Reads a passphrase from the environment variable `name`, for non-interactive use.
+///
+/// Returns `Ok(None)` if the variable is not set. If it is set, it is removed from the
+/// environment of this process so that child processes (such as plugins) do not
+/// inherit it, and must not be empty. This should be called before any other threads
+/// are started.
+///
+/// Environment variables can be visible to other processes run by the same user, so
+/// prefer [`read_passphrase_from_fd`] where possible.
+pub fn read_passphrase_from_env(name: &str) -> io::Result<Option<SecretString>> {
+    let passphrase = match env::var(name) {
+        Ok(passphrase) => passphrase,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(_)) => {
+            env::remove_var(name);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                fl!("cli-batch-passphrase-not-utf8"),
+            ));
+        }
+    };
+    env::remove_var(name);
+
+    let passphrase = SecretString::new(passphrase);
+    if passphrase.expose_secret().is_empty() {
+        Err(empty_passphrase_error())
+    } else {
+        Ok(Some(passphrase))
+    }
+}
+
 /// A passphrase.
 pub enum Passphrase {
     /// Typed by the user.
@@ -296,28 +538,255 @@ pub enum Passphrase {
 
 impl Passphrase {
     /// Generates a secure passphrase.
-    pub fn random<R: RngCore + CryptoRng>(mut rng: R) -> Self {
-        let between = Uniform::from(0..2048);
-        let new_passphrase = (0..10)
-            .map(|_| {
-                BIP39_WORDLIST
-                    .lines()
-                    .nth(between.sample(&mut rng))
-                    .expect("index is in range")
+    ///
+    /// This uses the default [`PassphraseGenerator`].
+    pub fn random<R: RngCore + CryptoRng>(rng: R) -> Self {
+        Passphrase::Generated(PassphraseGenerator::default().generate(rng))
+    }
+}
+
+/// The wordlists that are bundled with this library, by language.
+///
+/// A list can be added here for any language that has a translation, so that it is
+/// selected by [`Wordlist::localized`].
+const BUNDLED_WORDLISTS: &[(&str, &str)] = &[("en", BIP39_WORDLIST)];
+
+/// A list of words from which passphrases can be generated.
+#[derive(Clone, Debug)]
+pub struct Wordlist {
+    words: Vec<String>,
+}
+
+impl Wordlist {
+    /// Returns the English BIP 39 wordlist, which contains 2048 words.
+    pub fn bip39_english() -> Self {
+        Wordlist::from_lines(BIP39_WORDLIST).expect("BIP 39 wordlist is valid")
+    }
+
+    /// Returns the bundled wordlist for `language`, or `None` if there isn't one.
+    ///
+    /// Only the language subtag is considered, so `es-AR` and `es-ES` share a list.
+    pub fn for_language(language: &LanguageIdentifier) -> Option<Self> {
+        BUNDLED_WORDLISTS
+            .iter()
+            .find(|(lang, _)| *lang == language.language.as_str())
+            .map(|(_, list)| Wordlist::from_lines(list).expect("bundled wordlist is valid"))
+    }
+
+    /// Returns the bundled wordlist for the language selected with [`crate::localizer`],
+    /// or the English BIP 39 wordlist if there isn't one.
+    pub fn localized() -> Self {
+        Wordlist::for_language(&crate::i18n::LANGUAGE_LOADER.current_language())
+            .unwrap_or_else(Wordlist::bip39_english)
+    }
+
+    /// Parses a wordlist with one word per line.
+    ///
+    /// Empty lines and lines starting with `#` are ignored. If a line contains several
+    /// fields, the word is the last of them; this allows diceware lists such as the EFF
+    /// wordlists (which have the form `11111 abacus`) to be used as-is. Duplicate words
+    /// are ignored, as they would not add any entropy.
+    ///
+    /// Returns `None` if the list contains fewer than two distinct words.
+    pub fn from_lines(list: &str) -> Option<Self> {
+        let mut seen = HashSet::new();
+        let words: Vec<String> = list
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !(line.is_empty() || line.starts_with('#')))
+            .filter_map(|line| line.split_whitespace().last())
+            .filter(|word| seen.insert(*word))
+            .map(|word| word.to_owned())
+            .collect();
+
+        if words.len() < 2 {
+            None
+        } else {
+            Some(Wordlist { words })
+        }
+    }
+
+    /// Returns the number of distinct words in this list.
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Returns `true` if this list is empty. This is never the case.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+/// The ASCII letters and digits.
+pub const CHARSET_ALPHANUMERIC: &str =
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The lowercase ASCII letters and digits. Passphrases generated from this charset are
+/// unchanged by [`normalize_passphrase`].
+pub const CHARSET_LOWERCASE_ALPHANUMERIC: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The printable ASCII characters, excluding space.
+pub const CHARSET_PRINTABLE: &str =
+    "!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+
+#[derive(Clone, Debug)]
+enum Alphabet {
+    Words(Wordlist),
+    Chars(Vec<char>),
+}
+
+/// The entropy of passphrases from the default [`PassphraseGenerator`], in bits.
+pub const DEFAULT_PASSPHRASE_ENTROPY_BITS: f64 = 110.0;
+
+/// Generates random passphrases of a chosen strength.
+///
+/// Passphrases are either a number of words from a [`Wordlist`], separated by `-`, or a
+/// number of characters from a charset. The default generates words from the
+/// [localized wordlist](Wordlist::localized), with at least 110 bits of entropy (10
+/// words from the English BIP 39 wordlist).
+#[derive(Clone, Debug)]
+pub struct PassphraseGenerator {
+    alphabet: Alphabet,
+    length: usize,
+}
+
+impl Default for PassphraseGenerator {
+    fn default() -> Self {
+        PassphraseGenerator::words_with_entropy(
+            Wordlist::localized(),
+            DEFAULT_PASSPHRASE_ENTROPY_BITS,
+        )
+    }
+}
+
+impl PassphraseGenerator {
+    /// Generates passphrases of `count` words chosen from `wordlist`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero.
+    pub fn words(wordlist: Wordlist, count: usize) -> Self {
+        assert!(count > 0);
+        PassphraseGenerator {
+            alphabet: Alphabet::Words(wordlist),
+            length: count,
+        }
+    }
+
+    /// Generates passphrases of as many words chosen from `wordlist` as are needed for
+    /// at least `bits` of entropy.
+    ///
+    /// This keeps passphrases equally strong whichever wordlist is used: a shorter list
+    /// gives longer passphrases.
+    pub fn words_with_entropy(wordlist: Wordlist, bits: f64) -> Self {
+        let bits_per_word = (wordlist.len() as f64).log2();
+        let count = ((bits / bits_per_word).ceil() as usize).max(1);
+        PassphraseGenerator::words(wordlist, count)
+    }
+
+    /// Generates passphrases of `count` characters chosen from `charset`, such as
+    /// [`CHARSET_ALPHANUMERIC`].
+    ///
+    /// Returns `None` if `charset` contains fewer than two distinct characters, or any
+    /// whitespace.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero.
+    pub fn chars(charset: &str, count: usize) -> Option<Self> {
+        assert!(count > 0);
+        let mut seen = HashSet::new();
+        let chars: Vec<char> = charset.chars().filter(|c| seen.insert(*c)).collect();
+        if chars.len() < 2 || chars.iter().any(|c| c.is_whitespace()) {
+            None
+        } else {
+            Some(PassphraseGenerator {
+                alphabet: Alphabet::Chars(chars),
+                length: count,
             })
-            .fold(String::new(), |acc, s| {
-                if acc.is_empty() {
-                    acc + s
-                } else {
-                    acc + "-" + s
+        }
+    }
+
+    /// Returns the entropy of the generated passphrases, in bits.
+    pub fn entropy_bits(&self) -> f64 {
+        let choices = match &self.alphabet {
+            Alphabet::Words(wordlist) => wordlist.len(),
+            Alphabet::Chars(chars) => chars.len(),
+        };
+        self.length as f64 * (choices as f64).log2()
+    }
+
+    /// Returns `true` if every passphrase this generates is unchanged by
+    /// [`normalize_passphrase`], and so keeps its full entropy when normalized.
+    pub fn is_normalized(&self) -> bool {
+        let is_normalized = |s: &str| {
+            !s.contains(char::is_whitespace)
+                && normalize_passphrase(&SecretString::new(s.to_owned())).expose_secret() == s
+        };
+        match &self.alphabet {
+            Alphabet::Words(wordlist) => wordlist.words.iter().all(|w| is_normalized(w)),
+            Alphabet::Chars(chars) => chars.iter().all(|c| is_normalized(&c.to_string())),
+        }
+    }
+
+    /// Generates a passphrase.
+    pub fn generate<R: RngCore + CryptoRng>(&self, mut rng: R) -> SecretString {
+        let mut passphrase = String::new();
+        match &self.alphabet {
+            Alphabet::Words(wordlist) => {
+                let between = Uniform::from(0..wordlist.len());
+                for i in 0..self.length {
+                    if i > 0 {
+                        passphrase.push('-');
+                    }
+                    passphrase.push_str(&wordlist.words[between.sample(&mut rng)]);
                 }
-            });
-        Passphrase::Generated(SecretString::new(new_passphrase))
+            }
+            Alphabet::Chars(chars) => {
+                let between = Uniform::from(0..chars.len());
+                for _ in 0..self.length {
+                    passphrase.push(chars[between.sample(&mut rng)]);
+                }
+            }
+        }
+        SecretString::new(passphrase)
+    }
+}
+
+/// Normalizes a passphrase so that it can be reliably re-entered on another device.
+///
+/// This applies Unicode NFKC normalization, converts the passphrase to lowercase, trims
+/// leading and trailing whitespace, and collapses each run of internal whitespace into a
+/// single space. Passphrases generated by [`Passphrase::random`] are unchanged by it.
+///
+/// Normalization is opt-in: a file encrypted with a normalized passphrase must also be
+/// decrypted with a normalized passphrase.
+pub fn normalize_passphrase(passphrase: &SecretString) -> SecretString {
+    let mut nfkc: String = passphrase.expose_secret().nfkc().collect();
+    let mut lowercase = nfkc.to_lowercase();
+    nfkc.zeroize();
+
+    let mut normalized = String::with_capacity(lowercase.len());
+    for word in lowercase.split_whitespace() {
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        normalized.push_str(word);
     }
+    lowercase.zeroize();
+
+    SecretString::new(normalized)
 }
 
 /// Reads a passphrase from stdin, or generates a secure one if none is provided.
 pub fn read_or_generate_passphrase() -> pinentry::Result<Passphrase> {
+    read_or_generate_passphrase_with(&PassphraseGenerator::default())
+}
+
+/// Reads a passphrase from stdin, or generates one with `generator` if none is provided.
+pub fn read_or_generate_passphrase_with(
+    generator: &PassphraseGenerator,
+) -> pinentry::Result<Passphrase> {
     let res = read_secret(
         &fl!("cli-passphrase-desc"),
         &fl!("cli-passphrase-prompt"),
@@ -325,8 +794,182 @@ pub fn read_or_generate_passphrase() -> pinentry::Result<Passphrase> {
     )?;
 
     if res.expose_secret().is_empty() {
-        Ok(Passphrase::random(OsRng))
+        Ok(Passphrase::Generated(generator.generate(OsRng)))
     } else {
         Ok(Passphrase::Typed(res))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use age_core::secrecy::{ExposeSecret, SecretString};
+    use rand::rngs::OsRng;
+
+    use super::{
+        normalize_passphrase, read_passphrase_from_env, Passphrase, PassphraseGenerator, Wordlist,
+        CHARSET_ALPHANUMERIC, CHARSET_LOWERCASE_ALPHANUMERIC, CHARSET_PRINTABLE,
+    };
+
+    fn normalize(s: &str) -> String {
+        normalize_passphrase(&SecretString::new(s.to_owned()))
+            .expose_secret()
+            .clone()
+    }
+
+    #[test]
+    fn normalize_passphrase_variants() {
+        let expected = "caf\u{e9} au lait";
+        for variant in &[
+            // Precomposed.
+            "caf\u{e9} au lait",
+            // Decomposed.
+            "cafe\u{301} au lait",
+            // Case, padding, and repeated and non-ASCII whitespace.
+            "  CAF\u{c9}\tAu\u{a0}\u{a0}LAIT\n",
+            // Compatibility characters (fullwidth letters).
+            "\u{ff43}\u{ff41}\u{ff46}\u{e9} au lait",
+        ] {
+            assert_eq!(normalize(variant), expected, "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn normalize_passphrase_is_idempotent() {
+        let once = normalize(" \u{fb01}X  \u{212b} ");
+        assert_eq!(once, "fix \u{e5}");
+        assert_eq!(normalize(&once), once);
+    }
+
+    #[test]
+    fn generated_passphrases_are_normalized() {
+        for _ in 0..10 {
+            match Passphrase::random(OsRng) {
+                Passphrase::Generated(p) => {
+                    assert_eq!(&normalize(p.expose_secret()), p.expose_secret())
+                }
+                Passphrase::Typed(_) => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn wordlist_from_lines() {
+        assert_eq!(Wordlist::bip39_english().len(), 2048);
+        assert_eq!(
+            Wordlist::for_language(&"en-GB".parse().unwrap())
+                .unwrap()
+                .len(),
+            2048
+        );
+
+        let diceware = "# EFF-style list\n11111\tabacus\n11112\tabdomen\n\n11113\tabacus\n";
+        let wordlist = Wordlist::from_lines(diceware).unwrap();
+        assert_eq!(wordlist.words, ["abacus", "abdomen"]);
+
+        assert!(Wordlist::from_lines("one\none\n").is_none());
+    }
+
+    #[test]
+    fn generator_entropy() {
+        let generator = PassphraseGenerator::default();
+        assert_eq!(generator.entropy_bits(), 110.0);
+
+        let diceware: String = (0..7776).map(|i| format!("word{}\n", i)).collect();
+        let generator = PassphraseGenerator::words_with_entropy(
+            Wordlist::from_lines(&diceware).unwrap(),
+            110.0,
+        );
+        assert_eq!(
+            generator.generate(OsRng).expose_secret().split('-').count(),
+            9
+        );
+        assert!(generator.entropy_bits() >= 110.0);
+
+        let generator = PassphraseGenerator::words(Wordlist::bip39_english(), 6);
+        assert_eq!(generator.entropy_bits(), 66.0);
+        assert_eq!(
+            generator.generate(OsRng).expose_secret().split('-').count(),
+            6
+        );
+
+        let generator = PassphraseGenerator::chars("0123456789abcdef", 32).unwrap();
+        assert_eq!(generator.entropy_bits(), 128.0);
+        assert_eq!(generator.generate(OsRng).expose_secret().len(), 32);
+
+        assert!(PassphraseGenerator::chars("aaaa", 10).is_none());
+        assert!(PassphraseGenerator::chars("ab c", 10).is_none());
+    }
+
+    #[test]
+    fn generator_is_normalized() {
+        assert!(PassphraseGenerator::default().is_normalized());
+        assert!(
+            PassphraseGenerator::chars(CHARSET_LOWERCASE_ALPHANUMERIC, 20)
+                .unwrap()
+                .is_normalized()
+        );
+        assert!(!PassphraseGenerator::chars(CHARSET_ALPHANUMERIC, 20)
+            .unwrap()
+            .is_normalized());
+        assert!(!PassphraseGenerator::chars(CHARSET_PRINTABLE, 20)
+            .unwrap()
+            .is_normalized());
+    }
+
+    #[test]
+    fn passphrase_from_env() {
+        const VAR: &str = "AGE_TEST_PASSPHRASE_FROM_ENV";
+
+        assert!(read_passphrase_from_env(VAR).unwrap().is_none());
+
+        std::env::set_var(VAR, "correct horse battery staple");
+        assert_eq!(
+            read_passphrase_from_env(VAR)
+                .unwrap()
+                .unwrap()
+                .expose_secret(),
+            "correct horse battery staple"
+        );
+        // The variable is not left for child processes to inherit.
+        assert!(std::env::var_os(VAR).is_none());
+
+        std::env::set_var(VAR, "");
+        assert!(read_passphrase_from_env(VAR).is_err());
+        assert!(std::env::var_os(VAR).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn passphrase_from_fd() {
+        use std::io::{Seek, SeekFrom, Write};
+        use std::os::unix::io::AsRawFd;
+
+        let path = std::env::temp_dir().join(format!("age-passphrase-fd-{}", std::process::id()));
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut read = |contents: &[u8]| {
+            file.set_len(0).unwrap();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.write_all(contents).unwrap();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            super::read_passphrase_from_fd(file.as_raw_fd() as u32)
+                .map(|p| p.expose_secret().clone())
+        };
+
+        assert_eq!(read(b"passphrase\nignored\n").unwrap(), "passphrase");
+        assert_eq!(read(b"passphrase\r\n").unwrap(), "passphrase");
+        assert_eq!(read(b"pass phrase").unwrap(), "pass phrase");
+        assert!(read(b"\n").is_err());
+        assert!(read(b"\xff\n").is_err());
+
+        let longest = "a".repeat(super::MAX_FD_PASSPHRASE_LEN);
+        assert_eq!(read(format!("{}\n", longest).as_bytes()).unwrap(), longest);
+        assert!(read(format!("{}a\n", longest).as_bytes()).is_err());
+    }
+}
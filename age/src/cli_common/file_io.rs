@@ -3,13 +3,15 @@
 use std::fmt;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
 
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
 
 use zeroize::Zeroize;
 
-use crate::{fl, util::LINE_ENDING, wfl, wlnfl};
+use crate::{fl, fs::AtomicOutput, util::LINE_ENDING, wfl, wlnfl};
 
 const SHORT_OUTPUT_LENGTH: usize = 20 * 80;
 
@@ -143,6 +145,48 @@ pub enum OutputFormat {
     Unknown,
 }
 
+/// Unix permissions for new output files, parsed from octal such as `600` or `0o600`.
+///
+/// Output files are created with these permissions (subject to the umask) even if they
+/// replace an existing file. They are ignored on other platforms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mode(pub u32);
+
+impl FromStr for Mode {
+    type Err = InvalidModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0o").unwrap_or(s);
+        if digits.is_empty() || !digits.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+            return Err(InvalidModeError(s.to_owned()));
+        }
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(Mode(mode)),
+            _ => Err(InvalidModeError(s.to_owned())),
+        }
+    }
+}
+
+/// The error returned when parsing a [`Mode`] fails.
+#[derive(Debug)]
+pub struct InvalidModeError(String);
+
+impl fmt::Display for InvalidModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            i18n_embed_fl::fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-invalid-mode",
+                mode = self.0.as_str(),
+            )
+        )
+    }
+}
+
+impl std::error::Error for InvalidModeError {}
+
 /// Writer that wraps standard output to handle TTYs nicely.
 pub struct StdoutWriter {
     inner: StdoutBuffer,
@@ -242,15 +286,19 @@ impl Write for StdoutWriter {
 
 /// A lazy [`File`] that is not opened until the first call to [`Write::write`] or
 /// [`Write::flush`].
+///
+/// Once opened, the file is removed again on drop unless [`LazyFile::commit`] is called.
 pub struct LazyFile {
     filename: String,
     #[cfg(unix)]
     mode: u32,
-    file: Option<io::Result<File>>,
+    new_permissions: bool,
+    on_tmp_file: Option<fn(&Path)>,
+    file: Option<io::Result<AtomicOutput>>,
 }
 
 impl LazyFile {
-    fn get_file(&mut self) -> io::Result<&mut File> {
+    fn get_file(&mut self) -> io::Result<&mut AtomicOutput> {
         let filename = &self.filename;
 
         if self.file.is_none() {
@@ -260,7 +308,17 @@ impl LazyFile {
             #[cfg(unix)]
             options.mode(self.mode);
 
-            self.file = Some(options.open(filename));
+            let file = if self.new_permissions {
+                AtomicOutput::with_new_permissions(filename, &options)
+            } else {
+                AtomicOutput::with_options(filename, &options)
+            };
+            if let (Ok(file), Some(on_tmp_file)) = (&file, self.on_tmp_file) {
+                if let Some(tmp_path) = file.tmp_path() {
+                    on_tmp_file(tmp_path);
+                }
+            }
+            self.file = Some(file);
         }
 
         self.file
@@ -269,6 +327,47 @@ impl LazyFile {
             .as_mut()
             .map_err(|e| io::Error::new(e.kind(), format!("Failed to open file '{}'", filename)))
     }
+
+    /// Returns true if the output file can be read by all users, in a directory that
+    /// any user can write to (such as `/tmp`).
+    ///
+    /// This opens the output file, and checks the permissions it was actually created
+    /// with. Always returns false for outputs that are not regular files, and on
+    /// platforms other than Unix.
+    pub fn is_exposed(&mut self) -> io::Result<bool> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let file = self.get_file()?;
+            if file.tmp_path().is_none() {
+                return Ok(false);
+            }
+            if file.metadata()?.permissions().mode() & 0o004 == 0 {
+                return Ok(false);
+            }
+            let dir = match file.path().parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            Ok(std::fs::metadata(dir)
+                .map(|metadata| metadata.permissions().mode() & 0o002 != 0)
+                .unwrap_or(false))
+        }
+
+        #[cfg(not(unix))]
+        Ok(false)
+    }
+
+    /// Marks the output as complete, so that it is kept when this `LazyFile` is dropped.
+    ///
+    /// If the file was never opened, this does nothing.
+    pub fn commit(self) -> io::Result<()> {
+        match self.file {
+            Some(Ok(file)) => file.commit().map(|_| ()),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl io::Write for LazyFile {
@@ -306,6 +405,8 @@ impl OutputWriter {
                     filename,
                     #[cfg(unix)]
                     mode: _mode,
+                    new_permissions: false,
+                    on_tmp_file: None,
                     file: None,
                 }));
             } else {
@@ -327,6 +428,52 @@ impl OutputWriter {
         )))
     }
 
+    /// Sets a function to call with the path of the temporary file that an output file
+    /// is written to, when it is created.
+    ///
+    /// The temporary file is removed if this `OutputWriter` is dropped without being
+    /// committed. Binaries that may exit without running destructors (for example, on
+    /// Ctrl-C) can use this to track the file and remove it themselves. This has no
+    /// effect when writing to standard output.
+    pub fn on_tmp_file(&mut self, f: fn(&Path)) {
+        if let OutputWriter::File(file) = self {
+            file.on_tmp_file = Some(f);
+        }
+    }
+
+    /// Creates output files with the permissions passed to [`OutputWriter::new`], even
+    /// when they replace an existing file.
+    ///
+    /// By default, a replaced file keeps its own permissions. This has no effect when
+    /// writing to standard output.
+    pub fn use_new_permissions(&mut self) {
+        if let OutputWriter::File(file) = self {
+            file.new_permissions = true;
+        }
+    }
+
+    /// Returns true if the output is a file that can be read by all users, in a directory
+    /// that any user can write to (such as `/tmp`).
+    ///
+    /// See [`LazyFile::is_exposed`]. Always returns false for standard output.
+    pub fn is_exposed(&mut self) -> io::Result<bool> {
+        match self {
+            OutputWriter::File(file) => file.is_exposed(),
+            OutputWriter::Stdout(_) => Ok(false),
+        }
+    }
+
+    /// Marks the output as complete.
+    ///
+    /// Output files that are dropped without being committed are removed, so that a
+    /// failed operation does not leave a partially-written file behind.
+    pub fn commit(self) -> io::Result<()> {
+        match self {
+            OutputWriter::File(f) => f.commit(),
+            OutputWriter::Stdout(mut handle) => handle.flush(),
+        }
+    }
+
     /// Returns true if this output is to a terminal, and a user will likely see it.
     pub fn is_terminal(&self) -> bool {
         match self {
@@ -359,6 +506,62 @@ pub(crate) mod tests {
     #[cfg(unix)]
     use std::io::Write;
 
+    #[test]
+    fn parse_mode() {
+        use super::Mode;
+
+        assert_eq!("600".parse::<Mode>().unwrap(), Mode(0o600));
+        assert_eq!("0o640".parse::<Mode>().unwrap(), Mode(0o640));
+        assert_eq!("0600".parse::<Mode>().unwrap(), Mode(0o600));
+        assert!("".parse::<Mode>().is_err());
+        assert!("+600".parse::<Mode>().is_err());
+        assert!("680".parse::<Mode>().is_err());
+        assert!("17777".parse::<Mode>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exposed_output() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("age-exposed-output-{}", std::process::id()));
+        let shared = dir.join("shared");
+        let private = dir.join("private");
+        for (path, mode) in [(&shared, 0o777), (&private, 0o755)] {
+            fs::create_dir_all(path).unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let is_exposed = |output: String, mode, replace: Option<u32>| {
+            if let Some(existing) = replace {
+                fs::write(&output, b"").unwrap();
+                fs::set_permissions(&output, fs::Permissions::from_mode(existing)).unwrap();
+            }
+            let mut writer =
+                OutputWriter::new(Some(output.clone()), OutputFormat::Text, mode, false).unwrap();
+            let exposed = writer.is_exposed().unwrap();
+            drop(writer);
+            let _ = fs::remove_file(&output);
+            exposed
+        };
+        let output = |dir: &std::path::Path| dir.join("plaintext.txt").display().to_string();
+
+        // Only files that all users can read are exposed. Group and other permissions are
+        // never granted by the umask, but may be removed by it.
+        assert!(!is_exposed(output(&shared), 0o640, None));
+        assert!(!is_exposed(output(&shared), 0o600, None));
+        assert!(!is_exposed(output(&private), 0o644, None));
+        assert!(!is_exposed("-".to_string(), 0o644, None));
+
+        // A replaced file keeps its permissions, whatever the output was created with.
+        assert!(is_exposed(output(&shared), 0o600, Some(0o644)));
+        assert!(is_exposed(output(&shared), 0o600, Some(0o604)));
+        assert!(!is_exposed(output(&shared), 0o644, Some(0o640)));
+        assert!(!is_exposed(output(&private), 0o600, Some(0o644)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[cfg(unix)]
     #[test]
     fn lazy_existing_file() {
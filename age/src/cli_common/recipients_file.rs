@@ -0,0 +1,456 @@
+//! Annotated recipients files.
+//!
+//! A recipients file contains one recipient per line, ignoring empty lines and lines
+//! starting with `#`. A comment line can also annotate the recipient on the next
+//! non-comment line, with one or more space-separated `key:value` pairs:
+//!
+//! ```text
+//! # label:alice expires:2025-01-01
+//! age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p
+//! ```
+//!
+//! The supported annotations are:
+//! - `label:NAME`, a name for the recipient that is used in error messages.
+//! - `expires:YYYY-MM-DD`, the date (in UTC) from which the recipient can no longer be
+//!   encrypted to.
+//!
+//! Other tools that read recipients files treat annotations as ordinary comments.
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::wfl;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The date from which a recipient is expired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExpiryDate {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl ExpiryDate {
+    /// Returns the number of days between the Unix epoch and this date.
+    ///
+    /// Uses the algorithm from <https://howardhinnant.github.io/date_algorithms.html>.
+    fn days_since_epoch(&self) -> i64 {
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - if month <= 2 { 1 } else { 0 };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// Returns `true` if this date is on or before the day containing `now` (in UTC).
+    pub fn has_passed(&self, now: SystemTime) -> bool {
+        let today = match now.duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => (elapsed.as_secs() / SECONDS_PER_DAY) as i64,
+            // The clock is before 1970; treat every date as in the future.
+            Err(_) => return false,
+        };
+        self.days_since_epoch() <= today
+    }
+}
+
+impl FromStr for ExpiryDate {
+    type Err = ();
+
+    /// Parses a date of the form `YYYY-MM-DD`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split('-').collect();
+        let (year, month, day) = match parts[..] {
+            [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => {
+                (year, month, day)
+            }
+            _ => return Err(()),
+        };
+        fn digits<T: FromStr>(s: &str) -> Result<T, ()> {
+            if s.bytes().all(|b| b.is_ascii_digit()) {
+                s.parse().map_err(|_| ())
+            } else {
+                Err(())
+            }
+        }
+        let year: u16 = digits(year)?;
+        let month: u8 = digits(month)?;
+        let day: u8 = digits(day)?;
+
+        let is_leap_year = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+        let days_in_month = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if is_leap_year => 29,
+            2 => 28,
+            _ => return Err(()),
+        };
+        if day == 0 || day > days_in_month {
+            return Err(());
+        }
+
+        Ok(ExpiryDate { year, month, day })
+    }
+}
+
+impl fmt::Display for ExpiryDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// A recipient read from a recipients file, along with its annotations.
+#[derive(Debug)]
+pub struct RecipientLine {
+    line_number: usize,
+    recipient: String,
+    label: Option<String>,
+    expires: Option<ExpiryDate>,
+}
+
+impl RecipientLine {
+    /// Returns the (1-indexed) line of the recipients file containing this recipient.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Returns the recipient string.
+    pub fn recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    /// Returns the recipient's `label` annotation, if any.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Returns the recipient's `expires` annotation, if any.
+    pub fn expires(&self) -> Option<ExpiryDate> {
+        self.expires
+    }
+}
+
+/// Errors that can occur while reading a recipients file.
+#[derive(Debug)]
+pub enum RecipientsFileError {
+    /// A recipient in the file has expired.
+    Expired {
+        /// The name of the recipients file.
+        filename: String,
+        /// The line of the recipients file containing the expired recipient.
+        line_number: usize,
+        /// The recipient's label, if any.
+        label: Option<String>,
+        /// The date from which the recipient expired.
+        expires: ExpiryDate,
+    },
+    /// An annotation comment in the file could not be parsed.
+    InvalidAnnotation {
+        /// The name of the recipients file.
+        filename: String,
+        /// The line of the recipients file containing the invalid annotation.
+        line_number: usize,
+    },
+    /// An I/O error occurred while reading.
+    Io(io::Error),
+}
+
+impl From<io::Error> for RecipientsFileError {
+    fn from(e: io::Error) -> Self {
+        RecipientsFileError::Io(e)
+    }
+}
+
+impl fmt::Display for RecipientsFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecipientsFileError::Expired {
+                filename,
+                line_number,
+                label,
+                expires,
+            } => {
+                match label {
+                    Some(label) => writeln!(
+                        f,
+                        "{}",
+                        i18n_embed_fl::fl!(
+                            crate::i18n::LANGUAGE_LOADER,
+                            "err-recipients-file-expired-label",
+                            filename = filename.as_str(),
+                            label = label.as_str(),
+                            expires = expires.to_string()
+                        )
+                    )?,
+                    None => writeln!(
+                        f,
+                        "{}",
+                        i18n_embed_fl::fl!(
+                            crate::i18n::LANGUAGE_LOADER,
+                            "err-recipients-file-expired",
+                            filename = filename.as_str(),
+                            line_number = (*line_number),
+                            expires = expires.to_string()
+                        )
+                    )?,
+                }
+                wfl!(f, "rec-recipients-file-expired")
+            }
+            RecipientsFileError::InvalidAnnotation {
+                filename,
+                line_number,
+            } => {
+                writeln!(
+                    f,
+                    "{}",
+                    i18n_embed_fl::fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-recipients-file-invalid-annotation",
+                        filename = filename.as_str(),
+                        line_number = (*line_number)
+                    )
+                )?;
+                wfl!(f, "rec-recipients-file-invalid-annotation")
+            }
+            RecipientsFileError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RecipientsFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+/// The annotations that apply to the next recipient line.
+#[derive(Default)]
+struct Annotations {
+    label: Option<String>,
+    expires: Option<ExpiryDate>,
+}
+
+impl Annotations {
+    /// Parses `comment` (the part of a comment line after `#`) into `self`.
+    ///
+    /// Returns `Ok(false)` if `comment` is an ordinary comment, and `Err(())` if it looks
+    /// like an annotation but can't be parsed.
+    fn parse(&mut self, comment: &str) -> Result<bool, ()> {
+        let is_annotation =
+            |token: &str| token.starts_with("label:") || token.starts_with("expires:");
+        let mut tokens = comment.split_whitespace().peekable();
+        match tokens.peek() {
+            Some(token) if is_annotation(token) => (),
+            _ => return Ok(false),
+        }
+
+        for token in tokens {
+            match token.split_once(':') {
+                Some(("label", label)) if !label.is_empty() => self.label = Some(label.into()),
+                Some(("expires", date)) => self.expires = Some(date.parse()?),
+                _ => return Err(()),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Reads the recipients in a recipients file, along with any annotations.
+///
+/// Returns an error if any recipient has an `expires` annotation for today or an earlier
+/// date, so that files cannot be encrypted to keys that have been rotated out.
+pub fn read_recipients_file<R: BufRead>(
+    filename: &str,
+    buf: R,
+) -> Result<Vec<RecipientLine>, RecipientsFileError> {
+    read_recipients_file_at(filename, buf, SystemTime::now())
+}
+
+fn read_recipients_file_at<R: BufRead>(
+    filename: &str,
+    buf: R,
+    now: SystemTime,
+) -> Result<Vec<RecipientLine>, RecipientsFileError> {
+    let mut recipients = vec![];
+    let mut annotations = Annotations::default();
+
+    for (line_number, line) in buf.lines().enumerate() {
+        let line = line?;
+        let line_number = line_number + 1;
+
+        if line.is_empty() {
+            continue;
+        } else if let Some(comment) = line.strip_prefix('#') {
+            annotations
+                .parse(comment)
+                .map_err(|()| RecipientsFileError::InvalidAnnotation {
+                    filename: filename.to_owned(),
+                    line_number,
+                })?;
+        } else {
+            let Annotations { label, expires } = std::mem::take(&mut annotations);
+
+            if let Some(expires) = expires.filter(|expires| expires.has_passed(now)) {
+                return Err(RecipientsFileError::Expired {
+                    filename: filename.to_owned(),
+                    line_number,
+                    label,
+                    expires,
+                });
+            }
+
+            recipients.push(RecipientLine {
+                line_number,
+                recipient: line,
+                label,
+                expires,
+            });
+        }
+    }
+
+    Ok(recipients)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{read_recipients_file_at, ExpiryDate, RecipientsFileError};
+
+    const RECIPIENT_A: &str = "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p";
+    const RECIPIENT_B: &str = "age1lggyhqrw2nlhcxprm67z43rta597azn8gknawjehu9d9dl0jq3yqqvfafg";
+
+    /// 2024-06-15T12:00:00Z
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_718_452_800)
+    }
+
+    #[test]
+    fn expiry_date_parsing() {
+        for valid in &["2024-02-29", "2000-02-29", "1970-01-01", "9999-12-31"] {
+            assert_eq!(valid.parse::<ExpiryDate>().unwrap().to_string(), *valid);
+        }
+        for invalid in &[
+            "2023-02-29",
+            "1900-02-29",
+            "2024-04-31",
+            "2024-13-01",
+            "2024-00-01",
+            "2024-01-00",
+            "2024-1-01",
+            "24-01-01",
+            "2024-01-01T00:00",
+            "+024-01-01",
+            "",
+        ] {
+            assert_eq!(invalid.parse::<ExpiryDate>(), Err(()), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn expiry_date_has_passed() {
+        let date = |s: &str| s.parse::<ExpiryDate>().unwrap();
+        assert_eq!(date("1970-01-01").days_since_epoch(), 0);
+        assert_eq!(date("2024-06-15").days_since_epoch(), 19_889);
+
+        assert!(date("2024-06-15").has_passed(now()));
+        assert!(date("2000-01-01").has_passed(now()));
+        assert!(!date("2024-06-16").has_passed(now()));
+    }
+
+    #[test]
+    fn annotations() {
+        let file = format!(
+            "# Team keys\n\
+             # label:alice expires:2024-06-16\n\
+             {}\n\
+             \n\
+             # expires:2030-01-01\n\
+             #label:bob\n\
+             {}\n\
+             {}\n",
+            RECIPIENT_A, RECIPIENT_B, RECIPIENT_A,
+        );
+        let recipients = read_recipients_file_at("file", file.as_bytes(), now()).unwrap();
+        assert_eq!(recipients.len(), 3);
+
+        assert_eq!(recipients[0].line_number(), 3);
+        assert_eq!(recipients[0].recipient(), RECIPIENT_A);
+        assert_eq!(recipients[0].label(), Some("alice"));
+        assert_eq!(recipients[0].expires(), "2024-06-16".parse().ok());
+
+        assert_eq!(recipients[1].line_number(), 7);
+        assert_eq!(recipients[1].recipient(), RECIPIENT_B);
+        assert_eq!(recipients[1].label(), Some("bob"));
+        assert_eq!(recipients[1].expires(), "2030-01-01".parse().ok());
+
+        // Annotations only apply to the next recipient.
+        assert_eq!(recipients[2].label(), None);
+        assert_eq!(recipients[2].expires(), None);
+    }
+
+    #[test]
+    fn expired_recipient() {
+        let file = format!(
+            "{}\n# label:alice expires:2024-06-15\n{}\n",
+            RECIPIENT_B, RECIPIENT_A
+        );
+        match read_recipients_file_at("file", file.as_bytes(), now()) {
+            Err(RecipientsFileError::Expired {
+                filename,
+                line_number,
+                label,
+                expires,
+            }) => {
+                assert_eq!(filename, "file");
+                assert_eq!(line_number, 3);
+                assert_eq!(label.as_deref(), Some("alice"));
+                assert_eq!(expires.to_string(), "2024-06-15");
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_annotation() {
+        for annotation in &[
+            "# expires:2024-13-01",
+            "# expires:soon",
+            "# label:alice expires:",
+            "# label:",
+            "# label:alice colour:blue",
+            "# label:alice and bob",
+        ] {
+            let file = format!("{}\n{}\n", annotation, RECIPIENT_A);
+            match read_recipients_file_at("file", file.as_bytes(), now()) {
+                Err(RecipientsFileError::InvalidAnnotation {
+                    filename,
+                    line_number,
+                }) => {
+                    assert_eq!(filename, "file");
+                    assert_eq!(line_number, 1);
+                }
+                r => panic!("Unexpected result for {}: {:?}", annotation, r),
+            }
+        }
+    }
+
+    #[test]
+    fn ordinary_comments() {
+        let file = format!(
+            "# Labels: none\n# alice expires:2000-01-01\n{}\n",
+            RECIPIENT_A
+        );
+        let recipients = read_recipients_file_at("file", file.as_bytes(), now()).unwrap();
+        assert_eq!(recipients.len(), 1);
+        assert_eq!(recipients[0].label(), None);
+        assert_eq!(recipients[0].expires(), None);
+    }
+}
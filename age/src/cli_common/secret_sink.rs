@@ -0,0 +1,146 @@
+//! Handing generated secrets to other programs.
+//!
+//! A generated passphrase is normally printed to the terminal, where it may be seen by
+//! others or left in the scrollback. A [`SecretSink`] stores it somewhere else instead,
+//! such as the clipboard ([`Clipboard`]) or a password manager ([`CommandSink`]).
+
+use age_core::secrecy::{ExposeSecret, SecretString};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+/// Somewhere that a generated secret can be stored, instead of showing it on screen.
+pub trait SecretSink {
+    /// Stores `secret`.
+    fn store(&self, secret: &SecretString) -> io::Result<()>;
+}
+
+/// Writes `secret` to the standard input of `program`, and waits for it to exit.
+///
+/// The program's standard output is discarded, as our own standard output may be the
+/// encrypted file.
+fn pipe_to(program: &str, args: &[&str], secret: &[u8]) -> io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    // Wait for the program even if it stopped reading early, so that it isn't left
+    // running in the background.
+    let written = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(secret);
+    let status = child.wait()?;
+    written?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            i18n_embed_fl::fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-secret-sink-failed",
+                program = program,
+                status = status.to_string(),
+            ),
+        ))
+    }
+}
+
+/// A [`SecretSink`] that writes the secret (followed by a newline) to the standard
+/// input of a command, such as `pass insert -m NAME`.
+#[derive(Clone, Debug)]
+pub struct CommandSink {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandSink {
+    /// Parses `command` into a program and its arguments, which are separated by
+    /// whitespace. Shell quoting is not supported.
+    ///
+    /// Returns `None` if `command` is empty.
+    pub fn new(command: &str) -> Option<Self> {
+        let mut words = command.split_whitespace().map(|word| word.to_owned());
+        words.next().map(|program| CommandSink {
+            program,
+            args: words.collect(),
+        })
+    }
+}
+
+impl SecretSink for CommandSink {
+    fn store(&self, secret: &SecretString) -> io::Result<()> {
+        let args: Vec<_> = self.args.iter().map(|arg| arg.as_str()).collect();
+        let mut line = Zeroizing::new(secret.expose_secret().clone());
+        line.push('\n');
+        pipe_to(&self.program, &args, line.as_bytes())
+    }
+}
+
+/// A [`SecretSink`] that copies the secret to the system clipboard.
+///
+/// This runs the platform's clipboard command: `pbcopy` on macOS, `clip` on Windows,
+/// and otherwise the first of `wl-copy`, `xclip`, and `xsel` that is installed. Other
+/// programs can read the clipboard, so it should be cleared once the secret has been
+/// saved elsewhere.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Clipboard;
+
+impl Clipboard {
+    /// The clipboard commands to try, in order.
+    fn commands() -> &'static [(&'static str, &'static [&'static str])] {
+        if cfg!(target_os = "macos") {
+            &[("pbcopy", &[])]
+        } else if cfg!(windows) {
+            &[("clip", &[])]
+        } else {
+            &[
+                ("wl-copy", &[]),
+                ("xclip", &["-selection", "clipboard"]),
+                ("xsel", &["--clipboard", "--input"]),
+            ]
+        }
+    }
+}
+
+impl SecretSink for Clipboard {
+    fn store(&self, secret: &SecretString) -> io::Result<()> {
+        for (program, args) in Clipboard::commands() {
+            match pipe_to(program, args, secret.expose_secret().as_bytes()) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                res => return res,
+            }
+        }
+
+        let commands: Vec<_> = Clipboard::commands()
+            .iter()
+            .map(|(program, _)| *program)
+            .collect();
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            i18n_embed_fl::fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-clipboard-not-found",
+                commands = commands.join(", "),
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandSink;
+
+    #[test]
+    fn command_sink_parsing() {
+        let sink = CommandSink::new("  pass insert -m backups/photos ").unwrap();
+        assert_eq!(sink.program, "pass");
+        assert_eq!(sink.args, ["insert", "-m", "backups/photos"]);
+        assert!(CommandSink::new(" ").is_none());
+    }
+}
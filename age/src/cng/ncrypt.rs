@@ -0,0 +1,286 @@
+//! CNG identities, accessed through the Windows NCrypt API.
+
+#![allow(unsafe_code)]
+
+use age_core::format::{FileKey, Stanza};
+use i18n_embed_fl::fl;
+use p256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey};
+use std::{io, mem, ptr};
+use windows_sys::{
+    core::{HRESULT, PCWSTR},
+    Win32::{
+        Foundation::NTE_BAD_KEYSET,
+        Security::Cryptography::{
+            NCryptCreatePersistedKey, NCryptDeriveKey, NCryptExportKey, NCryptFinalizeKey,
+            NCryptFreeObject, NCryptImportKey, NCryptOpenKey, NCryptOpenStorageProvider,
+            NCryptSecretAgreement, NCryptSetProperty, BCRYPT_ECCPUBLIC_BLOB,
+            BCRYPT_ECDH_P256_ALGORITHM, BCRYPT_ECDH_PUBLIC_P256_MAGIC, BCRYPT_KDF_RAW_SECRET,
+            MS_KEY_STORAGE_PROVIDER, MS_NGC_KEY_STORAGE_PROVIDER, MS_PLATFORM_CRYPTO_PROVIDER,
+            NCRYPT_HANDLE, NCRYPT_UI_FORCE_HIGH_PROTECTION_FLAG, NCRYPT_UI_POLICY,
+            NCRYPT_UI_POLICY_PROPERTY,
+        },
+    },
+};
+
+use super::Recipient;
+use crate::{error::DecryptError, piv_p256, Callbacks};
+
+/// Size in bytes of a P-256 field element, and of an ECDH shared secret.
+const FIELD_BYTES: usize = 32;
+
+/// Size in bytes of a `BCRYPT_ECCKEY_BLOB` header.
+const BLOB_HEADER_BYTES: usize = 8;
+
+/// The CNG key storage provider that holds an [`Identity`]'s private key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    /// The Microsoft Software Key Storage Provider.
+    ///
+    /// Keys are stored encrypted on disk, and are not hardware-bound.
+    Software,
+    /// The Microsoft Platform Crypto Provider, which stores keys in the TPM.
+    Tpm,
+    /// The Microsoft Passport Key Storage Provider, which protects keys with Windows
+    /// Hello.
+    WindowsHello,
+}
+
+impl Provider {
+    fn name(self) -> PCWSTR {
+        match self {
+            Provider::Software => MS_KEY_STORAGE_PROVIDER,
+            Provider::Tpm => MS_PLATFORM_CRYPTO_PROVIDER,
+            Provider::WindowsHello => MS_NGC_KEY_STORAGE_PROVIDER,
+        }
+    }
+}
+
+/// An owned NCrypt object handle.
+struct Handle(NCRYPT_HANDLE);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if self.0 != 0 {
+            // SAFETY: we own this handle, and it is not used after this point.
+            unsafe { NCryptFreeObject(self.0) };
+        }
+    }
+}
+
+fn check(status: HRESULT) -> io::Result<()> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(status))
+    }
+}
+
+/// Encodes a string as a null-terminated UTF-16 string.
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+fn open_provider(provider: Provider) -> io::Result<Handle> {
+    let mut handle = Handle(0);
+    // SAFETY: the provider name is a valid null-terminated string constant.
+    check(unsafe { NCryptOpenStorageProvider(&mut handle.0, provider.name(), 0) })?;
+    Ok(handle)
+}
+
+/// Exports the public part of a P-256 key.
+fn export_public(key: &Handle) -> io::Result<PublicKey> {
+    let mut blob = vec![0; BLOB_HEADER_BYTES + 2 * FIELD_BYTES];
+    let mut len = 0;
+    // SAFETY: `blob` is valid for writes of `blob.len()` bytes.
+    check(unsafe {
+        NCryptExportKey(
+            key.0,
+            0,
+            BCRYPT_ECCPUBLIC_BLOB,
+            ptr::null(),
+            blob.as_mut_ptr(),
+            blob.len() as u32,
+            &mut len,
+            0,
+        )
+    })?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a P-256 ECDH key");
+    if len as usize != blob.len()
+        || blob[..4] != BCRYPT_ECDH_PUBLIC_P256_MAGIC.to_le_bytes()
+        || blob[4..8] != (FIELD_BYTES as u32).to_le_bytes()
+    {
+        return Err(invalid());
+    }
+
+    // The blob contains X || Y; convert it to an uncompressed SEC1 encoding.
+    blob[BLOB_HEADER_BYTES - 1] = 0x04;
+    PublicKey::from_sec1_bytes(&blob[BLOB_HEADER_BYTES - 1..]).map_err(|_| invalid())
+}
+
+/// An identity whose private key is held by a Windows CNG key storage provider.
+///
+/// The key is persisted by the provider under a name, and can be reopened with
+/// [`Identity::open`]. Depending on the provider and the key's UI policy, Windows may
+/// show a Windows Hello or consent prompt each time the key is used.
+pub struct Identity<C: Callbacks> {
+    // Declared before `provider` so that it is freed first.
+    key: Handle,
+    provider: Handle,
+    public: PublicKey,
+    callbacks: C,
+}
+
+impl<C: Callbacks> Identity<C> {
+    /// Generates a new P-256 key in the given provider, persisted under `name`.
+    ///
+    /// If `require_consent` is `true`, the key is created with a strong key protection
+    /// UI policy, so that Windows asks the user for consent each time it is used.
+    pub fn generate(
+        name: &str,
+        provider: Provider,
+        require_consent: bool,
+        callbacks: C,
+    ) -> io::Result<Self> {
+        let provider = open_provider(provider)?;
+        let name = wide(name);
+
+        let mut key = Handle(0);
+        // SAFETY: `name` is a valid null-terminated string.
+        check(unsafe {
+            NCryptCreatePersistedKey(
+                provider.0,
+                &mut key.0,
+                BCRYPT_ECDH_P256_ALGORITHM,
+                name.as_ptr(),
+                0,
+                0,
+            )
+        })?;
+
+        if require_consent {
+            let policy = NCRYPT_UI_POLICY {
+                dwVersion: 1,
+                dwFlags: NCRYPT_UI_FORCE_HIGH_PROTECTION_FLAG,
+                pszCreationTitle: ptr::null(),
+                pszFriendlyName: name.as_ptr(),
+                pszDescription: ptr::null(),
+            };
+            // SAFETY: `policy` is a valid `NCRYPT_UI_POLICY` that outlives this call.
+            check(unsafe {
+                NCryptSetProperty(
+                    key.0,
+                    NCRYPT_UI_POLICY_PROPERTY,
+                    &policy as *const NCRYPT_UI_POLICY as *const u8,
+                    mem::size_of::<NCRYPT_UI_POLICY>() as u32,
+                    0,
+                )
+            })?;
+        }
+
+        // SAFETY: `key` is a valid key handle.
+        check(unsafe { NCryptFinalizeKey(key.0, 0) })?;
+
+        Self::from_handles(key, provider, callbacks)
+    }
+
+    /// Opens the key persisted under `name` in the given provider.
+    ///
+    /// Returns `Ok(None)` if there is no such key.
+    pub fn open(name: &str, provider: Provider, callbacks: C) -> io::Result<Option<Self>> {
+        let provider = open_provider(provider)?;
+        let name = wide(name);
+
+        let mut key = Handle(0);
+        // SAFETY: `name` is a valid null-terminated string.
+        match unsafe { NCryptOpenKey(provider.0, &mut key.0, name.as_ptr(), 0, 0) } {
+            NTE_BAD_KEYSET => Ok(None),
+            status => {
+                check(status)?;
+                Self::from_handles(key, provider, callbacks).map(Some)
+            }
+        }
+    }
+
+    fn from_handles(key: Handle, provider: Handle, callbacks: C) -> io::Result<Self> {
+        let public = export_public(&key)?;
+        Ok(Identity {
+            key,
+            provider,
+            public,
+            callbacks,
+        })
+    }
+
+    /// Returns the recipient for this identity.
+    pub fn to_public(&self) -> Recipient {
+        Recipient(self.public)
+    }
+
+    /// Computes the raw ECDH shared secret between this key and `epk`.
+    fn ecdh(&self, epk: &PublicKey) -> io::Result<Vec<u8>> {
+        let epk = epk.to_encoded_point(false);
+        let mut blob = Vec::with_capacity(BLOB_HEADER_BYTES + 2 * FIELD_BYTES);
+        blob.extend_from_slice(&BCRYPT_ECDH_PUBLIC_P256_MAGIC.to_le_bytes());
+        blob.extend_from_slice(&(FIELD_BYTES as u32).to_le_bytes());
+        // Skip the SEC1 tag byte to obtain X || Y.
+        blob.extend_from_slice(&epk.as_bytes()[1..]);
+
+        let mut epk_handle = Handle(0);
+        // SAFETY: `blob` is a valid `BCRYPT_ECCPUBLIC_BLOB` of `blob.len()` bytes.
+        check(unsafe {
+            NCryptImportKey(
+                self.provider.0,
+                0,
+                BCRYPT_ECCPUBLIC_BLOB,
+                ptr::null(),
+                &mut epk_handle.0,
+                blob.as_ptr(),
+                blob.len() as u32,
+                0,
+            )
+        })?;
+
+        let mut secret = Handle(0);
+        // SAFETY: both handles are valid key handles.
+        check(unsafe { NCryptSecretAgreement(self.key.0, epk_handle.0, &mut secret.0, 0) })?;
+
+        let mut shared_secret = vec![0; FIELD_BYTES];
+        let mut len = 0;
+        // SAFETY: `shared_secret` is valid for writes of `FIELD_BYTES` bytes.
+        check(unsafe {
+            NCryptDeriveKey(
+                secret.0,
+                BCRYPT_KDF_RAW_SECRET,
+                ptr::null(),
+                shared_secret.as_mut_ptr(),
+                FIELD_BYTES as u32,
+                &mut len,
+                0,
+            )
+        })?;
+        if len as usize != FIELD_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected shared secret length",
+            ));
+        }
+
+        // CNG returns the raw secret in little-endian byte order.
+        shared_secret.reverse();
+        Ok(shared_secret)
+    }
+}
+
+impl<C: Callbacks> crate::Identity for Identity<C> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        piv_p256::unwrap_stanza_with(&self.public, stanza, |epk| {
+            self.callbacks
+                .display_message(&fl!(crate::i18n::LANGUAGE_LOADER, "cng-waiting"));
+
+            // This fails if the user declines the Windows Hello or consent prompt.
+            self.ecdh(epk)
+                .map_err(|_| DecryptError::KeyDecryptionFailed)
+        })
+    }
+}
@@ -9,6 +9,9 @@ use crate::{
     IdentityFileEntry,
 };
 
+#[cfg(feature = "plugin")]
+use crate::plugin;
+
 /// The state of the encrypted age identity.
 enum IdentityState<R: io::Read> {
     Encrypted {
@@ -80,6 +83,8 @@ pub struct Identity<R: io::Read, C: Callbacks> {
     state: Cell<IdentityState<R>>,
     filename: Option<String>,
     callbacks: C,
+    #[cfg(feature = "plugin")]
+    allowlist: plugin::Allowlist,
 }
 
 impl<R: io::Read, C: Callbacks> Identity<R, C> {
@@ -89,6 +94,10 @@ impl<R: io::Read, C: Callbacks> Identity<R, C> {
     ///
     /// Returns `Ok(None)` if the input contains an age ciphertext that is not encrypted
     /// to a passphrase.
+    ///
+    /// If the file contains plugin identities, the user is asked via
+    /// [`Callbacks::confirm`] before running their plugins; use
+    /// [`Identity::with_plugin_allowlist`] to run some of them without asking.
     pub fn from_buffer(
         data: R,
         filename: Option<String>,
@@ -104,10 +113,51 @@ impl<R: io::Read, C: Callbacks> Identity<R, C> {
                 }),
                 filename,
                 callbacks,
+                #[cfg(feature = "plugin")]
+                allowlist: plugin::Allowlist::new(),
             })),
         }
     }
 
+    /// Sets the plugins that the identities in this file may run without asking the
+    /// user.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    pub fn with_plugin_allowlist(mut self, allowlist: plugin::Allowlist) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    fn entry_identity(
+        &self,
+        entry: IdentityFileEntry,
+    ) -> Result<Box<dyn crate::Identity>, DecryptError> {
+        #[cfg(feature = "plugin")]
+        {
+            entry.into_identity(self.callbacks.clone(), &self.allowlist)
+        }
+
+        #[cfg(not(feature = "plugin"))]
+        {
+            entry.into_identity(self.callbacks.clone())
+        }
+    }
+
+    fn entry_recipient(
+        &self,
+        entry: &IdentityFileEntry,
+    ) -> Result<Box<dyn crate::Recipient + Send>, EncryptError> {
+        #[cfg(feature = "plugin")]
+        {
+            entry.to_recipient(self.callbacks.clone(), &self.allowlist)
+        }
+
+        #[cfg(not(feature = "plugin"))]
+        {
+            entry.to_recipient(self.callbacks.clone())
+        }
+    }
+
     /// Returns the recipients contained within this encrypted identity.
     ///
     /// If this encrypted identity has not been decrypted yet, calling this method will
@@ -121,7 +171,7 @@ impl<R: io::Read, C: Callbacks> Identity<R, C> {
             Ok((identities, _)) => {
                 let recipients = identities
                     .iter()
-                    .map(|entry| entry.to_recipient(self.callbacks.clone()))
+                    .map(|entry| self.entry_recipient(entry))
                     .collect::<Result<Vec<_>, _>>();
 
                 self.state.set(IdentityState::Decrypted(identities));
@@ -162,7 +212,7 @@ impl<R: io::Read, C: Callbacks> Identity<R, C> {
             Ok((identities, requested_passphrase)) => {
                 let result = identities
                     .iter()
-                    .map(|entry| entry.clone().into_identity(self.callbacks.clone()))
+                    .map(|entry| self.entry_identity(entry.clone()))
                     .find_map(filter);
 
                 // If we requested a passphrase to decrypt, and none of the identities
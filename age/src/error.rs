@@ -46,10 +46,11 @@ impl From<Stanza> for PluginError {
     fn from(mut s: Stanza) -> Self {
         assert!(s.tag == "error");
         let kind = s.args.remove(0);
+        let message = String::from_utf8_lossy(s.body()).to_string();
         PluginError::Other {
             kind,
             metadata: s.args,
-            message: String::from_utf8_lossy(&s.body).to_string(),
+            message,
         }
     }
 }
@@ -112,6 +113,16 @@ pub enum EncryptError {
     EncryptedIdentities(DecryptError),
     /// An I/O error occurred during encryption.
     Io(io::Error),
+    /// A key management service failed to wrap the file key.
+    #[cfg(feature = "kms")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "kms")))]
+    Kms(crate::kms::KmsError),
+    /// The system's random number generator failed a health check, so no keys were
+    /// generated.
+    Rng(RngError),
+    /// Recipients can't be added to the header of an age file that was encrypted to a
+    /// threshold of recipients.
+    ThresholdHeader,
     /// A required plugin could not be found.
     #[cfg(feature = "plugin")]
     #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
@@ -123,6 +134,34 @@ pub enum EncryptError {
     #[cfg(feature = "plugin")]
     #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
     Plugin(Vec<PluginError>),
+    /// A required plugin is not in the plugin allowlist, and the user did not approve
+    /// running it.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    PluginNotAllowed {
+        /// The plugin's binary name.
+        binary_name: String,
+    },
+    /// A plugin did not follow the plugin protocol.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    PluginProtocol {
+        /// The plugin's binary name.
+        binary_name: String,
+        /// A description of what went wrong.
+        message: String,
+    },
+    /// A plugin exited without responding, most likely because it is too old to
+    /// support the required version of the plugin protocol.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    PluginTooOld {
+        /// The plugin's binary name.
+        binary_name: String,
+        /// The plugin protocol state machine that the plugin does not support, such as
+        /// `recipient-v1`.
+        state_machine: String,
+    },
 }
 
 impl From<io::Error> for EncryptError {
@@ -136,21 +175,66 @@ impl Clone for EncryptError {
         match self {
             Self::EncryptedIdentities(e) => Self::EncryptedIdentities(e.clone()),
             Self::Io(e) => Self::Io(io::Error::new(e.kind(), e.to_string())),
+            #[cfg(feature = "kms")]
+            Self::Kms(e) => Self::Kms(e.clone()),
+            Self::Rng(e) => Self::Rng(e.clone()),
+            Self::ThresholdHeader => Self::ThresholdHeader,
             #[cfg(feature = "plugin")]
             Self::MissingPlugin { binary_name } => Self::MissingPlugin {
                 binary_name: binary_name.clone(),
             },
             #[cfg(feature = "plugin")]
             Self::Plugin(e) => Self::Plugin(e.clone()),
+            #[cfg(feature = "plugin")]
+            Self::PluginNotAllowed { binary_name } => Self::PluginNotAllowed {
+                binary_name: binary_name.clone(),
+            },
+            #[cfg(feature = "plugin")]
+            Self::PluginProtocol {
+                binary_name,
+                message,
+            } => Self::PluginProtocol {
+                binary_name: binary_name.clone(),
+                message: message.clone(),
+            },
+            #[cfg(feature = "plugin")]
+            Self::PluginTooOld {
+                binary_name,
+                state_machine,
+            } => Self::PluginTooOld {
+                binary_name: binary_name.clone(),
+                state_machine: state_machine.clone(),
+            },
         }
     }
 }
 
+/// Tells the user where else plugins can be installed, if anywhere.
+#[cfg(feature = "plugin")]
+fn write_plugin_dir(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match crate::plugin::user_plugin_dir() {
+        Some(dir) => write!(
+            f,
+            "\n{}",
+            fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "rec-missing-plugin-dir",
+                plugin_dir = dir.display().to_string()
+            )
+        ),
+        None => Ok(()),
+    }
+}
+
 impl fmt::Display for EncryptError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             EncryptError::EncryptedIdentities(e) => e.fmt(f),
             EncryptError::Io(e) => e.fmt(f),
+            #[cfg(feature = "kms")]
+            EncryptError::Kms(e) => e.fmt(f),
+            EncryptError::Rng(e) => e.fmt(f),
+            EncryptError::ThresholdHeader => wfl!(f, "err-threshold-header"),
             #[cfg(feature = "plugin")]
             EncryptError::MissingPlugin { binary_name } => {
                 writeln!(
@@ -162,7 +246,8 @@ impl fmt::Display for EncryptError {
                         plugin_name = binary_name.as_str()
                     )
                 )?;
-                wfl!(f, "rec-missing-plugin")
+                wfl!(f, "rec-missing-plugin")?;
+                write_plugin_dir(f)
             }
             #[cfg(feature = "plugin")]
             EncryptError::Plugin(errors) => match &errors[..] {
@@ -176,6 +261,50 @@ impl fmt::Display for EncryptError {
                     Ok(())
                 }
             },
+            #[cfg(feature = "plugin")]
+            EncryptError::PluginNotAllowed { binary_name } => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-plugin-not-allowed",
+                    plugin_name = binary_name.as_str()
+                )
+            ),
+            #[cfg(feature = "plugin")]
+            EncryptError::PluginProtocol {
+                binary_name,
+                message,
+            } => {
+                writeln!(
+                    f,
+                    "{}",
+                    fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-plugin-protocol",
+                        plugin_name = binary_name.as_str(),
+                        message = message.as_str()
+                    )
+                )?;
+                wfl!(f, "rec-plugin-protocol")
+            }
+            #[cfg(feature = "plugin")]
+            EncryptError::PluginTooOld {
+                binary_name,
+                state_machine,
+            } => {
+                writeln!(
+                    f,
+                    "{}",
+                    fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-plugin-too-old",
+                        plugin_name = binary_name.as_str(),
+                        state_machine = state_machine.as_str()
+                    )
+                )?;
+                wfl!(f, "rec-plugin-too-old")
+            }
         }
     }
 }
@@ -185,12 +314,143 @@ impl std::error::Error for EncryptError {
         match self {
             EncryptError::EncryptedIdentities(inner) => Some(inner),
             EncryptError::Io(inner) => Some(inner),
-            #[cfg(feature = "plugin")]
+            #[cfg(feature = "kms")]
+            EncryptError::Kms(inner) => Some(inner),
+            EncryptError::Rng(inner) => Some(inner),
             _ => None,
         }
     }
 }
 
+impl From<RngError> for EncryptError {
+    fn from(e: RngError) -> Self {
+        EncryptError::Rng(e)
+    }
+}
+
+#[cfg(feature = "kms")]
+impl From<crate::kms::KmsError> for EncryptError {
+    fn from(e: crate::kms::KmsError) -> Self {
+        EncryptError::Kms(e)
+    }
+}
+
+/// The ways in which the system's random number generator can fail a health check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RngError {
+    /// The random number generator returned an error.
+    Failed(String),
+    /// The random number generator returned a constant output, such as all zeroes.
+    Constant,
+    /// The random number generator returned the same output twice in a row.
+    Repeated,
+}
+
+impl fmt::Display for RngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            RngError::Failed(e) => fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-rng-failed",
+                err = e.as_str()
+            ),
+            RngError::Constant => fl!(crate::i18n::LANGUAGE_LOADER, "err-rng-constant"),
+            RngError::Repeated => fl!(crate::i18n::LANGUAGE_LOADER, "err-rng-repeated"),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for RngError {}
+
+/// The ways in which a recipient stanza can violate the format of its type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StanzaError {
+    /// The stanza has the wrong number of arguments.
+    ArgCount {
+        /// The stanza's tag.
+        tag: String,
+        /// The number of arguments that stanzas of this type have.
+        expected: usize,
+        /// The number of arguments that the stanza has.
+        actual: usize,
+    },
+    /// An argument is not the canonical base64 encoding of the expected number of bytes.
+    Base64Arg {
+        /// The stanza's tag.
+        tag: String,
+        /// The index of the argument (starting from 0).
+        index: usize,
+        /// The number of bytes that the argument should encode.
+        expected_len: usize,
+    },
+    /// An argument is not a decimal number between 0 and 255 without leading zeroes.
+    DecimalArg {
+        /// The stanza's tag.
+        tag: String,
+        /// The index of the argument (starting from 0).
+        index: usize,
+    },
+    /// The body has the wrong length.
+    BodyLength {
+        /// The stanza's tag.
+        tag: String,
+        /// The length in bytes that bodies of this type have.
+        expected: usize,
+        /// The length in bytes of the stanza's body.
+        actual: usize,
+    },
+}
+
+impl fmt::Display for StanzaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            StanzaError::ArgCount {
+                tag,
+                expected,
+                actual,
+            } => fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-stanza-arg-count",
+                tag = tag.as_str(),
+                expected = expected,
+                actual = actual
+            ),
+            StanzaError::Base64Arg {
+                tag,
+                index,
+                expected_len,
+            } => fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-stanza-base64-arg",
+                tag = tag.as_str(),
+                position = (index + 1),
+                expected_len = expected_len
+            ),
+            StanzaError::DecimalArg { tag, index } => fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-stanza-decimal-arg",
+                tag = tag.as_str(),
+                position = (index + 1)
+            ),
+            StanzaError::BodyLength {
+                tag,
+                expected,
+                actual,
+            } => fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-stanza-body-length",
+                tag = tag.as_str(),
+                expected = expected,
+                actual = actual
+            ),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for StanzaError {}
+
 /// The various errors that can be returned during the decryption process.
 #[derive(Debug)]
 pub enum DecryptError {
@@ -205,12 +465,18 @@ pub enum DecryptError {
     },
     /// The age header was invalid.
     InvalidHeader,
+    /// A recipient stanza in the age header did not match the format of its type.
+    InvalidStanza(StanzaError),
     /// The MAC in the age header was invalid.
     InvalidMac,
     /// An I/O error occurred during decryption.
     Io(io::Error),
     /// Failed to decrypt an encrypted key.
     KeyDecryptionFailed,
+    /// A key management service failed to unwrap the file key.
+    #[cfg(feature = "kms")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "kms")))]
+    Kms(crate::kms::KmsError),
     /// A required plugin could not be found.
     #[cfg(feature = "plugin")]
     #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
@@ -224,6 +490,54 @@ pub enum DecryptError {
     #[cfg(feature = "plugin")]
     #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
     Plugin(Vec<PluginError>),
+    /// A required plugin is not in the plugin allowlist, and the user did not approve
+    /// running it.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    PluginNotAllowed {
+        /// The plugin's binary name.
+        binary_name: String,
+    },
+    /// A plugin did not follow the plugin protocol.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    PluginProtocol {
+        /// The plugin's binary name.
+        binary_name: String,
+        /// A description of what went wrong.
+        message: String,
+    },
+    /// A plugin exited without responding, most likely because it is too old to
+    /// support the required version of the plugin protocol.
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    PluginTooOld {
+        /// The plugin's binary name.
+        binary_name: String,
+        /// The plugin protocol state machine that the plugin does not support, such as
+        /// `recipient-v1`.
+        state_machine: String,
+    },
+    /// The age file was encrypted to a threshold of recipients, and the provided keys
+    /// could only unwrap some of the required shares of the file key.
+    ThresholdNotMet {
+        /// The number of shares required to decrypt.
+        threshold: u8,
+        /// The number of shares that were unwrapped.
+        shares: u8,
+    },
+    /// A plugin did not finish unwrapping a file key within the timeout set with
+    /// [`IdentityPluginV1::with_timeout`], and was terminated.
+    ///
+    /// [`IdentityPluginV1::with_timeout`]: crate::plugin::IdentityPluginV1::with_timeout
+    #[cfg(feature = "plugin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
+    Timeout {
+        /// The plugin's binary name.
+        binary_name: String,
+        /// The timeout that the plugin exceeded.
+        timeout: std::time::Duration,
+    },
     /// An unknown age format, probably from a newer version.
     UnknownFormat,
 }
@@ -237,9 +551,12 @@ impl Clone for DecryptError {
                 target: *target,
             },
             Self::InvalidHeader => Self::InvalidHeader,
+            Self::InvalidStanza(e) => Self::InvalidStanza(e.clone()),
             Self::InvalidMac => Self::InvalidMac,
             Self::Io(e) => Self::Io(io::Error::new(e.kind(), e.to_string())),
             Self::KeyDecryptionFailed => Self::KeyDecryptionFailed,
+            #[cfg(feature = "kms")]
+            Self::Kms(e) => Self::Kms(e.clone()),
             #[cfg(feature = "plugin")]
             Self::MissingPlugin { binary_name } => Self::MissingPlugin {
                 binary_name: binary_name.clone(),
@@ -247,6 +564,38 @@ impl Clone for DecryptError {
             Self::NoMatchingKeys => Self::NoMatchingKeys,
             #[cfg(feature = "plugin")]
             Self::Plugin(e) => Self::Plugin(e.clone()),
+            #[cfg(feature = "plugin")]
+            Self::PluginNotAllowed { binary_name } => Self::PluginNotAllowed {
+                binary_name: binary_name.clone(),
+            },
+            #[cfg(feature = "plugin")]
+            Self::PluginProtocol {
+                binary_name,
+                message,
+            } => Self::PluginProtocol {
+                binary_name: binary_name.clone(),
+                message: message.clone(),
+            },
+            #[cfg(feature = "plugin")]
+            Self::PluginTooOld {
+                binary_name,
+                state_machine,
+            } => Self::PluginTooOld {
+                binary_name: binary_name.clone(),
+                state_machine: state_machine.clone(),
+            },
+            Self::ThresholdNotMet { threshold, shares } => Self::ThresholdNotMet {
+                threshold: *threshold,
+                shares: *shares,
+            },
+            #[cfg(feature = "plugin")]
+            Self::Timeout {
+                binary_name,
+                timeout,
+            } => Self::Timeout {
+                binary_name: binary_name.clone(),
+                timeout: *timeout,
+            },
             Self::UnknownFormat => Self::UnknownFormat,
         }
     }
@@ -269,9 +618,12 @@ impl fmt::Display for DecryptError {
                 )
             }
             DecryptError::InvalidHeader => wfl!(f, "err-header-invalid"),
+            DecryptError::InvalidStanza(e) => e.fmt(f),
             DecryptError::InvalidMac => wfl!(f, "err-header-mac-invalid"),
             DecryptError::Io(e) => e.fmt(f),
             DecryptError::KeyDecryptionFailed => wfl!(f, "err-key-decryption"),
+            #[cfg(feature = "kms")]
+            DecryptError::Kms(e) => e.fmt(f),
             #[cfg(feature = "plugin")]
             DecryptError::MissingPlugin { binary_name } => {
                 writeln!(
@@ -283,7 +635,8 @@ impl fmt::Display for DecryptError {
                         plugin_name = binary_name.as_str()
                     )
                 )?;
-                wfl!(f, "rec-missing-plugin")
+                wfl!(f, "rec-missing-plugin")?;
+                write_plugin_dir(f)
             }
             DecryptError::NoMatchingKeys => wfl!(f, "err-no-matching-keys"),
             #[cfg(feature = "plugin")]
@@ -298,6 +651,74 @@ impl fmt::Display for DecryptError {
                     Ok(())
                 }
             },
+            #[cfg(feature = "plugin")]
+            DecryptError::PluginNotAllowed { binary_name } => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-plugin-not-allowed",
+                    plugin_name = binary_name.as_str()
+                )
+            ),
+            #[cfg(feature = "plugin")]
+            DecryptError::PluginProtocol {
+                binary_name,
+                message,
+            } => {
+                writeln!(
+                    f,
+                    "{}",
+                    fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-plugin-protocol",
+                        plugin_name = binary_name.as_str(),
+                        message = message.as_str()
+                    )
+                )?;
+                wfl!(f, "rec-plugin-protocol")
+            }
+            #[cfg(feature = "plugin")]
+            DecryptError::PluginTooOld {
+                binary_name,
+                state_machine,
+            } => {
+                writeln!(
+                    f,
+                    "{}",
+                    fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-plugin-too-old",
+                        plugin_name = binary_name.as_str(),
+                        state_machine = state_machine.as_str()
+                    )
+                )?;
+                wfl!(f, "rec-plugin-too-old")
+            }
+            DecryptError::ThresholdNotMet { threshold, shares } => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-threshold-not-met",
+                    threshold = threshold,
+                    shares = shares,
+                )
+            ),
+            #[cfg(feature = "plugin")]
+            DecryptError::Timeout {
+                binary_name,
+                timeout,
+            } => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-plugin-timeout",
+                    plugin_name = binary_name.as_str(),
+                    seconds = timeout.as_secs(),
+                )
+            ),
             DecryptError::UnknownFormat => {
                 wlnfl!(f, "err-unknown-format")?;
                 wfl!(f, "rec-unknown-format")
@@ -318,6 +739,19 @@ impl From<io::Error> for DecryptError {
     }
 }
 
+impl From<StanzaError> for DecryptError {
+    fn from(e: StanzaError) -> Self {
+        DecryptError::InvalidStanza(e)
+    }
+}
+
+#[cfg(feature = "kms")]
+impl From<crate::kms::KmsError> for DecryptError {
+    fn from(e: crate::kms::KmsError) -> Self {
+        DecryptError::Kms(e)
+    }
+}
+
 impl From<hmac::digest::MacError> for DecryptError {
     fn from(_: hmac::digest::MacError) -> Self {
         DecryptError::InvalidMac
@@ -335,7 +769,10 @@ impl From<rsa::errors::Error> for DecryptError {
 impl std::error::Error for DecryptError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
+            DecryptError::InvalidStanza(inner) => Some(inner),
             DecryptError::Io(inner) => Some(inner),
+            #[cfg(feature = "kms")]
+            DecryptError::Kms(inner) => Some(inner),
             _ => None,
         }
     }
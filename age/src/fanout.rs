@@ -0,0 +1,236 @@
+//! Writing the same age file to several outputs.
+
+use i18n_embed_fl::fl;
+use std::fmt;
+use std::io::{self, Write};
+
+/// A writer that copies everything written to it to several sinks.
+///
+/// Wrapping a `FanOut` with [`Encryptor::wrap_output`] encrypts the payload once, and
+/// writes the identical ciphertext to every sink. This is useful for replicating a
+/// backup as it is written (for example, to a local file and a network upload) without
+/// encrypting it once per destination, which would also produce different files.
+///
+/// A sink that returns an error is dropped from the fan-out, and no longer receives
+/// data. Writes to a `FanOut` succeed as long as the number of remaining sinks is at
+/// least the required number (all of them, by default; see [`FanOut::with_required`]).
+/// Once too many sinks have failed, writes return an error containing a
+/// [`FanOutError`]. [`FanOut::into_inner`] reports the outcome for each sink.
+///
+/// [`Encryptor::wrap_output`]: crate::Encryptor::wrap_output
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+///
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let key = age::x25519::Identity::generate();
+///
+/// let encryptor = age::Encryptor::with_recipients(vec![Box::new(key.to_public())])
+///     .expect("we provided a recipient");
+/// let sinks = age::fanout::FanOut::new(vec![vec![], vec![]]);
+///
+/// let mut writer = encryptor.wrap_output(sinks)?;
+/// writer.write_all(b"Hello world!")?;
+/// let sinks = writer.finish()?.into_inner();
+///
+/// let copies: Vec<Vec<u8>> = sinks.into_iter().collect::<Result<_, _>>()?;
+/// assert_eq!(copies[0], copies[1]);
+/// # Ok(())
+/// # }
+/// # run_main().unwrap();
+/// ```
+pub struct FanOut<W: Write> {
+    sinks: Vec<Result<W, io::Error>>,
+    required: usize,
+}
+
+impl<W: Write> FanOut<W> {
+    /// Creates a fan-out that requires every sink to succeed.
+    pub fn new(sinks: Vec<W>) -> Self {
+        let required = sinks.len();
+        Self::with_required(sinks, required)
+    }
+
+    /// Creates a fan-out that tolerates sinks failing, as long as at least `required`
+    /// of them succeed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `required` is zero, or greater than the number of sinks.
+    pub fn with_required(sinks: Vec<W>, required: usize) -> Self {
+        assert!(required > 0 && required <= sinks.len());
+        FanOut {
+            sinks: sinks.into_iter().map(Ok).collect(),
+            required,
+        }
+    }
+
+    /// Returns the number of sinks that have not failed.
+    pub fn healthy(&self) -> usize {
+        self.sinks.iter().filter(|sink| sink.is_ok()).count()
+    }
+
+    /// Returns the sinks, in the order they were provided, or the error that caused each
+    /// failed sink to be dropped.
+    pub fn into_inner(self) -> Vec<Result<W, io::Error>> {
+        self.sinks
+    }
+
+    /// Applies `op` to every healthy sink, dropping the sinks for which it fails.
+    fn for_each_sink(&mut self, mut op: impl FnMut(&mut W) -> io::Result<()>) -> io::Result<()> {
+        for sink in &mut self.sinks {
+            if let Ok(inner) = sink {
+                if let Err(e) = op(inner) {
+                    *sink = Err(e);
+                }
+            }
+        }
+
+        let healthy = self.healthy();
+        if healthy < self.required {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                FanOutError {
+                    failed: self
+                        .sinks
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, sink)| sink.as_ref().err().map(|e| (i, e.to_string())))
+                        .collect(),
+                    healthy,
+                    required: self.required,
+                },
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<W: Write> Write for FanOut<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Each sink must receive exactly the same bytes, so we write all of `buf` to
+        // every sink rather than following the progress of any single one.
+        self.for_each_sink(|sink| sink.write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.for_each_sink(|sink| sink.flush())
+    }
+}
+
+/// The error returned by a [`FanOut`] once too many of its sinks have failed.
+#[derive(Clone, Debug)]
+pub struct FanOutError {
+    failed: Vec<(usize, String)>,
+    healthy: usize,
+    required: usize,
+}
+
+impl FanOutError {
+    /// Returns the index and error message of each sink that has failed.
+    pub fn failed(&self) -> &[(usize, String)] {
+        &self.failed
+    }
+}
+
+impl fmt::Display for FanOutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-fanout-failed",
+                healthy = self.healthy,
+                required = self.required,
+            )
+        )?;
+        for (i, e) in &self.failed {
+            write!(
+                f,
+                "\n- {}",
+                fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-fanout-sink",
+                    index = (i + 1),
+                    err = e.as_str(),
+                )
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FanOutError {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use super::{FanOut, FanOutError};
+
+    /// A sink that fails after accepting `limit` bytes.
+    struct Limited {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() + buf.len() > self.limit {
+                Err(io::Error::new(io::ErrorKind::Other, "full"))
+            } else {
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn limited(limit: usize) -> Limited {
+        Limited {
+            written: vec![],
+            limit,
+        }
+    }
+
+    #[test]
+    fn all_required() {
+        let mut fanout = FanOut::new(vec![limited(100), limited(5)]);
+        fanout.write_all(b"abcd").unwrap();
+        let e = fanout.write_all(b"efgh").unwrap_err();
+        let e = e.get_ref().unwrap().downcast_ref::<FanOutError>().unwrap();
+        assert_eq!(e.failed(), &[(1, "full".to_owned())]);
+
+        let sinks = fanout.into_inner();
+        assert_eq!(sinks[0].as_ref().unwrap().written, b"abcdefgh");
+        assert!(sinks[1].is_err());
+    }
+
+    #[test]
+    fn tolerates_failures() {
+        let mut fanout = FanOut::with_required(vec![limited(100), limited(5), limited(100)], 2);
+        fanout.write_all(b"abcd").unwrap();
+        fanout.write_all(b"efgh").unwrap();
+        assert_eq!(fanout.healthy(), 2);
+
+        let sinks = fanout.into_inner();
+        assert_eq!(sinks[0].as_ref().unwrap().written, b"abcdefgh");
+        assert!(sinks[1].is_err());
+        assert_eq!(sinks[2].as_ref().unwrap().written, b"abcdefgh");
+    }
+
+    #[test]
+    fn too_many_failures() {
+        let mut fanout = FanOut::with_required(vec![limited(2), limited(3), limited(100)], 2);
+        assert!(fanout.write_all(b"abcd").is_err());
+        assert_eq!(fanout.healthy(), 1);
+    }
+}
@@ -0,0 +1,519 @@
+//! The "fido2-hmac" recipient type, backed by a FIDO2 security key.
+//!
+//! File keys are wrapped with a key derived from the `hmac-secret` extension of a
+//! credential on the security key, with a random salt for each file. The credential's
+//! secret never leaves the security key, and it can only be used when the user touches
+//! the key, so most cheap FIDO2 security keys can protect age files.
+//!
+//! Unlike other recipient types, these recipients are symmetric: encrypting to a
+//! [`Recipient`] also requires the security key. The recipient string only identifies
+//! the credential, and [`IdentityStub`]s (`AGE-FIDO2-HMAC-1...`) can be stored in
+//! identity files in place of a private key.
+//!
+//! Security keys are used over USB. Prompts to insert or touch the security key, and for
+//! its PIN (which is only needed to create a credential, if the key has a PIN), are shown
+//! with [`Callbacks`].
+
+use age_core::{
+    format::{FileKey, Stanza, FILE_KEY_BYTES},
+    primitives::{aead_decrypt, aead_encrypt, hkdf},
+    secrecy::ExposeSecret,
+};
+use bech32::{ToBase32, Variant};
+use i18n_embed_fl::fl;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroize;
+
+use crate::{
+    error::{DecryptError, EncryptError},
+    stanza::{Arg, Body, Rule},
+    util::{parse_bech32, read::base64_arg},
+    Callbacks,
+};
+
+mod ctap;
+
+use ctap::Authenticator;
+
+const RECIPIENT_PREFIX: &str = "age1fido2hmac";
+const IDENTITY_PREFIX: &str = "age-fido2-hmac-";
+
+const STANZA_TAG: &str = "fido2-hmac";
+const STANZA_KEY_LABEL: &[u8] = b"age-encryption.org/v1/fido2-hmac";
+
+/// The relying party that credentials are created for.
+const RP_ID: &str = "age-encryption.org";
+
+const TAG_BYTES: usize = 4;
+const SALT_BYTES: usize = 32;
+const ENCRYPTED_FILE_KEY_BYTES: usize = FILE_KEY_BYTES + 16;
+
+/// The format of a fido2-hmac recipient stanza.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: STANZA_TAG,
+    args: &[Arg::Base64(TAG_BYTES), Arg::Base64(SALT_BYTES)],
+    body: Body::Exact(ENCRYPTED_FILE_KEY_BYTES),
+};
+
+/// The short identifier that is used to match stanzas to credentials without asking the
+/// user to touch the security key.
+fn credential_tag(credential_id: &[u8]) -> [u8; TAG_BYTES] {
+    Sha256::digest(credential_id)[..TAG_BYTES]
+        .try_into()
+        .expect("length is correct")
+}
+
+fn ctap_error(e: ctap::Error) -> io::Error {
+    match e {
+        ctap::Error::Io(e) => e,
+        ctap::Error::Status(ctap::ERR_PIN_BLOCKED)
+        | ctap::Error::Status(ctap::ERR_PIN_AUTH_BLOCKED) => io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            fl!(crate::i18n::LANGUAGE_LOADER, "err-fido2-pin-blocked"),
+        ),
+        ctap::Error::Status(status) => io::Error::new(
+            io::ErrorKind::Other,
+            fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-fido2-ctap",
+                status = format!("0x{:02x}", status)
+            ),
+        ),
+        ctap::Error::NoHmacSecret => io::Error::new(
+            io::ErrorKind::Unsupported,
+            fl!(crate::i18n::LANGUAGE_LOADER, "err-fido2-no-hmac-secret"),
+        ),
+    }
+}
+
+/// Opens the first connected security key.
+type Connector = Arc<dyn Fn() -> io::Result<Option<Authenticator>> + Send + Sync>;
+
+/// A credential on a security key, shared by a recipient and identity for it.
+#[derive(Clone)]
+struct Credential<C: Callbacks> {
+    id: Vec<u8>,
+    callbacks: C,
+    connect: Connector,
+    authenticator: Arc<Mutex<Option<Authenticator>>>,
+}
+
+impl<C: Callbacks> Credential<C> {
+    fn new(id: Vec<u8>, callbacks: C) -> Self {
+        Credential {
+            id,
+            callbacks,
+            connect: Arc::new(ctap::hid::open),
+            authenticator: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Calls `f` with the security key, connecting to it first if necessary.
+    ///
+    /// Returns `Ok(None)` if no security key is connected, and the user declined to
+    /// insert one.
+    fn with_authenticator<T>(
+        connect: &Connector,
+        authenticator: &Mutex<Option<Authenticator>>,
+        callbacks: &C,
+        f: impl FnOnce(&mut Authenticator) -> Result<T, ctap::Error>,
+    ) -> io::Result<Option<T>> {
+        let mut authenticator = authenticator.lock().expect("not poisoned");
+
+        while authenticator.is_none() {
+            *authenticator = connect()?;
+            if authenticator.is_none() {
+                let inserted = callbacks.confirm(
+                    &fl!(crate::i18n::LANGUAGE_LOADER, "fido2-insert"),
+                    &fl!(crate::i18n::LANGUAGE_LOADER, "fido2-insert-retry"),
+                    Some(&fl!(crate::i18n::LANGUAGE_LOADER, "fido2-insert-skip")),
+                );
+                if inserted != Some(true) {
+                    return Ok(None);
+                }
+            }
+        }
+
+        let result = f(authenticator.as_mut().expect("connected above"));
+        if let Err(ctap::Error::Io(_)) = result {
+            // The security key might have been removed; reconnect next time.
+            *authenticator = None;
+        }
+        result.map(Some).map_err(ctap_error)
+    }
+
+    /// Derives the key that wraps a file key with the given salt, asking the user to
+    /// touch the security key.
+    ///
+    /// Returns `Ok(None)` if the user declined to insert a security key, or the
+    /// connected security key doesn't have this credential.
+    fn wrapping_key(&self, salt: &[u8; SALT_BYTES]) -> io::Result<Option<[u8; 32]>> {
+        let result = Self::with_authenticator(
+            &self.connect,
+            &self.authenticator,
+            &self.callbacks,
+            |authenticator| {
+                self.callbacks
+                    .display_message(&fl!(crate::i18n::LANGUAGE_LOADER, "fido2-touch"));
+                match authenticator.hmac_secret(RP_ID, &self.id, salt) {
+                    Ok(output) => Ok(Some(output)),
+                    Err(ctap::Error::Status(ctap::ERR_NO_CREDENTIALS)) => Ok(None),
+                    Err(e) => Err(e),
+                }
+            },
+        )?;
+
+        Ok(result.flatten().map(|mut output| {
+            let key = hkdf(&[], STANZA_KEY_LABEL, &output);
+            output.zeroize();
+            key
+        }))
+    }
+}
+
+/// A recipient for a credential on a FIDO2 security key.
+///
+/// Encrypting to this recipient requires the security key, and asks the user to touch
+/// it. Files encrypted to it can be decrypted with the corresponding [`Identity`].
+#[derive(Clone)]
+pub struct Recipient<C: Callbacks>(Credential<C>);
+
+impl<C: Callbacks> Recipient<C> {
+    /// Parses a FIDO2 recipient (`age1fido2hmac1...`), which will use `callbacks` to ask
+    /// the user to insert or touch the security key.
+    pub fn new(recipient: &str, callbacks: C) -> Result<Self, &'static str> {
+        let (hrp, credential_id) = parse_bech32(recipient).ok_or("invalid Bech32 encoding")?;
+        if hrp != RECIPIENT_PREFIX {
+            return Err("incorrect HRP");
+        }
+        if credential_id.is_empty() {
+            return Err("missing credential ID");
+        }
+        Ok(Recipient(Credential::new(credential_id, callbacks)))
+    }
+}
+
+impl<C: Callbacks> fmt::Display for Recipient<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            bech32::encode(RECIPIENT_PREFIX, self.0.id.to_base32(), Variant::Bech32)
+                .expect("HRP is valid")
+        )
+    }
+}
+
+impl<C: Callbacks> crate::Recipient for Recipient<C> {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        let mut salt = [0; SALT_BYTES];
+        OsRng.fill_bytes(&mut salt);
+
+        let wrapping_key = self.0.wrapping_key(&salt)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                fl!(crate::i18n::LANGUAGE_LOADER, "err-fido2-no-credential"),
+            )
+        })?;
+        let encrypted_file_key = aead_encrypt(&wrapping_key, file_key.expose_secret());
+
+        Ok(vec![Stanza::new(
+            STANZA_TAG.to_owned(),
+            vec![
+                base64::encode_config(credential_tag(&self.0.id), base64::STANDARD_NO_PAD),
+                base64::encode_config(salt, base64::STANDARD_NO_PAD),
+            ],
+            encrypted_file_key,
+        )])
+    }
+}
+
+/// A reference to a credential on a FIDO2 security key, which can be stored in an
+/// identity file (as `AGE-FIDO2-HMAC-1...`) in place of a private key.
+///
+/// The credential ID is not secret on its own: it can only be used with the security
+/// key that created it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityStub {
+    credential_id: Vec<u8>,
+}
+
+impl std::str::FromStr for IdentityStub {
+    type Err = &'static str;
+
+    /// Parses a FIDO2 identity stub from a string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, credential_id) = parse_bech32(s).ok_or("invalid Bech32 encoding")?;
+        if hrp != IDENTITY_PREFIX {
+            return Err("incorrect HRP");
+        }
+        if credential_id.is_empty() {
+            return Err("missing credential ID");
+        }
+        Ok(IdentityStub { credential_id })
+    }
+}
+
+impl fmt::Display for IdentityStub {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            bech32::encode(
+                IDENTITY_PREFIX,
+                self.credential_id.to_base32(),
+                Variant::Bech32
+            )
+            .expect("HRP is valid")
+            .to_uppercase()
+        )
+    }
+}
+
+/// An identity for a credential on a FIDO2 security key.
+///
+/// The security key is connected to when a file with a stanza for the credential is
+/// decrypted. If it isn't connected, the user is asked to insert it with
+/// [`Callbacks::confirm`].
+pub struct Identity<C: Callbacks>(Credential<C>);
+
+impl<C: Callbacks> Identity<C> {
+    /// Creates a new credential on the first connected security key, asking the user to
+    /// insert it if necessary, and for its PIN if it has one.
+    ///
+    /// Returns `Ok(None)` if no security key is connected, and the user declined to
+    /// insert one.
+    pub fn generate(callbacks: C) -> io::Result<Option<Self>> {
+        Self::generate_with(Arc::new(ctap::hid::open), callbacks)
+    }
+
+    fn generate_with(connect: Connector, callbacks: C) -> io::Result<Option<Self>> {
+        let authenticator = Arc::new(Mutex::new(None));
+        let credential_id = Credential::with_authenticator(
+            &connect,
+            &authenticator,
+            &callbacks,
+            |authenticator| {
+                let mut pin_token = None;
+                loop {
+                    callbacks.display_message(&fl!(crate::i18n::LANGUAGE_LOADER, "fido2-touch"));
+                    match authenticator.make_credential(RP_ID, pin_token.as_deref()) {
+                        Err(ctap::Error::Status(ctap::ERR_PIN_REQUIRED)) if pin_token.is_none() => {
+                            pin_token = Some(Self::pin_token(authenticator, &callbacks)?);
+                        }
+                        result => return result,
+                    }
+                }
+            },
+        )?;
+
+        Ok(credential_id.map(|id| {
+            Identity(Credential {
+                id,
+                callbacks,
+                connect,
+                authenticator,
+            })
+        }))
+    }
+
+    /// Asks the user for the security key's PIN until it is correct, and returns a PIN
+    /// token.
+    fn pin_token(authenticator: &mut Authenticator, callbacks: &C) -> Result<Vec<u8>, ctap::Error> {
+        loop {
+            let pin = callbacks
+                .request_passphrase(&fl!(crate::i18n::LANGUAGE_LOADER, "fido2-pin-prompt"))
+                .ok_or_else(|| {
+                    ctap::Error::Io(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        fl!(crate::i18n::LANGUAGE_LOADER, "err-fido2-pin-required"),
+                    ))
+                })?;
+            match authenticator.pin_token(pin.expose_secret().as_bytes()) {
+                Err(ctap::Error::Status(ctap::ERR_PIN_INVALID)) => callbacks
+                    .display_message(&fl!(crate::i18n::LANGUAGE_LOADER, "fido2-pin-incorrect")),
+                result => return result,
+            }
+        }
+    }
+
+    /// Creates an identity for the credential referenced by `stub`.
+    pub fn from_stub(stub: IdentityStub, callbacks: C) -> Self {
+        Identity(Credential::new(stub.credential_id, callbacks))
+    }
+
+    /// Returns the stub referencing this identity's credential, which can be written to
+    /// an identity file.
+    pub fn to_stub(&self) -> IdentityStub {
+        IdentityStub {
+            credential_id: self.0.id.clone(),
+        }
+    }
+
+    /// Returns the recipient for the same credential. It shares this identity's
+    /// connection to the security key.
+    pub fn to_recipient(&self) -> Recipient<C> {
+        Recipient(self.0.clone())
+    }
+}
+
+impl<C: Callbacks> crate::Identity for Identity<C> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        if stanza.tag != STANZA_TAG {
+            return None;
+        }
+
+        // Enforce valid and canonical stanza format.
+        if let Err(e) = STANZA_RULE.check(stanza) {
+            return Some(Err(e.into()));
+        }
+        let tag = base64_arg(&stanza.args[0], [0; TAG_BYTES])
+            .expect("Argument should have been checked above");
+        let salt = base64_arg(&stanza.args[1], [0; SALT_BYTES])
+            .expect("Argument should have been checked above");
+
+        // This stanza is for a different credential.
+        if tag != credential_tag(&self.0.id) {
+            return None;
+        }
+
+        let wrapping_key = match self.0.wrapping_key(&salt) {
+            Ok(Some(wrapping_key)) => wrapping_key,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        // Tags are only four bytes, so a collision with another credential is possible;
+        // treat a failure to decrypt as "not ours" rather than a fatal error.
+        aead_decrypt(&wrapping_key, FILE_KEY_BYTES, stanza.body())
+            .ok()
+            .map(|mut pt| {
+                let file_key: [u8; FILE_KEY_BYTES] = pt[..].try_into().unwrap();
+                pt.zeroize();
+                Ok(file_key.into())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::FileKey,
+        secrecy::{ExposeSecret, SecretString},
+    };
+    use std::sync::{Arc, Mutex};
+
+    use super::{
+        ctap::{
+            tests::{MockAuthenticator, PIN},
+            Authenticator,
+        },
+        Identity, IdentityStub, Recipient,
+    };
+    use crate::{Callbacks, Identity as _, Recipient as _};
+
+    /// Callbacks that answer PIN requests from a list, and record messages.
+    #[derive(Clone, Default)]
+    struct TestCallbacks {
+        pins: Arc<Mutex<Vec<&'static str>>>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Callbacks for TestCallbacks {
+        fn display_message(&self, message: &str) {
+            self.messages.lock().unwrap().push(message.to_owned());
+        }
+
+        fn request_passphrase(&self, _: &str) -> Option<SecretString> {
+            let mut pins = self.pins.lock().unwrap();
+            (!pins.is_empty()).then(|| SecretString::new(pins.remove(0).to_owned()))
+        }
+    }
+
+    fn generate(mock: &MockAuthenticator, callbacks: TestCallbacks) -> Identity<TestCallbacks> {
+        let mock = mock.clone();
+        Identity::generate_with(
+            Arc::new(move || Ok(Some(Authenticator::new(Box::new(mock.clone()))))),
+            callbacks,
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn encodings() {
+        let identity = generate(&MockAuthenticator::new(false), TestCallbacks::default());
+
+        let stub = identity.to_stub();
+        let encoded = stub.to_string();
+        assert!(encoded.starts_with("AGE-FIDO2-HMAC-1"));
+        assert_eq!(encoded.parse::<IdentityStub>().unwrap(), stub);
+
+        let recipient = identity.to_recipient().to_string();
+        assert!(recipient.starts_with("age1fido2hmac1"));
+        assert_eq!(
+            Recipient::new(&recipient, TestCallbacks::default())
+                .unwrap()
+                .to_string(),
+            recipient
+        );
+        assert!(Recipient::new(&encoded, TestCallbacks::default()).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mock = MockAuthenticator::new(true);
+        let callbacks = TestCallbacks::default();
+        callbacks
+            .pins
+            .lock()
+            .unwrap()
+            .extend_from_slice(&["0000", std::str::from_utf8(PIN).unwrap()]);
+        let identity = generate(&mock, callbacks.clone());
+        // The user is asked again after entering the wrong PIN.
+        assert!(callbacks.pins.lock().unwrap().is_empty());
+
+        let file_key = FileKey::from([7; 16]);
+        let stanzas = identity.to_recipient().wrap_file_key(&file_key).unwrap();
+        assert_eq!(stanzas[0].tag, "fido2-hmac");
+
+        let unwrapped = identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+        assert_eq!(mock.state.lock().unwrap().touches, 3);
+
+        // Each file has its own salt.
+        let other = identity.to_recipient().wrap_file_key(&file_key).unwrap();
+        assert_ne!(other[0].args[1], stanzas[0].args[1]);
+
+        // Stanzas for other credentials don't need a touch.
+        callbacks
+            .pins
+            .lock()
+            .unwrap()
+            .push(std::str::from_utf8(PIN).unwrap());
+        let other = generate(&mock, callbacks);
+        let touches = mock.state.lock().unwrap().touches;
+        assert!(other.unwrap_stanza(&stanzas[0]).is_none());
+        assert_eq!(mock.state.lock().unwrap().touches, touches);
+    }
+
+    #[test]
+    fn other_security_key() {
+        let identity = generate(&MockAuthenticator::new(false), TestCallbacks::default());
+        let file_key = FileKey::from([7; 16]);
+        let stanzas = identity.to_recipient().wrap_file_key(&file_key).unwrap();
+
+        // A security key without the credential can't unwrap the stanza.
+        let other = MockAuthenticator::new(false);
+        let identity = Identity(super::Credential {
+            connect: Arc::new(move || Ok(Some(Authenticator::new(Box::new(other.clone()))))),
+            authenticator: Arc::new(Mutex::new(None)),
+            ..identity.0
+        });
+        assert!(identity.unwrap_stanza(&stanzas[0]).is_none());
+    }
+}
@@ -0,0 +1,872 @@
+//! A minimal CTAP 2.0 client, for FIDO2 credentials with the `hmac-secret` extension.
+//!
+//! Only the parts of the protocol that age needs are implemented: creating a
+//! credential, evaluating `hmac-secret` with it, and version 1 of the PIN protocol
+//! (which `hmac-secret` uses to encrypt its inputs and outputs, and which is needed to
+//! create credentials on authenticators that have a PIN).
+
+use aes::{
+    cipher::{block_padding::NoPadding, BlockDecryptMut, BlockEncryptMut, KeyIvInit},
+    Aes256,
+};
+use ciborium::value::Value;
+use hmac::{Hmac, Mac};
+use p256::{ecdh::EphemeralSecret, elliptic_curve::sec1::ToEncodedPoint, PublicKey};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::io;
+use zeroize::Zeroize;
+
+const MAKE_CREDENTIAL: u8 = 0x01;
+const GET_ASSERTION: u8 = 0x02;
+const CLIENT_PIN: u8 = 0x06;
+
+const PIN_PROTOCOL: i64 = 1;
+const GET_KEY_AGREEMENT: i64 = 0x02;
+const GET_PIN_TOKEN: i64 = 0x05;
+
+/// The `hmac-secret` extension identifier.
+const HMAC_SECRET: &str = "hmac-secret";
+
+/// Flags in authenticator data.
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+const FLAG_EXTENSION_DATA: u8 = 0x80;
+/// The length of the RP ID hash, flags, and signature counter in authenticator data.
+const AUTH_DATA_HEADER_BYTES: usize = 37;
+const AAGUID_BYTES: usize = 16;
+
+pub(super) const STATUS_OK: u8 = 0x00;
+pub(super) const ERR_NO_CREDENTIALS: u8 = 0x2e;
+pub(super) const ERR_PIN_INVALID: u8 = 0x31;
+pub(super) const ERR_PIN_BLOCKED: u8 = 0x32;
+pub(super) const ERR_PIN_AUTH_BLOCKED: u8 = 0x34;
+pub(super) const ERR_PIN_REQUIRED: u8 = 0x36;
+
+/// A way of sending CTAP2 commands to an authenticator.
+pub(super) trait Transport: Send {
+    /// Sends the CBOR-encoded `request` for `command`, and returns the authenticator's
+    /// response (its status byte, followed by the CBOR-encoded response).
+    fn cbor(&mut self, command: u8, request: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// The ways that a CTAP2 command can fail.
+#[derive(Debug)]
+pub(super) enum Error {
+    /// The authenticator couldn't be communicated with.
+    Io(io::Error),
+    /// The authenticator returned an error status.
+    Status(u8),
+    /// The authenticator doesn't support the `hmac-secret` extension.
+    NoHmacSecret,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+fn invalid_response() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "invalid CTAP2 response",
+    ))
+}
+
+fn int(value: i64) -> Value {
+    Value::Integer(value.into())
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_owned())
+}
+
+/// Returns the value for the integer `key` in the CBOR map `map`.
+fn get(map: &Value, key: i64) -> Option<&Value> {
+    match map {
+        Value::Map(entries) => entries.iter().find_map(|(k, v)| match k {
+            Value::Integer(k) if i64::try_from(*k) == Ok(key) => Some(v),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the value for the string `key` in the CBOR map `map`.
+fn get_text<'a>(map: &'a Value, key: &str) -> Option<&'a Value> {
+    match map {
+        Value::Map(entries) => entries.iter().find_map(|(k, v)| match k {
+            Value::Text(k) if k == key => Some(v),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn bytes(value: Option<&Value>) -> Option<&[u8]> {
+    match value {
+        Some(Value::Bytes(bytes)) => Some(bytes),
+        _ => None,
+    }
+}
+
+/// Encodes a P-256 public key as a COSE key for ECDH-ES+HKDF-256, as the PIN protocol
+/// requires.
+fn cose_key(pk: &PublicKey) -> Value {
+    let point = pk.to_encoded_point(false);
+    Value::Map(vec![
+        (int(1), int(2)),
+        (int(3), int(-25)),
+        (int(-1), int(1)),
+        (
+            int(-2),
+            Value::Bytes(point.x().expect("not identity").to_vec()),
+        ),
+        (
+            int(-3),
+            Value::Bytes(point.y().expect("not compressed").to_vec()),
+        ),
+    ])
+}
+
+fn parse_cose_key(key: &Value) -> Option<PublicKey> {
+    let mut sec1 = vec![0x04];
+    for coordinate in [-2, -3] {
+        match bytes(get(key, coordinate)) {
+            Some(coordinate) if coordinate.len() == 32 => sec1.extend_from_slice(coordinate),
+            _ => return None,
+        }
+    }
+    PublicKey::from_sec1_bytes(&sec1).ok()
+}
+
+/// Splits authenticator data into its flags, and the data after its header.
+fn parse_auth_data(response: &Value) -> Result<(u8, &[u8]), Error> {
+    match bytes(get(response, 2)) {
+        Some(auth_data) if auth_data.len() >= AUTH_DATA_HEADER_BYTES => Ok((
+            auth_data[AUTH_DATA_HEADER_BYTES - 5],
+            &auth_data[AUTH_DATA_HEADER_BYTES..],
+        )),
+        _ => Err(invalid_response()),
+    }
+}
+
+/// Returns 32 random bytes, for use as a client data hash. We don't verify the
+/// authenticator's signatures, so the client data isn't needed.
+fn client_data_hash() -> Value {
+    let mut hash = vec![0; 32];
+    OsRng.fill_bytes(&mut hash);
+    Value::Bytes(hash)
+}
+
+/// The secret shared with an authenticator by version 1 of the PIN protocol.
+struct SharedSecret {
+    /// The platform's public key, which the authenticator needs to compute the secret.
+    platform_key: Value,
+    key: [u8; 32],
+}
+
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl SharedSecret {
+    fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        cbc::Encryptor::<Aes256>::new_from_slices(&self.key, &[0; 16])
+            .expect("key and IV are correct length")
+            .encrypt_padded_vec_mut::<NoPadding>(plaintext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        cbc::Decryptor::<Aes256>::new_from_slices(&self.key, &[0; 16])
+            .expect("key and IV are correct length")
+            .decrypt_padded_vec_mut::<NoPadding>(ciphertext)
+            .map_err(|_| invalid_response())
+    }
+
+    fn authenticate(&self, data: &[u8]) -> Value {
+        authenticate(&self.key, data)
+    }
+}
+
+/// Computes the 16-byte HMAC that the PIN protocol uses to authenticate parameters.
+fn authenticate(key: &[u8], data: &[u8]) -> Value {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length is valid");
+    mac.update(data);
+    Value::Bytes(mac.finalize().into_bytes()[..16].to_vec())
+}
+
+/// A FIDO2 authenticator.
+pub(super) struct Authenticator {
+    transport: Box<dyn Transport>,
+}
+
+impl Authenticator {
+    pub(super) fn new(transport: Box<dyn Transport>) -> Self {
+        Authenticator { transport }
+    }
+
+    fn call(&mut self, command: u8, request: Value) -> Result<Value, Error> {
+        let mut data = vec![];
+        ciborium::ser::into_writer(&request, &mut data).expect("can serialize to a Vec");
+        let response = self.transport.cbor(command, &data)?;
+        match response.split_first() {
+            Some((&STATUS_OK, [])) => Ok(Value::Map(vec![])),
+            Some((&STATUS_OK, response)) => {
+                ciborium::de::from_reader(response).map_err(|_| invalid_response())
+            }
+            Some((&status, _)) => Err(Error::Status(status)),
+            None => Err(invalid_response()),
+        }
+    }
+
+    /// Agrees on a shared secret with the authenticator.
+    fn key_agreement(&mut self) -> Result<SharedSecret, Error> {
+        let response = self.call(
+            CLIENT_PIN,
+            Value::Map(vec![
+                (int(1), int(PIN_PROTOCOL)),
+                (int(2), int(GET_KEY_AGREEMENT)),
+            ]),
+        )?;
+        let authenticator_key = get(&response, 1)
+            .and_then(parse_cose_key)
+            .ok_or_else(invalid_response)?;
+
+        let esk = EphemeralSecret::random(&mut OsRng);
+        let shared_secret = esk.diffie_hellman(&authenticator_key);
+        Ok(SharedSecret {
+            platform_key: cose_key(&esk.public_key()),
+            key: Sha256::digest(shared_secret.raw_secret_bytes()).into(),
+        })
+    }
+
+    /// Exchanges the authenticator's PIN for a token that authorizes commands.
+    pub(super) fn pin_token(&mut self, pin: &[u8]) -> Result<Vec<u8>, Error> {
+        let shared_secret = self.key_agreement()?;
+        let mut pin_hash: [u8; 32] = Sha256::digest(pin).into();
+        let pin_hash_enc = shared_secret.encrypt(&pin_hash[..16]);
+        pin_hash.zeroize();
+
+        let response = self.call(
+            CLIENT_PIN,
+            Value::Map(vec![
+                (int(1), int(PIN_PROTOCOL)),
+                (int(2), int(GET_PIN_TOKEN)),
+                (int(3), shared_secret.platform_key.clone()),
+                (int(6), Value::Bytes(pin_hash_enc)),
+            ]),
+        )?;
+        shared_secret.decrypt(bytes(get(&response, 2)).ok_or_else(invalid_response)?)
+    }
+
+    /// Creates a credential with the `hmac-secret` extension for `rp_id`, returning its
+    /// ID.
+    ///
+    /// If the authenticator has a PIN, a `pin_token` is required.
+    pub(super) fn make_credential(
+        &mut self,
+        rp_id: &str,
+        pin_token: Option<&[u8]>,
+    ) -> Result<Vec<u8>, Error> {
+        let client_data_hash = client_data_hash();
+        let mut user_id = vec![0; 16];
+        OsRng.fill_bytes(&mut user_id);
+
+        let mut request = vec![
+            (int(1), client_data_hash.clone()),
+            (int(2), Value::Map(vec![(text("id"), text(rp_id))])),
+            (
+                int(3),
+                Value::Map(vec![
+                    (text("id"), Value::Bytes(user_id)),
+                    (text("name"), text("age")),
+                ]),
+            ),
+            (
+                int(4),
+                Value::Array(vec![Value::Map(vec![
+                    (text("alg"), int(-7)),
+                    (text("type"), text("public-key")),
+                ])]),
+            ),
+            (
+                int(6),
+                Value::Map(vec![(text(HMAC_SECRET), Value::Bool(true))]),
+            ),
+        ];
+        if let (Some(pin_token), Value::Bytes(client_data_hash)) = (pin_token, &client_data_hash) {
+            request.push((int(8), authenticate(pin_token, client_data_hash)));
+            request.push((int(9), int(PIN_PROTOCOL)));
+        }
+
+        let response = self.call(MAKE_CREDENTIAL, Value::Map(request))?;
+        let (flags, data) = parse_auth_data(&response)?;
+        if flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 || data.len() < AAGUID_BYTES + 2 {
+            return Err(invalid_response());
+        }
+        let data = &data[AAGUID_BYTES..];
+        let credential_id_len = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let credential_id = data
+            .get(2..2 + credential_id_len)
+            .ok_or_else(invalid_response)?;
+
+        // The extension outputs follow the credential public key.
+        let mut rest = &data[2 + credential_id_len..];
+        let _: Value = ciborium::de::from_reader(&mut rest).map_err(|_| invalid_response())?;
+        let extensions = if flags & FLAG_EXTENSION_DATA != 0 {
+            ciborium::de::from_reader(rest).map_err(|_| invalid_response())?
+        } else {
+            Value::Map(vec![])
+        };
+        match get_text(&extensions, HMAC_SECRET) {
+            Some(Value::Bool(true)) => Ok(credential_id.to_vec()),
+            _ => Err(Error::NoHmacSecret),
+        }
+    }
+
+    /// Evaluates `hmac-secret` for the credential `credential_id` of `rp_id` with the
+    /// given salt. The authenticator requires the user to touch it.
+    pub(super) fn hmac_secret(
+        &mut self,
+        rp_id: &str,
+        credential_id: &[u8],
+        salt: &[u8; 32],
+    ) -> Result<[u8; 32], Error> {
+        let shared_secret = self.key_agreement()?;
+        let salt_enc = shared_secret.encrypt(salt);
+        let salt_auth = shared_secret.authenticate(&salt_enc);
+
+        let response = self.call(
+            GET_ASSERTION,
+            Value::Map(vec![
+                (int(1), text(rp_id)),
+                (int(2), client_data_hash()),
+                (
+                    int(3),
+                    Value::Array(vec![Value::Map(vec![
+                        (text("id"), Value::Bytes(credential_id.to_vec())),
+                        (text("type"), text("public-key")),
+                    ])]),
+                ),
+                (
+                    int(4),
+                    Value::Map(vec![(
+                        text(HMAC_SECRET),
+                        Value::Map(vec![
+                            (int(1), shared_secret.platform_key.clone()),
+                            (int(2), Value::Bytes(salt_enc)),
+                            (int(3), salt_auth),
+                        ]),
+                    )]),
+                ),
+            ]),
+        )?;
+
+        let (flags, data) = parse_auth_data(&response)?;
+        if flags & FLAG_EXTENSION_DATA == 0 {
+            return Err(Error::NoHmacSecret);
+        }
+        let extensions: Value = ciborium::de::from_reader(data).map_err(|_| invalid_response())?;
+        let output = bytes(get_text(&extensions, HMAC_SECRET)).ok_or(Error::NoHmacSecret)?;
+        let mut output = shared_secret.decrypt(output)?;
+        let secret = output[..].try_into().map_err(|_| invalid_response());
+        output.zeroize();
+        secret
+    }
+}
+
+/// Connects to authenticators over USB, with the CTAPHID protocol.
+pub(super) mod hid {
+    use rand::{rngs::OsRng, RngCore};
+    use std::io;
+
+    use super::{Authenticator, Transport};
+
+    /// The HID usage page and usage of FIDO authenticators.
+    const FIDO_USAGE_PAGE: u16 = 0xf1d0;
+    const FIDO_USAGE: u16 = 0x01;
+
+    const REPORT_BYTES: usize = 64;
+    const INIT_DATA_BYTES: usize = REPORT_BYTES - 7;
+    const CONT_DATA_BYTES: usize = REPORT_BYTES - 5;
+    /// Continuation packets are numbered from 0; the high bit is reserved for the
+    /// command byte of initialization packets.
+    const MAX_SEQUENCE: u8 = 0x7f;
+    /// The longest message that fits in an initialization packet and the maximum number
+    /// of continuation packets.
+    const MAX_MESSAGE_BYTES: usize =
+        INIT_DATA_BYTES + (MAX_SEQUENCE as usize + 1) * CONT_DATA_BYTES;
+
+    const BROADCAST_CHANNEL: [u8; 4] = [0xff; 4];
+    const CTAPHID_INIT: u8 = 0x86;
+    const CTAPHID_CBOR: u8 = 0x90;
+    const CTAPHID_KEEPALIVE: u8 = 0xbb;
+    const CTAPHID_ERROR: u8 = 0xbf;
+    /// The capability flag for authenticators that implement CTAP2.
+    const CAPABILITY_CBOR: u8 = 0x04;
+
+    /// How long to wait for each packet. Authenticators send keepalive packets while
+    /// they wait for the user, so this only expires if the authenticator has stopped
+    /// responding.
+    const READ_TIMEOUT_MS: i32 = 5000;
+
+    /// Splits a CTAPHID message into HID reports.
+    pub(super) fn packets(channel: [u8; 4], command: u8, data: &[u8]) -> Vec<[u8; REPORT_BYTES]> {
+        let mut packets = vec![];
+
+        let mut packet = [0; REPORT_BYTES];
+        packet[..4].copy_from_slice(&channel);
+        packet[4] = command;
+        packet[5..7].copy_from_slice(&(data.len() as u16).to_be_bytes());
+        let (first, rest) = data.split_at(data.len().min(INIT_DATA_BYTES));
+        packet[7..7 + first.len()].copy_from_slice(first);
+        packets.push(packet);
+
+        for (sequence, chunk) in rest.chunks(CONT_DATA_BYTES).enumerate() {
+            let mut packet = [0; REPORT_BYTES];
+            packet[..4].copy_from_slice(&channel);
+            packet[4] = sequence as u8;
+            packet[5..5 + chunk.len()].copy_from_slice(chunk);
+            packets.push(packet);
+        }
+
+        packets
+    }
+
+    /// Reassembles a CTAPHID message from HID reports, skipping keepalive packets.
+    pub(super) fn message(
+        mut read_packet: impl FnMut() -> io::Result<[u8; REPORT_BYTES]>,
+    ) -> io::Result<(u8, Vec<u8>)> {
+        let packet = loop {
+            let packet = read_packet()?;
+            if packet[4] != CTAPHID_KEEPALIVE {
+                break packet;
+            }
+        };
+
+        let command = packet[4];
+        let len = u16::from_be_bytes([packet[5], packet[6]]) as usize;
+        if len > MAX_MESSAGE_BYTES {
+            return Err(protocol_error("CTAPHID message too long"));
+        }
+        let mut data = packet[7..7 + len.min(INIT_DATA_BYTES)].to_vec();
+        let mut sequence = 0;
+        while data.len() < len {
+            let packet = read_packet()?;
+            if sequence > MAX_SEQUENCE || packet[4] != sequence {
+                return Err(protocol_error("CTAPHID packet out of sequence"));
+            }
+            let remaining = (len - data.len()).min(CONT_DATA_BYTES);
+            data.extend_from_slice(&packet[5..5 + remaining]);
+            sequence += 1;
+        }
+
+        if command == CTAPHID_ERROR {
+            return Err(protocol_error("CTAPHID error"));
+        }
+        Ok((command, data))
+    }
+
+    struct Device {
+        device: hidapi::HidDevice,
+        channel: [u8; 4],
+    }
+
+    fn hid_error(e: hidapi::HidError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, e.to_string())
+    }
+
+    fn protocol_error(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+
+    impl Device {
+        fn send(&self, command: u8, data: &[u8]) -> io::Result<()> {
+            if data.len() > MAX_MESSAGE_BYTES {
+                return Err(protocol_error("CTAPHID message too long"));
+            }
+            for packet in packets(self.channel, command, data) {
+                // HID reports are written with a leading report number.
+                let mut report = [0; REPORT_BYTES + 1];
+                report[1..].copy_from_slice(&packet);
+                self.device.write(&report).map_err(hid_error)?;
+            }
+            Ok(())
+        }
+
+        fn read_packet(&self) -> io::Result<[u8; REPORT_BYTES]> {
+            loop {
+                let mut packet = [0; REPORT_BYTES];
+                match self.device.read_timeout(&mut packet, READ_TIMEOUT_MS) {
+                    Ok(0) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "authenticator did not respond",
+                        ))
+                    }
+                    // Ignore messages for other channels.
+                    Ok(_) if packet[..4] != self.channel => (),
+                    Ok(_) => return Ok(packet),
+                    Err(e) => return Err(hid_error(e)),
+                }
+            }
+        }
+
+        fn receive(&self) -> io::Result<(u8, Vec<u8>)> {
+            message(|| self.read_packet())
+        }
+
+        /// Allocates a channel for this process, returning the authenticator's
+        /// capabilities.
+        fn init(&mut self) -> io::Result<u8> {
+            let mut nonce = [0; 8];
+            OsRng.fill_bytes(&mut nonce);
+            self.send(CTAPHID_INIT, &nonce)?;
+            loop {
+                match self.receive()? {
+                    (CTAPHID_INIT, response) if response.len() >= 17 => {
+                        // Responses to other processes' requests are also broadcast.
+                        if response[..8] == nonce {
+                            self.channel.copy_from_slice(&response[8..12]);
+                            return Ok(response[16]);
+                        }
+                    }
+                    _ => return Err(protocol_error("invalid CTAPHID_INIT response")),
+                }
+            }
+        }
+    }
+
+    impl Transport for Device {
+        fn cbor(&mut self, command: u8, request: &[u8]) -> io::Result<Vec<u8>> {
+            let mut message = vec![command];
+            message.extend_from_slice(request);
+            self.send(CTAPHID_CBOR, &message)?;
+            match self.receive()? {
+                (CTAPHID_CBOR, response) => Ok(response),
+                _ => Err(protocol_error("unexpected CTAPHID response")),
+            }
+        }
+    }
+
+    /// Opens the first connected FIDO2 authenticator.
+    ///
+    /// Returns `Ok(None)` if none is connected.
+    pub(in crate::fido2) fn open() -> io::Result<Option<Authenticator>> {
+        let api = hidapi::HidApi::new().map_err(hid_error)?;
+        for info in api.device_list() {
+            if info.usage_page() != FIDO_USAGE_PAGE || info.usage() != FIDO_USAGE {
+                continue;
+            }
+            // The device might be in use by another process.
+            let mut device = match api.open_path(info.path()) {
+                Ok(device) => Device {
+                    device,
+                    channel: BROADCAST_CHANNEL,
+                },
+                Err(_) => continue,
+            };
+            // Skip U2F-only authenticators.
+            if device.init()? & CAPABILITY_CBOR != 0 {
+                return Ok(Some(Authenticator::new(Box::new(device))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use aes::{
+        cipher::{block_padding::NoPadding, BlockDecryptMut, BlockEncryptMut, KeyIvInit},
+        Aes256,
+    };
+    use ciborium::value::Value;
+    use hmac::{Hmac, Mac};
+    use p256::{ecdh::diffie_hellman, SecretKey};
+    use rand::{rngs::OsRng, RngCore};
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use super::{
+        authenticate, bytes, cose_key, get, get_text,
+        hid::{message, packets},
+        int, parse_cose_key, Authenticator, Error, Transport, ERR_NO_CREDENTIALS, ERR_PIN_INVALID,
+        ERR_PIN_REQUIRED, STATUS_OK,
+    };
+
+    pub(crate) const PIN: &[u8] = b"1234";
+
+    /// A software stand-in for a FIDO2 authenticator that supports `hmac-secret`.
+    #[derive(Clone)]
+    pub(crate) struct MockAuthenticator {
+        key_agreement: SecretKey,
+        has_pin: bool,
+        pub(crate) state: Arc<Mutex<MockState>>,
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MockState {
+        /// The `CredRandom` of each credential, by credential ID.
+        credentials: HashMap<Vec<u8>, [u8; 32]>,
+        pin_token: Vec<u8>,
+        pub(crate) touches: usize,
+    }
+
+    fn aes_cbc(key: &[u8], data: &[u8], encrypt: bool) -> Vec<u8> {
+        if encrypt {
+            cbc::Encryptor::<Aes256>::new_from_slices(key, &[0; 16])
+                .unwrap()
+                .encrypt_padded_vec_mut::<NoPadding>(data)
+        } else {
+            cbc::Decryptor::<Aes256>::new_from_slices(key, &[0; 16])
+                .unwrap()
+                .decrypt_padded_vec_mut::<NoPadding>(data)
+                .unwrap()
+        }
+    }
+
+    fn auth_data(flags: u8, data: &[u8]) -> Value {
+        let mut auth_data = Sha256::digest(b"age-encryption.org").to_vec();
+        auth_data.push(flags | 0x01);
+        auth_data.extend_from_slice(&[0; 4]);
+        auth_data.extend_from_slice(data);
+        Value::Bytes(auth_data)
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut data = vec![];
+        ciborium::ser::into_writer(value, &mut data).unwrap();
+        data
+    }
+
+    impl MockAuthenticator {
+        pub(crate) fn new(has_pin: bool) -> Self {
+            MockAuthenticator {
+                key_agreement: SecretKey::random(&mut OsRng),
+                has_pin,
+                state: Arc::new(Mutex::new(MockState::default())),
+            }
+        }
+
+        fn shared_secret(&self, platform_key: Option<&Value>) -> Option<[u8; 32]> {
+            let platform_key = parse_cose_key(platform_key?)?;
+            let shared_secret = diffie_hellman(
+                self.key_agreement.to_nonzero_scalar(),
+                platform_key.as_affine(),
+            );
+            Some(Sha256::digest(shared_secret.raw_secret_bytes()).into())
+        }
+
+        fn respond(&self, command: u8, request: &Value) -> Result<Value, u8> {
+            let mut state = self.state.lock().unwrap();
+            match (command, get(request, 2)) {
+                // getKeyAgreement
+                (0x06, Some(Value::Integer(i))) if i64::try_from(*i) == Ok(2) => Ok(Value::Map(
+                    vec![(int(1), cose_key(&self.key_agreement.public_key()))],
+                )),
+                // getPinToken
+                (0x06, Some(Value::Integer(i))) if i64::try_from(*i) == Ok(5) => {
+                    let shared_secret = self.shared_secret(get(request, 3)).ok_or(0x01u8)?;
+                    let pin_hash = aes_cbc(&shared_secret, bytes(get(request, 6)).unwrap(), false);
+                    if pin_hash != Sha256::digest(PIN)[..16] {
+                        return Err(ERR_PIN_INVALID);
+                    }
+                    state.pin_token = vec![0; 32];
+                    OsRng.fill_bytes(&mut state.pin_token);
+                    Ok(Value::Map(vec![(
+                        int(2),
+                        Value::Bytes(aes_cbc(&shared_secret, &state.pin_token, true)),
+                    )]))
+                }
+                // makeCredential
+                (0x01, _) => {
+                    if self.has_pin {
+                        let client_data_hash = bytes(get(request, 1)).unwrap();
+                        match get(request, 8) {
+                            Some(pin_auth)
+                                if *pin_auth
+                                    == authenticate(&state.pin_token, client_data_hash) => {}
+                            Some(_) => return Err(0x33),
+                            None => return Err(ERR_PIN_REQUIRED),
+                        }
+                    }
+                    assert_eq!(
+                        get_text(get(request, 6).unwrap(), "hmac-secret"),
+                        Some(&Value::Bool(true))
+                    );
+                    state.touches += 1;
+
+                    let mut credential_id = vec![0; 48];
+                    OsRng.fill_bytes(&mut credential_id);
+                    let mut cred_random = [0; 32];
+                    OsRng.fill_bytes(&mut cred_random);
+                    state.credentials.insert(credential_id.clone(), cred_random);
+
+                    let mut data = vec![0; 16];
+                    data.extend_from_slice(&(credential_id.len() as u16).to_be_bytes());
+                    data.extend_from_slice(&credential_id);
+                    data.extend_from_slice(&cbor(&cose_key(
+                        &SecretKey::random(&mut OsRng).public_key(),
+                    )));
+                    data.extend_from_slice(&cbor(&Value::Map(vec![(
+                        Value::Text("hmac-secret".into()),
+                        Value::Bool(true),
+                    )])));
+                    Ok(Value::Map(vec![
+                        (int(1), Value::Text("none".into())),
+                        (int(2), auth_data(0xc0, &data)),
+                        (int(3), Value::Map(vec![])),
+                    ]))
+                }
+                // getAssertion
+                (0x02, _) => {
+                    let credential_id = match get(request, 3) {
+                        Some(Value::Array(allow_list)) => {
+                            bytes(get_text(&allow_list[0], "id")).unwrap()
+                        }
+                        _ => unreachable!(),
+                    };
+                    let cred_random = *state
+                        .credentials
+                        .get(credential_id)
+                        .ok_or(ERR_NO_CREDENTIALS)?;
+                    let input = get_text(get(request, 4).unwrap(), "hmac-secret").unwrap();
+                    let shared_secret = self.shared_secret(get(input, 1)).ok_or(0x01u8)?;
+                    let salt_enc = bytes(get(input, 2)).unwrap();
+                    assert_eq!(
+                        *get(input, 3).unwrap(),
+                        authenticate(&shared_secret, salt_enc)
+                    );
+                    state.touches += 1;
+
+                    let salt = aes_cbc(&shared_secret, salt_enc, false);
+                    let mut mac = Hmac::<Sha256>::new_from_slice(&cred_random).unwrap();
+                    mac.update(&salt);
+                    let output = aes_cbc(&shared_secret, &mac.finalize().into_bytes(), true);
+                    Ok(Value::Map(vec![(
+                        int(2),
+                        auth_data(
+                            0x80,
+                            &cbor(&Value::Map(vec![(
+                                Value::Text("hmac-secret".into()),
+                                Value::Bytes(output),
+                            )])),
+                        ),
+                    )]))
+                }
+                _ => Err(0x01),
+            }
+        }
+    }
+
+    impl Transport for MockAuthenticator {
+        fn cbor(&mut self, command: u8, request: &[u8]) -> io::Result<Vec<u8>> {
+            let request = ciborium::de::from_reader(request).unwrap();
+            Ok(match self.respond(command, &request) {
+                Ok(response) => {
+                    let mut data = vec![STATUS_OK];
+                    data.extend_from_slice(&cbor(&response));
+                    data
+                }
+                Err(status) => vec![status],
+            })
+        }
+    }
+
+    #[test]
+    fn hmac_secret() {
+        let mock = MockAuthenticator::new(false);
+        let mut authenticator = Authenticator::new(Box::new(mock.clone()));
+
+        let credential_id = authenticator
+            .make_credential("age-encryption.org", None)
+            .unwrap();
+        let a = authenticator
+            .hmac_secret("age-encryption.org", &credential_id, &[1; 32])
+            .unwrap();
+        let b = authenticator
+            .hmac_secret("age-encryption.org", &credential_id, &[1; 32])
+            .unwrap();
+        let c = authenticator
+            .hmac_secret("age-encryption.org", &credential_id, &[2; 32])
+            .unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(mock.state.lock().unwrap().touches, 4);
+
+        assert!(matches!(
+            authenticator.hmac_secret("age-encryption.org", &[0; 48], &[1; 32]),
+            Err(Error::Status(ERR_NO_CREDENTIALS))
+        ));
+    }
+
+    #[test]
+    fn pin_required() {
+        let mut authenticator = Authenticator::new(Box::new(MockAuthenticator::new(true)));
+
+        assert!(matches!(
+            authenticator.make_credential("age-encryption.org", None),
+            Err(Error::Status(ERR_PIN_REQUIRED))
+        ));
+        assert!(matches!(
+            authenticator.pin_token(b"0000"),
+            Err(Error::Status(ERR_PIN_INVALID))
+        ));
+        let pin_token = authenticator.pin_token(PIN).unwrap();
+        authenticator
+            .make_credential("age-encryption.org", Some(&pin_token))
+            .unwrap();
+    }
+
+    #[test]
+    fn ctaphid_packets() {
+        let data = (0..=200).collect::<Vec<u8>>();
+        let split = packets([1, 2, 3, 4], 0x90, &data);
+        assert_eq!(split.len(), 4);
+        assert_eq!(split[0][..7], [1, 2, 3, 4, 0x90, 0, 201]);
+        assert_eq!(split[0][7..], data[..57]);
+        assert_eq!(split[1][..5], [1, 2, 3, 4, 0]);
+        assert_eq!(split[1][5..], data[57..116]);
+        assert_eq!(split[3][4], 2);
+        assert_eq!(split[3][5..5 + 26], data[175..]);
+        assert!(split[3][5 + 26..].iter().all(|b| *b == 0));
+
+        assert_eq!(packets([0xff; 4], 0x86, &[9; 8]).len(), 1);
+    }
+
+    #[test]
+    fn ctaphid_message() {
+        let data = (0..=200).collect::<Vec<u8>>();
+        let mut keepalive = [0; 64];
+        keepalive[4] = 0xbb;
+        let mut split = vec![keepalive];
+        split.extend(packets([1, 2, 3, 4], 0x90, &data));
+        let mut split = split.into_iter();
+        assert_eq!(
+            message(|| Ok(split.next().unwrap())).unwrap(),
+            (0x90, data.clone())
+        );
+
+        // Continuation packets must be in order.
+        let mut split = packets([1, 2, 3, 4], 0x90, &data);
+        split.swap(1, 2);
+        let mut split = split.into_iter();
+        assert!(message(|| Ok(split.next().unwrap())).is_err());
+
+        // A message can't be longer than 57 + 128 * 59 bytes, which needs continuation
+        // packets numbered above 127.
+        let data = vec![7; 7609];
+        let mut split = packets([1, 2, 3, 4], 0x90, &data).into_iter();
+        assert_eq!(message(|| Ok(split.next().unwrap())).unwrap().1, data);
+        let mut init = [0; 64];
+        init[4] = 0x90;
+        init[5..7].copy_from_slice(&7610u16.to_be_bytes());
+        assert!(message(|| Ok(init)).is_err());
+    }
+}
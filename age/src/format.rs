@@ -6,13 +6,16 @@ use std::io::{self, Read, Write};
 use crate::{
     error::DecryptError,
     primitives::{HmacKey, HmacWriter},
+    stanza,
 };
 
 #[cfg(feature = "async")]
 use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+#[cfg(feature = "async")]
 const V1_MAGIC: &[u8] = b"v1";
+#[cfg(feature = "async")]
 const MAC_TAG: &[u8] = b"---";
 const ENCODED_MAC_LENGTH: usize = 43;
 
@@ -24,8 +27,11 @@ pub(crate) struct HeaderV1 {
     /// to handle the possibility that the header is not round-trip canonical, such as it
     /// containing a legacy stanza with a body of length 0 mod 64.
     ///
-    /// We do not write this back out in `Header::write`, because we never write headers
-    /// that we have parsed, and headers we generate for writing will never have this set.
+    /// We do not write this back out in `Header::write`, because headers we generate for
+    /// writing will never have this set. [`HeaderV1::write`] does write it back out, for
+    /// headers that are passed on unchanged (such as in an [`airgap::HeaderBundle`]).
+    ///
+    /// [`airgap::HeaderBundle`]: crate::airgap::HeaderBundle
     encoded_bytes: Option<Vec<u8>>,
 }
 
@@ -47,6 +53,19 @@ impl HeaderV1 {
         header
     }
 
+    /// Returns the length of this header as it was read, if it was parsed from a reader.
+    pub(crate) fn encoded_len(&self) -> Option<usize> {
+        self.encoded_bytes.as_ref().map(|bytes| bytes.len())
+    }
+
+    /// Checks every recipient stanza of a type we know against the format of that type.
+    fn validate_stanzas(&self) -> Result<(), DecryptError> {
+        for stanza in &self.recipients {
+            stanza::validate(stanza)?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn verify_mac(&self, mac_key: HmacKey) -> Result<(), hmac::digest::MacError> {
         let mut mac = HmacWriter::new(mac_key);
         if let Some(bytes) = &self.encoded_bytes {
@@ -59,30 +78,56 @@ impl HeaderV1 {
         }
         mac.verify(&self.mac)
     }
+
+    /// Writes this header. A header that was parsed from a reader is written exactly as
+    /// it was read, so that its MAC still covers it.
+    pub(crate) fn write<W: Write>(&self, mut output: W) -> io::Result<()> {
+        match &self.encoded_bytes {
+            Some(bytes) => output.write_all(bytes),
+            None => serialize(write::header_v1(self), output),
+        }
+    }
+}
+
+/// The result of parsing the bytes of a header that have been received so far.
+pub(crate) enum Parsed {
+    /// The bytes are a complete header.
+    Header(Header),
+    /// The header needs at least this many more bytes.
+    Needed(usize),
 }
 
 impl Header {
+    /// Parses the header in `data`, which must not contain any bytes after the end of the
+    /// header. If the header is complete, `data` is moved into it.
+    ///
+    /// We need to be careful how the parser is constructed, because if we read more than
+    /// we need, the remainder of the input will be truncated. Callers should only add
+    /// the number of bytes in [`Parsed::Needed`] before parsing again.
+    pub(crate) fn parse(data: &mut Vec<u8>) -> Result<Parsed, DecryptError> {
+        match read::header(data) {
+            Ok((_, mut header)) => {
+                if let Header::V1(h) = &mut header {
+                    h.validate_stanzas()?;
+                    h.encoded_bytes = Some(std::mem::take(data));
+                }
+                Ok(Parsed::Header(header))
+            }
+            Err(nom::Err::Incomplete(nom::Needed::Size(n))) => Ok(Parsed::Needed(n.get())),
+            Err(_) => Err(DecryptError::InvalidHeader),
+        }
+    }
+
     pub(crate) fn read<R: Read>(mut input: R) -> Result<Self, DecryptError> {
         let mut data = vec![];
         loop {
-            match read::header(&data) {
-                Ok((_, mut header)) => {
-                    if let Header::V1(h) = &mut header {
-                        h.encoded_bytes = Some(data);
-                    }
-                    break Ok(header);
-                }
-                Err(nom::Err::Incomplete(nom::Needed::Size(n))) => {
-                    // Read the needed additional bytes. We need to be careful how the
-                    // parser is constructed, because if we read more than we need, the
-                    // remainder of the input will be truncated.
+            match Header::parse(&mut data)? {
+                Parsed::Header(header) => break Ok(header),
+                Parsed::Needed(n) => {
+                    // Read exactly the needed additional bytes.
                     let m = data.len();
-                    let new_len = m + n.get();
-                    data.resize(new_len, 0);
-                    input.read_exact(&mut data[m..new_len])?;
-                }
-                Err(_) => {
-                    break Err(DecryptError::InvalidHeader);
+                    data.resize(m + n, 0);
+                    input.read_exact(&mut data[m..])?;
                 }
             }
         }
@@ -95,100 +140,127 @@ impl Header {
     ) -> Result<Self, DecryptError> {
         let mut data = vec![];
         loop {
-            match read::header(&data) {
-                Ok((_, mut header)) => {
-                    if let Header::V1(h) = &mut header {
-                        h.encoded_bytes = Some(data);
-                    }
-                    break Ok(header);
-                }
-                Err(nom::Err::Incomplete(nom::Needed::Size(n))) => {
-                    // Read the needed additional bytes. We need to be careful how the
-                    // parser is constructed, because if we read more than we need, the
-                    // remainder of the input will be truncated.
+            match Header::parse(&mut data)? {
+                Parsed::Header(header) => break Ok(header),
+                Parsed::Needed(n) => {
+                    // Read exactly the needed additional bytes.
                     let m = data.len();
-                    let new_len = m + n.get();
-                    data.resize(new_len, 0);
-                    input.read_exact(&mut data[m..new_len]).await?;
-                }
-                Err(_) => {
-                    break Err(DecryptError::InvalidHeader);
+                    data.resize(m + n, 0);
+                    input.read_exact(&mut data[m..]).await?;
                 }
             }
         }
     }
 
-    pub(crate) fn write<W: Write>(&self, mut output: W) -> io::Result<()> {
-        cookie_factory::gen(write::header(self), &mut output)
-            .map(|_| ())
-            .map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("failed to write header: {}", e),
-                )
-            })
+    pub(crate) fn write<W: Write>(&self, output: W) -> io::Result<()> {
+        serialize(write::header(self), output)
     }
 
     #[cfg(feature = "async")]
     #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
     pub(crate) async fn write_async<W: AsyncWrite + Unpin>(&self, mut output: W) -> io::Result<()> {
-        let mut buf = vec![];
-        cookie_factory::gen(write::header(self), &mut buf)
-            .map(|_| ())
-            .map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("failed to write header: {}", e),
-                )
-            })?;
-
-        output.write_all(&buf).await
+        match self {
+            Header::V1(h) => {
+                // Write the header one piece at a time, so that we only ever buffer a
+                // single recipient stanza, and the writer can apply backpressure between
+                // stanzas. Headers with many recipients can be large.
+                let mut buf = vec![];
+                serialize(write::header_v1_intro(), &mut buf)?;
+                output.write_all(&buf).await?;
+
+                for r in &h.recipients {
+                    buf.clear();
+                    serialize(write::recipient_stanza(r), &mut buf)?;
+                    output.write_all(&buf).await?;
+                }
+
+                buf.clear();
+                serialize(write::header_v1_mac(h), &mut buf)?;
+                output.write_all(&buf).await
+            }
+            Header::Unknown(_) => {
+                let mut buf = vec![];
+                serialize(write::header(self), &mut buf)?;
+                output.write_all(&buf).await
+            }
+        }
     }
 }
 
+/// Runs a header serializer over `output`.
+fn serialize<W: Write>(f: impl cookie_factory::SerializeFn<W>, output: W) -> io::Result<()> {
+    cookie_factory::gen(f, output).map(|_| ()).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to write header: {}", e),
+        )
+    })
+}
+
 #[derive(Debug, PartialEq)]
 pub(crate) enum Header {
     V1(HeaderV1),
     Unknown(String),
 }
 
+/// Generates headers that can be written, as if they were created for encryption.
+#[cfg(feature = "fuzzing")]
+impl<'a> arbitrary::Arbitrary<'a> for Header {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        if u.arbitrary()? {
+            // A v1 header always has at least one recipient stanza.
+            let recipients = (0..u.int_in_range(1..=8)?)
+                .map(|_| u.arbitrary())
+                .collect::<arbitrary::Result<_>>()?;
+            Ok(Header::V1(HeaderV1 {
+                recipients,
+                mac: u.arbitrary()?,
+                encoded_bytes: None,
+            }))
+        } else {
+            let version = age_core::format::arbitrary_vchar_string(u)?;
+            if version == "v1" {
+                Err(arbitrary::Error::IncorrectFormat)
+            } else {
+                Ok(Header::Unknown(version))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "fuzzing")]
+impl Header {
+    /// Checks that this header is parsed back to itself after being written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the header does not round-trip.
+    pub(crate) fn check_round_trip(&self) {
+        let mut buf = vec![];
+        self.write(&mut buf).expect("can write header");
+
+        match (self, Header::read(&buf[..])) {
+            (Header::V1(h), Ok(Header::V1(parsed))) => {
+                assert_eq!(parsed.recipients, h.recipients);
+                assert_eq!(parsed.mac, h.mac);
+                assert_eq!(parsed.encoded_len(), Some(buf.len()));
+            }
+            (Header::Unknown(version), Ok(Header::Unknown(parsed))) => {
+                assert_eq!(&parsed, version)
+            }
+            // An arbitrary stanza can have the tag of a recipient type we know, without
+            // following the format of that type.
+            (Header::V1(_), Err(DecryptError::InvalidStanza(_))) => (),
+            (_, res) => panic!("header did not round-trip: {:?}", res),
+        }
+    }
+}
+
 mod read {
-    use age_core::format::read::{arbitrary_string, legacy_age_stanza};
-    use nom::{
-        branch::alt,
-        bytes::streaming::{tag, take},
-        character::streaming::newline,
-        combinator::{map, map_opt},
-        multi::many1,
-        sequence::{pair, preceded, terminated},
-        IResult,
-    };
+    use age_core::format::read::{age_header_v1, age_intro};
+    use nom::{branch::alt, combinator::map, IResult};
 
     use super::*;
-    use crate::util::read::base64_arg;
-
-    fn header_v1(input: &[u8]) -> IResult<&[u8], HeaderV1> {
-        preceded(
-            pair(tag(V1_MAGIC), newline),
-            map(
-                pair(
-                    many1(legacy_age_stanza),
-                    preceded(
-                        pair(tag(MAC_TAG), tag(b" ")),
-                        terminated(
-                            map_opt(take(ENCODED_MAC_LENGTH), |tag| base64_arg(&tag, [0; 32])),
-                            newline,
-                        ),
-                    ),
-                ),
-                |(recipients, mac)| HeaderV1 {
-                    recipients: recipients.into_iter().map(Stanza::from).collect(),
-                    mac,
-                    encoded_bytes: None,
-                },
-            ),
-        )(input)
-    }
 
     /// From the age specification:
     /// ```text
@@ -197,60 +269,70 @@ mod read {
     /// after the first line.
     /// ```
     pub(super) fn header(input: &[u8]) -> IResult<&[u8], Header> {
-        preceded(
-            tag(AGE_MAGIC),
-            alt((
-                map(header_v1, Header::V1),
-                map(terminated(arbitrary_string, newline), |s| {
-                    Header::Unknown(s.to_string())
-                }),
-            )),
-        )(input)
+        alt((
+            map(age_header_v1, |h| {
+                Header::V1(HeaderV1 {
+                    recipients: h.stanzas.into_iter().map(Stanza::from).collect(),
+                    mac: h.mac,
+                    encoded_bytes: None,
+                })
+            }),
+            map(age_intro, |s| Header::Unknown(s.to_string())),
+        ))(input)
     }
 }
 
 mod write {
-    use age_core::format::write::age_stanza;
+    use age_core::format::write::{age_header_v1, age_header_v1_mac_input};
     use cookie_factory::{
         combinator::{slice, string},
-        multi::all,
         sequence::tuple,
         SerializeFn, WriteContext,
     };
     use std::io::Write;
 
     use super::*;
+
+    #[cfg(feature = "async")]
+    use age_core::format::write::age_stanza;
+
+    #[cfg(feature = "async")]
     use crate::util::write::encoded_data;
 
-    fn recipient_stanza<'a, W: 'a + Write>(r: &'a Stanza) -> impl SerializeFn<W> + 'a {
+    #[cfg(feature = "async")]
+    pub(super) fn recipient_stanza<'a, W: 'a + Write>(r: &'a Stanza) -> impl SerializeFn<W> + 'a {
         move |w: WriteContext<W>| {
             let args: Vec<_> = r.args.iter().map(|s| s.as_str()).collect();
-            let writer = age_stanza(&r.tag, &args, &r.body);
+            let writer = age_stanza(&r.tag, &args, r.body());
             writer(w)
         }
     }
 
+    #[cfg(feature = "async")]
+    pub(super) fn header_v1_intro<W: Write>() -> impl SerializeFn<W> {
+        tuple((slice(AGE_MAGIC), slice(V1_MAGIC), string("\n")))
+    }
+
     pub(super) fn header_v1_minus_mac<'a, W: 'a + Write>(
         h: &'a HeaderV1,
     ) -> impl SerializeFn<W> + 'a {
-        tuple((
-            slice(AGE_MAGIC),
-            slice(V1_MAGIC),
-            string("\n"),
-            all(h.recipients.iter().map(move |r| recipient_stanza(r))),
-            slice(MAC_TAG),
-        ))
+        age_header_v1_mac_input(&h.recipients)
     }
 
-    fn header_v1<'a, W: 'a + Write>(h: &'a HeaderV1) -> impl SerializeFn<W> + 'a {
+    #[cfg(feature = "async")]
+    pub(super) fn header_v1_mac<'a, W: 'a + Write>(h: &'a HeaderV1) -> impl SerializeFn<W> + 'a {
         tuple((
-            header_v1_minus_mac(h),
+            slice(MAC_TAG),
             string(" "),
             encoded_data(&h.mac),
             string("\n"),
         ))
     }
 
+    pub(super) fn header_v1<'a, W: 'a + Write>(h: &'a HeaderV1) -> impl SerializeFn<W> + 'a {
+        age_header_v1(&h.recipients, &h.mac)
+    }
+
     pub(super) fn header<'a, W: 'a + Write>(h: &'a Header) -> impl SerializeFn<W> + 'a {
         move |w: WriteContext<W>| match h {
             Header::V1(v1) => header_v1(v1)(w),
@@ -263,6 +345,12 @@ mod write {
 mod tests {
     use super::Header;
 
+    #[cfg(feature = "fuzzing")]
+    #[quickcheck_macros::quickcheck]
+    fn arbitrary_header_round_trip(data: Vec<u8>) {
+        crate::fuzz_header_round_trip(&data);
+    }
+
     #[test]
     fn parse_header() {
         let test_header = "age-encryption.org/v1
@@ -0,0 +1,312 @@
+//! Filesystem helpers.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter that makes the names of concurrent temporary files in this process unique.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An output file that only appears at its path once it is complete.
+///
+/// Output is written to a temporary file in the same directory, which
+/// [`AtomicOutput::commit`] flushes to disk and then renames over `path`. If encryption
+/// or decryption fails partway through, dropping this guard removes the temporary file,
+/// so that a truncated output is never left behind looking like a complete one, and any
+/// existing file at `path` is left untouched. Processes that exit without running
+/// destructors (for example, from a signal handler) should track
+/// [`AtomicOutput::tmp_path`] and remove it themselves.
+///
+/// If `path` refers to something other than a regular file (such as `/dev/null` or a
+/// named pipe), it is written to directly, and dropping the guard leaves it in place.
+pub struct AtomicOutput {
+    path: PathBuf,
+    /// The file that is renamed to `path` on commit, if we aren't writing to it directly.
+    tmp_path: Option<PathBuf>,
+    file: Option<File>,
+}
+
+impl AtomicOutput {
+    /// Creates the file at `path` for writing, replacing any existing file on commit.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_options(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+    }
+
+    /// Opens the file at `path` with the given options.
+    ///
+    /// If `path` is an existing regular file, the output keeps its permissions, so that
+    /// replacing a file doesn't change who can read it. Otherwise, the output is created
+    /// with the permissions set in `options` (such as with `OpenOptionsExt::mode`),
+    /// subject to the umask.
+    pub fn with_options<P: AsRef<Path>>(path: P, options: &OpenOptions) -> io::Result<Self> {
+        Self::open(path.as_ref(), options, true)
+    }
+
+    /// Opens the file at `path` with the given options, giving the output the
+    /// permissions set in `options` even if it replaces an existing file.
+    ///
+    /// This is for permissions that were chosen explicitly, for example with a `--mode`
+    /// flag. They are still subject to the umask.
+    pub fn with_new_permissions<P: AsRef<Path>>(
+        path: P,
+        options: &OpenOptions,
+    ) -> io::Result<Self> {
+        Self::open(path.as_ref(), options, false)
+    }
+
+    fn open(path: &Path, options: &OpenOptions, keep_permissions: bool) -> io::Result<Self> {
+        let mut path = path.to_owned();
+
+        match fs::symlink_metadata(&path) {
+            // Replace the target of a symbolic link, rather than the link itself.
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if let Ok(target) = fs::canonicalize(&path) {
+                    path = target;
+                }
+            }
+            _ => (),
+        }
+
+        let existing = match fs::metadata(&path) {
+            Ok(metadata) if !metadata.is_file() => {
+                let file = options.open(&path)?;
+                return Ok(AtomicOutput {
+                    path,
+                    tmp_path: None,
+                    file: Some(file),
+                });
+            }
+            Ok(metadata) => Some(metadata.permissions()),
+            Err(_) => None,
+        };
+
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?
+            .to_string_lossy()
+            .into_owned();
+        let mut options = options.clone();
+        options.create_new(true);
+        // Until the replaced file's permissions are applied below, only we can open the
+        // temporary file, so no one can keep a handle to it that outlives the change.
+        #[cfg(unix)]
+        if keep_permissions && existing.is_some() {
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        }
+
+        let (tmp_path, file) = loop {
+            let tmp_path = path.with_file_name(format!(
+                ".{}.{}-{}.tmp",
+                name,
+                std::process::id(),
+                TMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+            ));
+            match options.open(&tmp_path) {
+                Ok(file) => break (tmp_path, file),
+                // Left behind by an earlier process with our PID; try the next name.
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        let output = AtomicOutput {
+            path,
+            tmp_path: Some(tmp_path),
+            file: Some(file),
+        };
+
+        // Give the output the replaced file's permissions before anything is written. On
+        // error, dropping the guard removes the temporary file.
+        if let (true, Some(permissions)) = (keep_permissions, existing) {
+            output.set_permissions(permissions)?;
+        }
+        Ok(output)
+    }
+
+    /// Returns the path of the output file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of the temporary file that output is written to, or `None` if
+    /// the output is written to [`AtomicOutput::path`] directly.
+    ///
+    /// This file is renamed away on commit, and removed when the guard is dropped.
+    pub fn tmp_path(&self) -> Option<&Path> {
+        self.tmp_path.as_deref()
+    }
+
+    /// Returns the metadata of the file that output is written to.
+    ///
+    /// Before commit, this is the temporary file, so its permissions are those that the
+    /// output will have once it is moved into place.
+    pub fn metadata(&self) -> io::Result<fs::Metadata> {
+        self.file.as_ref().expect("only taken on commit").metadata()
+    }
+
+    /// Sets the permissions of the output file.
+    pub fn set_permissions(&self, permissions: fs::Permissions) -> io::Result<()> {
+        self.file
+            .as_ref()
+            .expect("only taken on commit")
+            .set_permissions(permissions)
+    }
+
+    /// Flushes the output to disk and moves it into place, so that it is not removed
+    /// when this guard is dropped.
+    pub fn commit(mut self) -> io::Result<File> {
+        let file = self.file.as_mut().expect("only taken here or on drop");
+        file.flush()?;
+        if let Some(tmp_path) = &self.tmp_path {
+            file.sync_all()?;
+            fs::rename(tmp_path, &self.path)?;
+
+            // Make the rename itself durable. Not every platform or filesystem allows
+            // directories to be synced, so this is best-effort.
+            #[cfg(unix)]
+            {
+                let dir = match self.path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                let _ = File::open(dir).and_then(|dir| dir.sync_all());
+            }
+        }
+        Ok(self.file.take().expect("only taken here or on drop"))
+    }
+
+    fn file(&mut self) -> &mut File {
+        self.file.as_mut().expect("only taken on commit")
+    }
+}
+
+impl Write for AtomicOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for AtomicOutput {
+    fn drop(&mut self) {
+        // If the file is still present, we were not committed.
+        if let Some(file) = self.file.take() {
+            // Close the file before removing it, which is required on Windows.
+            drop(file);
+            if let Some(tmp_path) = &self.tmp_path {
+                let _ = fs::remove_file(tmp_path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::AtomicOutput;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("age-fs-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn removed_if_not_committed() {
+        let path = test_path("uncommitted");
+        {
+            let mut output = AtomicOutput::create(&path).unwrap();
+            output.write_all(b"partial").unwrap();
+            assert!(!path.exists());
+            assert!(output.tmp_path().unwrap().exists());
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn existing_file_kept_if_not_committed() {
+        let path = test_path("existing");
+        std::fs::write(&path, b"original").unwrap();
+        {
+            let mut output = AtomicOutput::create(&path).unwrap();
+            output.write_all(b"partial").unwrap();
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"original");
+
+        let mut output = AtomicOutput::create(&path).unwrap();
+        output.write_all(b"replaced").unwrap();
+        drop(output.commit().unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), b"replaced");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn kept_if_committed() {
+        let path = test_path("committed");
+        let mut output = AtomicOutput::create(&path).unwrap();
+        output.write_all(b"complete").unwrap();
+        drop(output.commit().unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), b"complete");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_applies_to_replaced_file() {
+        use std::fs::{self, OpenOptions};
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let path = test_path("mode");
+        fs::write(&path, b"original").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true).mode(0o600);
+        let output = AtomicOutput::with_new_permissions(&path, &options).unwrap();
+        drop(output.commit().unwrap());
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaced_file_keeps_permissions() {
+        use std::fs::{self, OpenOptions};
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let path = test_path("keep-mode");
+        fs::write(&path, b"original").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true).mode(0o600);
+        let output = AtomicOutput::with_options(&path, &options).unwrap();
+        // The permissions are applied before anything is written.
+        assert_eq!(
+            output.metadata().unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        drop(output.commit().unwrap());
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files_not_removed() {
+        drop(AtomicOutput::create("/dev/null").unwrap());
+        assert!(std::path::Path::new("/dev/null").exists());
+    }
+}
@@ -0,0 +1,397 @@
+//! Rewrapping the file key in an age file's header, without touching its payload.
+
+use age_core::format::FileKey;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    audit::HeaderRecord,
+    error::{DecryptError, EncryptError},
+    format::{self, HeaderV1},
+    keys::mac_key,
+    label,
+    protocol::decryptor::unwrap_file_key,
+    threshold::Threshold,
+    Identity, Recipient,
+};
+
+/// The size of the buffer used to move the payload when the header changes length.
+const BUF_SIZE: usize = 64 * 1024;
+
+/// The header of an age file, with its file key unwrapped so that it can be wrapped to
+/// different recipients.
+///
+/// The payload of an age file is encrypted with a key derived from the file key and the
+/// payload's nonce, and not from the header. Changing who can decrypt a file therefore
+/// only requires rewriting its header, which is much cheaper than re-encrypting a large
+/// payload with [`rekey`].
+///
+/// Removing a recipient only affects copies of the file made after the header is
+/// rewritten. Anyone who could decrypt the file before may have kept its file key,
+/// which will still decrypt the payload; use [`rekey`] to change the file key as well.
+///
+/// [`rekey`]: crate::rekey
+///
+/// # Examples
+///
+/// ```
+/// use std::iter;
+///
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let old_key = age::x25519::Identity::generate();
+/// # let mut header = vec![];
+/// # let mut payload = vec![];
+/// # {
+/// #     use std::io::Write;
+/// #     let encryptor = age::Encryptor::with_recipients(vec![Box::new(old_key.to_public())])
+/// #         .expect("we provided a recipient");
+/// #     let mut writer = encryptor.wrap_output_detached(&mut header, &mut payload)?;
+/// #     writer.write_all(b"Hello world!")?;
+/// #     writer.finish()?;
+/// # }
+/// let new_key = age::x25519::Identity::generate();
+///
+/// let identity = &old_key as &dyn age::Identity;
+/// let mut rewrapped = age::Header::read(&header[..], iter::once(identity))?;
+/// rewrapped.add_recipients(vec![Box::new(new_key.to_public())])?;
+/// rewrapped.remove_recipient(&old_key);
+///
+/// let mut header = vec![];
+/// rewrapped.write(&mut header)?;
+///
+/// // The new key can decrypt the unchanged payload with the new header.
+/// let decryptor = match age::Decryptor::new_detached(&header[..], &payload[..])? {
+///     age::Decryptor::Recipients(d) => d,
+///     _ => unreachable!(),
+/// };
+/// decryptor.decrypt(iter::once(&new_key as &dyn age::Identity))?;
+/// # Ok(())
+/// # }
+/// # run_main().unwrap();
+/// ```
+pub struct Header {
+    header: HeaderV1,
+    file_key: FileKey,
+    /// The header as it was read.
+    original: Vec<u8>,
+}
+
+impl Header {
+    /// Reads the header at the start of an age file, or a detached header written by
+    /// [`Encryptor::wrap_output_detached`], and unwraps its file key with the given
+    /// identities.
+    ///
+    /// Only the header is read from `input`. Armored age files are not supported, and
+    /// neither are passphrase-encrypted files.
+    ///
+    /// [`Encryptor::wrap_output_detached`]: crate::Encryptor::wrap_output_detached
+    pub fn read<'a, R: Read>(
+        input: R,
+        identities: impl Iterator<Item = &'a dyn Identity>,
+    ) -> Result<Self, DecryptError> {
+        let header = match format::Header::read(input)? {
+            format::Header::V1(header) => header,
+            format::Header::Unknown(_) => return Err(DecryptError::UnknownFormat),
+        };
+        label::check_structure(&header.recipients)?;
+
+        let identities: Vec<_> = identities.collect();
+        let file_key = unwrap_file_key(&header.recipients, &identities)
+            .unwrap_or(Err(DecryptError::NoMatchingKeys))?;
+        header.verify_mac(mac_key(&file_key))?;
+
+        let mut original = vec![];
+        header.write(&mut original)?;
+
+        Ok(Header {
+            header,
+            file_key,
+            original,
+        })
+    }
+
+    /// Wraps the file key to the given recipients, and adds their stanzas to the header.
+    ///
+    /// Returns [`EncryptError::ThresholdHeader`] if the file was encrypted to a threshold
+    /// of recipients, because any recipient added to it would be able to decrypt the
+    /// file on its own.
+    pub fn add_recipients(
+        &mut self,
+        recipients: Vec<Box<dyn Recipient + Send>>,
+    ) -> Result<(), EncryptError> {
+        if matches!(Threshold::parse(&self.header.recipients), Ok(Some(_))) {
+            return Err(EncryptError::ThresholdHeader);
+        }
+
+        let mut added = vec![];
+        for recipient in recipients {
+            added.append(&mut recipient.wrap_file_key(&self.file_key)?);
+        }
+
+        let mut stanzas = std::mem::take(&mut self.header.recipients);
+        stanzas.append(&mut added);
+        self.header = HeaderV1::new(stanzas, mac_key(&self.file_key));
+        Ok(())
+    }
+
+    /// Removes the stanzas that `identity` can unwrap from the header, along with any
+    /// labels they were given with [`Labeled`].
+    ///
+    /// Returns the number of stanzas that were removed. If this removes every stanza
+    /// that can be unwrapped, the file can no longer be decrypted once the header is
+    /// rewritten.
+    ///
+    /// [`Labeled`]: crate::Labeled
+    pub fn remove_recipient(&mut self, identity: &dyn Identity) -> usize {
+        let stanzas = &self.header.recipients;
+        let mut keep = vec![true; stanzas.len()];
+        let mut removed = 0;
+        for (i, stanza) in stanzas.iter().enumerate() {
+            if let Some(Ok(_)) = identity.unwrap_stanza(stanza) {
+                keep[i] = false;
+                if i > 0 && stanzas[i - 1].tag == label::LABEL_TAG {
+                    keep[i - 1] = false;
+                }
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            let stanzas = std::mem::take(&mut self.header.recipients)
+                .into_iter()
+                .zip(keep)
+                .filter_map(|(stanza, keep)| keep.then(|| stanza))
+                .collect();
+            self.header = HeaderV1::new(stanzas, mac_key(&self.file_key));
+        }
+        removed
+    }
+
+    /// Returns a record of this header, for audit logging.
+    ///
+    /// The header's MAC was verified when it was read, and is recomputed whenever its
+    /// recipients are changed, so the record shows exactly who the file is (or will be,
+    /// once the header is written) encrypted to. See the [`audit`] module for details.
+    ///
+    /// [`audit`]: crate::audit
+    pub fn audit_record(&self) -> HeaderRecord {
+        let mut header = vec![];
+        self.write(&mut header)
+            .expect("writing to a Vec never fails");
+        HeaderRecord::new(&self.header.recipients, &self.header.mac, header)
+    }
+
+    /// Writes the header to `output`.
+    ///
+    /// If the header has not been changed, it is written exactly as it was read.
+    pub fn write<W: Write>(&self, output: W) -> io::Result<()> {
+        self.header.write(output)
+    }
+
+    /// Replaces the header at the start of `file` with this header.
+    ///
+    /// `file` must be the binary age file that this header was read from; an error is
+    /// returned if it does not start with the header as it was read. The payload is not
+    /// decrypted or re-encrypted, but if the length of the header has changed, the
+    /// payload is moved to follow it, and the file could be left corrupted if this is
+    /// interrupted. Storing headers detached from their payloads avoids this.
+    pub fn rewrite_in_place(&self, file: &mut File) -> io::Result<()> {
+        let mut current = vec![0; self.original.len()];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut current)?;
+        if current != self.original {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file does not start with the header that was read",
+            ));
+        }
+
+        let mut header = vec![];
+        self.write(&mut header)?;
+
+        let old_len = self.original.len() as u64;
+        let new_len = header.len() as u64;
+        let payload_len = file.metadata()?.len() - old_len;
+        if new_len != old_len {
+            move_bytes(file, old_len, new_len, payload_len)?;
+            file.set_len(new_len + payload_len)?;
+        }
+
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        file.flush()
+    }
+}
+
+/// Moves `len` bytes in `file` from offset `from` to offset `to`.
+fn move_bytes(file: &mut File, from: u64, to: u64, len: u64) -> io::Result<()> {
+    let mut buf = vec![0; BUF_SIZE];
+    let mut moved = 0;
+    while moved < len {
+        let n = cmp::min(BUF_SIZE as u64, len - moved);
+        // Move from the front when moving towards the start of the file, and from the
+        // back otherwise, so that no bytes are overwritten before they are moved.
+        let offset = if to < from { moved } else { len - moved - n };
+        let buf = &mut buf[..n as usize];
+        file.seek(SeekFrom::Start(from + offset))?;
+        file.read_exact(buf)?;
+        file.seek(SeekFrom::Start(to + offset))?;
+        file.write_all(buf)?;
+        moved += n;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::{Read, Write};
+    use std::iter;
+
+    use super::Header;
+    use crate::{
+        x25519, DecryptError, Decryptor, EncryptError, Encryptor, Identity, Labeled, Recipient,
+    };
+
+    fn decrypt(encrypted: &[u8], identity: &dyn Identity) -> Result<Vec<u8>, DecryptError> {
+        let decryptor = match Decryptor::new(encrypted)? {
+            Decryptor::Recipients(d) => d,
+            _ => panic!(),
+        };
+        let mut reader = decryptor.decrypt(iter::once(identity))?;
+        let mut decrypted = vec![];
+        reader.read_to_end(&mut decrypted)?;
+        Ok(decrypted)
+    }
+
+    #[test]
+    fn rewrite_in_place() {
+        let old_key = x25519::Identity::generate();
+        let new_keys: Vec<_> = (0..3).map(|_| x25519::Identity::generate()).collect();
+        let plaintext: Vec<u8> = (0..200 * 1024).map(|i| i as u8).collect();
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(old_key.to_public())]).unwrap();
+        let mut w = e.wrap_output(&mut encrypted).unwrap();
+        w.write_all(&plaintext).unwrap();
+        w.finish().unwrap();
+
+        let path = std::env::temp_dir().join(format!("age-header-{}", std::process::id()));
+        fs::write(&path, &encrypted).unwrap();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        // Add recipients, growing the header.
+        let mut header =
+            Header::read(&encrypted[..], iter::once(&old_key as &dyn Identity)).unwrap();
+        header
+            .add_recipients(
+                new_keys
+                    .iter()
+                    .map(|k| Box::new(k.to_public()) as Box<dyn Recipient + Send>)
+                    .collect(),
+            )
+            .unwrap();
+        header.rewrite_in_place(&mut file).unwrap();
+        let grown = fs::read(&path).unwrap();
+        assert!(grown.len() > encrypted.len());
+        assert_eq!(decrypt(&grown, &old_key).unwrap(), plaintext);
+        for key in &new_keys {
+            assert_eq!(decrypt(&grown, key).unwrap(), plaintext);
+        }
+
+        // The old header no longer matches the file.
+        assert!(header.rewrite_in_place(&mut file).is_err());
+
+        // Remove recipients, shrinking the header.
+        let mut header =
+            Header::read(&grown[..], iter::once(&new_keys[0] as &dyn Identity)).unwrap();
+        assert_eq!(header.remove_recipient(&old_key), 1);
+        assert_eq!(header.remove_recipient(&new_keys[1]), 1);
+        assert_eq!(header.remove_recipient(&new_keys[1]), 0);
+        header.rewrite_in_place(&mut file).unwrap();
+        let shrunk = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(shrunk.len() < grown.len());
+        assert_eq!(decrypt(&shrunk, &new_keys[0]).unwrap(), plaintext);
+        assert_eq!(decrypt(&shrunk, &new_keys[2]).unwrap(), plaintext);
+        for key in [&old_key, &new_keys[1]] {
+            assert!(matches!(
+                decrypt(&shrunk, key),
+                Err(DecryptError::NoMatchingKeys)
+            ));
+        }
+    }
+
+    #[test]
+    fn audit_records() {
+        let keys: Vec<_> = (0..2).map(|_| x25519::Identity::generate()).collect();
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![
+            Box::new(keys[0].to_public()),
+            Box::new(Labeled::new("backup", keys[1].to_public()).unwrap()),
+        ])
+        .unwrap();
+        e.wrap_output(&mut encrypted).unwrap().finish().unwrap();
+
+        let header = Header::read(&encrypted[..], iter::once(&keys[1] as &dyn Identity)).unwrap();
+        let record = header.audit_record();
+        assert_eq!(record.version, "v1");
+        assert!(encrypted.starts_with(&record.header));
+        assert_eq!(record.mac.len(), 32);
+
+        // Grease stanzas may be added to the header.
+        let recipients: Vec<_> = record
+            .recipients
+            .iter()
+            .filter(|r| r.tag == "X25519")
+            .map(|r| r.label.as_deref())
+            .collect();
+        assert_eq!(recipients, vec![None, Some("backup")]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn audit_record_serialization() {
+        let key = x25519::Identity::generate();
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        e.wrap_output(&mut encrypted).unwrap().finish().unwrap();
+
+        let header = Header::read(&encrypted[..], iter::once(&key as &dyn Identity)).unwrap();
+        let record = header.audit_record();
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["version"], "v1");
+        assert_eq!(json["mac"], base64::encode(&record.mac));
+        assert_eq!(json["header"], base64::encode(&record.header));
+        assert_eq!(
+            serde_json::from_value::<crate::audit::HeaderRecord>(json).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn threshold_headers_cant_gain_recipients() {
+        let keys: Vec<_> = (0..2).map(|_| x25519::Identity::generate()).collect();
+        let mut encrypted = vec![];
+        let e = Encryptor::with_threshold(
+            1,
+            keys.iter()
+                .map(|k| Box::new(k.to_public()) as Box<dyn Recipient + Send>)
+                .collect(),
+        )
+        .unwrap();
+        e.wrap_output(&mut encrypted).unwrap().finish().unwrap();
+
+        let mut header =
+            Header::read(&encrypted[..], iter::once(&keys[0] as &dyn Identity)).unwrap();
+        assert!(matches!(
+            header.add_recipients(vec![Box::new(x25519::Identity::generate().to_public())]),
+            Err(EncryptError::ThresholdHeader)
+        ));
+    }
+}
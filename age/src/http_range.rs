@@ -0,0 +1,317 @@
+//! Serving byte ranges of an encrypted file's plaintext, for HTTP `Range` requests.
+//!
+//! [`RangeServer`] wraps the [`StreamReader`] for a seekable age file, and answers each
+//! request by seeking to the requested range, so only the chunks that overlap it are
+//! read and decrypted. This allows encrypted static files to be hosted as-is, and
+//! decrypted on the fly (for example, to stream media that the client seeks through).
+//!
+//! Only the `bytes` unit with a single range is supported. As allowed by [RFC 9110],
+//! other `Range` headers are ignored and the whole plaintext is served.
+//!
+//! [RFC 9110]: https://www.rfc-editor.org/rfc/rfc9110#section-14.2
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Cursor, Read, Write};
+//! use std::iter;
+//!
+//! # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+//! let key = age::x25519::Identity::generate();
+//! # let encrypted = {
+//! #     let encryptor = age::Encryptor::with_recipients(vec![Box::new(key.to_public())])
+//! #         .expect("we provided a recipient");
+//! #     let mut encrypted = vec![];
+//! #     let mut writer = encryptor.wrap_output(&mut encrypted)?;
+//! #     writer.write_all(b"Hello world!")?;
+//! #     writer.finish()?;
+//! #     encrypted
+//! # };
+//! let reader = match age::Decryptor::new(Cursor::new(encrypted))? {
+//!     age::Decryptor::Recipients(d) => d.decrypt(iter::once(&key as &dyn age::Identity))?,
+//!     _ => unreachable!(),
+//! };
+//! let mut server = age::http_range::RangeServer::new(reader)?;
+//!
+//! let mut response = server.respond(Some("bytes=6-"))?;
+//! assert_eq!(response.status().code(), 206);
+//! assert_eq!(response.content_range(), Some("bytes 6-11/12"));
+//! let mut body = String::new();
+//! response.read_to_string(&mut body)?;
+//! assert_eq!(body, "world!");
+//! # Ok(())
+//! # }
+//! # run_main().unwrap();
+//! ```
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::stream::StreamReader;
+
+/// The status of a [`Response`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// `200 OK`: the whole plaintext is served.
+    Ok,
+    /// `206 Partial Content`: the requested range of the plaintext is served.
+    PartialContent,
+    /// `416 Range Not Satisfiable`: the requested range is outside the plaintext, and
+    /// there is no body.
+    RangeNotSatisfiable,
+}
+
+impl Status {
+    /// Returns the HTTP status code.
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::PartialContent => 206,
+            Status::RangeNotSatisfiable => 416,
+        }
+    }
+}
+
+/// Serves ranges of the plaintext of an age file.
+pub struct RangeServer<R> {
+    reader: StreamReader<R>,
+    len: u64,
+}
+
+impl<R: Read + Seek> RangeServer<R> {
+    /// Creates a server for the plaintext that `reader` decrypts.
+    ///
+    /// This authenticates the length of the plaintext, by decrypting the last chunk.
+    pub fn new(mut reader: StreamReader<R>) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        Ok(RangeServer { reader, len })
+    }
+
+    /// Returns the length of the plaintext.
+    pub fn plaintext_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the response to a request with the given `Range` header value, if any.
+    ///
+    /// Errors are returned if seeking fails, or if a chunk overlapping the range can't
+    /// be decrypted. In the latter case, the file has been corrupted or tampered with.
+    pub fn respond(&mut self, range: Option<&str>) -> io::Result<Response<'_, R>> {
+        let (status, content_range, start, len) = match range.and_then(parse_range) {
+            None => (Status::Ok, None, 0, self.len),
+            Some(spec) => match spec.resolve(self.len) {
+                Some((start, end)) => (
+                    Status::PartialContent,
+                    Some(format!("bytes {}-{}/{}", start, end, self.len)),
+                    start,
+                    end + 1 - start,
+                ),
+                None => (
+                    Status::RangeNotSatisfiable,
+                    Some(format!("bytes */{}", self.len)),
+                    0,
+                    0,
+                ),
+            },
+        };
+
+        self.reader.seek(SeekFrom::Start(start))?;
+        Ok(Response {
+            status,
+            content_range,
+            body: (&mut self.reader).take(len),
+        })
+    }
+
+    /// Returns the underlying [`StreamReader`].
+    pub fn into_inner(self) -> StreamReader<R> {
+        self.reader
+    }
+}
+
+/// A response to a request for (part of) the plaintext.
+///
+/// The body of the response is read from this with [`Read`].
+pub struct Response<'a, R> {
+    status: Status,
+    content_range: Option<String>,
+    body: io::Take<&'a mut StreamReader<R>>,
+}
+
+impl<'a, R> Response<'a, R> {
+    /// Returns the status of the response.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Returns the value of the `Content-Range` header, if the response needs one.
+    pub fn content_range(&self) -> Option<&str> {
+        self.content_range.as_deref()
+    }
+
+    /// Returns the value of the `Content-Length` header.
+    ///
+    /// This is the number of bytes of the body that have not yet been read.
+    pub fn content_len(&self) -> u64 {
+        self.body.limit()
+    }
+}
+
+impl<'a, R: Read> Read for Response<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+/// A single byte range from a `Range` header.
+#[derive(Debug, PartialEq)]
+enum RangeSpec {
+    /// `first-` or `first-last`.
+    From { first: u64, last: Option<u64> },
+    /// `-suffix`: the last `suffix` bytes.
+    Suffix(u64),
+}
+
+impl RangeSpec {
+    /// Returns the first and last (inclusive) positions of this range in a plaintext of
+    /// length `len`, or `None` if the range is not satisfiable.
+    fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            RangeSpec::From { first, last } if first < len => {
+                Some((first, last.map_or(len - 1, |last| last.min(len - 1))))
+            }
+            RangeSpec::Suffix(suffix) if suffix > 0 && len > 0 => {
+                Some((len.saturating_sub(suffix), len - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses the value of a `Range` header, returning `None` if it should be ignored.
+fn parse_range(value: &str) -> Option<RangeSpec> {
+    let spec = value.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        // Serving multiple ranges requires a multipart response.
+        return None;
+    }
+
+    let (first, last) = spec.split_once('-')?;
+    let parse = |s: &str| {
+        (!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| s.parse::<u64>().ok())
+            .flatten()
+    };
+    match (first, last) {
+        ("", suffix) => parse(suffix).map(RangeSpec::Suffix),
+        (first, "") => parse(first).map(|first| RangeSpec::From { first, last: None }),
+        (first, last) => match (parse(first), parse(last)) {
+            (Some(first), Some(last)) if first <= last => Some(RangeSpec::From {
+                first,
+                last: Some(last),
+            }),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+    use std::iter;
+
+    use super::{parse_range, RangeServer, RangeSpec, Status};
+    use crate::{x25519, Decryptor, Encryptor, Identity};
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-499"),
+            Some(RangeSpec::From {
+                first: 0,
+                last: Some(499)
+            })
+        );
+        assert_eq!(
+            parse_range("bytes=9500-"),
+            Some(RangeSpec::From {
+                first: 9500,
+                last: None
+            })
+        );
+        assert_eq!(parse_range("bytes=-500"), Some(RangeSpec::Suffix(500)));
+
+        for ignored in [
+            "bytes=0-1,5-6",
+            "bytes=5-1",
+            "bytes=-",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "items=0-1",
+            "bytes 0-1",
+        ] {
+            assert_eq!(parse_range(ignored), None);
+        }
+    }
+
+    #[test]
+    fn resolve_ranges() {
+        let from = |first, last| RangeSpec::From { first, last };
+        assert_eq!(from(0, Some(499)).resolve(1000), Some((0, 499)));
+        assert_eq!(from(500, Some(2000)).resolve(1000), Some((500, 999)));
+        assert_eq!(from(999, None).resolve(1000), Some((999, 999)));
+        assert_eq!(from(1000, None).resolve(1000), None);
+        assert_eq!(RangeSpec::Suffix(100).resolve(1000), Some((900, 999)));
+        assert_eq!(RangeSpec::Suffix(2000).resolve(1000), Some((0, 999)));
+        assert_eq!(RangeSpec::Suffix(0).resolve(1000), None);
+        assert_eq!(RangeSpec::Suffix(10).resolve(0), None);
+    }
+
+    #[test]
+    fn serve_ranges() {
+        let key = x25519::Identity::generate();
+        let plaintext: Vec<u8> = (0..150 * 1024).map(|i| i as u8).collect();
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        let mut w = e.wrap_output(&mut encrypted).unwrap();
+        w.write_all(&plaintext).unwrap();
+        w.finish().unwrap();
+
+        let reader = match Decryptor::new(Cursor::new(encrypted)).unwrap() {
+            Decryptor::Recipients(d) => d.decrypt(iter::once(&key as &dyn Identity)).unwrap(),
+            _ => panic!(),
+        };
+        let mut server = RangeServer::new(reader).unwrap();
+        assert_eq!(server.plaintext_len(), plaintext.len() as u64);
+
+        let mut serve = |range| {
+            let mut response = server.respond(range).unwrap();
+            let status = response.status();
+            let content_range = response.content_range().map(|s| s.to_owned());
+            let len = response.content_len();
+            let mut body = vec![];
+            response.read_to_end(&mut body).unwrap();
+            assert_eq!(body.len() as u64, len);
+            (status, content_range, body)
+        };
+
+        assert_eq!(serve(None), (Status::Ok, None, plaintext.clone()));
+
+        // A range that crosses a chunk boundary.
+        let (status, content_range, body) = serve(Some("bytes=65530-65545"));
+        assert_eq!(status, Status::PartialContent);
+        assert_eq!(content_range.as_deref(), Some("bytes 65530-65545/153600"));
+        assert_eq!(body, &plaintext[65530..65546]);
+
+        let (_, _, body) = serve(Some("bytes=-10"));
+        assert_eq!(body, &plaintext[plaintext.len() - 10..]);
+
+        assert_eq!(
+            serve(Some("bytes=153600-")),
+            (
+                Status::RangeNotSatisfiable,
+                Some("bytes */153600".to_owned()),
+                vec![]
+            )
+        );
+    }
+}
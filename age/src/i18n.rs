@@ -63,7 +63,28 @@ macro_rules! wlnfl {
 /// // If none of the requested languages are available, or if this function
 /// // is not called, age defaults to en-US.
 /// age::localizer().select(&requested_languages).unwrap();
+///
+/// // Or force a particular language, such as one chosen in the application's settings.
+/// let language: i18n_embed::unic_langid::LanguageIdentifier = "zh-TW".parse().unwrap();
+/// age::localizer().select(&[language]).unwrap();
 /// ```
 pub fn localizer() -> Box<dyn Localizer> {
     Box::from(DefaultLocalizer::new(&*LANGUAGE_LOADER, &TRANSLATIONS))
 }
+
+/// Returns the [`FluentLanguageLoader`] that holds this library's Fluent bundles.
+///
+/// Applications with their own Fluent-based UI can use this to look up age's messages
+/// in the language selected with [`localizer`], or to check which language that is.
+///
+/// # Examples
+///
+/// ```
+/// use i18n_embed::LanguageLoader;
+///
+/// let loader = age::language_loader();
+/// println!("age is localized into {}", loader.current_language());
+/// ```
+pub fn language_loader() -> &'static FluentLanguageLoader {
+    &LANGUAGE_LOADER
+}
@@ -15,46 +15,194 @@ pub enum IdentityFileEntry {
     #[cfg(feature = "plugin")]
     #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
     Plugin(plugin::Identity),
+    /// An experimental post-quantum hybrid identity.
+    #[cfg(feature = "pq")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "pq")))]
+    Pq(crate::pq::Identity),
+    /// A reference to a credential on a FIDO2 security key.
+    #[cfg(feature = "fido2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "fido2")))]
+    Fido2(crate::fido2::IdentityStub),
+    /// A reference to a key held in a YubiKey.
+    #[cfg(feature = "yubikey")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "yubikey")))]
+    YubiKey(crate::yubikey::IdentityStub),
 }
 
 impl IdentityFileEntry {
+    /// Converts this entry into an identity.
+    ///
+    /// Plugin identities only run their plugin if it is in `allowlist`, or if the user
+    /// approves it via [`Callbacks::confirm`].
     #[allow(unused_variables)]
     pub(crate) fn into_identity(
         self,
         callbacks: impl Callbacks,
+        #[cfg(feature = "plugin")] allowlist: &plugin::Allowlist,
     ) -> Result<Box<dyn crate::Identity>, DecryptError> {
         match self {
             IdentityFileEntry::Native(i) => Ok(Box::new(i)),
             #[cfg(feature = "plugin")]
-            IdentityFileEntry::Plugin(i) => Ok(Box::new(crate::plugin::IdentityPluginV1::new(
-                i.plugin(),
-                &[i.clone()],
-                callbacks,
-            )?)),
+            IdentityFileEntry::Plugin(i) => {
+                Ok(Box::new(crate::plugin::IdentityPluginV1::with_allowlist(
+                    i.plugin(),
+                    &[i.clone()],
+                    callbacks,
+                    allowlist,
+                )?))
+            }
+            #[cfg(feature = "pq")]
+            IdentityFileEntry::Pq(i) => Ok(Box::new(i)),
+            #[cfg(feature = "fido2")]
+            IdentityFileEntry::Fido2(stub) => {
+                Ok(Box::new(crate::fido2::Identity::from_stub(stub, callbacks)))
+            }
+            #[cfg(feature = "yubikey")]
+            IdentityFileEntry::YubiKey(stub) => Ok(Box::new(crate::yubikey::Identity::from_stub(
+                stub, callbacks,
+            ))),
         }
     }
 
+    /// Converts this entry into the corresponding recipient.
+    ///
+    /// As with [`IdentityFileEntry::into_identity`], plugins are subject to `allowlist`.
     #[allow(unused_variables)]
     pub(crate) fn to_recipient(
         &self,
         callbacks: impl Callbacks,
+        #[cfg(feature = "plugin")] allowlist: &plugin::Allowlist,
     ) -> Result<Box<dyn crate::Recipient + Send>, EncryptError> {
         match self {
             IdentityFileEntry::Native(i) => Ok(Box::new(i.to_public())),
             #[cfg(feature = "plugin")]
-            IdentityFileEntry::Plugin(i) => Ok(Box::new(crate::plugin::RecipientPluginV1::new(
-                i.plugin(),
-                &[],
-                &[i.clone()],
-                callbacks,
-            )?)),
+            IdentityFileEntry::Plugin(i) => {
+                Ok(Box::new(crate::plugin::RecipientPluginV1::with_allowlist(
+                    i.plugin(),
+                    &[],
+                    &[i.clone()],
+                    callbacks,
+                    allowlist,
+                )?))
+            }
+            #[cfg(feature = "pq")]
+            IdentityFileEntry::Pq(i) => Ok(Box::new(i.to_public())),
+            #[cfg(feature = "fido2")]
+            IdentityFileEntry::Fido2(stub) => Ok(Box::new(
+                crate::fido2::Identity::from_stub(stub.clone(), callbacks).to_recipient(),
+            )),
+            #[cfg(feature = "yubikey")]
+            IdentityFileEntry::YubiKey(stub) => Ok(Box::new(
+                crate::yubikey::Identity::from_stub(stub.clone(), callbacks).to_public()?,
+            )),
+        }
+    }
+}
+
+/// The label of the comment that records when an identity was created.
+const CREATED_LABEL: &str = "created";
+
+/// The label of the comment that records the recipient for an identity.
+const PUBLIC_KEY_LABEL: &str = "public key";
+
+/// Metadata about an identity in an [`IdentityFile`], from the comments before it.
+///
+/// An identity file is a list of identities, one per line. Each identity can be
+/// preceded by comment lines (starting with `#`), and comments of the form
+/// `# created: TIMESTAMP` and `# public key: RECIPIENT` (as written by `rage-keygen`
+/// and `age-keygen`) are recognized. A blank line separates the comments that
+/// belong to an identity from any comments before them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IdentityMetadata {
+    created: Option<String>,
+    public_key: Option<String>,
+    comments: Vec<String>,
+}
+
+impl IdentityMetadata {
+    /// Returns empty metadata, for writing a new identity with
+    /// [`IdentityMetadata::write_identity`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records when the identity was created, usually as an RFC 3339 timestamp.
+    pub fn with_created(mut self, created: impl Into<String>) -> Self {
+        self.created = Some(created.into());
+        self
+    }
+
+    /// Records the recipient corresponding to the identity.
+    pub fn with_public_key(mut self, public_key: impl Into<String>) -> Self {
+        self.public_key = Some(public_key.into());
+        self
+    }
+
+    /// Adds a free-form comment about the identity.
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comments.push(comment.into());
+        self
+    }
+
+    /// Returns when the identity was created, if recorded.
+    pub fn created(&self) -> Option<&str> {
+        self.created.as_deref()
+    }
+
+    /// Returns the recipient corresponding to the identity, if recorded.
+    ///
+    /// This is copied from the file as-is, and is not checked against the identity.
+    pub fn public_key(&self) -> Option<&str> {
+        self.public_key.as_deref()
+    }
+
+    /// Returns the comments about the identity that aren't otherwise recognized.
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// Parses the text of a comment line, without its leading `#`.
+    fn add_comment(&mut self, comment: &str) {
+        let comment = comment.trim();
+        let value = |label: &str| {
+            comment
+                .strip_prefix(label)
+                .and_then(|rest| rest.strip_prefix(':'))
+                .map(|value| value.trim().to_owned())
+        };
+
+        if let Some(created) = value(CREATED_LABEL) {
+            self.created = Some(created);
+        } else if let Some(public_key) = value(PUBLIC_KEY_LABEL) {
+            self.public_key = Some(public_key);
+        } else {
+            self.comments.push(comment.to_owned());
         }
     }
+
+    /// Writes `identity` (in its string encoding) to `output` as an entry of an
+    /// identity file, preceded by comments holding this metadata.
+    ///
+    /// Several identities can be written to the same output, and will be parsed back
+    /// by [`IdentityFile`] with their metadata.
+    pub fn write_identity<W: io::Write>(&self, mut output: W, identity: &str) -> io::Result<()> {
+        if let Some(created) = &self.created {
+            writeln!(output, "# {}: {}", CREATED_LABEL, created)?;
+        }
+        for comment in &self.comments {
+            writeln!(output, "# {}", comment)?;
+        }
+        if let Some(public_key) = &self.public_key {
+            writeln!(output, "# {}: {}", PUBLIC_KEY_LABEL, public_key)?;
+        }
+        writeln!(output, "{}", identity)
+    }
 }
 
 /// A list of identities that has been parsed from some input file.
 pub struct IdentityFile {
     identities: Vec<IdentityFileEntry>,
+    metadata: Vec<IdentityMetadata>,
 }
 
 impl IdentityFile {
@@ -72,10 +220,37 @@ impl IdentityFile {
 
     fn parse_identities<R: io::BufRead>(filename: Option<String>, data: R) -> io::Result<Self> {
         let mut identities = vec![];
+        let mut metadata = vec![];
+        let mut pending = IdentityMetadata::default();
 
         for (line_number, line) in data.lines().enumerate() {
             let line = line?;
-            if line.is_empty() || line.starts_with('#') {
+            if line.is_empty() {
+                pending = IdentityMetadata::default();
+                continue;
+            }
+            if let Some(comment) = line.strip_prefix('#') {
+                pending.add_comment(comment);
+                continue;
+            }
+            // The comments so far belong to this line, which must be an identity.
+            metadata.push(std::mem::take(&mut pending));
+
+            #[cfg(feature = "pq")]
+            if let Ok(identity) = line.parse::<crate::pq::Identity>() {
+                identities.push(IdentityFileEntry::Pq(identity));
+                continue;
+            }
+
+            #[cfg(feature = "fido2")]
+            if let Ok(stub) = line.parse::<crate::fido2::IdentityStub>() {
+                identities.push(IdentityFileEntry::Fido2(stub));
+                continue;
+            }
+
+            #[cfg(feature = "yubikey")]
+            if let Ok(stub) = line.parse::<crate::yubikey::IdentityStub>() {
+                identities.push(IdentityFileEntry::YubiKey(stub));
                 continue;
             }
 
@@ -119,7 +294,15 @@ impl IdentityFile {
             }
         }
 
-        Ok(IdentityFile { identities })
+        Ok(IdentityFile {
+            identities,
+            metadata,
+        })
+    }
+
+    /// Returns the identities in this file, with the metadata about each of them.
+    pub fn identities(&self) -> impl Iterator<Item = (&IdentityFileEntry, &IdentityMetadata)> {
+        self.identities.iter().zip(self.metadata.iter())
     }
 
     /// Returns the identities in this file.
@@ -133,7 +316,7 @@ pub(crate) mod tests {
     use age_core::secrecy::ExposeSecret;
     use std::io::BufReader;
 
-    use super::{IdentityFile, IdentityFileEntry};
+    use super::{IdentityFile, IdentityFileEntry, IdentityMetadata};
 
     pub(crate) const TEST_SK: &str =
         "AGE-SECRET-KEY-1GQ9778VQXMMJVE8SK7J6VT8UJ4HDQAJUVSFCWCM02D8GEWQ72PVQ2Y5J33";
@@ -148,6 +331,12 @@ pub(crate) mod tests {
             }
             #[cfg(feature = "plugin")]
             IdentityFileEntry::Plugin(_) => panic!(),
+            #[cfg(feature = "pq")]
+            IdentityFileEntry::Pq(_) => panic!(),
+            #[cfg(feature = "fido2")]
+            IdentityFileEntry::Fido2(_) => panic!(),
+            #[cfg(feature = "yubikey")]
+            IdentityFileEntry::YubiKey(_) => panic!(),
         }
     }
 
@@ -203,4 +392,47 @@ pub(crate) mod tests {
         let buf = BufReader::new(&TEST_SK.as_bytes()[..4]);
         assert!(IdentityFile::from_buffer(buf).is_err());
     }
+
+    #[test]
+    fn metadata() {
+        let keydata = format!(
+            "# Keys for the backup server\n\n# created: 2022-11-01T12:00:00Z\n# public key: age1foo\n{}\n# laptop\n{}",
+            TEST_SK, TEST_SK,
+        );
+        let f = IdentityFile::from_buffer(BufReader::new(keydata.as_bytes())).unwrap();
+        let metadata: Vec<_> = f
+            .identities()
+            .map(|(_, metadata)| metadata.clone())
+            .collect();
+        assert_eq!(
+            metadata,
+            vec![
+                IdentityMetadata::new()
+                    .with_created("2022-11-01T12:00:00Z")
+                    .with_public_key("age1foo"),
+                IdentityMetadata::new().with_comment("laptop"),
+            ],
+        );
+    }
+
+    #[test]
+    fn write_identities() {
+        let first = IdentityMetadata::new()
+            .with_created("2022-11-01T12:00:00Z")
+            .with_comment("derived with label: backup")
+            .with_public_key("age1foo");
+        let second = IdentityMetadata::new().with_created("2022-11-02T12:00:00Z");
+
+        let mut buf = vec![];
+        first.write_identity(&mut buf, TEST_SK).unwrap();
+        second.write_identity(&mut buf, TEST_SK).unwrap();
+
+        let f = IdentityFile::from_buffer(&buf[..]).unwrap();
+        let metadata: Vec<_> = f
+            .identities()
+            .map(|(_, metadata)| metadata.clone())
+            .collect();
+        assert_eq!(metadata, vec![first, second]);
+        valid_secret_key_encoding(std::str::from_utf8(&buf).unwrap(), 2);
+    }
 }
@@ -0,0 +1,154 @@
+//! Storing X25519 identities in the platform keyring.
+//!
+//! [`PlatformKeyring`] stores identities in the macOS Keychain, or the Windows
+//! Credential Manager, instead of in plaintext identity files. The keyring encrypts them
+//! at rest, and releases them only to the logged-in user.
+//!
+//! On other platforms, every [`PlatformKeyring`] operation returns an error with kind
+//! [`io::ErrorKind::Unsupported`].
+
+use age_core::secrecy::ExposeSecret;
+use std::io;
+use zeroize::Zeroizing;
+
+use crate::x25519;
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+use macos as platform;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+use windows as platform;
+
+#[cfg(not(any(target_os = "macos", windows)))]
+use unsupported as platform;
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod unsupported {
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no platform keyring is available on this platform",
+        )
+    }
+
+    pub(super) fn store(_: &str, _: &str, _: &[u8]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn load(_: &str, _: &str) -> io::Result<Option<Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn delete(_: &str, _: &str) -> io::Result<bool> {
+        Err(unsupported())
+    }
+}
+
+/// A handle to the X25519 identities stored in the platform keyring by an application.
+///
+/// Each identity is stored under a name, within a service name that is shared by all of
+/// the application's identities. On macOS, identities are stored as generic passwords
+/// with the service name as their service, and the identity's name as their account. On
+/// Windows, identities are stored as generic credentials with the target name
+/// `SERVICE:NAME`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::iter;
+///
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let keyring = age::keyring::PlatformKeyring::new("rage");
+///
+/// let identity = age::x25519::Identity::generate();
+/// keyring.store("work", &identity)?;
+/// let recipient = identity.to_public();
+///
+/// // Later, to decrypt a file encrypted to `recipient`:
+/// let identity = keyring.load("work")?.expect("identity was stored");
+/// # let encrypted: Vec<u8> = vec![];
+/// let decryptor = match age::Decryptor::new(&encrypted[..])? {
+///     age::Decryptor::Recipients(d) => d,
+///     _ => unreachable!(),
+/// };
+/// let reader = decryptor.decrypt(iter::once(&identity as &dyn age::Identity))?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PlatformKeyring {
+    service: String,
+}
+
+impl PlatformKeyring {
+    /// Returns a handle to the identities stored under the given service name.
+    pub fn new(service: &str) -> Self {
+        PlatformKeyring {
+            service: service.to_owned(),
+        }
+    }
+
+    /// Stores `identity` in the keyring under `name`, replacing any identity that was
+    /// already stored under that name.
+    pub fn store(&self, name: &str, identity: &x25519::Identity) -> io::Result<()> {
+        let encoded = identity.to_string();
+        platform::store(&self.service, name, encoded.expose_secret().as_bytes())
+    }
+
+    /// Loads the identity stored in the keyring under `name`.
+    ///
+    /// Returns `Ok(None)` if there is no such identity. Depending on the platform and
+    /// the keyring's settings, the user may be asked to approve access to it.
+    pub fn load(&self, name: &str) -> io::Result<Option<x25519::Identity>> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "keyring entry is not an X25519 identity",
+            )
+        };
+
+        match platform::load(&self.service, name)? {
+            Some(encoded) => {
+                let encoded = Zeroizing::new(encoded);
+                std::str::from_utf8(&encoded)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .map(Some)
+                    .ok_or_else(invalid)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes the identity stored in the keyring under `name`.
+    ///
+    /// Returns `Ok(false)` if there was no such identity.
+    pub fn delete(&self, name: &str) -> io::Result<bool> {
+        platform::delete(&self.service, name)
+    }
+}
+
+#[cfg(all(test, not(any(target_os = "macos", windows))))]
+mod tests {
+    use super::PlatformKeyring;
+
+    #[test]
+    fn unsupported_platform() {
+        let keyring = PlatformKeyring::new("age-test");
+        let identity = crate::x25519::Identity::generate();
+        assert_eq!(
+            keyring.store("test", &identity).unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+        assert!(matches!(
+            keyring.load("test"),
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported
+        ));
+    }
+}
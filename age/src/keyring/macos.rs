@@ -0,0 +1,31 @@
+//! Identities stored as generic passwords in the macOS Keychain.
+
+use security_framework::passwords::{
+    delete_generic_password, get_generic_password, set_generic_password,
+};
+use security_framework_sys::base::errSecItemNotFound;
+use std::io;
+
+fn other_err(e: impl std::error::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+pub(super) fn store(service: &str, name: &str, secret: &[u8]) -> io::Result<()> {
+    set_generic_password(service, name, secret).map_err(other_err)
+}
+
+pub(super) fn load(service: &str, name: &str) -> io::Result<Option<Vec<u8>>> {
+    match get_generic_password(service, name) {
+        Ok(secret) => Ok(Some(secret)),
+        Err(e) if e.code() == errSecItemNotFound => Ok(None),
+        Err(e) => Err(other_err(e)),
+    }
+}
+
+pub(super) fn delete(service: &str, name: &str) -> io::Result<bool> {
+    match delete_generic_password(service, name) {
+        Ok(()) => Ok(true),
+        Err(e) if e.code() == errSecItemNotFound => Ok(false),
+        Err(e) => Err(other_err(e)),
+    }
+}
@@ -0,0 +1,106 @@
+//! Identities stored as generic credentials in the Windows Credential Manager.
+
+#![allow(unsafe_code)]
+
+use std::{ffi::c_void, io, ptr, slice};
+use windows_sys::Win32::{
+    Foundation::{GetLastError, ERROR_NOT_FOUND, FILETIME},
+    Security::Credentials::{
+        CredDeleteW, CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE,
+        CRED_TYPE_GENERIC,
+    },
+};
+use zeroize::Zeroize;
+
+/// Encodes a string as a null-terminated UTF-16 string.
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+fn target_name(service: &str, name: &str) -> Vec<u16> {
+    wide(&format!("{}:{}", service, name))
+}
+
+fn last_error() -> io::Error {
+    // SAFETY: `GetLastError` has no preconditions.
+    io::Error::from_raw_os_error(unsafe { GetLastError() } as i32)
+}
+
+pub(super) fn store(service: &str, name: &str, secret: &[u8]) -> io::Result<()> {
+    let mut target = target_name(service, name);
+    let mut user = wide(name);
+    let mut blob = secret.to_vec();
+
+    let credential = CREDENTIALW {
+        Flags: 0,
+        Type: CRED_TYPE_GENERIC,
+        TargetName: target.as_mut_ptr(),
+        Comment: ptr::null_mut(),
+        LastWritten: FILETIME {
+            dwLowDateTime: 0,
+            dwHighDateTime: 0,
+        },
+        CredentialBlobSize: blob.len() as u32,
+        CredentialBlob: blob.as_mut_ptr(),
+        Persist: CRED_PERSIST_LOCAL_MACHINE,
+        AttributeCount: 0,
+        Attributes: ptr::null_mut(),
+        TargetAlias: ptr::null_mut(),
+        UserName: user.as_mut_ptr(),
+    };
+
+    // SAFETY: `credential` only points to buffers that outlive this call.
+    let res = unsafe { CredWriteW(&credential, 0) };
+    blob.zeroize();
+    if res == 0 {
+        Err(last_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub(super) fn load(service: &str, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let target = target_name(service, name);
+
+    let mut credential: *mut CREDENTIALW = ptr::null_mut();
+    // SAFETY: `target` is a valid null-terminated string.
+    if unsafe { CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+        let err = last_error();
+        return if err.raw_os_error() == Some(ERROR_NOT_FOUND as i32) {
+            Ok(None)
+        } else {
+            Err(err)
+        };
+    }
+
+    // SAFETY: `CredReadW` succeeded, so `credential` points to a valid `CREDENTIALW`
+    // whose blob is valid for reads of `CredentialBlobSize` bytes, until it is freed.
+    let secret = unsafe {
+        let credential = &*credential;
+        slice::from_raw_parts(
+            credential.CredentialBlob,
+            credential.CredentialBlobSize as usize,
+        )
+        .to_vec()
+    };
+    // SAFETY: `credential` was allocated by `CredReadW`, and is not used after this.
+    unsafe { CredFree(credential as *const c_void) };
+
+    Ok(Some(secret))
+}
+
+pub(super) fn delete(service: &str, name: &str) -> io::Result<bool> {
+    let target = target_name(service, name);
+
+    // SAFETY: `target` is a valid null-terminated string.
+    if unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+        let err = last_error();
+        if err.raw_os_error() == Some(ERROR_NOT_FOUND as i32) {
+            Ok(false)
+        } else {
+            Err(err)
+        }
+    } else {
+        Ok(true)
+    }
+}
@@ -1,26 +1,23 @@
 //! Key structs and serialization.
 
-use age_core::{
-    format::FileKey,
-    primitives::hkdf,
-    secrecy::{ExposeSecret, Secret},
-};
-use rand::{rngs::OsRng, RngCore};
-
 use crate::{
-    error::DecryptError,
+    error::{DecryptError, RngError},
     format::HeaderV1,
     primitives::{stream::PayloadKey, HmacKey},
     protocol::Nonce,
+    rng::random_bytes,
+};
+use age_core::{
+    format::FileKey,
+    primitives::hkdf,
+    secrecy::{ExposeSecret, Secret},
 };
 
 const HEADER_KEY_LABEL: &[u8] = b"header";
 const PAYLOAD_KEY_LABEL: &[u8] = b"payload";
 
-pub(crate) fn new_file_key() -> FileKey {
-    let mut file_key = [0; 16];
-    OsRng.fill_bytes(&mut file_key);
-    file_key.into()
+pub(crate) fn new_file_key() -> Result<FileKey, RngError> {
+    random_bytes().map(FileKey::from)
 }
 
 pub(crate) fn mac_key(file_key: &FileKey) -> HmacKey {
@@ -40,7 +37,9 @@ pub(crate) fn v1_payload_key(
     header.verify_mac(mac_key(file_key))?;
 
     // Return the payload key
-    Ok(PayloadKey(
-        hkdf(nonce.as_ref(), PAYLOAD_KEY_LABEL, file_key.expose_secret()).into(),
-    ))
+    Ok(PayloadKey::from(hkdf(
+        nonce.as_ref(),
+        PAYLOAD_KEY_LABEL,
+        file_key.expose_secret(),
+    )))
 }
@@ -0,0 +1,508 @@
+//! Recipients and identities backed by a cloud key management service (KMS).
+//!
+//! A KMS recipient wraps the file key by asking the service to encrypt it with a key
+//! that never leaves the service. Decrypting the file requires the service to decrypt
+//! the wrapped file key again, so access to encrypted files is controlled by the
+//! service's access policies (such as AWS IAM policies or Vault policies) instead of
+//! by distributing private keys.
+//!
+//! Each service has its own stanza type, which stores the key's identifier as its only
+//! argument, and the ciphertext returned by the service as its body:
+//!
+//! ```text
+//! -> aws-kms arn:aws:kms:us-east-1:111122223333:key/1234abcd-12ab-34cd-56ef-1234567890ab
+//! <ciphertext returned by the service>
+//! ```
+//!
+//! An identity only unwraps stanzas for the key it was created with.
+//!
+//! The services are each behind their own feature flag:
+//!
+//! | Service             | Feature flag | Types                                                 |
+//! |---------------------|--------------|-------------------------------------------------------|
+//! | AWS KMS             | `kms-aws`    | `AwsKmsRecipient`, `AwsKmsIdentity`                   |
+//! | Azure Key Vault     | `kms-azure`  | `AzureKeyVaultRecipient`, `AzureKeyVaultIdentity`     |
+//! | Google Cloud KMS    | `kms-gcp`    | `GcpKmsRecipient`, `GcpKmsIdentity`                   |
+//! | HashiCorp Vault     | `kms-vault`  | `VaultTransitRecipient`, `VaultTransitIdentity`       |
+
+use age_core::{
+    format::{FileKey, Stanza, FILE_KEY_BYTES},
+    secrecy::{ExposeSecret, SecretString},
+};
+use i18n_embed_fl::fl;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+use crate::{
+    error::{DecryptError, EncryptError},
+    stanza::Rule,
+};
+
+#[cfg(feature = "kms-aws")]
+pub(crate) mod aws;
+#[cfg(feature = "kms-aws")]
+#[cfg_attr(docsrs, doc(cfg(feature = "kms-aws")))]
+pub use aws::{AwsCredentials, AwsKmsIdentity, AwsKmsRecipient};
+
+#[cfg(feature = "kms-azure")]
+pub(crate) mod azure;
+#[cfg(feature = "kms-azure")]
+#[cfg_attr(docsrs, doc(cfg(feature = "kms-azure")))]
+pub use azure::{AzureKeyVaultIdentity, AzureKeyVaultRecipient};
+
+#[cfg(feature = "kms-gcp")]
+pub(crate) mod gcp;
+#[cfg(feature = "kms-gcp")]
+#[cfg_attr(docsrs, doc(cfg(feature = "kms-gcp")))]
+pub use gcp::{GcpKmsIdentity, GcpKmsRecipient};
+
+#[cfg(feature = "kms-vault")]
+pub(crate) mod vault;
+#[cfg(feature = "kms-vault")]
+#[cfg_attr(docsrs, doc(cfg(feature = "kms-vault")))]
+pub use vault::{VaultAuth, VaultTransitIdentity, VaultTransitRecipient};
+
+/// An error returned by, or while contacting, a key management service.
+#[derive(Clone, Debug)]
+pub struct KmsError {
+    service: &'static str,
+    message: String,
+}
+
+impl KmsError {
+    pub(crate) fn new(service: &'static str, message: String) -> Self {
+        KmsError { service, message }
+    }
+
+    /// Returns the name of the service that the error came from.
+    pub fn service(&self) -> &str {
+        self.service
+    }
+
+    /// Returns a description of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for KmsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-kms",
+                service = self.service,
+                message = self.message.as_str(),
+            )
+        )
+    }
+}
+
+impl std::error::Error for KmsError {}
+
+/// A key held by a key management service.
+pub(crate) trait KeyService {
+    /// The format of the stanzas containing file keys wrapped with this service.
+    const STANZA_RULE: Rule;
+
+    /// The identifier of the key, which is stored in the stanza so that identities can
+    /// find the stanzas they can unwrap.
+    fn key_id(&self) -> &str;
+
+    /// Encrypts `plaintext` with the key.
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, KmsError>;
+
+    /// Decrypts `ciphertext` (returned by [`KeyService::encrypt`]) with the key.
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, KmsError>;
+}
+
+pub(crate) fn wrap_file_key<K: KeyService>(
+    key: &K,
+    file_key: &FileKey,
+) -> Result<Vec<Stanza>, EncryptError> {
+    let ciphertext = key.encrypt(file_key.expose_secret())?;
+    Ok(vec![Stanza::new(
+        K::STANZA_RULE.tag.to_owned(),
+        vec![key.key_id().to_owned()],
+        ciphertext,
+    )])
+}
+
+pub(crate) fn unwrap_stanza<K: KeyService>(
+    key: &K,
+    stanza: &Stanza,
+) -> Option<Result<FileKey, DecryptError>> {
+    if stanza.tag != K::STANZA_RULE.tag {
+        return None;
+    }
+    if let Err(e) = K::STANZA_RULE.check(stanza) {
+        return Some(Err(e.into()));
+    }
+    if stanza.args[0] != key.key_id() {
+        return None;
+    }
+
+    Some(
+        key.decrypt(stanza.body())
+            .map_err(DecryptError::from)
+            .and_then(|plaintext| {
+                let plaintext = Zeroizing::new(plaintext);
+                <[u8; FILE_KEY_BYTES]>::try_from(&plaintext[..])
+                    .map(FileKey::from)
+                    .map_err(|_| DecryptError::KeyDecryptionFailed)
+            }),
+    )
+}
+
+/// Sends a JSON request to a key management service, and parses its JSON response.
+pub(crate) fn send_json(
+    service: &'static str,
+    request: ureq::Request,
+    body: &str,
+) -> Result<serde_json::Value, KmsError> {
+    parse_response(service, request.send_string(body))
+}
+
+/// Parses the JSON response to a request to a key management service (or one of its
+/// authentication endpoints).
+pub(crate) fn parse_response(
+    service: &'static str,
+    response: Result<ureq::Response, ureq::Error>,
+) -> Result<serde_json::Value, KmsError> {
+    let response = match response {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, response)) => {
+            let body = response.into_string().unwrap_or_default();
+            return Err(KmsError::new(service, error_message(status, &body)));
+        }
+        Err(e) => return Err(KmsError::new(service, e.to_string())),
+    };
+
+    let body = response
+        .into_string()
+        .map_err(|e| KmsError::new(service, e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| KmsError::new(service, e.to_string()))
+}
+
+/// A bearer token (or other credentials) for a key management service, which is found
+/// or requested when it is first needed, and then shared by clones.
+#[derive(Clone)]
+pub(crate) struct CachedToken<T = SecretString>(Arc<Mutex<Option<T>>>);
+
+impl<T> Default for CachedToken<T> {
+    fn default() -> Self {
+        CachedToken(Arc::new(Mutex::new(None)))
+    }
+}
+
+impl<T: Clone> CachedToken<T> {
+    pub(crate) fn new(token: Option<T>) -> Self {
+        CachedToken(Arc::new(Mutex::new(token)))
+    }
+
+    /// Returns the token, calling `fetch` to get it if this is the first time.
+    pub(crate) fn get_or_fetch(
+        &self,
+        fetch: impl FnOnce() -> Result<T, KmsError>,
+    ) -> Result<T, KmsError> {
+        let mut token = self.0.lock().expect("not poisoned");
+        if let Some(token) = token.as_ref() {
+            return Ok(token.clone());
+        }
+        let new_token = fetch()?;
+        *token = Some(new_token.clone());
+        Ok(new_token)
+    }
+}
+
+/// Returns the string at `pointer` (a JSON pointer such as `/data/ciphertext`) in a
+/// response from a key management service.
+pub(crate) fn str_field<'a>(
+    service: &'static str,
+    response: &'a serde_json::Value,
+    pointer: &str,
+) -> Result<&'a str, KmsError> {
+    response
+        .pointer(pointer)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| invalid_response(service, pointer))
+}
+
+/// Returns the bytes encoded with `config` at `pointer` in a response from a key
+/// management service.
+pub(crate) fn base64_field(
+    service: &'static str,
+    response: &serde_json::Value,
+    pointer: &str,
+    config: base64::Config,
+) -> Result<Vec<u8>, KmsError> {
+    base64::decode_config(str_field(service, response, pointer)?, config)
+        .map_err(|_| invalid_response(service, pointer))
+}
+
+fn invalid_response(service: &'static str, pointer: &str) -> KmsError {
+    KmsError::new(
+        service,
+        fl!(
+            crate::i18n::LANGUAGE_LOADER,
+            "err-kms-invalid-response",
+            field = pointer.trim_start_matches('/').replace('/', ".")
+        ),
+    )
+}
+
+/// Extracts the error message from the body of an error response.
+///
+/// Services describe errors in different ways, so this looks for each of them, and
+/// falls back to the HTTP status.
+fn error_message(status: u16, body: &str) -> String {
+    let json = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => json,
+        Err(_) => return format!("HTTP {}", status),
+    };
+    let str_field = |value: &serde_json::Value, field: &str| {
+        value
+            .get(field)
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_owned())
+    };
+
+    // AWS: {"__type": "...", "message": "..."}
+    let message = str_field(&json, "message").or_else(|| str_field(&json, "Message"));
+    if let Some(error_type) = str_field(&json, "__type") {
+        return match message {
+            Some(message) => format!("{}: {}", error_type, message),
+            None => error_type,
+        };
+    }
+    if let Some(message) = message {
+        return message;
+    }
+
+    // Google Cloud and Azure: {"error": {"message": "..."}}
+    if let Some(message) = json.get("error").and_then(|e| str_field(e, "message")) {
+        return message;
+    }
+
+    // Vault: {"errors": ["...", ...]}
+    if let Some(errors) = json.get("errors").and_then(|e| e.as_array()) {
+        let errors = errors.iter().filter_map(|e| e.as_str()).collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return errors.join("; ");
+        }
+    }
+
+    format!("HTTP {}", status)
+}
+
+/// A mock HTTP server for testing key management services.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A request received by the mock server.
+    pub(crate) struct Request {
+        pub(crate) method: String,
+        pub(crate) path: String,
+        headers: Vec<(String, String)>,
+        pub(crate) body: String,
+    }
+
+    impl Request {
+        pub(crate) fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        pub(crate) fn json(&self) -> serde_json::Value {
+            serde_json::from_str(&self.body).unwrap()
+        }
+    }
+
+    /// Serves `requests` requests, answering each with the JSON response returned by
+    /// `handler`, or a 403 error if it returns `None`. Returns the server's address,
+    /// and a handle that returns the paths (and queries) that were requested.
+    pub(crate) fn serve(
+        requests: usize,
+        handler: impl Fn(&Request) -> Option<serde_json::Value> + Send + 'static,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut paths = vec![];
+            for stream in listener.incoming().take(requests) {
+                let mut stream = BufReader::new(stream.unwrap());
+
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                let mut request_line = line.split(' ');
+                let method = request_line.next().unwrap().to_owned();
+                let path = request_line.next().unwrap().to_owned();
+                let mut headers = vec![];
+                loop {
+                    line.clear();
+                    stream.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((name, value)) => headers.push((name.to_owned(), value.to_owned())),
+                        None => break,
+                    }
+                }
+                let length = headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+
+                let request = Request {
+                    method,
+                    path,
+                    headers,
+                    body: String::from_utf8(body).unwrap(),
+                };
+                let (status, response) = match handler(&request) {
+                    Some(response) => ("200 OK", response),
+                    None => (
+                        "403 Forbidden",
+                        serde_json::json!({ "error": { "message": "permission denied" } }),
+                    ),
+                };
+                let response = response.to_string();
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    response.len(),
+                    response
+                )
+                .unwrap();
+                paths.push(request.path);
+            }
+            paths
+        });
+
+        (address, handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::{FileKey, Stanza},
+        secrecy::ExposeSecret,
+    };
+
+    use super::{error_message, unwrap_stanza, wrap_file_key, KeyService, KmsError};
+    use crate::{
+        error::StanzaError,
+        stanza::{Arg, Body, Rule},
+        DecryptError,
+    };
+
+    /// A "service" that reverses the plaintext, and fails to decrypt anything else.
+    struct Reverse(&'static str);
+
+    impl KeyService for Reverse {
+        const STANZA_RULE: Rule = Rule {
+            tag: "reverse",
+            args: &[Arg::Any],
+            body: Body::Any,
+        };
+
+        fn key_id(&self) -> &str {
+            self.0
+        }
+
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, KmsError> {
+            Ok(plaintext.iter().rev().cloned().collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, KmsError> {
+            if ciphertext.is_empty() {
+                Err(KmsError::new("Reverse", "AccessDenied".into()))
+            } else {
+                Ok(ciphertext.iter().rev().cloned().collect())
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = Reverse("key-1");
+        let file_key = FileKey::from([7; 16]);
+
+        let stanzas = wrap_file_key(&key, &file_key).unwrap();
+        assert_eq!(stanzas.len(), 1);
+        assert_eq!(stanzas[0].tag, "reverse");
+        assert_eq!(stanzas[0].args, vec!["key-1".to_owned()]);
+
+        let unwrapped = unwrap_stanza(&key, &stanzas[0]).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        // Stanzas for other keys or services are ignored.
+        assert!(unwrap_stanza(&Reverse("key-2"), &stanzas[0]).is_none());
+        let other = Stanza::new("X25519".into(), vec!["key-1".into()], vec![1; 32]);
+        assert!(unwrap_stanza(&key, &other).is_none());
+    }
+
+    #[test]
+    fn invalid_stanzas() {
+        let key = Reverse("key-1");
+
+        let no_args = Stanza::new("reverse".into(), vec![], vec![1; 16]);
+        assert!(matches!(
+            unwrap_stanza(&key, &no_args),
+            Some(Err(DecryptError::InvalidStanza(StanzaError::ArgCount {
+                expected: 1,
+                actual: 0,
+                ..
+            })))
+        ));
+
+        let short = Stanza::new("reverse".into(), vec!["key-1".into()], vec![1; 15]);
+        assert!(matches!(
+            unwrap_stanza(&key, &short),
+            Some(Err(DecryptError::KeyDecryptionFailed))
+        ));
+
+        let denied = Stanza::new("reverse".into(), vec!["key-1".into()], vec![]);
+        match unwrap_stanza(&key, &denied) {
+            Some(Err(DecryptError::Kms(e))) => {
+                assert_eq!(e.service(), "Reverse");
+                assert_eq!(e.message(), "AccessDenied");
+            }
+            _ => panic!("expected a KMS error"),
+        }
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(
+            error_message(
+                400,
+                r#"{"__type":"AccessDeniedException","message":"Not allowed"}"#
+            ),
+            "AccessDeniedException: Not allowed"
+        );
+        assert_eq!(
+            error_message(
+                403,
+                r#"{"error":{"code":403,"message":"Permission denied"}}"#
+            ),
+            "Permission denied"
+        );
+        assert_eq!(
+            error_message(403, r#"{"errors":["permission denied"]}"#),
+            "permission denied"
+        );
+        assert_eq!(error_message(502, "<html>Bad Gateway</html>"), "HTTP 502");
+    }
+}
@@ -0,0 +1,546 @@
+//! The "aws-kms" recipient type, backed by AWS Key Management Service.
+
+use age_core::{
+    format::{FileKey, Stanza},
+    secrecy::{ExposeSecret, SecretString},
+};
+use hmac::{Hmac, Mac};
+use i18n_embed_fl::fl;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{CachedToken, KeyService, KmsError};
+use crate::{
+    error::{DecryptError, EncryptError},
+    stanza::{Arg, Body, Rule},
+};
+
+const SERVICE: &str = "AWS KMS";
+const STANZA_TAG: &str = "aws-kms";
+
+/// The format of an aws-kms stanza.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: STANZA_TAG,
+    args: &[Arg::Any],
+    body: Body::Any,
+};
+
+/// The encryption context that file keys are encrypted with. AWS KMS binds the
+/// ciphertext to it, and records it in CloudTrail logs of decryption requests.
+const ENCRYPTION_CONTEXT_KEY: &str = "age-encryption.org/v1";
+const ENCRYPTION_CONTEXT_VALUE: &str = STANZA_TAG;
+
+/// The address of the ECS container credentials endpoint.
+const ECS_CREDENTIALS_HOST: &str = "http://169.254.170.2";
+/// The address of the EC2 instance metadata service.
+const IMDS_HOST: &str = "http://169.254.169.254";
+/// How long to wait for a credentials endpoint, which isn't there when we aren't
+/// running in AWS.
+const CREDENTIALS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Credentials for an AWS IAM principal.
+#[derive(Clone)]
+pub struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: SecretString,
+    session_token: Option<SecretString>,
+}
+
+impl AwsCredentials {
+    /// Constructs credentials from an access key, and the session token for temporary
+    /// credentials.
+    pub fn new(
+        access_key_id: String,
+        secret_access_key: SecretString,
+        session_token: Option<SecretString>,
+    ) -> Self {
+        AwsCredentials {
+            access_key_id,
+            secret_access_key,
+            session_token,
+        }
+    }
+
+    /// Finds credentials in the environment, in the same places as the AWS SDKs (apart
+    /// from their configuration files):
+    /// - The `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and `AWS_SESSION_TOKEN`
+    ///   environment variables.
+    /// - The credentials of the ECS task role, if
+    ///   `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` is set.
+    /// - The credentials of the EC2 instance's IAM role, from the instance metadata
+    ///   service.
+    pub fn from_environment() -> Result<Self, KmsError> {
+        if let (Ok(access_key_id), Ok(secret_access_key)) = (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Ok(AwsCredentials::new(
+                access_key_id,
+                SecretString::new(secret_access_key),
+                std::env::var("AWS_SESSION_TOKEN")
+                    .ok()
+                    .map(SecretString::new),
+            ));
+        }
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout(CREDENTIALS_TIMEOUT)
+            .build();
+        let fetch = |request: ureq::Request| {
+            request
+                .call()
+                .map_err(|e| e.to_string())
+                .and_then(|response| response.into_string().map_err(|e| e.to_string()))
+                .map_err(|e| {
+                    KmsError::new(
+                        SERVICE,
+                        fl!(
+                            crate::i18n::LANGUAGE_LOADER,
+                            "err-kms-aws-no-credentials",
+                            err = e
+                        ),
+                    )
+                })
+        };
+
+        let role_credentials =
+            if let Ok(uri) = std::env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+                fetch(agent.get(&format!("{}{}", ECS_CREDENTIALS_HOST, uri)))?
+            } else {
+                // IMDSv2 requires a session token for each request.
+                let token = fetch(
+                    agent
+                        .put(&format!("{}/latest/api/token", IMDS_HOST))
+                        .set("X-aws-ec2-metadata-token-ttl-seconds", "60"),
+                )?;
+                let roles_url = format!("{}/latest/meta-data/iam/security-credentials/", IMDS_HOST);
+                let roles = fetch(
+                    agent
+                        .get(&roles_url)
+                        .set("X-aws-ec2-metadata-token", &token),
+                )?;
+                let role = roles.lines().next().unwrap_or_default();
+                fetch(
+                    agent
+                        .get(&format!("{}{}", roles_url, role))
+                        .set("X-aws-ec2-metadata-token", &token),
+                )?
+            };
+
+        let role_credentials: serde_json::Value = serde_json::from_str(&role_credentials)
+            .map_err(|e| KmsError::new(SERVICE, e.to_string()))?;
+        let field = |name| super::str_field(SERVICE, &role_credentials, name).map(|s| s.to_owned());
+        Ok(AwsCredentials::new(
+            field("/AccessKeyId")?,
+            SecretString::new(field("/SecretAccessKey")?),
+            Some(SecretString::new(field("/Token")?)),
+        ))
+    }
+
+    /// Returns the session token, for temporary credentials.
+    pub(crate) fn session_token(&self) -> Option<&SecretString> {
+        self.session_token.as_ref()
+    }
+}
+
+/// A symmetric key in AWS KMS, identified by its key ARN or alias ARN.
+#[derive(Clone)]
+struct AwsKmsKey {
+    arn: String,
+    region: String,
+    host: String,
+    /// The credentials that requests are signed with, once they have been found. These
+    /// are shared by clones, so that the environment is only searched once.
+    credentials: CachedToken<AwsCredentials>,
+}
+
+impl std::str::FromStr for AwsKmsKey {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The region ends up in the hostname that requests (including credentials) are
+        // sent to, so it must not be able to change which host that is.
+        let is_dns_label = |s: &str| {
+            !s.is_empty()
+                && s.bytes()
+                    .all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-'))
+        };
+
+        // arn:PARTITION:kms:REGION:ACCOUNT:key/ID or arn:PARTITION:kms:REGION:ACCOUNT:alias/NAME
+        let parts = s.splitn(6, ':').collect::<Vec<_>>();
+        match &parts[..] {
+            ["arn", partition, "kms", region, account, resource]
+                if is_dns_label(region)
+                    && !account.is_empty()
+                    && (resource.starts_with("key/") || resource.starts_with("alias/"))
+                    && s.bytes().all(|b| b.is_ascii_graphic()) =>
+            {
+                let domain = match *partition {
+                    "aws" | "aws-us-gov" => "amazonaws.com",
+                    "aws-cn" => "amazonaws.com.cn",
+                    _ => return Err("unsupported AWS partition in KMS key ARN"),
+                };
+                Ok(AwsKmsKey {
+                    arn: s.to_owned(),
+                    region: region.to_string(),
+                    host: format!("kms.{}.{}", region, domain),
+                    credentials: CachedToken::default(),
+                })
+            }
+            _ => Err("invalid AWS KMS key ARN"),
+        }
+    }
+}
+
+impl AwsKmsKey {
+    /// Calls the AWS KMS API action `action` (such as `Encrypt`).
+    fn call(&self, action: &str, body: serde_json::Value) -> Result<serde_json::Value, KmsError> {
+        let credentials = self
+            .credentials
+            .get_or_fetch(AwsCredentials::from_environment)?;
+
+        let body = body.to_string();
+        let amz_date = amz_date(SystemTime::now());
+        let target = format!("TrentService.{}", action);
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1"),
+            ("host", self.host.as_str()),
+            ("x-amz-date", amz_date.as_str()),
+        ];
+        if let Some(token) = credentials.session_token() {
+            headers.push(("x-amz-security-token", token.expose_secret()));
+        }
+        headers.push(("x-amz-target", target.as_str()));
+
+        let authorization = authorization(
+            &credentials,
+            "POST",
+            "/",
+            "",
+            &headers,
+            body.as_bytes(),
+            &self.region,
+            "kms",
+            &amz_date,
+        );
+
+        let mut request = ureq::post(&format!("https://{}/", self.host));
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.set(name, value);
+        }
+        request = request.set("authorization", &authorization);
+
+        super::send_json(SERVICE, request, &body)
+    }
+}
+
+impl KeyService for AwsKmsKey {
+    const STANZA_RULE: Rule = STANZA_RULE;
+
+    fn key_id(&self) -> &str {
+        &self.arn
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let response = self.call(
+            "Encrypt",
+            json!({
+                "KeyId": self.arn,
+                "Plaintext": base64::encode(plaintext),
+                "EncryptionContext": { ENCRYPTION_CONTEXT_KEY: ENCRYPTION_CONTEXT_VALUE },
+            }),
+        )?;
+        super::base64_field(SERVICE, &response, "/CiphertextBlob", base64::STANDARD)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let response = self.call(
+            "Decrypt",
+            json!({
+                "KeyId": self.arn,
+                "CiphertextBlob": base64::encode(ciphertext),
+                "EncryptionContext": { ENCRYPTION_CONTEXT_KEY: ENCRYPTION_CONTEXT_VALUE },
+            }),
+        )?;
+        super::base64_field(SERVICE, &response, "/Plaintext", base64::STANDARD)
+    }
+}
+
+/// A recipient that wraps file keys with a symmetric key in AWS KMS.
+///
+/// Files encrypted to this recipient can be decrypted by an [`AwsKmsIdentity`] for the
+/// same key ARN, with credentials that IAM allows to use the key for `kms:Decrypt`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let recipient: age::kms::AwsKmsRecipient =
+///     "arn:aws:kms:us-east-1:111122223333:alias/backups".parse()?;
+/// let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient)])
+///     .expect("we provided a recipient");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AwsKmsRecipient(AwsKmsKey);
+
+impl std::str::FromStr for AwsKmsRecipient {
+    type Err = &'static str;
+
+    /// Parses a recipient from a key ARN (`arn:aws:kms:REGION:ACCOUNT:key/ID`) or an
+    /// alias ARN (`arn:aws:kms:REGION:ACCOUNT:alias/NAME`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(AwsKmsRecipient)
+    }
+}
+
+impl fmt::Display for AwsKmsRecipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.arn)
+    }
+}
+
+impl AwsKmsRecipient {
+    /// Uses the given credentials instead of finding them with
+    /// [`AwsCredentials::from_environment`] when they are first needed.
+    pub fn with_credentials(mut self, credentials: AwsCredentials) -> Self {
+        self.0.credentials = CachedToken::new(Some(credentials));
+        self
+    }
+}
+
+impl crate::Recipient for AwsKmsRecipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        super::wrap_file_key(&self.0, file_key)
+    }
+}
+
+/// An identity that unwraps file keys with a symmetric key in AWS KMS.
+///
+/// This only unwraps stanzas created by an [`AwsKmsRecipient`] with the same key ARN.
+#[derive(Clone)]
+pub struct AwsKmsIdentity(AwsKmsKey);
+
+impl std::str::FromStr for AwsKmsIdentity {
+    type Err = &'static str;
+
+    /// Parses an identity from a key ARN (`arn:aws:kms:REGION:ACCOUNT:key/ID`) or an
+    /// alias ARN (`arn:aws:kms:REGION:ACCOUNT:alias/NAME`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(AwsKmsIdentity)
+    }
+}
+
+impl AwsKmsIdentity {
+    /// Uses the given credentials instead of finding them with
+    /// [`AwsCredentials::from_environment`] when they are first needed.
+    pub fn with_credentials(mut self, credentials: AwsCredentials) -> Self {
+        self.0.credentials = CachedToken::new(Some(credentials));
+        self
+    }
+
+    /// Returns the recipient for the same key.
+    pub fn to_recipient(&self) -> AwsKmsRecipient {
+        AwsKmsRecipient(self.0.clone())
+    }
+}
+
+impl crate::Identity for AwsKmsIdentity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        super::unwrap_stanza(&self.0, stanza)
+    }
+}
+
+/// Formats `time` as an `X-Amz-Date` timestamp (`YYYYMMDD'T'HHMMSS'Z'`).
+pub(crate) fn amz_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is after 1970")
+        .as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Convert days since the epoch to a civil date, from
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Signs a request with AWS Signature Version 4, returning its `Authorization` header.
+///
+/// `headers` must have lowercase names, be sorted by name, and include `host` and
+/// `x-amz-date`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn authorization(
+    credentials: &AwsCredentials,
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    payload: &[u8],
+    region: &str,
+    service: &str,
+    amz_date: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        query,
+        canonical_headers,
+        signed_headers,
+        hex(&Sha256::digest(payload)),
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes())),
+    );
+
+    let secret = format!("AWS4{}", credentials.secret_access_key.expose_secret());
+    let signing_key = [region, service, "aws4_request"].iter().fold(
+        hmac_sha256(secret.as_bytes(), date.as_bytes()),
+        |key, part| hmac_sha256(&key, part.as_bytes()),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_headers, signature
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::secrecy::SecretString;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{amz_date, authorization, AwsCredentials, AwsKmsIdentity, AwsKmsKey};
+
+    #[test]
+    fn parse_key_arns() {
+        let key: AwsKmsKey = "arn:aws:kms:us-east-1:111122223333:key/1234abcd-12ab-34cd-56ef"
+            .parse()
+            .unwrap();
+        assert_eq!(key.region, "us-east-1");
+        assert_eq!(key.host, "kms.us-east-1.amazonaws.com");
+
+        let key: AwsKmsKey = "arn:aws-cn:kms:cn-north-1:111122223333:alias/backups"
+            .parse()
+            .unwrap();
+        assert_eq!(key.host, "kms.cn-north-1.amazonaws.com.cn");
+
+        let key: AwsKmsKey = "arn:aws-us-gov:kms:us-gov-west-1:111122223333:key/1234"
+            .parse()
+            .unwrap();
+        assert_eq!(key.host, "kms.us-gov-west-1.amazonaws.com");
+
+        for invalid in [
+            "1234abcd-12ab-34cd-56ef",
+            "alias/backups",
+            "arn:aws:s3:us-east-1:111122223333:key/1234",
+            "arn:aws:kms::111122223333:key/1234",
+            "arn:aws:kms:us-east-1:111122223333:grant/1234",
+            "arn:aws:kms:us-east-1:111122223333:alias/has space",
+            // Regions that would change the host that requests are sent to.
+            "arn:aws:kms:evil.example/#:1:key/x",
+            "arn:aws:kms:evil.example:1:key/x",
+            "arn:aws:kms:us-east-1.evil.example?:1:key/x",
+            "arn:aws:kms:US-EAST-1:111122223333:key/1234",
+            // Unknown partitions.
+            "arn:evil:kms:us-east-1:111122223333:key/1234",
+            "arn:aws-iso:kms:us-iso-east-1:111122223333:key/1234",
+        ] {
+            assert!(invalid.parse::<AwsKmsIdentity>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn amz_dates() {
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(1_440_938_160)),
+            "20150830T123600Z"
+        );
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(951_868_799)),
+            "20000229T235959Z"
+        );
+        assert_eq!(amz_date(UNIX_EPOCH), "19700101T000000Z");
+    }
+
+    #[test]
+    fn signature_v4() {
+        // The example from the AWS Signature Version 4 documentation.
+        let credentials = AwsCredentials::new(
+            "AKIDEXAMPLE".into(),
+            SecretString::new("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into()),
+            None,
+        );
+        assert_eq!(
+            authorization(
+                &credentials,
+                "GET",
+                "/",
+                "Action=ListUsers&Version=2010-05-08",
+                &[
+                    (
+                        "content-type",
+                        "application/x-www-form-urlencoded; charset=utf-8"
+                    ),
+                    ("host", "iam.amazonaws.com"),
+                    ("x-amz-date", "20150830T123600Z"),
+                ],
+                b"",
+                "us-east-1",
+                "iam",
+                "20150830T123600Z",
+            ),
+            "AWS4-HMAC-SHA256 \
+             Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+}
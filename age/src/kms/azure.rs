@@ -0,0 +1,366 @@
+//! The "azure-keyvault" recipient type, backed by Azure Key Vault.
+
+use age_core::{
+    format::{FileKey, Stanza},
+    secrecy::{ExposeSecret, SecretString},
+};
+use i18n_embed_fl::fl;
+use serde_json::json;
+use std::fmt;
+use std::time::Duration;
+
+use super::{CachedToken, KeyService, KmsError};
+use crate::{
+    error::{DecryptError, EncryptError},
+    stanza::{Arg, Body, Rule},
+};
+
+const SERVICE: &str = "Azure Key Vault";
+const STANZA_TAG: &str = "azure-keyvault";
+
+/// The format of an azure-keyvault stanza.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: STANZA_TAG,
+    args: &[Arg::Any],
+    body: Body::Any,
+};
+
+const API_VERSION: &str = "7.4";
+/// The algorithm that file keys are wrapped with, which requires an RSA key.
+const WRAP_ALGORITHM: &str = "RSA-OAEP-256";
+
+const DEFAULT_AUTHORITY_HOST: &str = "https://login.microsoftonline.com";
+/// The address of the Azure Instance Metadata Service.
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+/// How long to wait for a managed identity endpoint, which isn't there when we aren't
+/// running in Azure.
+const MANAGED_IDENTITY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An RSA key in Azure Key Vault (or a Managed HSM), identified by its key identifier.
+#[derive(Clone)]
+struct AzureKey {
+    key_id: String,
+    /// The URL that requests for this key are sent to (which is `key_id`, except in
+    /// tests).
+    url: String,
+    /// The resource that access tokens are requested for, such as
+    /// `https://vault.azure.net`.
+    resource: String,
+    token: CachedToken,
+}
+
+impl std::str::FromStr for AzureKey {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // https://VAULT.vault.azure.net/keys/NAME[/VERSION]
+        let parts = s
+            .strip_prefix("https://")
+            .map(|rest| rest.split('/').collect::<Vec<_>>())
+            .unwrap_or_default();
+        let host = match &parts[..] {
+            [host, "keys", name] | [host, "keys", name, _]
+                if !name.is_empty()
+                    && !parts.last().unwrap().is_empty()
+                    && s.bytes().all(|b| b.is_ascii_graphic()) =>
+            {
+                host
+            }
+            _ => return Err("invalid Azure Key Vault key identifier"),
+        };
+        // Tokens are requested for the vault's domain, which differs between clouds
+        // and between Key Vault and Managed HSM.
+        let domain = match host.split_once('.') {
+            Some((vault, domain)) if !vault.is_empty() && domain.contains('.') => domain,
+            _ => return Err("invalid Azure Key Vault key identifier"),
+        };
+
+        Ok(AzureKey {
+            key_id: s.to_owned(),
+            url: s.to_owned(),
+            resource: format!("https://{}", domain),
+            token: CachedToken::default(),
+        })
+    }
+}
+
+impl AzureKey {
+    /// Finds an access token in the same places as the Azure SDKs' environment and
+    /// managed identity credentials:
+    /// - A client secret for a service principal, from the `AZURE_TENANT_ID`,
+    ///   `AZURE_CLIENT_ID`, and `AZURE_CLIENT_SECRET` environment variables.
+    /// - The managed identity of an App Service or Functions app.
+    /// - The managed identity of a virtual machine, from the instance metadata service.
+    fn access_token(&self) -> Result<SecretString, KmsError> {
+        self.token.get_or_fetch(|| {
+            let response = if let (Ok(tenant_id), Ok(client_id), Ok(client_secret)) = (
+                std::env::var("AZURE_TENANT_ID"),
+                std::env::var("AZURE_CLIENT_ID"),
+                std::env::var("AZURE_CLIENT_SECRET"),
+            ) {
+                let authority = std::env::var("AZURE_AUTHORITY_HOST")
+                    .unwrap_or_else(|_| DEFAULT_AUTHORITY_HOST.to_owned());
+                ureq::post(&format!(
+                    "{}/{}/oauth2/v2.0/token",
+                    authority.trim_end_matches('/'),
+                    tenant_id
+                ))
+                .send_form(&[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", &client_id),
+                    ("client_secret", &client_secret),
+                    ("scope", &format!("{}/.default", self.resource)),
+                ])
+            } else {
+                let agent = ureq::AgentBuilder::new()
+                    .timeout(MANAGED_IDENTITY_TIMEOUT)
+                    .build();
+                if let (Ok(endpoint), Ok(header)) = (
+                    std::env::var("IDENTITY_ENDPOINT"),
+                    std::env::var("IDENTITY_HEADER"),
+                ) {
+                    agent
+                        .get(&endpoint)
+                        .query("api-version", "2019-08-01")
+                        .query("resource", &self.resource)
+                        .set("X-IDENTITY-HEADER", &header)
+                        .call()
+                } else {
+                    agent
+                        .get(IMDS_TOKEN_URL)
+                        .query("api-version", "2018-02-01")
+                        .query("resource", &self.resource)
+                        .set("Metadata", "true")
+                        .call()
+                }
+            };
+
+            let response = super::parse_response(SERVICE, response).map_err(|e| {
+                KmsError::new(
+                    SERVICE,
+                    fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-kms-azure-no-credentials",
+                        err = e.message()
+                    ),
+                )
+            })?;
+            super::str_field(SERVICE, &response, "/access_token")
+                .map(|token| SecretString::new(token.to_owned()))
+        })
+    }
+
+    /// Calls the key operation `operation` (such as `wrapkey`) on this key.
+    fn call(&self, operation: &str, value: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let token = self.access_token()?;
+        let request = ureq::post(&format!("{}/{}", self.url, operation))
+            .query("api-version", API_VERSION)
+            .set(
+                "Authorization",
+                &format!("Bearer {}", token.expose_secret()),
+            )
+            .set("Content-Type", "application/json");
+        let body = json!({
+            "alg": WRAP_ALGORITHM,
+            "value": base64::encode_config(value, base64::URL_SAFE_NO_PAD),
+        });
+        let response = super::send_json(SERVICE, request, &body.to_string())?;
+        super::base64_field(SERVICE, &response, "/value", base64::URL_SAFE_NO_PAD)
+    }
+}
+
+impl KeyService for AzureKey {
+    const STANZA_RULE: Rule = STANZA_RULE;
+
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        self.call("wrapkey", plaintext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        self.call("unwrapkey", ciphertext)
+    }
+}
+
+/// A recipient that wraps file keys with an RSA key in Azure Key Vault.
+///
+/// Files encrypted to this recipient can be decrypted by an [`AzureKeyVaultIdentity`]
+/// for the same key identifier, with credentials that are allowed to unwrap keys with
+/// it.
+///
+/// Key identifiers without a version use the key's current version. Include the
+/// version if files should still be decryptable after the key is rotated.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let recipient: age::kms::AzureKeyVaultRecipient =
+///     "https://my-vault.vault.azure.net/keys/backups/0123456789abcdef".parse()?;
+/// let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient)])
+///     .expect("we provided a recipient");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AzureKeyVaultRecipient(AzureKey);
+
+impl std::str::FromStr for AzureKeyVaultRecipient {
+    type Err = &'static str;
+
+    /// Parses a recipient from a key identifier
+    /// (`https://VAULT.vault.azure.net/keys/NAME` or
+    /// `https://VAULT.vault.azure.net/keys/NAME/VERSION`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(AzureKeyVaultRecipient)
+    }
+}
+
+impl fmt::Display for AzureKeyVaultRecipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.key_id)
+    }
+}
+
+impl AzureKeyVaultRecipient {
+    /// Uses the given OAuth 2.0 access token, instead of finding one in the
+    /// environment.
+    pub fn with_access_token(mut self, token: SecretString) -> Self {
+        self.0.token = CachedToken::new(Some(token));
+        self
+    }
+}
+
+impl crate::Recipient for AzureKeyVaultRecipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        super::wrap_file_key(&self.0, file_key)
+    }
+}
+
+/// An identity that unwraps file keys with an RSA key in Azure Key Vault.
+///
+/// This only unwraps stanzas created by an [`AzureKeyVaultRecipient`] with the same key
+/// identifier.
+#[derive(Clone)]
+pub struct AzureKeyVaultIdentity(AzureKey);
+
+impl std::str::FromStr for AzureKeyVaultIdentity {
+    type Err = &'static str;
+
+    /// Parses an identity from a key identifier
+    /// (`https://VAULT.vault.azure.net/keys/NAME` or
+    /// `https://VAULT.vault.azure.net/keys/NAME/VERSION`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(AzureKeyVaultIdentity)
+    }
+}
+
+impl AzureKeyVaultIdentity {
+    /// Uses the given OAuth 2.0 access token, instead of finding one in the
+    /// environment.
+    pub fn with_access_token(mut self, token: SecretString) -> Self {
+        self.0.token = CachedToken::new(Some(token));
+        self
+    }
+
+    /// Returns the recipient for the same key.
+    pub fn to_recipient(&self) -> AzureKeyVaultRecipient {
+        AzureKeyVaultRecipient(self.0.clone())
+    }
+}
+
+impl crate::Identity for AzureKeyVaultIdentity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        super::unwrap_stanza(&self.0, stanza)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::FileKey,
+        secrecy::{ExposeSecret, SecretString},
+    };
+    use serde_json::json;
+
+    use super::{AzureKey, AzureKeyVaultIdentity};
+    use crate::{kms::mock, Identity, Recipient};
+
+    const KEY: &str = "https://my-vault.vault.azure.net/keys/backups/0123456789abcdef";
+
+    #[test]
+    fn parse_key_identifiers() {
+        let key: AzureKey = KEY.parse().unwrap();
+        assert_eq!(key.resource, "https://vault.azure.net");
+
+        let key: AzureKey = "https://my-hsm.managedhsm.azure.net/keys/backups"
+            .parse()
+            .unwrap();
+        assert_eq!(key.resource, "https://managedhsm.azure.net");
+
+        let key: AzureKey = "https://my-vault.vault.azure.cn/keys/backups"
+            .parse()
+            .unwrap();
+        assert_eq!(key.resource, "https://vault.azure.cn");
+
+        for invalid in [
+            "backups",
+            "http://my-vault.vault.azure.net/keys/backups",
+            "https://my-vault.vault.azure.net/secrets/backups",
+            "https://my-vault.vault.azure.net/keys/",
+            "https://my-vault.vault.azure.net/keys/backups/",
+            "https://my-vault.vault.azure.net/keys/backups/1/2",
+            "https://localhost/keys/backups",
+        ] {
+            assert!(
+                invalid.parse::<AzureKeyVaultIdentity>().is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // A Key Vault whose "ciphertexts" are the reversed plaintext.
+        let (address, server) = mock::serve(2, |request| {
+            let body = request.json();
+            if request.method != "POST"
+                || request.header("Authorization") != Some("Bearer eyJ.token")
+                || body["alg"] != "RSA-OAEP-256"
+            {
+                return None;
+            }
+            let mut value =
+                base64::decode_config(body["value"].as_str()?, base64::URL_SAFE_NO_PAD).ok()?;
+            value.reverse();
+            let value = base64::encode_config(value, base64::URL_SAFE_NO_PAD);
+            match request.path.as_str() {
+                "/keys/backups/0123456789abcdef/wrapkey?api-version=7.4"
+                | "/keys/backups/0123456789abcdef/unwrapkey?api-version=7.4" => {
+                    Some(json!({ "kid": KEY, "value": value }))
+                }
+                _ => None,
+            }
+        });
+        let file_key = FileKey::from([7; 16]);
+
+        let mut identity = KEY
+            .parse::<AzureKeyVaultIdentity>()
+            .unwrap()
+            .with_access_token(SecretString::new("eyJ.token".into()));
+        identity.0.url = format!("{}/keys/backups/0123456789abcdef", address);
+
+        let stanzas = identity.to_recipient().wrap_file_key(&file_key).unwrap();
+        assert_eq!(stanzas[0].tag, "azure-keyvault");
+        assert_eq!(stanzas[0].args, vec![KEY.to_owned()]);
+
+        let unwrapped = identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+}
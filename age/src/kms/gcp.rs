@@ -0,0 +1,303 @@
+//! The "gcp-kms" recipient type, backed by Google Cloud Key Management Service.
+
+use age_core::{
+    format::{FileKey, Stanza},
+    secrecy::{ExposeSecret, SecretString},
+};
+use i18n_embed_fl::fl;
+use serde_json::json;
+use std::fmt;
+use std::time::Duration;
+
+use super::{CachedToken, KeyService, KmsError};
+use crate::{
+    error::{DecryptError, EncryptError},
+    stanza::{Arg, Body, Rule},
+};
+
+const SERVICE: &str = "Google Cloud KMS";
+const STANZA_TAG: &str = "gcp-kms";
+
+/// The format of a gcp-kms stanza.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: STANZA_TAG,
+    args: &[Arg::Any],
+    body: Body::Any,
+};
+
+/// The additional authenticated data that file keys are encrypted with, which binds the
+/// ciphertext to its use in age files.
+const ADDITIONAL_AUTHENTICATED_DATA: &[u8] = b"age-encryption.org/v1";
+
+const ENDPOINT: &str = "https://cloudkms.googleapis.com";
+/// The address of the Compute Engine metadata server.
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// How long to wait for the metadata server, which isn't there when we aren't running
+/// in Google Cloud.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A symmetric key in Google Cloud KMS, identified by its resource name.
+#[derive(Clone)]
+struct GcpKmsKey {
+    name: String,
+    endpoint: String,
+    token: CachedToken,
+}
+
+impl std::str::FromStr for GcpKmsKey {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // projects/PROJECT/locations/LOCATION/keyRings/KEY_RING/cryptoKeys/KEY
+        let parts = s.split('/').collect::<Vec<_>>();
+        match &parts[..] {
+            ["projects", project, "locations", location, "keyRings", key_ring, "cryptoKeys", key]
+                if [project, location, key_ring, key]
+                    .iter()
+                    .all(|part| !part.is_empty())
+                    && s.bytes().all(|b| b.is_ascii_graphic()) =>
+            {
+                Ok(GcpKmsKey {
+                    name: s.to_owned(),
+                    endpoint: ENDPOINT.to_owned(),
+                    token: CachedToken::default(),
+                })
+            }
+            _ => Err("invalid Google Cloud KMS key name"),
+        }
+    }
+}
+
+impl GcpKmsKey {
+    /// Finds an access token in the same places as the Google Cloud CLI (apart from its
+    /// configuration files):
+    /// - The `GOOGLE_OAUTH_ACCESS_TOKEN` environment variable.
+    /// - The token of the attached service account, from the metadata server.
+    fn access_token(&self) -> Result<SecretString, KmsError> {
+        self.token.get_or_fetch(|| {
+            if let Ok(token) = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+                return Ok(SecretString::new(token));
+            }
+
+            let response = ureq::AgentBuilder::new()
+                .timeout(METADATA_TIMEOUT)
+                .build()
+                .get(METADATA_TOKEN_URL)
+                .set("Metadata-Flavor", "Google")
+                .call();
+            let response = super::parse_response(SERVICE, response).map_err(|e| {
+                KmsError::new(
+                    SERVICE,
+                    fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-kms-gcp-no-credentials",
+                        err = e.message()
+                    ),
+                )
+            })?;
+            super::str_field(SERVICE, &response, "/access_token")
+                .map(|token| SecretString::new(token.to_owned()))
+        })
+    }
+
+    /// Calls the Cloud KMS method `method` (such as `encrypt`) on this key.
+    fn call(&self, method: &str, body: serde_json::Value) -> Result<serde_json::Value, KmsError> {
+        let token = self.access_token()?;
+        let request = ureq::post(&format!("{}/v1/{}:{}", self.endpoint, self.name, method))
+            .set(
+                "Authorization",
+                &format!("Bearer {}", token.expose_secret()),
+            )
+            .set("Content-Type", "application/json");
+        super::send_json(SERVICE, request, &body.to_string())
+    }
+}
+
+impl KeyService for GcpKmsKey {
+    const STANZA_RULE: Rule = STANZA_RULE;
+
+    fn key_id(&self) -> &str {
+        &self.name
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let response = self.call(
+            "encrypt",
+            json!({
+                "plaintext": base64::encode(plaintext),
+                "additionalAuthenticatedData": base64::encode(ADDITIONAL_AUTHENTICATED_DATA),
+            }),
+        )?;
+        super::base64_field(SERVICE, &response, "/ciphertext", base64::STANDARD)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let response = self.call(
+            "decrypt",
+            json!({
+                "ciphertext": base64::encode(ciphertext),
+                "additionalAuthenticatedData": base64::encode(ADDITIONAL_AUTHENTICATED_DATA),
+            }),
+        )?;
+        super::base64_field(SERVICE, &response, "/plaintext", base64::STANDARD)
+    }
+}
+
+/// A recipient that wraps file keys with a symmetric key in Google Cloud KMS.
+///
+/// Files encrypted to this recipient can be decrypted by a [`GcpKmsIdentity`] for the
+/// same key, with credentials that IAM allows to decrypt with the key.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let recipient: age::kms::GcpKmsRecipient =
+///     "projects/my-project/locations/global/keyRings/age/cryptoKeys/backups".parse()?;
+/// let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient)])
+///     .expect("we provided a recipient");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct GcpKmsRecipient(GcpKmsKey);
+
+impl std::str::FromStr for GcpKmsRecipient {
+    type Err = &'static str;
+
+    /// Parses a recipient from a key's resource name
+    /// (`projects/PROJECT/locations/LOCATION/keyRings/KEY_RING/cryptoKeys/KEY`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(GcpKmsRecipient)
+    }
+}
+
+impl fmt::Display for GcpKmsRecipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.name)
+    }
+}
+
+impl GcpKmsRecipient {
+    /// Uses the given OAuth 2.0 access token, instead of finding one in the
+    /// environment.
+    pub fn with_access_token(mut self, token: SecretString) -> Self {
+        self.0.token = CachedToken::new(Some(token));
+        self
+    }
+}
+
+impl crate::Recipient for GcpKmsRecipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        super::wrap_file_key(&self.0, file_key)
+    }
+}
+
+/// An identity that unwraps file keys with a symmetric key in Google Cloud KMS.
+///
+/// This only unwraps stanzas created by a [`GcpKmsRecipient`] for the same key.
+#[derive(Clone)]
+pub struct GcpKmsIdentity(GcpKmsKey);
+
+impl std::str::FromStr for GcpKmsIdentity {
+    type Err = &'static str;
+
+    /// Parses an identity from a key's resource name
+    /// (`projects/PROJECT/locations/LOCATION/keyRings/KEY_RING/cryptoKeys/KEY`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(GcpKmsIdentity)
+    }
+}
+
+impl GcpKmsIdentity {
+    /// Uses the given OAuth 2.0 access token, instead of finding one in the
+    /// environment.
+    pub fn with_access_token(mut self, token: SecretString) -> Self {
+        self.0.token = CachedToken::new(Some(token));
+        self
+    }
+
+    /// Returns the recipient for the same key.
+    pub fn to_recipient(&self) -> GcpKmsRecipient {
+        GcpKmsRecipient(self.0.clone())
+    }
+}
+
+impl crate::Identity for GcpKmsIdentity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        super::unwrap_stanza(&self.0, stanza)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::FileKey,
+        secrecy::{ExposeSecret, SecretString},
+    };
+    use serde_json::json;
+
+    use super::{GcpKmsIdentity, GcpKmsRecipient, ADDITIONAL_AUTHENTICATED_DATA};
+    use crate::{kms::mock, Identity, Recipient};
+
+    const KEY: &str = "projects/p/locations/global/keyRings/age/cryptoKeys/backups";
+
+    #[test]
+    fn parse_key_names() {
+        assert_eq!(KEY.parse::<GcpKmsRecipient>().unwrap().to_string(), KEY);
+
+        for invalid in [
+            "backups",
+            "projects/p/locations/global/keyRings/age",
+            "projects/p/locations/global/keyRings/age/cryptoKeys/",
+            "projects/p/locations/global/keyRings/age/cryptoKeys/backups/cryptoKeyVersions/1",
+            "projects/p/locations/global/keyRings/age/cryptoKeys/my backups",
+        ] {
+            assert!(invalid.parse::<GcpKmsIdentity>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // A Cloud KMS whose "ciphertexts" are the plaintext with the AAD appended.
+        let aad = base64::encode(ADDITIONAL_AUTHENTICATED_DATA);
+        let (address, server) = mock::serve(2, move |request| {
+            let body = request.json();
+            if request.header("Authorization") != Some("Bearer ya29.token")
+                || body["additionalAuthenticatedData"] != aad.as_str()
+            {
+                return None;
+            }
+            match request.path.as_str() {
+                "/v1/projects/p/locations/global/keyRings/age/cryptoKeys/backups:encrypt" => {
+                    let mut ciphertext = base64::decode(body["plaintext"].as_str()?).ok()?;
+                    ciphertext.extend_from_slice(ADDITIONAL_AUTHENTICATED_DATA);
+                    Some(json!({ "ciphertext": base64::encode(ciphertext) }))
+                }
+                "/v1/projects/p/locations/global/keyRings/age/cryptoKeys/backups:decrypt" => {
+                    let ciphertext = base64::decode(body["ciphertext"].as_str()?).ok()?;
+                    let plaintext = ciphertext.strip_suffix(ADDITIONAL_AUTHENTICATED_DATA)?;
+                    Some(json!({ "plaintext": base64::encode(plaintext) }))
+                }
+                _ => None,
+            }
+        });
+        let file_key = FileKey::from([7; 16]);
+
+        let mut identity = KEY
+            .parse::<GcpKmsIdentity>()
+            .unwrap()
+            .with_access_token(SecretString::new("ya29.token".into()));
+        identity.0.endpoint = address;
+
+        let stanzas = identity.to_recipient().wrap_file_key(&file_key).unwrap();
+        assert_eq!(stanzas[0].tag, "gcp-kms");
+        assert_eq!(stanzas[0].args, vec![KEY.to_owned()]);
+
+        let unwrapped = identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+}
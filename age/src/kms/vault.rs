@@ -0,0 +1,413 @@
+//! The "vault-transit" recipient type, backed by the transit secrets engine of
+//! HashiCorp Vault.
+
+use age_core::{
+    format::{FileKey, Stanza},
+    secrecy::{ExposeSecret, SecretString},
+};
+use i18n_embed_fl::fl;
+use serde_json::json;
+use std::fmt;
+
+use super::{CachedToken, KeyService, KmsError};
+use crate::{
+    error::{DecryptError, EncryptError},
+    stanza::{Arg, Body, Rule},
+    Callbacks,
+};
+
+const SERVICE: &str = "Vault";
+const STANZA_TAG: &str = "vault-transit";
+
+/// The format of a vault-transit stanza.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: STANZA_TAG,
+    args: &[Arg::Any],
+    body: Body::Any,
+};
+
+/// The default mount path of the transit secrets engine.
+const DEFAULT_MOUNT: &str = "transit";
+
+/// How to authenticate to Vault.
+#[derive(Clone)]
+pub enum VaultAuth {
+    /// Authenticate with a Vault token.
+    ///
+    /// If the token is `None`, it is read from the `VAULT_TOKEN` environment variable,
+    /// or requested with [`Callbacks::request_passphrase`].
+    Token(Option<SecretString>),
+    /// Log in with the AppRole auth method (mounted at `auth/approle`).
+    ///
+    /// If the secret ID is `None`, it is requested with
+    /// [`Callbacks::request_passphrase`].
+    AppRole {
+        /// The role ID of the AppRole.
+        role_id: String,
+        /// A secret ID for the AppRole.
+        secret_id: Option<SecretString>,
+    },
+}
+
+/// A named encryption key in a transit secrets engine.
+#[derive(Clone)]
+struct VaultKey<C: Callbacks> {
+    address: String,
+    mount: String,
+    name: String,
+    key_id: String,
+    auth: VaultAuth,
+    /// The token that requests are made with, once it has been found or logged in
+    /// for. This is shared by clones, so that the user is only asked once.
+    token: CachedToken,
+    callbacks: C,
+}
+
+impl<C: Callbacks> VaultKey<C> {
+    fn new(address: &str, key: &str, auth: VaultAuth, callbacks: C) -> Result<Self, &'static str> {
+        let (mount, name) = match key.rsplit_once('/') {
+            Some((mount, name)) => (mount.trim_matches('/'), name),
+            None => (DEFAULT_MOUNT, key),
+        };
+        if mount.is_empty() || name.is_empty() || !key.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err("invalid Vault transit key");
+        }
+
+        Ok(VaultKey {
+            address: address.trim_end_matches('/').to_owned(),
+            mount: mount.to_owned(),
+            name: name.to_owned(),
+            key_id: format!("{}/{}", mount, name),
+            auth,
+            token: CachedToken::default(),
+            callbacks,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/{}", self.address, path)
+    }
+
+    /// Returns the token to make requests with, logging in if necessary.
+    fn token(&self) -> Result<SecretString, KmsError> {
+        self.token.get_or_fetch(|| match &self.auth {
+            VaultAuth::Token(Some(token)) => Ok(token.clone()),
+            VaultAuth::Token(None) => match std::env::var("VAULT_TOKEN") {
+                Ok(token) => Ok(SecretString::new(token)),
+                Err(_) => self.request_secret(fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "vault-token-prompt",
+                    address = self.address.as_str()
+                )),
+            },
+            VaultAuth::AppRole { role_id, secret_id } => {
+                let secret_id = match secret_id {
+                    Some(secret_id) => secret_id.clone(),
+                    None => self.request_secret(fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "vault-secret-id-prompt",
+                        role_id = role_id.as_str()
+                    ))?,
+                };
+                let response = super::send_json(
+                    SERVICE,
+                    ureq::post(&self.url("auth/approle/login")),
+                    &json!({
+                        "role_id": role_id,
+                        "secret_id": secret_id.expose_secret(),
+                    })
+                    .to_string(),
+                )?;
+                super::str_field(SERVICE, &response, "/auth/client_token")
+                    .map(|token| SecretString::new(token.to_owned()))
+            }
+        })
+    }
+
+    fn request_secret(&self, description: String) -> Result<SecretString, KmsError> {
+        self.callbacks
+            .request_passphrase(&description)
+            .ok_or_else(|| {
+                KmsError::new(
+                    SERVICE,
+                    fl!(crate::i18n::LANGUAGE_LOADER, "err-kms-vault-no-token"),
+                )
+            })
+    }
+
+    /// Calls the transit engine's `operation` (such as `encrypt`) with this key.
+    fn call(
+        &self,
+        operation: &str,
+        body: serde_json::Value,
+    ) -> Result<serde_json::Value, KmsError> {
+        let token = self.token()?;
+        let request = ureq::post(&self.url(&format!("{}/{}/{}", self.mount, operation, self.name)))
+            .set("X-Vault-Token", token.expose_secret());
+        super::send_json(SERVICE, request, &body.to_string())
+    }
+}
+
+impl<C: Callbacks> KeyService for VaultKey<C> {
+    const STANZA_RULE: Rule = STANZA_RULE;
+
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let response = self.call("encrypt", json!({ "plaintext": base64::encode(plaintext) }))?;
+        // Vault's ciphertexts are strings of the form "vault:v1:BASE64".
+        super::str_field(SERVICE, &response, "/data/ciphertext").map(|s| s.as_bytes().to_vec())
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let response = self.call(
+            "decrypt",
+            json!({ "ciphertext": String::from_utf8_lossy(ciphertext) }),
+        )?;
+        super::base64_field(SERVICE, &response, "/data/plaintext", base64::STANDARD)
+    }
+}
+
+/// A recipient that wraps file keys with a key in a HashiCorp Vault transit secrets
+/// engine.
+///
+/// Files encrypted to this recipient can be decrypted by a [`VaultTransitIdentity`]
+/// for the same key, with a Vault token whose policy allows decrypting with it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use age::kms::{VaultAuth, VaultTransitRecipient};
+///
+/// # #[derive(Clone)]
+/// # struct Callbacks;
+/// # impl age::Callbacks for Callbacks {
+/// #     fn request_passphrase(&self, _: &str) -> Option<age::secrecy::SecretString> {
+/// #         None
+/// #     }
+/// # }
+/// # fn run_main() -> Result<(), &'static str> {
+/// let recipient = VaultTransitRecipient::new(
+///     "https://vault.example.com:8200",
+///     "transit/backups",
+///     VaultAuth::Token(None),
+///     Callbacks,
+/// )?;
+/// let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient)])
+///     .expect("we provided a recipient");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct VaultTransitRecipient<C: Callbacks>(VaultKey<C>);
+
+impl<C: Callbacks> VaultTransitRecipient<C> {
+    /// Creates a recipient for the key `key` in the Vault server at `address` (such as
+    /// `https://vault.example.com:8200`).
+    ///
+    /// `key` is the mount path of the transit secrets engine and the key's name,
+    /// separated by a slash (such as `transit/backups`). If there is no slash, the
+    /// engine is assumed to be mounted at `transit`.
+    ///
+    /// Returns an error if `key` is invalid.
+    pub fn new(
+        address: &str,
+        key: &str,
+        auth: VaultAuth,
+        callbacks: C,
+    ) -> Result<Self, &'static str> {
+        VaultKey::new(address, key, auth, callbacks).map(VaultTransitRecipient)
+    }
+}
+
+impl<C: Callbacks> fmt::Display for VaultTransitRecipient<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/v1/{}", self.0.address, self.0.key_id)
+    }
+}
+
+impl<C: Callbacks> crate::Recipient for VaultTransitRecipient<C> {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        super::wrap_file_key(&self.0, file_key)
+    }
+}
+
+/// An identity that unwraps file keys with a key in a HashiCorp Vault transit secrets
+/// engine.
+///
+/// This only unwraps stanzas created by a [`VaultTransitRecipient`] for the same key
+/// (identified by its mount path and name).
+#[derive(Clone)]
+pub struct VaultTransitIdentity<C: Callbacks>(VaultKey<C>);
+
+impl<C: Callbacks> VaultTransitIdentity<C> {
+    /// Creates an identity for the key `key` in the Vault server at `address`.
+    ///
+    /// See [`VaultTransitRecipient::new`] for the format of `key`.
+    pub fn new(
+        address: &str,
+        key: &str,
+        auth: VaultAuth,
+        callbacks: C,
+    ) -> Result<Self, &'static str> {
+        VaultKey::new(address, key, auth, callbacks).map(VaultTransitIdentity)
+    }
+
+    /// Returns the recipient for the same key, which shares this identity's token.
+    pub fn to_recipient(&self) -> VaultTransitRecipient<C> {
+        VaultTransitRecipient(self.0.clone())
+    }
+}
+
+impl<C: Callbacks> crate::Identity for VaultTransitIdentity<C> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        super::unwrap_stanza(&self.0, stanza)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::FileKey,
+        secrecy::{ExposeSecret, SecretString},
+    };
+    use serde_json::json;
+    use std::thread;
+
+    use super::{VaultAuth, VaultTransitIdentity, VaultTransitRecipient};
+    use crate::{kms::mock, Callbacks, DecryptError, Identity, Recipient};
+
+    #[derive(Clone)]
+    struct SecretId;
+
+    impl Callbacks for SecretId {
+        fn request_passphrase(&self, _: &str) -> Option<SecretString> {
+            Some(SecretString::new("secret-id".into()))
+        }
+    }
+
+    /// Serves `requests` requests like a Vault server with a transit engine, whose
+    /// "ciphertexts" are the base64 plaintext.
+    fn mock_vault(requests: usize) -> (String, thread::JoinHandle<Vec<String>>) {
+        mock::serve(requests, |request| {
+            let body = request.json();
+            match (request.path.as_str(), request.header("X-Vault-Token")) {
+                ("/v1/auth/approle/login", None)
+                    if body == json!({"role_id": "role", "secret_id": "secret-id"}) =>
+                {
+                    Some(json!({ "auth": { "client_token": "s.token" } }))
+                }
+                ("/v1/transit/encrypt/backups", Some("s.token")) => Some(json!({
+                    "data": {
+                        "ciphertext": format!("vault:v1:{}", body["plaintext"].as_str().unwrap()),
+                    }
+                })),
+                ("/v1/transit/decrypt/backups", Some("s.token")) => Some(json!({
+                    "data": {
+                        "plaintext": body["ciphertext"].as_str().unwrap().strip_prefix("vault:v1:"),
+                    }
+                })),
+                _ => None,
+            }
+        })
+    }
+
+    #[test]
+    fn parse_keys() {
+        let recipient = VaultTransitRecipient::new(
+            "http://vault:8200/",
+            "backups",
+            VaultAuth::Token(None),
+            SecretId,
+        )
+        .unwrap();
+        assert_eq!(recipient.0.mount, "transit");
+        assert_eq!(recipient.0.key_id, "transit/backups");
+        assert_eq!(
+            recipient.to_string(),
+            "http://vault:8200/v1/transit/backups"
+        );
+
+        let identity = VaultTransitIdentity::new(
+            "http://vault:8200",
+            "/teams/infra/transit/backups",
+            VaultAuth::Token(None),
+            SecretId,
+        )
+        .unwrap();
+        assert_eq!(identity.0.mount, "teams/infra/transit");
+        assert_eq!(identity.0.name, "backups");
+
+        for invalid in ["", "transit/", "/backups", "transit/my backups"] {
+            assert!(
+                VaultTransitIdentity::new(
+                    "http://vault:8200",
+                    invalid,
+                    VaultAuth::Token(None),
+                    SecretId
+                )
+                .is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let (address, server) = mock_vault(5);
+        let file_key = FileKey::from([7; 16]);
+
+        let recipient = VaultTransitRecipient::new(
+            &address,
+            "transit/backups",
+            VaultAuth::AppRole {
+                role_id: "role".into(),
+                secret_id: None,
+            },
+            SecretId,
+        )
+        .unwrap();
+        let stanzas = recipient.wrap_file_key(&file_key).unwrap();
+        assert_eq!(stanzas[0].tag, "vault-transit");
+        assert_eq!(stanzas[0].args, vec!["transit/backups".to_owned()]);
+        assert!(stanzas[0].body().starts_with(b"vault:v1:"));
+        // The token is reused for later requests.
+        recipient.wrap_file_key(&file_key).unwrap();
+
+        let identity = VaultTransitIdentity::new(
+            &address,
+            "backups",
+            VaultAuth::Token(Some(SecretString::new("s.token".into()))),
+            SecretId,
+        )
+        .unwrap();
+        let unwrapped = identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        let unauthorized = VaultTransitIdentity::new(
+            &address,
+            "backups",
+            VaultAuth::Token(Some(SecretString::new("s.other".into()))),
+            SecretId,
+        )
+        .unwrap();
+        match unauthorized.unwrap_stanza(&stanzas[0]) {
+            Some(Err(DecryptError::Kms(e))) => assert_eq!(e.message(), "permission denied"),
+            _ => panic!("expected a KMS error"),
+        }
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "/v1/auth/approle/login",
+                "/v1/transit/encrypt/backups",
+                "/v1/transit/encrypt/backups",
+                "/v1/transit/decrypt/backups",
+                "/v1/transit/decrypt/backups",
+            ]
+        );
+    }
+}
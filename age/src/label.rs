@@ -0,0 +1,161 @@
+//! Labels that identify which key a recipient stanza was wrapped to.
+//!
+//! A labeled recipient's stanzas are each preceded by a stanza that carries the label
+//! and no secrets:
+//!
+//! ```text
+//! -> label finance-escrow
+//!
+//! -> X25519 ...
+//! ...
+//! ```
+//!
+//! Identities ignore `label` stanzas like any other stanza type they don't know, so
+//! labeled files can be decrypted by any age implementation. The label is not
+//! authenticated until the header MAC has been verified.
+
+use age_core::format::{FileKey, Stanza};
+
+use crate::{
+    error::{DecryptError, EncryptError},
+    stanza::{Arg, Body, Rule},
+    Recipient,
+};
+
+pub(crate) const LABEL_TAG: &str = "label";
+
+/// The format of a label stanza.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: LABEL_TAG,
+    args: &[Arg::Any],
+    body: Body::Exact(0),
+};
+
+/// A recipient whose stanzas are labeled in the age file's header.
+///
+/// Labels are opaque strings chosen by the encrypting party, such as a key ID or the
+/// name of a team. They can be read back before decryption with
+/// [`RecipientStanza::label`], so that callers can tell which key a stanza targets
+/// without trying every identity against it.
+///
+/// Labels are visible to anyone who can read the age file, so they should not contain
+/// anything that the recipients' public keys would not already reveal.
+///
+/// [`RecipientStanza::label`]: crate::decryptor::RecipientStanza::label
+#[derive(Clone, Debug)]
+pub struct Labeled<R> {
+    label: String,
+    recipient: R,
+}
+
+impl<R: Recipient> Labeled<R> {
+    /// Labels the stanzas that `recipient` wraps with `label`.
+    ///
+    /// Returns `None` if `label` is empty, or contains characters other than printable
+    /// ASCII (spaces are not allowed).
+    pub fn new(label: impl Into<String>, recipient: R) -> Option<Self> {
+        let label = label.into();
+        (!label.is_empty() && label.bytes().all(|b| b.is_ascii_graphic()))
+            .then(|| Labeled { label, recipient })
+    }
+
+    /// Returns the label.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the recipient that is being labeled.
+    pub fn into_inner(self) -> R {
+        self.recipient
+    }
+}
+
+impl<R: Recipient> Recipient for Labeled<R> {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        let stanzas = self.recipient.wrap_file_key(file_key)?;
+        Ok(stanzas
+            .into_iter()
+            .flat_map(|stanza| {
+                [
+                    Stanza::new(LABEL_TAG.to_owned(), vec![self.label.clone()], vec![]),
+                    stanza,
+                ]
+            })
+            .collect())
+    }
+}
+
+/// Checks that every label stanza is followed by the stanza that it labels.
+pub(crate) fn check_structure(stanzas: &[Stanza]) -> Result<(), DecryptError> {
+    let mut iter = stanzas.iter().peekable();
+    while let Some(stanza) = iter.next() {
+        let labels_next = matches!(iter.peek(), Some(next) if next.tag != LABEL_TAG);
+        if stanza.tag == LABEL_TAG && !labels_next {
+            return Err(DecryptError::InvalidHeader);
+        }
+    }
+    Ok(())
+}
+
+/// Pairs each stanza that is not a label with the label that precedes it, if any.
+///
+/// The stanzas must have passed [`check_structure`].
+pub(crate) fn labeled_stanzas(stanzas: &[Stanza]) -> impl Iterator<Item = (&Stanza, Option<&str>)> {
+    let mut label = None;
+    stanzas.iter().filter_map(move |stanza| {
+        if stanza.tag == LABEL_TAG {
+            label = Some(stanza.args[0].as_str());
+            None
+        } else {
+            Some((stanza, label.take()))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::format::{FileKey, Stanza};
+
+    use super::{check_structure, labeled_stanzas, Labeled};
+    use crate::{x25519, DecryptError, Recipient};
+
+    fn stanza(tag: &str) -> Stanza {
+        Stanza::new(tag.to_owned(), vec![], vec![])
+    }
+
+    #[test]
+    fn invalid_labels() {
+        let recipient = x25519::Identity::generate().to_public();
+        assert!(Labeled::new("", recipient.clone()).is_none());
+        assert!(Labeled::new("two words", recipient.clone()).is_none());
+        assert!(Labeled::new("caf\u{e9}", recipient.clone()).is_none());
+        assert_eq!(Labeled::new("key-1", recipient).unwrap().label(), "key-1");
+    }
+
+    #[test]
+    fn wrap_and_read_labels() {
+        let recipient = Labeled::new("key-1", x25519::Identity::generate().to_public()).unwrap();
+        let mut stanzas = recipient.wrap_file_key(&FileKey::from([7; 16])).unwrap();
+        stanzas.push(stanza("grease"));
+        check_structure(&stanzas).unwrap();
+
+        let labeled: Vec<_> = labeled_stanzas(&stanzas)
+            .map(|(s, label)| (s.tag.as_str(), label))
+            .collect();
+        assert_eq!(labeled, vec![("X25519", Some("key-1")), ("grease", None)]);
+    }
+
+    #[test]
+    fn dangling_labels() {
+        let label = |l: &str| Stanza::new("label".to_owned(), vec![l.to_owned()], vec![]);
+        for invalid in [
+            &[stanza("X25519"), label("a")][..],
+            &[label("a"), label("b"), stanza("X25519")][..],
+        ] {
+            assert!(matches!(
+                check_structure(invalid),
+                Err(DecryptError::InvalidHeader)
+            ));
+        }
+    }
+}
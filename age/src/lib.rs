@@ -134,7 +134,22 @@
 //! ```
 
 #![cfg_attr(docsrs, feature(doc_cfg))]
-#![forbid(unsafe_code)]
+#![cfg_attr(
+    not(any(
+        all(any(feature = "cng", feature = "keyring"), windows),
+        all(feature = "secure-enclave", target_os = "macos"),
+    )),
+    forbid(unsafe_code)
+)]
+// The CNG, Credential Manager, and Secure Enclave backends need FFI; they opt back in to
+// `unsafe` locally.
+#![cfg_attr(
+    any(
+        all(any(feature = "cng", feature = "keyring"), windows),
+        all(feature = "secure-enclave", target_os = "macos"),
+    ),
+    deny(unsafe_code)
+)]
 // Catch documentation errors caused by code changes.
 #![deny(rustdoc::broken_intra_doc_links)]
 #![deny(missing_docs)]
@@ -144,26 +159,75 @@ pub use age_core::secrecy;
 
 mod error;
 mod format;
+mod header;
 mod identity;
 mod keys;
+mod label;
 mod primitives;
 mod protocol;
+mod rekey;
+mod rng;
+mod stanza;
+mod threshold;
 mod util;
 
-pub use error::{DecryptError, EncryptError};
-pub use identity::{IdentityFile, IdentityFileEntry};
+#[cfg(any(feature = "cng", feature = "secure-enclave", feature = "yubikey"))]
+mod piv_p256;
+
+pub use error::{DecryptError, EncryptError, RngError, StanzaError};
+pub use header::Header;
+pub use identity::{IdentityFile, IdentityFileEntry, IdentityMetadata};
+pub use label::Labeled;
 pub use primitives::stream;
-pub use protocol::{decryptor, Decryptor, Encryptor};
+pub use protocol::{airgap, decryptor, Decryptor, Encryptor, EncryptorBuilder};
+pub use rekey::rekey;
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+pub use protocol::experimental;
 
 #[cfg(feature = "armor")]
 pub use primitives::armor;
 
+pub mod audit;
+
 #[cfg(feature = "cli-common")]
 #[cfg_attr(docsrs, doc(cfg(feature = "cli-common")))]
 pub mod cli_common;
 
+pub mod fanout;
+pub mod fs;
+
+#[cfg(feature = "http-range")]
+#[cfg_attr(docsrs, doc(cfg(feature = "http-range")))]
+pub mod http_range;
+
+#[cfg(feature = "keyring")]
+#[cfg_attr(docsrs, doc(cfg(feature = "keyring")))]
+pub mod keyring;
+
+#[cfg(feature = "kms")]
+#[cfg_attr(docsrs, doc(cfg(feature = "kms")))]
+pub mod kms;
+
+#[cfg(feature = "mnemonic")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mnemonic")))]
+pub mod mnemonic;
+
+#[cfg(feature = "qr")]
+#[cfg_attr(docsrs, doc(cfg(feature = "qr")))]
+pub mod qr;
+
+#[cfg(feature = "remote")]
+#[cfg_attr(docsrs, doc(cfg(feature = "remote")))]
+pub mod remote;
+
+#[cfg(feature = "service")]
+#[cfg_attr(docsrs, doc(cfg(feature = "service")))]
+pub mod service;
+
 mod i18n;
-pub use i18n::localizer;
+pub use i18n::{language_loader, localizer};
 
 //
 // Identity types
@@ -173,14 +237,34 @@ pub mod encrypted;
 mod scrypt;
 pub mod x25519;
 
+#[cfg(feature = "cng")]
+#[cfg_attr(docsrs, doc(cfg(feature = "cng")))]
+pub mod cng;
+
+#[cfg(feature = "fido2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fido2")))]
+pub mod fido2;
+
 #[cfg(feature = "plugin")]
 #[cfg_attr(docsrs, doc(cfg(feature = "plugin")))]
 pub mod plugin;
 
+#[cfg(feature = "pq")]
+#[cfg_attr(docsrs, doc(cfg(feature = "pq")))]
+pub mod pq;
+
+#[cfg(feature = "secure-enclave")]
+#[cfg_attr(docsrs, doc(cfg(feature = "secure-enclave")))]
+pub mod secure_enclave;
+
 #[cfg(feature = "ssh")]
 #[cfg_attr(docsrs, doc(cfg(feature = "ssh")))]
 pub mod ssh;
 
+#[cfg(feature = "yubikey")]
+#[cfg_attr(docsrs, doc(cfg(feature = "yubikey")))]
+pub mod yubikey;
+
 use age_core::{
     format::{FileKey, Stanza},
     secrecy::SecretString,
@@ -216,10 +300,38 @@ pub trait Identity {
     /// - `Some(Err(e))` if a decryption error occurs.
     /// - `None` if none of the recipient stanzas match this identity.
     ///
+    /// The result is that of the first stanza that matches this identity. Unless this
+    /// identity [is interactive](Identity::is_interactive), the default implementation
+    /// tries every stanza before selecting that result, so that the time taken does not
+    /// reveal which stanza matched.
+    ///
     /// [one joint]: https://www.imperialviolet.org/2016/05/16/agility.html
     /// [`RecipientsDecryptor::decrypt`]: protocol::decryptor::RecipientsDecryptor::decrypt
     fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, DecryptError>> {
-        stanzas.iter().find_map(|stanza| self.unwrap_stanza(stanza))
+        if self.is_interactive() {
+            stanzas.iter().find_map(|stanza| self.unwrap_stanza(stanza))
+        } else {
+            util::select_file_key(
+                stanzas
+                    .iter()
+                    .map(|stanza| self.unwrap_stanza(stanza))
+                    .collect(),
+            )
+        }
+    }
+
+    /// Returns whether unwrapping stanzas with this identity may interact with the user
+    /// or with another system, for example by prompting for a passphrase or a touch,
+    /// running a plugin, or contacting a key management service.
+    ///
+    /// Interactive identities (the default) are only tried until the file key has been
+    /// unwrapped, and stop at the first stanza that they can unwrap, so that the user
+    /// isn't prompted more than necessary. Identities that only do local computation
+    /// should override this to return `false`: they are then tried against every stanza
+    /// when decrypting, even once the file key has been unwrapped, so that the time
+    /// taken does not reveal which identity matched which stanza.
+    fn is_interactive(&self) -> bool {
+        true
     }
 }
 
@@ -239,16 +351,65 @@ pub trait Recipient {
     fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError>;
 }
 
+impl<I: Identity + ?Sized> Identity for &I {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        (**self).unwrap_stanza(stanza)
+    }
+
+    fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, DecryptError>> {
+        (**self).unwrap_stanzas(stanzas)
+    }
+
+    fn is_interactive(&self) -> bool {
+        (**self).is_interactive()
+    }
+}
+
+impl<I: Identity + ?Sized> Identity for Box<I> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        (**self).unwrap_stanza(stanza)
+    }
+
+    fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, DecryptError>> {
+        (**self).unwrap_stanzas(stanzas)
+    }
+
+    fn is_interactive(&self) -> bool {
+        (**self).is_interactive()
+    }
+}
+
+impl<R: Recipient + ?Sized> Recipient for &R {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        (**self).wrap_file_key(file_key)
+    }
+}
+
+impl<R: Recipient + ?Sized> Recipient for Box<R> {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        (**self).wrap_file_key(file_key)
+    }
+}
+
 /// Callbacks that might be triggered during encryption or decryption.
 ///
 /// Structs that implement this trait should be given directly to the individual
 /// `Recipient` or `Identity` implementations that require them.
+///
+/// Only [`Callbacks::request_passphrase`] must be implemented. The remaining methods
+/// have default implementations that behave as if there is no UI available for them,
+/// so implementations can opt in to richer interaction (such as touch prompts or
+/// confirmation dialogs for hardware keys) as needed.
 pub trait Callbacks: Clone + Send + Sync + 'static {
     /// Shows a message to the user.
     ///
     /// This can be used to prompt the user to take some physical action, such as
     /// inserting a hardware key.
-    fn display_message(&self, message: &str);
+    ///
+    /// The default implementation discards the message.
+    fn display_message(&self, message: &str) {
+        let _ = message;
+    }
 
     /// Requests that the user provides confirmation for some action.
     ///
@@ -265,12 +426,22 @@ pub trait Callbacks: Clone + Send + Sync + 'static {
     ///   default negative confirmation label).
     /// - `None` if the confirmation request could not be given to the user (for example,
     ///   if there is no UI for displaying messages).
-    fn confirm(&self, message: &str, yes_string: &str, no_string: Option<&str>) -> Option<bool>;
+    ///
+    /// The default implementation returns `None`.
+    fn confirm(&self, message: &str, yes_string: &str, no_string: Option<&str>) -> Option<bool> {
+        let _ = (message, yes_string, no_string);
+        None
+    }
 
     /// Requests non-private input from the user.
     ///
     /// To request private inputs, use [`Callbacks::request_passphrase`].
-    fn request_public_string(&self, description: &str) -> Option<String>;
+    ///
+    /// The default implementation returns `None`.
+    fn request_public_string(&self, description: &str) -> Option<String> {
+        let _ = description;
+        None
+    }
 
     /// Requests a passphrase to decrypt a key.
     fn request_passphrase(&self, description: &str) -> Option<SecretString>;
@@ -285,3 +456,17 @@ pub fn fuzz_header(data: &[u8]) {
         assert_eq!(&buf[..], &data[..buf.len()]);
     }
 }
+
+/// Helper for fuzzing the Header serializer and parser with structured inputs.
+///
+/// Generates an arbitrary header from `data`, and checks that writing and then reading
+/// it returns the same header.
+#[cfg(feature = "fuzzing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fuzzing")))]
+pub fn fuzz_header_round_trip(data: &[u8]) {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    if let Ok(header) = format::Header::arbitrary(&mut Unstructured::new(data)) {
+        header.check_round_trip();
+    }
+}
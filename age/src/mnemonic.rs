@@ -0,0 +1,208 @@
+//! Backing up X25519 identities as mnemonic phrases.
+//!
+//! [`x25519::Identity::to_mnemonic`] encodes a secret key as 24 words from the English
+//! BIP 39 wordlist, which are easier to write down on paper (and to type back in) than
+//! a Bech32 string. [`x25519::Identity::from_mnemonic`] recovers the secret key.
+//!
+//! The phrase encodes the 32 bytes of the secret key as BIP 39 entropy, followed by an
+//! 8-bit checksum that catches most mistyped or swapped words. Unlike a BIP 39 wallet
+//! seed, the phrase is not stretched with PBKDF2, so the same phrase always gives the
+//! same identity.
+//!
+//! [`x25519::Identity::to_mnemonic`]: crate::x25519::Identity::to_mnemonic
+//! [`x25519::Identity::from_mnemonic`]: crate::x25519::Identity::from_mnemonic
+
+use age_core::secrecy::SecretString;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use zeroize::Zeroizing;
+
+use crate::{util::BIP39_WORDLIST, wfl};
+
+/// The number of words in a mnemonic phrase.
+pub const MNEMONIC_WORDS: usize = 24;
+
+/// The number of bits encoded by each word.
+const BITS_PER_WORD: usize = 11;
+
+/// The number of entropy bytes, followed by one checksum byte.
+const ENTROPY_BYTES: usize = 32;
+
+/// Errors that can occur while parsing a mnemonic phrase.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MnemonicError {
+    /// The phrase has the wrong number of words.
+    WrongLength(usize),
+    /// The word at `position` is not in the wordlist. Positions count from 0.
+    UnknownWord {
+        /// The position of the unknown word.
+        position: usize,
+        /// The unknown word.
+        word: String,
+    },
+    /// The checksum doesn't match, so a word was probably mistyped or swapped.
+    InvalidChecksum,
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MnemonicError::WrongLength(words) => write!(
+                f,
+                "{}",
+                i18n_embed_fl::fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-mnemonic-length",
+                    expected = MNEMONIC_WORDS,
+                    words = words,
+                )
+            ),
+            MnemonicError::UnknownWord { position, word } => write!(
+                f,
+                "{}",
+                i18n_embed_fl::fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "err-mnemonic-unknown-word",
+                    // Positions are shown to the user counting from 1.
+                    position = (position + 1),
+                    word = word.as_str(),
+                )
+            ),
+            MnemonicError::InvalidChecksum => wfl!(f, "err-mnemonic-checksum"),
+        }
+    }
+}
+
+impl std::error::Error for MnemonicError {}
+
+fn wordlist() -> impl Iterator<Item = &'static str> {
+    BIP39_WORDLIST.lines()
+}
+
+/// Finds the index of `word` in the wordlist.
+///
+/// The first four letters of each word are unique, so (as for BIP 39) a word can be
+/// abbreviated to them.
+fn word_index(word: &str) -> Option<u16> {
+    wordlist()
+        .position(|w| w == word || (word.len() >= 4 && w.starts_with(word)))
+        .map(|i| i as u16)
+}
+
+/// Encodes `entropy` as a mnemonic phrase.
+pub(crate) fn encode(entropy: &[u8; ENTROPY_BYTES]) -> SecretString {
+    let checksum = Sha256::digest(entropy)[0];
+    let mut bits = entropy
+        .iter()
+        .chain(Some(&checksum))
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+
+    let words: Vec<_> = wordlist().collect();
+    let phrase = (0..MNEMONIC_WORDS)
+        .map(|_| {
+            let index = (0..BITS_PER_WORD).fold(0, |index, _| {
+                (index << 1) | usize::from(bits.next().expect("enough bits"))
+            });
+            words[index]
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    SecretString::new(phrase)
+}
+
+/// Decodes the entropy from a mnemonic phrase.
+///
+/// Words are separated by whitespace, and case is ignored.
+pub(crate) fn decode(phrase: &str) -> Result<Zeroizing<[u8; ENTROPY_BYTES]>, MnemonicError> {
+    let phrase = Zeroizing::new(phrase.to_lowercase());
+    let words: Vec<_> = phrase.split_whitespace().collect();
+    if words.len() != MNEMONIC_WORDS {
+        return Err(MnemonicError::WrongLength(words.len()));
+    }
+
+    let mut bytes = Zeroizing::new([0; ENTROPY_BYTES + 1]);
+    for (position, word) in words.into_iter().enumerate() {
+        let index = word_index(word).ok_or_else(|| MnemonicError::UnknownWord {
+            position,
+            word: word.to_owned(),
+        })?;
+        for i in 0..BITS_PER_WORD {
+            let bit = position * BITS_PER_WORD + i;
+            if (index >> (BITS_PER_WORD - 1 - i)) & 1 == 1 {
+                bytes[bit / 8] |= 0x80 >> (bit % 8);
+            }
+        }
+    }
+
+    let mut entropy = Zeroizing::new([0; ENTROPY_BYTES]);
+    entropy.copy_from_slice(&bytes[..ENTROPY_BYTES]);
+    if Sha256::digest(&entropy[..])[0] == bytes[ENTROPY_BYTES] {
+        Ok(entropy)
+    } else {
+        Err(MnemonicError::InvalidChecksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::secrecy::ExposeSecret;
+
+    use super::{decode, encode, MnemonicError};
+    use crate::x25519;
+
+    #[test]
+    fn bip39_vector() {
+        // From the BIP 39 English test vectors.
+        let entropy = [0x7f; 32];
+        let phrase = "legal winner thank year wave sausage worth useful legal winner thank \
+                      year wave sausage worth useful legal winner thank year wave sausage \
+                      worth title";
+        assert_eq!(encode(&entropy).expose_secret(), phrase);
+        assert_eq!(*decode(phrase).unwrap(), entropy);
+    }
+
+    #[test]
+    fn identity_round_trip() {
+        let identity = x25519::Identity::generate();
+        let phrase = identity.to_mnemonic();
+        let recovered = x25519::Identity::from_mnemonic(phrase.expose_secret()).unwrap();
+        assert_eq!(
+            recovered.to_string().expose_secret(),
+            identity.to_string().expose_secret()
+        );
+
+        // Case, extra whitespace, and four-letter abbreviations are accepted.
+        let abbreviated = phrase
+            .expose_secret()
+            .split(' ')
+            .map(|word| word.chars().take(4).collect::<String>().to_uppercase())
+            .collect::<Vec<_>>()
+            .join("\n  ");
+        assert_eq!(
+            x25519::Identity::from_mnemonic(&abbreviated)
+                .unwrap()
+                .to_public()
+                .to_string(),
+            identity.to_public().to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_phrases() {
+        let phrase = "legal winner thank year wave sausage worth useful legal winner thank \
+                      year wave sausage worth useful legal winner thank year wave sausage \
+                      worth";
+        assert_eq!(decode(phrase).unwrap_err(), MnemonicError::WrongLength(23));
+        assert_eq!(
+            decode(&format!("{} tilte", phrase)).unwrap_err(),
+            MnemonicError::UnknownWord {
+                position: 23,
+                word: "tilte".into(),
+            }
+        );
+        assert_eq!(
+            decode(&format!("{} winner", phrase)).unwrap_err(),
+            MnemonicError::InvalidChecksum
+        );
+    }
+}
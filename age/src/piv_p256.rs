@@ -0,0 +1,242 @@
+//! The "piv-p256" stanza format, shared by hardware-backed P-256 identities.
+//!
+//! This is the same stanza format used by `age-plugin-yubikey` for PIV keys, so files
+//! encrypted to any P-256 hardware key are compatible across implementations.
+
+use age_core::{
+    format::{FileKey, Stanza, FILE_KEY_BYTES},
+    primitives::{aead_decrypt, aead_encrypt, hkdf},
+    secrecy::ExposeSecret,
+};
+use bech32::{ToBase32, Variant};
+use p256::{
+    ecdh::EphemeralSecret,
+    elliptic_curve::sec1::{EncodedPoint, ToEncodedPoint},
+    NistP256, PublicKey,
+};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use crate::{
+    error::DecryptError,
+    stanza::{Arg, Body, Rule},
+    util::{parse_bech32, read::base64_arg},
+};
+
+const STANZA_TAG: &str = "piv-p256";
+const STANZA_KEY_LABEL: &[u8] = b"piv-p256";
+
+pub(crate) const TAG_BYTES: usize = 4;
+const EPK_BYTES: usize = 33;
+const ENCRYPTED_FILE_KEY_BYTES: usize = FILE_KEY_BYTES + 16;
+
+/// The format of a piv-p256 recipient stanza.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: STANZA_TAG,
+    args: &[Arg::Base64(TAG_BYTES), Arg::Base64(EPK_BYTES)],
+    body: Body::Exact(ENCRYPTED_FILE_KEY_BYTES),
+};
+
+/// Returns the compressed SEC1 encoding of the given public key.
+fn compressed(pk: &PublicKey) -> EncodedPoint<NistP256> {
+    pk.to_encoded_point(true)
+}
+
+/// The short identifier that is used to match stanzas to keys without trial decryption.
+pub(crate) fn key_tag(pk: &PublicKey) -> [u8; TAG_BYTES] {
+    Sha256::digest(compressed(pk).as_bytes())[..TAG_BYTES]
+        .try_into()
+        .expect("length is correct")
+}
+
+/// Parses a Bech32-encoded P-256 recipient with the given HRP.
+pub(crate) fn parse_recipient(s: &str, prefix: &str) -> Result<PublicKey, &'static str> {
+    parse_bech32(s)
+        .ok_or("invalid Bech32 encoding")
+        .and_then(|(hrp, bytes)| {
+            if hrp == prefix {
+                if bytes.len() == EPK_BYTES {
+                    PublicKey::from_sec1_bytes(&bytes).map_err(|_| "invalid pubkey")
+                } else {
+                    Err("incorrect pubkey length")
+                }
+            } else {
+                Err("incorrect HRP")
+            }
+        })
+}
+
+/// Encodes a P-256 recipient as Bech32 with the given HRP.
+pub(crate) fn encode_recipient(pk: &PublicKey, prefix: &str) -> String {
+    bech32::encode(
+        prefix,
+        compressed(pk).as_bytes().to_base32(),
+        Variant::Bech32,
+    )
+    .expect("HRP is valid")
+}
+
+/// Wraps `file_key` to the P-256 key `pk`.
+pub(crate) fn wrap_file_key(pk: &PublicKey, file_key: &FileKey) -> Stanza {
+    let esk = EphemeralSecret::random(&mut OsRng);
+    let epk = esk.public_key();
+    let epk_bytes = compressed(&epk);
+    let shared_secret = esk.diffie_hellman(pk);
+
+    let mut salt = vec![];
+    salt.extend_from_slice(epk_bytes.as_bytes());
+    salt.extend_from_slice(compressed(pk).as_bytes());
+
+    let enc_key = hkdf(
+        &salt,
+        STANZA_KEY_LABEL,
+        shared_secret.raw_secret_bytes().as_slice(),
+    );
+    let encrypted_file_key = aead_encrypt(&enc_key, file_key.expose_secret());
+
+    Stanza::new(
+        STANZA_TAG.to_owned(),
+        vec![
+            base64::encode_config(key_tag(pk), base64::STANDARD_NO_PAD),
+            base64::encode_config(epk_bytes.as_bytes(), base64::STANDARD_NO_PAD),
+        ],
+        encrypted_file_key,
+    )
+}
+
+/// Returns the key tag of a `piv-p256` stanza, or `None` if this is some other stanza.
+///
+/// Returns an error if the stanza is malformed, as [`unwrap_stanza_with`] would.
+#[cfg(feature = "yubikey")]
+pub(crate) fn stanza_key_tag(stanza: &Stanza) -> Option<Result<[u8; TAG_BYTES], DecryptError>> {
+    if stanza.tag != STANZA_TAG {
+        return None;
+    }
+    if let Err(e) = STANZA_RULE.check(stanza) {
+        return Some(Err(e.into()));
+    }
+    let tag = base64_arg(&stanza.args[0], [0; TAG_BYTES])
+        .expect("Argument should have been checked above");
+    Some(Ok(tag))
+}
+
+/// Unwraps a `piv-p256` stanza for the key `pk`, using `ecdh` to compute the shared
+/// secret with the stanza's ephemeral public key.
+///
+/// `ecdh` is only called if the stanza is addressed to `pk`, so hardware backends will
+/// not prompt the user for stanzas that they cannot unwrap.
+#[cfg_attr(
+    not(any(
+        all(feature = "secure-enclave", target_os = "macos"),
+        all(feature = "cng", windows),
+        feature = "yubikey",
+        test
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn unwrap_stanza_with<F>(
+    pk: &PublicKey,
+    stanza: &Stanza,
+    ecdh: F,
+) -> Option<Result<FileKey, DecryptError>>
+where
+    F: FnOnce(&PublicKey) -> Result<Vec<u8>, DecryptError>,
+{
+    if stanza.tag != STANZA_TAG {
+        return None;
+    }
+
+    // Enforce valid and canonical stanza format.
+    if let Err(e) = STANZA_RULE.check(stanza) {
+        return Some(Err(e.into()));
+    }
+    let tag = base64_arg(&stanza.args[0], [0; TAG_BYTES])
+        .expect("Argument should have been checked above");
+    let epk_bytes = base64_arg(&stanza.args[1], [0; EPK_BYTES])
+        .expect("Argument should have been checked above");
+
+    // This stanza is for a different key.
+    if tag != key_tag(pk) {
+        return None;
+    }
+
+    let epk = match PublicKey::from_sec1_bytes(&epk_bytes) {
+        Ok(epk) => epk,
+        Err(_) => return Some(Err(DecryptError::InvalidHeader)),
+    };
+
+    let mut shared_secret = match ecdh(&epk) {
+        Ok(shared_secret) => shared_secret,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let mut salt = vec![];
+    salt.extend_from_slice(&epk_bytes);
+    salt.extend_from_slice(compressed(pk).as_bytes());
+
+    let enc_key = hkdf(&salt, STANZA_KEY_LABEL, &shared_secret);
+    shared_secret.zeroize();
+
+    // Tags are only four bytes, so a collision with another key is possible; treat a
+    // failure to decrypt as "not ours" rather than a fatal error.
+    aead_decrypt(&enc_key, FILE_KEY_BYTES, stanza.body())
+        .ok()
+        .map(|mut pt| {
+            let file_key: [u8; FILE_KEY_BYTES] = pt[..].try_into().unwrap();
+            pt.zeroize();
+            Ok(file_key.into())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{format::FileKey, secrecy::ExposeSecret};
+    use p256::ecdh::EphemeralSecret;
+    use rand::rngs::OsRng;
+
+    use super::{unwrap_stanza_with, wrap_file_key};
+
+    #[test]
+    fn wrap_and_unwrap() {
+        // Stand in for a hardware key with a software key.
+        let sk = EphemeralSecret::random(&mut OsRng);
+        let pk = sk.public_key();
+
+        let file_key: FileKey = [12; 16].into();
+        let stanza = wrap_file_key(&pk, &file_key);
+
+        let unwrapped = unwrap_stanza_with(&pk, &stanza, |epk| {
+            Ok(sk.diffie_hellman(epk).raw_secret_bytes().to_vec())
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        // A different key ignores the stanza without attempting ECDH.
+        let other = EphemeralSecret::random(&mut OsRng).public_key();
+        assert!(unwrap_stanza_with(&other, &stanza, |_| unreachable!()).is_none());
+    }
+
+    #[cfg(feature = "yubikey")]
+    #[test]
+    fn stanza_key_tag() {
+        use super::{key_tag, stanza_key_tag};
+        use crate::DecryptError;
+
+        let pk = EphemeralSecret::random(&mut OsRng).public_key();
+        let mut stanza = wrap_file_key(&pk, &[12; 16].into());
+        assert_eq!(stanza_key_tag(&stanza).unwrap().unwrap(), key_tag(&pk));
+
+        // Malformed stanzas are rejected, as they would be when unwrapping them.
+        stanza.args.pop();
+        assert!(matches!(
+            stanza_key_tag(&stanza),
+            Some(Err(DecryptError::InvalidStanza(_)))
+        ));
+        assert!(matches!(
+            unwrap_stanza_with(&pk, &stanza, |_| unreachable!()),
+            Some(Err(DecryptError::InvalidStanza(_)))
+        ));
+    }
+}
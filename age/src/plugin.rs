@@ -3,19 +3,24 @@
 use age_core::{
     format::{FileKey, Stanza},
     io::{DebugReader, DebugWriter},
-    plugin::{Connection, Reply, Response, IDENTITY_V1, RECIPIENT_V1},
+    plugin::{Connection, Reply, Response, Terminator, IDENTITY_V1, RECIPIENT_V1},
     secrecy::ExposeSecret,
 };
 use bech32::Variant;
 use i18n_embed_fl::fl;
 
 use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::env;
 use std::fmt;
 use std::io;
 use std::iter;
 use std::path::PathBuf;
 use std::process::{ChildStdin, ChildStdout};
-use std::sync::mpsc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
 use std::thread;
 use std::time::{Duration, SystemTime};
 
@@ -44,41 +49,121 @@ fn binary_name(plugin_name: &str) -> String {
     format!("age-plugin-{}", plugin_name)
 }
 
-struct SlowPluginGuard(mpsc::Sender<()>);
+/// Returns the per-user directory in which plugins can be installed without adding
+/// them to `$PATH`.
+///
+/// This is `$XDG_DATA_HOME/age/plugins` (or `~/.local/share/age/plugins`) on Unix,
+/// `~/Library/Application Support/age/plugins` on macOS, and
+/// `%LOCALAPPDATA%\age\plugins` on Windows.
+pub fn user_plugin_dir() -> Option<PathBuf> {
+    fn var(key: &str) -> Option<PathBuf> {
+        env::var_os(key)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    }
+
+    #[cfg(windows)]
+    let base = var("LOCALAPPDATA");
+
+    #[cfg(target_os = "macos")]
+    let base = var("HOME").map(|home| home.join("Library").join("Application Support"));
+
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let base =
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local").join("share")));
+
+    base.map(|base| base.join("age").join("plugins"))
+}
+
+/// Returns the directories that are searched for plugins after `$PATH`.
+fn plugin_dirs() -> Vec<PathBuf> {
+    // Applications launched from the macOS GUI don't inherit the `$PATH` of the user's
+    // shell, so also check where package managers install binaries.
+    #[cfg(target_os = "macos")]
+    let system_dirs = ["/opt/homebrew/bin", "/usr/local/bin"];
+    #[cfg(not(target_os = "macos"))]
+    let system_dirs: [&str; 0] = [];
+
+    user_plugin_dir()
+        .into_iter()
+        .chain(system_dirs.iter().map(PathBuf::from))
+        .collect()
+}
+
+struct SlowPluginGuard {
+    _cancel: mpsc::Sender<()>,
+    timed_out: Arc<AtomicBool>,
+}
 
 impl SlowPluginGuard {
     /// Starts a thread to print out a progress message after 10 seconds if the plugin
-    /// hasn't finished.
+    /// hasn't finished, and to terminate the plugin if it hasn't finished within
+    /// `timeout`.
     ///
     /// Returns a guard that can be dropped once the plugin finishes to cancel the timer.
-    fn new<C: Callbacks>(callbacks: C, plugin_binary_name: String) -> Self {
+    fn new<C: Callbacks>(
+        callbacks: C,
+        plugin_binary_name: String,
+        timeout: Option<(Duration, Terminator)>,
+    ) -> Self {
         // We use a channel to detect when the guard is dropped.
         let (send, recv) = mpsc::channel::<()>();
+        let timed_out = Arc::new(AtomicBool::new(false));
 
+        let timed_out_inner = timed_out.clone();
         thread::spawn(move || {
             let start = SystemTime::now();
+            let mut notified = false;
             loop {
                 // If the send side of the channel has been dropped, we've been cancelled.
                 if matches!(recv.try_recv(), Err(mpsc::TryRecvError::Disconnected)) {
                     break;
                 }
 
-                // If we've waited long enough, emit the progress message and exit.
-                match SystemTime::now().duration_since(start) {
-                    Ok(end) if end >= TEN_SECONDS => {
-                        callbacks.display_message(&fl!(
+                let elapsed = SystemTime::now().duration_since(start).unwrap_or_default();
+
+                // If we've waited long enough, emit the progress message.
+                if !notified && elapsed >= TEN_SECONDS {
+                    callbacks.display_message(&match &timeout {
+                        Some((timeout, _)) => fl!(
+                            crate::i18n::LANGUAGE_LOADER,
+                            "plugin-waiting-on-binary-timeout",
+                            binary_name = plugin_binary_name.as_str(),
+                            seconds = timeout.saturating_sub(elapsed).as_secs(),
+                        ),
+                        None => fl!(
                             crate::i18n::LANGUAGE_LOADER,
                             "plugin-waiting-on-binary",
-                            binary_name = plugin_binary_name,
-                        ));
+                            binary_name = plugin_binary_name.as_str(),
+                        ),
+                    });
+                    notified = true;
+                }
+
+                match &timeout {
+                    // If we've waited too long, give up on the plugin and exit.
+                    Some((timeout, terminator)) if elapsed >= *timeout => {
+                        timed_out_inner.store(true, Ordering::SeqCst);
+                        // The plugin may have exited on its own in the meantime.
+                        let _ = terminator.terminate();
                         break;
                     }
+                    None if notified => break,
                     _ => thread::sleep(ONE_HUNDRED_MS),
                 }
             }
         });
 
-        SlowPluginGuard(send)
+        SlowPluginGuard {
+            _cancel: send,
+            timed_out,
+        }
+    }
+
+    /// Returns `true` if the plugin was terminated because it didn't finish in time.
+    fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
     }
 }
 
@@ -119,6 +204,9 @@ impl fmt::Display for Recipient {
     }
 }
 
+#[cfg(feature = "serde")]
+crate::util::serde_via_str!(Recipient);
+
 impl Recipient {
     /// Returns the plugin name for this recipient.
     pub fn plugin(&self) -> &str {
@@ -187,6 +275,62 @@ impl Identity {
     }
 }
 
+/// A set of plugins that may be run without asking the user.
+///
+/// Plugin recipients and identities name the plugin that handles them, so a recipient
+/// such as `age1evil1...` from an untrusted source would otherwise cause the binary
+/// `age-plugin-evil` to be run. The `new` and `with_allowlist` constructors of
+/// [`RecipientPluginV1`] and [`IdentityPluginV1`] only run a plugin if it is in the
+/// allowlist (which for `new` is empty), or if the user approves it via
+/// [`Callbacks::confirm`].
+#[derive(Clone, Debug, Default)]
+pub struct Allowlist {
+    plugins: HashSet<String>,
+}
+
+impl Allowlist {
+    /// Creates an empty allowlist, with which every plugin requires confirmation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the plugin with the given name (e.g. `yubikey` for `age-plugin-yubikey`).
+    pub fn allow(&mut self, plugin_name: &str) {
+        self.plugins.insert(plugin_name.to_owned());
+    }
+
+    /// Returns `true` if the plugin with the given name is in this allowlist.
+    pub fn contains(&self, plugin_name: &str) -> bool {
+        self.plugins.contains(plugin_name)
+    }
+
+    /// Returns `true` if `plugin` may be run, asking the user if it is not in this
+    /// allowlist.
+    fn permits<C: Callbacks>(&self, plugin_name: &str, plugin: &Plugin, callbacks: &C) -> bool {
+        self.contains(plugin_name)
+            || callbacks.confirm(
+                &fl!(
+                    crate::i18n::LANGUAGE_LOADER,
+                    "plugin-confirm-run",
+                    binary_name = plugin.binary_name.as_str(),
+                    path = plugin.path.display().to_string(),
+                ),
+                &fl!(crate::i18n::LANGUAGE_LOADER, "plugin-confirm-run-yes"),
+                Some(&fl!(crate::i18n::LANGUAGE_LOADER, "plugin-confirm-run-no")),
+            ) == Some(true)
+    }
+}
+
+impl<S: AsRef<str>> iter::FromIterator<S> for Allowlist {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut allowlist = Allowlist::new();
+        for plugin_name in iter {
+            allowlist.allow(plugin_name.as_ref());
+        }
+        allowlist
+    }
+}
+
 /// An age plugin.
 struct Plugin {
     binary_name: String,
@@ -194,21 +338,29 @@ struct Plugin {
 }
 
 impl Plugin {
-    /// Finds the age plugin with the given name in `$PATH`.
+    /// Finds the age plugin with the given name in `$PATH`, or in one of the
+    /// platform-specific plugin directories (see [`user_plugin_dir`]).
     ///
     /// On error, returns the binary name that could not be located.
     fn new(name: &str) -> Result<Self, String> {
         let binary_name = binary_name(name);
-        match which::which(&binary_name).or_else(|e| {
-            // If we are running in WSL, try appending `.exe`; plugins installed in
-            // the Windows host are available to us, but `which` only trials PATHEXT
-            // extensions automatically when compiled for Windows.
-            if wsl::is_wsl() {
-                which::which(format!("{}.exe", binary_name)).map_err(|_| e)
-            } else {
-                Err(e)
-            }
-        }) {
+        match which::which(&binary_name)
+            .or_else(|e| {
+                // If we are running in WSL, try appending `.exe`; plugins installed in
+                // the Windows host are available to us, but `which` only trials PATHEXT
+                // extensions automatically when compiled for Windows.
+                if wsl::is_wsl() {
+                    which::which(format!("{}.exe", binary_name)).map_err(|_| e)
+                } else {
+                    Err(e)
+                }
+            })
+            .or_else(|e| {
+                plugin_dirs()
+                    .into_iter()
+                    .find_map(|dir| which::which_in(&binary_name, Some(&dir), &dir).ok())
+                    .ok_or(e)
+            }) {
             Ok(path) => Ok(Plugin { binary_name, path }),
             Err(_) => Err(binary_name),
         }
@@ -220,6 +372,87 @@ impl Plugin {
     ) -> io::Result<Connection<DebugReader<ChildStdout>, DebugWriter<ChildStdin>>> {
         Connection::open(&self.path, state_machine)
     }
+
+    /// Classifies an I/O error that occurred while running `state_machine` with this
+    /// plugin.
+    ///
+    /// The plugin protocol is versioned by its state machines. A plugin that does not
+    /// implement `state_machine` exits without responding, so if the plugin hung up
+    /// before sending us any command, it is most likely too old. Any other failure is a
+    /// violation of the protocol.
+    fn protocol_failure(
+        &self,
+        state_machine: &str,
+        responded: bool,
+        e: io::Error,
+    ) -> ProtocolFailure {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe if !responded => {
+                ProtocolFailure::TooOld {
+                    binary_name: self.binary_name.clone(),
+                    state_machine: state_machine.to_owned(),
+                }
+            }
+            _ => ProtocolFailure::Protocol {
+                binary_name: self.binary_name.clone(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+/// Ways in which a conversation with a plugin can fail.
+enum ProtocolFailure {
+    TooOld {
+        binary_name: String,
+        state_machine: String,
+    },
+    Protocol {
+        binary_name: String,
+        message: String,
+    },
+}
+
+impl From<ProtocolFailure> for EncryptError {
+    fn from(failure: ProtocolFailure) -> Self {
+        match failure {
+            ProtocolFailure::TooOld {
+                binary_name,
+                state_machine,
+            } => EncryptError::PluginTooOld {
+                binary_name,
+                state_machine,
+            },
+            ProtocolFailure::Protocol {
+                binary_name,
+                message,
+            } => EncryptError::PluginProtocol {
+                binary_name,
+                message,
+            },
+        }
+    }
+}
+
+impl From<ProtocolFailure> for DecryptError {
+    fn from(failure: ProtocolFailure) -> Self {
+        match failure {
+            ProtocolFailure::TooOld {
+                binary_name,
+                state_machine,
+            } => DecryptError::PluginTooOld {
+                binary_name,
+                state_machine,
+            },
+            ProtocolFailure::Protocol {
+                binary_name,
+                message,
+            } => DecryptError::PluginProtocol {
+                binary_name,
+                message,
+            },
+        }
+    }
 }
 
 fn handle_confirm<R: io::Read, W: io::Write, C: Callbacks>(
@@ -228,7 +461,7 @@ fn handle_confirm<R: io::Read, W: io::Write, C: Callbacks>(
     errors: &mut Vec<PluginError>,
     callbacks: &C,
 ) -> Response {
-    let message = String::from_utf8_lossy(&command.body);
+    let message = String::from_utf8_lossy(command.body());
     let mut strings = command
         .args
         .iter()
@@ -293,28 +526,97 @@ impl<C: Callbacks> RecipientPluginV1<C> {
     /// recipients that don't match will be ignored.
     ///
     /// Returns an error if the plugin's binary cannot be found in `$PATH`.
+    ///
+    /// The user is asked whether to run the plugin via [`Callbacks::confirm`], as with
+    /// an empty [`RecipientPluginV1::with_allowlist`]. Returns
+    /// [`EncryptError::PluginNotAllowed`] if the user declines, or cannot be asked.
     pub fn new(
         plugin_name: &str,
         recipients: &[Recipient],
         identities: &[Identity],
         callbacks: C,
+    ) -> Result<Self, EncryptError> {
+        Self::with_allowlist(
+            plugin_name,
+            recipients,
+            identities,
+            callbacks,
+            &Allowlist::new(),
+        )
+    }
+
+    /// Creates an age plugin from a plugin name and lists of recipients and identities,
+    /// without asking the user whether to run it.
+    ///
+    /// Only use this when the user chose the plugin directly (for example, by name on
+    /// the command line). If the recipients or identities might come from an untrusted
+    /// source, use [`RecipientPluginV1::with_allowlist`].
+    ///
+    /// See [`RecipientPluginV1::new`] for details of the other arguments.
+    pub fn new_unchecked(
+        plugin_name: &str,
+        recipients: &[Recipient],
+        identities: &[Identity],
+        callbacks: C,
     ) -> Result<Self, EncryptError> {
         Plugin::new(plugin_name)
             .map_err(|binary_name| EncryptError::MissingPlugin { binary_name })
-            .map(|plugin| RecipientPluginV1 {
+            .map(|plugin| Self::from_plugin(plugin, plugin_name, recipients, identities, callbacks))
+    }
+
+    /// Creates an age plugin from a plugin name and lists of recipients and identities,
+    /// if the plugin is permitted to run.
+    ///
+    /// If the plugin is not in `allowlist`, the user is asked whether to run it via
+    /// [`Callbacks::confirm`]. Returns [`EncryptError::PluginNotAllowed`] if the user
+    /// declines, or cannot be asked.
+    ///
+    /// See [`RecipientPluginV1::new`] for details of the other arguments.
+    pub fn with_allowlist(
+        plugin_name: &str,
+        recipients: &[Recipient],
+        identities: &[Identity],
+        callbacks: C,
+        allowlist: &Allowlist,
+    ) -> Result<Self, EncryptError> {
+        let plugin = Plugin::new(plugin_name)
+            .map_err(|binary_name| EncryptError::MissingPlugin { binary_name })?;
+        if allowlist.permits(plugin_name, &plugin, &callbacks) {
+            Ok(Self::from_plugin(
                 plugin,
-                recipients: recipients
-                    .iter()
-                    .filter(|r| r.name == plugin_name)
-                    .cloned()
-                    .collect(),
-                identities: identities
-                    .iter()
-                    .filter(|r| r.name == plugin_name)
-                    .cloned()
-                    .collect(),
+                plugin_name,
+                recipients,
+                identities,
                 callbacks,
+            ))
+        } else {
+            Err(EncryptError::PluginNotAllowed {
+                binary_name: plugin.binary_name,
             })
+        }
+    }
+
+    fn from_plugin(
+        plugin: Plugin,
+        plugin_name: &str,
+        recipients: &[Recipient],
+        identities: &[Identity],
+        callbacks: C,
+    ) -> Self {
+        RecipientPluginV1 {
+            plugin,
+            recipients: recipients
+                .iter()
+                .filter(|r| r.name == plugin_name)
+                .cloned()
+                .collect(),
+            identities: identities
+                .iter()
+                .filter(|r| r.name == plugin_name)
+                .cloned()
+                .collect(),
+            callbacks,
+        }
     }
 }
 
@@ -323,7 +625,11 @@ impl<C: Callbacks> crate::Recipient for RecipientPluginV1<C> {
         // Open connection
         let mut conn = self.plugin.connect(RECIPIENT_V1)?;
 
-        let _guard = SlowPluginGuard::new(self.callbacks.clone(), self.plugin.binary_name.clone());
+        let _guard = SlowPluginGuard::new(
+            self.callbacks.clone(),
+            self.plugin.binary_name.clone(),
+            None,
+        );
 
         // Phase 1: add recipients, identities, and file key to wrap
         conn.unidir_send(|mut phase| {
@@ -334,11 +640,13 @@ impl<C: Callbacks> crate::Recipient for RecipientPluginV1<C> {
                 phase.send("add-identity", &[&identity.identity], &[])?;
             }
             phase.send("wrap-file-key", &[], file_key.expose_secret())
-        })?;
+        })
+        .map_err(|e| self.plugin.protocol_failure(RECIPIENT_V1, false, e))?;
 
         // Phase 2: collect either stanzas or errors
         let mut stanzas = vec![];
         let mut errors = vec![];
+        let mut responded = false;
         if let Err(e) = conn.bidir_receive(
             &[
                 CMD_MSG,
@@ -348,82 +656,88 @@ impl<C: Callbacks> crate::Recipient for RecipientPluginV1<C> {
                 CMD_RECIPIENT_STANZA,
                 CMD_ERROR,
             ],
-            |mut command, reply| match command.tag.as_str() {
-                CMD_MSG => {
-                    self.callbacks
-                        .display_message(&String::from_utf8_lossy(&command.body));
-                    reply.ok(None)
-                }
-                CMD_CONFIRM => handle_confirm(command, reply, &mut errors, &self.callbacks),
-                CMD_REQUEST_PUBLIC => {
-                    if let Some(value) = self
-                        .callbacks
-                        .request_public_string(&String::from_utf8_lossy(&command.body))
-                    {
-                        reply.ok(Some(value.as_bytes()))
-                    } else {
-                        reply.fail()
+            |mut command, reply| {
+                responded = true;
+                match command.tag.as_str() {
+                    CMD_MSG => {
+                        self.callbacks
+                            .display_message(&String::from_utf8_lossy(command.body()));
+                        reply.ok(None)
                     }
-                }
-                CMD_REQUEST_SECRET => {
-                    if let Some(secret) = self
-                        .callbacks
-                        .request_passphrase(&String::from_utf8_lossy(&command.body))
-                    {
-                        reply.ok(Some(secret.expose_secret().as_bytes()))
-                    } else {
-                        reply.fail()
+                    CMD_CONFIRM => handle_confirm(command, reply, &mut errors, &self.callbacks),
+                    CMD_REQUEST_PUBLIC => {
+                        if let Some(value) = self
+                            .callbacks
+                            .request_public_string(&String::from_utf8_lossy(command.body()))
+                        {
+                            reply.ok(Some(value.as_bytes()))
+                        } else {
+                            reply.fail()
+                        }
                     }
-                }
-                CMD_RECIPIENT_STANZA => {
-                    if command.args.len() >= 2 {
-                        // We only requested one file key be wrapped.
-                        if command.args.remove(0) == "0" {
-                            command.tag = command.args.remove(0);
-                            stanzas.push(command);
+                    CMD_REQUEST_SECRET => {
+                        if let Some(secret) = self
+                            .callbacks
+                            .request_passphrase(&String::from_utf8_lossy(command.body()))
+                        {
+                            reply.ok(Some(secret.expose_secret().as_bytes()))
+                        } else {
+                            reply.fail()
+                        }
+                    }
+                    CMD_RECIPIENT_STANZA => {
+                        if command.args.len() >= 2 {
+                            // We only requested one file key be wrapped.
+                            if command.args.remove(0) == "0" {
+                                command.tag = command.args.remove(0);
+                                stanzas.push(command);
+                            } else {
+                                errors.push(PluginError::Other {
+                                    kind: "internal".to_owned(),
+                                    metadata: vec![],
+                                    message: "plugin wrapped file key to a file we didn't provide"
+                                        .to_owned(),
+                                });
+                            }
                         } else {
                             errors.push(PluginError::Other {
                                 kind: "internal".to_owned(),
                                 metadata: vec![],
-                                message: "plugin wrapped file key to a file we didn't provide"
-                                    .to_owned(),
+                                message: format!(
+                                    "{} command must have at least two metadata arguments",
+                                    CMD_RECIPIENT_STANZA
+                                ),
                             });
                         }
-                    } else {
-                        errors.push(PluginError::Other {
-                            kind: "internal".to_owned(),
-                            metadata: vec![],
-                            message: format!(
-                                "{} command must have at least two metadata arguments",
-                                CMD_RECIPIENT_STANZA
-                            ),
-                        });
+                        reply.ok(None)
                     }
-                    reply.ok(None)
-                }
-                CMD_ERROR => {
-                    if command.args.len() == 2 && command.args[0] == "recipient" {
-                        let index: usize = command.args[1].parse().unwrap();
-                        errors.push(PluginError::Recipient {
-                            binary_name: binary_name(&self.recipients[index].name),
-                            recipient: self.recipients[index].recipient.clone(),
-                            message: String::from_utf8_lossy(&command.body).to_string(),
-                        });
-                    } else if command.args.len() == 2 && command.args[0] == "identity" {
-                        let index: usize = command.args[1].parse().unwrap();
-                        errors.push(PluginError::Identity {
-                            binary_name: binary_name(&self.identities[index].name),
-                            message: String::from_utf8_lossy(&command.body).to_string(),
-                        });
-                    } else {
-                        errors.push(PluginError::from(command));
+                    CMD_ERROR => {
+                        if command.args.len() == 2 && command.args[0] == "recipient" {
+                            let index: usize = command.args[1].parse().unwrap();
+                            errors.push(PluginError::Recipient {
+                                binary_name: binary_name(&self.recipients[index].name),
+                                recipient: self.recipients[index].recipient.clone(),
+                                message: String::from_utf8_lossy(command.body()).to_string(),
+                            });
+                        } else if command.args.len() == 2 && command.args[0] == "identity" {
+                            let index: usize = command.args[1].parse().unwrap();
+                            errors.push(PluginError::Identity {
+                                binary_name: binary_name(&self.identities[index].name),
+                                message: String::from_utf8_lossy(command.body()).to_string(),
+                            });
+                        } else {
+                            errors.push(PluginError::from(command));
+                        }
+                        reply.ok(None)
                     }
-                    reply.ok(None)
+                    _ => unreachable!(),
                 }
-                _ => unreachable!(),
             },
         ) {
-            return Err(e.into());
+            return Err(self
+                .plugin
+                .protocol_failure(RECIPIENT_V1, responded, e)
+                .into());
         };
         match (stanzas.is_empty(), errors.is_empty()) {
             (false, true) => Ok(stanzas),
@@ -455,6 +769,7 @@ pub struct IdentityPluginV1<C: Callbacks> {
     plugin: Plugin,
     identities: Vec<Identity>,
     callbacks: C,
+    timeout: Option<Duration>,
 }
 
 impl<C: Callbacks> IdentityPluginV1<C> {
@@ -464,22 +779,96 @@ impl<C: Callbacks> IdentityPluginV1<C> {
     /// match will be ignored.
     ///
     /// Returns an error if the plugin's binary cannot be found in `$PATH`.
+    ///
+    /// The user is asked whether to run the plugin via [`Callbacks::confirm`], as with
+    /// an empty [`IdentityPluginV1::with_allowlist`]. Returns
+    /// [`DecryptError::PluginNotAllowed`] if the user declines, or cannot be asked.
     pub fn new(
         plugin_name: &str,
         identities: &[Identity],
         callbacks: C,
+    ) -> Result<Self, DecryptError> {
+        Self::with_allowlist(plugin_name, identities, callbacks, &Allowlist::new())
+    }
+
+    /// Creates an age plugin from a plugin name and a list of identities, without asking
+    /// the user whether to run it.
+    ///
+    /// Only use this when the user chose the plugin directly (for example, by name on
+    /// the command line). If the identities might come from an untrusted source, use
+    /// [`IdentityPluginV1::with_allowlist`].
+    ///
+    /// See [`IdentityPluginV1::new`] for details of the other arguments.
+    pub fn new_unchecked(
+        plugin_name: &str,
+        identities: &[Identity],
+        callbacks: C,
     ) -> Result<Self, DecryptError> {
         Plugin::new(plugin_name)
             .map_err(|binary_name| DecryptError::MissingPlugin { binary_name })
-            .map(|plugin| IdentityPluginV1 {
+            .map(|plugin| Self::from_plugin(plugin, plugin_name, identities, callbacks))
+    }
+
+    /// Creates an age plugin from a plugin name and a list of identities, if the plugin
+    /// is permitted to run.
+    ///
+    /// If the plugin is not in `allowlist`, the user is asked whether to run it via
+    /// [`Callbacks::confirm`]. Returns [`DecryptError::PluginNotAllowed`] if the user
+    /// declines, or cannot be asked.
+    ///
+    /// See [`IdentityPluginV1::new`] for details of the other arguments.
+    pub fn with_allowlist(
+        plugin_name: &str,
+        identities: &[Identity],
+        callbacks: C,
+        allowlist: &Allowlist,
+    ) -> Result<Self, DecryptError> {
+        let plugin = Plugin::new(plugin_name)
+            .map_err(|binary_name| DecryptError::MissingPlugin { binary_name })?;
+        if allowlist.permits(plugin_name, &plugin, &callbacks) {
+            Ok(Self::from_plugin(
                 plugin,
-                identities: identities
-                    .iter()
-                    .filter(|r| r.name == plugin_name)
-                    .cloned()
-                    .collect(),
+                plugin_name,
+                identities,
                 callbacks,
+            ))
+        } else {
+            Err(DecryptError::PluginNotAllowed {
+                binary_name: plugin.binary_name,
             })
+        }
+    }
+
+    fn from_plugin(
+        plugin: Plugin,
+        plugin_name: &str,
+        identities: &[Identity],
+        callbacks: C,
+    ) -> Self {
+        IdentityPluginV1 {
+            plugin,
+            identities: identities
+                .iter()
+                .filter(|r| r.name == plugin_name)
+                .cloned()
+                .collect(),
+            callbacks,
+            timeout: None,
+        }
+    }
+
+    /// Sets the maximum time that the plugin may take to unwrap a file key.
+    ///
+    /// A plugin may wait indefinitely for a hardware token to be touched or inserted.
+    /// With a timeout, the plugin is terminated if it hasn't finished in time, and
+    /// decryption fails with [`DecryptError::Timeout`] instead of waiting forever. The
+    /// timeout includes any time spent waiting for the user to respond to [`Callbacks`]
+    /// requests from the plugin.
+    ///
+    /// By default, there is no timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
     fn unwrap_stanzas<'a>(
@@ -490,7 +879,23 @@ impl<C: Callbacks> IdentityPluginV1<C> {
         // by returning `None`.
         let mut conn = self.plugin.connect(IDENTITY_V1).ok()?;
 
-        let _guard = SlowPluginGuard::new(self.callbacks.clone(), self.plugin.binary_name.clone());
+        let guard = SlowPluginGuard::new(
+            self.callbacks.clone(),
+            self.plugin.binary_name.clone(),
+            self.timeout.map(|timeout| (timeout, conn.terminator())),
+        );
+        let failure = |responded, e| {
+            if guard.timed_out() {
+                DecryptError::Timeout {
+                    binary_name: self.plugin.binary_name.clone(),
+                    timeout: self.timeout.expect("only times out with a timeout"),
+                }
+            } else {
+                self.plugin
+                    .protocol_failure(IDENTITY_V1, responded, e)
+                    .into()
+            }
+        };
 
         // Phase 1: add identities and stanzas
         if let Err(e) = conn.unidir_send(|mut phase| {
@@ -502,12 +907,13 @@ impl<C: Callbacks> IdentityPluginV1<C> {
             }
             Ok(())
         }) {
-            return Some(Err(e.into()));
+            return Some(Err(failure(false, e)));
         };
 
         // Phase 2: interactively unwrap
         let mut file_key = None;
         let mut errors = vec![];
+        let mut responded = false;
         if let Err(e) = conn.bidir_receive(
             &[
                 CMD_MSG,
@@ -517,60 +923,63 @@ impl<C: Callbacks> IdentityPluginV1<C> {
                 CMD_FILE_KEY,
                 CMD_ERROR,
             ],
-            |command, reply| match command.tag.as_str() {
-                CMD_MSG => {
-                    self.callbacks
-                        .display_message(&String::from_utf8_lossy(&command.body));
-                    reply.ok(None)
-                }
-                CMD_CONFIRM => handle_confirm(command, reply, &mut errors, &self.callbacks),
-                CMD_REQUEST_PUBLIC => {
-                    if let Some(value) = self
-                        .callbacks
-                        .request_public_string(&String::from_utf8_lossy(&command.body))
-                    {
-                        reply.ok(Some(value.as_bytes()))
-                    } else {
-                        reply.fail()
+            |command, reply| {
+                responded = true;
+                match command.tag.as_str() {
+                    CMD_MSG => {
+                        self.callbacks
+                            .display_message(&String::from_utf8_lossy(command.body()));
+                        reply.ok(None)
                     }
-                }
-                CMD_REQUEST_SECRET => {
-                    if let Some(secret) = self
-                        .callbacks
-                        .request_passphrase(&String::from_utf8_lossy(&command.body))
-                    {
-                        reply.ok(Some(secret.expose_secret().as_bytes()))
-                    } else {
-                        reply.fail()
+                    CMD_CONFIRM => handle_confirm(command, reply, &mut errors, &self.callbacks),
+                    CMD_REQUEST_PUBLIC => {
+                        if let Some(value) = self
+                            .callbacks
+                            .request_public_string(&String::from_utf8_lossy(command.body()))
+                        {
+                            reply.ok(Some(value.as_bytes()))
+                        } else {
+                            reply.fail()
+                        }
                     }
-                }
-                CMD_FILE_KEY => {
-                    // We only support a single file.
-                    assert!(command.args[0] == "0");
-                    assert!(file_key.is_none());
-                    file_key = Some(
-                        TryInto::<[u8; 16]>::try_into(&command.body[..])
-                            .map_err(|_| DecryptError::DecryptionFailed)
-                            .map(FileKey::from),
-                    );
-                    reply.ok(None)
-                }
-                CMD_ERROR => {
-                    if command.args.len() == 2 && command.args[0] == "identity" {
-                        let index: usize = command.args[1].parse().unwrap();
-                        errors.push(PluginError::Identity {
-                            binary_name: binary_name(&self.identities[index].name),
-                            message: String::from_utf8_lossy(&command.body).to_string(),
-                        });
-                    } else {
-                        errors.push(PluginError::from(command));
+                    CMD_REQUEST_SECRET => {
+                        if let Some(secret) = self
+                            .callbacks
+                            .request_passphrase(&String::from_utf8_lossy(command.body()))
+                        {
+                            reply.ok(Some(secret.expose_secret().as_bytes()))
+                        } else {
+                            reply.fail()
+                        }
+                    }
+                    CMD_FILE_KEY => {
+                        // We only support a single file.
+                        assert!(command.args[0] == "0");
+                        assert!(file_key.is_none());
+                        file_key = Some(
+                            TryInto::<[u8; 16]>::try_into(command.body())
+                                .map_err(|_| DecryptError::DecryptionFailed)
+                                .map(FileKey::from),
+                        );
+                        reply.ok(None)
                     }
-                    reply.ok(None)
+                    CMD_ERROR => {
+                        if command.args.len() == 2 && command.args[0] == "identity" {
+                            let index: usize = command.args[1].parse().unwrap();
+                            errors.push(PluginError::Identity {
+                                binary_name: binary_name(&self.identities[index].name),
+                                message: String::from_utf8_lossy(command.body()).to_string(),
+                            });
+                        } else {
+                            errors.push(PluginError::from(command));
+                        }
+                        reply.ok(None)
+                    }
+                    _ => unreachable!(),
                 }
-                _ => unreachable!(),
             },
         ) {
-            return Some(Err(e.into()));
+            return Some(Err(failure(responded, e)));
         };
 
         if file_key.is_none() && !errors.is_empty() {
@@ -593,7 +1002,10 @@ impl<C: Callbacks> crate::Identity for IdentityPluginV1<C> {
 
 #[cfg(test)]
 mod tests {
-    use super::Identity;
+    use std::io;
+    use std::path::PathBuf;
+
+    use super::{Allowlist, Identity, Plugin, ProtocolFailure, RECIPIENT_V1};
 
     #[test]
     fn default_for_plugin() {
@@ -602,4 +1014,91 @@ mod tests {
             "AGE-PLUGIN-FOOBAR-1QVHULF",
         );
     }
+
+    #[test]
+    fn allowlist() {
+        let allowlist: Allowlist = ["yubikey", "tpm"].iter().collect();
+        assert!(allowlist.contains("yubikey"));
+        assert!(allowlist.contains("tpm"));
+        assert!(!allowlist.contains("evil"));
+        assert!(!allowlist.contains("age-plugin-yubikey"));
+        assert!(!Allowlist::new().contains("yubikey"));
+    }
+
+    #[test]
+    fn protocol_failure() {
+        let plugin = Plugin {
+            binary_name: "age-plugin-foobar".to_owned(),
+            path: PathBuf::from("/usr/bin/age-plugin-foobar"),
+        };
+        let eof = || io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete response");
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid response");
+
+        assert!(matches!(
+            plugin.protocol_failure(RECIPIENT_V1, false, eof()),
+            ProtocolFailure::TooOld { state_machine, .. } if state_machine == RECIPIENT_V1,
+        ));
+        assert!(matches!(
+            plugin.protocol_failure(RECIPIENT_V1, false, io::ErrorKind::BrokenPipe.into()),
+            ProtocolFailure::TooOld { .. },
+        ));
+        assert!(matches!(
+            plugin.protocol_failure(RECIPIENT_V1, true, eof()),
+            ProtocolFailure::Protocol { .. },
+        ));
+        assert!(matches!(
+            plugin.protocol_failure(RECIPIENT_V1, false, invalid()),
+            ProtocolFailure::Protocol { .. },
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unwrap_timeout() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, Instant};
+
+        use age_core::{format::Stanza, secrecy::SecretString};
+
+        use super::IdentityPluginV1;
+        use crate::{Callbacks, DecryptError};
+
+        #[derive(Clone)]
+        struct NoCallbacks;
+
+        impl Callbacks for NoCallbacks {
+            fn request_passphrase(&self, _: &str) -> Option<SecretString> {
+                None
+            }
+        }
+
+        // A plugin that never responds, like one waiting for a hardware token.
+        let path = std::env::temp_dir().join(format!("age-plugin-hang-{}", std::process::id()));
+        fs::write(&path, "#!/bin/sh\nexec sleep 60\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let plugin = Plugin {
+            binary_name: "age-plugin-hang".to_owned(),
+            path: path.clone(),
+        };
+        let identity = IdentityPluginV1::from_plugin(
+            plugin,
+            "hang",
+            &[Identity::default_for_plugin("hang")],
+            NoCallbacks,
+        )
+        .with_timeout(Duration::from_millis(200));
+
+        let stanza = Stanza::new("hang".to_owned(), vec![], vec![]);
+        let start = Instant::now();
+        let res = crate::Identity::unwrap_stanza(&identity, &stanza);
+        let _ = fs::remove_file(&path);
+
+        assert!(matches!(
+            res,
+            Some(Err(DecryptError::Timeout { binary_name, .. })) if binary_name == "age-plugin-hang"
+        ));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
 }
@@ -0,0 +1,388 @@
+//! An experimental post-quantum hybrid recipient type, combining X25519 with ML-KEM-768.
+//!
+//! **This recipient type is not part of the age specification**, and files encrypted to
+//! it can only be decrypted by this library with the `pq` feature flag enabled. Its
+//! encodings (`age1pqexp1...` recipients, `AGE-SECRET-KEY-PQEXP-1...` identities, and
+//! `mlkem768x25519-experimental` stanzas) are deliberately distinct from any that a
+//! future standard post-quantum recipient type might use, and may change in a future
+//! release without a migration path.
+//!
+//! The file key is wrapped with a key derived from both an X25519 shared secret and an
+//! ML-KEM-768 ([FIPS 203]) shared secret, so files remain confidential as long as either
+//! is secure. This protects files that need to stay confidential for a long time against
+//! an adversary who records them now, and later gains access to a quantum computer.
+//!
+//! [FIPS 203]: https://doi.org/10.6028/NIST.FIPS.203
+
+use age_core::{
+    format::{FileKey, Stanza, FILE_KEY_BYTES},
+    primitives::{aead_decrypt, aead_encrypt, hkdf},
+    secrecy::{ExposeSecret, SecretString},
+};
+use bech32::{ToBase32, Variant};
+use rand_7::rngs::OsRng;
+use sha3::{
+    digest::{ExtendableOutput, Update, XofReader},
+    Shake256,
+};
+use std::fmt;
+use subtle::ConstantTimeEq;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+use crate::{
+    error::{DecryptError, EncryptError, RngError},
+    rng::random_bytes,
+    stanza::{Arg, Body, Rule},
+    util::{parse_bech32, read::base64_arg},
+};
+
+mod ml_kem;
+
+use ml_kem::{DecapsulationKey, EncapsulationKey, CIPHERTEXT_BYTES, ENCAPSULATION_KEY_BYTES};
+
+// Use lower-case HRP to avoid https://github.com/rust-bitcoin/rust-bech32/issues/40
+const SECRET_KEY_PREFIX: &str = "age-secret-key-pqexp-";
+const PUBLIC_KEY_PREFIX: &str = "age1pqexp";
+
+const STANZA_TAG: &str = "mlkem768x25519-experimental";
+const STANZA_KEY_LABEL: &[u8] = b"age-encryption.org/v1/mlkem768x25519-experimental";
+
+const SEED_BYTES: usize = 32;
+const EPK_BYTES: usize = 32;
+const ENCRYPTED_FILE_KEY_BYTES: usize = FILE_KEY_BYTES + 16;
+
+/// The format of an mlkem768x25519-experimental recipient stanza.
+///
+/// The argument is the ephemeral X25519 share, and the body is the ML-KEM-768 ciphertext
+/// followed by the wrapped file key.
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: STANZA_TAG,
+    args: &[Arg::Base64(EPK_BYTES)],
+    body: Body::Exact(CIPHERTEXT_BYTES + ENCRYPTED_FILE_KEY_BYTES),
+};
+
+fn is_all_zero(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0, |acc, b| acc | b).ct_eq(&0).into()
+}
+
+/// Derives the key that wraps the file key from both shared secrets.
+///
+/// ML-KEM's shared secret is derived from its encapsulation key and the encapsulated
+/// message, but not from the ciphertext itself, so the ciphertext is bound to the
+/// wrapping key along with the X25519 shares.
+fn wrapping_key(
+    ml_kem_ciphertext: &[u8],
+    epk: &PublicKey,
+    pk: &PublicKey,
+    ml_kem_shared_secret: &[u8],
+    x25519_shared_secret: &[u8],
+) -> [u8; 32] {
+    let mut salt = vec![];
+    salt.extend_from_slice(ml_kem_ciphertext);
+    salt.extend_from_slice(epk.as_bytes());
+    salt.extend_from_slice(pk.as_bytes());
+
+    let mut ikm = vec![];
+    ikm.extend_from_slice(ml_kem_shared_secret);
+    ikm.extend_from_slice(x25519_shared_secret);
+
+    let key = hkdf(&salt, STANZA_KEY_LABEL, &ikm);
+    ikm.zeroize();
+    key
+}
+
+/// An identity for the experimental post-quantum hybrid recipient type, which can
+/// decrypt files encrypted to the corresponding [`Recipient`].
+///
+/// Both the ML-KEM-768 and X25519 keys are derived from a single 32-byte seed, which is
+/// all that is stored in the identity's encoding.
+#[derive(Clone)]
+pub struct Identity {
+    seed: [u8; SEED_BYTES],
+    // Boxed because the expanded key is several kilobytes.
+    ml_kem: Box<DecapsulationKey>,
+    x25519: StaticSecret,
+}
+
+impl Drop for Identity {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+impl std::str::FromStr for Identity {
+    type Err = &'static str;
+
+    /// Parses an experimental post-quantum identity from a string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, mut bytes) = parse_bech32(s).ok_or("invalid Bech32 encoding")?;
+        if hrp != SECRET_KEY_PREFIX {
+            bytes.zeroize();
+            return Err("incorrect HRP");
+        }
+        let seed = TryInto::<[u8; SEED_BYTES]>::try_into(&bytes[..])
+            .map_err(|_| "incorrect identity length");
+        bytes.zeroize();
+        seed.map(Identity::from_seed)
+    }
+}
+
+impl Identity {
+    fn from_seed(mut seed: [u8; SEED_BYTES]) -> Self {
+        let mut shake = Shake256::default();
+        shake.update(&seed);
+        let mut expanded = [0; 96];
+        shake.finalize_xof().read(&mut expanded);
+
+        let d = expanded[..32].try_into().expect("correct length");
+        let z = expanded[32..64].try_into().expect("correct length");
+        let x25519_bytes: [u8; 32] = expanded[64..].try_into().expect("correct length");
+
+        let identity = Identity {
+            seed,
+            ml_kem: Box::new(DecapsulationKey::from_seed(&d, &z)),
+            x25519: StaticSecret::from(x25519_bytes),
+        };
+
+        seed.zeroize();
+        expanded.zeroize();
+        identity
+    }
+
+    /// Generates a new secret key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the system's random number generator fails a health check. Use
+    /// [`Identity::try_generate`] to handle this as an error instead.
+    pub fn generate() -> Self {
+        Self::try_generate().expect("system RNG is healthy")
+    }
+
+    /// Generates a new secret key, or returns an error if the system's random number
+    /// generator fails a health check.
+    pub fn try_generate() -> Result<Self, RngError> {
+        random_bytes().map(Self::from_seed)
+    }
+
+    /// Serializes this secret key as a string.
+    pub fn to_string(&self) -> SecretString {
+        let mut encoded = bech32::encode(SECRET_KEY_PREFIX, self.seed.to_base32(), Variant::Bech32)
+            .expect("HRP is valid");
+        let ret = SecretString::new(encoded.to_uppercase());
+        encoded.zeroize();
+        ret
+    }
+
+    /// Returns the recipient key for this secret key.
+    pub fn to_public(&self) -> Recipient {
+        Recipient {
+            ml_kem: self.ml_kem.encapsulation_key().clone(),
+            x25519: (&self.x25519).into(),
+        }
+    }
+}
+
+impl crate::Identity for Identity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        if stanza.tag != STANZA_TAG {
+            return None;
+        }
+
+        // Enforce valid and canonical stanza format.
+        if let Err(e) = STANZA_RULE.check(stanza) {
+            return Some(Err(e.into()));
+        }
+
+        let epk: PublicKey = base64_arg(&stanza.args[0], [0; EPK_BYTES])
+            .expect("Argument should have been checked above")
+            .into();
+        let (ciphertext, encrypted_file_key) = stanza.body().split_at(CIPHERTEXT_BYTES);
+        let ciphertext = ciphertext
+            .try_into()
+            .expect("Length should have been checked above");
+
+        let x25519_shared_secret = self.x25519.diffie_hellman(&epk);
+        if is_all_zero(x25519_shared_secret.as_bytes()) {
+            return Some(Err(DecryptError::InvalidHeader));
+        }
+        let mut ml_kem_shared_secret = self.ml_kem.decapsulate(ciphertext);
+
+        let pk: PublicKey = (&self.x25519).into();
+        let enc_key = wrapping_key(
+            &ciphertext[..],
+            &epk,
+            &pk,
+            &ml_kem_shared_secret,
+            x25519_shared_secret.as_bytes(),
+        );
+        ml_kem_shared_secret.zeroize();
+
+        // A failure to decrypt is non-fatal (we try to decrypt the recipient stanza with
+        // other identities), because we cannot tell which key matches a particular
+        // stanza. ML-KEM's implicit rejection means a stanza for another identity just
+        // produces an unrelated wrapping key.
+        aead_decrypt(&enc_key, FILE_KEY_BYTES, encrypted_file_key)
+            .ok()
+            .map(|mut pt| {
+                // It's ours!
+                let file_key: [u8; FILE_KEY_BYTES] = pt[..].try_into().unwrap();
+                pt.zeroize();
+                Ok(file_key.into())
+            })
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+/// A recipient for the experimental post-quantum hybrid recipient type. Files encrypted
+/// to this recipient can be decrypted with the corresponding [`Identity`].
+///
+/// Recipients of this type are much longer than other recipients (almost 2000
+/// characters), because they contain an ML-KEM-768 encapsulation key.
+#[derive(Clone)]
+pub struct Recipient {
+    ml_kem: EncapsulationKey,
+    x25519: PublicKey,
+}
+
+impl std::str::FromStr for Recipient {
+    type Err = &'static str;
+
+    /// Parses an experimental post-quantum recipient from a string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, bytes) = parse_bech32(s).ok_or("invalid Bech32 encoding")?;
+        if hrp != PUBLIC_KEY_PREFIX {
+            return Err("incorrect HRP");
+        }
+        if bytes.len() != ENCAPSULATION_KEY_BYTES + 32 {
+            return Err("incorrect pubkey length");
+        }
+        let (ml_kem, x25519) = bytes.split_at(ENCAPSULATION_KEY_BYTES);
+        Ok(Recipient {
+            ml_kem: EncapsulationKey::from_bytes(ml_kem).ok_or("invalid ML-KEM-768 key")?,
+            x25519: TryInto::<[u8; 32]>::try_into(x25519)
+                .expect("correct length")
+                .into(),
+        })
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bytes = self.ml_kem.as_bytes().to_vec();
+        bytes.extend_from_slice(self.x25519.as_bytes());
+        write!(
+            f,
+            "{}",
+            bech32::encode(PUBLIC_KEY_PREFIX, bytes.to_base32(), Variant::Bech32)
+                .expect("HRP is valid")
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::util::serde_via_str!(Recipient);
+
+impl crate::Recipient for Recipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        let mut m: [u8; 32] = random_bytes()?;
+        let (mut ml_kem_shared_secret, ciphertext) = self.ml_kem.encapsulate(&m);
+        m.zeroize();
+
+        let esk = EphemeralSecret::new(OsRng);
+        let epk: PublicKey = (&esk).into();
+        let x25519_shared_secret = esk.diffie_hellman(&self.x25519);
+
+        // It is vanishingly unlikely that we generate the all-zero esk, so if we do then
+        // it is likely that the RNG is bad, and we should fail loudly.
+        if is_all_zero(x25519_shared_secret.as_bytes()) {
+            panic!("Generated the all-zero esk; OS RNG is likely failing!");
+        }
+
+        let enc_key = wrapping_key(
+            &ciphertext[..],
+            &epk,
+            &self.x25519,
+            &ml_kem_shared_secret,
+            x25519_shared_secret.as_bytes(),
+        );
+        ml_kem_shared_secret.zeroize();
+
+        let mut body = ciphertext.to_vec();
+        body.extend_from_slice(&aead_encrypt(&enc_key, file_key.expose_secret()));
+
+        Ok(vec![Stanza::new(
+            STANZA_TAG.to_owned(),
+            vec![base64::encode_config(
+                epk.as_bytes(),
+                base64::STANDARD_NO_PAD,
+            )],
+            body,
+        )])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::{FileKey, Stanza},
+        secrecy::ExposeSecret,
+    };
+
+    use super::{Identity, Recipient, STANZA_TAG};
+    use crate::{Identity as _, Recipient as _};
+
+    #[test]
+    fn encodings() {
+        let identity = Identity::generate();
+
+        let encoded = identity.to_string();
+        assert!(encoded
+            .expose_secret()
+            .starts_with("AGE-SECRET-KEY-PQEXP-1"));
+        let parsed: Identity = encoded.expose_secret().parse().unwrap();
+        assert_eq!(parsed.to_string().expose_secret(), encoded.expose_secret());
+
+        let recipient = identity.to_public().to_string();
+        assert!(recipient.starts_with("age1pqexp1"));
+        assert_eq!(parsed.to_public().to_string(), recipient);
+        assert_eq!(
+            recipient.parse::<Recipient>().unwrap().to_string(),
+            recipient
+        );
+
+        // Neither encoding is mistaken for a native age key.
+        assert!(encoded
+            .expose_secret()
+            .parse::<crate::x25519::Identity>()
+            .is_err());
+        assert!(recipient.parse::<crate::x25519::Recipient>().is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let identity = Identity::generate();
+        let file_key = FileKey::from([7; 16]);
+
+        let stanzas = identity.to_public().wrap_file_key(&file_key).unwrap();
+        assert_eq!(stanzas.len(), 1);
+        assert_eq!(stanzas[0].tag, STANZA_TAG);
+
+        let unwrapped = identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        // Other identities can't unwrap the stanza.
+        assert!(Identity::generate().unwrap_stanza(&stanzas[0]).is_none());
+
+        // Modifying the ML-KEM ciphertext breaks the stanza, even though the X25519
+        // shares are unchanged.
+        let mut body = stanzas[0].body().to_vec();
+        body[0] ^= 1;
+        let modified = Stanza::new(stanzas[0].tag.clone(), stanzas[0].args.clone(), body);
+        assert!(identity.unwrap_stanza(&modified).is_none());
+    }
+}
@@ -0,0 +1,615 @@
+//! ML-KEM-768, the module-lattice-based key-encapsulation mechanism from [FIPS 203].
+//!
+//! This follows the algorithms in the specification directly, without the arithmetic
+//! optimizations of production implementations. Secret values are only used in
+//! arithmetic, never in branches or table indices. Reductions modulo `q` and the
+//! divisions in `Compress_d` use Barrett reduction rather than the `/` and `%`
+//! operators, whose timing can depend on their operands (see KyberSlash).
+//!
+//! [FIPS 203]: https://doi.org/10.6028/NIST.FIPS.203
+
+use sha3::{
+    digest::{ExtendableOutput, Update, XofReader},
+    Digest, Sha3_256, Sha3_512, Shake128, Shake256,
+};
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+use zeroize::Zeroize;
+
+const N: usize = 256;
+const Q: u32 = 3329;
+const K: usize = 3;
+/// `η₁` and `η₂`, which are equal for ML-KEM-768.
+const ETA: usize = 2;
+const DU: usize = 10;
+const DV: usize = 4;
+
+const POLY_BYTES: usize = 384;
+pub(super) const ENCAPSULATION_KEY_BYTES: usize = POLY_BYTES * K + 32;
+pub(super) const CIPHERTEXT_BYTES: usize = 32 * (DU * K + DV);
+pub(super) const SHARED_SECRET_BYTES: usize = 32;
+
+/// A polynomial in `Z_q[X]/(X^256 + 1)`, or its NTT representation, with coefficients
+/// in `[0, q)`.
+type Poly = [u32; N];
+
+const fn bit_rev7(i: usize) -> usize {
+    let mut r = 0;
+    let mut j = 0;
+    while j < 7 {
+        r |= ((i >> j) & 1) << (6 - j);
+        j += 1;
+    }
+    r
+}
+
+const fn pow17(exp: usize) -> u32 {
+    let mut r = 1;
+    let mut i = 0;
+    while i < exp {
+        r = (r * 17) % Q;
+        i += 1;
+    }
+    r
+}
+
+/// `ζ^BitRev₇(i)`, where `ζ = 17`.
+const ZETAS: [u32; 128] = {
+    let mut zetas = [0; 128];
+    let mut i = 0;
+    while i < 128 {
+        zetas[i] = pow17(bit_rev7(i));
+        i += 1;
+    }
+    zetas
+};
+
+/// `ζ^(2·BitRev₇(i) + 1)`.
+const GAMMAS: [u32; 128] = {
+    let mut gammas = [0; 128];
+    let mut i = 0;
+    while i < 128 {
+        gammas[i] = pow17(2 * bit_rev7(i) + 1);
+        i += 1;
+    }
+    gammas
+};
+
+/// `⌊2³² / q⌋`
+const BARRETT_MULTIPLIER: u64 = (1 << 32) / Q as u64;
+
+/// Returns `(⌊a / q⌋, a mod q)` in constant time.
+///
+/// `a · ⌊2³² / q⌋ / 2³²` underestimates `a / q` by less than one, so the first
+/// remainder is in `[0, 2q)`, and is corrected with a masked subtraction.
+fn barrett(a: u32) -> (u32, u32) {
+    let quotient = ((a as u64 * BARRETT_MULTIPLIER) >> 32) as u32;
+    let remainder = a - quotient * Q;
+    // All ones if remainder ≥ q, in which case the subtraction does not underflow.
+    let mask = (remainder.wrapping_sub(Q) >> 31).wrapping_sub(1);
+    (quotient + (mask & 1), remainder - (mask & Q))
+}
+
+fn reduce(a: u32) -> u32 {
+    barrett(a).1
+}
+
+fn add(a: u32, b: u32) -> u32 {
+    reduce(a + b)
+}
+
+fn sub(a: u32, b: u32) -> u32 {
+    reduce(a + Q - b)
+}
+
+fn mul(a: u32, b: u32) -> u32 {
+    reduce(a * b)
+}
+
+fn poly_add(a: &Poly, b: &Poly) -> Poly {
+    let mut r = [0; N];
+    for i in 0..N {
+        r[i] = add(a[i], b[i]);
+    }
+    r
+}
+
+/// Algorithm 9: NTT.
+fn ntt(mut f: Poly) -> Poly {
+    let mut i = 1;
+    let mut len = 128;
+    while len >= 2 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = ZETAS[i];
+            i += 1;
+            for j in start..start + len {
+                let t = mul(zeta, f[j + len]);
+                f[j + len] = sub(f[j], t);
+                f[j] = add(f[j], t);
+            }
+        }
+        len /= 2;
+    }
+    f
+}
+
+/// Algorithm 10: NTT⁻¹.
+fn ntt_inverse(mut f: Poly) -> Poly {
+    let mut i = 127;
+    let mut len = 2;
+    while len <= 128 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = ZETAS[i];
+            i -= 1;
+            for j in start..start + len {
+                let t = f[j];
+                f[j] = add(t, f[j + len]);
+                f[j + len] = mul(zeta, sub(f[j + len], t));
+            }
+        }
+        len *= 2;
+    }
+    // 3303 = 128⁻¹ mod q
+    for c in f.iter_mut() {
+        *c = mul(*c, 3303);
+    }
+    f
+}
+
+/// Algorithms 11 and 12: MultiplyNTTs.
+fn multiply_ntts(f: &Poly, g: &Poly) -> Poly {
+    let mut h = [0; N];
+    for i in 0..N / 2 {
+        let (a0, a1, b0, b1) = (f[2 * i], f[2 * i + 1], g[2 * i], g[2 * i + 1]);
+        h[2 * i] = add(mul(a0, b0), mul(mul(a1, b1), GAMMAS[i]));
+        h[2 * i + 1] = add(mul(a0, b1), mul(a1, b0));
+    }
+    h
+}
+
+/// Returns `Σⱼ a[j] ∘ b[j]`.
+fn inner_product(a: &[Poly; K], b: &[Poly; K]) -> Poly {
+    let mut r = [0; N];
+    for (a, b) in a.iter().zip(b.iter()) {
+        r = poly_add(&r, &multiply_ntts(a, b));
+    }
+    r
+}
+
+/// Algorithm 5: ByteEncode_d.
+fn byte_encode(f: &Poly, d: usize, out: &mut [u8]) {
+    debug_assert_eq!(out.len(), 32 * d);
+    out.iter_mut().for_each(|b| *b = 0);
+    let mut bit = 0;
+    for &c in f.iter() {
+        for j in 0..d {
+            out[bit / 8] |= (((c >> j) & 1) as u8) << (bit % 8);
+            bit += 1;
+        }
+    }
+}
+
+/// Algorithm 6: ByteDecode_d.
+fn byte_decode(bytes: &[u8], d: usize) -> Poly {
+    debug_assert_eq!(bytes.len(), 32 * d);
+    let mut f = [0; N];
+    let mut bit = 0;
+    for c in f.iter_mut() {
+        for j in 0..d {
+            *c |= (((bytes[bit / 8] >> (bit % 8)) & 1) as u32) << j;
+            bit += 1;
+        }
+        if d == 12 {
+            *c = reduce(*c);
+        }
+    }
+    f
+}
+
+/// Compress_d, from section 4.2.1.
+///
+/// This rounds `x · 2ᵈ / q` to the nearest integer. `q` is odd, so there are no ties:
+/// the quotient is rounded up if the remainder is more than `q / 2`.
+fn compress(f: &Poly, d: usize) -> Poly {
+    let mut r = [0; N];
+    for i in 0..N {
+        let (quotient, remainder) = barrett(f[i] << d);
+        let round_up = (Q / 2).wrapping_sub(remainder) >> 31;
+        r[i] = (quotient + round_up) & ((1 << d) - 1);
+    }
+    r
+}
+
+/// Decompress_d, from section 4.2.1.
+fn decompress(f: &Poly, d: usize) -> Poly {
+    let mut r = [0; N];
+    for i in 0..N {
+        r[i] = (f[i] * Q + (1 << (d - 1))) >> d;
+    }
+    r
+}
+
+/// Algorithm 7: SampleNTT, for the matrix entry `Â[i, j]`.
+fn sample_ntt(rho: &[u8], i: usize, j: usize) -> Poly {
+    let mut xof = Shake128::default();
+    xof.update(rho);
+    xof.update(&[j as u8, i as u8]);
+    let mut reader = xof.finalize_xof();
+
+    let mut a = [0; N];
+    let mut n = 0;
+    while n < N {
+        let mut c = [0; 3];
+        reader.read(&mut c);
+        let d1 = c[0] as u32 | ((c[1] as u32 & 0x0f) << 8);
+        let d2 = (c[1] as u32 >> 4) | ((c[2] as u32) << 4);
+        if d1 < Q {
+            a[n] = d1;
+            n += 1;
+        }
+        if d2 < Q && n < N {
+            a[n] = d2;
+            n += 1;
+        }
+    }
+    a
+}
+
+/// Algorithm 8: SamplePolyCBD_η, applied to `PRF_η(s, b)`.
+fn sample_cbd(s: &[u8; 32], b: u8) -> Poly {
+    let mut prf = Shake256::default();
+    prf.update(s);
+    prf.update(&[b]);
+    let mut bytes = [0; 64 * ETA];
+    prf.finalize_xof().read(&mut bytes);
+
+    // With η = 2, each nibble of the PRF output is one coefficient.
+    let mut f = [0; N];
+    for (i, c) in f.iter_mut().enumerate() {
+        let nibble = (bytes[i / 2] >> (4 * (i % 2))) as u32;
+        let x = (nibble & 1) + ((nibble >> 1) & 1);
+        let y = ((nibble >> 2) & 1) + ((nibble >> 3) & 1);
+        *c = sub(x, y);
+    }
+    bytes.zeroize();
+    f
+}
+
+/// Returns the matrix `Â` generated from `rho`, transposed if `transpose` is set.
+fn matrix(rho: &[u8], transpose: bool) -> [[Poly; K]; K] {
+    let mut a = [[[0; N]; K]; K];
+    for (i, row) in a.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = if transpose {
+                sample_ntt(rho, j, i)
+            } else {
+                sample_ntt(rho, i, j)
+            };
+        }
+    }
+    a
+}
+
+/// Algorithm 13: K-PKE.KeyGen. Returns the encryption key, and the decryption key `ŝ`.
+fn pke_key_gen(d: &[u8; 32]) -> ([u8; ENCAPSULATION_KEY_BYTES], [Poly; K]) {
+    let mut g = Sha3_512::new();
+    Digest::update(&mut g, d);
+    Digest::update(&mut g, [K as u8]);
+    let mut g = g.finalize();
+    let (rho, sigma) = g.split_at(32);
+    let mut sigma: [u8; 32] = sigma.try_into().expect("correct length");
+
+    let a = matrix(rho, false);
+    let mut s = [[0; N]; K];
+    let mut e = [[0; N]; K];
+    for i in 0..K {
+        s[i] = ntt(sample_cbd(&sigma, i as u8));
+        e[i] = ntt(sample_cbd(&sigma, (K + i) as u8));
+    }
+
+    let mut ek = [0; ENCAPSULATION_KEY_BYTES];
+    for i in 0..K {
+        let t = poly_add(&inner_product(&a[i], &s), &e[i]);
+        byte_encode(&t, 12, &mut ek[POLY_BYTES * i..POLY_BYTES * (i + 1)]);
+    }
+    ek[POLY_BYTES * K..].copy_from_slice(rho);
+
+    g.zeroize();
+    sigma.zeroize();
+    e.zeroize();
+    (ek, s)
+}
+
+/// Algorithm 14: K-PKE.Encrypt.
+fn pke_encrypt(
+    ek: &[u8; ENCAPSULATION_KEY_BYTES],
+    m: &[u8; 32],
+    r: &[u8; 32],
+) -> [u8; CIPHERTEXT_BYTES] {
+    let mut t = [[0; N]; K];
+    for (i, t) in t.iter_mut().enumerate() {
+        *t = byte_decode(&ek[POLY_BYTES * i..POLY_BYTES * (i + 1)], 12);
+    }
+    let a_t = matrix(&ek[POLY_BYTES * K..], true);
+
+    let mut y = [[0; N]; K];
+    for (i, y) in y.iter_mut().enumerate() {
+        *y = ntt(sample_cbd(r, i as u8));
+    }
+
+    let mut c = [0; CIPHERTEXT_BYTES];
+    for i in 0..K {
+        let e1 = sample_cbd(r, (K + i) as u8);
+        let u = poly_add(&ntt_inverse(inner_product(&a_t[i], &y)), &e1);
+        byte_encode(
+            &compress(&u, DU),
+            DU,
+            &mut c[32 * DU * i..32 * DU * (i + 1)],
+        );
+    }
+
+    let e2 = sample_cbd(r, (2 * K) as u8);
+    let mut mu = decompress(&byte_decode(m, 1), 1);
+    let v = poly_add(&poly_add(&ntt_inverse(inner_product(&t, &y)), &e2), &mu);
+    byte_encode(&compress(&v, DV), DV, &mut c[32 * DU * K..]);
+
+    y.zeroize();
+    mu.zeroize();
+    c
+}
+
+/// Algorithm 15: K-PKE.Decrypt.
+fn pke_decrypt(s: &[Poly; K], c: &[u8; CIPHERTEXT_BYTES]) -> [u8; 32] {
+    let mut u = [[0; N]; K];
+    for (i, u) in u.iter_mut().enumerate() {
+        *u = ntt(decompress(
+            &byte_decode(&c[32 * DU * i..32 * DU * (i + 1)], DU),
+            DU,
+        ));
+    }
+    let v = decompress(&byte_decode(&c[32 * DU * K..], DV), DV);
+
+    let su = ntt_inverse(inner_product(s, &u));
+    let mut w = [0; N];
+    for i in 0..N {
+        w[i] = sub(v[i], su[i]);
+    }
+
+    let mut m = [0; 32];
+    byte_encode(&compress(&w, 1), 1, &mut m);
+    w.zeroize();
+    m
+}
+
+/// Returns `(K, r) = G(m || H(ek))`.
+fn g_kr(m: &[u8; 32], h: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut g = Sha3_512::new();
+    Digest::update(&mut g, m);
+    Digest::update(&mut g, h);
+    let mut g = g.finalize();
+    let kr = (
+        g[..32].try_into().expect("correct length"),
+        g[32..].try_into().expect("correct length"),
+    );
+    g.zeroize();
+    kr
+}
+
+/// An ML-KEM-768 encapsulation key.
+#[derive(Clone)]
+pub(super) struct EncapsulationKey {
+    ek: [u8; ENCAPSULATION_KEY_BYTES],
+    /// `H(ek)`
+    h: [u8; 32],
+}
+
+impl EncapsulationKey {
+    fn new(ek: [u8; ENCAPSULATION_KEY_BYTES]) -> Self {
+        let h = Sha3_256::digest(ek).into();
+        EncapsulationKey { ek, h }
+    }
+
+    /// Parses an encapsulation key, performing the modulus check from section 7.2.
+    pub(super) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let ek: [u8; ENCAPSULATION_KEY_BYTES] = bytes.try_into().ok()?;
+        let mut encoded = [0; POLY_BYTES];
+        for chunk in ek[..POLY_BYTES * K].chunks(POLY_BYTES) {
+            byte_encode(&byte_decode(chunk, 12), 12, &mut encoded);
+            if encoded[..] != chunk[..] {
+                return None;
+            }
+        }
+        Some(Self::new(ek))
+    }
+
+    pub(super) fn as_bytes(&self) -> &[u8; ENCAPSULATION_KEY_BYTES] {
+        &self.ek
+    }
+
+    /// Algorithm 17: ML-KEM.Encaps_internal, with the randomness `m`. Returns the shared
+    /// secret and the ciphertext.
+    pub(super) fn encapsulate(
+        &self,
+        m: &[u8; 32],
+    ) -> ([u8; SHARED_SECRET_BYTES], [u8; CIPHERTEXT_BYTES]) {
+        let (shared_secret, mut r) = g_kr(m, &self.h);
+        let c = pke_encrypt(&self.ek, m, &r);
+        r.zeroize();
+        (shared_secret, c)
+    }
+}
+
+/// An ML-KEM-768 decapsulation key.
+#[derive(Clone)]
+pub(super) struct DecapsulationKey {
+    s: [Poly; K],
+    ek: EncapsulationKey,
+    z: [u8; 32],
+}
+
+impl Drop for DecapsulationKey {
+    fn drop(&mut self) {
+        self.s.zeroize();
+        self.z.zeroize();
+    }
+}
+
+impl DecapsulationKey {
+    /// Algorithm 16: ML-KEM.KeyGen_internal, from the seeds `d` and `z`.
+    pub(super) fn from_seed(d: &[u8; 32], z: &[u8; 32]) -> Self {
+        let (ek, s) = pke_key_gen(d);
+        DecapsulationKey {
+            s,
+            ek: EncapsulationKey::new(ek),
+            z: *z,
+        }
+    }
+
+    pub(super) fn encapsulation_key(&self) -> &EncapsulationKey {
+        &self.ek
+    }
+
+    /// Algorithm 18: ML-KEM.Decaps_internal.
+    pub(super) fn decapsulate(&self, c: &[u8; CIPHERTEXT_BYTES]) -> [u8; SHARED_SECRET_BYTES] {
+        let mut m = pke_decrypt(&self.s, c);
+        let (mut shared_secret, mut r) = g_kr(&m, &self.ek.h);
+
+        // Implicit rejection: if the ciphertext doesn't re-encrypt to itself, return a
+        // pseudorandom shared secret instead.
+        let mut j = Shake256::default();
+        j.update(&self.z);
+        j.update(c);
+        let mut rejection = [0; SHARED_SECRET_BYTES];
+        j.finalize_xof().read(&mut rejection);
+
+        let valid = pke_encrypt(&self.ek.ek, &m, &r)[..].ct_eq(&c[..]);
+        for (k, rejection) in shared_secret.iter_mut().zip(rejection.iter()) {
+            k.conditional_assign(rejection, !valid);
+        }
+
+        m.zeroize();
+        r.zeroize();
+        rejection.zeroize();
+        shared_secret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::{barrett, compress, DecapsulationKey, EncapsulationKey, CIPHERTEXT_BYTES, N, Q};
+
+    fn seeds() -> ([u8; 32], [u8; 32], [u8; 32]) {
+        let mut d = [0; 32];
+        let mut z = [0; 32];
+        let mut m = [0; 32];
+        for i in 0..32 {
+            d[i] = i as u8;
+            z[i] = 32 + i as u8;
+            m[i] = 64 + i as u8;
+        }
+        (d, z, m)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (d, z, m) = seeds();
+        let dk = DecapsulationKey::from_seed(&d, &z);
+        let (shared_secret, c) = dk.encapsulation_key().encapsulate(&m);
+        assert_eq!(dk.decapsulate(&c), shared_secret);
+
+        // A modified ciphertext is implicitly rejected.
+        let mut modified = c;
+        modified[0] ^= 1;
+        assert_ne!(dk.decapsulate(&modified), shared_secret);
+        assert_eq!(dk.decapsulate(&modified), dk.decapsulate(&modified));
+    }
+
+    #[test]
+    fn modulus_check() {
+        let (d, z, _) = seeds();
+        let dk = DecapsulationKey::from_seed(&d, &z);
+        let ek = dk.encapsulation_key().as_bytes();
+        assert!(EncapsulationKey::from_bytes(ek).is_some());
+        assert!(EncapsulationKey::from_bytes(&ek[1..]).is_none());
+
+        // Set the first coefficient to q, which isn't reduced.
+        let mut invalid = *ek;
+        invalid[0] = (Q & 0xff) as u8;
+        invalid[1] = (invalid[1] & 0xf0) | (Q >> 8) as u8;
+        assert!(EncapsulationKey::from_bytes(&invalid).is_none());
+    }
+
+    #[test]
+    fn barrett_reduction() {
+        for a in (0..Q * Q).chain([u32::MAX - 1, u32::MAX]) {
+            assert_eq!(barrett(a), (a / Q, a % Q));
+        }
+    }
+
+    #[test]
+    fn compress_rounding() {
+        for &d in &[1, 4, 10, 11] {
+            for x in 0..Q {
+                let mut f = [0; N];
+                f[0] = x;
+                let expected = (((x << d) + Q / 2) / Q) & ((1 << d) - 1);
+                assert_eq!(compress(&f, d)[0], expected);
+            }
+        }
+    }
+
+    /// Vectors generated with the ML-KEM-768 implementation in OpenSSL 3.5: the
+    /// encapsulation key derived from the seeds `d` and `z`, a ciphertext from its
+    /// encapsulation, and the shared secrets that it decapsulates from that ciphertext
+    /// and from the ciphertext with a flipped bit (which is implicitly rejected).
+    #[test]
+    fn openssl_vectors() {
+        let vectors: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("../../tests/testdata/ml-kem-768.json")).unwrap();
+        assert!(!vectors.is_empty());
+        for v in vectors {
+            let field = |name: &str| hex::decode(v[name].as_str().unwrap()).unwrap();
+            let seed = |name: &str| -> [u8; 32] { field(name).try_into().unwrap() };
+            let ciphertext =
+                |name: &str| -> [u8; CIPHERTEXT_BYTES] { field(name).try_into().unwrap() };
+
+            let dk = DecapsulationKey::from_seed(&seed("d"), &seed("z"));
+            assert_eq!(&dk.encapsulation_key().as_bytes()[..], &field("ek")[..]);
+            assert_eq!(&dk.decapsulate(&ciphertext("c"))[..], &field("k")[..]);
+            assert_eq!(
+                &dk.decapsulate(&ciphertext("c_invalid"))[..],
+                &field("k_invalid")[..]
+            );
+        }
+    }
+
+    #[test]
+    fn known_answer() {
+        // Checked against the ML-KEM-768 implementation in OpenSSL, which
+        // decapsulates the same shared secrets from a key generated with the seeds above.
+        let (d, z, m) = seeds();
+        let dk = DecapsulationKey::from_seed(&d, &z);
+        let (shared_secret, c) = dk.encapsulation_key().encapsulate(&m);
+
+        assert_eq!(
+            hex::encode(Sha256::digest(dk.encapsulation_key().as_bytes())),
+            EK_SHA256,
+        );
+        assert_eq!(hex::encode(Sha256::digest(c)), CT_SHA256);
+        assert_eq!(hex::encode(shared_secret), SHARED_SECRET);
+
+        let mut modified = c;
+        modified[0] ^= 1;
+        assert_eq!(
+            hex::encode(dk.decapsulate(&modified)),
+            REJECTED_SHARED_SECRET
+        );
+    }
+
+    const EK_SHA256: &str = "0b7934c83125c788995e2ba6bd761e33046b3e40571be53e023309a29f398cc9";
+    const CT_SHA256: &str = "dbf4e9aa48b078ad46ec1c9c47bda8c2d2fec9d0e7a21bd48d2238a2abedb856";
+    const SHARED_SECRET: &str = "9cddd089ffe70e3996e76f7c8d06746df34d07e8657bc0fcf2bb0e1c3084aea1";
+    const REJECTED_SHARED_SECRET: &str =
+        "dcfc80c6db46ff7028e3a4398651c063ae7a42c107a6dc8cb07141861698ab92";
+}
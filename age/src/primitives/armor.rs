@@ -29,15 +29,68 @@ const ARMORED_END_MARKER: &str = "-----END AGE ENCRYPTED FILE-----";
 
 const MIN_ARMOR_LEN: usize = 36; // ARMORED_BEGIN_MARKER.len() + 2
 
+/// The interval (in bytes of data inside the armor) between the positions that an
+/// [`ArmoredReader`] records for seeking.
+const CHECKPOINT_INTERVAL: u64 = ARMORED_BYTES_PER_LINE as u64 * 1024;
+
 const BASE64_CHUNK_SIZE_COLUMNS: usize = 8 * 1024;
 const BASE64_CHUNK_SIZE_BYTES: usize = BASE64_CHUNK_SIZE_COLUMNS / 4 * 3;
 
 /// Specifies the format that [`ArmoredWriter`] should apply to its output.
+///
+/// Both armored formats are strict PEM, as defined in [RFC 7468 section 3]. The type
+/// label is `AGE ENCRYPTED FILE`, and the Base64 is wrapped at 64 columns with
+/// canonical padding. Armored age files can therefore be stored by systems that
+/// validate PEM strictly. The label is fixed by the age format, so that every age
+/// implementation can decrypt these files.
+///
+/// [RFC 7468 section 3]: https://www.rfc-editor.org/rfc/rfc7468#section-3
 pub enum Format {
     /// age binary format.
     Binary,
     /// ASCII armored format.
+    ///
+    /// Lines end with the platform's line ending (`\r\n` on Windows, and `\n`
+    /// elsewhere).
     AsciiArmor,
+    /// ASCII armored format, byte-for-byte identical to the output of the reference Go
+    /// implementation for the same ciphertext.
+    ///
+    /// Lines end with `\n` on every platform. This is useful for pipelines that compare
+    /// armored files produced on different platforms or by different implementations.
+    AsciiArmorInterop,
+}
+
+impl Format {
+    /// Returns the line ending for this format, or `None` if it is not armored.
+    fn line_ending(&self) -> Option<&'static str> {
+        match self {
+            Format::Binary => None,
+            Format::AsciiArmor => Some(LINE_ENDING),
+            Format::AsciiArmorInterop => Some("\n"),
+        }
+    }
+}
+
+/// Specifies how much of the data written to an [`ArmoredWriter`] is written out when
+/// it is flushed.
+///
+/// This has no effect on [`Format::Binary`] output, which is always flushed straight
+/// through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flushing writes out the data that has already been encoded.
+    ///
+    /// Data is encoded in blocks of 128 armor lines, so up to 6 KiB of written data
+    /// may remain buffered after a flush. This is the default.
+    Buffered,
+    /// Flushing also encodes and writes out every complete armor line (48 bytes of
+    /// data) written so far, along with its line ending.
+    ///
+    /// After a flush, at most one partial line of data remains buffered. This is
+    /// useful when the output is consumed as it is written, for example by a reader
+    /// on the other end of a pipe.
+    CompleteLines,
 }
 
 #[cfg(feature = "async")]
@@ -60,6 +113,10 @@ pub(crate) struct LineEndingWriter<W> {
     inner: W,
     buf: Vec<u8>,
     total_written: usize,
+    line_ending: &'static str,
+    /// Whether the line ending after the last complete line has already been written,
+    /// by [`FlushPolicy::CompleteLines`].
+    line_ended: bool,
 
     /// None if `AsyncWrite::poll_closed` has been called.
     #[cfg(feature = "async")]
@@ -71,15 +128,17 @@ pub(crate) struct LineEndingWriter<W> {
 }
 
 impl<W: Write> LineEndingWriter<W> {
-    fn new(mut inner: W) -> io::Result<Self> {
+    fn new(mut inner: W, line_ending: &'static str) -> io::Result<Self> {
         // Write the begin marker
         inner.write_all(ARMORED_BEGIN_MARKER.as_bytes())?;
-        inner.write_all(LINE_ENDING.as_bytes())?;
+        inner.write_all(line_ending.as_bytes())?;
 
         Ok(LineEndingWriter {
             inner,
             buf: Vec::with_capacity(8 * 1024),
             total_written: 0,
+            line_ending,
+            line_ended: false,
             #[cfg(feature = "async")]
             line: None,
             #[cfg(feature = "async")]
@@ -89,19 +148,31 @@ impl<W: Write> LineEndingWriter<W> {
 
     fn flush_buffered(&mut self) -> io::Result<()> {
         self.inner.write_all(&self.buf)?;
-        self.total_written += self.buf.len();
         self.buf.clear();
         Ok(())
     }
 
+    /// Adds the line ending after the last line, if it is complete.
+    fn end_complete_line(&mut self) {
+        if self.total_written > 0
+            && self.total_written % ARMORED_COLUMNS_PER_LINE == 0
+            && !self.line_ended
+        {
+            self.buf.extend_from_slice(self.line_ending.as_bytes());
+            self.line_ended = true;
+        }
+    }
+
     fn finish(mut self) -> io::Result<W> {
         // Ensure all bytes have been written.
         self.flush_buffered()?;
 
         // Write the end marker
-        self.inner.write_all(LINE_ENDING.as_bytes())?;
+        if !self.line_ended {
+            self.inner.write_all(self.line_ending.as_bytes())?;
+        }
         self.inner.write_all(ARMORED_END_MARKER.as_bytes())?;
-        self.inner.write_all(LINE_ENDING.as_bytes())?;
+        self.inner.write_all(self.line_ending.as_bytes())?;
 
         Ok(self.inner)
     }
@@ -119,14 +190,15 @@ impl<W: Write> Write for LineEndingWriter<W> {
                 ARMORED_COLUMNS_PER_LINE - (self.total_written % ARMORED_COLUMNS_PER_LINE);
 
             // Write the next newline if we are at the end of the line.
-            if remaining == ARMORED_COLUMNS_PER_LINE && self.total_written > 0 {
-                self.buf.extend_from_slice(LINE_ENDING.as_bytes());
+            if remaining == ARMORED_COLUMNS_PER_LINE && self.total_written > 0 && !self.line_ended {
+                self.buf.extend_from_slice(self.line_ending.as_bytes());
             }
             let to_write = cmp::min(remaining, buf.len());
 
             self.buf.extend_from_slice(&buf[..to_write]);
             buf = &buf[to_write..];
             self.total_written += to_write;
+            self.line_ended = false;
         }
 
         // Write the buffer to the inner writer, and drop the written bytes. We trigger
@@ -155,14 +227,16 @@ impl<W: Write> Write for LineEndingWriter<W> {
 #[cfg(feature = "async")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 impl<W: AsyncWrite> LineEndingWriter<W> {
-    fn new_async(inner: W) -> Self {
+    fn new_async(inner: W, line_ending: &'static str) -> Self {
         // Write the begin marker
-        let bytes = [ARMORED_BEGIN_MARKER.as_bytes(), LINE_ENDING.as_bytes()].concat();
+        let bytes = [ARMORED_BEGIN_MARKER.as_bytes(), line_ending.as_bytes()].concat();
 
         LineEndingWriter {
             inner,
             buf: vec![],
             total_written: 0,
+            line_ending,
+            line_ended: false,
             line: Some(Vec::with_capacity(ARMORED_COLUMNS_PER_LINE)),
             line_with_ending: Some(EncodedLine { bytes, offset: 0 }),
         }
@@ -187,6 +261,25 @@ impl<W: AsyncWrite> LineEndingWriter<W> {
 
         Poll::Ready(Ok(()))
     }
+
+    /// Writes out the last line along with its line ending, if it is complete.
+    fn poll_end_complete_line(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush_line(cx))?;
+
+        let this = self.as_mut().project();
+        if let Some(line) = this.line {
+            if line.len() == ARMORED_COLUMNS_PER_LINE {
+                *this.line_with_ending = Some(EncodedLine {
+                    bytes: [line, this.line_ending.as_bytes()].concat(),
+                    offset: 0,
+                });
+                line.clear();
+                *this.line_ended = true;
+            }
+        }
+
+        self.poll_flush_line(cx)
+    }
 }
 
 #[cfg(feature = "async")]
@@ -208,6 +301,9 @@ impl<W: AsyncWrite> AsyncWrite for LineEndingWriter<W> {
 
             line.extend_from_slice(&buf[..to_write]);
             buf = &buf[to_write..];
+            if to_write > 0 {
+                *this.line_ended = false;
+            }
 
             // At this point, either buf is empty, or we have a complete line.
             assert!(buf.is_empty() || line.len() == ARMORED_COLUMNS_PER_LINE);
@@ -216,7 +312,7 @@ impl<W: AsyncWrite> AsyncWrite for LineEndingWriter<W> {
             // line must be written in poll_close().
             if !buf.is_empty() {
                 *this.line_with_ending = Some(EncodedLine {
-                    bytes: [line, LINE_ENDING.as_bytes()].concat(),
+                    bytes: [line, this.line_ending.as_bytes()].concat(),
                     offset: 0,
                 });
                 line.clear();
@@ -243,13 +339,19 @@ impl<W: AsyncWrite> AsyncWrite for LineEndingWriter<W> {
         let this = self.as_mut().project();
         if let Some(line) = this.line {
             // Finish the armored format with a partial line (if necessary) and the end
-            // marker.
+            // marker. The last line's ending has already been written if it was ended
+            // by a flush.
+            let line_ending: &[u8] = if *this.line_ended {
+                &[]
+            } else {
+                this.line_ending.as_bytes()
+            };
             *this.line_with_ending = Some(EncodedLine {
                 bytes: [
                     line,
-                    LINE_ENDING.as_bytes(),
+                    line_ending,
                     ARMORED_END_MARKER.as_bytes(),
-                    LINE_ENDING.as_bytes(),
+                    this.line_ending.as_bytes(),
                 ]
                 .concat(),
                 offset: 0,
@@ -273,6 +375,7 @@ enum ArmorIs<W> {
         #[cfg(feature = "async")]
         #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
         encoded_line: Option<EncodedBytes>,
+        flush_policy: FlushPolicy,
     },
 
     Disabled {
@@ -285,20 +388,38 @@ enum ArmorIs<W> {
 #[pin_project]
 pub struct ArmoredWriter<W>(#[pin] ArmorIs<W>);
 
+impl<W> ArmoredWriter<W> {
+    /// Sets how much of the written data is written out when this writer is flushed.
+    ///
+    /// The default is [`FlushPolicy::Buffered`].
+    pub fn with_flush_policy(mut self, policy: FlushPolicy) -> Self {
+        if let ArmorIs::Enabled { flush_policy, .. } = &mut self.0 {
+            *flush_policy = policy;
+        }
+        self
+    }
+}
+
+/// Returns the length of the prefix of `byte_buf` that fills complete armor lines.
+fn complete_lines_len(byte_buf: &[u8]) -> usize {
+    byte_buf.len() - byte_buf.len() % ARMORED_BYTES_PER_LINE
+}
+
 impl<W: Write> ArmoredWriter<W> {
     /// Wraps the given output in an `ArmoredWriter` that will apply the given [`Format`].
     pub fn wrap_output(output: W, format: Format) -> io::Result<Self> {
-        match format {
-            Format::AsciiArmor => LineEndingWriter::new(output).map(|w| {
+        match format.line_ending() {
+            Some(line_ending) => LineEndingWriter::new(output, line_ending).map(|w| {
                 ArmoredWriter(ArmorIs::Enabled {
                     inner: w,
                     byte_buf: Some(Vec::with_capacity(BASE64_CHUNK_SIZE_BYTES)),
                     encoded_buf: Box::new([0; BASE64_CHUNK_SIZE_COLUMNS]),
                     #[cfg(feature = "async")]
                     encoded_line: None,
+                    flush_policy: FlushPolicy::Buffered,
                 })
             }),
-            Format::Binary => Ok(ArmoredWriter(ArmorIs::Disabled { inner: output })),
+            None => Ok(ArmoredWriter(ArmorIs::Disabled { inner: output })),
         }
     }
 
@@ -379,6 +500,27 @@ impl<W: Write> Write for ArmoredWriter<W> {
 
     fn flush(&mut self) -> io::Result<()> {
         match &mut self.0 {
+            ArmorIs::Enabled {
+                inner,
+                byte_buf,
+                encoded_buf,
+                flush_policy: FlushPolicy::CompleteLines,
+                ..
+            } => {
+                let byte_buf = byte_buf.as_mut().unwrap();
+                let complete = complete_lines_len(byte_buf);
+                if complete > 0 {
+                    let encoded = base64::encode_config_slice(
+                        &byte_buf[..complete],
+                        base64::STANDARD,
+                        &mut encoded_buf[..],
+                    );
+                    inner.write_all(&encoded_buf[..encoded])?;
+                    byte_buf.drain(..complete);
+                }
+                inner.end_complete_line();
+                inner.flush()
+            }
             ArmorIs::Enabled { inner, .. } => inner.flush(),
             ArmorIs::Disabled { inner } => inner.flush(),
         }
@@ -390,14 +532,15 @@ impl<W: Write> Write for ArmoredWriter<W> {
 impl<W: AsyncWrite> ArmoredWriter<W> {
     /// Wraps the given output in an `ArmoredWriter` that will apply the given [`Format`].
     pub fn wrap_async_output(output: W, format: Format) -> Self {
-        match format {
-            Format::AsciiArmor => ArmoredWriter(ArmorIs::Enabled {
-                inner: LineEndingWriter::new_async(output),
+        match format.line_ending() {
+            Some(line_ending) => ArmoredWriter(ArmorIs::Enabled {
+                inner: LineEndingWriter::new_async(output, line_ending),
                 byte_buf: Some(Vec::with_capacity(BASE64_CHUNK_SIZE_BYTES)),
                 encoded_buf: Box::new([0; BASE64_CHUNK_SIZE_COLUMNS]),
                 encoded_line: None,
+                flush_policy: FlushPolicy::Buffered,
             }),
-            Format::Binary => ArmoredWriter(ArmorIs::Disabled { inner: output }),
+            None => ArmoredWriter(ArmorIs::Disabled { inner: output }),
         }
     }
 
@@ -487,8 +630,44 @@ impl<W: AsyncWrite> AsyncWrite for ArmoredWriter<W> {
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
         ready!(self.as_mut().poll_flush_line(cx))?;
+
+        if let ArmorIsProj::Enabled {
+            byte_buf: Some(byte_buf),
+            encoded_buf,
+            encoded_line,
+            flush_policy: FlushPolicy::CompleteLines,
+            ..
+        } = self.as_mut().project().0.project()
+        {
+            let complete = complete_lines_len(byte_buf);
+            if complete > 0 {
+                let encoded = base64::encode_config_slice(
+                    &byte_buf[..complete],
+                    base64::STANDARD,
+                    &mut encoded_buf[..],
+                );
+                *encoded_line = Some(EncodedBytes {
+                    offset: 0,
+                    end: encoded,
+                });
+                byte_buf.drain(..complete);
+            }
+        }
+
+        // Flush the complete lines (if we didn't in the first call).
+        ready!(self.as_mut().poll_flush_line(cx))?;
+
         match self.project().0.project() {
-            ArmorIsProj::Enabled { inner, .. } => inner.poll_flush(cx),
+            ArmorIsProj::Enabled {
+                mut inner,
+                flush_policy,
+                ..
+            } => {
+                if *flush_policy == FlushPolicy::CompleteLines {
+                    ready!(inner.as_mut().poll_end_complete_line(cx))?;
+                }
+                inner.poll_flush(cx)
+            }
             ArmorIsProj::Disabled { inner } => inner.poll_flush(cx),
         }
     }
@@ -546,6 +725,15 @@ pub enum ArmoredReadError {
     ShortLineInMiddle,
     /// There are trailing non-whitespace characters after the end marker.
     TrailingGarbage,
+    /// The armor ends without an end marker.
+    MissingEndMarker,
+    /// The input does not contain a begin marker. This is only returned when surrounding
+    /// text is ignored; see [`ArmoredReader::ignore_surrounding_text`].
+    MissingBeginMarker,
+    /// A line of the armor does not start with the text that precedes the begin marker.
+    /// This is only returned when surrounding text is ignored; see
+    /// [`ArmoredReader::ignore_surrounding_text`].
+    MissingLinePrefix,
 }
 
 impl fmt::Display for ArmoredReadError {
@@ -570,6 +758,18 @@ impl fmt::Display for ArmoredReadError {
                     "invalid armor (non-whitespace characters after end marker)"
                 )
             }
+            ArmoredReadError::MissingEndMarker => {
+                write!(f, "invalid armor (missing end marker)")
+            }
+            ArmoredReadError::MissingBeginMarker => {
+                write!(f, "invalid armor (missing begin marker)")
+            }
+            ArmoredReadError::MissingLinePrefix => {
+                write!(
+                    f,
+                    "invalid armor (line does not start with the prefix of the begin marker)"
+                )
+            }
         }
     }
 }
@@ -598,6 +798,31 @@ enum StartPos {
     Explicit(u64),
 }
 
+/// Buffers part of the start of the input in `byte_buf` for armor detection.
+///
+/// `available` is the data currently buffered by the underlying reader. Returns the
+/// number of bytes of it that were used, which the caller must consume. This is shared
+/// by the sync and async readers, so that both handle input that arrives in pieces of
+/// any size.
+fn buffer_for_detection(
+    byte_buf: &mut [u8],
+    detect_len: &mut usize,
+    available: &[u8],
+) -> io::Result<usize> {
+    if available.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "input is too short to be an age file",
+        ));
+    }
+
+    let used = cmp::min(MIN_ARMOR_LEN - *detect_len, available.len());
+    byte_buf[*detect_len..*detect_len + used].copy_from_slice(&available[..used]);
+    *detect_len += used;
+
+    Ok(used)
+}
+
 /// Reader that will parse the age ASCII armor format if detected.
 #[pin_project]
 pub struct ArmoredReader<R> {
@@ -605,14 +830,28 @@ pub struct ArmoredReader<R> {
     inner: R,
     start: StartPos,
     is_armored: Option<bool>,
+    /// The number of bytes at the start of `byte_buf` buffered for armor detection.
+    detect_len: usize,
     line_buf: Zeroizing<String>,
     byte_buf: Zeroizing<[u8; ARMORED_BYTES_PER_LINE]>,
     byte_start: usize,
     byte_end: usize,
     found_short_line: bool,
     found_end: bool,
+    /// Whether to scan for the begin marker, and ignore text after the end marker.
+    surrounding_text: bool,
+    /// The text preceding the begin marker on its line, which every armor line starts
+    /// with.
+    line_prefix: String,
+    /// A partial line read while scanning for the begin marker.
+    scan_buf: Vec<u8>,
     data_len: Option<u64>,
     data_read: usize,
+    /// The number of bytes that have been read from `inner`, relative to the start.
+    reader_pos: u64,
+    /// Pairs of (data position, reader position relative to the start) for the start
+    /// of armor lines, recorded every [`CHECKPOINT_INTERVAL`] bytes of data.
+    checkpoints: Vec<(u64, u64)>,
 }
 
 impl<R: Read> ArmoredReader<BufReader<R>> {
@@ -622,6 +861,48 @@ impl<R: Read> ArmoredReader<BufReader<R>> {
     }
 }
 
+impl<R: BufRead> ArmoredReader<R> {
+    /// Wraps a buffered reader that may contain an armored age file.
+    ///
+    /// Unlike [`ArmoredReader::new`], this does not add another layer of buffering. If
+    /// `reader` also implements [`Seek`], so does the returned reader (and so does a
+    /// [`StreamReader`] decrypting from it), whether or not the age file is armored.
+    ///
+    /// [`StreamReader`]: crate::stream::StreamReader
+    pub fn new_buffered(reader: R) -> Self {
+        ArmoredReader::with_buffered(reader)
+    }
+
+    /// Reads the start of the input, and detects whether this is an armored age file.
+    fn read_and_detect_armor(&mut self) -> io::Result<()> {
+        if self.surrounding_text {
+            let mut line = vec![];
+            loop {
+                line.clear();
+                if self.inner.read_until(b'\n', &mut line)? == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        ArmoredReadError::MissingBeginMarker,
+                    ));
+                }
+                if self.scan_line(&line) {
+                    return Ok(());
+                }
+            }
+        }
+
+        while self.detect_len < MIN_ARMOR_LEN {
+            let used = buffer_for_detection(
+                &mut self.byte_buf[..],
+                &mut self.detect_len,
+                self.inner.fill_buf()?,
+            )?;
+            self.inner.consume(used);
+        }
+        self.detect_armor()
+    }
+}
+
 #[cfg(feature = "async")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 impl<R: AsyncRead + Unpin> ArmoredReader<AsyncBufReader<R>> {
@@ -632,23 +913,72 @@ impl<R: AsyncRead + Unpin> ArmoredReader<AsyncBufReader<R>> {
 }
 
 impl<R> ArmoredReader<R> {
-    fn with_buffered(inner: R) -> Self {
+    pub(crate) fn with_buffered(inner: R) -> Self {
         ArmoredReader {
             inner,
             start: StartPos::Implicit(0),
             is_armored: None,
+            detect_len: 0,
             line_buf: Zeroizing::new(String::with_capacity(ARMORED_COLUMNS_PER_LINE + 2)),
             byte_buf: Zeroizing::new([0; ARMORED_BYTES_PER_LINE]),
             byte_start: ARMORED_BYTES_PER_LINE,
             byte_end: ARMORED_BYTES_PER_LINE,
             found_short_line: false,
             found_end: false,
+            surrounding_text: false,
+            line_prefix: String::new(),
+            scan_buf: vec![],
             data_len: None,
             data_read: 0,
+            reader_pos: 0,
+            checkpoints: vec![(0, 0)],
+        }
+    }
+
+    /// Ignores any text around the armor, so that an armored age file embedded in a
+    /// larger document (such as an email body, or a YAML or Markdown file) can be read.
+    ///
+    /// The input is scanned for a line containing the begin marker, and anything after
+    /// the end marker is ignored. If the begin marker is preceded by other text on its
+    /// line (such as indentation, or a `> ` quote prefix), every following line of the
+    /// armor must start with the same text, which is removed before it is parsed.
+    ///
+    /// The input must be armored; binary age files are not detected in this mode. This
+    /// must be called before anything is read from the `ArmoredReader`.
+    pub fn ignore_surrounding_text(mut self) -> Self {
+        assert!(self.is_armored.is_none() && self.detect_len == 0);
+        self.surrounding_text = true;
+        self
+    }
+
+    /// Returns whether the input is armored, or `None` if nothing has been read yet.
+    pub fn is_armored(&self) -> Option<bool> {
+        self.is_armored
+    }
+
+    /// Checks whether `line`, which was read while scanning for the armor, contains the
+    /// begin marker.
+    fn scan_line(&mut self, line: &[u8]) -> bool {
+        let line = match line.iter().rposition(|b| !b.is_ascii_whitespace()) {
+            Some(end) => &line[..=end],
+            None => return false,
+        };
+        match line
+            .strip_suffix(ARMORED_BEGIN_MARKER.as_bytes())
+            .map(std::str::from_utf8)
+        {
+            Some(Ok(prefix)) => {
+                self.line_prefix = prefix.to_owned();
+                self.is_armored = Some(true);
+                true
+            }
+            _ => false,
         }
     }
 
     fn count_reader_bytes(&mut self, read: usize) -> usize {
+        self.reader_pos += read as u64;
+
         // We only need to count if we haven't yet worked out the start position.
         if let StartPos::Implicit(offset) = &mut self.start {
             *offset += read as u64;
@@ -722,6 +1052,23 @@ impl<R> ArmoredReader<R> {
         Ok(())
     }
 
+    /// Records the position of the next armor line, if it starts a new checkpoint
+    /// interval.
+    ///
+    /// Must only be called when all cached data has been read, so that the next line
+    /// starts at `self.data_read`.
+    fn record_checkpoint(&mut self) {
+        let data_pos = self.data_read as u64;
+        if !self.found_short_line
+            && data_pos % CHECKPOINT_INTERVAL == 0
+            && self.checkpoints.last().map_or(true, |&(d, _)| d < data_pos)
+        {
+            // Part of the line might already have been read into the line buffer.
+            let line_start = self.reader_pos - self.line_buf.len() as u64;
+            self.checkpoints.push((data_pos, line_start));
+        }
+    }
+
     /// Reads cached data into the given buffer.
     ///
     /// Returns the number of bytes read into the buffer, or None if there was no cached
@@ -747,6 +1094,17 @@ impl<R> ArmoredReader<R> {
     ///
     /// Returns `true` if this was the last line.
     fn parse_armor_line(&mut self) -> io::Result<bool> {
+        // Remove the text that preceded the begin marker.
+        if !self.line_prefix.is_empty() {
+            if !self.line_buf.starts_with(self.line_prefix.as_str()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    ArmoredReadError::MissingLinePrefix,
+                ));
+            }
+            self.line_buf.drain(..self.line_prefix.len());
+        }
+
         // Handle line endings
         let line = if self.line_buf.ends_with("\r\n") {
             // trim_end_matches will trim the pattern repeatedly, but because
@@ -824,10 +1182,7 @@ impl<R: BufRead> Read for ArmoredReader<R> {
     fn read(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
         loop {
             match self.is_armored {
-                None => {
-                    self.inner.read_exact(&mut self.byte_buf[..MIN_ARMOR_LEN])?;
-                    self.detect_armor()?
-                }
+                None => self.read_and_detect_armor()?,
                 Some(false) => {
                     // Return any leftover data from armor detection
                     return if let Some(read) = self.read_cached_data(buf) {
@@ -854,6 +1209,8 @@ impl<R: BufRead> Read for ArmoredReader<R> {
         }
 
         while !buf.is_empty() {
+            self.record_checkpoint();
+
             // Read the next line
             self.inner
                 .read_line(&mut self.line_buf)
@@ -862,7 +1219,7 @@ impl<R: BufRead> Read for ArmoredReader<R> {
             // Parse the line into bytes
             if self.parse_armor_line()? {
                 // This was the last line! Check for trailing garbage.
-                loop {
+                while !self.surrounding_text {
                     let amt = match self.inner.fill_buf()? {
                         &[] => break,
                         buf => {
@@ -955,16 +1312,35 @@ impl<R: AsyncBufRead + Unpin> AsyncRead for ArmoredReader<R> {
     ) -> Poll<Result<usize, Error>> {
         loop {
             match self.is_armored {
+                None if self.surrounding_text => {
+                    let mut this = self.as_mut().project();
+                    let mut read = 0;
+                    let read = ready!(read_until_internal(
+                        this.inner.as_mut(),
+                        cx,
+                        b'\n',
+                        this.scan_buf,
+                        &mut read,
+                    ))?;
+                    let line = mem::take(this.scan_buf);
+                    if !self.scan_line(&line) && read == 0 {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            ArmoredReadError::MissingBeginMarker,
+                        )));
+                    }
+                }
                 None => {
                     let mut this = self.as_mut().project();
-                    let available = loop {
-                        let buf = ready!(this.inner.as_mut().poll_fill_buf(cx))?;
-                        if buf.len() >= MIN_ARMOR_LEN {
-                            break buf;
-                        }
-                    };
-                    this.byte_buf[..MIN_ARMOR_LEN].copy_from_slice(&available[..MIN_ARMOR_LEN]);
-                    this.inner.as_mut().consume(MIN_ARMOR_LEN);
+                    while *this.detect_len < MIN_ARMOR_LEN {
+                        let available = ready!(this.inner.as_mut().poll_fill_buf(cx))?;
+                        let used = buffer_for_detection(
+                            &mut this.byte_buf[..],
+                            this.detect_len,
+                            available,
+                        )?;
+                        this.inner.as_mut().consume(used);
+                    }
                     self.detect_armor()?
                 }
                 Some(false) => {
@@ -1008,7 +1384,7 @@ impl<R: AsyncBufRead + Unpin> AsyncRead for ArmoredReader<R> {
                     let read = if self.parse_armor_line()? {
                         // This was the last line! Check for trailing garbage.
                         let mut this = self.as_mut().project();
-                        loop {
+                        while !*this.surrounding_text {
                             let amt = match ready!(this.inner.as_mut().poll_fill_buf(cx))? {
                                 &[] => break,
                                 buf => {
@@ -1057,10 +1433,7 @@ impl<R: BufRead + Seek> Seek for ArmoredReader<R> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         loop {
             match self.is_armored {
-                None => {
-                    self.inner.read_exact(&mut self.byte_buf[..MIN_ARMOR_LEN])?;
-                    self.detect_armor()?
-                }
+                None => self.read_and_detect_armor()?,
                 Some(false) => {
                     break if self.byte_start >= self.byte_end {
                         // Map the data read onto the underlying stream.
@@ -1146,21 +1519,30 @@ impl<R: BufRead + Seek> Seek for ArmoredReader<R> {
                         }
                     };
 
-                    // Jump back to the start of the armor data, and then read and drop
-                    // until we reach the target position. This is very inefficient, but
-                    // as armored files can have arbitrary line endings within the file,
+                    // As armored files can have arbitrary line endings within the file,
                     // we can't determine where the armor line containing the target
-                    // position begins within the reader.
-                    self.inner.seek(SeekFrom::Start(start))?;
+                    // position begins within the reader. Instead, jump back to the
+                    // closest line we have recorded before the target position (at
+                    // worst, the start of the armor data), and then read and drop until
+                    // we reach the target position.
+                    let (data_pos, reader_pos) = self
+                        .checkpoints
+                        .iter()
+                        .rev()
+                        .find(|(data_pos, _)| *data_pos <= target_pos)
+                        .copied()
+                        .unwrap_or((0, 0));
+                    self.inner.seek(SeekFrom::Start(start + reader_pos))?;
+                    self.reader_pos = reader_pos;
                     self.line_buf.clear();
                     self.byte_start = ARMORED_BYTES_PER_LINE;
                     self.byte_end = ARMORED_BYTES_PER_LINE;
                     self.found_short_line = false;
                     self.found_end = false;
-                    self.data_read = 0;
+                    self.data_read = data_pos as usize;
 
                     let mut buf = [0; 4096];
-                    let mut to_read = target_pos as usize;
+                    let mut to_read = (target_pos - data_pos) as usize;
                     while to_read > buf.len() {
                         self.read_exact(&mut buf)?;
                         to_read -= buf.len();
@@ -1177,11 +1559,166 @@ impl<R: BufRead + Seek> Seek for ArmoredReader<R> {
     }
 }
 
+/// The first violation of the armor format in an armored age file, as found by
+/// [`check`].
+#[derive(Debug)]
+pub struct ArmorViolation {
+    line: usize,
+    column: usize,
+    error: ArmoredReadError,
+}
+
+impl ArmorViolation {
+    /// Returns the line containing the violation, starting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the byte within the line at which the violation starts, starting from 1.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the violation.
+    pub fn error(&self) -> &ArmoredReadError {
+        &self.error
+    }
+}
+
+impl fmt::Display for ArmorViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.error
+        )
+    }
+}
+
+impl error::Error for ArmorViolation {}
+
+/// Checks that `reader` contains a strictly well-formed armored age file.
+///
+/// This applies the same rules as [`ArmoredReader`] (begin and end markers, line
+/// lengths, the Base64 charset, and canonical padding), but reports where the first
+/// violation is instead of only what it is. No keys are needed, because the armored
+/// data is not decrypted, or checked to be an age file; this is intended for working
+/// out how a file was mangled in transit.
+///
+/// Returns `Ok(None)` if the armor is valid.
+pub fn check<R: BufRead>(mut reader: R) -> io::Result<Option<ArmorViolation>> {
+    let violation = |line, column, error| {
+        Ok(Some(ArmorViolation {
+            line,
+            column,
+            error,
+        }))
+    };
+
+    let mut buf = Vec::with_capacity(ARMORED_COLUMNS_PER_LINE + 2);
+    let mut bytes = [0; ARMORED_BYTES_PER_LINE];
+    let mut line_num = 0;
+    // The line number and length of the short line, once we have found it.
+    let mut short_line = None;
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return if line_num == 0 {
+                violation(1, 1, ArmoredReadError::InvalidBeginMarker)
+            } else {
+                violation(line_num + 1, 1, ArmoredReadError::MissingEndMarker)
+            };
+        }
+        line_num += 1;
+
+        let line = match buf.strip_suffix(b"\n") {
+            Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+            None => &buf,
+        };
+        if let Some(i) = line.iter().position(|&b| b == b'\r') {
+            return violation(line_num, i + 1, ArmoredReadError::LineContainsCr);
+        }
+
+        if line_num == 1 {
+            if line != ARMORED_BEGIN_MARKER.as_bytes() {
+                let column = line
+                    .iter()
+                    .zip(ARMORED_BEGIN_MARKER.as_bytes())
+                    .position(|(a, b)| a != b)
+                    .unwrap_or_else(|| cmp::min(line.len(), ARMORED_BEGIN_MARKER.len()));
+                return violation(1, column + 1, ArmoredReadError::InvalidBeginMarker);
+            }
+            continue;
+        }
+
+        if let Err(e) = std::str::from_utf8(line) {
+            return violation(line_num, e.valid_up_to() + 1, ArmoredReadError::InvalidUtf8);
+        }
+
+        if line == ARMORED_END_MARKER.as_bytes() {
+            break;
+        }
+
+        match (short_line, line.len()) {
+            (Some((short_num, short_len)), ARMORED_COLUMNS_PER_LINE) => {
+                return violation(
+                    short_num,
+                    short_len + 1,
+                    ArmoredReadError::ShortLineInMiddle,
+                );
+            }
+            // Only the end marker may follow the short line.
+            (Some(_), _) => return violation(line_num, 1, ArmoredReadError::MissingEndMarker),
+            (None, ARMORED_COLUMNS_PER_LINE) => (),
+            (None, n) if n > ARMORED_COLUMNS_PER_LINE => {
+                return violation(
+                    line_num,
+                    ARMORED_COLUMNS_PER_LINE + 1,
+                    ArmoredReadError::NotWrappedAt64Chars,
+                );
+            }
+            (None, n) if n % 4 != 0 => {
+                return violation(line_num, n + 1, ArmoredReadError::MissingPadding);
+            }
+            (None, n) => short_line = Some((line_num, n)),
+        }
+
+        if let Err(e) = base64::decode_config_slice(line, base64::STANDARD, &mut bytes) {
+            let column = match e {
+                base64::DecodeError::InvalidByte(i, _)
+                | base64::DecodeError::InvalidLastSymbol(i, _) => i + 1,
+                base64::DecodeError::InvalidLength => line.len() + 1,
+            };
+            return violation(line_num, column, ArmoredReadError::Base64(e));
+        }
+    }
+
+    // Only whitespace may follow the end marker.
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(None);
+        }
+        line_num += 1;
+
+        if let Some(i) = buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            return violation(line_num, i + 1, ArmoredReadError::TrailingGarbage);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
-    use super::{ArmoredReader, ArmoredWriter, Format, ARMORED_BYTES_PER_LINE};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{
+        check, ArmoredReadError, ArmoredReader, ArmoredWriter, FlushPolicy, Format,
+        ARMORED_BYTES_PER_LINE, ARMORED_COLUMNS_PER_LINE, CHECKPOINT_INTERVAL,
+    };
 
     #[cfg(feature = "async")]
     use futures::{
@@ -1192,6 +1729,300 @@ mod tests {
     #[cfg(feature = "async")]
     use futures_test::task::noop_context;
 
+    /// A writer whose contents can be inspected while another writer wraps it.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuf {
+        fn contents(&self) -> Vec<u8> {
+            self.0.borrow().clone()
+        }
+    }
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl AsyncWrite for SharedBuf {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Ready(self.0.borrow_mut().write(buf))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// The number of complete armor lines that should have been written out after
+    /// flushing two and a half lines of data with the given policy.
+    fn lines_after_flush(policy: FlushPolicy) -> usize {
+        match policy {
+            FlushPolicy::Buffered => 0,
+            FlushPolicy::CompleteLines => 2,
+        }
+    }
+
+    fn assert_flushed_lines(flushed: &[u8], lines: usize) {
+        let flushed = std::str::from_utf8(flushed).unwrap();
+        assert!(flushed.ends_with('\n'));
+        // The begin marker, followed by the complete lines.
+        assert_eq!(flushed.lines().count(), 1 + lines);
+        assert!(flushed
+            .lines()
+            .skip(1)
+            .all(|line| line.len() == ARMORED_COLUMNS_PER_LINE));
+    }
+
+    #[test]
+    fn armored_flush_policy() {
+        let data: Vec<u8> = (0..ARMORED_BYTES_PER_LINE * 3).map(|i| i as u8).collect();
+
+        let mut expected = vec![];
+        {
+            let mut out =
+                ArmoredWriter::wrap_output(&mut expected, Format::AsciiArmorInterop).unwrap();
+            out.write_all(&data).unwrap();
+            out.finish().unwrap();
+        }
+
+        for policy in [FlushPolicy::Buffered, FlushPolicy::CompleteLines] {
+            let output = SharedBuf::default();
+            let mut out = ArmoredWriter::wrap_output(output.clone(), Format::AsciiArmorInterop)
+                .unwrap()
+                .with_flush_policy(policy);
+
+            let (first, rest) = data.split_at(ARMORED_BYTES_PER_LINE * 5 / 2);
+            out.write_all(first).unwrap();
+            out.flush().unwrap();
+            assert_flushed_lines(&output.contents(), lines_after_flush(policy));
+
+            // Flushing when the data ends on a line boundary, and then finishing,
+            // doesn't change the armor.
+            out.write_all(rest).unwrap();
+            out.flush().unwrap();
+            out.finish().unwrap();
+            assert_eq!(output.contents(), expected);
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn poll_write_and_flush(mut w: std::pin::Pin<&mut ArmoredWriter<SharedBuf>>, mut buf: &[u8]) {
+        let mut cx = noop_context();
+        while !buf.is_empty() {
+            match w.as_mut().poll_write(&mut cx, buf) {
+                Poll::Ready(Ok(written)) => buf = &buf[written..],
+                _ => panic!(),
+            }
+        }
+        assert!(matches!(w.poll_flush(&mut cx), Poll::Ready(Ok(()))));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn armored_async_flush_policy() {
+        let data: Vec<u8> = (0..ARMORED_BYTES_PER_LINE * 3).map(|i| i as u8).collect();
+
+        let mut expected = vec![];
+        {
+            let mut out =
+                ArmoredWriter::wrap_output(&mut expected, Format::AsciiArmorInterop).unwrap();
+            out.write_all(&data).unwrap();
+            out.finish().unwrap();
+        }
+
+        for policy in [FlushPolicy::Buffered, FlushPolicy::CompleteLines] {
+            let output = SharedBuf::default();
+            let w = ArmoredWriter::wrap_async_output(output.clone(), Format::AsciiArmorInterop)
+                .with_flush_policy(policy);
+            pin_mut!(w);
+
+            let (first, rest) = data.split_at(ARMORED_BYTES_PER_LINE * 5 / 2);
+            poll_write_and_flush(w.as_mut(), first);
+            assert_flushed_lines(&output.contents(), lines_after_flush(policy));
+
+            poll_write_and_flush(w.as_mut(), rest);
+            assert!(matches!(
+                w.as_mut().poll_close(&mut noop_context()),
+                Poll::Ready(Ok(()))
+            ));
+            assert_eq!(output.contents(), expected);
+        }
+    }
+
+    /// Checks that armored output matches the `stricttextualmsg` grammar of RFC 7468, and
+    /// that PEM from other encoders (which may use CRLF line endings) is accepted.
+    #[test]
+    fn armor_is_strict_pem() {
+        const LABEL: &str = "AGE ENCRYPTED FILE";
+
+        // RFC 7468 labels are printable characters other than hyphen-minus, with
+        // single spaces between words.
+        assert!(
+            LABEL
+                .split(' ')
+                .all(|word| !word.is_empty()
+                    && word.chars().all(|c| c.is_ascii_graphic() && c != '-'))
+        );
+        assert_eq!(
+            super::ARMORED_BEGIN_MARKER,
+            format!("-----BEGIN {}-----", LABEL)
+        );
+        assert_eq!(
+            super::ARMORED_END_MARKER,
+            format!("-----END {}-----", LABEL)
+        );
+
+        let is_base64 = |c: u8| c.is_ascii_alphanumeric() || c == b'+' || c == b'/';
+
+        for len in 1..ARMORED_BYTES_PER_LINE * 3 {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+            let mut encoded = vec![];
+            {
+                let mut out =
+                    ArmoredWriter::wrap_output(&mut encoded, Format::AsciiArmorInterop).unwrap();
+                out.write_all(&data).unwrap();
+                out.finish().unwrap();
+            }
+
+            let pem = std::str::from_utf8(&encoded).unwrap();
+            assert!(pem.ends_with('\n'));
+            let lines: Vec<_> = pem.lines().collect();
+            assert_eq!(lines[0], super::ARMORED_BEGIN_MARKER);
+            assert_eq!(lines[lines.len() - 1], super::ARMORED_END_MARKER);
+
+            let (last, full) = lines[1..lines.len() - 1].split_last().unwrap();
+            for line in full {
+                assert_eq!(line.len(), ARMORED_COLUMNS_PER_LINE);
+                assert!(line.bytes().all(is_base64));
+            }
+            assert!(last.len() <= ARMORED_COLUMNS_PER_LINE && last.len() % 4 == 0);
+            let unpadded = last.trim_end_matches('=');
+            assert!(last.len() - unpadded.len() <= 2);
+            assert!(unpadded.bytes().all(is_base64));
+
+            let crlf = pem.replace('\n', "\r\n");
+            let mut buf = vec![];
+            ArmoredReader::new(crlf.as_bytes())
+                .read_to_end(&mut buf)
+                .unwrap();
+            assert_eq!(buf, data);
+        }
+    }
+
+    /// Checks that [`Format::AsciiArmorInterop`] reproduces armored files generated by
+    /// the reference Go implementation.
+    #[test]
+    fn interop_matches_go() {
+        for vector in [
+            &include_bytes!("../../tests/testdata/testkit/armor")[..],
+            &include_bytes!("../../tests/testdata/testkit/armor_full_last_line")[..],
+        ] {
+            // Skip the test vector's headers.
+            let start = vector.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+            let armored = &vector[start..];
+
+            let mut binary = vec![];
+            ArmoredReader::new(armored)
+                .read_to_end(&mut binary)
+                .unwrap();
+
+            let mut encoded = vec![];
+            let mut w =
+                ArmoredWriter::wrap_output(&mut encoded, Format::AsciiArmorInterop).unwrap();
+            w.write_all(&binary).unwrap();
+            w.finish().unwrap();
+            assert_eq!(encoded, armored);
+
+            #[cfg(not(windows))]
+            {
+                let mut encoded = vec![];
+                let mut w = ArmoredWriter::wrap_output(&mut encoded, Format::AsciiArmor).unwrap();
+                w.write_all(&binary).unwrap();
+                w.finish().unwrap();
+                assert_eq!(encoded, armored);
+            }
+        }
+    }
+
+    #[test]
+    fn check_reports_first_violation() {
+        let mut encoded = vec![];
+        let mut w = ArmoredWriter::wrap_output(&mut encoded, Format::AsciiArmorInterop).unwrap();
+        w.write_all(&[42; 3 * ARMORED_BYTES_PER_LINE + 10]).unwrap();
+        w.finish().unwrap();
+        let encoded = String::from_utf8(encoded).unwrap();
+        assert!(check(encoded.as_bytes()).unwrap().is_none());
+
+        let violation = |armor: String| {
+            check(armor.as_bytes())
+                .unwrap()
+                .map(|v| (v.line(), v.column(), v.error().to_string()))
+                .unwrap()
+        };
+        let mut lines: Vec<_> = encoded.lines().map(|l| l.to_owned()).collect();
+        let error = |e: ArmoredReadError| e.to_string();
+
+        // A mail client re-wrapped the armor.
+        let mut joined = lines.clone();
+        let line = joined.remove(3);
+        joined[2].push_str(&line);
+        assert_eq!(
+            violation(joined.join("\n")),
+            (3, 65, error(ArmoredReadError::NotWrappedAt64Chars)),
+        );
+
+        // A padding character in the middle of a line.
+        let mut mangled = lines.clone();
+        mangled[2].replace_range(9..10, "=");
+        let (line, column, _) = violation(mangled.join("\n"));
+        assert_eq!((line, column), (3, 10));
+
+        // A line was lost from the middle.
+        let mut shortened = lines.clone();
+        shortened[2].truncate(60);
+        assert_eq!(
+            violation(shortened.join("\n")),
+            (3, 61, error(ArmoredReadError::ShortLineInMiddle)),
+        );
+
+        // Trailing garbage, such as a mail signature.
+        lines.push(String::new());
+        lines.push("-- ".to_owned());
+        assert_eq!(
+            violation(lines.join("\n")),
+            (8, 1, error(ArmoredReadError::TrailingGarbage)),
+        );
+
+        // The file was truncated.
+        lines.truncate(5);
+        assert_eq!(
+            violation(lines.join("\n")),
+            (6, 1, error(ArmoredReadError::MissingEndMarker)),
+        );
+    }
+
     #[test]
     fn armored_round_trip() {
         const MAX_LEN: usize = ARMORED_BYTES_PER_LINE * 50;
@@ -1275,6 +2106,163 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_detection_with_short_reads() {
+        use futures_test::io::AsyncReadTestExt;
+
+        let data: Vec<u8> = (0..ARMORED_BYTES_PER_LINE * 3).map(|i| i as u8).collect();
+        let mut armored = vec![];
+        {
+            let mut out = ArmoredWriter::wrap_output(&mut armored, Format::AsciiArmor).unwrap();
+            out.write_all(&data).unwrap();
+            out.finish().unwrap();
+        }
+
+        for encoded in [&armored[..], &data[..]] {
+            // The underlying reader returns one byte at a time, so armor detection needs
+            // to buffer across several reads.
+            let input = ArmoredReader::from_async_reader(encoded.limited(1));
+            pin_mut!(input);
+
+            let mut cx = noop_context();
+
+            let mut buf = vec![];
+            let mut tmp = [0; 4096];
+            loop {
+                match input.as_mut().poll_read(&mut cx, &mut tmp) {
+                    Poll::Ready(Ok(0)) => break,
+                    Poll::Ready(Ok(read)) => buf.extend_from_slice(&tmp[..read]),
+                    Poll::Ready(Err(e)) => panic!("Unexpected error: {}", e),
+                    Poll::Pending => panic!("Unexpected Pending"),
+                }
+            }
+
+            assert_eq!(buf, data);
+        }
+
+        // Input that ends before armor detection is an error, not a hang.
+        let input = ArmoredReader::from_async_reader(&data[..10]);
+        pin_mut!(input);
+        let mut tmp = [0; 4096];
+        assert!(matches!(
+            input.as_mut().poll_read(&mut noop_context(), &mut tmp),
+            Poll::Ready(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn surrounding_text() {
+        let data: Vec<u8> = (0..ARMORED_BYTES_PER_LINE * 3).map(|i| i as u8).collect();
+        let mut armored = vec![];
+        {
+            let mut out = ArmoredWriter::wrap_output(&mut armored, Format::AsciiArmor).unwrap();
+            out.write_all(&data).unwrap();
+            out.finish().unwrap();
+        }
+        let armored = String::from_utf8(armored).unwrap();
+        let with_prefix = |prefix: &str| -> String {
+            armored
+                .lines()
+                .map(|line| format!("{}{}\n", prefix, line))
+                .collect()
+        };
+
+        let read = |document: String| {
+            let mut buf = vec![];
+            ArmoredReader::new(document.as_bytes())
+                .ignore_surrounding_text()
+                .read_to_end(&mut buf)
+                .map(|_| buf)
+        };
+
+        // An email body.
+        let email = format!(
+            "Hi,\r\n\r\nHere is the file:\r\n\r\n{}\r\nThanks!\r\n",
+            armored
+        );
+        assert_eq!(read(email.clone()).unwrap(), data);
+        // A YAML file, with the armor indented.
+        let yaml = format!("name: test\nsecret: |\n{}other: value\n", with_prefix("  "));
+        assert_eq!(read(yaml.clone()).unwrap(), data);
+        // Seeking is relative to the data inside the armor.
+        let mut reader = ArmoredReader::new_buffered(Cursor::new(yaml)).ignore_surrounding_text();
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        reader.seek(SeekFrom::Start(100)).unwrap();
+        buf.clear();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, &data[100..]);
+        // A quoted reply.
+        let quoted = format!("> Here is the file:\n{}> Thanks!\n", with_prefix("> "));
+        assert_eq!(read(quoted).unwrap(), data);
+
+        // Without the option, the document is not detected as armored.
+        let mut buf = vec![];
+        ArmoredReader::new(email.as_bytes())
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, email.as_bytes());
+
+        // Every armor line must have the prefix of the begin marker.
+        let mut broken = with_prefix("  ");
+        broken.replace_range(
+            broken.find("\n  ").unwrap() + 1..broken.find("\n  ").unwrap() + 3,
+            "",
+        );
+        assert_eq!(
+            read(broken)
+                .unwrap_err()
+                .into_inner()
+                .map(|inner| inner.to_string()),
+            Some(ArmoredReadError::MissingLinePrefix.to_string())
+        );
+
+        // The begin marker must be present.
+        assert_eq!(
+            read("Hi,\nno file here.\n".into())
+                .unwrap_err()
+                .into_inner()
+                .map(|inner| inner.to_string()),
+            Some(ArmoredReadError::MissingBeginMarker.to_string())
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_surrounding_text() {
+        use futures_test::io::AsyncReadTestExt;
+
+        let data: Vec<u8> = (0..ARMORED_BYTES_PER_LINE * 3).map(|i| i as u8).collect();
+        let mut armored = vec![];
+        {
+            let mut out = ArmoredWriter::wrap_output(&mut armored, Format::AsciiArmor).unwrap();
+            out.write_all(&data).unwrap();
+            out.finish().unwrap();
+        }
+        let mut document = b"Here is the file:\n\n".to_vec();
+        document.extend_from_slice(&armored);
+        document.extend_from_slice(b"\nThanks!\n");
+
+        let input = ArmoredReader::from_async_reader(document.limited(7)).ignore_surrounding_text();
+        pin_mut!(input);
+
+        let mut cx = noop_context();
+
+        let mut buf = vec![];
+        let mut tmp = [0; 4096];
+        loop {
+            match input.as_mut().poll_read(&mut cx, &mut tmp) {
+                Poll::Ready(Ok(0)) => break,
+                Poll::Ready(Ok(read)) => buf.extend_from_slice(&tmp[..read]),
+                Poll::Ready(Err(e)) => panic!("Unexpected error: {}", e),
+                Poll::Pending => panic!("Unexpected Pending"),
+            }
+        }
+
+        assert_eq!(buf, data);
+    }
+
     #[test]
     fn binary_seeking() {
         let mut data = vec![0; 100 * 100];
@@ -1367,4 +2355,47 @@ mod tests {
         r.read_exact(&mut buf).unwrap();
         assert_eq!(&buf[..], &data[data.len() - 1337..data.len() - 1237]);
     }
+
+    #[test]
+    fn armored_seeking_uses_checkpoints() {
+        let data: Vec<u8> = (0..5 * CHECKPOINT_INTERVAL + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        let mut armored = vec![];
+        {
+            let mut w =
+                ArmoredWriter::wrap_output(&mut armored, Format::AsciiArmorInterop).unwrap();
+            w.write_all(&data).unwrap();
+            w.finish().unwrap();
+        };
+        let crlf = String::from_utf8(armored.clone())
+            .unwrap()
+            .replace('\n', "\r\n")
+            .into_bytes();
+
+        for armored in [armored, crlf] {
+            let mut r = ArmoredReader::new_buffered(Cursor::new(armored));
+
+            // Read to the end, recording checkpoints along the way.
+            let len = r.seek(SeekFrom::End(0)).unwrap();
+            assert_eq!(len, data.len() as u64);
+            assert_eq!(r.checkpoints.len(), 6);
+
+            let mut buf = vec![0; 100];
+            for &pos in &[
+                4 * CHECKPOINT_INTERVAL + 17,
+                CHECKPOINT_INTERVAL - 50,
+                0,
+                3 * CHECKPOINT_INTERVAL,
+                data.len() as u64 - 100,
+                2 * CHECKPOINT_INTERVAL + 999,
+            ] {
+                r.seek(SeekFrom::Start(pos)).unwrap();
+                r.read_exact(&mut buf).unwrap();
+                let pos = pos as usize;
+                assert_eq!(&buf[..], &data[pos..pos + 100]);
+            }
+        }
+    }
 }
@@ -1,13 +1,15 @@
 //! I/O helper structs for age file encryption and decryption.
 
-use age_core::secrecy::{ExposeSecret, SecretVec};
-use chacha20poly1305::{
-    aead::{generic_array::GenericArray, Aead, KeyInit, KeySizeUser},
-    ChaCha20Poly1305,
+use age_core::{
+    primitives::stream::{self as core_stream, CHUNK_SIZE, ENCRYPTED_CHUNK_SIZE, TAG_SIZE},
+    secret::SecretBuf,
 };
 use pin_project::pin_project;
 use std::cmp;
+use std::fmt;
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex};
 use zeroize::Zeroize;
 
 #[cfg(feature = "async")]
@@ -19,85 +21,43 @@ use futures::{
 #[cfg(feature = "async")]
 use std::pin::Pin;
 
-const CHUNK_SIZE: usize = 64 * 1024;
-const TAG_SIZE: usize = 16;
-const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
-
-pub(crate) struct PayloadKey(
-    pub(crate) GenericArray<u8, <ChaCha20Poly1305 as KeySizeUser>::KeySize>,
-);
-
-impl Drop for PayloadKey {
-    fn drop(&mut self) {
-        self.0.as_mut_slice().zeroize();
-    }
-}
-
-/// The nonce used in age's STREAM encryption.
+/// The key for the `STREAM` encryption of an age payload.
 ///
-/// Structured as an 11 bytes of big endian counter, and 1 byte of last block flag
-/// (`0x00 / 0x01`). We store this in the lower 12 bytes of a `u128`.
-#[derive(Clone, Copy, Default)]
-struct Nonce(u128);
-
-impl Nonce {
-    /// Unsets last-chunk flag.
-    fn set_counter(&mut self, val: u64) {
-        self.0 = u128::from(val) << 8;
-    }
-
-    fn increment_counter(&mut self) {
-        // Increment the 11-byte counter
-        self.0 += 1 << 8;
-        if self.0 >> (8 * 12) != 0 {
-            panic!("We overflowed the nonce!");
-        }
-    }
-
-    fn is_last(&self) -> bool {
-        self.0 & 1 != 0
-    }
-
-    fn set_last(&mut self, last: bool) -> Result<(), ()> {
-        if !self.is_last() {
-            self.0 |= if last { 1 } else { 0 };
-            Ok(())
-        } else {
-            Err(())
-        }
-    }
-
-    fn to_bytes(self) -> [u8; 12] {
-        self.0.to_be_bytes()[4..]
-            .try_into()
-            .expect("slice is correct length")
+/// The key is held in a [`SecretBuf`], so it is locked into memory with the `mlock`
+/// feature flag.
+pub(crate) struct PayloadKey(SecretBuf);
+
+impl From<[u8; 32]> for PayloadKey {
+    fn from(mut key: [u8; 32]) -> Self {
+        let payload_key = PayloadKey(SecretBuf::from_slice(&key));
+        key.zeroize();
+        payload_key
     }
 }
 
 #[cfg(feature = "async")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 struct EncryptedChunk {
-    bytes: Vec<u8>,
+    len: usize,
     offset: usize,
 }
 
 /// `STREAM[key](plaintext)`
 ///
-/// The [STREAM] construction for online authenticated encryption, instantiated with
-/// ChaCha20-Poly1305 in 64KiB chunks, and a nonce structure of 11 bytes of big endian
-/// counter, and 1 byte of last block flag (0x00 / 0x01).
-///
-/// [STREAM]: https://eprint.iacr.org/2015/189.pdf
+/// The chunks are encrypted and decrypted by [`core_stream::Stream`]; this wraps it
+/// in readers and writers, and reports its errors as I/O errors.
 pub(crate) struct Stream {
-    aead: ChaCha20Poly1305,
-    nonce: Nonce,
+    inner: core_stream::Stream,
 }
 
 impl Stream {
     fn new(key: PayloadKey) -> Self {
         Stream {
-            aead: ChaCha20Poly1305::new(&key.0),
-            nonce: Nonce::default(),
+            inner: core_stream::Stream::new(
+                key.0[..]
+                    .try_into()
+                    .expect("payload keys have the correct length"),
+            ),
         }
     }
 
@@ -112,7 +72,8 @@ impl Stream {
         StreamWriter {
             stream: Self::new(key),
             inner,
-            chunk: Vec::with_capacity(CHUNK_SIZE),
+            chunk: vec![0; ENCRYPTED_CHUNK_SIZE].into_boxed_slice(),
+            chunk_len: 0,
             #[cfg(feature = "async")]
             encrypted_chunk: None,
         }
@@ -131,7 +92,8 @@ impl Stream {
         StreamWriter {
             stream: Self::new(key),
             inner,
-            chunk: Vec::with_capacity(CHUNK_SIZE),
+            chunk: vec![0; ENCRYPTED_CHUNK_SIZE].into_boxed_slice(),
+            chunk_len: 0,
             encrypted_chunk: None,
         }
     }
@@ -147,12 +109,18 @@ impl Stream {
         StreamReader {
             stream: Self::new(key),
             inner,
-            encrypted_chunk: vec![0; ENCRYPTED_CHUNK_SIZE],
+            encrypted_chunk: ChunkBuffer::default(),
             encrypted_pos: 0,
             start: StartPos::Implicit(0),
             plaintext_len: None,
+            plaintext_len_hint: None,
+            max_plaintext_len: None,
             cur_plaintext_pos: 0,
+            ciphertext_pos: 0,
             chunk: None,
+            plaintext: None,
+            lenient: false,
+            skipped: vec![],
         }
     }
 
@@ -169,50 +137,107 @@ impl Stream {
         StreamReader {
             stream: Self::new(key),
             inner,
-            encrypted_chunk: vec![0; ENCRYPTED_CHUNK_SIZE],
+            encrypted_chunk: ChunkBuffer::default(),
             encrypted_pos: 0,
             start: StartPos::Implicit(0),
             plaintext_len: None,
+            plaintext_len_hint: None,
+            max_plaintext_len: None,
             cur_plaintext_pos: 0,
+            ciphertext_pos: 0,
             chunk: None,
+            plaintext: None,
+            lenient: false,
+            skipped: vec![],
         }
     }
 
-    fn encrypt_chunk(&mut self, chunk: &[u8], last: bool) -> io::Result<Vec<u8>> {
-        assert!(chunk.len() <= CHUNK_SIZE);
+    /// Encrypts the chunk in `buf[..len]` in place, appending the tag.
+    ///
+    /// `buf` must have room for the tag after the chunk. Returns the length of the
+    /// encrypted chunk.
+    fn encrypt_chunk(&mut self, buf: &mut [u8], len: usize, last: bool) -> io::Result<usize> {
+        self.inner
+            .encrypt_chunk(buf, len, last)
+            .map_err(|e| io::Error::new(io::ErrorKind::WriteZero, e.to_string()))
+    }
 
-        self.nonce.set_last(last).map_err(|_| {
-            io::Error::new(io::ErrorKind::WriteZero, "last chunk has been processed")
-        })?;
+    /// Decrypts the encrypted chunk in `buf[..len]` in place, returning the length of
+    /// the plaintext at the start of `buf`.
+    fn decrypt_chunk(&mut self, buf: &mut [u8], len: usize, last: bool) -> io::Result<usize> {
+        self.inner
+            .decrypt_chunk_in_place(buf, len, last)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
 
-        let encrypted = self
-            .aead
-            .encrypt(&self.nonce.to_bytes().into(), chunk)
-            .expect("we will never hit chacha20::MAX_BLOCKS because of the chunk size");
-        self.nonce.increment_counter();
+    /// Skips a chunk that failed to decrypt, so that the next chunk can be decrypted.
+    fn skip_chunk(&mut self) {
+        self.inner.skip_chunk()
+    }
 
-        Ok(encrypted)
+    fn is_complete(&self) -> bool {
+        self.inner.is_complete()
     }
+}
 
-    fn decrypt_chunk(&mut self, chunk: &[u8], last: bool) -> io::Result<SecretVec<u8>> {
-        assert!(chunk.len() <= ENCRYPTED_CHUNK_SIZE);
+/// A pool of buffers for encrypted chunks, shared between [`StreamReader`]s.
+#[derive(Default)]
+pub(crate) struct ChunkPool(Mutex<Vec<Vec<u8>>>);
 
-        self.nonce.set_last(last).map_err(|_| {
-            io::Error::new(io::ErrorKind::InvalidData, "last chunk has been processed")
-        })?;
+impl ChunkPool {
+    /// Returns the number of buffers in the pool.
+    #[cfg(all(test, feature = "service"))]
+    pub(crate) fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
 
-        let decrypted = self
-            .aead
-            .decrypt(&self.nonce.to_bytes().into(), chunk)
-            .map(SecretVec::new)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "decryption error"))?;
-        self.nonce.increment_counter();
+    fn take(&self) -> Option<Vec<u8>> {
+        self.0.lock().unwrap().pop()
+    }
 
-        Ok(decrypted)
+    fn put(&self, buf: Vec<u8>) {
+        self.0.lock().unwrap().push(buf);
     }
+}
 
-    fn is_complete(&self) -> bool {
-        self.nonce.is_last()
+/// The buffer that a [`StreamReader`] reads each encrypted chunk into.
+///
+/// The buffer is allocated (or taken from the pool) when the first chunk is read, and
+/// returned to the pool when dropped.
+#[derive(Default)]
+struct ChunkBuffer {
+    buf: Vec<u8>,
+    pool: Option<Arc<ChunkPool>>,
+}
+
+impl ChunkBuffer {
+    fn get_mut(&mut self) -> &mut [u8] {
+        if self.buf.is_empty() {
+            self.buf = self
+                .pool
+                .as_ref()
+                .and_then(|pool| pool.take())
+                .unwrap_or_else(|| vec![0; ENCRYPTED_CHUNK_SIZE]);
+        }
+        &mut self.buf
+    }
+}
+
+impl Deref for ChunkBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for ChunkBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            if !self.buf.is_empty() {
+                pool.put(std::mem::take(&mut self.buf));
+            }
+        }
     }
 }
 
@@ -222,21 +247,77 @@ pub struct StreamWriter<W> {
     stream: Stream,
     #[pin]
     inner: W,
-    chunk: Vec<u8>,
+    /// Buffer for the current chunk, with room to encrypt it in place.
+    chunk: Box<[u8]>,
+    /// The number of plaintext bytes buffered in `chunk`.
+    chunk_len: usize,
     #[cfg(feature = "async")]
     #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
     encrypted_chunk: Option<EncryptedChunk>,
 }
 
+impl<W> StreamWriter<W> {
+    /// Splits this writer into the wrapped writer and its position in the stream,
+    /// without finishing the stream.
+    ///
+    /// This allows a caller to take over the underlying transport, for example when
+    /// switching protocols. The stream is left incomplete: the final chunk has not been
+    /// written, and any plaintext that has been buffered for the current chunk is
+    /// discarded (it is not included in [`StreamPosition::plaintext`]). Use
+    /// [`StreamWriter::finish`] instead to complete the stream.
+    ///
+    /// If an asynchronous write of an encrypted chunk is in progress, the part of that
+    /// chunk that has not yet been written is also discarded.
+    ///
+    /// After [`StreamWriter::finish_async`] has completed, this returns the wrapped
+    /// writer of the finished stream, and [`StreamPosition::is_complete`] is `true`.
+    pub fn into_inner(self) -> (W, StreamPosition) {
+        let chunks = self.stream.inner.chunk_index();
+        let (plaintext, ciphertext) = if self.stream.is_complete() {
+            // The final chunk (which may be partial) was encrypted, and its plaintext
+            // length is left in `chunk_len`.
+            let full_chunks = chunks - 1;
+            (
+                full_chunks * CHUNK_SIZE as u64 + self.chunk_len as u64,
+                full_chunks * ENCRYPTED_CHUNK_SIZE as u64 + (self.chunk_len + TAG_SIZE) as u64,
+            )
+        } else {
+            (
+                chunks * CHUNK_SIZE as u64,
+                chunks * ENCRYPTED_CHUNK_SIZE as u64,
+            )
+        };
+        let is_complete = self.stream.is_complete();
+
+        #[cfg(feature = "async")]
+        let (ciphertext, is_complete) = match &self.encrypted_chunk {
+            Some(chunk) => (ciphertext - (chunk.len - chunk.offset) as u64, false),
+            None => (ciphertext, is_complete),
+        };
+
+        let position = StreamPosition {
+            plaintext,
+            ciphertext,
+            is_complete,
+        };
+        (self.inner, position)
+    }
+}
+
 impl<W: Write> StreamWriter<W> {
     /// Writes the final chunk of the age file.
     ///
     /// You **MUST** call `finish` when you are done writing, in order to finish the
     /// encryption process. Failing to call `finish` will result in a truncated file that
     /// that will fail to decrypt.
+    ///
+    /// If no data was written, this writes a single empty chunk, which is how an empty
+    /// plaintext is encrypted.
     pub fn finish(mut self) -> io::Result<W> {
-        let encrypted = self.stream.encrypt_chunk(&self.chunk, true)?;
-        self.inner.write_all(&encrypted)?;
+        let len = self
+            .stream
+            .encrypt_chunk(&mut self.chunk, self.chunk_len, true)?;
+        self.inner.write_all(&self.chunk[..len])?;
         Ok(self.inner)
     }
 }
@@ -246,20 +327,23 @@ impl<W: Write> Write for StreamWriter<W> {
         let mut bytes_written = 0;
 
         while !buf.is_empty() {
-            let to_write = cmp::min(CHUNK_SIZE - self.chunk.len(), buf.len());
-            self.chunk.extend_from_slice(&buf[..to_write]);
+            let to_write = cmp::min(CHUNK_SIZE - self.chunk_len, buf.len());
+            self.chunk[self.chunk_len..self.chunk_len + to_write].copy_from_slice(&buf[..to_write]);
+            self.chunk_len += to_write;
             bytes_written += to_write;
             buf = &buf[to_write..];
 
             // At this point, either buf is empty, or we have a full chunk.
-            assert!(buf.is_empty() || self.chunk.len() == CHUNK_SIZE);
+            assert!(buf.is_empty() || self.chunk_len == CHUNK_SIZE);
 
             // Only encrypt the chunk if we have more data to write, as the last
             // chunk must be written in finish().
             if !buf.is_empty() {
-                let encrypted = self.stream.encrypt_chunk(&self.chunk, false)?;
-                self.inner.write_all(&encrypted)?;
-                self.chunk.clear();
+                let len = self
+                    .stream
+                    .encrypt_chunk(&mut self.chunk, CHUNK_SIZE, false)?;
+                self.inner.write_all(&self.chunk[..len])?;
+                self.chunk_len = 0;
             }
         }
 
@@ -277,15 +361,17 @@ impl<W: AsyncWrite> StreamWriter<W> {
     fn poll_flush_chunk(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
         let StreamWriterProj {
             mut inner,
+            chunk,
             encrypted_chunk,
             ..
         } = self.project();
 
-        if let Some(chunk) = encrypted_chunk {
+        if let Some(encrypted) = encrypted_chunk {
             loop {
-                chunk.offset +=
-                    ready!(inner.as_mut().poll_write(cx, &chunk.bytes[chunk.offset..]))?;
-                if chunk.offset == chunk.bytes.len() {
+                encrypted.offset += ready!(inner
+                    .as_mut()
+                    .poll_write(cx, &chunk[encrypted.offset..encrypted.len]))?;
+                if encrypted.offset == encrypted.len {
                     break;
                 }
             }
@@ -294,6 +380,58 @@ impl<W: AsyncWrite> StreamWriter<W> {
 
         Poll::Ready(Ok(()))
     }
+
+    /// Attempts to write the final chunk of the age file, and flush the wrapped writer.
+    ///
+    /// Unlike [`AsyncWrite::poll_close`], this does not close the wrapped writer, so
+    /// that more data can be written to it after the age file (for example, further
+    /// frames of a protocol).
+    ///
+    /// This is resumable: if it returns [`Poll::Pending`], or the task calling it is
+    /// cancelled, calling it again continues from where it stopped, and the final chunk
+    /// is only encrypted once. Once it returns `Poll::Ready(Ok(()))`, the stream is
+    /// complete, and nothing more can be written to this `StreamWriter`.
+    pub fn poll_finish(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        // Flush any remaining encrypted chunk bytes.
+        ready!(self.as_mut().poll_flush_chunk(cx))?;
+
+        if !self.stream.is_complete() {
+            // Finish the stream. `chunk_len` is left as the final chunk's length, for
+            // `StreamWriter::into_inner`.
+            let this = self.as_mut().project();
+            *this.encrypted_chunk = Some(EncryptedChunk {
+                len: this
+                    .stream
+                    .encrypt_chunk(this.chunk, *this.chunk_len, true)?,
+                offset: 0,
+            });
+        }
+
+        // Flush the final chunk (if we didn't in the first call).
+        ready!(self.as_mut().poll_flush_chunk(cx))?;
+        self.project().inner.poll_flush(cx)
+    }
+
+    /// Writes the final chunk of the age file, and flushes the wrapped writer.
+    ///
+    /// This is the asynchronous equivalent of [`StreamWriter::finish`]. You **MUST**
+    /// either await this, or call [`AsyncWrite::poll_close`], when you are done
+    /// writing, in order to finish the encryption process. Dropping a `StreamWriter`
+    /// does neither: nothing is written on drop, and the file will be truncated and
+    /// fail to decrypt.
+    ///
+    /// The wrapped writer is not closed; use [`StreamWriter::into_inner`] afterwards to
+    /// take it back.
+    ///
+    /// This is cancel-safe: if the returned future is dropped before it completes, the
+    /// `StreamWriter` keeps track of how much of the final chunk has been written, and
+    /// awaiting `finish_async` again completes the stream.
+    pub async fn finish_async(&mut self) -> io::Result<()>
+    where
+        W: Unpin,
+    {
+        futures::future::poll_fn(|cx| Pin::new(&mut *self).poll_finish(cx)).await
+    }
 }
 
 #[cfg(feature = "async")]
@@ -306,26 +444,31 @@ impl<W: AsyncWrite> AsyncWrite for StreamWriter<W> {
     ) -> Poll<io::Result<usize>> {
         ready!(self.as_mut().poll_flush_chunk(cx))?;
 
-        let to_write = cmp::min(CHUNK_SIZE - self.chunk.len(), buf.len());
+        if self.stream.is_complete() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "StreamWriter has been finished",
+            )));
+        }
+
+        let this = self.as_mut().project();
+        let to_write = cmp::min(CHUNK_SIZE - *this.chunk_len, buf.len());
 
-        self.as_mut()
-            .project()
-            .chunk
-            .extend_from_slice(&buf[..to_write]);
+        this.chunk[*this.chunk_len..*this.chunk_len + to_write].copy_from_slice(&buf[..to_write]);
+        *this.chunk_len += to_write;
         buf = &buf[to_write..];
 
         // At this point, either buf is empty, or we have a full chunk.
-        assert!(buf.is_empty() || self.chunk.len() == CHUNK_SIZE);
+        assert!(buf.is_empty() || *this.chunk_len == CHUNK_SIZE);
 
         // Only encrypt the chunk if we have more data to write, as the last
         // chunk must be written in poll_close().
         if !buf.is_empty() {
-            let this = self.as_mut().project();
             *this.encrypted_chunk = Some(EncryptedChunk {
-                bytes: this.stream.encrypt_chunk(this.chunk, false)?,
+                len: this.stream.encrypt_chunk(this.chunk, CHUNK_SIZE, false)?,
                 offset: 0,
             });
-            this.chunk.clear();
+            *this.chunk_len = 0;
         }
 
         Poll::Ready(Ok(to_write))
@@ -336,22 +479,194 @@ impl<W: AsyncWrite> AsyncWrite for StreamWriter<W> {
         self.project().inner.poll_flush(cx)
     }
 
+    /// Finishes the stream with [`StreamWriter::poll_finish`], and then closes the
+    /// wrapped writer.
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        // Flush any remaining encrypted chunk bytes.
-        ready!(self.as_mut().poll_flush_chunk(cx))?;
+        ready!(self.as_mut().poll_finish(cx))?;
+        self.project().inner.poll_close(cx)
+    }
+}
 
-        if !self.stream.is_complete() {
-            // Finish the stream.
-            let this = self.as_mut().project();
-            *this.encrypted_chunk = Some(EncryptedChunk {
-                bytes: this.stream.encrypt_chunk(this.chunk, true)?,
-                offset: 0,
-            });
-        }
+/// Returns the number of chunks in a STREAM ciphertext of the given length.
+fn num_chunks(ct_len: u64) -> u64 {
+    // Use ceiling division to determine the number of chunks.
+    (ct_len + (ENCRYPTED_CHUNK_SIZE as u64 - 1)) / ENCRYPTED_CHUNK_SIZE as u64
+}
 
-        // Flush the final chunk (if we didn't in the first call).
-        ready!(self.as_mut().poll_flush_chunk(cx))?;
-        self.project().inner.poll_close(cx)
+/// Returns the index of the last chunk of a STREAM ciphertext with the given plaintext
+/// length.
+fn last_chunk_index(pt_len: u64) -> u64 {
+    // An empty plaintext is encrypted as a single empty chunk, and a plaintext that is
+    // an integer multiple of the chunk size ends with a full chunk.
+    pt_len.saturating_sub(1) / CHUNK_SIZE as u64
+}
+
+/// Returns the length of the plaintext for a STREAM ciphertext of the given length.
+///
+/// Returns `None` if the ciphertext is too short to contain even an empty chunk.
+pub(crate) fn plaintext_len(ct_len: u64) -> Option<u64> {
+    if ct_len < TAG_SIZE as u64 {
+        None
+    } else {
+        Some(ct_len - num_chunks(ct_len) * TAG_SIZE as u64)
+    }
+}
+
+/// Returns the number of chunks in a STREAM ciphertext of the given length.
+///
+/// Returns `None` if the ciphertext is too short to contain even an empty chunk.
+pub(crate) fn chunk_count(ct_len: u64) -> Option<u64> {
+    plaintext_len(ct_len).map(|_| num_chunks(ct_len))
+}
+
+/// Progress through the plaintext of an age file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    processed: u64,
+    total: Option<u64>,
+}
+
+impl Progress {
+    /// Returns the number of plaintext bytes that have been read.
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    /// Returns the total length of the plaintext, if known.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Returns the percentage of the plaintext that has been read, if the total length
+    /// is known.
+    ///
+    /// If the total length was estimated from an incorrect hint, this is clamped to at
+    /// most 100.
+    pub fn percentage(&self) -> Option<f64> {
+        self.total.map(|total| {
+            if total == 0 {
+                100.0
+            } else {
+                (self.processed as f64 * 100.0 / total as f64).min(100.0)
+            }
+        })
+    }
+}
+
+/// The error returned by a [`StreamReader`] when the plaintext is longer than the
+/// maximum set with [`RecipientsDecryptor::with_max_plaintext_len`].
+///
+/// This is returned inside an [`io::Error`] of kind [`io::ErrorKind::InvalidData`],
+/// from which it can be obtained with [`io::Error::get_ref`] and downcasting.
+///
+/// [`RecipientsDecryptor::with_max_plaintext_len`]: crate::decryptor::RecipientsDecryptor::with_max_plaintext_len
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaintextTooLong {
+    max: u64,
+}
+
+impl PlaintextTooLong {
+    /// Returns the maximum plaintext length that was exceeded.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+}
+
+impl fmt::Display for PlaintextTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            i18n_embed_fl::fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-stream-plaintext-too-long",
+                max = self.max,
+            )
+        )
+    }
+}
+
+impl std::error::Error for PlaintextTooLong {}
+
+/// The error returned by a [`StreamReader`] when the age file ends before its final
+/// chunk.
+///
+/// This is returned inside an [`io::Error`] of kind [`io::ErrorKind::UnexpectedEof`],
+/// from which it can be obtained with [`io::Error::get_ref`] and downcasting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Truncated {
+    plaintext: u64,
+    ciphertext: u64,
+}
+
+impl Truncated {
+    /// Returns the number of plaintext bytes before the truncation.
+    ///
+    /// Every chunk containing these bytes was authenticated, so they can be trusted to
+    /// be a prefix of the original plaintext.
+    pub fn plaintext(&self) -> u64 {
+        self.plaintext
+    }
+
+    /// Returns the offset into the payload at which the age file ends.
+    ///
+    /// This is counted from the start of the encrypted chunks, after the header and
+    /// payload nonce.
+    pub fn ciphertext(&self) -> u64 {
+        self.ciphertext
+    }
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            i18n_embed_fl::fl!(
+                crate::i18n::LANGUAGE_LOADER,
+                "err-stream-truncated",
+                plaintext = self.plaintext,
+                ciphertext = self.ciphertext,
+            )
+        )
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+/// The position of a [`StreamReader`] or [`StreamWriter`] when it was split into its
+/// parts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamPosition {
+    plaintext: u64,
+    ciphertext: u64,
+    is_complete: bool,
+}
+
+impl StreamPosition {
+    /// Returns the number of plaintext bytes that had been read (for a [`StreamReader`])
+    /// or encrypted (for a [`StreamWriter`]).
+    pub fn plaintext(&self) -> u64 {
+        self.plaintext
+    }
+
+    /// Returns the number of bytes of the payload that had been read from or written to
+    /// the wrapped reader or writer.
+    ///
+    /// This is counted from the start of the encrypted chunks, after the header and
+    /// payload nonce.
+    pub fn ciphertext(&self) -> u64 {
+        self.ciphertext
+    }
+
+    /// Returns `true` if the final chunk of the stream had been read.
+    ///
+    /// If the wrapped reader only contained the age file, it has then been read to the
+    /// end. For a [`StreamWriter`], this is only `true` once
+    /// [`StreamWriter::finish_async`] (or [`StreamWriter::poll_finish`]) has completed;
+    /// [`StreamWriter::finish`] consumes the writer instead.
+    pub fn is_complete(&self) -> bool {
+        self.is_complete
     }
 }
 
@@ -376,15 +691,121 @@ pub struct StreamReader<R> {
     stream: Stream,
     #[pin]
     inner: R,
-    encrypted_chunk: Vec<u8>,
+    encrypted_chunk: ChunkBuffer,
     encrypted_pos: usize,
     start: StartPos,
     plaintext_len: Option<u64>,
+    /// An estimate of the plaintext length, derived from a caller-provided ciphertext
+    /// length.
+    plaintext_len_hint: Option<u64>,
+    /// The maximum plaintext length that will be decrypted, if limited by the caller.
+    max_plaintext_len: Option<u64>,
     cur_plaintext_pos: u64,
-    chunk: Option<SecretVec<u8>>,
+    /// The number of bytes of the payload that have been read from `inner`.
+    ciphertext_pos: u64,
+    /// The length of the decrypted current chunk in `plaintext`, if there is one.
+    chunk: Option<usize>,
+    /// The buffer that chunks are decrypted into, which is allocated when the first chunk
+    /// is decrypted and then reused. It is zeroized when the reader is dropped, and
+    /// locked into memory with the `mlock` feature flag.
+    plaintext: Option<SecretBuf>,
+    /// Whether chunks that fail to authenticate are skipped instead of returning an error.
+    lenient: bool,
+    /// The plaintext ranges of the chunks that were skipped in lenient mode.
+    skipped: Vec<Range<u64>>,
 }
 
 impl<R> StreamReader<R> {
+    /// Takes the buffer for encrypted chunks from `pool`, and returns it to `pool` when
+    /// this reader is dropped.
+    #[cfg(feature = "service")]
+    pub(crate) fn with_chunk_pool(mut self, pool: Arc<ChunkPool>) -> Self {
+        self.encrypted_chunk.pool = Some(pool);
+        self
+    }
+
+    pub(crate) fn with_plaintext_len_hint(mut self, hint: Option<u64>) -> Self {
+        self.plaintext_len_hint = hint;
+        self
+    }
+
+    pub(crate) fn with_max_plaintext_len(mut self, max: Option<u64>) -> Self {
+        self.max_plaintext_len = max;
+        self
+    }
+
+    /// Splits this reader into the wrapped reader and its position in the stream.
+    ///
+    /// This allows a caller to take over the underlying transport after partially
+    /// decrypting the stream, for example when switching protocols. Chunks are read
+    /// from the wrapped reader in their entirety, so the wrapped reader is positioned
+    /// after the chunk containing the current plaintext position; any decrypted
+    /// plaintext from that chunk that has not been read is discarded.
+    pub fn into_inner(self) -> (R, StreamPosition) {
+        let position = StreamPosition {
+            plaintext: self.cur_plaintext_pos,
+            ciphertext: self.ciphertext_pos,
+            is_complete: self.stream.is_complete(),
+        };
+        (self.inner, position)
+    }
+
+    /// Returns how far through the plaintext this reader is.
+    ///
+    /// The total length is known if the decryptor was given the expected length of the
+    /// age file (for example, from an HTTP `Content-Length` header), or if it has been
+    /// computed by seeking within a seekable input.
+    pub fn progress(&self) -> Progress {
+        Progress {
+            processed: self.cur_plaintext_pos,
+            total: self.plaintext_len.or(self.plaintext_len_hint),
+        }
+    }
+
+    /// Enables lenient mode, for recovering what can be recovered from a corrupted age
+    /// file.
+    ///
+    /// By default, reading returns an error as soon as a chunk fails to authenticate. In
+    /// lenient mode, such a chunk is instead replaced with zeroes of the same length,
+    /// and reading continues with the next chunk. This preserves the offsets of the
+    /// recovered plaintext. [`StreamReader::skipped`] returns the ranges of plaintext
+    /// that were replaced.
+    ///
+    /// **The plaintext read in lenient mode is not authenticated as a whole.** Each
+    /// chunk that was not skipped is authentic and at its original position, but if any
+    /// chunk was skipped then the age file has been corrupted or tampered with, and
+    /// might also have been truncated (if its final chunk was skipped, the end of the
+    /// file cannot be verified). Lenient mode is only intended for forensic recovery;
+    /// never use its output as if it had been decrypted normally.
+    pub fn with_lenient_mode(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    /// Returns the ranges of plaintext that were replaced with zeroes in lenient mode,
+    /// because the chunks containing them failed to authenticate.
+    ///
+    /// If this is not empty, the plaintext that has been read is **not authenticated**.
+    /// See [`StreamReader::with_lenient_mode`] for details.
+    pub fn skipped(&self) -> &[Range<u64>] {
+        &self.skipped
+    }
+
+    /// Returns `true` if the final chunk of the stream has been read and authenticated.
+    ///
+    /// Once this returns `true`, the age file is known not to be truncated, and the
+    /// plaintext that has been and will be read from this reader is complete. Until
+    /// then, any plaintext that has been read is authentic, but might be followed by a
+    /// [`Truncated`] error.
+    pub fn is_complete(&self) -> bool {
+        self.stream.is_complete()
+    }
+
+    /// Returns the plaintext offset of the start of the current chunk.
+    fn chunk_start(&self) -> u64 {
+        self.cur_plaintext_pos - self.cur_plaintext_pos % CHUNK_SIZE as u64
+    }
+
     fn count_bytes(&mut self, read: usize) {
         // We only need to count if we haven't yet worked out the start position.
         if let StartPos::Implicit(offset) = &mut self.start {
@@ -397,33 +818,89 @@ impl<R> StreamReader<R> {
         let chunk = &self.encrypted_chunk[..self.encrypted_pos];
 
         if chunk.is_empty() {
-            if !self.stream.is_complete() {
-                // Stream has ended before seeing the last chunk.
+            // In lenient mode, the skipped chunk might have been the last chunk.
+            let skipped_last = self.lenient
+                && self
+                    .skipped
+                    .last()
+                    .map_or(false, |r| r.end == self.cur_plaintext_pos);
+            if !self.stream.is_complete() && !skipped_last {
+                // Stream has ended before seeing the last chunk. Every chunk before this
+                // point was authenticated, so all plaintext up to here is trustworthy.
                 return Err(io::Error::new(
                     io::ErrorKind::UnexpectedEof,
-                    "age file is truncated",
+                    Truncated {
+                        plaintext: self.cur_plaintext_pos,
+                        ciphertext: self.ciphertext_pos,
+                    },
                 ));
             }
         } else {
-            // This check works for all cases except when the age file is an integer
-            // multiple of the chunk size. In that case, we try decrypting twice on a
-            // decryption failure.
-            let last = chunk.len() < ENCRYPTED_CHUNK_SIZE;
-
-            self.chunk = match (self.stream.decrypt_chunk(chunk, last), last) {
-                (Ok(chunk), _)
-                    if chunk.expose_secret().is_empty() && self.cur_plaintext_pos > 0 =>
-                {
+            // Once the plaintext length has been authenticated (by seeking relative to the
+            // end), we know which chunk is the last chunk, and only decrypt it as such.
+            let last_chunk_index = self.plaintext_len.map(last_chunk_index);
+            let expected_last =
+                last_chunk_index.map(|index| self.stream.inner.chunk_index() == index);
+
+            // Otherwise, this check works for all cases except when the age file is an
+            // integer multiple of the chunk size. In that case, we try decrypting twice
+            // on a decryption failure.
+            let last = expected_last.unwrap_or(chunk.len() < ENCRYPTED_CHUNK_SIZE);
+
+            let plaintext = self
+                .plaintext
+                .get_or_insert_with(|| SecretBuf::new(ENCRYPTED_CHUNK_SIZE));
+            let stream = &mut self.stream;
+            let mut decrypt = |last| {
+                plaintext[..chunk.len()].copy_from_slice(chunk);
+                stream.decrypt_chunk(&mut plaintext[..], chunk.len(), last)
+            };
+
+            let decrypted = match (decrypt(last), last) {
+                (Ok(0), _) if self.cur_plaintext_pos > 0 => {
                     assert!(last);
                     return Err(io::Error::new(
                         io::ErrorKind::InvalidData,
                         crate::fl!("err-stream-last-chunk-empty"),
                     ));
                 }
-                (Ok(chunk), _) => Some(chunk),
-                (Err(_), false) => Some(self.stream.decrypt_chunk(chunk, true)?),
-                (Err(e), true) => return Err(e),
+                (Ok(len), _) => Ok(len),
+                (Err(_), false) if expected_last.is_none() => decrypt(true),
+                (Err(e), _) => Err(e),
             };
+
+            self.chunk = Some(match decrypted {
+                Ok(len) => len,
+                Err(_) if self.lenient => {
+                    let start = self.chunk_start();
+                    let len = chunk.len().saturating_sub(TAG_SIZE);
+                    self.stream.skip_chunk();
+                    // We might decrypt the same chunk again after seeking.
+                    if !self.skipped.iter().any(|r| r.start == start) {
+                        self.skipped.push(start..start + len as u64);
+                    }
+                    // The plaintext of a skipped chunk reads as zeroes.
+                    if let Some(plaintext) = &mut self.plaintext {
+                        plaintext[..len].fill(0);
+                    }
+                    len
+                }
+                Err(e) => return Err(e),
+            });
+
+            if let Some(max) = self.max_plaintext_len {
+                let chunk_start = self.chunk_start();
+                let chunk_len = self.chunk.unwrap_or(0);
+                if chunk_start + chunk_len as u64 > max {
+                    // Drop the chunk, so none of the plaintext beyond the limit is
+                    // returned.
+                    self.chunk = None;
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        PlaintextTooLong { max },
+                    ));
+                }
+            }
         }
 
         // We've finished with this encrypted chunk.
@@ -433,17 +910,15 @@ impl<R> StreamReader<R> {
     }
 
     fn read_from_chunk(&mut self, buf: &mut [u8]) -> usize {
-        if self.chunk.is_none() {
-            return 0;
-        }
-
-        let chunk = self.chunk.as_ref().unwrap();
+        let chunk = match (self.chunk, &self.plaintext) {
+            (Some(len), Some(plaintext)) => &plaintext[..len],
+            _ => return 0,
+        };
         let cur_chunk_offset = self.cur_plaintext_pos as usize % CHUNK_SIZE;
 
-        let to_read = cmp::min(chunk.expose_secret().len() - cur_chunk_offset, buf.len());
+        let to_read = cmp::min(chunk.len() - cur_chunk_offset, buf.len());
 
-        buf[..to_read]
-            .copy_from_slice(&chunk.expose_secret()[cur_chunk_offset..cur_chunk_offset + to_read]);
+        buf[..to_read].copy_from_slice(&chunk[cur_chunk_offset..cur_chunk_offset + to_read]);
         self.cur_plaintext_pos += to_read as u64;
         if self.cur_plaintext_pos % CHUNK_SIZE as u64 == 0 {
             // We've finished with the current chunk.
@@ -454,23 +929,52 @@ impl<R> StreamReader<R> {
     }
 }
 
+impl<R: Read> StreamReader<R> {
+    /// Reads and decrypts the next chunk.
+    fn read_chunk(&mut self) -> io::Result<()> {
+        while self.encrypted_pos < ENCRYPTED_CHUNK_SIZE {
+            match self
+                .inner
+                .read(&mut self.encrypted_chunk.get_mut()[self.encrypted_pos..])
+            {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.encrypted_pos += n;
+                    self.ciphertext_pos += n as u64;
+                }
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => (),
+                    _ => return Err(e),
+                },
+            }
+        }
+        self.decrypt_chunk()
+    }
+
+    /// Returns `true` if the plaintext is empty.
+    ///
+    /// An empty plaintext is encrypted as a single empty final chunk, so this only needs
+    /// to decrypt the first chunk, rather than the entire payload. The decrypted chunk is
+    /// buffered, so this does not affect subsequent reads.
+    ///
+    /// Returns `false` if any plaintext has already been read.
+    pub fn is_empty(&mut self) -> io::Result<bool> {
+        if self.cur_plaintext_pos > 0 {
+            return Ok(false);
+        }
+        if self.chunk.is_none() {
+            self.read_chunk()?;
+        }
+
+        // If there is still no chunk, we previously reached the end of an empty stream.
+        Ok(self.chunk.map_or(true, |len| len == 0))
+    }
+}
+
 impl<R: Read> Read for StreamReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if self.chunk.is_none() {
-            while self.encrypted_pos < ENCRYPTED_CHUNK_SIZE {
-                match self
-                    .inner
-                    .read(&mut self.encrypted_chunk[self.encrypted_pos..])
-                {
-                    Ok(0) => break,
-                    Ok(n) => self.encrypted_pos += n,
-                    Err(e) => match e.kind() {
-                        io::ErrorKind::Interrupted => (),
-                        _ => return Err(e),
-                    },
-                }
-            }
-            self.decrypt_chunk()?;
+            self.read_chunk()?;
         }
 
         Ok(self.read_from_chunk(buf))
@@ -488,12 +992,15 @@ impl<R: AsyncRead + Unpin> AsyncRead for StreamReader<R> {
         if self.chunk.is_none() {
             while self.encrypted_pos < ENCRYPTED_CHUNK_SIZE {
                 let this = self.as_mut().project();
-                match ready!(this
-                    .inner
-                    .poll_read(cx, &mut this.encrypted_chunk[*this.encrypted_pos..]))
-                {
+                match ready!(this.inner.poll_read(
+                    cx,
+                    &mut this.encrypted_chunk.get_mut()[*this.encrypted_pos..]
+                )) {
                     Ok(0) => break,
-                    Ok(n) => self.encrypted_pos += n,
+                    Ok(n) => {
+                        self.encrypted_pos += n;
+                        self.ciphertext_pos += n as u64;
+                    }
                     Err(e) => match e.kind() {
                         io::ErrorKind::Interrupted => (),
                         _ => return Poll::Ready(Err(e)),
@@ -527,42 +1034,23 @@ impl<R: Read + Seek> StreamReader<R> {
     fn len(&mut self) -> io::Result<u64> {
         match self.plaintext_len {
             None => {
-                // Cache the current position and nonce, and then grab the start and end
-                // ciphertext positions.
+                // Cache the current position and chunk.
                 let cur_pos = self.inner.seek(SeekFrom::Current(0))?;
-                let cur_nonce = self.stream.nonce.0;
-                let ct_start = self.start()?;
-                let ct_end = self.inner.seek(SeekFrom::End(0))?;
-                let ct_len = ct_end - ct_start;
-
-                // Use ceiling division to determine the number of chunks.
-                let num_chunks =
-                    (ct_len + (ENCRYPTED_CHUNK_SIZE as u64 - 1)) / ENCRYPTED_CHUNK_SIZE as u64;
-
-                // Authenticate the ciphertext length by checking that we can successfully
-                // decrypt the last chunk _as_ a last chunk.
-                let last_chunk_start = ct_start + ((num_chunks - 1) * ENCRYPTED_CHUNK_SIZE as u64);
-                let mut last_chunk = Vec::with_capacity((ct_end - last_chunk_start) as usize);
-                self.inner.seek(SeekFrom::Start(last_chunk_start))?;
-                self.inner.read_to_end(&mut last_chunk)?;
-                self.stream.nonce.set_counter(num_chunks - 1);
-                self.stream.decrypt_chunk(&last_chunk, true).map_err(|_| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Last chunk is invalid, stream might be truncated",
-                    )
-                })?;
+                let cur_chunk = self.stream.inner.chunk_index();
+                let cur_complete = self.stream.is_complete();
 
-                // Now that we have authenticated the ciphertext length, we can use it to
-                // calculate the plaintext length.
-                let total_tag_size = num_chunks * TAG_SIZE as u64;
-                let pt_len = ct_len - total_tag_size;
+                let res = self.authenticate_len();
 
-                // Return to the original position and restore the nonce.
+                // Return to the original position and restore the chunk, whether or not
+                // the last chunk was valid, so that the reader can still be used.
                 self.inner.seek(SeekFrom::Start(cur_pos))?;
-                self.stream.nonce = Nonce(cur_nonce);
+                self.stream.inner.seek_to_chunk(cur_chunk);
+                if cur_complete {
+                    self.stream.inner.mark_complete();
+                }
 
                 // Cache the length for future calls.
+                let pt_len = res?;
                 self.plaintext_len = Some(pt_len);
 
                 Ok(pt_len)
@@ -570,6 +1058,51 @@ impl<R: Read + Seek> StreamReader<R> {
             Some(pt_len) => Ok(pt_len),
         }
     }
+
+    /// Computes the length of the plaintext from the length of the ciphertext, and
+    /// authenticates it by decrypting the last chunk.
+    ///
+    /// This moves the reader and the stream to the end; the caller must restore them.
+    fn authenticate_len(&mut self) -> io::Result<u64> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Last chunk is invalid, stream might be truncated",
+            )
+        };
+
+        let ct_start = self.start()?;
+        let ct_end = self.inner.seek(SeekFrom::End(0))?;
+        let ct_len = ct_end.checked_sub(ct_start).ok_or_else(invalid)?;
+        let pt_len = plaintext_len(ct_len).ok_or_else(invalid)?;
+
+        // Authenticate the ciphertext length by checking that we can successfully
+        // decrypt the last chunk _as_ a last chunk.
+        let last_chunk_index = num_chunks(ct_len) - 1;
+        let last_chunk_start = ct_start + last_chunk_index * ENCRYPTED_CHUNK_SIZE as u64;
+        let mut last_chunk = SecretBuf::new((ct_end - last_chunk_start) as usize);
+        self.inner.seek(SeekFrom::Start(last_chunk_start))?;
+        self.inner.read_exact(&mut last_chunk)?;
+        self.stream.inner.seek_to_chunk(last_chunk_index);
+        let len = last_chunk.len();
+        let decrypted_len = self
+            .stream
+            .decrypt_chunk(&mut last_chunk, len, true)
+            .map_err(|_| invalid())?;
+
+        // Only an empty plaintext has an empty last chunk, as when reading in order. This
+        // also ensures that the last chunk is the one found by `last_chunk_index`.
+        if decrypted_len == 0 && last_chunk_index > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                crate::fl!("err-stream-last-chunk-empty"),
+            ));
+        }
+
+        // Now that we have authenticated the ciphertext length, we can use it as the
+        // plaintext length.
+        Ok(pt_len)
+    }
 }
 
 impl<R: Read + Seek> Seek for StreamReader<R> {
@@ -618,8 +1151,9 @@ impl<R: Read + Seek> Seek for StreamReader<R> {
             self.inner.seek(SeekFrom::Start(
                 start + (target_chunk_index * ENCRYPTED_CHUNK_SIZE as u64),
             ))?;
-            self.stream.nonce.set_counter(target_chunk_index);
+            self.stream.inner.seek_to_chunk(target_chunk_index);
             self.cur_plaintext_pos = target_chunk_index * CHUNK_SIZE as u64;
+            self.ciphertext_pos = target_chunk_index * ENCRYPTED_CHUNK_SIZE as u64;
 
             // Read and drop bytes from the chunk to reach the target position.
             if target_chunk_offset > 0 {
@@ -636,10 +1170,8 @@ impl<R: Read + Seek> Seek for StreamReader<R> {
             // size (i.e. this conditional branch), we compute the length of the
             // plaintext. This is cached, so the overhead should be minimal.
             else if target_pos == self.len()? {
-                self.stream
-                    .nonce
-                    .set_last(true)
-                    .expect("We unset the last chunk flag earlier");
+                // We unset the last chunk flag earlier.
+                self.stream.inner.mark_complete();
             }
         }
 
@@ -650,10 +1182,9 @@ impl<R: Read + Seek> Seek for StreamReader<R> {
 
 #[cfg(test)]
 mod tests {
-    use age_core::secrecy::ExposeSecret;
     use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 
-    use super::{PayloadKey, Stream, CHUNK_SIZE};
+    use super::{PayloadKey, Stream, Truncated, CHUNK_SIZE, ENCRYPTED_CHUNK_SIZE, TAG_SIZE};
 
     #[cfg(feature = "async")]
     use futures::{
@@ -664,21 +1195,137 @@ mod tests {
     #[cfg(feature = "async")]
     use futures_test::task::noop_context;
 
+    fn encrypt_chunk(s: &mut Stream, data: &[u8], last: bool) -> io::Result<Vec<u8>> {
+        let mut buf = data.to_vec();
+        buf.resize(data.len() + TAG_SIZE, 0);
+        s.encrypt_chunk(&mut buf, data.len(), last).map(|len| {
+            buf.truncate(len);
+            buf
+        })
+    }
+
+    fn decrypt_chunk(s: &mut Stream, encrypted: &[u8], last: bool) -> io::Result<Vec<u8>> {
+        let mut buf = encrypted.to_vec();
+        s.decrypt_chunk(&mut buf, encrypted.len(), last).map(|len| {
+            buf.truncate(len);
+            buf
+        })
+    }
+
+    /// The ChaCha20 implementation used to encrypt and decrypt age payloads.
+    ///
+    /// Only some of these are possible on each target, and the tests below only check
+    /// the backend on some targets.
+    #[allow(dead_code)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum AeadBackend {
+        /// The portable software implementation.
+        Soft,
+        /// The SSE2 implementation (x86 and x86-64).
+        Sse2,
+        /// The AVX2 implementation (x86 and x86-64).
+        Avx2,
+        /// The NEON implementation (AArch64).
+        Neon,
+    }
+
+    /// Returns the ChaCha20 backend that age payloads are encrypted and decrypted with on
+    /// this machine.
+    ///
+    /// The [`chacha20`] crate does not report which backend it selected, so this is a
+    /// best-effort answer: it mirrors the selection logic of `chacha20` 0.9, including the
+    /// `chacha20_force_*` configuration flags, and may be wrong for other versions.
+    ///
+    /// [`chacha20`]: https://docs.rs/chacha20
+    #[allow(dead_code, unknown_lints, unexpected_cfgs)]
+    fn aead_backend() -> AeadBackend {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if cfg!(chacha20_force_soft) {
+                AeadBackend::Soft
+            } else if cfg!(chacha20_force_avx2) {
+                AeadBackend::Avx2
+            } else if cfg!(chacha20_force_sse2) {
+                AeadBackend::Sse2
+            } else if is_x86_feature_detected!("avx2") {
+                AeadBackend::Avx2
+            } else if is_x86_feature_detected!("sse2") {
+                AeadBackend::Sse2
+            } else {
+                AeadBackend::Soft
+            }
+        }
+
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        {
+            // The NEON backend is only used when explicitly enabled.
+            if cfg!(all(
+                not(chacha20_force_soft),
+                chacha20_force_neon,
+                target_arch = "aarch64",
+                target_feature = "neon"
+            )) {
+                AeadBackend::Neon
+            } else {
+                AeadBackend::Soft
+            }
+        }
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(chacha20_force_soft)))]
+    #[allow(unknown_lints, unexpected_cfgs)]
+    #[test]
+    fn aead_backend_uses_simd() {
+        // SSE2 is part of the x86-64 baseline, so we never need the software backend.
+        assert_ne!(aead_backend(), AeadBackend::Soft);
+    }
+
+    #[cfg(chacha20_force_soft)]
+    #[allow(unknown_lints, unexpected_cfgs)]
+    #[test]
+    fn aead_backend_can_be_forced_to_soft() {
+        assert_eq!(aead_backend(), AeadBackend::Soft);
+    }
+
+    #[test]
+    fn plaintext_len() {
+        for &pt_len in &[
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+        ] {
+            let mut encrypted = vec![];
+            {
+                let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
+                w.write_all(&vec![42; pt_len]).unwrap();
+                w.finish().unwrap();
+            };
+            assert_eq!(
+                super::plaintext_len(encrypted.len() as u64),
+                Some(pt_len as u64)
+            );
+        }
+        assert_eq!(super::plaintext_len(TAG_SIZE as u64 - 1), None);
+    }
+
     #[test]
     fn chunk_round_trip() {
         let data = vec![42; CHUNK_SIZE];
 
         let encrypted = {
-            let mut s = Stream::new(PayloadKey([7; 32].into()));
-            s.encrypt_chunk(&data, false).unwrap()
+            let mut s = Stream::new(PayloadKey::from([7; 32]));
+            encrypt_chunk(&mut s, &data, false).unwrap()
         };
 
         let decrypted = {
-            let mut s = Stream::new(PayloadKey([7; 32].into()));
-            s.decrypt_chunk(&encrypted, false).unwrap()
+            let mut s = Stream::new(PayloadKey::from([7; 32]));
+            decrypt_chunk(&mut s, &encrypted, false).unwrap()
         };
 
-        assert_eq!(decrypted.expose_secret(), &data);
+        assert_eq!(decrypted, data);
     }
 
     #[test]
@@ -686,16 +1333,16 @@ mod tests {
         let data = vec![42; CHUNK_SIZE];
 
         let encrypted = {
-            let mut s = Stream::new(PayloadKey([7; 32].into()));
-            let res = s.encrypt_chunk(&data, true).unwrap();
+            let mut s = Stream::new(PayloadKey::from([7; 32]));
+            let res = encrypt_chunk(&mut s, &data, true).unwrap();
 
             // Further calls return an error
             assert_eq!(
-                s.encrypt_chunk(&data, false).unwrap_err().kind(),
+                encrypt_chunk(&mut s, &data, false).unwrap_err().kind(),
                 io::ErrorKind::WriteZero
             );
             assert_eq!(
-                s.encrypt_chunk(&data, true).unwrap_err().kind(),
+                encrypt_chunk(&mut s, &data, true).unwrap_err().kind(),
                 io::ErrorKind::WriteZero
             );
 
@@ -703,15 +1350,15 @@ mod tests {
         };
 
         let decrypted = {
-            let mut s = Stream::new(PayloadKey([7; 32].into()));
-            let res = s.decrypt_chunk(&encrypted, true).unwrap();
+            let mut s = Stream::new(PayloadKey::from([7; 32]));
+            let res = decrypt_chunk(&mut s, &encrypted, true).unwrap();
 
             // Further calls return an error
-            match s.decrypt_chunk(&encrypted, false) {
+            match decrypt_chunk(&mut s, &encrypted, false) {
                 Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
                 _ => panic!("Expected error"),
             }
-            match s.decrypt_chunk(&encrypted, true) {
+            match decrypt_chunk(&mut s, &encrypted, true) {
                 Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
                 _ => panic!("Expected error"),
             }
@@ -719,21 +1366,23 @@ mod tests {
             res
         };
 
-        assert_eq!(decrypted.expose_secret(), &data);
+        assert_eq!(decrypted, data);
     }
 
     fn stream_round_trip(data: &[u8]) {
         let mut encrypted = vec![];
         {
-            let mut w = Stream::encrypt(PayloadKey([7; 32].into()), &mut encrypted);
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
             w.write_all(data).unwrap();
             w.finish().unwrap();
         };
 
         let decrypted = {
             let mut buf = vec![];
-            let mut r = Stream::decrypt(PayloadKey([7; 32].into()), &encrypted[..]);
+            let mut r = Stream::decrypt(PayloadKey::from([7; 32]), &encrypted[..]);
+            assert!(!r.is_complete());
             r.read_to_end(&mut buf).unwrap();
+            assert!(r.is_complete());
             buf
         };
 
@@ -748,6 +1397,98 @@ mod tests {
         stream_round_trip(&[]);
     }
 
+    #[test]
+    fn stream_is_empty() {
+        for &pt_len in &[0, 1, CHUNK_SIZE, 2 * CHUNK_SIZE] {
+            let data = vec![42; pt_len];
+            let mut encrypted = vec![];
+            {
+                let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
+                w.write_all(&data).unwrap();
+                w.finish().unwrap();
+            };
+
+            let mut r = Stream::decrypt(PayloadKey::from([7; 32]), &encrypted[..]);
+            assert_eq!(r.is_empty().unwrap(), pt_len == 0);
+
+            // Checking does not consume any plaintext.
+            let mut buf = vec![];
+            r.read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, data);
+            assert_eq!(r.is_empty().unwrap(), pt_len == 0);
+        }
+    }
+
+    #[test]
+    fn stream_is_empty_rejects_truncated_file() {
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), &[][..]);
+        assert_eq!(
+            r.is_empty().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn stream_writer_into_inner() {
+        let data = vec![42; CHUNK_SIZE + 5];
+
+        let mut w = Stream::encrypt(PayloadKey::from([7; 32]), vec![]);
+        w.write_all(&data[..5]).unwrap();
+        let (encrypted, position) = w.into_inner();
+        assert!(encrypted.is_empty());
+        assert_eq!(position.plaintext(), 0);
+        assert_eq!(position.ciphertext(), 0);
+        assert!(!position.is_complete());
+
+        let mut w = Stream::encrypt(PayloadKey::from([7; 32]), vec![]);
+        w.write_all(&data).unwrap();
+        let (encrypted, position) = w.into_inner();
+        assert_eq!(encrypted.len(), ENCRYPTED_CHUNK_SIZE);
+        assert_eq!(position.plaintext(), CHUNK_SIZE as u64);
+        assert_eq!(position.ciphertext(), ENCRYPTED_CHUNK_SIZE as u64);
+        assert!(!position.is_complete());
+    }
+
+    #[test]
+    fn stream_reader_into_inner() {
+        let data = vec![42; CHUNK_SIZE + 5];
+        let mut encrypted = vec![];
+        {
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
+            w.write_all(&data).unwrap();
+            w.finish().unwrap();
+        };
+
+        // Splitting after a partial read leaves the reader after the current chunk.
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), &encrypted[..]);
+        let mut buf = [0; 10];
+        r.read_exact(&mut buf).unwrap();
+        let (rest, position) = r.into_inner();
+        assert_eq!(rest, &encrypted[ENCRYPTED_CHUNK_SIZE..]);
+        assert_eq!(position.plaintext(), 10);
+        assert_eq!(position.ciphertext(), ENCRYPTED_CHUNK_SIZE as u64);
+        assert!(!position.is_complete());
+
+        // Splitting after reading the final chunk leaves the reader at the end.
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), &encrypted[..]);
+        let mut buf = vec![];
+        r.read_to_end(&mut buf).unwrap();
+        let (rest, position) = r.into_inner();
+        assert!(rest.is_empty());
+        assert_eq!(position.plaintext(), data.len() as u64);
+        assert_eq!(position.ciphertext(), encrypted.len() as u64);
+        assert!(position.is_complete());
+
+        // Seeking updates the position.
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(&encrypted));
+        r.seek(SeekFrom::Start(CHUNK_SIZE as u64 + 1)).unwrap();
+        let (inner, position) = r.into_inner();
+        assert_eq!(inner.position(), encrypted.len() as u64);
+        assert_eq!(position.plaintext(), CHUNK_SIZE as u64 + 1);
+        assert_eq!(position.ciphertext(), encrypted.len() as u64);
+        assert!(position.is_complete());
+    }
+
     #[test]
     fn stream_round_trip_short() {
         stream_round_trip(&[42; 1024]);
@@ -767,7 +1508,7 @@ mod tests {
     fn stream_async_round_trip(data: &[u8]) {
         let mut encrypted = vec![];
         {
-            let w = Stream::encrypt_async(PayloadKey([7; 32].into()), &mut encrypted);
+            let w = Stream::encrypt_async(PayloadKey::from([7; 32]), &mut encrypted);
             pin_mut!(w);
 
             let mut cx = noop_context();
@@ -792,7 +1533,7 @@ mod tests {
 
         let decrypted = {
             let mut buf = vec![];
-            let r = Stream::decrypt_async(PayloadKey([7; 32].into()), &encrypted[..]);
+            let r = Stream::decrypt_async(PayloadKey::from([7; 32]), &encrypted[..]);
             pin_mut!(r);
 
             let mut cx = noop_context();
@@ -811,6 +1552,12 @@ mod tests {
         assert_eq!(decrypted, data);
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn stream_async_round_trip_empty() {
+        stream_async_round_trip(&[]);
+    }
+
     #[cfg(feature = "async")]
     #[test]
     fn stream_async_round_trip_short() {
@@ -829,23 +1576,136 @@ mod tests {
         stream_async_round_trip(&[42; 100 * 1024]);
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn stream_async_finish_is_resumable() {
+        use futures::Future;
+        use futures_test::io::AsyncWriteTestExt;
+        use std::pin::Pin;
+
+        let data = vec![42; CHUNK_SIZE + 5];
+        let mut cx = noop_context();
+
+        let mut w =
+            Stream::encrypt_async(PayloadKey::from([7; 32]), vec![].interleave_pending_write());
+
+        let mut tmp = &data[..];
+        while !tmp.is_empty() {
+            match Pin::new(&mut w).poll_write(&mut cx, tmp) {
+                Poll::Ready(Ok(written)) => tmp = &tmp[written..],
+                Poll::Ready(Err(e)) => panic!("Unexpected error: {}", e),
+                Poll::Pending => (),
+            }
+        }
+
+        // Cancel the first attempt to finish the stream while it is writing the final
+        // chunk, and then finish it.
+        {
+            let f = w.finish_async();
+            pin_mut!(f);
+            assert!(f.as_mut().poll(&mut cx).is_pending());
+        }
+        loop {
+            let f = w.finish_async();
+            pin_mut!(f);
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(Ok(())) => break,
+                Poll::Ready(Err(e)) => panic!("Unexpected error: {}", e),
+                Poll::Pending => (),
+            }
+        }
+
+        // Nothing more can be written once the stream is finished.
+        assert!(matches!(
+            Pin::new(&mut w).poll_write(&mut cx, b"more"),
+            Poll::Ready(Err(_)),
+        ));
+
+        let (inner, position) = w.into_inner();
+        let encrypted = inner.into_inner();
+        assert_eq!(encrypted.len(), ENCRYPTED_CHUNK_SIZE + 5 + TAG_SIZE);
+        assert_eq!(position.plaintext(), data.len() as u64);
+        assert_eq!(position.ciphertext(), encrypted.len() as u64);
+        assert!(position.is_complete());
+
+        let mut decrypted = vec![];
+        Stream::decrypt(PayloadKey::from([7; 32]), &encrypted[..])
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, data);
+    }
+
     #[test]
     fn stream_fails_to_decrypt_truncated_file() {
         let data = vec![42; 2 * CHUNK_SIZE];
 
         let mut encrypted = vec![];
         {
-            let mut w = Stream::encrypt(PayloadKey([7; 32].into()), &mut encrypted);
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
             w.write_all(&data).unwrap();
             // Forget to call w.finish()!
         };
 
         let mut buf = vec![];
-        let mut r = Stream::decrypt(PayloadKey([7; 32].into()), &encrypted[..]);
-        assert_eq!(
-            r.read_to_end(&mut buf).unwrap_err().kind(),
-            io::ErrorKind::UnexpectedEof
-        );
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), &encrypted[..]);
+        let e = r.read_to_end(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(!r.is_complete());
+
+        // Only the first chunk was written, and it was authenticated before the
+        // truncation was detected.
+        let e = e.get_ref().unwrap().downcast_ref::<Truncated>().unwrap();
+        assert_eq!(e.plaintext(), CHUNK_SIZE as u64);
+        assert_eq!(e.ciphertext(), ENCRYPTED_CHUNK_SIZE as u64);
+        assert_eq!(buf, &data[..CHUNK_SIZE]);
+    }
+
+    #[test]
+    fn stream_lenient_mode_skips_corrupted_chunks() {
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 100).map(|i| i as u8 | 1).collect();
+
+        let mut encrypted = vec![];
+        {
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
+            w.write_all(&data).unwrap();
+            w.finish().unwrap();
+        };
+
+        let read = |encrypted: &[u8], lenient: bool| {
+            let mut r = Stream::decrypt(PayloadKey::from([7; 32]), encrypted);
+            if lenient {
+                r = r.with_lenient_mode();
+            }
+            let mut buf = vec![];
+            let res = r.read_to_end(&mut buf);
+            (res.map(|_| buf), r.skipped().to_vec())
+        };
+
+        // Without corruption, lenient mode skips nothing.
+        let (res, skipped) = read(&encrypted, true);
+        assert_eq!(res.unwrap(), data);
+        assert!(skipped.is_empty());
+
+        for (corrupt_chunk, range) in [
+            (0, 0..CHUNK_SIZE),
+            (1, CHUNK_SIZE..2 * CHUNK_SIZE),
+            (2, 2 * CHUNK_SIZE..data.len()),
+        ] {
+            let mut corrupted = encrypted.clone();
+            corrupted[corrupt_chunk * ENCRYPTED_CHUNK_SIZE + 5] ^= 1;
+
+            // By default, corruption is an error.
+            assert!(read(&corrupted, false).0.is_err());
+
+            // In lenient mode, the corrupted chunk is replaced with zeroes.
+            let (res, skipped) = read(&corrupted, true);
+            let decrypted = res.unwrap();
+            assert_eq!(decrypted.len(), data.len());
+            assert!(decrypted[range.clone()].iter().all(|&b| b == 0));
+            assert_eq!(decrypted[..range.start], data[..range.start]);
+            assert_eq!(decrypted[range.end..], data[range.end..]);
+            assert_eq!(skipped, vec![range.start as u64..range.end as u64]);
+        }
     }
 
     #[test]
@@ -857,12 +1717,12 @@ mod tests {
 
         let mut encrypted = vec![];
         {
-            let mut w = Stream::encrypt(PayloadKey([7; 32].into()), &mut encrypted);
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
             w.write_all(&data).unwrap();
             w.finish().unwrap();
         };
 
-        let mut r = Stream::decrypt(PayloadKey([7; 32].into()), Cursor::new(encrypted));
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(encrypted));
 
         // Read through into the second chunk
         let mut buf = vec![0; 100];
@@ -897,7 +1757,7 @@ mod tests {
         // Encrypt the plaintext just like the example code in the docs.
         let mut encrypted = vec![];
         {
-            let mut w = Stream::encrypt(PayloadKey([7; 32].into()), &mut encrypted);
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
             w.write_all(&plaintext).unwrap();
             w.finish().unwrap();
         };
@@ -905,7 +1765,7 @@ mod tests {
         // First check the correct behavior of seeks relative to EOF. Create a decrypting
         // reader, and move it one byte forward from the start, using SeekFrom::End.
         // Confirm that reading 4 bytes from that point gives us "ello", as it should.
-        let mut reader = Stream::decrypt(PayloadKey([7; 32].into()), Cursor::new(&encrypted));
+        let mut reader = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(&encrypted));
         let eof_relative_offset = 1_i64 - plaintext.len() as i64;
         reader.seek(SeekFrom::End(eof_relative_offset)).unwrap();
         let mut buf = [0; 4];
@@ -916,10 +1776,8 @@ mod tests {
         // first. This should cause some sort of error, instead of a successful read that
         // returns the wrong plaintext.
         let truncated_ciphertext = &encrypted[..encrypted.len() - 1];
-        let mut truncated_reader = Stream::decrypt(
-            PayloadKey([7; 32].into()),
-            Cursor::new(truncated_ciphertext),
-        );
+        let mut truncated_reader =
+            Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(truncated_ciphertext));
         // Use the same seek target as above.
         match truncated_reader.seek(SeekFrom::End(eof_relative_offset)) {
             Err(e) => {
@@ -933,6 +1791,69 @@ mod tests {
         }
     }
 
+    #[test]
+    fn seek_into_truncated_file() {
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 100).map(|i| i as u8).collect();
+
+        let mut encrypted = vec![];
+        {
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
+            w.write_all(&data).unwrap();
+            w.finish().unwrap();
+        };
+
+        // Drop the last chunk, so the file ends with a full chunk that is not the last.
+        let truncated = &encrypted[..2 * ENCRYPTED_CHUNK_SIZE];
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(truncated));
+        let mut buf = vec![0; 100];
+        r.read_exact(&mut buf).unwrap();
+
+        // Seeking relative to the end fails, and leaves the reader where it was.
+        let e = r.seek(SeekFrom::End(-10)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(!r.is_complete());
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &data[100..200]);
+
+        // Seeking into the chunk before the truncation reads it, but not past it.
+        r.seek(SeekFrom::Start(2 * CHUNK_SIZE as u64 - 10)).unwrap();
+        r.read_exact(&mut buf[..10]).unwrap();
+        assert_eq!(&buf[..10], &data[2 * CHUNK_SIZE - 10..2 * CHUNK_SIZE]);
+        let e = r.read(&mut buf).unwrap_err();
+        assert!(e.get_ref().unwrap().downcast_ref::<Truncated>().is_some());
+        assert!(!r.is_complete());
+
+        // Truncating the last chunk instead means it fails to authenticate when seeking
+        // into it, which must not complete the stream.
+        let truncated = &encrypted[..encrypted.len() - 1];
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(truncated));
+        assert!(r.seek(SeekFrom::Start(2 * CHUNK_SIZE as u64 + 5)).is_err());
+        assert!(!r.is_complete());
+
+        // A file truncated to less than a tag has no last chunk at all.
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(&encrypted[..5]));
+        let e = r.seek(SeekFrom::End(0)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn seek_from_end_rejects_empty_last_chunk() {
+        // A full chunk followed by an empty last chunk is not a valid STREAM, because
+        // the full chunk should have been the last chunk.
+        let mut s = Stream::new(PayloadKey::from([7; 32]));
+        let mut encrypted = encrypt_chunk(&mut s, &[42; CHUNK_SIZE], false).unwrap();
+        encrypted.extend_from_slice(&encrypt_chunk(&mut s, &[], true).unwrap());
+
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(&encrypted));
+        let e = r.seek(SeekFrom::End(0)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // This matches what happens when reading it in order.
+        let mut r = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(&encrypted));
+        let e = r.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
     #[test]
     fn seek_from_end_with_exact_chunk() {
         let plaintext: Vec<u8> = vec![42; 65536];
@@ -940,13 +1861,13 @@ mod tests {
         // Encrypt the plaintext just like the example code in the docs.
         let mut encrypted = vec![];
         {
-            let mut w = Stream::encrypt(PayloadKey([7; 32].into()), &mut encrypted);
+            let mut w = Stream::encrypt(PayloadKey::from([7; 32]), &mut encrypted);
             w.write_all(&plaintext).unwrap();
             w.finish().unwrap();
         };
 
         // Seek to the end of the plaintext before decrypting.
-        let mut reader = Stream::decrypt(PayloadKey([7; 32].into()), Cursor::new(&encrypted));
+        let mut reader = Stream::decrypt(PayloadKey::from([7; 32]), Cursor::new(&encrypted));
         reader.seek(SeekFrom::End(0)).unwrap();
 
         // Reading should return no bytes, because we're already at EOF.
@@ -1,22 +1,39 @@
 //! Encryption and decryption routines for age.
 
-use age_core::{format::grease_the_joint, secrecy::SecretString};
-use rand::{rngs::OsRng, RngCore};
+use age_core::{
+    format::{grease_the_joint, FileKey},
+    secrecy::SecretString,
+};
 use std::io::{self, Read, Write};
 
 use crate::{
-    error::{DecryptError, EncryptError},
+    error::{DecryptError, EncryptError, RngError},
     format::{Header, HeaderV1},
     keys::{mac_key, new_file_key, v1_payload_key},
+    label,
     primitives::stream::{PayloadKey, Stream, StreamWriter},
-    scrypt, Recipient,
+    rng::random_bytes,
+    scrypt, threshold, Recipient,
 };
 
+#[cfg(feature = "armor")]
+use crate::armor::{ArmoredReader, ArmoredWriter, Format};
+#[cfg(feature = "armor")]
+use std::io::BufRead;
+
 #[cfg(feature = "async")]
 use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+#[cfg(all(feature = "armor", feature = "async"))]
+use futures::io::AsyncBufRead;
+
+pub mod airgap;
 pub mod decryptor;
 
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+pub mod experimental;
+
 pub(crate) struct Nonce([u8; 16]);
 
 impl AsRef<[u8]> for Nonce {
@@ -26,10 +43,8 @@ impl AsRef<[u8]> for Nonce {
 }
 
 impl Nonce {
-    fn random() -> Self {
-        let mut nonce = [0; 16];
-        OsRng.fill_bytes(&mut nonce);
-        Nonce(nonce)
+    fn random() -> Result<Self, RngError> {
+        random_bytes().map(Nonce)
     }
 
     fn read<R: Read>(input: &mut R) -> io::Result<Self> {
@@ -51,20 +66,55 @@ impl Nonce {
 enum EncryptorType {
     /// Encryption to a list of recipients identified by keys.
     Keys(Vec<Box<dyn Recipient + Send>>),
+    /// Encryption to a threshold of a list of recipients.
+    Threshold(u8, Vec<Box<dyn Recipient + Send>>),
     /// Encryption to a passphrase.
     Passphrase(SecretString),
 }
 
 /// Encryptor for creating an age file.
-pub struct Encryptor(EncryptorType);
+///
+/// An `Encryptor` can be constructed directly for the common cases, or with an
+/// [`EncryptorBuilder`] to set further options.
+pub struct Encryptor {
+    kind: EncryptorType,
+    #[cfg(feature = "armor")]
+    format: Option<Format>,
+}
 
 impl Encryptor {
+    fn new(kind: EncryptorType) -> Self {
+        Encryptor {
+            kind,
+            #[cfg(feature = "armor")]
+            format: None,
+        }
+    }
+
     /// Constructs an `Encryptor` that will create an age file encrypted to a list of
     /// recipients.
     ///
     /// Returns `None` if no recipients were provided.
     pub fn with_recipients(recipients: Vec<Box<dyn Recipient + Send>>) -> Option<Self> {
-        (!recipients.is_empty()).then(|| Encryptor(EncryptorType::Keys(recipients)))
+        (!recipients.is_empty()).then(|| Encryptor::new(EncryptorType::Keys(recipients)))
+    }
+
+    /// Constructs an `Encryptor` that will create an age file that any `threshold` of the
+    /// given recipients can decrypt together.
+    ///
+    /// The file key is split with Shamir's secret sharing, and one share is wrapped to
+    /// each recipient. A recipient that wraps several stanzas at once (such as a plugin
+    /// recipient for multiple keys) receives a single share. Fewer than `threshold`
+    /// recipients learn nothing about the file key.
+    ///
+    /// Returns `None` if no recipients were provided, if there are more than 255
+    /// recipients, or if `threshold` is zero or greater than the number of recipients.
+    pub fn with_threshold(
+        threshold: u8,
+        recipients: Vec<Box<dyn Recipient + Send>>,
+    ) -> Option<Self> {
+        (threshold > 0 && usize::from(threshold) <= recipients.len() && recipients.len() <= 255)
+            .then(|| Encryptor::new(EncryptorType::Threshold(threshold, recipients)))
     }
 
     /// Returns an `Encryptor` that will create an age file encrypted with a passphrase.
@@ -76,14 +126,14 @@ impl Encryptor {
     ///
     /// [`x25519::Identity`]: crate::x25519::Identity
     pub fn with_user_passphrase(passphrase: SecretString) -> Self {
-        Encryptor(EncryptorType::Passphrase(passphrase))
+        Encryptor::new(EncryptorType::Passphrase(passphrase))
     }
 
-    /// Creates the header for this age file.
-    fn prepare_header(self) -> Result<(Header, Nonce, PayloadKey), EncryptError> {
-        let file_key = new_file_key();
+    /// Creates the header for this age file, and the file key that it wraps.
+    fn prepare_header_v1(self) -> Result<(HeaderV1, FileKey), EncryptError> {
+        let file_key = new_file_key()?;
 
-        let recipients = match self.0 {
+        let recipients = match self.kind {
             EncryptorType::Keys(recipients) => {
                 let mut stanzas = Vec::with_capacity(recipients.len() + 1);
                 for recipient in recipients {
@@ -93,13 +143,30 @@ impl Encryptor {
                 stanzas.push(grease_the_joint());
                 stanzas
             }
+            EncryptorType::Threshold(threshold, recipients) => {
+                let shares = threshold::split(&file_key, threshold, recipients.len() as u8)?;
+                let mut stanzas = Vec::with_capacity(2 * recipients.len() + 2);
+                stanzas.push(threshold::threshold_stanza(threshold));
+                for ((index, share), recipient) in (1..).zip(shares).zip(recipients) {
+                    stanzas.push(threshold::share_stanza(index));
+                    stanzas.append(&mut recipient.wrap_file_key(&share)?);
+                }
+                stanzas.push(grease_the_joint());
+                stanzas
+            }
             EncryptorType::Passphrase(passphrase) => {
                 scrypt::Recipient { passphrase }.wrap_file_key(&file_key)?
             }
         };
 
         let header = HeaderV1::new(recipients, mac_key(&file_key));
-        let nonce = Nonce::random();
+        Ok((header, file_key))
+    }
+
+    /// Creates the header for this age file.
+    fn prepare_header(self) -> Result<(Header, Nonce, PayloadKey), EncryptError> {
+        let (header, file_key) = self.prepare_header_v1()?;
+        let nonce = Nonce::random()?;
         let payload_key = v1_payload_key(&file_key, &header, &nonce).expect("MAC is correct");
 
         Ok((Header::V1(header), nonce, payload_key))
@@ -119,13 +186,32 @@ impl Encryptor {
         Ok(Stream::encrypt(payload_key, output))
     }
 
+    /// Creates a wrapper around a writer that will encrypt its input, in the format set
+    /// with [`EncryptorBuilder::armor`] (the binary format by default).
+    ///
+    /// Returns errors from the underlying writer while writing the header.
+    ///
+    /// You **MUST** call [`StreamWriter::finish`] and then [`ArmoredWriter::finish`]
+    /// when you are done writing, in order to finish the encryption process. Failing
+    /// to call them will result in a truncated file that will fail to decrypt.
+    #[cfg(feature = "armor")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "armor")))]
+    pub fn wrap_armored_output<W: Write>(
+        mut self,
+        output: W,
+    ) -> Result<StreamWriter<ArmoredWriter<W>>, EncryptError> {
+        let format = self.format.take().unwrap_or(Format::Binary);
+        self.wrap_output(ArmoredWriter::wrap_output(output, format)?)
+    }
+
     /// Creates a wrapper around a writer that will encrypt its input.
     ///
     /// Returns errors from the underlying writer while writing the header.
     ///
-    /// You **MUST** call [`AsyncWrite::poll_close`] when you are done writing, in order
-    /// to finish the encryption process. Failing to call [`AsyncWrite::poll_close`]
-    /// will result in a truncated file that will fail to decrypt.
+    /// You **MUST** await [`StreamWriter::finish_async`] (which leaves `output` open) or
+    /// call [`AsyncWrite::poll_close`] (which also closes it) when you are done writing,
+    /// in order to finish the encryption process. Failing to do either will result in a
+    /// truncated file that will fail to decrypt.
     #[cfg(feature = "async")]
     #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
     pub async fn wrap_async_output<W: AsyncWrite + Unpin>(
@@ -137,6 +223,177 @@ impl Encryptor {
         output.write_all(nonce.as_ref()).await?;
         Ok(Stream::encrypt_async(payload_key, output))
     }
+
+    /// Creates a wrapper around a writer that will encrypt its input, in the format set
+    /// with [`EncryptorBuilder::armor`] (the binary format by default).
+    ///
+    /// Returns errors from the underlying writer while writing the header.
+    ///
+    /// You **MUST** call [`AsyncWrite::poll_close`] when you are done writing, in order
+    /// to finish the encryption process (including the armor's end marker). Failing to
+    /// call [`AsyncWrite::poll_close`] will result in a truncated file that will fail
+    /// to decrypt.
+    #[cfg(all(feature = "armor", feature = "async"))]
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "armor", feature = "async"))))]
+    pub async fn wrap_async_armored_output<W: AsyncWrite + Unpin>(
+        mut self,
+        output: W,
+    ) -> Result<StreamWriter<ArmoredWriter<W>>, EncryptError> {
+        let format = self.format.take().unwrap_or(Format::Binary);
+        self.wrap_async_output(ArmoredWriter::wrap_async_output(output, format))
+            .await
+    }
+
+    /// Creates a wrapper around a writer that will encrypt its input, with the header
+    /// written to a separate output.
+    ///
+    /// The header (ending with its MAC line) is written to `header_output`, and the
+    /// payload (the nonce followed by the encrypted chunks) to `output`. Concatenating
+    /// the two gives the same age file that [`Encryptor::wrap_output`] creates, and
+    /// [`Decryptor::new_detached`] decrypts them without concatenating them. This
+    /// allows the small header to be stored apart from the bulk of the ciphertext.
+    ///
+    /// The payload is encrypted with a key derived from the file key alone, so the
+    /// header can later be replaced with one that wraps the same file key to different
+    /// recipients, without touching the payload.
+    ///
+    /// Returns errors from `header_output` while writing the header.
+    ///
+    /// You **MUST** call [`StreamWriter::finish`] when you are done writing, in order to
+    /// finish the encryption process. Failing to call [`StreamWriter::finish`] will
+    /// result in a truncated payload that will fail to decrypt.
+    pub fn wrap_output_detached<H: Write, W: Write>(
+        self,
+        mut header_output: H,
+        mut output: W,
+    ) -> Result<StreamWriter<W>, EncryptError> {
+        let (header, nonce, payload_key) = self.prepare_header()?;
+        header.write(&mut header_output)?;
+        header_output.flush()?;
+        output.write_all(nonce.as_ref())?;
+        Ok(Stream::encrypt(payload_key, output))
+    }
+
+    /// Creates a wrapper around a writer that will encrypt its input, with the header
+    /// written to a separate output.
+    ///
+    /// See [`Encryptor::wrap_output_detached`] for details.
+    ///
+    /// You **MUST** await [`StreamWriter::finish_async`] (which leaves `output` open) or
+    /// call [`AsyncWrite::poll_close`] (which also closes it) when you are done writing,
+    /// in order to finish the encryption process. Failing to do either will result in a
+    /// truncated payload that will fail to decrypt.
+    #[cfg(feature = "async")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub async fn wrap_async_output_detached<H: AsyncWrite + Unpin, W: AsyncWrite + Unpin>(
+        self,
+        mut header_output: H,
+        mut output: W,
+    ) -> Result<StreamWriter<W>, EncryptError> {
+        let (header, nonce, payload_key) = self.prepare_header()?;
+        header.write_async(&mut header_output).await?;
+        header_output.flush().await?;
+        output.write_all(nonce.as_ref()).await?;
+        Ok(Stream::encrypt_async(payload_key, output))
+    }
+}
+
+/// A builder for an [`Encryptor`], for setting options beyond the recipients or
+/// passphrase that a file is encrypted to.
+///
+/// ```
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let key = age::x25519::Identity::generate();
+///
+/// let encryptor = age::EncryptorBuilder::new()
+///     .recipient(Box::new(key.to_public()))
+///     .build()
+///     .expect("we provided a recipient");
+///
+/// let mut encrypted = vec![];
+/// let mut writer = encryptor.wrap_output(&mut encrypted)?;
+/// std::io::Write::write_all(&mut writer, b"Hello world!")?;
+/// writer.finish()?;
+/// # Ok(())
+/// # }
+/// # run_main().unwrap();
+/// ```
+#[derive(Default)]
+pub struct EncryptorBuilder {
+    recipients: Vec<Box<dyn Recipient + Send>>,
+    threshold: Option<u8>,
+    passphrase: Option<SecretString>,
+    #[cfg(feature = "armor")]
+    format: Option<Format>,
+}
+
+impl EncryptorBuilder {
+    /// Returns a builder with no recipients or passphrase, and the default options.
+    pub fn new() -> Self {
+        EncryptorBuilder::default()
+    }
+
+    /// Adds a recipient that the file will be encrypted to.
+    pub fn recipient(mut self, recipient: Box<dyn Recipient + Send>) -> Self {
+        self.recipients.push(recipient);
+        self
+    }
+
+    /// Adds recipients that the file will be encrypted to.
+    pub fn recipients(
+        mut self,
+        recipients: impl IntoIterator<Item = Box<dyn Recipient + Send>>,
+    ) -> Self {
+        self.recipients.extend(recipients);
+        self
+    }
+
+    /// Requires any `threshold` of the recipients to decrypt the file together,
+    /// instead of any one of them.
+    ///
+    /// See [`Encryptor::with_threshold`] for details.
+    pub fn threshold(mut self, threshold: u8) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Encrypts the file with a passphrase instead of to recipients.
+    ///
+    /// See [`Encryptor::with_user_passphrase`] for details.
+    pub fn passphrase(mut self, passphrase: SecretString) -> Self {
+        self.passphrase = Some(passphrase);
+        self
+    }
+
+    /// Sets the format that [`Encryptor::wrap_armored_output`] writes.
+    #[cfg(feature = "armor")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "armor")))]
+    pub fn armor(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Builds the [`Encryptor`].
+    ///
+    /// Returns `None` if neither recipients nor a passphrase were provided, if both
+    /// were, if a threshold was set with a passphrase, or if the threshold is invalid
+    /// for the number of recipients (as for [`Encryptor::with_threshold`]).
+    pub fn build(self) -> Option<Encryptor> {
+        let Encryptor { kind, .. } = match (self.passphrase, self.threshold) {
+            (Some(passphrase), None) if self.recipients.is_empty() => {
+                Encryptor::with_user_passphrase(passphrase)
+            }
+            (Some(_), _) => return None,
+            (None, Some(threshold)) => Encryptor::with_threshold(threshold, self.recipients)?,
+            (None, None) => Encryptor::with_recipients(self.recipients)?,
+        };
+
+        Some(Encryptor {
+            kind,
+            #[cfg(feature = "armor")]
+            format: self.format,
+        })
+    }
 }
 
 /// Decryptor for an age file.
@@ -162,6 +419,8 @@ impl<R> From<decryptor::PassphraseDecryptor<R>> for Decryptor<R> {
 impl<R> Decryptor<R> {
     fn from_v1_header(input: R, header: HeaderV1, nonce: Nonce) -> Result<Self, DecryptError> {
         // Enforce structural requirements on the v1 header.
+        label::check_structure(&header.recipients)?;
+
         let any_scrypt = header
             .recipients
             .iter()
@@ -177,6 +436,64 @@ impl<R> Decryptor<R> {
     }
 }
 
+impl<R> Decryptor<R> {
+    /// Returns the recipient stanzas in the age file's header.
+    ///
+    /// This can be used to decide which identities to provide before decrypting.
+    pub fn peek_recipients(&self) -> Vec<decryptor::RecipientStanza<'_>> {
+        match self {
+            Decryptor::Recipients(d) => d.peek_recipients(),
+            Decryptor::Passphrase(d) => d.peek_recipients(),
+        }
+    }
+
+    /// Returns the length in bytes of the age file's header, including the MAC line.
+    ///
+    /// This can be used by framing layers that embed age files to find where the
+    /// payload begins. See [`RecipientsDecryptor::peek_header_len`] for details.
+    ///
+    /// [`RecipientsDecryptor::peek_header_len`]: decryptor::RecipientsDecryptor::peek_header_len
+    pub fn peek_header_len(&self) -> usize {
+        match self {
+            Decryptor::Recipients(d) => d.peek_header_len(),
+            Decryptor::Passphrase(d) => d.peek_header_len(),
+        }
+    }
+
+    /// Returns a summary of the age file, which can be logged or serialized (with the
+    /// `serde` feature flag) before decrypting it.
+    pub fn peek_metadata(&self) -> decryptor::FileMetadata {
+        match self {
+            Decryptor::Recipients(d) => d.peek_metadata(),
+            Decryptor::Passphrase(d) => d.peek_metadata(),
+        }
+    }
+
+    /// Sets the expected length of the entire age file, including the header.
+    ///
+    /// See [`RecipientsDecryptor::with_expected_len`] for details.
+    ///
+    /// [`RecipientsDecryptor::with_expected_len`]: decryptor::RecipientsDecryptor::with_expected_len
+    pub fn with_expected_len(self, len: u64) -> Self {
+        match self {
+            Decryptor::Recipients(d) => d.with_expected_len(len).into(),
+            Decryptor::Passphrase(d) => d.with_expected_len(len).into(),
+        }
+    }
+
+    /// Sets the maximum length of the plaintext that will be decrypted.
+    ///
+    /// See [`RecipientsDecryptor::with_max_plaintext_len`] for details.
+    ///
+    /// [`RecipientsDecryptor::with_max_plaintext_len`]: decryptor::RecipientsDecryptor::with_max_plaintext_len
+    pub fn with_max_plaintext_len(self, max: u64) -> Self {
+        match self {
+            Decryptor::Recipients(d) => d.with_max_plaintext_len(max).into(),
+            Decryptor::Passphrase(d) => d.with_max_plaintext_len(max).into(),
+        }
+    }
+}
+
 impl<R: Read> Decryptor<R> {
     /// Attempts to create a decryptor for an age file.
     ///
@@ -192,6 +509,28 @@ impl<R: Read> Decryptor<R> {
             Header::Unknown(_) => Err(DecryptError::UnknownFormat),
         }
     }
+
+    /// Attempts to create a decryptor for an age file whose header is stored apart from
+    /// its payload, as written by [`Encryptor::wrap_output_detached`].
+    ///
+    /// `header_input` must contain only the header, and `input` the payload that
+    /// follows it.
+    ///
+    /// Returns an error if the inputs do not contain a valid age file.
+    pub fn new_detached<H: Read>(mut header_input: H, mut input: R) -> Result<Self, DecryptError> {
+        let header = Header::read(&mut header_input)?;
+
+        match header {
+            Header::V1(v1_header) => {
+                if header_input.read(&mut [0])? != 0 {
+                    return Err(DecryptError::InvalidHeader);
+                }
+                let nonce = Nonce::read(&mut input)?;
+                Decryptor::from_v1_header(input, v1_header, nonce)
+            }
+            Header::Unknown(_) => Err(DecryptError::UnknownFormat),
+        }
+    }
 }
 
 #[cfg(feature = "async")]
@@ -211,20 +550,73 @@ impl<R: AsyncRead + Unpin> Decryptor<R> {
             Header::Unknown(_) => Err(DecryptError::UnknownFormat),
         }
     }
+
+    /// Attempts to create a decryptor for an age file whose header is stored apart from
+    /// its payload.
+    ///
+    /// See [`Decryptor::new_detached`] for details.
+    pub async fn new_async_detached<H: AsyncRead + Unpin>(
+        mut header_input: H,
+        mut input: R,
+    ) -> Result<Self, DecryptError> {
+        let header = Header::read_async(&mut header_input).await?;
+
+        match header {
+            Header::V1(v1_header) => {
+                if header_input.read(&mut [0]).await? != 0 {
+                    return Err(DecryptError::InvalidHeader);
+                }
+                let nonce = Nonce::read_async(&mut input).await?;
+                Decryptor::from_v1_header(input, v1_header, nonce)
+            }
+            Header::Unknown(_) => Err(DecryptError::UnknownFormat),
+        }
+    }
+}
+
+#[cfg(feature = "armor")]
+#[cfg_attr(docsrs, doc(cfg(feature = "armor")))]
+impl<R: BufRead> Decryptor<ArmoredReader<R>> {
+    /// Attempts to create a decryptor for an age file that may be armored.
+    ///
+    /// Whether the age file is armored is detected automatically. This is equivalent to
+    /// `Decryptor::new(ArmoredReader::new_buffered(input))`.
+    ///
+    /// Returns an error if the input does not contain a valid age file.
+    pub fn new_buffered(input: R) -> Result<Self, DecryptError> {
+        Decryptor::new(ArmoredReader::new_buffered(input))
+    }
+}
+
+#[cfg(all(feature = "armor", feature = "async"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "armor", feature = "async"))))]
+impl<R: AsyncBufRead + Unpin> Decryptor<ArmoredReader<R>> {
+    /// Attempts to create a decryptor for an age file that may be armored.
+    ///
+    /// Whether the age file is armored is detected automatically, in the same way as
+    /// [`Decryptor::new_buffered`].
+    ///
+    /// Returns an error if the input does not contain a valid age file.
+    pub async fn new_async_buffered(input: R) -> Result<Self, DecryptError> {
+        Decryptor::new_async(ArmoredReader::with_buffered(input)).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use age_core::secrecy::SecretString;
-    use std::io::{BufReader, Read, Write};
+    use std::io::{self, BufReader, Read, Write};
 
-    #[cfg(feature = "ssh")]
     use std::iter;
 
-    use super::{Decryptor, Encryptor};
+    use super::{
+        decryptor::{HeaderParser, Progress},
+        Decryptor, Encryptor, EncryptorBuilder,
+    };
     use crate::{
         identity::{IdentityFile, IdentityFileEntry},
-        x25519, Identity, Recipient,
+        stream::{self, StreamReader},
+        x25519, DecryptError, Identity, Labeled, Recipient,
     };
 
     #[cfg(feature = "async")]
@@ -237,12 +629,13 @@ mod tests {
     #[cfg(feature = "async")]
     use futures_test::task::noop_context;
 
+    const TEST_MSG: &[u8] = b"This is a test message. For testing.";
+
     fn recipient_round_trip<'a>(
         recipients: Vec<Box<dyn Recipient + Send>>,
         identities: impl Iterator<Item = &'a dyn Identity>,
+        test_msg: &[u8],
     ) {
-        let test_msg = b"This is a test message. For testing.";
-
         let mut encrypted = vec![];
         let e = Encryptor::with_recipients(recipients).unwrap();
         {
@@ -259,15 +652,15 @@ mod tests {
         let mut decrypted = vec![];
         r.read_to_end(&mut decrypted).unwrap();
 
-        assert_eq!(&decrypted[..], &test_msg[..]);
+        assert_eq!(decrypted, test_msg);
     }
 
     #[cfg(feature = "async")]
     fn recipient_async_round_trip<'a>(
         recipients: Vec<Box<dyn Recipient + Send>>,
         identities: impl Iterator<Item = &'a dyn Identity>,
+        test_msg: &[u8],
     ) {
-        let test_msg = b"This is a test message. For testing.";
         let mut cx = noop_context();
 
         let mut encrypted = vec![];
@@ -287,7 +680,7 @@ mod tests {
             };
             pin_mut!(w);
 
-            let mut tmp = &test_msg[..];
+            let mut tmp = test_msg;
             loop {
                 match w.as_mut().poll_write(&mut cx, tmp) {
                     Poll::Ready(Ok(0)) => break,
@@ -337,7 +730,7 @@ mod tests {
             }
         };
 
-        assert_eq!(&decrypted[..], &test_msg[..]);
+        assert_eq!(decrypted, test_msg);
     }
 
     #[test]
@@ -351,10 +744,389 @@ mod tests {
                 IdentityFileEntry::Native(sk) => sk as &dyn Identity,
                 #[cfg(feature = "plugin")]
                 IdentityFileEntry::Plugin(_) => unreachable!(),
+                #[cfg(feature = "pq")]
+                IdentityFileEntry::Pq(_) => unreachable!(),
+                #[cfg(feature = "fido2")]
+                IdentityFileEntry::Fido2(_) => unreachable!(),
+                #[cfg(feature = "yubikey")]
+                IdentityFileEntry::YubiKey(_) => unreachable!(),
             }),
+            TEST_MSG,
+        );
+    }
+
+    #[test]
+    fn boxed_round_trip() {
+        let sk: x25519::Identity = crate::x25519::tests::TEST_SK.parse().unwrap();
+        let pk: Box<dyn Recipient + Send> = Box::new(
+            crate::x25519::tests::TEST_PK
+                .parse::<x25519::Recipient>()
+                .unwrap(),
+        );
+        let boxed_sk: Box<dyn Identity> = Box::new(sk);
+
+        // Boxed and borrowed trait objects can themselves be used as recipients and
+        // identities, so wrappers around them don't need to unbox them.
+        recipient_round_trip(
+            vec![Box::new(pk)],
+            iter::once(&&boxed_sk as &dyn Identity),
+            TEST_MSG,
+        );
+    }
+
+    #[test]
+    fn many_identities_round_trip() {
+        /// An identity that fails to unwrap every stanza.
+        struct Broken;
+
+        impl Identity for Broken {
+            fn unwrap_stanza(
+                &self,
+                _: &age_core::format::Stanza,
+            ) -> Option<Result<age_core::format::FileKey, crate::DecryptError>> {
+                Some(Err(crate::DecryptError::DecryptionFailed))
+            }
+        }
+
+        let keys: Vec<_> = (0..3).map(|_| x25519::Identity::generate()).collect();
+        let unrelated = x25519::Identity::generate();
+
+        // The file key is unwrapped whichever stanza the identity matches, and an error
+        // from a later identity is ignored.
+        for key in &keys {
+            recipient_round_trip(
+                keys.iter()
+                    .map(|key| Box::new(key.to_public()) as _)
+                    .collect(),
+                vec![&unrelated as &dyn Identity, key, &Broken].into_iter(),
+                TEST_MSG,
+            );
+        }
+
+        // An error from an earlier identity is returned.
+        let mut encrypted = vec![];
+        {
+            let e = Encryptor::with_recipients(vec![Box::new(keys[0].to_public()) as _]).unwrap();
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(TEST_MSG).unwrap();
+            w.finish().unwrap();
+        }
+        let d = match Decryptor::new(&encrypted[..]) {
+            Ok(Decryptor::Recipients(d)) => d,
+            _ => panic!(),
+        };
+        assert!(matches!(
+            d.decrypt(vec![&Broken as &dyn Identity, &keys[0]].into_iter()),
+            Err(crate::DecryptError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn interactive_identities_are_tried_until_a_match() {
+        use std::cell::Cell;
+
+        /// An identity that counts the stanzas it is asked to unwrap.
+        struct Counting<'a> {
+            inner: &'a x25519::Identity,
+            interactive: bool,
+            calls: Cell<usize>,
+        }
+
+        impl Identity for Counting<'_> {
+            fn unwrap_stanza(
+                &self,
+                stanza: &age_core::format::Stanza,
+            ) -> Option<Result<age_core::format::FileKey, crate::DecryptError>> {
+                self.calls.set(self.calls.get() + 1);
+                self.inner.unwrap_stanza(stanza)
+            }
+
+            fn is_interactive(&self) -> bool {
+                self.interactive
+            }
+        }
+
+        let keys: Vec<_> = (0..3).map(|_| x25519::Identity::generate()).collect();
+        let mut encrypted = vec![];
+        {
+            let e = Encryptor::with_recipients(
+                keys.iter()
+                    .map(|key| Box::new(key.to_public()) as _)
+                    .collect(),
+            )
+            .unwrap();
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(TEST_MSG).unwrap();
+            w.finish().unwrap();
+        }
+
+        let identities: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| Counting {
+                inner: key,
+                interactive: i == 1,
+                calls: Cell::new(0),
+            })
+            .collect();
+
+        let d = match Decryptor::new(&encrypted[..]) {
+            Ok(Decryptor::Recipients(d)) => d,
+            _ => panic!(),
+        };
+        let mut r = d
+            .decrypt(identities.iter().map(|i| i as &dyn Identity))
+            .unwrap();
+        let mut decrypted = vec![];
+        r.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, TEST_MSG);
+
+        // The first identity matches the first stanza. The other non-interactive identity
+        // still tries every stanza (including the grease stanza), but the interactive
+        // identity is skipped.
+        let calls: Vec<_> = identities.iter().map(|i| i.calls.get()).collect();
+        assert_eq!(calls, [keys.len() + 1, 0, keys.len() + 1]);
+    }
+
+    #[test]
+    fn empty_plaintext_round_trip() {
+        let sk = x25519::Identity::generate();
+        recipient_round_trip(
+            vec![Box::new(sk.to_public())],
+            iter::once(&sk as &dyn Identity),
+            &[],
         );
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn empty_plaintext_async_round_trip() {
+        let sk = x25519::Identity::generate();
+        recipient_async_round_trip(
+            vec![Box::new(sk.to_public())],
+            iter::once(&sk as &dyn Identity),
+            &[],
+        );
+    }
+
+    #[cfg(feature = "armor")]
+    #[test]
+    fn empty_plaintext_armored_round_trip() {
+        use crate::armor::{ArmoredReader, ArmoredWriter, Format};
+
+        let sk = x25519::Identity::generate();
+
+        let mut encrypted = vec![];
+        {
+            let e = Encryptor::with_recipients(vec![Box::new(sk.to_public())]).unwrap();
+            let w = ArmoredWriter::wrap_output(&mut encrypted, Format::AsciiArmor).unwrap();
+            let w = e.wrap_output(w).unwrap();
+            w.finish().and_then(|armor| armor.finish()).unwrap();
+        }
+
+        let d = match Decryptor::new(ArmoredReader::new(&encrypted[..])) {
+            Ok(Decryptor::Recipients(d)) => d,
+            _ => panic!(),
+        };
+        let mut r = d.decrypt(iter::once(&sk as &dyn Identity)).unwrap();
+        assert!(r.is_empty().unwrap());
+
+        let mut decrypted = vec![];
+        r.read_to_end(&mut decrypted).unwrap();
+        assert!(decrypted.is_empty());
+    }
+
+    #[test]
+    fn builder_requires_one_kind_of_encryption() {
+        let sk = x25519::Identity::generate();
+        let passphrase = || SecretString::new("passphrase".to_owned());
+
+        assert!(EncryptorBuilder::new().build().is_none());
+        assert!(EncryptorBuilder::new()
+            .recipient(Box::new(sk.to_public()))
+            .passphrase(passphrase())
+            .build()
+            .is_none());
+        assert!(EncryptorBuilder::new()
+            .passphrase(passphrase())
+            .threshold(1)
+            .build()
+            .is_none());
+        assert!(EncryptorBuilder::new()
+            .recipient(Box::new(sk.to_public()))
+            .threshold(2)
+            .build()
+            .is_none());
+
+        assert!(EncryptorBuilder::new()
+            .passphrase(passphrase())
+            .build()
+            .is_some());
+        assert!(EncryptorBuilder::new()
+            .recipients(vec![
+                Box::new(sk.to_public()) as Box<dyn Recipient + Send>,
+                Box::new(x25519::Identity::generate().to_public()),
+            ])
+            .threshold(1)
+            .build()
+            .is_some());
+    }
+
+    #[cfg(feature = "armor")]
+    #[test]
+    fn builder_armored_round_trip() {
+        use crate::armor::{ArmoredReader, Format};
+
+        let sk = x25519::Identity::generate();
+
+        for (format, armored) in [(None, false), (Some(Format::AsciiArmor), true)] {
+            let builder = EncryptorBuilder::new().recipient(Box::new(sk.to_public()));
+            let builder = match format {
+                Some(format) => builder.armor(format),
+                None => builder,
+            };
+
+            let mut encrypted = vec![];
+            {
+                let mut w = builder
+                    .build()
+                    .unwrap()
+                    .wrap_armored_output(&mut encrypted)
+                    .unwrap();
+                w.write_all(TEST_MSG).unwrap();
+                w.finish().and_then(|armor| armor.finish()).unwrap();
+            }
+            assert_eq!(
+                encrypted.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"),
+                armored,
+            );
+
+            let d = match Decryptor::new(ArmoredReader::new(&encrypted[..])) {
+                Ok(Decryptor::Recipients(d)) => d,
+                _ => panic!(),
+            };
+            let mut r = d.decrypt(iter::once(&sk as &dyn Identity)).unwrap();
+            let mut decrypted = vec![];
+            r.read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, TEST_MSG);
+        }
+    }
+
+    #[cfg(all(feature = "armor", feature = "async"))]
+    #[test]
+    fn builder_async_armored_round_trip() {
+        use crate::armor::Format;
+
+        let mut cx = noop_context();
+        let sk = x25519::Identity::generate();
+
+        for (format, armored) in [(Format::Binary, false), (Format::AsciiArmor, true)] {
+            let e = EncryptorBuilder::new()
+                .recipient(Box::new(sk.to_public()))
+                .armor(format)
+                .build()
+                .unwrap();
+
+            let mut encrypted = vec![];
+            {
+                let w = {
+                    let f = e.wrap_async_armored_output(&mut encrypted);
+                    pin_mut!(f);
+                    match f.as_mut().poll(&mut cx) {
+                        Poll::Ready(Ok(w)) => w,
+                        _ => panic!(),
+                    }
+                };
+                pin_mut!(w);
+
+                let mut tmp = TEST_MSG;
+                while !tmp.is_empty() {
+                    match w.as_mut().poll_write(&mut cx, tmp) {
+                        Poll::Ready(Ok(written)) => tmp = &tmp[written..],
+                        _ => panic!(),
+                    }
+                }
+                match w.as_mut().poll_close(&mut cx) {
+                    Poll::Ready(Ok(())) => (),
+                    _ => panic!(),
+                }
+            }
+            assert_eq!(
+                encrypted.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"),
+                armored,
+            );
+            // Closing the writer also writes the armor's end marker.
+            const END_MARKER: &[u8] = b"-----END AGE ENCRYPTED FILE-----";
+            assert_eq!(
+                encrypted.windows(END_MARKER.len()).any(|w| w == END_MARKER),
+                armored,
+            );
+
+            let d = {
+                let f = Decryptor::new_async_buffered(&encrypted[..]);
+                pin_mut!(f);
+                match f.as_mut().poll(&mut cx) {
+                    Poll::Ready(Ok(Decryptor::Recipients(d))) => d,
+                    _ => panic!(),
+                }
+            };
+            let r = d.decrypt_async(iter::once(&sk as &dyn Identity)).unwrap();
+            pin_mut!(r);
+
+            let mut decrypted = vec![];
+            let mut tmp = [0; 4096];
+            loop {
+                match r.as_mut().poll_read(&mut cx, &mut tmp) {
+                    Poll::Ready(Ok(0)) => break,
+                    Poll::Ready(Ok(read)) => decrypted.extend_from_slice(&tmp[..read]),
+                    _ => panic!(),
+                }
+            }
+            assert_eq!(decrypted, TEST_MSG);
+        }
+    }
+
+    #[cfg(all(feature = "armor", feature = "async"))]
+    #[test]
+    fn async_armor_detection() {
+        use crate::armor::{ArmoredWriter, Format};
+
+        let sk = x25519::Identity::generate();
+
+        for format in [Format::Binary, Format::AsciiArmor] {
+            let mut encrypted = vec![];
+            {
+                let e = Encryptor::with_recipients(vec![Box::new(sk.to_public())]).unwrap();
+                let w = ArmoredWriter::wrap_output(&mut encrypted, format).unwrap();
+                let mut w = e.wrap_output(w).unwrap();
+                w.write_all(TEST_MSG).unwrap();
+                w.finish().and_then(|armor| armor.finish()).unwrap();
+            }
+
+            let d = {
+                let f = Decryptor::new_async_buffered(&encrypted[..]);
+                pin_mut!(f);
+                match f.as_mut().poll(&mut noop_context()) {
+                    Poll::Ready(Ok(Decryptor::Recipients(d))) => d,
+                    _ => panic!(),
+                }
+            };
+            let r = d.decrypt_async(iter::once(&sk as &dyn Identity)).unwrap();
+            pin_mut!(r);
+
+            let mut decrypted = vec![];
+            let mut tmp = [0; 4096];
+            loop {
+                match r.as_mut().poll_read(&mut noop_context(), &mut tmp) {
+                    Poll::Ready(Ok(0)) => break,
+                    Poll::Ready(Ok(read)) => decrypted.extend_from_slice(&tmp[..read]),
+                    _ => panic!(),
+                }
+            }
+            assert_eq!(decrypted, TEST_MSG);
+        }
+    }
+
     #[cfg(feature = "async")]
     #[test]
     fn x25519_async_round_trip() {
@@ -367,10 +1139,166 @@ mod tests {
                 IdentityFileEntry::Native(sk) => sk as &dyn Identity,
                 #[cfg(feature = "plugin")]
                 IdentityFileEntry::Plugin(_) => unreachable!(),
+                #[cfg(feature = "pq")]
+                IdentityFileEntry::Pq(_) => unreachable!(),
+                #[cfg(feature = "fido2")]
+                IdentityFileEntry::Fido2(_) => unreachable!(),
+                #[cfg(feature = "yubikey")]
+                IdentityFileEntry::YubiKey(_) => unreachable!(),
             }),
+            TEST_MSG,
         );
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_header_is_written_incrementally() {
+        use crate::format::Header;
+        use futures::task::Context;
+        use std::{cmp, io, pin::Pin};
+
+        /// A sink that accepts a few bytes at a time, and is only ready every other poll.
+        #[derive(Default)]
+        struct SlowSink {
+            written: Vec<u8>,
+            ready: bool,
+            max_offered: usize,
+        }
+
+        impl AsyncWrite for SlowSink {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                self.max_offered = cmp::max(self.max_offered, buf.len());
+                if self.ready {
+                    self.ready = false;
+                    let n = cmp::min(buf.len(), 7);
+                    self.written.extend_from_slice(&buf[..n]);
+                    Poll::Ready(Ok(n))
+                } else {
+                    self.ready = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        /// A recipient that produces a fixed-size stanza without doing any work.
+        struct TestRecipient;
+
+        impl Recipient for TestRecipient {
+            fn wrap_file_key(
+                &self,
+                _: &age_core::format::FileKey,
+            ) -> Result<Vec<age_core::format::Stanza>, crate::EncryptError> {
+                Ok(vec![age_core::format::Stanza::new(
+                    "test".to_owned(),
+                    vec![],
+                    vec![0; 32],
+                )])
+            }
+        }
+
+        const RECIPIENTS: usize = 200;
+        let recipients = (0..RECIPIENTS)
+            .map(|_| Box::new(TestRecipient) as Box<dyn Recipient + Send>)
+            .collect();
+        let e = Encryptor::with_recipients(recipients).unwrap();
+
+        let mut sink = SlowSink::default();
+        let mut cx = noop_context();
+        {
+            let f = e.wrap_async_output(&mut sink);
+            pin_mut!(f);
+
+            let mut pending = 0;
+            loop {
+                match f.as_mut().poll(&mut cx) {
+                    Poll::Ready(Ok(_)) => break,
+                    Poll::Ready(Err(e)) => panic!("Unexpected error: {}", e),
+                    Poll::Pending => pending += 1,
+                }
+            }
+            assert!(pending > 0);
+        }
+
+        // The whole header (including the grease stanza) was written, but the sink was
+        // never offered more than a single recipient stanza at a time.
+        match Header::read(&sink.written[..]).unwrap() {
+            Header::V1(h) => assert_eq!(h.recipients.len(), RECIPIENTS + 1),
+            Header::Unknown(_) => panic!("Unexpected header version"),
+        }
+        assert!(sink.max_offered < 256);
+    }
+
+    #[test]
+    fn threshold_round_trip() {
+        let keys: Vec<_> = (0..3).map(|_| x25519::Identity::generate()).collect();
+        let recipients = keys
+            .iter()
+            .map(|sk| Box::new(sk.to_public()) as Box<dyn Recipient + Send>)
+            .collect();
+        assert!(Encryptor::with_threshold(0, vec![]).is_none());
+        assert!(Encryptor::with_threshold(
+            4,
+            keys.iter()
+                .map(|sk| Box::new(sk.to_public()) as Box<dyn Recipient + Send>)
+                .collect()
+        )
+        .is_none());
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_threshold(2, recipients).unwrap();
+        {
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(TEST_MSG).unwrap();
+            w.finish().unwrap();
+        }
+
+        let decrypt = |identities: &[&x25519::Identity]| {
+            let d = match Decryptor::new(&encrypted[..]) {
+                Ok(Decryptor::Recipients(d)) => d,
+                _ => panic!(),
+            };
+            d.decrypt(identities.iter().map(|i| *i as &dyn Identity))
+                .map(|mut r| {
+                    let mut decrypted = vec![];
+                    r.read_to_end(&mut decrypted).unwrap();
+                    decrypted
+                })
+        };
+
+        // Any two of the three identities can decrypt.
+        for pair in [[0, 1], [1, 2], [2, 0]] {
+            let identities = [&keys[pair[0]], &keys[pair[1]]];
+            assert_eq!(decrypt(&identities).unwrap(), TEST_MSG);
+        }
+        assert_eq!(decrypt(&[&keys[0], &keys[1], &keys[2]]).unwrap(), TEST_MSG);
+
+        // A single identity can't.
+        assert!(matches!(
+            decrypt(&[&keys[1]]),
+            Err(DecryptError::ThresholdNotMet {
+                threshold: 2,
+                shares: 1
+            })
+        ));
+        assert!(matches!(
+            decrypt(&[&x25519::Identity::generate()]),
+            Err(DecryptError::NoMatchingKeys)
+        ));
+    }
+
     #[test]
     fn scrypt_round_trip() {
         let test_msg = b"This is a test message. For testing.";
@@ -396,6 +1324,339 @@ mod tests {
         assert_eq!(&decrypted[..], &test_msg[..]);
     }
 
+    #[test]
+    fn peek_recipients() {
+        let key_a = x25519::Identity::generate();
+        let key_b = x25519::Identity::generate();
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![
+            Box::new(key_a.to_public()),
+            Box::new(key_b.to_public()),
+        ])
+        .unwrap();
+        e.wrap_output(&mut encrypted).unwrap().finish().unwrap();
+
+        let d = Decryptor::new(&encrypted[..]).unwrap();
+        let recipients = d.peek_recipients();
+        // The header also contains a grease stanza.
+        assert_eq!(recipients.iter().filter(|r| r.tag() == "X25519").count(), 2);
+        for r in recipients.iter().filter(|r| r.tag() == "X25519") {
+            assert_eq!(r.args().len(), 1);
+            assert_eq!(r.label(), None);
+        }
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![
+            Box::new(key_a.to_public()),
+            Box::new(Labeled::new("key-b", key_b.to_public()).unwrap()),
+        ])
+        .unwrap();
+        e.wrap_output(&mut encrypted).unwrap().finish().unwrap();
+
+        let d = Decryptor::new(&encrypted[..]).unwrap();
+        let labels: Vec<_> = d
+            .peek_recipients()
+            .iter()
+            .filter(|r| r.tag() == "X25519")
+            .map(|r| r.label())
+            .collect();
+        assert_eq!(labels, vec![None, Some("key-b")]);
+        match d {
+            Decryptor::Recipients(d) => {
+                assert!(d.decrypt(iter::once(&key_b as &dyn Identity)).is_ok())
+            }
+            _ => panic!(),
+        }
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_user_passphrase(SecretString::new("passphrase".to_string()));
+        e.wrap_output(&mut encrypted).unwrap().finish().unwrap();
+
+        let d = Decryptor::new(&encrypted[..]).unwrap();
+        let recipients = d.peek_recipients();
+        assert_eq!(recipients.len(), 1);
+        assert_eq!(recipients[0].tag(), "scrypt");
+        assert_eq!(recipients[0].args().len(), 2);
+    }
+
+    #[test]
+    fn detached_header_round_trip() {
+        let key = x25519::Identity::generate();
+
+        let mut header = vec![];
+        let mut payload = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        {
+            let mut w = e.wrap_output_detached(&mut header, &mut payload).unwrap();
+            w.write_all(TEST_MSG).unwrap();
+            w.finish().unwrap();
+        }
+        assert!(header.ends_with(b"\n"));
+
+        let decrypt = |d: Decryptor<&[u8]>| {
+            let d = match d {
+                Decryptor::Recipients(d) => d,
+                _ => panic!(),
+            };
+            let mut r = d.decrypt(iter::once(&key as &dyn Identity)).unwrap();
+            let mut decrypted = vec![];
+            r.read_to_end(&mut decrypted).unwrap();
+            decrypted
+        };
+
+        let d = Decryptor::new_detached(&header[..], &payload[..]).unwrap();
+        assert_eq!(d.peek_header_len(), header.len());
+        assert_eq!(decrypt(d), TEST_MSG);
+
+        // The header and payload together are a regular age file.
+        let file = [&header[..], &payload[..]].concat();
+        assert_eq!(decrypt(Decryptor::new(&file[..]).unwrap()), TEST_MSG);
+
+        // The header input can't contain anything else.
+        assert!(matches!(
+            Decryptor::new_detached(&file[..], &payload[..]),
+            Err(DecryptError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn header_parser() {
+        let key = x25519::Identity::generate();
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        {
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(TEST_MSG).unwrap();
+            w.finish().unwrap();
+        }
+
+        for packet_len in [1, 7, 100, encrypted.len()] {
+            let mut parser = HeaderParser::new();
+            let mut payload = vec![];
+            for packet in encrypted.chunks(packet_len) {
+                match parser.feed(packet).unwrap() {
+                    Progress::NeedMore => assert!(payload.is_empty()),
+                    Progress::Done { consumed } => payload.extend_from_slice(&packet[consumed..]),
+                }
+            }
+            assert!(parser.is_done());
+
+            let d = match parser.finish(&payload[..]).unwrap() {
+                Decryptor::Recipients(d) => d,
+                _ => panic!(),
+            };
+            assert_eq!(d.peek_header_len() + 16 + payload.len(), encrypted.len());
+            let mut r = d.decrypt(iter::once(&key as &dyn Identity)).unwrap();
+            let mut decrypted = vec![];
+            r.read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, TEST_MSG);
+        }
+
+        // A truncated header can't be finished.
+        let mut parser = HeaderParser::new();
+        assert_eq!(parser.feed(&encrypted[..20]).unwrap(), Progress::NeedMore);
+        assert!(matches!(
+            parser.finish(io::empty()),
+            Err(DecryptError::Io(_))
+        ));
+
+        // Invalid headers are detected as soon as possible.
+        let mut parser = HeaderParser::new();
+        assert!(matches!(
+            parser.feed(b"This is not an age file at all.\n"),
+            Err(DecryptError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn peek_header_len() {
+        let key = x25519::Identity::generate();
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        {
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(b"payload").unwrap();
+            w.finish().unwrap();
+        }
+        let file_len = encrypted.len();
+
+        // Embed the age file in a frame with trailing data.
+        encrypted.extend_from_slice(b"trailing data");
+        let mut input = io::Cursor::new(&encrypted);
+
+        let d = Decryptor::new(&mut input).unwrap();
+        let header_len = d.peek_header_len();
+        drop(d);
+
+        // The header ends with the MAC line.
+        let header = &encrypted[..header_len];
+        assert!(header.starts_with(b"age-encryption.org/v1\n"));
+        assert!(header.ends_with(b"\n"));
+        let mac_line = header[..header_len - 1].rsplit(|&b| b == b'\n').next();
+        assert!(mac_line.unwrap().starts_with(b"--- "));
+
+        // Only the header and nonce have been read.
+        assert_eq!(input.position(), header_len as u64 + 16);
+
+        // The rest of the age file is the encrypted payload.
+        let payload_len = file_len - header_len - 16;
+        assert_eq!(stream::plaintext_len(payload_len as u64), Some(7));
+    }
+
+    #[test]
+    fn peek_metadata() {
+        let key = x25519::Identity::generate();
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(
+            Labeled::new("primary", key.to_public()).unwrap(),
+        )])
+        .unwrap();
+        {
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(b"payload").unwrap();
+            w.finish().unwrap();
+        }
+
+        let d = Decryptor::new(&encrypted[..])
+            .unwrap()
+            .with_expected_len(encrypted.len() as u64);
+        let metadata = d.peek_metadata();
+        assert_eq!(metadata.version, "v1");
+        assert!(!metadata.passphrase);
+        assert_eq!(metadata.header_len, d.peek_header_len());
+        assert_eq!(metadata.plaintext_len_hint, Some(7));
+        assert_eq!(metadata.chunk_count_hint, Some(1));
+        let x25519 = metadata
+            .recipients
+            .iter()
+            .find(|r| r.tag == "X25519")
+            .unwrap();
+        assert_eq!(x25519.label.as_deref(), Some("primary"));
+        assert_eq!(x25519.args.len(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_metadata() {
+        let key = x25519::Identity::generate();
+        let recipient = key.to_public();
+
+        let json = serde_json::to_value(&recipient).unwrap();
+        assert_eq!(json, recipient.to_string());
+        assert_eq!(
+            serde_json::from_value::<x25519::Recipient>(json)
+                .unwrap()
+                .to_string(),
+            recipient.to_string()
+        );
+        assert!(serde_json::from_str::<x25519::Recipient>("\"age1invalid\"").is_err());
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(recipient)]).unwrap();
+        e.wrap_output(&mut encrypted).unwrap().finish().unwrap();
+
+        let d = Decryptor::new(&encrypted[..]).unwrap();
+        let metadata = d.peek_metadata();
+        let json = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(json["header_len"], metadata.header_len);
+        assert_eq!(
+            serde_json::to_value(d.peek_recipients()).unwrap(),
+            json["recipients"]
+        );
+        assert_eq!(
+            serde_json::from_value::<super::decryptor::FileMetadata>(json).unwrap(),
+            metadata
+        );
+    }
+
+    #[test]
+    fn progress_with_expected_len() {
+        let key = x25519::Identity::generate();
+        let test_msg = vec![42; 100 * 1024];
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        {
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(&test_msg).unwrap();
+            w.finish().unwrap();
+        }
+
+        fn decrypt<'a>(d: Decryptor<&'a [u8]>, key: &x25519::Identity) -> StreamReader<&'a [u8]> {
+            match d {
+                Decryptor::Recipients(d) => d.decrypt(iter::once(key as &dyn Identity)).unwrap(),
+                _ => panic!(),
+            }
+        }
+
+        // Without a hint, the total length of a non-seekable input is unknown.
+        let r = decrypt(Decryptor::new(&encrypted[..]).unwrap(), &key);
+        assert_eq!(r.progress().total(), None);
+        assert_eq!(r.progress().percentage(), None);
+
+        let mut r = decrypt(
+            Decryptor::new(&encrypted[..])
+                .unwrap()
+                .with_expected_len(encrypted.len() as u64),
+            &key,
+        );
+        assert_eq!(r.progress().processed(), 0);
+        assert_eq!(r.progress().total(), Some(test_msg.len() as u64));
+
+        let mut buf = vec![0; test_msg.len() / 2];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(r.progress().percentage(), Some(50.0));
+
+        let mut rest = vec![];
+        r.read_to_end(&mut rest).unwrap();
+        assert_eq!(r.progress().processed(), test_msg.len() as u64);
+        assert_eq!(r.progress().percentage(), Some(100.0));
+    }
+
+    #[test]
+    fn max_plaintext_len() {
+        let key = x25519::Identity::generate();
+        // Span several chunks, ending partway through one.
+        let test_msg = vec![7; 150 * 1024];
+
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        {
+            let mut w = e.wrap_output(&mut encrypted).unwrap();
+            w.write_all(&test_msg).unwrap();
+            w.finish().unwrap();
+        }
+
+        let decrypt = |max| -> io::Result<Vec<u8>> {
+            let d = Decryptor::new(&encrypted[..])
+                .unwrap()
+                .with_max_plaintext_len(max);
+            let mut r = match d {
+                Decryptor::Recipients(d) => d.decrypt(iter::once(&key as &dyn Identity)).unwrap(),
+                _ => panic!(),
+            };
+            let mut decrypted = vec![];
+            r.read_to_end(&mut decrypted).map(|_| decrypted)
+        };
+
+        assert_eq!(decrypt(test_msg.len() as u64).unwrap(), test_msg);
+
+        for max in [0, 64 * 1024, test_msg.len() as u64 - 1] {
+            let e = decrypt(max).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            let e = e
+                .get_ref()
+                .unwrap()
+                .downcast_ref::<stream::PlaintextTooLong>()
+                .unwrap();
+            assert_eq!(e.max(), max);
+        }
+    }
+
     #[cfg(feature = "ssh")]
     #[test]
     fn ssh_rsa_round_trip() {
@@ -404,7 +1665,11 @@ mod tests {
         let pk: crate::ssh::Recipient = crate::ssh::recipient::tests::TEST_SSH_RSA_PK
             .parse()
             .unwrap();
-        recipient_round_trip(vec![Box::new(pk)], iter::once(&sk as &dyn Identity));
+        recipient_round_trip(
+            vec![Box::new(pk)],
+            iter::once(&sk as &dyn Identity),
+            TEST_MSG,
+        );
     }
 
     #[cfg(all(feature = "ssh", feature = "async"))]
@@ -415,7 +1680,11 @@ mod tests {
         let pk: crate::ssh::Recipient = crate::ssh::recipient::tests::TEST_SSH_RSA_PK
             .parse()
             .unwrap();
-        recipient_async_round_trip(vec![Box::new(pk)], iter::once(&sk as &dyn Identity));
+        recipient_async_round_trip(
+            vec![Box::new(pk)],
+            iter::once(&sk as &dyn Identity),
+            TEST_MSG,
+        );
     }
 
     #[cfg(feature = "ssh")]
@@ -426,7 +1695,11 @@ mod tests {
         let pk: crate::ssh::Recipient = crate::ssh::recipient::tests::TEST_SSH_ED25519_PK
             .parse()
             .unwrap();
-        recipient_round_trip(vec![Box::new(pk)], iter::once(&sk as &dyn Identity));
+        recipient_round_trip(
+            vec![Box::new(pk)],
+            iter::once(&sk as &dyn Identity),
+            TEST_MSG,
+        );
     }
 
     #[cfg(all(feature = "ssh", feature = "async"))]
@@ -437,6 +1710,10 @@ mod tests {
         let pk: crate::ssh::Recipient = crate::ssh::recipient::tests::TEST_SSH_ED25519_PK
             .parse()
             .unwrap();
-        recipient_async_round_trip(vec![Box::new(pk)], iter::once(&sk as &dyn Identity));
+        recipient_async_round_trip(
+            vec![Box::new(pk)],
+            iter::once(&sk as &dyn Identity),
+            TEST_MSG,
+        );
     }
 }
@@ -0,0 +1,276 @@
+//! Splitting encryption between an online machine and an air-gapped machine.
+//!
+//! Wrapping the file key for some recipients needs network access (for example, a
+//! plugin for a remote key management service), while the plaintext may only be
+//! available on an air-gapped machine. Encryption can be split in two:
+//!
+//! - On the online machine, [`HeaderBundle::new`] creates the age header for the
+//!   recipients, and seals the file key in an envelope encrypted to a recipient whose
+//!   identity is held by the air-gapped machine. The bundle is written out with
+//!   [`HeaderBundle::write`], and carried to the air-gapped machine.
+//! - On the air-gapped machine, [`HeaderBundle::read`] and [`HeaderBundle::open`]
+//!   recover the file key, and [`PayloadEncryptor::wrap_output`] streams the plaintext
+//!   into an age file that can be decrypted by any of the recipients.
+//!
+//! The online machine generates the file key, so it must be trusted to not keep it.
+//! The envelope protects the file key while the bundle is carried between machines, and
+//! [`HeaderBundle::open`] checks the header's MAC with the file key, so a header that
+//! does not match the envelope (for example, because it was replaced in transit) is
+//! rejected.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Read, Write};
+//! use std::iter;
+//!
+//! # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+//! let recipient_key = age::x25519::Identity::generate();
+//! let airgap_key = age::x25519::Identity::generate();
+//!
+//! // On the online machine:
+//! let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient_key.to_public())])
+//!     .expect("we provided a recipient");
+//! let bundle = age::airgap::HeaderBundle::new(encryptor, Box::new(airgap_key.to_public()))?;
+//! let mut transfer = vec![];
+//! bundle.write(&mut transfer)?;
+//!
+//! // On the air-gapped machine:
+//! let bundle = age::airgap::HeaderBundle::read(&transfer[..])?;
+//! let encryptor = bundle.open(iter::once(&airgap_key as &dyn age::Identity))?;
+//! let mut encrypted = vec![];
+//! let mut writer = encryptor.wrap_output(&mut encrypted)?;
+//! writer.write_all(b"Hello world!")?;
+//! writer.finish()?;
+//!
+//! // The age file is decrypted as usual.
+//! let decryptor = match age::Decryptor::new(&encrypted[..])? {
+//!     age::Decryptor::Recipients(d) => d,
+//!     _ => unreachable!(),
+//! };
+//! let mut decrypted = vec![];
+//! let mut reader = decryptor.decrypt(iter::once(&recipient_key as &dyn age::Identity))?;
+//! reader.read_to_end(&mut decrypted)?;
+//! assert_eq!(decrypted, b"Hello world!");
+//! # Ok(())
+//! # }
+//! # run_main().unwrap();
+//! ```
+
+use age_core::{
+    format::{FileKey, FILE_KEY_BYTES},
+    secrecy::ExposeSecret,
+};
+use std::io::{self, Read, Write};
+use zeroize::Zeroize;
+
+use super::{Decryptor, Encryptor, Nonce};
+use crate::{
+    error::{DecryptError, EncryptError},
+    format::{Header, HeaderV1},
+    keys::{mac_key, v1_payload_key},
+    primitives::stream::{Stream, StreamWriter},
+    Identity, Recipient,
+};
+
+/// An age header, and the file key it wraps sealed in an envelope.
+///
+/// When written out, a bundle is the age header followed by the envelope, which is an
+/// age file containing the file key.
+pub struct HeaderBundle {
+    header: HeaderV1,
+    envelope: Vec<u8>,
+}
+
+impl HeaderBundle {
+    /// Creates the header for an age file that will be encrypted by `encryptor`, and
+    /// seals its file key in an envelope encrypted to `envelope_recipient`.
+    pub fn new(
+        encryptor: Encryptor,
+        envelope_recipient: Box<dyn Recipient + Send>,
+    ) -> Result<Self, EncryptError> {
+        let (header, file_key) = encryptor.prepare_header_v1()?;
+
+        let mut envelope = vec![];
+        let mut writer = Encryptor::with_recipients(vec![envelope_recipient])
+            .expect("we provided a recipient")
+            .wrap_output(&mut envelope)?;
+        writer.write_all(file_key.expose_secret())?;
+        writer.finish()?;
+
+        Ok(HeaderBundle { header, envelope })
+    }
+
+    /// Reads a bundle that was written with [`HeaderBundle::write`].
+    pub fn read<R: Read>(mut input: R) -> Result<Self, DecryptError> {
+        let header = match Header::read(&mut input)? {
+            Header::V1(header) => header,
+            Header::Unknown(_) => return Err(DecryptError::UnknownFormat),
+        };
+
+        let mut envelope = vec![];
+        input.read_to_end(&mut envelope)?;
+
+        Ok(HeaderBundle { header, envelope })
+    }
+
+    /// Writes this bundle to `output`.
+    ///
+    /// The bundle does not contain any secrets in the clear, but the header reveals the
+    /// recipients' stanzas in the same way as the age file that will be created from it.
+    pub fn write<W: Write>(&self, mut output: W) -> io::Result<()> {
+        self.header.write(&mut output)?;
+        output.write_all(&self.envelope)
+    }
+
+    /// Opens the envelope with the given identities, and checks that the file key it
+    /// contains is the one wrapped by the header.
+    ///
+    /// Returns [`DecryptError::InvalidMac`] if the header was not created with the file
+    /// key in the envelope.
+    pub fn open<'a>(
+        self,
+        identities: impl Iterator<Item = &'a dyn Identity>,
+    ) -> Result<PayloadEncryptor, DecryptError> {
+        let mut reader = match Decryptor::new(&self.envelope[..])? {
+            Decryptor::Recipients(d) => d.decrypt(identities)?,
+            // We never create passphrase-encrypted envelopes.
+            Decryptor::Passphrase(_) => return Err(DecryptError::InvalidHeader),
+        };
+
+        let mut buf = vec![];
+        let res = reader.read_to_end(&mut buf);
+        let file_key = match (res, buf.len()) {
+            (Ok(_), FILE_KEY_BYTES) => {
+                let mut file_key = [0; FILE_KEY_BYTES];
+                file_key.copy_from_slice(&buf);
+                let ret = FileKey::from(file_key);
+                file_key.zeroize();
+                Ok(ret)
+            }
+            (Ok(_), _) => Err(DecryptError::InvalidHeader),
+            (Err(e), _) => Err(e.into()),
+        };
+        buf.zeroize();
+        let file_key = file_key?;
+
+        self.header.verify_mac(mac_key(&file_key))?;
+
+        Ok(PayloadEncryptor {
+            header: self.header,
+            file_key,
+        })
+    }
+}
+
+/// Encryptor for creating an age file with the header from a [`HeaderBundle`].
+pub struct PayloadEncryptor {
+    header: HeaderV1,
+    file_key: FileKey,
+}
+
+impl PayloadEncryptor {
+    /// Creates a wrapper around a writer that will encrypt its input.
+    ///
+    /// Returns errors from the underlying writer while writing the header.
+    ///
+    /// You **MUST** call [`StreamWriter::finish`] when you are done writing, in order to
+    /// finish the encryption process. Failing to call [`StreamWriter::finish`] will
+    /// result in a truncated file that will fail to decrypt.
+    pub fn wrap_output<W: Write>(self, mut output: W) -> Result<StreamWriter<W>, EncryptError> {
+        let nonce = Nonce::random()?;
+        let payload_key = v1_payload_key(&self.file_key, &self.header, &nonce)
+            .expect("MAC was checked by HeaderBundle::open");
+        self.header.write(&mut output)?;
+        output.write_all(nonce.as_ref())?;
+        Ok(Stream::encrypt(payload_key, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::iter;
+
+    use super::HeaderBundle;
+    use crate::{x25519, DecryptError, Decryptor, Encryptor, Identity};
+
+    fn bundle(recipient: &x25519::Identity, airgap: &x25519::Identity) -> Vec<u8> {
+        let encryptor = Encryptor::with_recipients(vec![Box::new(recipient.to_public())]);
+        let bundle = HeaderBundle::new(encryptor.unwrap(), Box::new(airgap.to_public())).unwrap();
+        let mut buf = vec![];
+        bundle.write(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn airgap_round_trip() {
+        let recipient = x25519::Identity::generate();
+        let airgap = x25519::Identity::generate();
+        let transfer = bundle(&recipient, &airgap);
+
+        let bundle = HeaderBundle::read(&transfer[..]).unwrap();
+        let encryptor = bundle.open(iter::once(&airgap as &dyn Identity)).unwrap();
+        let plaintext: Vec<u8> = (0..100 * 1024).map(|i| i as u8).collect();
+        let mut encrypted = vec![];
+        let mut writer = encryptor.wrap_output(&mut encrypted).unwrap();
+        writer.write_all(&plaintext).unwrap();
+        writer.finish().unwrap();
+
+        let decryptor = match Decryptor::new(&encrypted[..]).unwrap() {
+            Decryptor::Recipients(d) => d,
+            _ => panic!(),
+        };
+        let mut reader = decryptor
+            .decrypt(iter::once(&recipient as &dyn Identity))
+            .unwrap();
+        let mut decrypted = vec![];
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        // The air-gapped machine's identity cannot decrypt the age file.
+        let decryptor = match Decryptor::new(&encrypted[..]).unwrap() {
+            Decryptor::Recipients(d) => d,
+            _ => panic!(),
+        };
+        assert!(matches!(
+            decryptor.decrypt(iter::once(&airgap as &dyn Identity)),
+            Err(DecryptError::NoMatchingKeys)
+        ));
+    }
+
+    #[test]
+    fn mismatched_header_is_rejected() {
+        let recipient = x25519::Identity::generate();
+        let airgap = x25519::Identity::generate();
+        let first = bundle(&recipient, &airgap);
+        let second = bundle(&recipient, &airgap);
+
+        // Combine the header of the first bundle with the envelope of the second.
+        let header_len = |bundle: &[u8]| match Decryptor::new(bundle).unwrap() {
+            Decryptor::Recipients(d) => d.peek_header_len(),
+            _ => panic!(),
+        };
+        let mut spliced = first[..header_len(&first)].to_vec();
+        spliced.extend_from_slice(&second[header_len(&second)..]);
+
+        let bundle = HeaderBundle::read(&spliced[..]).unwrap();
+        assert!(matches!(
+            bundle.open(iter::once(&airgap as &dyn Identity)),
+            Err(DecryptError::InvalidMac)
+        ));
+    }
+
+    #[test]
+    fn wrong_identity_is_rejected() {
+        let recipient = x25519::Identity::generate();
+        let airgap = x25519::Identity::generate();
+        let transfer = bundle(&recipient, &airgap);
+
+        let bundle = HeaderBundle::read(&transfer[..]).unwrap();
+        assert!(matches!(
+            bundle.open(iter::once(&recipient as &dyn Identity)),
+            Err(DecryptError::NoMatchingKeys)
+        ));
+    }
+}
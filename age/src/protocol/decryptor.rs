@@ -8,16 +8,110 @@ use std::io::Read;
 
 use super::Nonce;
 use crate::{
+    audit::StanzaRecord,
     error::DecryptError,
-    format::Header,
+    format::{Header, HeaderV1, Parsed},
     keys::v1_payload_key,
-    primitives::stream::{PayloadKey, Stream, StreamReader},
-    scrypt, Identity,
+    label,
+    primitives::stream::{self, PayloadKey, Stream, StreamReader},
+    scrypt,
+    threshold::Threshold,
+    util::select_file_key,
+    Identity,
 };
 
 #[cfg(feature = "async")]
 use futures::io::AsyncRead;
 
+/// A recipient stanza in an age file's header.
+///
+/// This exposes the stanza's type and arguments, so that callers can decide which
+/// identities to use (for example, whether to prompt for a hardware token) before
+/// attempting decryption. The stanza body, which contains the wrapped file key, is not
+/// exposed.
+#[derive(Clone, Copy, Debug)]
+pub struct RecipientStanza<'a> {
+    stanza: &'a Stanza,
+    label: Option<&'a str>,
+}
+
+impl<'a> RecipientStanza<'a> {
+    /// Returns the stanza's tag, which identifies its type (for example, `X25519`,
+    /// `ssh-ed25519`, or `scrypt`).
+    pub fn tag(&self) -> &'a str {
+        &self.stanza.tag
+    }
+
+    /// Returns the stanza's arguments.
+    ///
+    /// These are public values, such as ephemeral public keys and key tags.
+    pub fn args(&self) -> &'a [String] {
+        &self.stanza.args
+    }
+
+    /// Returns the label that the stanza was given with [`Labeled`], if any.
+    ///
+    /// The label has not been authenticated: until the file has been decrypted, anyone
+    /// could have changed it.
+    ///
+    /// [`Labeled`]: crate::Labeled
+    pub fn label(&self) -> Option<&'a str> {
+        self.label
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl<'a> serde::Serialize for RecipientStanza<'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        // This matches the representation of StanzaRecord, which can be deserialized.
+        let mut s = serializer.serialize_struct("RecipientStanza", 3)?;
+        s.serialize_field("tag", self.tag())?;
+        s.serialize_field("args", self.args())?;
+        s.serialize_field("label", &self.label())?;
+        s.end()
+    }
+}
+
+impl<'a> From<RecipientStanza<'a>> for StanzaRecord {
+    fn from(stanza: RecipientStanza<'a>) -> Self {
+        StanzaRecord {
+            tag: stanza.tag().to_owned(),
+            args: stanza.args().to_vec(),
+            label: stanza.label().map(|l| l.to_owned()),
+        }
+    }
+}
+
+/// A summary of an age file, which is available before it is decrypted.
+///
+/// None of this has been authenticated: until the file has been decrypted, anyone could
+/// have changed it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileMetadata {
+    /// The version of the age format (currently always `v1`).
+    pub version: String,
+    /// The recipient stanzas in the header, in order.
+    pub recipients: Vec<StanzaRecord>,
+    /// Whether the file is encrypted with a passphrase.
+    pub passphrase: bool,
+    /// The length in bytes of the header, including the MAC line.
+    pub header_len: usize,
+    /// An estimate of the length of the plaintext, if the length of the age file was
+    /// provided with [`Decryptor::with_expected_len`].
+    ///
+    /// [`Decryptor::with_expected_len`]: crate::Decryptor::with_expected_len
+    pub plaintext_len_hint: Option<u64>,
+    /// The number of chunks in the encrypted payload, if the length of the age file was
+    /// provided with [`Decryptor::with_expected_len`].
+    ///
+    /// [`Decryptor::with_expected_len`]: crate::Decryptor::with_expected_len
+    pub chunk_count_hint: Option<u64>,
+}
+
 struct BaseDecryptor<R> {
     /// The age file.
     input: R,
@@ -25,9 +119,58 @@ struct BaseDecryptor<R> {
     header: Header,
     /// The age file's AEAD nonce
     nonce: Nonce,
+    /// The expected length of the age file, if provided by the caller.
+    expected_len: Option<u64>,
+    /// The maximum length of the plaintext, if provided by the caller.
+    max_plaintext_len: Option<u64>,
 }
 
 impl<R> BaseDecryptor<R> {
+    fn header_len(&self) -> usize {
+        match &self.header {
+            Header::V1(header) => header
+                .encoded_len()
+                .expect("Decryptors are only constructed from parsed headers"),
+            Header::Unknown(_) => unreachable!(),
+        }
+    }
+
+    /// Returns the length of the payload (after the nonce) from the expected length of
+    /// the age file.
+    fn payload_len_hint(&self) -> Option<u64> {
+        self.expected_len?
+            .checked_sub((self.header_len() + self.nonce.as_ref().len()) as u64)
+    }
+
+    /// Estimates the length of the plaintext from the expected length of the age file.
+    fn plaintext_len_hint(&self) -> Option<u64> {
+        stream::plaintext_len(self.payload_len_hint()?)
+    }
+
+    fn peek_recipients(&self) -> Vec<RecipientStanza<'_>> {
+        match &self.header {
+            Header::V1(header) => label::labeled_stanzas(&header.recipients)
+                .map(|(stanza, label)| RecipientStanza { stanza, label })
+                .collect(),
+            Header::Unknown(_) => unreachable!(),
+        }
+    }
+
+    fn metadata(&self, passphrase: bool) -> FileMetadata {
+        FileMetadata {
+            version: "v1".to_owned(),
+            recipients: self
+                .peek_recipients()
+                .into_iter()
+                .map(StanzaRecord::from)
+                .collect(),
+            passphrase,
+            header_len: self.header_len(),
+            plaintext_len_hint: self.plaintext_len_hint(),
+            chunk_count_hint: self.payload_len_hint().and_then(stream::chunk_count),
+        }
+    }
+
     fn obtain_payload_key<F>(&self, mut filter: F) -> Result<PayloadKey, DecryptError>
     where
         F: FnMut(&[Stanza]) -> Option<Result<FileKey, DecryptError>>,
@@ -50,15 +193,126 @@ impl<R> RecipientsDecryptor<R> {
             input,
             header,
             nonce,
+            expected_len: None,
+            max_plaintext_len: None,
         })
     }
 
+    /// Returns the recipient stanzas in the age file's header.
+    pub fn peek_recipients(&self) -> Vec<RecipientStanza<'_>> {
+        self.0.peek_recipients()
+    }
+
+    /// Returns the length in bytes of the age file's header, including the MAC line.
+    ///
+    /// The payload of the age file starts immediately after the header, with a 16-byte
+    /// nonce that has also been read from the input. The decryptor has therefore read
+    /// exactly `peek_header_len() + 16` bytes from its input, and nothing beyond that.
+    ///
+    /// If the input was wrapped in an [`ArmoredReader`], this is the length of the
+    /// dearmored header.
+    ///
+    /// [`ArmoredReader`]: crate::armor::ArmoredReader
+    pub fn peek_header_len(&self) -> usize {
+        self.0.header_len()
+    }
+
+    /// Returns a summary of the age file.
+    pub fn peek_metadata(&self) -> FileMetadata {
+        self.0.metadata(false)
+    }
+
+    /// Sets the expected length of the entire age file, including the header.
+    ///
+    /// This is used to estimate the total length of the plaintext, which is reported by
+    /// [`StreamReader::progress`]. It is useful for inputs that can't be seeked to
+    /// determine their length, but where the length is known from elsewhere (such as
+    /// an HTTP `Content-Length` header).
+    pub fn with_expected_len(mut self, len: u64) -> Self {
+        self.0.expected_len = Some(len);
+        self
+    }
+
+    /// Sets the maximum length of the plaintext that will be decrypted.
+    ///
+    /// Once the plaintext would exceed `max` bytes, the [`StreamReader`] returns an
+    /// error containing a [`PlaintextTooLong`] instead of any further plaintext. This
+    /// is useful when decrypting untrusted files into memory, where a large payload
+    /// could otherwise exhaust it.
+    ///
+    /// The limit is checked as each chunk is decrypted, so no plaintext beyond it is
+    /// ever returned.
+    ///
+    /// [`PlaintextTooLong`]: crate::stream::PlaintextTooLong
+    pub fn with_max_plaintext_len(mut self, max: u64) -> Self {
+        self.0.max_plaintext_len = Some(max);
+        self
+    }
+
+    /// Unwraps the file key with the given identities, and derives the payload key.
+    ///
+    /// To avoid revealing through timing which identity matched which stanza, identities
+    /// that are not [interactive](Identity::is_interactive) are all tried, even after the
+    /// file key has been unwrapped. The data flow is:
+    ///
+    /// - Each identity tries the stanzas with [`Identity::unwrap_stanzas`], which by
+    ///   default tries every stanza for non-interactive identities.
+    /// - Interactive identities (such as plugins and hardware keys) are skipped once an
+    ///   earlier identity has matched a stanza, so that the user is not prompted for
+    ///   them. Whether they are invoked is not hidden.
+    /// - The result of the first identity that matched a stanza is selected from the
+    ///   results of all identities. If it is an error, that error is returned.
+    /// - The payload key is derived from the selected file key, and the header MAC is
+    ///   verified with it.
+    ///
+    /// Only the file key bytes are selected with constant-time conditional assignments.
+    /// The selection still branches on whether each identity matched and whether it
+    /// returned an error, and the time taken by each unwrap depends on the primitives
+    /// it uses, and on whether the stanza matched.
+    ///
+    /// If the file was encrypted to a threshold of recipients, the identities are tried
+    /// against the stanzas for every share of the file key in the same way, and the file
+    /// key is reconstructed from the shares they unwrap.
     fn obtain_payload_key<'a>(
         &self,
-        mut identities: impl Iterator<Item = &'a dyn Identity>,
+        identities: impl Iterator<Item = &'a dyn Identity>,
     ) -> Result<PayloadKey, DecryptError> {
+        let identities: Vec<_> = identities.collect();
         self.0
-            .obtain_payload_key(|r| identities.find_map(|key| key.unwrap_stanzas(r)))
+            .obtain_payload_key(|r| unwrap_file_key(r, &identities))
+    }
+}
+
+/// Unwraps the file key from a header's stanzas with the given identities, handling
+/// headers for a threshold of recipients.
+///
+/// See [`RecipientsDecryptor::obtain_payload_key`] for how the identities are tried.
+///
+/// The header MAC is not checked.
+pub(crate) fn unwrap_file_key(
+    stanzas: &[Stanza],
+    identities: &[&dyn Identity],
+) -> Option<Result<FileKey, DecryptError>> {
+    // `skip_interactive` is set by the threshold logic once enough shares have been
+    // unwrapped.
+    let unwrap = |stanzas: &[Stanza], skip_interactive: bool| {
+        let mut results = Vec::with_capacity(identities.len());
+        let mut matched = skip_interactive;
+        for key in identities {
+            if matched && key.is_interactive() {
+                continue;
+            }
+            let result = key.unwrap_stanzas(stanzas);
+            matched |= result.is_some();
+            results.push(result);
+        }
+        select_file_key(results)
+    };
+
+    match Threshold::parse(stanzas) {
+        Ok(Some(threshold)) => threshold.unwrap_file_key(unwrap),
+        Ok(None) => unwrap(stanzas, false),
+        Err(e) => Some(Err(e)),
     }
 }
 
@@ -70,8 +324,13 @@ impl<R: Read> RecipientsDecryptor<R> {
         self,
         identities: impl Iterator<Item = &'a dyn Identity>,
     ) -> Result<StreamReader<R>, DecryptError> {
-        self.obtain_payload_key(identities)
-            .map(|payload_key| Stream::decrypt(payload_key, self.0.input))
+        let hint = self.0.plaintext_len_hint();
+        let max = self.0.max_plaintext_len;
+        self.obtain_payload_key(identities).map(|payload_key| {
+            Stream::decrypt(payload_key, self.0.input)
+                .with_plaintext_len_hint(hint)
+                .with_max_plaintext_len(max)
+        })
     }
 }
 
@@ -85,8 +344,13 @@ impl<R: AsyncRead + Unpin> RecipientsDecryptor<R> {
         self,
         identities: impl Iterator<Item = &'a dyn Identity>,
     ) -> Result<StreamReader<R>, DecryptError> {
-        self.obtain_payload_key(identities)
-            .map(|payload_key| Stream::decrypt_async(payload_key, self.0.input))
+        let hint = self.0.plaintext_len_hint();
+        let max = self.0.max_plaintext_len;
+        self.obtain_payload_key(identities).map(|payload_key| {
+            Stream::decrypt_async(payload_key, self.0.input)
+                .with_plaintext_len_hint(hint)
+                .with_max_plaintext_len(max)
+        })
     }
 }
 
@@ -99,9 +363,46 @@ impl<R> PassphraseDecryptor<R> {
             input,
             header,
             nonce,
+            expected_len: None,
+            max_plaintext_len: None,
         })
     }
 
+    /// Returns the recipient stanzas in the age file's header.
+    ///
+    /// A passphrase-encrypted file always has a single `scrypt` stanza.
+    pub fn peek_recipients(&self) -> Vec<RecipientStanza<'_>> {
+        self.0.peek_recipients()
+    }
+
+    /// Returns the length in bytes of the age file's header, including the MAC line.
+    ///
+    /// See [`RecipientsDecryptor::peek_header_len`] for details.
+    pub fn peek_header_len(&self) -> usize {
+        self.0.header_len()
+    }
+
+    /// Returns a summary of the age file.
+    pub fn peek_metadata(&self) -> FileMetadata {
+        self.0.metadata(true)
+    }
+
+    /// Sets the expected length of the entire age file, including the header.
+    ///
+    /// See [`RecipientsDecryptor::with_expected_len`] for details.
+    pub fn with_expected_len(mut self, len: u64) -> Self {
+        self.0.expected_len = Some(len);
+        self
+    }
+
+    /// Sets the maximum length of the plaintext that will be decrypted.
+    ///
+    /// See [`RecipientsDecryptor::with_max_plaintext_len`] for details.
+    pub fn with_max_plaintext_len(mut self, max: u64) -> Self {
+        self.0.max_plaintext_len = Some(max);
+        self
+    }
+
     fn obtain_payload_key(
         &self,
         passphrase: &SecretString,
@@ -128,8 +429,14 @@ impl<R: Read> PassphraseDecryptor<R> {
         passphrase: &SecretString,
         max_work_factor: Option<u8>,
     ) -> Result<StreamReader<R>, DecryptError> {
+        let hint = self.0.plaintext_len_hint();
+        let max = self.0.max_plaintext_len;
         self.obtain_payload_key(passphrase, max_work_factor)
-            .map(|payload_key| Stream::decrypt(payload_key, self.0.input))
+            .map(|payload_key| {
+                Stream::decrypt(payload_key, self.0.input)
+                    .with_plaintext_len_hint(hint)
+                    .with_max_plaintext_len(max)
+            })
     }
 }
 
@@ -147,7 +454,181 @@ impl<R: AsyncRead + Unpin> PassphraseDecryptor<R> {
         passphrase: &SecretString,
         max_work_factor: Option<u8>,
     ) -> Result<StreamReader<R>, DecryptError> {
+        let hint = self.0.plaintext_len_hint();
+        let max = self.0.max_plaintext_len;
         self.obtain_payload_key(passphrase, max_work_factor)
-            .map(|payload_key| Stream::decrypt_async(payload_key, self.0.input))
+            .map(|payload_key| {
+                Stream::decrypt_async(payload_key, self.0.input)
+                    .with_plaintext_len_hint(hint)
+                    .with_max_plaintext_len(max)
+            })
+    }
+}
+
+/// The progress of a [`HeaderParser`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// The header is incomplete, and more bytes need to be fed to the parser.
+    NeedMore,
+    /// The header is complete.
+    ///
+    /// Only the first `consumed` bytes of the most recent input were part of the header;
+    /// the remainder is the start of the payload.
+    Done {
+        /// The number of bytes of the most recent input that were consumed.
+        consumed: usize,
+    },
+}
+
+enum ParserState {
+    Header {
+        /// The bytes of the header received so far.
+        data: Vec<u8>,
+        /// The length that `data` needs to reach before it is parsed again.
+        needed: usize,
+    },
+    Nonce {
+        header: HeaderV1,
+        nonce: [u8; 16],
+        /// The number of bytes of the nonce received so far.
+        received: usize,
+    },
+}
+
+/// A push-based parser for the header of an age file.
+///
+/// [`Decryptor::new`] blocks on its input until the whole header has been read. This
+/// parser is instead fed bytes as they arrive, which allows event-driven programs (such
+/// as network servers) to construct a decryptor from partially-received data without
+/// blocking a thread.
+///
+/// [`Decryptor::new`]: crate::Decryptor::new
+///
+/// # Examples
+///
+/// ```
+/// use age::decryptor::{HeaderParser, Progress};
+/// use std::io::Write;
+///
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let key = age::x25519::Identity::generate();
+/// # let encrypted = {
+/// #     let encryptor = age::Encryptor::with_recipients(vec![Box::new(key.to_public())])
+/// #         .expect("we provided a recipient");
+/// #     let mut encrypted = vec![];
+/// #     let mut writer = encryptor.wrap_output(&mut encrypted)?;
+/// #     writer.write_all(b"Hello world!")?;
+/// #     writer.finish()?;
+/// #     encrypted
+/// # };
+/// let mut parser = HeaderParser::new();
+/// let mut payload = vec![];
+/// // Bytes arrive from the network in packets.
+/// for packet in encrypted.chunks(10) {
+///     if let Progress::Done { consumed } = parser.feed(packet)? {
+///         payload.extend_from_slice(&packet[consumed..]);
+///     }
+/// }
+/// let decryptor = parser.finish(&payload[..])?;
+/// # Ok(())
+/// # }
+/// # run_main().unwrap();
+/// ```
+pub struct HeaderParser {
+    state: ParserState,
+}
+
+impl Default for HeaderParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeaderParser {
+    /// Creates a parser that has not been fed any bytes.
+    pub fn new() -> Self {
+        HeaderParser {
+            state: ParserState::Header {
+                data: vec![],
+                needed: 0,
+            },
+        }
+    }
+
+    /// Feeds the next bytes of the age file to the parser.
+    ///
+    /// Once the header is complete, this returns [`Progress::Done`] with the number of
+    /// bytes of `input` that it consumed. Any further calls consume nothing, and return
+    /// `Progress::Done { consumed: 0 }`.
+    ///
+    /// Returns an error if the bytes do not start a valid age file.
+    pub fn feed(&mut self, mut input: &[u8]) -> Result<Progress, DecryptError> {
+        let input_len = input.len();
+        loop {
+            match &mut self.state {
+                ParserState::Header { data, needed } => {
+                    let n = std::cmp::min(*needed - data.len(), input.len());
+                    data.extend_from_slice(&input[..n]);
+                    input = &input[n..];
+                    if data.len() < *needed {
+                        return Ok(Progress::NeedMore);
+                    }
+
+                    match Header::parse(data)? {
+                        Parsed::Header(Header::V1(header)) => {
+                            self.state = ParserState::Nonce {
+                                header,
+                                nonce: [0; 16],
+                                received: 0,
+                            }
+                        }
+                        Parsed::Header(Header::Unknown(_)) => {
+                            return Err(DecryptError::UnknownFormat)
+                        }
+                        Parsed::Needed(n) => *needed = data.len() + n,
+                    }
+                }
+                ParserState::Nonce {
+                    nonce, received, ..
+                } => {
+                    if *received == nonce.len() {
+                        return Ok(Progress::Done { consumed: 0 });
+                    }
+
+                    let n = std::cmp::min(nonce.len() - *received, input.len());
+                    nonce[*received..*received + n].copy_from_slice(&input[..n]);
+                    *received += n;
+                    return Ok(if *received == nonce.len() {
+                        Progress::Done {
+                            consumed: input_len - input.len() + n,
+                        }
+                    } else {
+                        Progress::NeedMore
+                    });
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the header is complete.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, ParserState::Nonce { received: 16, .. })
+    }
+
+    /// Constructs a decryptor from the parsed header, and the rest of the age file.
+    ///
+    /// `input` must start with the bytes that followed the header, including any bytes
+    /// that were fed to the parser but not consumed.
+    ///
+    /// Returns an error if the header is not complete.
+    pub fn finish<R>(self, input: R) -> Result<super::Decryptor<R>, DecryptError> {
+        match self.state {
+            ParserState::Nonce {
+                header,
+                nonce,
+                received: 16,
+            } => super::Decryptor::from_v1_header(input, header, Nonce(nonce)),
+            _ => Err(DecryptError::Io(std::io::ErrorKind::UnexpectedEof.into())),
+        }
     }
 }
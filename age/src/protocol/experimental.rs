@@ -0,0 +1,143 @@
+//! Hooks for prototyping new versions of the age file format.
+//!
+//! The age specification allows any version string to follow the intro line, and says
+//! that other versions can change anything after the first line. [`VersionHandlers`]
+//! lets you plug decryption logic for experimental versions in alongside the native
+//! `v1` support, without needing to fork the rest of this crate.
+//!
+//! These APIs are unstable, and may change or be removed in any release.
+
+use std::io::Read;
+
+use super::{Decryptor, Nonce};
+use crate::{error::DecryptError, format::Header};
+
+/// The version string of the native age format.
+const V1_VERSION: &str = "v1";
+
+/// A handler for decrypting files that use an experimental age format version.
+pub trait VersionHandler<R> {
+    /// Decrypts a file using this handler's format version.
+    ///
+    /// `input` is positioned immediately after the intro line of the file. Returns a
+    /// reader for the plaintext, which may read from `input` as it goes.
+    fn decrypt<'a>(&self, input: R) -> Result<Box<dyn Read + 'a>, DecryptError>
+    where
+        R: 'a;
+}
+
+/// A decryptor for either a native `v1` age file, or a file with an experimental
+/// format version.
+///
+/// `'a` is the lifetime of the plaintext reader for experimental versions, which may
+/// borrow from the input.
+pub enum VersionedDecryptor<'a, R> {
+    /// A native `v1` age file.
+    V1(Decryptor<R>),
+    /// A file that was decrypted by a registered [`VersionHandler`].
+    Experimental {
+        /// The version string from the file's intro line.
+        version: String,
+        /// The plaintext of the file.
+        plaintext: Box<dyn Read + 'a>,
+    },
+}
+
+/// A set of handlers for experimental age format versions.
+pub struct VersionHandlers<R> {
+    handlers: Vec<(String, Box<dyn VersionHandler<R>>)>,
+}
+
+impl<R> Default for VersionHandlers<R> {
+    fn default() -> Self {
+        VersionHandlers { handlers: vec![] }
+    }
+}
+
+impl<R: Read> VersionHandlers<R> {
+    /// Creates an empty set of version handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for the given version string.
+    ///
+    /// If a handler was already registered for `version`, it is replaced. Handlers for
+    /// `v1` are ignored, because native age files are always handled by [`Decryptor`].
+    pub fn register(mut self, version: &str, handler: impl VersionHandler<R> + 'static) -> Self {
+        if version != V1_VERSION {
+            self.handlers.retain(|(v, _)| v != version);
+            self.handlers.push((version.to_owned(), Box::new(handler)));
+        }
+        self
+    }
+
+    /// Attempts to create a decryptor for an age file, dispatching to a registered
+    /// handler if the file uses an experimental format version.
+    ///
+    /// Returns [`DecryptError::UnknownFormat`] if the file's version is neither `v1` nor
+    /// registered.
+    pub fn decrypt<'a>(&self, mut input: R) -> Result<VersionedDecryptor<'a, R>, DecryptError>
+    where
+        R: 'a,
+    {
+        match Header::read(&mut input)? {
+            Header::V1(v1_header) => {
+                let nonce = Nonce::read(&mut input)?;
+                Decryptor::from_v1_header(input, v1_header, nonce).map(VersionedDecryptor::V1)
+            }
+            Header::Unknown(version) => match self.handlers.iter().find(|(v, _)| v == &version) {
+                Some((_, handler)) => handler
+                    .decrypt(input)
+                    .map(|plaintext| VersionedDecryptor::Experimental { version, plaintext }),
+                None => Err(DecryptError::UnknownFormat),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{VersionHandler, VersionHandlers, VersionedDecryptor};
+    use crate::DecryptError;
+
+    struct Passthrough;
+
+    impl<R: Read> VersionHandler<R> for Passthrough {
+        fn decrypt<'a>(&self, input: R) -> Result<Box<dyn Read + 'a>, DecryptError>
+        where
+            R: 'a,
+        {
+            Ok(Box::new(input))
+        }
+    }
+
+    #[test]
+    fn experimental_version_dispatch() {
+        // The plaintext reader can borrow from a non-'static input.
+        let file = b"age-encryption.org/v2-test\nplaintext".to_vec();
+        let file = &file[..];
+
+        // Unregistered versions are rejected.
+        assert!(matches!(
+            VersionHandlers::new().decrypt(file),
+            Err(DecryptError::UnknownFormat)
+        ));
+
+        let handlers = VersionHandlers::new().register("v2-test", Passthrough);
+        match handlers.decrypt(file) {
+            Ok(VersionedDecryptor::Experimental {
+                version,
+                mut plaintext,
+            }) => {
+                assert_eq!(version, "v2-test");
+                let mut buf = vec![];
+                plaintext.read_to_end(&mut buf).unwrap();
+                assert_eq!(buf, b"plaintext");
+            }
+            _ => panic!("experimental version was not dispatched"),
+        };
+    }
+}
@@ -0,0 +1,221 @@
+//! Exchanging recipients as QR codes.
+//!
+//! A recipient can be rendered as a QR code with [`x25519::Recipient::to_qr_svg`], and
+//! the scanned string parsed with [`x25519::Recipient::from_scanned`]. Scanning is not
+//! always reliable, so if a scanned recipient is invalid but differs in one character
+//! from a valid recipient, [`ScanError::Misread`] suggests the correction.
+//!
+//! Recipients and small armored files can also be shown in a terminal as a QR code,
+//! with [`to_terminal`].
+//!
+//! [`x25519::Recipient::to_qr_svg`]: crate::x25519::Recipient::to_qr_svg
+//! [`x25519::Recipient::from_scanned`]: crate::x25519::Recipient::from_scanned
+
+use qrcode::{
+    render::{svg, unicode::Dense1x2},
+    EcLevel, QrCode,
+};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::wfl;
+
+/// The characters of the Bech32 data encoding.
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Errors that can occur while parsing a scanned recipient.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanError {
+    /// The scanned string is not a valid recipient.
+    Invalid,
+    /// The scanned string is not a valid recipient, but would be if the character at
+    /// `position` was replaced. Positions count from 0, and ignore whitespace.
+    ///
+    /// `correction` is the recipient with the character replaced. It is only a hint:
+    /// it should be confirmed by the user (for example, by comparing it with the
+    /// recipient on the other device) before it is used.
+    Misread {
+        /// The position of the misread character.
+        position: usize,
+        /// The corrected recipient.
+        correction: String,
+    },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::Invalid => wfl!(f, "err-scan-invalid"),
+            ScanError::Misread {
+                position,
+                correction,
+            } => {
+                // Positions are shown to the user counting from 1.
+                let position = position + 1;
+                write!(
+                    f,
+                    "{}",
+                    i18n_embed_fl::fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-scan-misread",
+                        position = position,
+                        correction = correction.as_str(),
+                    )
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+/// The error correction level of the QR codes we render.
+///
+/// A QR code displayed on a screen or printed may be scanned at an angle or partly
+/// obscured, so we use the second-highest level. An upper-case recipient at this level
+/// fits in a version 4 (33x33) QR code.
+const EC_LEVEL: EcLevel = EcLevel::Q;
+
+/// Renders `data` as a QR code, in SVG format.
+pub(crate) fn to_svg(data: &str) -> String {
+    QrCode::with_error_correction_level(data, EC_LEVEL)
+        .expect("data fits in a QR code")
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build()
+}
+
+/// Renders `data` as a QR code for display in a terminal.
+///
+/// Each character covers two rows of the QR code, using Unicode half blocks. As with
+/// `qrencode -t UTF8`, the code is drawn for a terminal with a dark background: light
+/// modules use the foreground color, and dark modules are left as background.
+///
+/// Returns `None` if `data` is too long to fit in a QR code. At the error correction
+/// level we use, a QR code holds up to 1663 bytes of arbitrary data, such as an
+/// armored age file.
+pub fn to_terminal(data: &str) -> Option<String> {
+    QrCode::with_error_correction_level(data, EC_LEVEL)
+        .ok()
+        .map(|code| {
+            code.render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build()
+        })
+}
+
+/// Parses a scanned Bech32 string with the human-readable part `hrp`.
+///
+/// Whitespace (such as line breaks inserted by a scanner) is ignored, and upper case is
+/// accepted (QR codes encode upper-case strings more compactly).
+pub(crate) fn parse_scanned<T: FromStr>(s: &str, hrp: &str) -> Result<T, ScanError> {
+    let normalized: String = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    if let Ok(parsed) = normalized.parse() {
+        return Ok(parsed);
+    }
+
+    // Look for a single misread character in the data part. Bech32 detects any single
+    // substitution, so a unique correction is a strong hint of what was scanned.
+    let data_start = hrp.len() + 1;
+    if !normalized.is_ascii() || !normalized.starts_with(hrp) || normalized.len() <= data_start {
+        return Err(ScanError::Invalid);
+    }
+    let mut candidate = normalized.into_bytes();
+    let mut corrections = vec![];
+    for position in data_start..candidate.len() {
+        let scanned = candidate[position];
+        for &c in BECH32_CHARSET.iter().filter(|&&c| c != scanned) {
+            candidate[position] = c;
+            let correction = std::str::from_utf8(&candidate).expect("is ASCII");
+            if correction.parse::<T>().is_ok() {
+                corrections.push((position, correction.to_owned()));
+            }
+        }
+        candidate[position] = scanned;
+    }
+
+    if corrections.len() == 1 {
+        let (position, correction) = corrections.pop().expect("len is 1");
+        Err(ScanError::Misread {
+            position,
+            correction,
+        })
+    } else {
+        Err(ScanError::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qrcode::{QrCode, Version};
+
+    use super::{to_terminal, ScanError, EC_LEVEL};
+    use crate::x25519;
+
+    #[test]
+    fn upper_case_is_compact() {
+        let recipient = x25519::Identity::generate().to_public().to_string();
+        let upper = QrCode::with_error_correction_level(recipient.to_uppercase(), EC_LEVEL);
+        let lower = QrCode::with_error_correction_level(recipient, EC_LEVEL);
+        assert_eq!(upper.unwrap().version(), Version::Normal(4));
+        assert_eq!(lower.unwrap().version(), Version::Normal(6));
+    }
+
+    #[test]
+    fn svg_is_rendered() {
+        let recipient = x25519::Identity::generate().to_public();
+        let svg = recipient.to_qr_svg();
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn terminal_is_rendered() {
+        let recipient = x25519::Identity::generate().to_public().to_string();
+        let code = to_terminal(&recipient).unwrap();
+
+        // A version 6 code is 41 modules wide, plus a quiet zone of 4 on each side. Each
+        // line holds two rows of modules.
+        let lines: Vec<_> = code.lines().collect();
+        assert_eq!(lines.len(), (41 + 8 + 1) / 2);
+        assert!(lines.iter().all(|line| line.chars().count() == 41 + 8));
+
+        assert_eq!(to_terminal(&"a".repeat(1663)).map(|_| ()), Some(()));
+        assert_eq!(to_terminal(&"a".repeat(1664)), None);
+    }
+
+    #[test]
+    fn scanned_recipients() {
+        let recipient = x25519::Identity::generate().to_public().to_string();
+        let parse = |s: &str| x25519::Recipient::from_scanned(s).map(|r| r.to_string());
+
+        assert_eq!(parse(&recipient), Ok(recipient.clone()));
+        assert_eq!(parse(&recipient.to_uppercase()), Ok(recipient.clone()));
+        let (a, b) = recipient.split_at(30);
+        assert_eq!(parse(&format!(" {}\n{}\r\n", a, b)), Ok(recipient.clone()));
+
+        // A single misread character is found, but not silently corrected.
+        let mut misread = recipient.clone().into_bytes();
+        misread[20] = if misread[20] == b'q' { b'p' } else { b'q' };
+        let misread = String::from_utf8(misread).unwrap();
+        assert_eq!(
+            x25519::Recipient::from_scanned(&misread).map(|r| r.to_string()),
+            Err(ScanError::Misread {
+                position: 20,
+                correction: recipient.clone(),
+            })
+        );
+
+        assert_eq!(parse("age1"), Err(ScanError::Invalid));
+        assert_eq!(parse("hello world"), Err(ScanError::Invalid));
+        assert_eq!(
+            parse(&recipient.replace("age1", "age-plugin-foo1")),
+            Err(ScanError::Invalid)
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Re-encrypting age files to new recipients.
+
+use std::io::{self, Read, Write};
+
+use zeroize::Zeroize;
+
+use crate::{
+    error::EncryptError,
+    stream::{Progress, StreamReader},
+    Encryptor,
+};
+
+/// The size of the buffer used to move plaintext between the reader and the writer.
+///
+/// This matches the STREAM chunk size, so each read decrypts at most one chunk and each
+/// write encrypts at most one chunk.
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Re-encrypts an age file with a new [`Encryptor`], writing the result to `output`.
+///
+/// `reader` is the plaintext of the existing file, as returned by
+/// [`RecipientsDecryptor::decrypt`] or [`PassphraseDecryptor::decrypt`]. The plaintext is
+/// streamed directly into the new file, one chunk at a time, so it is never held in
+/// memory in full or written anywhere else. The plaintext buffer is zeroized before
+/// returning.
+///
+/// `progress` is called after each chunk is re-encrypted, with the progress through the
+/// existing file (see [`StreamReader::progress`]).
+///
+/// Returns `output` once the new file has been completely written. If this returns an
+/// error, `output` may contain a partially-written file, which must be discarded. In
+/// particular, an error is returned if the existing file is truncated or has been
+/// modified, in which case the new file would not contain the full plaintext.
+///
+/// [`RecipientsDecryptor::decrypt`]: crate::decryptor::RecipientsDecryptor::decrypt
+/// [`PassphraseDecryptor::decrypt`]: crate::decryptor::PassphraseDecryptor::decrypt
+///
+/// # Examples
+///
+/// ```
+/// use std::iter;
+///
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let old_key = age::x25519::Identity::generate();
+/// # let encrypted = {
+/// #     use std::io::Write;
+/// #     let encryptor = age::Encryptor::with_recipients(vec![Box::new(old_key.to_public())])
+/// #         .expect("we provided a recipient");
+/// #     let mut encrypted = vec![];
+/// #     let mut writer = encryptor.wrap_output(&mut encrypted)?;
+/// #     writer.write_all(b"Hello world!")?;
+/// #     writer.finish()?;
+/// #     encrypted
+/// # };
+/// let new_key = age::x25519::Identity::generate();
+///
+/// let decryptor = match age::Decryptor::new(&encrypted[..])? {
+///     age::Decryptor::Recipients(d) => d,
+///     _ => unreachable!(),
+/// };
+/// let reader = decryptor.decrypt(iter::once(&old_key as &dyn age::Identity))?;
+///
+/// let encryptor = age::Encryptor::with_recipients(vec![Box::new(new_key.to_public())])
+///     .expect("we provided a recipient");
+/// let rekeyed = age::rekey(reader, encryptor, vec![], |progress| {
+///     eprintln!("Re-encrypted {} bytes", progress.processed());
+/// })?;
+/// # Ok(())
+/// # }
+/// # run_main().unwrap();
+/// ```
+pub fn rekey<R: Read, W: Write>(
+    mut reader: StreamReader<R>,
+    encryptor: Encryptor,
+    output: W,
+    mut progress: impl FnMut(Progress),
+) -> Result<W, EncryptError> {
+    let mut writer = encryptor.wrap_output(output)?;
+
+    let mut buf = vec![0; BUF_SIZE];
+    let res = loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => {
+                if let Err(e) = writer.write_all(&buf[..n]) {
+                    break Err(e);
+                }
+                progress(reader.progress());
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => break Err(e),
+        }
+    };
+    buf.zeroize();
+    res?;
+
+    Ok(writer.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::iter;
+
+    use super::rekey;
+    use crate::{x25519, Decryptor, Encryptor, Identity};
+
+    fn encrypt(recipient: x25519::Recipient, plaintext: &[u8]) -> Vec<u8> {
+        let encryptor = Encryptor::with_recipients(vec![Box::new(recipient)]).unwrap();
+        let mut encrypted = vec![];
+        let mut writer = encryptor.wrap_output(&mut encrypted).unwrap();
+        writer.write_all(plaintext).unwrap();
+        writer.finish().unwrap();
+        encrypted
+    }
+
+    fn decrypt(identity: &x25519::Identity, encrypted: &[u8]) -> Result<Vec<u8>, ()> {
+        let decryptor = match Decryptor::new(encrypted).map_err(|_| ())? {
+            Decryptor::Recipients(d) => d,
+            _ => panic!(),
+        };
+        let mut reader = decryptor
+            .decrypt(iter::once(identity as &dyn Identity))
+            .map_err(|_| ())?;
+        let mut decrypted = vec![];
+        reader.read_to_end(&mut decrypted).map_err(|_| ())?;
+        Ok(decrypted)
+    }
+
+    #[test]
+    fn rekey_round_trip() {
+        let old_key = x25519::Identity::generate();
+        let new_key = x25519::Identity::generate();
+
+        // Span several chunks, ending partway through one.
+        let plaintext: Vec<u8> = (0..200 * 1024).map(|i| i as u8).collect();
+        let encrypted = encrypt(old_key.to_public(), &plaintext);
+
+        let reader = match Decryptor::new(&encrypted[..]).unwrap() {
+            Decryptor::Recipients(d) => d.decrypt(iter::once(&old_key as &dyn Identity)).unwrap(),
+            _ => panic!(),
+        };
+        let encryptor = Encryptor::with_recipients(vec![Box::new(new_key.to_public())]).unwrap();
+
+        let mut updates = vec![];
+        let rekeyed = rekey(reader, encryptor, vec![], |progress| {
+            updates.push(progress.processed())
+        })
+        .unwrap();
+
+        assert_eq!(updates.last(), Some(&(plaintext.len() as u64)));
+        assert!(updates.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(decrypt(&new_key, &rekeyed), Ok(plaintext));
+        assert_eq!(decrypt(&old_key, &rekeyed), Err(()));
+    }
+
+    #[test]
+    fn rekey_truncated() {
+        let old_key = x25519::Identity::generate();
+        let new_key = x25519::Identity::generate();
+
+        let plaintext = vec![42; 100 * 1024];
+        let mut encrypted = encrypt(old_key.to_public(), &plaintext);
+        encrypted.truncate(encrypted.len() - 100);
+
+        let reader = match Decryptor::new(&encrypted[..]).unwrap() {
+            Decryptor::Recipients(d) => d.decrypt(iter::once(&old_key as &dyn Identity)).unwrap(),
+            _ => panic!(),
+        };
+        let encryptor = Encryptor::with_recipients(vec![Box::new(new_key.to_public())]).unwrap();
+
+        assert!(rekey(reader, encryptor, vec![], |_| ()).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Reading age files from remote storage, without downloading them first.
+//!
+//! [`RemoteReader`] implements [`Read`] and [`Seek`] over any [`RangeSource`], which
+//! fetches byte ranges of a remote object. It can be passed to [`Decryptor::new`], and
+//! seeking the resulting [`StreamReader`] only fetches the ranges of the object that
+//! contain the chunks being read.
+//!
+//! Sources are provided for plain HTTP servers and for Amazon S3 (or S3-compatible
+//! object stores), behind their own feature flags:
+//!
+//! | Storage            | Feature flag  | Type           |
+//! |--------------------|---------------|----------------|
+//! | HTTP(S) URLs       | `remote-http` | `HttpSource`   |
+//! | Amazon S3          | `remote-s3`   | `S3Source`     |
+//!
+//! The ciphertext is authenticated as it is decrypted, so a remote object that is
+//! modified between requests causes a decryption error, rather than incorrect
+//! plaintext.
+//!
+//! [`Decryptor::new`]: crate::Decryptor::new
+//! [`StreamReader`]: crate::stream::StreamReader
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[cfg(feature = "remote-http")]
+mod http;
+#[cfg(feature = "remote-http")]
+#[cfg_attr(docsrs, doc(cfg(feature = "remote-http")))]
+pub use http::HttpSource;
+
+#[cfg(feature = "remote-s3")]
+mod s3;
+#[cfg(feature = "remote-s3")]
+#[cfg_attr(docsrs, doc(cfg(feature = "remote-s3")))]
+pub use s3::S3Source;
+
+/// The number of bytes that [`RemoteReader`] fetches at a time, by default.
+///
+/// This is large enough to hold several chunks of an age file's payload, so that
+/// reading sequentially doesn't make a request for every chunk.
+const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// A remote object that can be read in byte ranges.
+pub trait RangeSource {
+    /// Returns the length of the object in bytes.
+    fn object_len(&mut self) -> io::Result<u64>;
+
+    /// Reads bytes from the object, starting at `offset`, into `buf`.
+    ///
+    /// Returns the number of bytes read, which may be less than `buf.len()`. `offset` is
+    /// always less than the length of the object, and `buf` is never empty.
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+impl<S: RangeSource + ?Sized> RangeSource for Box<S> {
+    fn object_len(&mut self) -> io::Result<u64> {
+        (**self).object_len()
+    }
+
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read_range(offset, buf)
+    }
+}
+
+/// A reader over a remote object, which fetches it a block at a time.
+pub struct RemoteReader<S> {
+    source: S,
+    block_size: usize,
+    /// The length of the object, once it is needed.
+    len: Option<u64>,
+    /// The current position in the object.
+    pos: u64,
+    /// The most recently fetched block, and its offset in the object.
+    block: Vec<u8>,
+    block_start: u64,
+}
+
+impl<S: RangeSource> RemoteReader<S> {
+    /// Creates a reader over the object that `source` reads from.
+    pub fn new(source: S) -> Self {
+        RemoteReader {
+            source,
+            block_size: DEFAULT_BLOCK_SIZE,
+            len: None,
+            pos: 0,
+            block: vec![],
+            block_start: 0,
+        }
+    }
+
+    /// Sets the number of bytes to fetch at a time.
+    ///
+    /// Smaller blocks are quicker to fetch when seeking to scattered positions, while
+    /// larger blocks need fewer requests to read the object sequentially.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        assert!(block_size > 0);
+        self.block_size = block_size;
+        self
+    }
+
+    /// Returns the length of the object.
+    pub fn object_len(&mut self) -> io::Result<u64> {
+        match self.len {
+            Some(len) => Ok(len),
+            None => {
+                let len = self.source.object_len()?;
+                self.len = Some(len);
+                Ok(len)
+            }
+        }
+    }
+
+    /// Returns the underlying source.
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+
+    /// Fetches the block starting at the current position.
+    fn fetch_block(&mut self, len: u64) -> io::Result<()> {
+        let block_len = cmp::min(self.block_size as u64, len - self.pos) as usize;
+        self.block.resize(block_len, 0);
+        self.block_start = self.pos;
+
+        let mut filled = 0;
+        while filled < block_len {
+            match self
+                .source
+                .read_range(self.block_start + filled as u64, &mut self.block[filled..])
+            {
+                Ok(0) => {
+                    self.block.truncate(filled);
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.block.truncate(filled);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: RangeSource> Read for RemoteReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.object_len()?;
+        if buf.is_empty() || self.pos >= len {
+            return Ok(0);
+        }
+
+        let block_end = self.block_start + self.block.len() as u64;
+        if self.pos < self.block_start || self.pos >= block_end {
+            self.fetch_block(len)?;
+        }
+
+        let block = &self.block[(self.pos - self.block_start) as usize..];
+        let n = cmp::min(block.len(), buf.len());
+        buf[..n].copy_from_slice(&block[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: RangeSource> Seek for RemoteReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+            SeekFrom::End(offset) => (self.object_len()?, offset),
+        };
+
+        let pos = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot seek before byte 0",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::iter;
+
+    use super::{RangeSource, RemoteReader};
+    use crate::{x25519, Decryptor, Encryptor, Identity};
+
+    /// An in-memory object that counts the bytes fetched from it.
+    struct MemorySource {
+        data: Vec<u8>,
+        fetched: usize,
+    }
+
+    impl RangeSource for &mut MemorySource {
+        fn object_len(&mut self) -> io::Result<u64> {
+            Ok(self.data.len() as u64)
+        }
+
+        fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            // Return short reads, like a server that closes the connection early.
+            let data = &self.data[offset as usize..];
+            let n = buf.len().min(data.len()).min(1000);
+            buf[..n].copy_from_slice(&data[..n]);
+            self.fetched += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_and_seek() {
+        let mut source = MemorySource {
+            data: (0..10_000).map(|i| i as u8).collect(),
+            fetched: 0,
+        };
+        let data = source.data.clone();
+        let mut reader = RemoteReader::new(&mut source).with_block_size(4096);
+
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 9990);
+        let mut buf = [0; 20];
+        assert_eq!(reader.read(&mut buf).unwrap(), 10);
+        assert_eq!(&buf[..10], &data[9990..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        assert_eq!(reader.seek(SeekFrom::Current(-5000)).unwrap(), 5000);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &data[5000..5020]);
+
+        assert!(reader.seek(SeekFrom::Current(-6000)).is_err());
+    }
+
+    #[test]
+    fn decrypt_remote_object() {
+        let key = x25519::Identity::generate();
+        let plaintext: Vec<u8> = (0..500 * 1024).map(|i| i as u8).collect();
+        let mut encrypted = vec![];
+        let e = Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap();
+        let mut w = e.wrap_output(&mut encrypted).unwrap();
+        w.write_all(&plaintext).unwrap();
+        w.finish().unwrap();
+
+        let mut source = MemorySource {
+            data: encrypted,
+            fetched: 0,
+        };
+        let reader = RemoteReader::new(&mut source).with_block_size(64 * 1024);
+        let mut stream = match Decryptor::new(reader).unwrap() {
+            Decryptor::Recipients(d) => d.decrypt(iter::once(&key as &dyn Identity)).unwrap(),
+            _ => panic!(),
+        };
+
+        // Only the end of the object is fetched to read the last bytes.
+        stream.seek(SeekFrom::End(-100)).unwrap();
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, &plaintext[plaintext.len() - 100..]);
+        drop(stream);
+        assert!(source.fetched < source.data.len() / 2);
+    }
+}
@@ -0,0 +1,125 @@
+//! Objects served over HTTP(S).
+
+use std::io::{self, Read};
+
+use super::RangeSource;
+
+/// An object at an HTTP(S) URL, read with `Range` requests.
+///
+/// The server must support range requests for the object. Most static file servers and
+/// CDNs do, as do the pre-signed URLs of object stores.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// use age::remote::{HttpSource, RemoteReader};
+///
+/// let source = HttpSource::new("https://example.com/backups/photos.tar.age")
+///     .with_header("Authorization", "Bearer TOKEN");
+/// let decryptor = age::Decryptor::new(RemoteReader::new(source))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct HttpSource {
+    agent: ureq::Agent,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpSource {
+    /// Reads the object at `url`.
+    pub fn new(url: impl Into<String>) -> Self {
+        HttpSource {
+            agent: ureq::Agent::new(),
+            url: url.into(),
+            headers: vec![],
+        }
+    }
+
+    /// Sends the given header with every request, for example to authenticate them.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    fn request(&self, method: &str) -> ureq::Request {
+        self.headers.iter().fold(
+            self.agent.request(method, &self.url),
+            |request, (name, value)| request.set(name, value),
+        )
+    }
+}
+
+impl RangeSource for HttpSource {
+    fn object_len(&mut self) -> io::Result<u64> {
+        content_length(send(self.request("HEAD"))?)
+    }
+
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let request = self.request("GET").set("Range", &range(offset, buf.len()));
+        read_range_response(send(request)?, offset, buf)
+    }
+}
+
+/// Returns the value of a `Range` header for `len` bytes starting at `offset`.
+pub(super) fn range(offset: u64, len: usize) -> String {
+    format!("bytes={}-{}", offset, offset + len as u64 - 1)
+}
+
+/// Sends `request`, turning HTTP errors into I/O errors.
+pub(super) fn send(request: ureq::Request) -> io::Result<ureq::Response> {
+    request.call().map_err(|e| match e {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, e.to_string()),
+        ureq::Error::Status(401, _) | ureq::Error::Status(403, _) => {
+            io::Error::new(io::ErrorKind::PermissionDenied, e.to_string())
+        }
+        e => io::Error::new(io::ErrorKind::Other, e.to_string()),
+    })
+}
+
+/// Returns the `Content-Length` of the response to a `HEAD` request.
+pub(super) fn content_length(response: ureq::Response) -> io::Result<u64> {
+    response
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "server did not return the length of the object",
+            )
+        })
+}
+
+/// Reads the body of the response to a `Range` request for `buf.len()` bytes starting at
+/// `offset` into `buf`.
+pub(super) fn read_range_response(
+    response: ureq::Response,
+    offset: u64,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    // A server that doesn't support range requests responds with the whole object.
+    let start = response
+        .header("Content-Range")
+        .and_then(|range| range.strip_prefix("bytes "))
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(start, _)| start.parse::<u64>().ok());
+    if response.status() != 206 || start != Some(offset) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "server does not support range requests",
+        ));
+    }
+
+    let mut body = response.into_reader().take(buf.len() as u64);
+    let mut filled = 0;
+    loop {
+        match body.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,198 @@
+//! Objects in Amazon S3, or an S3-compatible object store.
+
+use age_core::secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::time::SystemTime;
+
+use super::{
+    http::{content_length, range, read_range_response, send},
+    RangeSource,
+};
+use crate::kms::{
+    aws::{amz_date, authorization, hex},
+    AwsCredentials,
+};
+
+/// An object in Amazon S3, read with `GetObject` requests for byte ranges.
+///
+/// Requests are signed with credentials found by [`AwsCredentials::from_environment`],
+/// unless others are given with [`S3Source::with_credentials`]. They need IAM
+/// permission for `s3:GetObject` on the object.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// use age::remote::{RemoteReader, S3Source};
+///
+/// let source = S3Source::new("us-east-1", "backups", "2024/photos.tar.age");
+/// let decryptor = age::Decryptor::new(RemoteReader::new(source))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct S3Source {
+    agent: ureq::Agent,
+    region: String,
+    bucket: String,
+    key: String,
+    endpoint: Option<String>,
+    credentials: Option<AwsCredentials>,
+}
+
+impl S3Source {
+    /// Reads the object with the given key, in a bucket in the given AWS region.
+    pub fn new(
+        region: impl Into<String>,
+        bucket: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Self {
+        S3Source {
+            agent: ureq::Agent::new(),
+            region: region.into(),
+            bucket: bucket.into(),
+            key: key.into(),
+            endpoint: None,
+            credentials: None,
+        }
+    }
+
+    /// Sends requests to an S3-compatible object store at `endpoint` (such as
+    /// `https://minio.example.com:9000`), instead of Amazon S3.
+    ///
+    /// Objects are addressed with path-style URLs (`ENDPOINT/BUCKET/KEY`).
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Uses the given credentials instead of finding them with
+    /// [`AwsCredentials::from_environment`] for each request.
+    pub fn with_credentials(mut self, credentials: AwsCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Returns the URL scheme and host of the object, and its path.
+    fn location(&self) -> (&str, String, String) {
+        let key = uri_encode(&self.key);
+        match &self.endpoint {
+            Some(endpoint) => {
+                let (scheme, host) = endpoint
+                    .split_once("://")
+                    .unwrap_or(("https", endpoint.as_str()));
+                (
+                    scheme,
+                    host.to_owned(),
+                    format!("/{}/{}", uri_encode(&self.bucket), key),
+                )
+            }
+            None => (
+                "https",
+                format!("{}.s3.{}.amazonaws.com", self.bucket, self.region),
+                format!("/{}", key),
+            ),
+        }
+    }
+
+    /// Signs and sends a request for the object, with an optional `Range` header.
+    fn send(&self, method: &str, range: Option<&str>) -> io::Result<ureq::Response> {
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials.clone(),
+            None => AwsCredentials::from_environment()
+                .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e.to_string()))?,
+        };
+
+        let (scheme, host, path) = self.location();
+        let amz_date = amz_date(SystemTime::now());
+        let payload_hash = hex(&Sha256::digest(b""));
+        let mut headers = vec![("host", host.as_str())];
+        if let Some(range) = range {
+            headers.push(("range", range));
+        }
+        headers.push(("x-amz-content-sha256", payload_hash.as_str()));
+        headers.push(("x-amz-date", amz_date.as_str()));
+        if let Some(token) = credentials.session_token() {
+            headers.push(("x-amz-security-token", token.expose_secret()));
+        }
+
+        let authorization = authorization(
+            &credentials,
+            method,
+            &path,
+            "",
+            &headers,
+            b"",
+            &self.region,
+            "s3",
+            &amz_date,
+        );
+
+        let mut request = self
+            .agent
+            .request(method, &format!("{}://{}{}", scheme, host, path));
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.set(name, value);
+        }
+        send(request.set("authorization", &authorization))
+    }
+}
+
+impl RangeSource for S3Source {
+    fn object_len(&mut self) -> io::Result<u64> {
+        content_length(self.send("HEAD", None)?)
+    }
+
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let response = self.send("GET", Some(&range(offset, buf.len())))?;
+        read_range_response(response, offset, buf)
+    }
+}
+
+/// Percent-encodes an object key for a request path, as S3 expects in the canonical
+/// request that is signed.
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{uri_encode, S3Source};
+
+    #[test]
+    fn object_locations() {
+        let source = S3Source::new("eu-west-2", "backups", "2024/photos (1).tar.age");
+        assert_eq!(
+            source.location(),
+            (
+                "https",
+                "backups.s3.eu-west-2.amazonaws.com".to_owned(),
+                "/2024/photos%20%281%29.tar.age".to_owned()
+            )
+        );
+
+        let source = S3Source::new("us-east-1", "backups", "a+b.age")
+            .with_endpoint("http://localhost:9000/");
+        assert_eq!(
+            source.location(),
+            (
+                "http",
+                "localhost:9000".to_owned(),
+                "/backups/a%2Bb.age".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn uri_encoding() {
+        assert_eq!(uri_encode("a/b-c_d.e~f"), "a/b-c_d.e~f");
+        assert_eq!(uri_encode("caf\u{e9}"), "caf%C3%A9");
+    }
+}
@@ -0,0 +1,154 @@
+//! Health checks for the randomness used to generate keys.
+
+use rand::{rngs::OsRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::error::RngError;
+
+/// Fills an array with random bytes from the operating system, failing closed if the
+/// randomness looks broken.
+///
+/// This is used for everything that must be unpredictable (file keys, nonces, salts,
+/// and secret keys), so that a broken RNG results in an error rather than a weak key.
+pub(crate) fn random_bytes<const N: usize>() -> Result<[u8; N], RngError> {
+    random_bytes_from(&mut OsRng)
+}
+
+/// Fills an array with random bytes from `rng`, with health checks.
+///
+/// These are not statistical tests, and will not detect a subtly biased RNG. They only
+/// catch the catastrophic failures that have been seen in practice:
+/// - the RNG returning an error (for example, if `getrandom` is unavailable).
+/// - the RNG returning a constant output, such as an unfilled (all-zero) buffer.
+/// - the RNG returning the same output twice in a row.
+pub(crate) fn random_bytes_from<R: RngCore, const N: usize>(
+    rng: &mut R,
+) -> Result<[u8; N], RngError> {
+    let mut bytes = [0; N];
+    let mut check = [0; N];
+
+    let res = rng
+        .try_fill_bytes(&mut bytes)
+        .and_then(|()| rng.try_fill_bytes(&mut check))
+        .map_err(|e| RngError::Failed(e.to_string()))
+        .and_then(|()| {
+            if bytes.iter().all(|b| *b == bytes[0]) {
+                Err(RngError::Constant)
+            } else if bytes == check {
+                Err(RngError::Repeated)
+            } else {
+                Ok(())
+            }
+        });
+
+    check.zeroize();
+    match res {
+        Ok(()) => Ok(bytes),
+        Err(e) => {
+            bytes.zeroize();
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::OsRng, Error, RngCore};
+
+    use super::random_bytes_from;
+    use crate::error::RngError;
+
+    /// A mock RNG that returns the same output for every request.
+    struct Stuck(u8);
+
+    impl RngCore for Stuck {
+        fn next_u32(&mut self) -> u32 {
+            unimplemented!()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unimplemented!()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.try_fill_bytes(dest).unwrap()
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+            for (i, b) in dest.iter_mut().enumerate() {
+                *b = self.0.wrapping_add(i as u8);
+            }
+            Ok(())
+        }
+    }
+
+    /// A mock RNG that reports success without writing anything.
+    struct Unfilled;
+
+    impl RngCore for Unfilled {
+        fn next_u32(&mut self) -> u32 {
+            unimplemented!()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unimplemented!()
+        }
+
+        fn fill_bytes(&mut self, _: &mut [u8]) {}
+
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    /// A mock RNG that always fails, like `getrandom` in an unsupported environment.
+    struct Failing;
+
+    impl RngCore for Failing {
+        fn next_u32(&mut self) -> u32 {
+            unimplemented!()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unimplemented!()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.try_fill_bytes(dest).unwrap()
+        }
+
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), Error> {
+            Err(Error::new("entropy source is unavailable"))
+        }
+    }
+
+    #[test]
+    fn os_rng_passes() {
+        assert!(random_bytes_from::<_, 16>(&mut OsRng).is_ok());
+    }
+
+    #[test]
+    fn all_zero_output_fails() {
+        // This is what an RNG that silently leaves the buffer unfilled returns.
+        assert_eq!(
+            random_bytes_from::<_, 16>(&mut Unfilled),
+            Err(RngError::Constant)
+        );
+    }
+
+    #[test]
+    fn repeated_output_fails() {
+        assert_eq!(
+            random_bytes_from::<_, 16>(&mut Stuck(42)),
+            Err(RngError::Repeated)
+        );
+    }
+
+    #[test]
+    fn rng_failure_fails() {
+        assert!(matches!(
+            random_bytes_from::<_, 16>(&mut Failing),
+            Err(RngError::Failed(_))
+        ));
+    }
+}
@@ -3,13 +3,14 @@ use age_core::{
     primitives::{aead_decrypt, aead_encrypt},
     secrecy::{ExposeSecret, SecretString},
 };
-use rand::{rngs::OsRng, RngCore};
 use std::time::Duration;
 use zeroize::Zeroize;
 
 use crate::{
     error::{DecryptError, EncryptError},
     primitives::scrypt,
+    rng::random_bytes,
+    stanza::{Arg, Body, Rule},
     util::read::{base64_arg, decimal_digit_arg},
 };
 
@@ -20,6 +21,15 @@ const ONE_SECOND: Duration = Duration::from_secs(1);
 const SALT_LEN: usize = 16;
 const ENCRYPTED_FILE_KEY_BYTES: usize = FILE_KEY_BYTES + 16;
 
+/// The format of an scrypt recipient stanza.
+///
+/// https://c2sp.org/age#scrypt-recipient-stanza
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: SCRYPT_RECIPIENT_TAG,
+    args: &[Arg::Base64(SALT_LEN), Arg::Decimal],
+    body: Body::Exact(ENCRYPTED_FILE_KEY_BYTES),
+};
+
 /// Pick an scrypt work factor that will take around 1 second on this device.
 ///
 /// Guaranteed to return a valid work factor (less than 64).
@@ -81,8 +91,7 @@ pub(crate) struct Recipient {
 
 impl crate::Recipient for Recipient {
     fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
-        let mut salt = [0; SALT_LEN];
-        OsRng.fill_bytes(&mut salt);
+        let salt: [u8; SALT_LEN] = random_bytes()?;
 
         let mut inner_salt = vec![];
         inner_salt.extend_from_slice(SCRYPT_SALT_LABEL);
@@ -96,11 +105,11 @@ impl crate::Recipient for Recipient {
 
         let encoded_salt = base64::encode_config(&salt, base64::STANDARD_NO_PAD);
 
-        Ok(vec![Stanza {
-            tag: SCRYPT_RECIPIENT_TAG.to_owned(),
-            args: vec![encoded_salt, format!("{}", log_n)],
-            body: encrypted_file_key,
-        }])
+        Ok(vec![Stanza::new(
+            SCRYPT_RECIPIENT_TAG.to_owned(),
+            vec![encoded_salt, format!("{}", log_n)],
+            encrypted_file_key,
+        )])
     }
 }
 
@@ -116,17 +125,13 @@ impl<'a> crate::Identity for Identity<'a> {
         }
 
         // Enforce valid and canonical stanza format.
-        // https://c2sp.org/age#scrypt-recipient-stanza
-        let (salt, log_n) = match &stanza.args[..] {
-            [salt, log_n] => match (base64_arg(salt, [0; SALT_LEN]), decimal_digit_arg(log_n)) {
-                (Some(salt), Some(log_n)) => (salt, log_n),
-                _ => return Some(Err(DecryptError::InvalidHeader)),
-            },
-            _ => return Some(Err(DecryptError::InvalidHeader)),
-        };
-        if stanza.body.len() != ENCRYPTED_FILE_KEY_BYTES {
-            return Some(Err(DecryptError::InvalidHeader));
+        if let Err(e) = STANZA_RULE.check(stanza) {
+            return Some(Err(e.into()));
         }
+        let salt = base64_arg(&stanza.args[0], [0; SALT_LEN])
+            .expect("Argument should have been checked above");
+        let log_n: u8 =
+            decimal_digit_arg(&stanza.args[1]).expect("Argument should have been checked above");
 
         // Place bounds on the work factor we will accept (roughly 16 seconds).
         let target = target_scrypt_work_factor();
@@ -159,7 +164,7 @@ impl<'a> crate::Identity for Identity<'a> {
         // does not bypass any scrypt work, but that work can be precomputed in an online
         // oracle scenario.
         Some(
-            aead_decrypt(&enc_key, FILE_KEY_BYTES, &stanza.body)
+            aead_decrypt(&enc_key, FILE_KEY_BYTES, stanza.body())
                 .map(|mut pt| {
                     // It's ours!
                     let file_key: [u8; FILE_KEY_BYTES] = pt[..].try_into().unwrap();
@@ -0,0 +1,71 @@
+//! The "piv-p256" recipient type, backed by the Apple Secure Enclave.
+//!
+//! Files can be encrypted to a Secure Enclave [`Recipient`] on any platform. Decrypting
+//! them requires the corresponding [`Identity`], which is only available on macOS: the
+//! private key is generated inside the Secure Enclave and can never be exported, so the
+//! ECDH step is performed by the Security framework (which handles any Touch ID or
+//! passcode prompts that the key's access control requires).
+//!
+//! The stanza format is the same one used by `age-plugin-yubikey` for PIV keys, so these
+//! recipients are compatible with other P-256 hardware-backed age implementations.
+
+use age_core::format::{FileKey, Stanza};
+use p256::PublicKey;
+use std::fmt;
+
+use crate::{error::EncryptError, piv_p256};
+
+#[cfg(target_os = "macos")]
+mod keychain;
+#[cfg(target_os = "macos")]
+#[cfg_attr(docsrs, doc(cfg(target_os = "macos")))]
+pub use keychain::Identity;
+
+const PUBLIC_KEY_PREFIX: &str = "age1se";
+
+/// A recipient whose private key is held in a Secure Enclave. Files encrypted to this
+/// recipient can be decrypted with the corresponding [`Identity`].
+#[derive(Clone)]
+pub struct Recipient(PublicKey);
+
+impl std::str::FromStr for Recipient {
+    type Err = &'static str;
+
+    /// Parses a Secure Enclave recipient from a string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        piv_p256::parse_recipient(s, PUBLIC_KEY_PREFIX).map(Recipient)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            piv_p256::encode_recipient(&self.0, PUBLIC_KEY_PREFIX)
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::util::serde_via_str!(Recipient);
+
+impl crate::Recipient for Recipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        Ok(vec![piv_p256::wrap_file_key(&self.0, file_key)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recipient;
+
+    const TEST_RECIPIENT: &str =
+        "age1se1qd43050juykyy3lchnnw2caygre8wqmasyk7kvaq7jsnj3wcnrpfv8np9xv";
+
+    #[test]
+    fn recipient_encoding() {
+        let recipient: Recipient = TEST_RECIPIENT.parse().unwrap();
+        assert_eq!(recipient.to_string(), TEST_RECIPIENT);
+    }
+}
@@ -0,0 +1,295 @@
+//! Secure Enclave identities, accessed through the macOS Security framework.
+//!
+//! `security-framework` only wraps key generation and key agreement in releases that
+//! require a newer Rust than our MSRV, so those calls go through the raw bindings.
+
+#![allow(unsafe_code)]
+
+use age_core::format::{FileKey, Stanza};
+use core_foundation::{
+    base::{kCFAllocatorDefault, CFType, CFTypeRef, TCFType},
+    boolean::CFBoolean,
+    data::{CFData, CFDataRef},
+    dictionary::{CFDictionary, CFDictionaryRef},
+    error::{CFError, CFErrorRef},
+    number::CFNumber,
+    string::{CFString, CFStringRef},
+};
+use i18n_embed_fl::fl;
+use p256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey};
+use security_framework::{base::Error as SecError, key::SecKey};
+use security_framework_sys::{
+    access_control::{
+        kSecAccessControlBiometryCurrentSet, kSecAccessControlPrivateKeyUsage,
+        kSecAttrAccessibleWhenUnlockedThisDeviceOnly, SecAccessControlCreateWithFlags,
+    },
+    base::{errSecItemNotFound, errSecSuccess, SecKeyRef},
+    item::{
+        kSecAttrAccessControl, kSecAttrIsPermanent, kSecAttrKeyClass, kSecAttrKeyClassPrivate,
+        kSecAttrKeyClassPublic, kSecAttrKeySizeInBits, kSecAttrKeyType,
+        kSecAttrKeyTypeECSECPrimeRandom, kSecAttrLabel, kSecAttrTokenID,
+        kSecAttrTokenIDSecureEnclave, kSecClass, kSecClassKey, kSecPrivateKeyAttrs, kSecReturnRef,
+        kSecUseDataProtectionKeychain,
+    },
+    key::{
+        kSecKeyAlgorithmECDHKeyExchangeStandard, SecKeyAlgorithm, SecKeyCopyPublicKey,
+        SecKeyCreateRandomKey,
+    },
+    keychain_item::SecItemCopyMatching,
+};
+use std::{io, ptr};
+
+use super::Recipient;
+use crate::{error::DecryptError, piv_p256, Callbacks};
+
+#[link(name = "Security", kind = "framework")]
+extern "C" {
+    fn SecKeyCreateWithData(
+        key_data: CFDataRef,
+        attributes: CFDictionaryRef,
+        error: *mut CFErrorRef,
+    ) -> SecKeyRef;
+
+    fn SecKeyCopyKeyExchangeResult(
+        private_key: SecKeyRef,
+        algorithm: SecKeyAlgorithm,
+        public_key: SecKeyRef,
+        parameters: CFDictionaryRef,
+        error: *mut CFErrorRef,
+    ) -> CFDataRef;
+}
+
+/// Size in bytes of a P-256 ECDH shared secret.
+const SHARED_SECRET_BYTES: usize = 32;
+
+fn other_err(e: impl std::error::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+/// Wraps one of the Security framework's constant dictionary keys or values.
+fn cf_str(s: CFStringRef) -> CFString {
+    unsafe { CFString::wrap_under_get_rule(s) }
+}
+
+/// Takes ownership of the error reported by a failed Security framework call.
+fn take_error(error: CFErrorRef) -> CFError {
+    unsafe { CFError::wrap_under_create_rule(error) }
+}
+
+/// Extracts the public key corresponding to a Secure Enclave private key.
+fn public_key(key: &SecKey) -> Option<PublicKey> {
+    let public = unsafe { SecKeyCopyPublicKey(key.as_concrete_TypeRef()) };
+    if public.is_null() {
+        return None;
+    }
+    let public = unsafe { SecKey::wrap_under_create_rule(public) };
+
+    public
+        .external_representation()
+        .and_then(|data| PublicKey::from_sec1_bytes(data.bytes()).ok())
+}
+
+/// Imports an uncompressed SEC1 P-256 public key, for use in key agreement.
+fn import_public_key(pk: &PublicKey) -> Result<SecKey, CFError> {
+    let data = CFData::from_buffer(pk.to_encoded_point(false).as_bytes());
+    let attributes = CFDictionary::from_CFType_pairs(&[
+        (
+            cf_str(unsafe { kSecAttrKeyType }),
+            cf_str(unsafe { kSecAttrKeyTypeECSECPrimeRandom }).as_CFType(),
+        ),
+        (
+            cf_str(unsafe { kSecAttrKeyClass }),
+            cf_str(unsafe { kSecAttrKeyClassPublic }).as_CFType(),
+        ),
+    ]);
+
+    let mut error = ptr::null_mut();
+    let key = unsafe {
+        SecKeyCreateWithData(
+            data.as_concrete_TypeRef(),
+            attributes.as_concrete_TypeRef(),
+            &mut error,
+        )
+    };
+    if key.is_null() {
+        Err(take_error(error))
+    } else {
+        Ok(unsafe { SecKey::wrap_under_create_rule(key) })
+    }
+}
+
+/// An identity whose private key is held in the Secure Enclave.
+///
+/// The private key is stored in the data protection keychain, and is referenced by its
+/// keychain label. Decryption is performed inside the Secure Enclave; if the key was
+/// generated with biometry required, macOS will show a Touch ID prompt each time it is
+/// used.
+pub struct Identity<C: Callbacks> {
+    key: SecKey,
+    public: PublicKey,
+    callbacks: C,
+}
+
+impl<C: Callbacks> Identity<C> {
+    /// Generates a new P-256 key inside the Secure Enclave, and stores a reference to it
+    /// in the keychain under `label`.
+    ///
+    /// If `require_biometry` is `true`, every use of the key requires authentication
+    /// with Touch ID (using the currently-enrolled fingerprints).
+    pub fn generate(label: &str, require_biometry: bool, callbacks: C) -> io::Result<Self> {
+        let mut flags = kSecAccessControlPrivateKeyUsage;
+        if require_biometry {
+            flags |= kSecAccessControlBiometryCurrentSet;
+        }
+
+        let mut error = ptr::null_mut();
+        let access_control = unsafe {
+            SecAccessControlCreateWithFlags(
+                kCFAllocatorDefault,
+                kSecAttrAccessibleWhenUnlockedThisDeviceOnly as CFTypeRef,
+                flags,
+                &mut error,
+            )
+        };
+        if access_control.is_null() {
+            return Err(other_err(take_error(error)));
+        }
+        let access_control = unsafe { CFType::wrap_under_create_rule(access_control as _) };
+
+        let private_key_attrs = CFDictionary::from_CFType_pairs(&[
+            (
+                cf_str(unsafe { kSecAttrIsPermanent }),
+                CFBoolean::true_value().as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecAttrLabel }),
+                CFString::new(label).as_CFType(),
+            ),
+            (cf_str(unsafe { kSecAttrAccessControl }), access_control),
+        ]);
+        let parameters = CFDictionary::from_CFType_pairs(&[
+            (
+                cf_str(unsafe { kSecAttrKeyType }),
+                cf_str(unsafe { kSecAttrKeyTypeECSECPrimeRandom }).as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecAttrKeySizeInBits }),
+                CFNumber::from(256).as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecAttrTokenID }),
+                cf_str(unsafe { kSecAttrTokenIDSecureEnclave }).as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecUseDataProtectionKeychain }),
+                CFBoolean::true_value().as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecPrivateKeyAttrs }),
+                private_key_attrs.as_CFType(),
+            ),
+        ]);
+
+        let key = unsafe { SecKeyCreateRandomKey(parameters.as_concrete_TypeRef(), &mut error) };
+        if key.is_null() {
+            return Err(other_err(take_error(error)));
+        }
+        let key = unsafe { SecKey::wrap_under_create_rule(key) };
+
+        Self::from_key(key, callbacks).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Secure Enclave returned an invalid public key",
+            )
+        })
+    }
+
+    /// Loads the Secure Enclave key stored in the keychain under `label`.
+    ///
+    /// Returns `Ok(None)` if there is no such key.
+    pub fn from_label(label: &str, callbacks: C) -> io::Result<Option<Self>> {
+        let query = CFDictionary::from_CFType_pairs(&[
+            (
+                cf_str(unsafe { kSecClass }),
+                cf_str(unsafe { kSecClassKey }).as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecAttrKeyClass }),
+                cf_str(unsafe { kSecAttrKeyClassPrivate }).as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecAttrLabel }),
+                CFString::new(label).as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecUseDataProtectionKeychain }),
+                CFBoolean::true_value().as_CFType(),
+            ),
+            (
+                cf_str(unsafe { kSecReturnRef }),
+                CFBoolean::true_value().as_CFType(),
+            ),
+        ]);
+
+        let mut result: CFTypeRef = ptr::null();
+        let status = unsafe { SecItemCopyMatching(query.as_concrete_TypeRef(), &mut result) };
+        if status == errSecItemNotFound {
+            return Ok(None);
+        } else if status != errSecSuccess {
+            return Err(other_err(SecError::from_code(status)));
+        }
+        let key = unsafe { SecKey::wrap_under_create_rule(result as SecKeyRef) };
+
+        Ok(Self::from_key(key, callbacks))
+    }
+
+    fn from_key(key: SecKey, callbacks: C) -> Option<Self> {
+        public_key(&key).map(|public| Identity {
+            key,
+            public,
+            callbacks,
+        })
+    }
+
+    /// Returns the recipient for this identity.
+    pub fn to_public(&self) -> Recipient {
+        Recipient(self.public)
+    }
+}
+
+impl<C: Callbacks> crate::Identity for Identity<C> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        piv_p256::unwrap_stanza_with(&self.public, stanza, |epk| {
+            let epk = import_public_key(epk).map_err(|_| DecryptError::InvalidHeader)?;
+
+            self.callbacks
+                .display_message(&fl!(crate::i18n::LANGUAGE_LOADER, "secure-enclave-waiting"));
+
+            // The standard (non-KDF) algorithm takes no parameters, and returns the
+            // x-coordinate of the shared point.
+            let parameters = CFDictionary::<CFType, CFType>::from_CFType_pairs(&[]);
+            let mut error = ptr::null_mut();
+            let shared_secret = unsafe {
+                SecKeyCopyKeyExchangeResult(
+                    self.key.as_concrete_TypeRef(),
+                    kSecKeyAlgorithmECDHKeyExchangeStandard,
+                    epk.as_concrete_TypeRef(),
+                    parameters.as_concrete_TypeRef(),
+                    &mut error,
+                )
+            };
+
+            // This fails if the user cancels the Touch ID prompt.
+            if shared_secret.is_null() {
+                take_error(error);
+                return Err(DecryptError::KeyDecryptionFailed);
+            }
+            let shared_secret = unsafe { CFData::wrap_under_create_rule(shared_secret) };
+            if shared_secret.len() as usize != SHARED_SECRET_BYTES {
+                return Err(DecryptError::KeyDecryptionFailed);
+            }
+
+            Ok(shared_secret.bytes().to_vec())
+        })
+    }
+}
@@ -0,0 +1,187 @@
+//! Decrypting many age files concurrently with a shared set of identities.
+
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::{
+    primitives::stream::ChunkPool, stream::StreamReader, DecryptError, Decryptor, Identity,
+};
+
+#[cfg(feature = "async")]
+use futures::io::AsyncRead;
+
+/// A handle for decrypting age files with a fixed set of identities.
+///
+/// This is the common shape of a server that embeds age: the identities are loaded
+/// once at startup, and then every request decrypts its own age file with them.
+/// `DecryptService` is cheap to clone, and can be shared between threads and tasks;
+/// each clone refers to the same identities.
+///
+/// Every [`StreamReader`] needs a buffer for the encrypted chunk it is reading (64 KiB
+/// plus the tag). The readers returned by a `DecryptService` take these buffers from a
+/// pool shared with its clones, and return them when dropped, so that a busy server
+/// does not allocate a new buffer for every request. The pool keeps as many buffers as
+/// there were readers alive at once.
+///
+/// Only files encrypted to recipients can be decrypted; passphrase-encrypted files
+/// return [`DecryptError::NoMatchingKeys`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use std::thread;
+///
+/// # fn run_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let key = age::x25519::Identity::generate();
+/// # let encrypted = {
+/// #     let encryptor = age::Encryptor::with_recipients(vec![Box::new(key.to_public())])
+/// #         .expect("we provided a recipient");
+/// #     let mut encrypted = vec![];
+/// #     let mut writer = encryptor.wrap_output(&mut encrypted)?;
+/// #     writer.write_all(b"Hello world!")?;
+/// #     writer.finish()?;
+/// #     encrypted
+/// # };
+/// let service = age::service::DecryptService::new(vec![Box::new(key)]);
+///
+/// let handles: Vec<_> = (0..4)
+///     .map(|_| {
+///         let service = service.clone();
+///         let encrypted = encrypted.clone();
+///         thread::spawn(move || {
+///             let mut reader = service.decrypt_stream(&encrypted[..]).unwrap();
+///             let mut decrypted = vec![];
+///             reader.read_to_end(&mut decrypted).unwrap();
+///             decrypted
+///         })
+///     })
+///     .collect();
+///
+/// for handle in handles {
+///     assert_eq!(handle.join().unwrap(), b"Hello world!");
+/// }
+/// # Ok(())
+/// # }
+/// # run_main().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct DecryptService {
+    identities: Arc<[Box<dyn Identity + Send + Sync>]>,
+    pool: Arc<ChunkPool>,
+}
+
+impl DecryptService {
+    /// Creates a service that decrypts age files with the given identities.
+    pub fn new(identities: Vec<Box<dyn Identity + Send + Sync>>) -> Self {
+        DecryptService {
+            identities: identities.into(),
+            pool: Arc::default(),
+        }
+    }
+
+    fn identities(&self) -> impl Iterator<Item = &dyn Identity> {
+        self.identities.iter().map(|i| i.as_ref() as &dyn Identity)
+    }
+
+    /// Decrypts the age file read from `reader`.
+    ///
+    /// If successful, returns a reader that will provide the plaintext.
+    pub fn decrypt_stream<R: Read>(&self, reader: R) -> Result<StreamReader<R>, DecryptError> {
+        match Decryptor::new(reader)? {
+            Decryptor::Recipients(d) => d
+                .decrypt(self.identities())
+                .map(|r| r.with_chunk_pool(self.pool.clone())),
+            Decryptor::Passphrase(_) => Err(DecryptError::NoMatchingKeys),
+        }
+    }
+
+    /// Decrypts the age file read from `reader`.
+    ///
+    /// If successful, returns a reader that will provide the plaintext.
+    #[cfg(feature = "async")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub async fn decrypt_async_stream<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+    ) -> Result<StreamReader<R>, DecryptError> {
+        match Decryptor::new_async(reader).await? {
+            Decryptor::Recipients(d) => d
+                .decrypt_async(self.identities())
+                .map(|r| r.with_chunk_pool(self.pool.clone())),
+            Decryptor::Passphrase(_) => Err(DecryptError::NoMatchingKeys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+    use std::thread;
+
+    use age_core::secrecy::SecretString;
+
+    use super::DecryptService;
+    use crate::{x25519, DecryptError, Encryptor};
+
+    fn encrypt(encryptor: Encryptor, plaintext: &[u8]) -> Vec<u8> {
+        let mut encrypted = vec![];
+        let mut writer = encryptor.wrap_output(&mut encrypted).unwrap();
+        writer.write_all(plaintext).unwrap();
+        writer.finish().unwrap();
+        encrypted
+    }
+
+    #[test]
+    fn concurrent_decryption_reuses_buffers() {
+        let key = x25519::Identity::generate();
+        let plaintext: Vec<u8> = (0..100 * 1024).map(|i| i as u8).collect();
+        let encrypted = Arc::new(encrypt(
+            Encryptor::with_recipients(vec![Box::new(key.to_public())]).unwrap(),
+            &plaintext,
+        ));
+        let service = DecryptService::new(vec![Box::new(key)]);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let service = service.clone();
+                let encrypted = encrypted.clone();
+                thread::spawn(move || {
+                    let mut reader = service.decrypt_stream(&encrypted[..]).unwrap();
+                    let mut decrypted = vec![];
+                    reader.read_to_end(&mut decrypted).unwrap();
+                    decrypted
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), plaintext);
+        }
+
+        // Every reader returned its buffer to the pool.
+        let pooled = service.pool.len();
+        assert!((1..=8).contains(&pooled));
+
+        // A later reader takes a buffer from the pool, and returns it.
+        let mut reader = service.decrypt_stream(&encrypted[..]).unwrap();
+        let mut decrypted = vec![];
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(service.pool.len(), pooled - 1);
+        drop(reader);
+        assert_eq!(service.pool.len(), pooled);
+    }
+
+    #[test]
+    fn passphrase_files_are_rejected() {
+        let encrypted = encrypt(
+            Encryptor::with_user_passphrase(SecretString::new("passphrase".to_owned())),
+            b"",
+        );
+        let service = DecryptService::new(vec![Box::new(x25519::Identity::generate())]);
+        assert!(matches!(
+            service.decrypt_stream(&encrypted[..]),
+            Err(DecryptError::NoMatchingKeys)
+        ));
+    }
+}
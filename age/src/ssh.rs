@@ -6,15 +6,25 @@
 //!
 //! Note that these recipient types are not anonymous: the encrypted message will include
 //! a short 32-bit ID of the public key.
+//!
+//! Private keys can be read from OpenSSH and PEM files, and from PuTTY `.ppk` files. Keys
+//! held by an SSH agent (including the Windows OpenSSH agent and Pageant) can't be
+//! used, because agents only make signatures, and decrypting requires the private key.
 
 use aes::{Aes128, Aes192, Aes256};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
 use age_core::secrecy::{ExposeSecret, SecretString};
 use bcrypt_pbkdf::bcrypt_pbkdf;
 use sha2::{Digest, Sha256};
+use std::ops::RangeInclusive;
 
-use crate::error::DecryptError;
+use crate::{
+    error::DecryptError,
+    stanza::{Arg, Body, Rule},
+};
 
 pub(crate) mod identity;
+mod ppk;
 pub(crate) mod recipient;
 
 pub use identity::{Identity, UnsupportedKey};
@@ -22,15 +32,43 @@ pub use recipient::{ParseRecipientKeyError, Recipient};
 
 pub(crate) const SSH_RSA_KEY_PREFIX: &str = "ssh-rsa";
 pub(crate) const SSH_ED25519_KEY_PREFIX: &str = "ssh-ed25519";
+pub(crate) const SSH_DSS_KEY_PREFIX: &str = "ssh-dss";
 
 pub(super) const SSH_RSA_RECIPIENT_TAG: &str = "ssh-rsa";
 const SSH_RSA_OAEP_LABEL: &str = "age-encryption.org/v1/ssh-rsa";
 
+/// The ssh-rsa key sizes (in bits) that can be used as recipients.
+///
+/// Keys smaller than 2048 bits are rejected, matching the reference implementation. The
+/// upper bound is the largest modulus that the `rsa` crate accepts for RSA-OAEP.
+pub(crate) const SSH_RSA_KEY_BITS: RangeInclusive<usize> = 2048..=4096;
+
 pub(super) const SSH_ED25519_RECIPIENT_TAG: &str = "ssh-ed25519";
 const SSH_ED25519_RECIPIENT_KEY_LABEL: &[u8] = b"age-encryption.org/v1/ssh-ed25519";
 
 const TAG_LEN_BYTES: usize = 4;
 
+/// The format of an ssh-rsa recipient stanza.
+///
+/// The body is an RSA-OAEP ciphertext, the length of which depends on the key size.
+pub(crate) const SSH_RSA_STANZA_RULE: Rule = Rule {
+    tag: SSH_RSA_RECIPIENT_TAG,
+    args: &[Arg::Base64(TAG_LEN_BYTES)],
+    body: Body::Any,
+};
+
+/// The format of an ssh-ed25519 recipient stanza.
+pub(crate) const SSH_ED25519_STANZA_RULE: Rule = Rule {
+    tag: SSH_ED25519_RECIPIENT_TAG,
+    args: &[
+        Arg::Base64(TAG_LEN_BYTES),
+        Arg::Base64(crate::x25519::EPK_LEN_BYTES),
+    ],
+    body: Body::Exact(crate::x25519::ENCRYPTED_FILE_KEY_BYTES),
+};
+
+type Aes128CbcDec = cbc::Decryptor<Aes128>;
+type Aes192CbcDec = cbc::Decryptor<Aes192>;
 type Aes256CbcDec = cbc::Decryptor<Aes256>;
 type Aes128Ctr = ctr::Ctr64BE<Aes128>;
 type Aes192Ctr = ctr::Ctr64BE<Aes192>;
@@ -47,10 +85,15 @@ fn ssh_tag(pubkey: &[u8]) -> [u8; TAG_LEN_BYTES] {
 #[allow(clippy::enum_variant_names)]
 #[derive(Clone, Copy, Debug)]
 enum OpenSshCipher {
+    Aes128Cbc,
+    Aes192Cbc,
     Aes256Cbc,
     Aes128Ctr,
     Aes192Ctr,
     Aes256Ctr,
+    Aes128Gcm,
+    Aes256Gcm,
+    ChaCha20Poly1305,
 }
 
 impl OpenSshCipher {
@@ -61,10 +104,26 @@ impl OpenSshCipher {
         ct: &[u8],
     ) -> Result<Vec<u8>, DecryptError> {
         match self {
+            OpenSshCipher::Aes128Cbc => decrypt::aes_cbc::<Aes128CbcDec>(kdf, p, ct, 16),
+            OpenSshCipher::Aes192Cbc => decrypt::aes_cbc::<Aes192CbcDec>(kdf, p, ct, 24),
             OpenSshCipher::Aes256Cbc => decrypt::aes_cbc::<Aes256CbcDec>(kdf, p, ct, 32),
             OpenSshCipher::Aes128Ctr => Ok(decrypt::aes_ctr::<Aes128Ctr>(kdf, p, ct, 16)),
             OpenSshCipher::Aes192Ctr => Ok(decrypt::aes_ctr::<Aes192Ctr>(kdf, p, ct, 24)),
             OpenSshCipher::Aes256Ctr => Ok(decrypt::aes_ctr::<Aes256Ctr>(kdf, p, ct, 32)),
+            OpenSshCipher::Aes128Gcm => decrypt::aes_gcm::<Aes128Gcm>(kdf, p, ct, 16),
+            OpenSshCipher::Aes256Gcm => decrypt::aes_gcm::<Aes256Gcm>(kdf, p, ct, 32),
+            OpenSshCipher::ChaCha20Poly1305 => decrypt::chacha20_poly1305(kdf, p, ct),
+        }
+    }
+
+    /// Returns the length of the authentication tag that follows the encrypted private
+    /// key, for AEAD ciphers.
+    fn auth_len(self) -> usize {
+        match self {
+            OpenSshCipher::Aes128Gcm
+            | OpenSshCipher::Aes256Gcm
+            | OpenSshCipher::ChaCha20Poly1305 => 16,
+            _ => 0,
         }
     }
 }
@@ -88,13 +147,24 @@ impl OpenSshKdf {
     }
 }
 
+/// The format and encryption of an encrypted SSH private key.
+#[derive(Clone)]
+enum KeyEncryption {
+    /// An OpenSSH private key.
+    OpenSsh {
+        cipher: OpenSshCipher,
+        kdf: OpenSshKdf,
+        encrypted: Vec<u8>,
+    },
+    /// A PuTTY private key.
+    Ppk(ppk::EncryptedPpk),
+}
+
 /// An encrypted SSH private key.
 #[derive(Clone)]
 pub struct EncryptedKey {
     ssh_key: Vec<u8>,
-    cipher: OpenSshCipher,
-    kdf: OpenSshKdf,
-    encrypted: Vec<u8>,
+    encryption: KeyEncryption,
     filename: Option<String>,
 }
 
@@ -104,9 +174,15 @@ impl EncryptedKey {
         &self,
         passphrase: SecretString,
     ) -> Result<identity::UnencryptedKey, DecryptError> {
-        let decrypted = self
-            .cipher
-            .decrypt(&self.kdf, passphrase, &self.encrypted)?;
+        let (cipher, kdf, encrypted) = match &self.encryption {
+            KeyEncryption::OpenSsh {
+                cipher,
+                kdf,
+                encrypted,
+            } => (cipher, kdf, encrypted),
+            KeyEncryption::Ppk(key) => return key.decrypt(&self.ssh_key, passphrase),
+        };
+        let decrypted = cipher.decrypt(kdf, passphrase, encrypted)?;
 
         let mut parser = read_ssh::openssh_unencrypted_privkey(&self.ssh_key);
         match parser(&decrypted)
@@ -121,8 +197,14 @@ impl EncryptedKey {
 }
 
 mod decrypt {
-    use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit, StreamCipher};
+    use aes::cipher::{
+        block_padding::NoPadding, BlockDecryptMut, KeyIvInit, StreamCipher, StreamCipherSeek,
+    };
+    use aes_gcm::aead::{Aead, KeyInit, Nonce};
     use age_core::secrecy::SecretString;
+    use chacha20::ChaCha20Legacy;
+    use poly1305::Poly1305;
+    use subtle::ConstantTimeEq;
 
     use super::OpenSshKdf;
     use crate::error::DecryptError;
@@ -157,6 +239,65 @@ mod decrypt {
         cipher.apply_keystream(&mut plaintext);
         plaintext
     }
+
+    /// `ciphertext` is the encrypted private key, followed by the authentication tag.
+    pub(super) fn aes_gcm<C: Aead + KeyInit>(
+        kdf: &OpenSshKdf,
+        passphrase: SecretString,
+        ciphertext: &[u8],
+        key_len: usize,
+    ) -> Result<Vec<u8>, DecryptError> {
+        let kdf_output = kdf.derive(passphrase, key_len + 12);
+        let (key, nonce) = kdf_output.split_at(key_len);
+
+        let cipher = C::new_from_slice(key).expect("key is correct length");
+        cipher
+            .decrypt(Nonce::<C>::from_slice(nonce), ciphertext)
+            .map_err(|_| DecryptError::KeyDecryptionFailed)
+    }
+
+    /// OpenSSH's `chacha20-poly1305@openssh.com`, as used for private keys.
+    ///
+    /// `ciphertext` is the encrypted private key, followed by the authentication tag.
+    ///
+    /// The KDF output is two 256-bit ChaCha20 keys, of which only the first is used:
+    /// the second encrypts packet lengths in the SSH transport, and private keys have
+    /// none. The nonce is the (zero) sequence number. The first block of the keystream
+    /// is the Poly1305 key, and the private key is encrypted starting from the second.
+    ///
+    /// - [Specification](https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.chacha20poly1305)
+    pub(super) fn chacha20_poly1305(
+        kdf: &OpenSshKdf,
+        passphrase: SecretString,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, DecryptError> {
+        let kdf_output = kdf.derive(passphrase, 64);
+        let key = &kdf_output[..32];
+        let nonce = [0; 8];
+
+        let (ciphertext, tag) = ciphertext.split_at(
+            ciphertext
+                .len()
+                .checked_sub(16)
+                .ok_or(DecryptError::KeyDecryptionFailed)?,
+        );
+
+        let mut cipher =
+            ChaCha20Legacy::new_from_slices(key, &nonce).expect("key and nonce are correct length");
+        let mut poly_key = [0; 32];
+        cipher.apply_keystream(&mut poly_key);
+        let expected = Poly1305::new_from_slice(&poly_key)
+            .expect("key is correct length")
+            .compute_unpadded(ciphertext);
+        if !bool::from(expected.as_slice().ct_eq(tag)) {
+            return Err(DecryptError::KeyDecryptionFailed);
+        }
+
+        let mut plaintext = ciphertext.to_vec();
+        cipher.seek(64);
+        cipher.apply_keystream(&mut plaintext);
+        Ok(plaintext)
+    }
 }
 
 mod read_ssh {
@@ -176,7 +317,7 @@ mod read_ssh {
 
     use super::{
         identity::{UnencryptedKey, UnsupportedKey},
-        EncryptedKey, Identity, OpenSshCipher, OpenSshKdf, SSH_ED25519_KEY_PREFIX,
+        EncryptedKey, Identity, KeyEncryption, OpenSshCipher, OpenSshKdf, SSH_ED25519_KEY_PREFIX,
         SSH_RSA_KEY_PREFIX,
     };
 
@@ -204,7 +345,7 @@ mod read_ssh {
     /// included.  The value zero MUST be stored as a string with zero
     /// bytes of data.
     /// ```
-    fn mpint(input: &[u8]) -> IResult<&[u8], BigUint> {
+    pub(super) fn mpint(input: &[u8]) -> IResult<&[u8], BigUint> {
         map_opt(string, |bytes| {
             if bytes.is_empty() {
                 Some(BigUint::zero())
@@ -244,6 +385,12 @@ mod read_ssh {
             map(
                 tuple((
                     alt((
+                        map(string_tag("aes128-cbc"), |_| {
+                            CipherResult::Supported(OpenSshCipher::Aes128Cbc)
+                        }),
+                        map(string_tag("aes192-cbc"), |_| {
+                            CipherResult::Supported(OpenSshCipher::Aes192Cbc)
+                        }),
                         map(string_tag("aes256-cbc"), |_| {
                             CipherResult::Supported(OpenSshCipher::Aes256Cbc)
                         }),
@@ -256,6 +403,15 @@ mod read_ssh {
                         map(string_tag("aes256-ctr"), |_| {
                             CipherResult::Supported(OpenSshCipher::Aes256Ctr)
                         }),
+                        map(string_tag("aes128-gcm@openssh.com"), |_| {
+                            CipherResult::Supported(OpenSshCipher::Aes128Gcm)
+                        }),
+                        map(string_tag("aes256-gcm@openssh.com"), |_| {
+                            CipherResult::Supported(OpenSshCipher::Aes256Gcm)
+                        }),
+                        map(string_tag("chacha20-poly1305@openssh.com"), |_| {
+                            CipherResult::Supported(OpenSshCipher::ChaCha20Poly1305)
+                        }),
                         map(string, |s| {
                             CipherResult::Unsupported(String::from_utf8_lossy(s).into_owned())
                         }),
@@ -398,24 +554,34 @@ mod read_ssh {
                         tag(b"\x00\x00\x00\x01"),
                         string, // The public key in SSH format
                     ),
-                    string, // The private key in SSH format
+                    pair(
+                        string, // The private key in SSH format
+                        rest,   // The authentication tag, for AEAD ciphers
+                    ),
                 ),
             ),
-            |(encryption, (ssh_key, private))| match &encryption {
+            |(encryption, (ssh_key, (private, auth_tag)))| match &encryption {
                 None => {
                     let (_, privkey) = openssh_unencrypted_privkey(ssh_key)(private).ok()?;
                     Some(privkey)
                 }
-                Some((CipherResult::Supported(cipher), kdf)) => Some(
-                    EncryptedKey {
-                        ssh_key: ssh_key.to_vec(),
-                        cipher: *cipher,
-                        kdf: kdf.clone(),
-                        encrypted: private.to_vec(),
-                        filename: None,
+                Some((CipherResult::Supported(cipher), kdf)) => {
+                    if auth_tag.len() != cipher.auth_len() {
+                        return None;
                     }
-                    .into(),
-                ),
+                    Some(
+                        EncryptedKey {
+                            ssh_key: ssh_key.to_vec(),
+                            encryption: KeyEncryption::OpenSsh {
+                                cipher: *cipher,
+                                kdf: kdf.clone(),
+                                encrypted: [private, auth_tag].concat(),
+                            },
+                            filename: None,
+                        }
+                        .into(),
+                    )
+                }
                 Some((CipherResult::Unsupported(cipher), _)) => {
                     Some(UnsupportedKey::EncryptedSsh(cipher.clone()).into())
                 }
@@ -440,6 +606,17 @@ mod read_ssh {
         )(input)
     }
 
+    /// The size in bits of the modulus of an SSH-encoded RSA public key.
+    ///
+    /// Unlike [`rsa_pubkey`], this does not validate the key, so it can be used to
+    /// explain why a key was rejected.
+    pub(super) fn rsa_pubkey_bits(input: &[u8]) -> IResult<&[u8], usize> {
+        preceded(
+            string_tag(SSH_RSA_KEY_PREFIX),
+            map(tuple((mpint, mpint)), |(_, modulus)| modulus.bits()),
+        )(input)
+    }
+
     /// An SSH-encoded Ed25519 public key.
     ///
     /// From [draft-ietf-curdle-ssh-ed25519-02](https://tools.ietf.org/html/draft-ietf-curdle-ssh-ed25519-02#section-4):
@@ -0,0 +1,388 @@
+//! PuTTY private key files (`.ppk`), in versions 2 and 3 of the format.
+//!
+//! - [Specification](https://the.earth.li/~sgtatham/putty/0.78/htmldoc/AppendixC.html)
+
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit};
+use age_core::secrecy::{ExposeSecret, Secret, SecretString};
+use hmac::{Hmac, Mac};
+use nom::{
+    branch::alt,
+    bytes::streaming::{is_not, tag},
+    character::streaming::line_ending,
+    combinator::{flat_map, map, map_opt, map_res, opt},
+    multi::count,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+use rsa::PublicKeyParts;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use super::{
+    identity::{UnencryptedKey, UnsupportedKey},
+    read_ssh, Aes256CbcDec, EncryptedKey, Identity, KeyEncryption, SSH_ED25519_KEY_PREFIX,
+    SSH_RSA_KEY_PREFIX,
+};
+use crate::error::DecryptError;
+
+const V2_MAC_KEY_LABEL: &[u8] = b"putty-private-key-file-mac-key";
+
+/// The only cipher that PuTTY encrypts private keys with.
+const PPK_CIPHER: &str = "aes256-cbc";
+const PPK_CIPHER_BLOCK_LEN: usize = 16;
+
+/// The most memory that we let Argon2 use, in KiB. PuTTY uses 8 MiB by default.
+const MAX_ARGON2_MEMORY: u32 = 1 << 20;
+
+/// The most passes that we let Argon2 make. PuTTY chooses as many as take 100ms by
+/// default, which is usually a few dozen.
+const MAX_ARGON2_PASSES: u32 = 256;
+
+/// The most lanes that we let Argon2 use. PuTTY uses 1 by default.
+const MAX_ARGON2_PARALLELISM: u32 = 64;
+
+#[derive(Clone, Copy, Debug)]
+enum Version {
+    V2,
+    V3,
+}
+
+/// The parameters for deriving the keys of an encrypted version 3 file.
+#[derive(Clone, Debug)]
+struct Argon2Params {
+    algorithm: argon2::Algorithm,
+    memory: u32,
+    passes: u32,
+    parallelism: u32,
+    salt: Vec<u8>,
+}
+
+/// The keys that protect the private key.
+struct Keys {
+    cipher_key: Zeroizing<[u8; 32]>,
+    iv: [u8; 16],
+    mac_key: Zeroizing<Vec<u8>>,
+}
+
+impl Keys {
+    /// Derives the keys from `passphrase`, which is empty for unencrypted files.
+    fn derive(
+        version: Version,
+        argon2: Option<&Argon2Params>,
+        passphrase: &[u8],
+    ) -> Result<Self, DecryptError> {
+        let mut keys = Keys {
+            cipher_key: Zeroizing::new([0; 32]),
+            iv: [0; 16],
+            mac_key: Zeroizing::new(vec![]),
+        };
+        match (version, argon2) {
+            (Version::V2, _) => {
+                // The cipher key is SHA-1(0 || passphrase) || SHA-1(1 || passphrase),
+                // truncated to 32 bytes, and the IV is zero.
+                let mut cipher_key = Zeroizing::new(vec![]);
+                for i in 0u32..2 {
+                    let mut hasher = Sha1::new();
+                    hasher.update(i.to_be_bytes());
+                    hasher.update(passphrase);
+                    cipher_key.extend_from_slice(&hasher.finalize());
+                }
+                keys.cipher_key.copy_from_slice(&cipher_key[..32]);
+
+                let mut hasher = Sha1::new();
+                hasher.update(V2_MAC_KEY_LABEL);
+                hasher.update(passphrase);
+                keys.mac_key.extend_from_slice(&hasher.finalize());
+            }
+            (Version::V3, Some(params)) => {
+                let argon2 = argon2::Argon2::new(
+                    params.algorithm,
+                    argon2::Version::V0x13,
+                    argon2::Params::new(params.memory, params.passes, params.parallelism, Some(80))
+                        .map_err(|_| DecryptError::KeyDecryptionFailed)?,
+                );
+                let mut output = Zeroizing::new([0; 80]);
+                argon2
+                    .hash_password_into(passphrase, &params.salt, &mut output[..])
+                    .map_err(|_| DecryptError::KeyDecryptionFailed)?;
+                keys.cipher_key.copy_from_slice(&output[..32]);
+                keys.iv.copy_from_slice(&output[32..48]);
+                keys.mac_key.extend_from_slice(&output[48..]);
+            }
+            // Unencrypted version 3 files are authenticated with an empty MAC key.
+            (Version::V3, None) => (),
+        }
+        Ok(keys)
+    }
+}
+
+/// The parts of a PPK file that are authenticated by its MAC.
+#[derive(Clone)]
+struct Contents {
+    version: Version,
+    algorithm: String,
+    encryption: String,
+    comment: String,
+    mac: Vec<u8>,
+}
+
+impl Contents {
+    /// Checks the MAC over the file, given its decrypted (but still padded) private key.
+    fn verify_mac(&self, mac_key: &[u8], public: &[u8], private: &[u8]) -> bool {
+        let mut data = vec![];
+        for field in [
+            self.algorithm.as_bytes(),
+            self.encryption.as_bytes(),
+            self.comment.as_bytes(),
+            public,
+            private,
+        ] {
+            data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            data.extend_from_slice(field);
+        }
+        let data = Zeroizing::new(data);
+
+        match self.version {
+            Version::V2 => {
+                let mut mac =
+                    Hmac::<Sha1>::new_from_slice(mac_key).expect("HMAC accepts any key length");
+                mac.update(&data);
+                mac.verify_slice(&self.mac).is_ok()
+            }
+            Version::V3 => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(mac_key).expect("HMAC accepts any key length");
+                mac.update(&data);
+                mac.verify_slice(&self.mac).is_ok()
+            }
+        }
+    }
+}
+
+/// An encrypted PuTTY private key.
+#[derive(Clone)]
+pub(super) struct EncryptedPpk {
+    contents: Contents,
+    argon2: Option<Argon2Params>,
+    encrypted: Vec<u8>,
+}
+
+impl EncryptedPpk {
+    /// Decrypts this private key, the public key of which is `ssh_key`.
+    pub(super) fn decrypt(
+        &self,
+        ssh_key: &[u8],
+        passphrase: SecretString,
+    ) -> Result<UnencryptedKey, DecryptError> {
+        let keys = Keys::derive(
+            self.contents.version,
+            self.argon2.as_ref(),
+            passphrase.expose_secret().as_bytes(),
+        )?;
+
+        let private = Zeroizing::new(
+            Aes256CbcDec::new_from_slices(&keys.cipher_key[..], &keys.iv)
+                .expect("key and IV are correct length")
+                .decrypt_padded_vec_mut::<NoPadding>(&self.encrypted)
+                .map_err(|_| DecryptError::KeyDecryptionFailed)?,
+        );
+
+        // An incorrect passphrase gives the wrong MAC key.
+        if !self.contents.verify_mac(&keys.mac_key, ssh_key, &private) {
+            return Err(DecryptError::KeyDecryptionFailed);
+        }
+
+        match private_key(ssh_key, &private) {
+            Some(Identity::Unencrypted(key)) => Ok(key),
+            _ => Err(DecryptError::KeyDecryptionFailed),
+        }
+    }
+}
+
+/// Parses the decrypted private key, the public key of which is `ssh_key`.
+///
+/// Any padding after the private key is ignored; it has already been authenticated by
+/// the MAC.
+fn private_key(ssh_key: &[u8], private: &[u8]) -> Option<Identity> {
+    if let Ok((_, pubkey)) = preceded(
+        read_ssh::string_tag(SSH_ED25519_KEY_PREFIX),
+        read_ssh::string,
+    )(ssh_key)
+    {
+        // string  private exponent, little-endian with trailing zero bytes removed
+        let (_, seed) = read_ssh::string(private).ok()?;
+        if pubkey.len() != 32 || seed.len() > 32 {
+            return None;
+        }
+
+        let mut privkey = [0; 64];
+        privkey[..seed.len()].copy_from_slice(seed);
+        privkey[32..].copy_from_slice(pubkey);
+        Some(UnencryptedKey::SshEd25519(ssh_key.to_vec(), Secret::new(privkey)).into())
+    } else if let Ok((_, pubkey)) = read_ssh::rsa_pubkey(ssh_key) {
+        // mpint  d, mpint  p, mpint  q, mpint  iqmp
+        let (_, (d, p, q, _iqmp)) = tuple((
+            read_ssh::mpint,
+            read_ssh::mpint,
+            read_ssh::mpint,
+            read_ssh::mpint,
+        ))(private)
+        .ok()?;
+        rsa::RsaPrivateKey::from_components(pubkey.n().clone(), pubkey.e().clone(), d, vec![p, q])
+            .ok()
+            .map(|sk| UnencryptedKey::SshRsa(ssh_key.to_vec(), Box::new(sk)).into())
+    } else {
+        None
+    }
+}
+
+fn hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Recognizes a `Name: value` line, returning the value (which may be empty).
+fn header<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    delimited(
+        pair(tag(name), tag(": ")),
+        map(opt(is_not("\r\n")), |value| value.unwrap_or("")),
+        line_ending,
+    )
+}
+
+fn number<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, u32> {
+    map_res(header(name), str::parse)
+}
+
+/// Recognizes a `Name-Lines: N` line (given `Name-Lines` as `name`) followed by `N`
+/// lines of Base64.
+fn base64_lines<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<u8>> {
+    map_opt(
+        flat_map(number(name), |lines| {
+            count(terminated(is_not("\r\n"), line_ending), lines as usize)
+        }),
+        |lines| base64::decode(lines.concat()).ok(),
+    )
+}
+
+fn argon2_params(input: &str) -> IResult<&str, Argon2Params> {
+    map_opt(
+        tuple((
+            header("Key-Derivation"),
+            number("Argon2-Memory"),
+            number("Argon2-Passes"),
+            number("Argon2-Parallelism"),
+            map_opt(header("Argon2-Salt"), hex),
+        )),
+        |(algorithm, memory, passes, parallelism, salt)| {
+            let algorithm = match algorithm {
+                "Argon2d" => argon2::Algorithm::Argon2d,
+                "Argon2i" => argon2::Algorithm::Argon2i,
+                "Argon2id" => argon2::Algorithm::Argon2id,
+                _ => return None,
+            };
+            if memory > MAX_ARGON2_MEMORY
+                || passes > MAX_ARGON2_PASSES
+                || parallelism > MAX_ARGON2_PARALLELISM
+            {
+                return None;
+            }
+            Some(Argon2Params {
+                algorithm,
+                memory,
+                passes,
+                parallelism,
+                salt,
+            })
+        },
+    )(input)
+}
+
+/// A PuTTY private key file.
+///
+/// ```text
+/// PuTTY-User-Key-File-3: ssh-ed25519
+/// Encryption: aes256-cbc
+/// Comment: alice@example.com
+/// Public-Lines: 2
+/// ...
+/// Key-Derivation: Argon2id
+/// Argon2-Memory: 8192
+/// Argon2-Passes: 13
+/// Argon2-Parallelism: 1
+/// Argon2-Salt: ...
+/// Private-Lines: 1
+/// ...
+/// Private-MAC: ...
+/// ```
+///
+/// The `Key-Derivation` and `Argon2-*` lines are only present in encrypted version 3
+/// files.
+pub(super) fn ppk_privkey(input: &str) -> IResult<&str, Identity> {
+    map_opt(
+        tuple((
+            alt((
+                map(tag("PuTTY-User-Key-File-2: "), |_| Version::V2),
+                map(tag("PuTTY-User-Key-File-3: "), |_| Version::V3),
+            )),
+            terminated(is_not("\r\n"), line_ending),
+            header("Encryption"),
+            header("Comment"),
+            base64_lines("Public-Lines"),
+            opt(argon2_params),
+            base64_lines("Private-Lines"),
+            map_opt(header("Private-MAC"), hex),
+        )),
+        |(version, algorithm, encryption, comment, public, argon2, private, mac)| {
+            if algorithm != SSH_ED25519_KEY_PREFIX && algorithm != SSH_RSA_KEY_PREFIX {
+                return Some(UnsupportedKey::Type(algorithm.into()).into());
+            }
+            let (_, key_type) = read_ssh::string(&public).ok()?;
+            if key_type != algorithm.as_bytes() {
+                return None;
+            }
+
+            let contents = Contents {
+                version,
+                algorithm: algorithm.into(),
+                encryption: encryption.into(),
+                comment: comment.into(),
+                mac,
+            };
+            match (encryption, version, &argon2) {
+                ("none", _, None) => {
+                    let keys = Keys::derive(version, None, b"").ok()?;
+                    if contents.verify_mac(&keys.mac_key, &public, &private) {
+                        private_key(&public, &private)
+                    } else {
+                        None
+                    }
+                }
+                (PPK_CIPHER, Version::V2, None) | (PPK_CIPHER, Version::V3, Some(_))
+                    if private.len() % PPK_CIPHER_BLOCK_LEN == 0 =>
+                {
+                    Some(
+                        EncryptedKey {
+                            ssh_key: public,
+                            encryption: KeyEncryption::Ppk(EncryptedPpk {
+                                contents,
+                                argon2,
+                                encrypted: private,
+                            }),
+                            filename: None,
+                        }
+                        .into(),
+                    )
+                }
+                ("none", _, Some(_)) | (PPK_CIPHER, _, _) => None,
+                (cipher, _, _) => Some(UnsupportedKey::EncryptedSsh(cipher.into()).into()),
+            }
+        },
+    )(input)
+}
@@ -19,9 +19,9 @@ use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, StaticSecret};
 
 use super::{
     identity::{Identity, UnencryptedKey},
-    read_ssh, ssh_tag, EncryptedKey, UnsupportedKey, SSH_ED25519_KEY_PREFIX,
-    SSH_ED25519_RECIPIENT_KEY_LABEL, SSH_ED25519_RECIPIENT_TAG, SSH_RSA_KEY_PREFIX,
-    SSH_RSA_OAEP_LABEL, SSH_RSA_RECIPIENT_TAG,
+    read_ssh, ssh_tag, EncryptedKey, UnsupportedKey, SSH_DSS_KEY_PREFIX, SSH_ED25519_KEY_PREFIX,
+    SSH_ED25519_RECIPIENT_KEY_LABEL, SSH_ED25519_RECIPIENT_TAG, SSH_RSA_KEY_BITS,
+    SSH_RSA_KEY_PREFIX, SSH_RSA_OAEP_LABEL, SSH_RSA_RECIPIENT_TAG,
 };
 use crate::{
     error::EncryptError,
@@ -40,6 +40,8 @@ pub enum Recipient {
 pub(crate) enum ParsedRecipient {
     Supported(Recipient),
     Unsupported(String),
+    UnsupportedRsaKeySize(usize),
+    Dsa,
 }
 
 /// Error conditions when parsing an SSH recipient.
@@ -53,6 +55,30 @@ pub enum ParseRecipientKeyError {
     Invalid(&'static str),
     /// The string is a parseable value that corresponds to an unsupported SSH key type.
     Unsupported(String),
+    /// The string is an ssh-rsa public key with an unsupported modulus size (in bits).
+    UnsupportedRsaKeySize(usize),
+    /// The string is an ssh-dss (DSA) public key, which is too weak to encrypt to.
+    Dsa,
+}
+
+impl fmt::Display for ParseRecipientKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseRecipientKeyError::Ignore => write!(f, "ignored SSH key type"),
+            ParseRecipientKeyError::Invalid(e) => write!(f, "{}", e),
+            ParseRecipientKeyError::Unsupported(key_type) => {
+                write!(f, "unsupported SSH key type: {}", key_type)
+            }
+            ParseRecipientKeyError::UnsupportedRsaKeySize(bits) => write!(
+                f,
+                "unsupported ssh-rsa key size: {} bits (must be between {} and {})",
+                bits,
+                SSH_RSA_KEY_BITS.start(),
+                SSH_RSA_KEY_BITS.end(),
+            ),
+            ParseRecipientKeyError::Dsa => write!(f, "DSA SSH keys are not supported"),
+        }
+    }
 }
 
 impl std::str::FromStr for Recipient {
@@ -65,6 +91,10 @@ impl std::str::FromStr for Recipient {
             Ok((_, ParsedRecipient::Unsupported(key_type))) => {
                 Err(ParseRecipientKeyError::Unsupported(key_type))
             }
+            Ok((_, ParsedRecipient::UnsupportedRsaKeySize(bits))) => {
+                Err(ParseRecipientKeyError::UnsupportedRsaKeySize(bits))
+            }
+            Ok((_, ParsedRecipient::Dsa)) => Err(ParseRecipientKeyError::Dsa),
             _ => Err(ParseRecipientKeyError::Invalid("invalid SSH recipient")),
         }
     }
@@ -83,6 +113,9 @@ impl fmt::Display for Recipient {
     }
 }
 
+#[cfg(feature = "serde")]
+crate::util::serde_via_str!(Recipient);
+
 impl TryFrom<Identity> for Recipient {
     type Error = ParseRecipientKeyError;
 
@@ -91,6 +124,12 @@ impl TryFrom<Identity> for Recipient {
             Identity::Unencrypted(UnencryptedKey::SshRsa(ssh_key, _))
             | Identity::Unencrypted(UnencryptedKey::SshEd25519(ssh_key, _))
             | Identity::Encrypted(EncryptedKey { ssh_key, .. }) => {
+                if let Ok((_, bits)) = read_ssh::rsa_pubkey_bits(&ssh_key) {
+                    if !SSH_RSA_KEY_BITS.contains(&bits) {
+                        return Err(ParseRecipientKeyError::UnsupportedRsaKeySize(bits));
+                    }
+                }
+
                 if let Ok((_, pk)) = read_ssh::rsa_pubkey(&ssh_key) {
                     Ok(Recipient::SshRsa(ssh_key, pk))
                 } else if let Ok((_, pk)) = read_ssh::ed25519_pubkey(&ssh_key) {
@@ -108,6 +147,10 @@ impl TryFrom<Identity> for Recipient {
             Identity::Unsupported(UnsupportedKey::Type(key_type)) => {
                 Err(ParseRecipientKeyError::Unsupported(key_type))
             }
+            Identity::Unsupported(UnsupportedKey::RsaKeySize(bits)) => {
+                Err(ParseRecipientKeyError::UnsupportedRsaKeySize(bits))
+            }
+            Identity::Unsupported(UnsupportedKey::Dsa) => Err(ParseRecipientKeyError::Dsa),
             Identity::Unsupported(_) => Err(ParseRecipientKeyError::Ignore),
         }
     }
@@ -129,11 +172,11 @@ impl crate::Recipient for Recipient {
 
                 let encoded_tag = base64::encode_config(&ssh_tag(ssh_key), base64::STANDARD_NO_PAD);
 
-                Ok(vec![Stanza {
-                    tag: SSH_RSA_RECIPIENT_TAG.to_owned(),
-                    args: vec![encoded_tag],
-                    body: encrypted_file_key,
-                }])
+                Ok(vec![Stanza::new(
+                    SSH_RSA_RECIPIENT_TAG.to_owned(),
+                    vec![encoded_tag],
+                    encrypted_file_key,
+                )])
             }
             Recipient::SshEd25519(ssh_key, ed25519_pk) => {
                 let pk: X25519PublicKey = ed25519_pk.to_montgomery().to_bytes().into();
@@ -161,11 +204,11 @@ impl crate::Recipient for Recipient {
                 let encoded_tag = base64::encode_config(&ssh_tag(ssh_key), base64::STANDARD_NO_PAD);
                 let encoded_epk = base64::encode_config(epk.as_bytes(), base64::STANDARD_NO_PAD);
 
-                Ok(vec![Stanza {
-                    tag: SSH_ED25519_RECIPIENT_TAG.to_owned(),
-                    args: vec![encoded_tag, encoded_epk],
-                    body: encrypted_file_key,
-                }])
+                Ok(vec![Stanza::new(
+                    SSH_ED25519_RECIPIENT_TAG.to_owned(),
+                    vec![encoded_tag, encoded_epk],
+                    encrypted_file_key,
+                )])
             }
         }
     }
@@ -174,13 +217,18 @@ impl crate::Recipient for Recipient {
 fn ssh_rsa_pubkey(input: &str) -> IResult<&str, ParsedRecipient> {
     preceded(
         pair(tag(SSH_RSA_KEY_PREFIX), tag(" ")),
-        map_opt(
-            str_while_encoded(base64::STANDARD_NO_PAD),
-            |ssh_key| match read_ssh::rsa_pubkey(&ssh_key) {
-                Ok((_, pk)) => Some(ParsedRecipient::Supported(Recipient::SshRsa(ssh_key, pk))),
+        map_opt(str_while_encoded(base64::STANDARD_NO_PAD), |ssh_key| {
+            match read_ssh::rsa_pubkey_bits(&ssh_key) {
+                Ok((_, bits)) if !SSH_RSA_KEY_BITS.contains(&bits) => {
+                    Some(ParsedRecipient::UnsupportedRsaKeySize(bits))
+                }
+                Ok(_) => match read_ssh::rsa_pubkey(&ssh_key) {
+                    Ok((_, pk)) => Some(ParsedRecipient::Supported(Recipient::SshRsa(ssh_key, pk))),
+                    Err(_) => None,
+                },
                 Err(_) => None,
-            },
-        ),
+            }
+        }),
     )(input)
 }
 
@@ -210,7 +258,13 @@ fn ssh_ignore_pubkey(input: &str) -> IResult<&str, ParsedRecipient> {
         ),
         |(key_type, ssh_key)| {
             read_ssh::string_tag(key_type)(&ssh_key)
-                .map(|_| ParsedRecipient::Unsupported(key_type.to_string()))
+                .map(|_| {
+                    if key_type == SSH_DSS_KEY_PREFIX {
+                        ParsedRecipient::Dsa
+                    } else {
+                        ParsedRecipient::Unsupported(key_type.to_string())
+                    }
+                })
                 .ok()
         },
     )(input)
@@ -227,6 +281,7 @@ pub(crate) mod tests {
     pub(crate) const TEST_SSH_RSA_PK: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQDE7nIXTGNuaRBN9toI/wNALuQec8mvlt0iJ7o3OaD2UvoKHJ7S8rmIn4FiQDUed/Vac3OhUibei1k+TBmm16u2Rj3klgWZOIDgi8d4vXKI5N3YBhxr3jsQ+kz1c+iZ4z/tTtz306+4K46XViVMWwyyg9j82Jn41mOAy9vdeDIfQ5fLeaGqn5KwlT61GNkZ+ozWK/ZNlQIlNCcoXxhJULIs9XrtczWyVBAea1nlDo0WHODePxoJjmsNHrpQXn5mf9O83xs10qfTUjnRUt48jRmedFy4tcra3QGmSTQ3KZne+wXXSb0cIpXLGvZjQSPHgG1hc4r3uBpiSzvesGLv79XL alice@rust";
     pub(crate) const TEST_SSH_ED25519_PK: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHsKLqeplhpW+uObz5dvMgjz1OxfM/XXUB+VHtZ6isGN alice@rust";
     const TEST_SSH_UNSUPPORTED_PK: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBHFliOyIZs1gxGF3fmDxFykQhE88wy6AKDGFBfn0R6ZuvRmENABZQa9+pj9hMki+LX0qDJbmHTiWDbYv/cmFt/Q=";
+    const TEST_SSH_DSA_PK: &str = "ssh-dss AAAAB3NzaC1kc3MAAACBALaAM1TofEIar2r2q2SFIjz+nBEk8KmsC1VKM7rZ9xIM5SQ1SsONxv/yJ86HynaH1W7rl+coBharbpe6TiY3AxxziQs7AfZ2hjEeRmNmz04fkmocLhvMhmRqMx+YEhAa8CDMevQ9NSgeo0fdB3ABqH3XygrHf2twwUs/ei/75FnjAAAAFQCEMICOWa3kAvwHcWcJpsyxmCQHrQAAAIBRy0Of91rAllDEwMOy0R9hUkXroe6YqEdE7hSoBDuS0+G3Y5z+uiMz02bS4jkAUO+Z6sVQm8BxTvud+EexVitae5MEQN1d6caGEqscHi9Gnw9ihgiN2htXr/OSfSOJch1cnwm/fd7AtVCxdRKmCTby58SH3+A9y3ona/24Ur9WegAAAIBM8/C9toac2oS73WsEFNeVBfueHR4QUr4fjONdzORhLsrUtrfvntSXwWspV792j+oh1UzpUbWVfx0gxHL+1uORFNRDienDIHx7tlysZOSv6UzhjwhR75Rdl7xMNv+Z9y16Fnsc/lkIIVjAmRVpE57FbjgDZFiMQRfDgX7DEJUoJQ== alice@rust";
     const TEST_SSH_INVALID_PK: &str = "ecdsa-sha2-nistp256 AAAAC3NzaC1lZDI1NTE5AAAAIHsKLqeplhpW+uObz5dvMgjz1OxfM/XXUB+VHtZ6isGN alice@rust";
 
     #[test]
@@ -250,6 +305,12 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn ssh_dsa_key() {
+        let pk: Result<Recipient, ParseRecipientKeyError> = TEST_SSH_DSA_PK.parse();
+        assert_eq!(pk.unwrap_err(), ParseRecipientKeyError::Dsa);
+    }
+
     #[test]
     fn ssh_invalid_encoding() {
         let pk: Result<Recipient, ParseRecipientKeyError> = TEST_SSH_INVALID_PK.parse();
@@ -0,0 +1,214 @@
+//! Format rules for the recipient stanza types that age knows about.
+//!
+//! Each stanza type defines a [`Rule`] alongside its recipient and identity, which is
+//! listed in [`RULES`]. The header parser checks every stanza against the rule for its
+//! tag, so identities can rely on any stanza they are given having the correct shape.
+
+use age_core::format::Stanza;
+
+use crate::{
+    error::StanzaError,
+    util::read::{base64_arg, decimal_digit_arg},
+};
+
+/// The expected format of a stanza argument.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Arg {
+    /// The canonical unpadded base64 encoding of this many bytes.
+    Base64(usize),
+    /// A decimal number between 0 and 255 without leading zeroes.
+    Decimal,
+    /// Any argument that the header parser accepts.
+    Any,
+}
+
+/// The expected length of a stanza body.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Body {
+    /// The body is exactly this many bytes.
+    Exact(usize),
+    /// The body can have any length.
+    #[cfg_attr(not(any(feature = "kms", feature = "ssh")), allow(dead_code))]
+    Any,
+}
+
+/// The format of a recipient stanza type.
+#[derive(Debug)]
+pub(crate) struct Rule {
+    pub(crate) tag: &'static str,
+    pub(crate) args: &'static [Arg],
+    pub(crate) body: Body,
+}
+
+impl Rule {
+    /// Checks that `stanza` matches this format.
+    ///
+    /// The caller must have already checked that `stanza` has this rule's tag.
+    pub(crate) fn check(&self, stanza: &Stanza) -> Result<(), StanzaError> {
+        if stanza.args.len() != self.args.len() {
+            return Err(StanzaError::ArgCount {
+                tag: stanza.tag.clone(),
+                expected: self.args.len(),
+                actual: stanza.args.len(),
+            });
+        }
+
+        for (index, (arg, expected)) in stanza.args.iter().zip(self.args).enumerate() {
+            match expected {
+                Arg::Base64(len) => {
+                    if base64_arg(arg, vec![0; *len]).is_none() {
+                        return Err(StanzaError::Base64Arg {
+                            tag: stanza.tag.clone(),
+                            index,
+                            expected_len: *len,
+                        });
+                    }
+                }
+                Arg::Decimal => {
+                    if decimal_digit_arg::<u8>(arg).is_none() {
+                        return Err(StanzaError::DecimalArg {
+                            tag: stanza.tag.clone(),
+                            index,
+                        });
+                    }
+                }
+                Arg::Any => (),
+            }
+        }
+
+        match self.body {
+            Body::Exact(len) if stanza.body_len() != len => Err(StanzaError::BodyLength {
+                tag: stanza.tag.clone(),
+                expected: len,
+                actual: stanza.body_len(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The rules for all stanza types that this build of age understands.
+///
+/// Stanzas with other tags (for example, those handled by plugins) are not checked.
+const RULES: &[&Rule] = &[
+    &crate::x25519::STANZA_RULE,
+    &crate::scrypt::STANZA_RULE,
+    &crate::threshold::THRESHOLD_STANZA_RULE,
+    &crate::threshold::SHARE_STANZA_RULE,
+    &crate::label::STANZA_RULE,
+    #[cfg(feature = "ssh")]
+    &crate::ssh::SSH_RSA_STANZA_RULE,
+    #[cfg(feature = "ssh")]
+    &crate::ssh::SSH_ED25519_STANZA_RULE,
+    #[cfg(any(feature = "cng", feature = "secure-enclave", feature = "yubikey"))]
+    &crate::piv_p256::STANZA_RULE,
+    #[cfg(feature = "kms-aws")]
+    &crate::kms::aws::STANZA_RULE,
+    #[cfg(feature = "kms-azure")]
+    &crate::kms::azure::STANZA_RULE,
+    #[cfg(feature = "kms-gcp")]
+    &crate::kms::gcp::STANZA_RULE,
+    #[cfg(feature = "kms-vault")]
+    &crate::kms::vault::STANZA_RULE,
+    #[cfg(feature = "fido2")]
+    &crate::fido2::STANZA_RULE,
+    #[cfg(feature = "pq")]
+    &crate::pq::STANZA_RULE,
+];
+
+/// Checks that `stanza` matches the format of its type, if it is a type we know.
+pub(crate) fn validate(stanza: &Stanza) -> Result<(), StanzaError> {
+    match RULES.iter().find(|rule| rule.tag == stanza.tag) {
+        Some(rule) => rule.check(stanza),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::format::Stanza;
+
+    use super::validate;
+    use crate::error::StanzaError;
+
+    fn stanza(tag: &str, args: &[&str], body_len: usize) -> Stanza {
+        Stanza::new(
+            tag.to_owned(),
+            args.iter().map(|s| s.to_string()).collect(),
+            vec![0; body_len],
+        )
+    }
+
+    const EPK: &str = "mZl7K4BhQZmEdLBTV5bGw9ftJBSKXgxk2rISQj39UCU";
+    const SALT: &str = "rF0/NwblUHHTpgQgRpe5CQ";
+
+    #[test]
+    fn valid_stanzas() {
+        assert_eq!(validate(&stanza("X25519", &[EPK], 32)), Ok(()));
+        assert_eq!(validate(&stanza("scrypt", &[SALT, "18"], 32)), Ok(()));
+    }
+
+    #[test]
+    fn unknown_stanzas_are_not_checked() {
+        assert_eq!(validate(&stanza("unknown", &["!!"], 5)), Ok(()));
+    }
+
+    #[test]
+    fn arg_count() {
+        assert_eq!(
+            validate(&stanza("X25519", &[EPK, EPK], 32)),
+            Err(StanzaError::ArgCount {
+                tag: "X25519".into(),
+                expected: 1,
+                actual: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn base64_arg() {
+        // Wrong length.
+        assert_eq!(
+            validate(&stanza("X25519", &[SALT], 32)),
+            Err(StanzaError::Base64Arg {
+                tag: "X25519".into(),
+                index: 0,
+                expected_len: 32,
+            })
+        );
+        // Padded.
+        assert_eq!(
+            validate(&stanza("scrypt", &["rF0/NwblUHHTpgQgRpe5CQ==", "18"], 32)),
+            Err(StanzaError::Base64Arg {
+                tag: "scrypt".into(),
+                index: 0,
+                expected_len: 16,
+            })
+        );
+    }
+
+    #[test]
+    fn decimal_arg() {
+        for log_n in &["018", "256", "-1", ""] {
+            assert_eq!(
+                validate(&stanza("scrypt", &[SALT, log_n], 32)),
+                Err(StanzaError::DecimalArg {
+                    tag: "scrypt".into(),
+                    index: 1,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn body_length() {
+        assert_eq!(
+            validate(&stanza("X25519", &[EPK], 33)),
+            Err(StanzaError::BodyLength {
+                tag: "X25519".into(),
+                expected: 32,
+                actual: 33,
+            })
+        );
+    }
+}
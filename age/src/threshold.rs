@@ -0,0 +1,374 @@
+//! Threshold (k-of-n) encryption, with Shamir's secret sharing of the file key.
+//!
+//! The file key is split into `n` shares, any `k` of which can reconstruct it, and each
+//! share is wrapped to one recipient as if it were a file key. The header records the
+//! structure with two stanza types that carry no secrets:
+//!
+//! ```text
+//! -> threshold 2
+//!
+//! -> share 1
+//!
+//! -> X25519 ...
+//! ...
+//! -> share 2
+//!
+//! -> X25519 ...
+//! ...
+//! ```
+//!
+//! The `threshold` stanza must come first, and each `share` stanza applies to the
+//! stanzas that follow it, up to the next `share` stanza. The header MAC is computed
+//! with the file key itself, so a reconstructed file key is verified as usual.
+
+use age_core::{
+    format::{FileKey, Stanza, FILE_KEY_BYTES},
+    secrecy::ExposeSecret,
+};
+use zeroize::Zeroize;
+
+use crate::{
+    error::{DecryptError, RngError},
+    rng::random_bytes,
+    stanza::{Arg, Body, Rule},
+    util::read::decimal_digit_arg,
+};
+
+const THRESHOLD_TAG: &str = "threshold";
+const SHARE_TAG: &str = "share";
+
+/// The format of a threshold stanza.
+pub(crate) const THRESHOLD_STANZA_RULE: Rule = Rule {
+    tag: THRESHOLD_TAG,
+    args: &[Arg::Decimal],
+    body: Body::Exact(0),
+};
+
+/// The format of a share stanza.
+pub(crate) const SHARE_STANZA_RULE: Rule = Rule {
+    tag: SHARE_TAG,
+    args: &[Arg::Decimal],
+    body: Body::Exact(0),
+};
+
+/// Multiplication in GF(2^8) with the AES polynomial, without secret-dependent branches.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut r = 0;
+    for _ in 0..8 {
+        r ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    r
+}
+
+/// Inversion in GF(2^8), as `a^254`. Only used for the public share indices.
+fn gf_inv(a: u8) -> u8 {
+    let mut r = 1;
+    for _ in 0..254 {
+        r = gf_mul(r, a);
+    }
+    r
+}
+
+/// Splits `file_key` into `shares` shares, any `threshold` of which can reconstruct it.
+///
+/// Share `i` (counting from zero) has the index `i + 1`.
+pub(crate) fn split(
+    file_key: &FileKey,
+    threshold: u8,
+    shares: u8,
+) -> Result<Vec<FileKey>, RngError> {
+    assert!(0 < threshold && threshold <= shares);
+
+    // The coefficients of one polynomial per byte of the file key, with the file key as
+    // the constant terms.
+    let mut coefficients = vec![*file_key.expose_secret()];
+    for _ in 1..threshold {
+        coefficients.push(random_bytes()?);
+    }
+
+    let shares = (1..=shares)
+        .map(|x| {
+            let mut y = [0; FILE_KEY_BYTES];
+            for coefficient in coefficients.iter().rev() {
+                for (y, c) in y.iter_mut().zip(coefficient.iter()) {
+                    *y = gf_mul(*y, x) ^ c;
+                }
+            }
+            FileKey::from(y)
+        })
+        .collect();
+
+    coefficients.zeroize();
+    Ok(shares)
+}
+
+/// Reconstructs the file key from shares with distinct, non-zero indices.
+fn combine(shares: &[(u8, FileKey)]) -> FileKey {
+    let mut file_key = [0; FILE_KEY_BYTES];
+    for (i, (x_i, y_i)) in shares.iter().enumerate() {
+        // The Lagrange basis polynomial for this share, evaluated at zero.
+        let basis = shares
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .fold(1, |acc, (_, (x_j, _))| {
+                gf_mul(acc, gf_mul(*x_j, gf_inv(x_i ^ x_j)))
+            });
+        for (k, y) in file_key.iter_mut().zip(y_i.expose_secret().iter()) {
+            *k ^= gf_mul(basis, *y);
+        }
+    }
+    FileKey::from(file_key)
+}
+
+/// Returns the stanza that starts a threshold header.
+pub(crate) fn threshold_stanza(threshold: u8) -> Stanza {
+    Stanza::new(
+        THRESHOLD_TAG.to_owned(),
+        vec![threshold.to_string()],
+        vec![],
+    )
+}
+
+/// Returns the stanza that precedes the stanzas for the share with the given index.
+pub(crate) fn share_stanza(index: u8) -> Stanza {
+    Stanza::new(SHARE_TAG.to_owned(), vec![index.to_string()], vec![])
+}
+
+/// The structure of a threshold header.
+pub(crate) struct Threshold<'a> {
+    threshold: u8,
+    /// The index of each share, and the stanzas that wrap it.
+    shares: Vec<(u8, &'a [Stanza])>,
+}
+
+impl<'a> Threshold<'a> {
+    /// Parses the structure of a header's stanzas.
+    ///
+    /// Returns `Ok(None)` if this is not a threshold header. The threshold and share
+    /// indices must be canonical decimals that fit in a `u8`, which rules out zero (share
+    /// zero would be the file key itself).
+    pub(crate) fn parse(stanzas: &'a [Stanza]) -> Result<Option<Self>, DecryptError> {
+        let is_structural = |s: &Stanza| s.tag == THRESHOLD_TAG || s.tag == SHARE_TAG;
+        let arg =
+            |s: &Stanza| decimal_digit_arg::<u8>(&s.args[0]).ok_or(DecryptError::InvalidHeader);
+
+        let (threshold, rest) = match stanzas.split_first() {
+            Some((first, rest)) if first.tag == THRESHOLD_TAG => (arg(first)?, rest),
+            _ if stanzas.iter().any(is_structural) => return Err(DecryptError::InvalidHeader),
+            _ => return Ok(None),
+        };
+
+        let mut shares: Vec<(u8, &[Stanza])> = vec![];
+        let mut rest = rest;
+        while let Some((first, tail)) = rest.split_first() {
+            if first.tag != SHARE_TAG {
+                return Err(DecryptError::InvalidHeader);
+            }
+            let index = arg(first)?;
+            if shares.iter().any(|(x, _)| *x == index) {
+                return Err(DecryptError::InvalidHeader);
+            }
+            let len = tail.iter().position(is_structural).unwrap_or(tail.len());
+            shares.push((index, &tail[..len]));
+            rest = &tail[len..];
+        }
+
+        if usize::from(threshold) > shares.len() {
+            return Err(DecryptError::InvalidHeader);
+        }
+
+        Ok(Some(Threshold { threshold, shares }))
+    }
+
+    /// Reconstructs the file key from the shares that `unwrap` can unwrap.
+    ///
+    /// `unwrap` is called for every share, even once enough shares have been unwrapped,
+    /// so that the time taken does not reveal which shares could be unwrapped. Its second
+    /// argument is `true` once enough shares have been unwrapped, so that it can skip
+    /// identities that would interact with the user. If fewer than the threshold are
+    /// unwrapped, returns the first error from `unwrap` if any, or otherwise
+    /// [`DecryptError::ThresholdNotMet`] if any share was unwrapped.
+    pub(crate) fn unwrap_file_key(
+        &self,
+        mut unwrap: impl FnMut(&[Stanza], bool) -> Option<Result<FileKey, DecryptError>>,
+    ) -> Option<Result<FileKey, DecryptError>> {
+        let threshold = usize::from(self.threshold);
+        let mut shares = vec![];
+        let mut error = None;
+        for (index, stanzas) in &self.shares {
+            match unwrap(stanzas, shares.len() >= threshold) {
+                Some(Ok(share)) => shares.push((*index, share)),
+                Some(Err(e)) => {
+                    error.get_or_insert(e);
+                }
+                None => (),
+            }
+        }
+
+        if shares.len() >= threshold {
+            Some(Ok(combine(&shares[..threshold])))
+        } else if let Some(e) = error {
+            Some(Err(e))
+        } else if shares.is_empty() {
+            None
+        } else {
+            Some(Err(DecryptError::ThresholdNotMet {
+                threshold: self.threshold,
+                shares: shares.len() as u8,
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::{FileKey, Stanza},
+        secrecy::ExposeSecret,
+    };
+
+    use super::{combine, gf_inv, gf_mul, share_stanza, split, threshold_stanza, Threshold};
+    use crate::DecryptError;
+
+    #[test]
+    fn gf_arithmetic() {
+        // From FIPS 197, section 4.2.
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+        for a in 1..=255 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn split_and_combine() {
+        let file_key = FileKey::from([7; 16]);
+        let shares: Vec<_> = split(&file_key, 3, 5)
+            .unwrap()
+            .into_iter()
+            .enumerate()
+            .map(|(i, share)| (i as u8 + 1, share))
+            .collect();
+
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let subset: Vec<_> = subset
+                .iter()
+                .map(|&i| (shares[i].0, FileKey::from(*shares[i].1.expose_secret())))
+                .collect();
+            assert_eq!(combine(&subset).expose_secret(), file_key.expose_secret());
+        }
+
+        // Two shares are not enough.
+        assert_ne!(
+            combine(&shares[..2]).expose_secret(),
+            file_key.expose_secret()
+        );
+    }
+
+    fn stanza(tag: &str) -> Stanza {
+        Stanza::new(tag.to_owned(), vec![], vec![])
+    }
+
+    #[test]
+    fn parse_structure() {
+        assert!(Threshold::parse(&[stanza("X25519")]).unwrap().is_none());
+
+        let stanzas = [
+            threshold_stanza(2),
+            share_stanza(1),
+            stanza("X25519"),
+            share_stanza(2),
+            share_stanza(3),
+            stanza("X25519"),
+            stanza("grease"),
+        ];
+        let threshold = Threshold::parse(&stanzas).unwrap().unwrap();
+        assert_eq!(threshold.threshold, 2);
+        assert_eq!(
+            threshold
+                .shares
+                .iter()
+                .map(|(x, s)| (*x, s.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (2, 0), (3, 2)]
+        );
+
+        for invalid in [
+            // Structural stanzas without a threshold stanza first.
+            &[stanza("X25519"), threshold_stanza(1), share_stanza(1)][..],
+            &[share_stanza(1), stanza("X25519")][..],
+            // Stanzas that don't belong to a share.
+            &[threshold_stanza(1), stanza("X25519"), share_stanza(1)][..],
+            // Zero or repeated share indices.
+            &[threshold_stanza(1), share_stanza(0)][..],
+            &[threshold_stanza(1), share_stanza(1), share_stanza(1)][..],
+            // A zero threshold.
+            &[threshold_stanza(0), share_stanza(1)][..],
+            // Unreachable or repeated thresholds.
+            &[threshold_stanza(2), share_stanza(1)][..],
+            &[threshold_stanza(1), share_stanza(1), threshold_stanza(1)][..],
+        ] {
+            assert!(matches!(
+                Threshold::parse(invalid),
+                Err(DecryptError::InvalidHeader)
+            ));
+        }
+    }
+
+    #[test]
+    fn unwrap_with_missing_shares() {
+        let file_key = FileKey::from([7; 16]);
+        let shares = split(&file_key, 2, 3).unwrap();
+        let stanzas = [
+            threshold_stanza(2),
+            share_stanza(1),
+            share_stanza(2),
+            share_stanza(3),
+        ];
+        let threshold = Threshold::parse(&stanzas).unwrap().unwrap();
+
+        // Every share is tried, but once two have been unwrapped, `unwrap` is told that
+        // it can skip interactive identities.
+        let mut enough = vec![];
+        let unwrapped = threshold
+            .unwrap_file_key(|_, skip_interactive| {
+                enough.push(skip_interactive);
+                Some(Ok(FileKey::from(*shares[enough.len() - 1].expose_secret())))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(enough, [false, false, true]);
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        // Any two shares reconstruct the file key.
+        let mut calls = 0;
+        let unwrapped = threshold
+            .unwrap_file_key(|_, _| {
+                calls += 1;
+                (calls != 2).then(|| Ok(FileKey::from(*shares[calls - 1].expose_secret())))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(calls, 3);
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+
+        // One share is not enough.
+        let mut calls = 0;
+        let res = threshold.unwrap_file_key(|_, _| {
+            calls += 1;
+            (calls == 3).then(|| Ok(FileKey::from(*shares[2].expose_secret())))
+        });
+        assert!(matches!(
+            res,
+            Some(Err(DecryptError::ThresholdNotMet {
+                threshold: 2,
+                shares: 1
+            }))
+        ));
+        assert!(threshold.unwrap_file_key(|_, _| None).is_none());
+    }
+}
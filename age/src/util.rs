@@ -1,10 +1,43 @@
+use age_core::{format::FileKey, secrecy::ExposeSecret};
 use bech32::{FromBase32, Variant};
+use subtle::{Choice, ConditionallySelectable};
+use zeroize::Zeroize;
+
+use crate::DecryptError;
 
 #[cfg(all(any(feature = "armor", feature = "cli-common"), windows))]
 pub(crate) const LINE_ENDING: &str = "\r\n";
 #[cfg(all(any(feature = "armor", feature = "cli-common"), not(windows)))]
 pub(crate) const LINE_ENDING: &str = "\n";
 
+/// The English BIP 39 wordlist, with one word per line.
+#[cfg(any(feature = "cli-common", feature = "mnemonic"))]
+pub(crate) const BIP39_WORDLIST: &str = include_str!("../assets/bip39-english.txt");
+
+/// Implements `Serialize` and `Deserialize` for a type with its string encoding, using
+/// its `Display` and `FromStr` impls.
+#[cfg(feature = "serde")]
+macro_rules! serde_via_str {
+    ($type:ty) => {
+        #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+        impl serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+#[cfg(feature = "serde")]
+pub(crate) use serde_via_str;
+
 pub(crate) fn parse_bech32(s: &str) -> Option<(String, Vec<u8>)> {
     bech32::decode(s).ok().and_then(|(hrp, data, variant)| {
         if let Variant::Bech32 = variant {
@@ -15,6 +48,53 @@ pub(crate) fn parse_bech32(s: &str) -> Option<(String, Vec<u8>)> {
     })
 }
 
+/// Selects the result of the first candidate that matched, from the results of trying
+/// every candidate unwrap.
+///
+/// Taking the results as a `Vec` ensures that every candidate has already been tried, so
+/// the work done up to this point does not depend on which candidate matched. The file
+/// key bytes are then copied out of the first matching candidate with constant-time
+/// conditional assignments. Which candidates matched, and whether they returned an
+/// error, is not hidden.
+///
+/// If the first matching candidate returned an error, that error is returned instead.
+pub(crate) fn select_file_key(
+    candidates: Vec<Option<Result<FileKey, DecryptError>>>,
+) -> Option<Result<FileKey, DecryptError>> {
+    let mut file_key = [0; 16];
+    let mut matched = Choice::from(0);
+    let mut unwrapped = Choice::from(0);
+    let mut error = None;
+
+    for candidate in candidates {
+        match candidate {
+            Some(Ok(candidate)) => {
+                let take = !matched;
+                for (dst, src) in file_key.iter_mut().zip(candidate.expose_secret()) {
+                    dst.conditional_assign(src, take);
+                }
+                unwrapped |= take;
+                matched |= Choice::from(1);
+            }
+            Some(Err(e)) => {
+                // If this is the first error but not the first match, `unwrapped` is
+                // already set, and the error is ignored.
+                error.get_or_insert(e);
+                matched |= Choice::from(1);
+            }
+            None => (),
+        }
+    }
+
+    let res = if unwrapped.into() {
+        Some(Ok(FileKey::from(file_key)))
+    } else {
+        error.map(Err)
+    };
+    file_key.zeroize();
+    res
+}
+
 pub(crate) mod read {
     use std::str::FromStr;
 
@@ -113,6 +193,7 @@ pub(crate) mod read {
     }
 }
 
+#[cfg(feature = "async")]
 pub(crate) mod write {
     use cookie_factory::{combinator::string, SerializeFn};
     use std::io::Write;
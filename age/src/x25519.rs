@@ -13,7 +13,9 @@ use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
 use zeroize::Zeroize;
 
 use crate::{
-    error::{DecryptError, EncryptError},
+    error::{DecryptError, EncryptError, RngError},
+    rng::random_bytes,
+    stanza::{Arg, Body, Rule},
     util::{parse_bech32, read::base64_arg},
 };
 
@@ -24,9 +26,21 @@ const PUBLIC_KEY_PREFIX: &str = "age";
 pub(super) const X25519_RECIPIENT_TAG: &str = "X25519";
 const X25519_RECIPIENT_KEY_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
 
+/// The HKDF salt for [`derive_identity`]. This is not part of the age specification.
+const DERIVED_IDENTITY_SALT: &[u8] = b"rage/v1/derived-x25519-identity";
+
 pub(super) const EPK_LEN_BYTES: usize = 32;
 pub(super) const ENCRYPTED_FILE_KEY_BYTES: usize = FILE_KEY_BYTES + 16;
 
+/// The format of an X25519 recipient stanza.
+///
+/// https://c2sp.org/age#x25519-recipient-stanza
+pub(crate) const STANZA_RULE: Rule = Rule {
+    tag: X25519_RECIPIENT_TAG,
+    args: &[Arg::Base64(EPK_LEN_BYTES)],
+    body: Body::Exact(ENCRYPTED_FILE_KEY_BYTES),
+};
+
 /// The standard age identity type, which can decrypt files encrypted to the corresponding
 /// [`Recipient`].
 #[derive(Clone)]
@@ -54,9 +68,22 @@ impl std::str::FromStr for Identity {
 
 impl Identity {
     /// Generates a new secret key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the system's random number generator fails a health check. Use
+    /// [`Identity::try_generate`] to handle this as an error instead.
     pub fn generate() -> Self {
-        let mut rng = OsRng;
-        Identity(StaticSecret::new(&mut rng))
+        Self::try_generate().expect("system RNG is healthy")
+    }
+
+    /// Generates a new secret key, or returns an error if the system's random number
+    /// generator fails a health check.
+    pub fn try_generate() -> Result<Self, RngError> {
+        let mut sk_bytes: [u8; 32] = random_bytes()?;
+        let identity = Identity(StaticSecret::from(sk_bytes));
+        sk_bytes.zeroize();
+        Ok(identity)
     }
 
     /// Serializes this secret key as a string.
@@ -80,6 +107,54 @@ impl Identity {
     pub fn to_public(&self) -> Recipient {
         Recipient((&self.0).into())
     }
+
+    /// Derives the identity for `label`, using this secret key as the master secret.
+    ///
+    /// This is equivalent to [`derive_identity`] with the bytes of this secret key, so
+    /// this identity (or its mnemonic phrase, with the `mnemonic` feature flag) can be
+    /// backed up to recover every identity derived from it.
+    pub fn derive(&self, label: &str) -> Identity {
+        let mut sk_bytes = self.0.to_bytes();
+        let identity = derive_identity(&sk_bytes, label);
+        sk_bytes.zeroize();
+        identity
+    }
+}
+
+/// Derives the identity for `label` from the master secret `seed`, with HKDF-SHA-256.
+///
+/// The same seed and label always give the same identity, and the identities for
+/// different labels are unrelated, so any number of identities (such as one for
+/// `"backups-2024"` and one for `"work"`) can be recovered from a single backed-up
+/// seed. The seed must be secret, and should have at least 128 bits of entropy.
+pub fn derive_identity(seed: &[u8], label: &str) -> Identity {
+    let mut sk_bytes = hkdf(DERIVED_IDENTITY_SALT, label.as_bytes(), seed);
+    let identity = Identity(StaticSecret::from(sk_bytes));
+    sk_bytes.zeroize();
+    identity
+}
+
+#[cfg(feature = "mnemonic")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mnemonic")))]
+impl Identity {
+    /// Encodes this secret key as a phrase of 24 words from the English BIP 39
+    /// wordlist, for backing it up on paper.
+    ///
+    /// See the [`mnemonic`](crate::mnemonic) module for details of the encoding.
+    pub fn to_mnemonic(&self) -> SecretString {
+        let mut sk_bytes = self.0.to_bytes();
+        let phrase = crate::mnemonic::encode(&sk_bytes);
+        sk_bytes.zeroize();
+        phrase
+    }
+
+    /// Recovers a secret key from the phrase returned by [`Identity::to_mnemonic`].
+    ///
+    /// Words can be separated by any whitespace, are case-insensitive, and can be
+    /// abbreviated to their first four letters.
+    pub fn from_mnemonic(phrase: &str) -> Result<Self, crate::mnemonic::MnemonicError> {
+        crate::mnemonic::decode(phrase).map(|sk_bytes| Identity(StaticSecret::from(*sk_bytes)))
+    }
 }
 
 impl crate::Identity for Identity {
@@ -89,20 +164,15 @@ impl crate::Identity for Identity {
         }
 
         // Enforce valid and canonical stanza format.
-        // https://c2sp.org/age#x25519-recipient-stanza
-        let ephemeral_share = match &stanza.args[..] {
-            [arg] => match base64_arg(arg, [0; EPK_LEN_BYTES]) {
-                Some(ephemeral_share) => ephemeral_share,
-                None => return Some(Err(DecryptError::InvalidHeader)),
-            },
-            _ => return Some(Err(DecryptError::InvalidHeader)),
-        };
-        if stanza.body.len() != ENCRYPTED_FILE_KEY_BYTES {
-            return Some(Err(DecryptError::InvalidHeader));
+        if let Err(e) = STANZA_RULE.check(stanza) {
+            return Some(Err(e.into()));
         }
 
-        let epk: PublicKey = ephemeral_share.into();
-        let encrypted_file_key: [u8; ENCRYPTED_FILE_KEY_BYTES] = stanza.body[..]
+        let epk: PublicKey = base64_arg(&stanza.args[0], [0; EPK_LEN_BYTES])
+            .expect("Argument should have been checked above")
+            .into();
+        let encrypted_file_key: [u8; ENCRYPTED_FILE_KEY_BYTES] = stanza
+            .body()
             .try_into()
             .expect("Length should have been checked above");
 
@@ -138,6 +208,10 @@ impl crate::Identity for Identity {
                 Ok(file_key.into())
             })
     }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
 }
 
 /// The standard age recipient type. Files encrypted to this recipient can be decrypted
@@ -183,6 +257,31 @@ impl fmt::Display for Recipient {
     }
 }
 
+#[cfg(feature = "serde")]
+crate::util::serde_via_str!(Recipient);
+
+#[cfg(feature = "qr")]
+#[cfg_attr(docsrs, doc(cfg(feature = "qr")))]
+impl Recipient {
+    /// Renders this recipient as a QR code, in SVG format.
+    ///
+    /// The recipient is encoded in upper case (which Bech32 allows), so that the QR code
+    /// can use its compact alphanumeric mode.
+    pub fn to_qr_svg(&self) -> String {
+        crate::qr::to_svg(&self.to_string().to_uppercase())
+    }
+
+    /// Parses a recipient from a string scanned from a QR code.
+    ///
+    /// This is more lenient than [`Recipient::from_str`] about how the recipient is
+    /// written, and on failure suggests a correction if one character was misread.
+    ///
+    /// [`Recipient::from_str`]: std::str::FromStr::from_str
+    pub fn from_scanned(s: &str) -> Result<Self, crate::qr::ScanError> {
+        crate::qr::parse_scanned(s, PUBLIC_KEY_PREFIX)
+    }
+}
+
 impl crate::Recipient for Recipient {
     fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
         let mut rng = OsRng;
@@ -213,11 +312,11 @@ impl crate::Recipient for Recipient {
 
         let encoded_epk = base64::encode_config(epk.as_bytes(), base64::STANDARD_NO_PAD);
 
-        Ok(vec![Stanza {
-            tag: X25519_RECIPIENT_TAG.to_owned(),
-            args: vec![encoded_epk],
-            body: encrypted_file_key,
-        }])
+        Ok(vec![Stanza::new(
+            X25519_RECIPIENT_TAG.to_owned(),
+            vec![encoded_epk],
+            encrypted_file_key,
+        )])
     }
 }
 
@@ -228,7 +327,7 @@ pub(crate) mod tests {
     use quickcheck_macros::quickcheck;
     use x25519_dalek::{PublicKey, StaticSecret};
 
-    use super::{Identity, Recipient};
+    use super::{derive_identity, Identity, Recipient};
     use crate::{Identity as _, Recipient as _};
 
     pub(crate) const TEST_SK: &str =
@@ -248,6 +347,23 @@ pub(crate) mod tests {
         assert_eq!(key.to_public().to_string(), TEST_PK);
     }
 
+    #[test]
+    fn derived_identities() {
+        let master = TEST_SK.parse::<Identity>().unwrap();
+        let derive = |label| master.derive(label).to_public().to_string();
+
+        // Derivation is deterministic, and different labels give different identities.
+        assert_eq!(derive("backups-2024"), derive("backups-2024"));
+        assert_ne!(derive("backups-2024"), derive("backups-2025"));
+        assert_ne!(derive("backups-2024"), TEST_PK);
+        assert_eq!(
+            derive("backups-2024"),
+            derive_identity(&master.0.to_bytes(), "backups-2024")
+                .to_public()
+                .to_string()
+        );
+    }
+
     #[quickcheck]
     fn wrap_and_unwrap(sk_bytes: Vec<u8>) -> TestResult {
         if sk_bytes.len() > 32 {
@@ -0,0 +1,555 @@
+//! The "piv-p256" recipient type, backed by a P-256 key in a YubiKey's PIV application.
+//!
+//! Files can be encrypted to a YubiKey [`Recipient`] without the YubiKey. Decrypting
+//! them requires the corresponding [`Identity`], which performs the ECDH step on the
+//! YubiKey (over PC/SC), so the private key never leaves it. PIN and touch prompts are
+//! shown with [`Callbacks`].
+//!
+//! The key must be a P-256 key in one of the PIV [`Slot`]s, generated (for example) with
+//! `ykman piv keys generate -a ECCP256 9a -`. Reading a key's policies requires YubiKey
+//! firmware 5.3 or later.
+//!
+//! The stanza format is the same one used by `age-plugin-yubikey`, and recipients use
+//! the same encoding, so files encrypted to either can be decrypted by either (given
+//! access to the same key).
+
+use age_core::{
+    format::{FileKey, Stanza},
+    secrecy::{ExposeSecret, SecretString},
+};
+use bech32::{ToBase32, Variant};
+use i18n_embed_fl::fl;
+use p256::PublicKey;
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+
+use crate::{
+    error::{DecryptError, EncryptError},
+    piv_p256,
+    util::parse_bech32,
+    Callbacks,
+};
+
+mod piv;
+
+use piv::{PinPolicy, PinStatus, Piv, SlotMetadata, TouchPolicy};
+
+const PUBLIC_KEY_PREFIX: &str = "age1yubikey";
+const IDENTITY_PREFIX: &str = "age-yubikey-";
+
+/// A PIV slot that can hold a YubiKey identity's key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    /// Slot 9a, PIV Authentication.
+    Authentication,
+    /// Slot 9c, Digital Signature. Keys in this slot require the PIN for every use by
+    /// default.
+    Signature,
+    /// Slot 9d, Key Management.
+    KeyManagement,
+    /// Slot 9e, Card Authentication. Keys in this slot don't require the PIN by default.
+    CardAuthentication,
+}
+
+impl Slot {
+    fn id(self) -> u8 {
+        match self {
+            Slot::Authentication => 0x9a,
+            Slot::Signature => 0x9c,
+            Slot::KeyManagement => 0x9d,
+            Slot::CardAuthentication => 0x9e,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x9a => Some(Slot::Authentication),
+            0x9c => Some(Slot::Signature),
+            0x9d => Some(Slot::KeyManagement),
+            0x9e => Some(Slot::CardAuthentication),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Slot {
+    type Err = &'static str;
+
+    /// Parses a slot from its hexadecimal ID (such as `9a`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u8::from_str_radix(s, 16)
+            .ok()
+            .filter(|_| s.len() == 2)
+            .and_then(Slot::from_id)
+            .ok_or("invalid PIV slot (expected 9a, 9c, 9d, or 9e)")
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}", self.id())
+    }
+}
+
+/// A recipient whose private key is held in a YubiKey. Files encrypted to this recipient
+/// can be decrypted with the corresponding [`Identity`].
+#[derive(Clone)]
+pub struct Recipient(PublicKey);
+
+impl std::str::FromStr for Recipient {
+    type Err = &'static str;
+
+    /// Parses a YubiKey recipient from a string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        piv_p256::parse_recipient(s, PUBLIC_KEY_PREFIX).map(Recipient)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            piv_p256::encode_recipient(&self.0, PUBLIC_KEY_PREFIX)
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::util::serde_via_str!(Recipient);
+
+impl crate::Recipient for Recipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        Ok(vec![piv_p256::wrap_file_key(&self.0, file_key)])
+    }
+}
+
+/// A reference to the key in a slot of a particular YubiKey, which can be stored in an
+/// identity file (as `AGE-YUBIKEY-1...`) in place of a private key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityStub {
+    serial: u32,
+    slot: Slot,
+    /// The tag of the key's public key, which detects if the slot's key is replaced.
+    tag: [u8; piv_p256::TAG_BYTES],
+}
+
+impl IdentityStub {
+    /// Returns the serial number of the YubiKey.
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    /// Returns the slot holding the key.
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+}
+
+impl std::str::FromStr for IdentityStub {
+    type Err = &'static str;
+
+    /// Parses a YubiKey identity stub from a string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, bytes) = parse_bech32(s).ok_or("invalid Bech32 encoding")?;
+        if hrp != IDENTITY_PREFIX {
+            return Err("incorrect HRP");
+        }
+        match &bytes[..] {
+            [s0, s1, s2, s3, slot, tag @ ..] if tag.len() == piv_p256::TAG_BYTES => {
+                Ok(IdentityStub {
+                    serial: u32::from_be_bytes([*s0, *s1, *s2, *s3]),
+                    slot: Slot::from_id(*slot).ok_or("invalid PIV slot")?,
+                    tag: tag.try_into().expect("length is correct"),
+                })
+            }
+            _ => Err("incorrect identity length"),
+        }
+    }
+}
+
+impl fmt::Display for IdentityStub {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bytes = self.serial.to_be_bytes().to_vec();
+        bytes.push(self.slot.id());
+        bytes.extend_from_slice(&self.tag);
+        let encoded = bech32::encode(IDENTITY_PREFIX, bytes.to_base32(), Variant::Bech32)
+            .expect("HRP is valid");
+        write!(f, "{}", encoded.to_uppercase())
+    }
+}
+
+/// Opens the PIV application on the YubiKey with the given serial number (or on any
+/// YubiKey), returning it along with its serial number.
+type Connector = Box<dyn Fn(Option<u32>) -> io::Result<Option<(Piv, u32)>> + Send + Sync>;
+
+/// An open connection to the YubiKey holding an identity's key.
+struct Connection {
+    piv: Piv,
+    metadata: SlotMetadata,
+    /// The PIN, once it has been verified, for keys that require it for every use.
+    pin: Option<SecretString>,
+}
+
+/// An identity whose private key is held in a YubiKey.
+///
+/// The YubiKey is connected to when the identity is opened, or (for an identity created
+/// from an [`IdentityStub`]) when a file with a stanza for its key is decrypted.
+pub struct Identity<C: Callbacks> {
+    stub: IdentityStub,
+    callbacks: C,
+    connect: Connector,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl<C: Callbacks> Identity<C> {
+    /// Opens the P-256 key in `slot` of the connected YubiKey with the given serial
+    /// number, or of the first connected YubiKey if `serial` is `None`.
+    ///
+    /// Returns `Ok(None)` if there is no such YubiKey, or the slot doesn't hold a P-256
+    /// key.
+    pub fn open(serial: Option<u32>, slot: Slot, callbacks: C) -> io::Result<Option<Self>> {
+        Self::open_with(Box::new(piv::pcsc::open), serial, slot, callbacks)
+    }
+
+    fn open_with(
+        connect: Connector,
+        serial: Option<u32>,
+        slot: Slot,
+        callbacks: C,
+    ) -> io::Result<Option<Self>> {
+        let (mut piv, serial) = match connect(serial)? {
+            Some(piv) => piv,
+            None => return Ok(None),
+        };
+        let metadata = match piv.metadata(slot)? {
+            Some(metadata) => metadata,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Identity {
+            stub: IdentityStub {
+                serial,
+                slot,
+                tag: piv_p256::key_tag(&metadata.public_key),
+            },
+            callbacks,
+            connect,
+            connection: Mutex::new(Some(Connection {
+                piv,
+                metadata,
+                pin: None,
+            })),
+        }))
+    }
+
+    /// Creates an identity for the key referenced by `stub`.
+    ///
+    /// The YubiKey is not connected to until it is needed. If it isn't connected then,
+    /// the user is asked to insert it with [`Callbacks::confirm`].
+    pub fn from_stub(stub: IdentityStub, callbacks: C) -> Self {
+        Identity {
+            stub,
+            callbacks,
+            connect: Box::new(piv::pcsc::open),
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Returns the stub referencing this identity's key, which can be written to an
+    /// identity file.
+    pub fn to_stub(&self) -> IdentityStub {
+        self.stub.clone()
+    }
+
+    /// Returns the recipient for this identity, connecting to the YubiKey if necessary.
+    pub fn to_public(&self) -> io::Result<Recipient> {
+        self.with_connection(|connection| Recipient(connection.metadata.public_key))?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    fl!(
+                        crate::i18n::LANGUAGE_LOADER,
+                        "err-yubikey-not-found",
+                        serial = self.stub.serial
+                    ),
+                )
+            })
+    }
+
+    /// Calls `f` with the connection to the YubiKey, connecting to it first if
+    /// necessary.
+    ///
+    /// Returns `Ok(None)` if the YubiKey isn't connected, and the user declined to
+    /// insert it.
+    fn with_connection<T>(&self, f: impl FnOnce(&mut Connection) -> T) -> io::Result<Option<T>> {
+        let mut connection = self.connection.lock().expect("not poisoned");
+
+        while connection.is_none() {
+            match (self.connect)(Some(self.stub.serial))? {
+                Some((mut piv, _)) => {
+                    let metadata = piv
+                        .metadata(self.stub.slot)?
+                        .filter(|metadata| piv_p256::key_tag(&metadata.public_key) == self.stub.tag)
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::NotFound,
+                                fl!(
+                                    crate::i18n::LANGUAGE_LOADER,
+                                    "err-yubikey-key-replaced",
+                                    serial = self.stub.serial,
+                                    slot = self.stub.slot.to_string()
+                                ),
+                            )
+                        })?;
+                    *connection = Some(Connection {
+                        piv,
+                        metadata,
+                        pin: None,
+                    });
+                }
+                None => {
+                    let inserted = self.callbacks.confirm(
+                        &fl!(
+                            crate::i18n::LANGUAGE_LOADER,
+                            "yubikey-insert",
+                            serial = self.stub.serial
+                        ),
+                        &fl!(crate::i18n::LANGUAGE_LOADER, "yubikey-insert-retry"),
+                        Some(&fl!(crate::i18n::LANGUAGE_LOADER, "yubikey-insert-skip")),
+                    );
+                    if inserted != Some(true) {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+
+        Ok(connection.as_mut().map(f))
+    }
+
+    /// Computes the ECDH shared secret with `epk` on the YubiKey, asking the user for
+    /// the PIN and to touch the YubiKey as required by the key's policies.
+    fn ecdh(&self, connection: &mut Connection, epk: &PublicKey) -> Result<Vec<u8>, DecryptError> {
+        if connection.metadata.pin_policy != PinPolicy::Never && !connection.piv.pin_verified()? {
+            loop {
+                let pin = match connection.pin.take() {
+                    Some(pin) => pin,
+                    None => self
+                        .callbacks
+                        .request_passphrase(&fl!(
+                            crate::i18n::LANGUAGE_LOADER,
+                            "yubikey-pin-prompt",
+                            serial = self.stub.serial
+                        ))
+                        .ok_or(DecryptError::KeyDecryptionFailed)?,
+                };
+
+                match connection.piv.verify_pin(pin.expose_secret().as_bytes())? {
+                    PinStatus::Verified => {
+                        if connection.metadata.pin_policy == PinPolicy::Always {
+                            connection.pin = Some(pin);
+                        }
+                        break;
+                    }
+                    PinStatus::Incorrect(retries) if retries > 0 => {
+                        self.callbacks.display_message(&fl!(
+                            crate::i18n::LANGUAGE_LOADER,
+                            "yubikey-pin-incorrect",
+                            retries = retries
+                        ))
+                    }
+                    PinStatus::Incorrect(_) | PinStatus::Blocked => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            fl!(
+                                crate::i18n::LANGUAGE_LOADER,
+                                "err-yubikey-pin-blocked",
+                                serial = self.stub.serial
+                            ),
+                        )
+                        .into())
+                    }
+                }
+            }
+        }
+
+        if connection.metadata.touch_policy != TouchPolicy::Never {
+            self.callbacks
+                .display_message(&fl!(crate::i18n::LANGUAGE_LOADER, "yubikey-touch"));
+        }
+
+        Ok(connection.piv.ecdh(self.stub.slot, epk)?)
+    }
+}
+
+impl<C: Callbacks> crate::Identity for Identity<C> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        // Only connect to the YubiKey for stanzas addressed to its key.
+        match piv_p256::stanza_key_tag(stanza)? {
+            Ok(tag) if tag == self.stub.tag => (),
+            Ok(_) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        match self.with_connection(|connection| {
+            let public_key = connection.metadata.public_key;
+            piv_p256::unwrap_stanza_with(&public_key, stanza, |epk| self.ecdh(connection, epk))
+        }) {
+            Ok(result) => result.flatten(),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age_core::{
+        format::FileKey,
+        secrecy::{ExposeSecret, SecretString},
+    };
+    use std::sync::{Arc, Mutex};
+
+    use super::{
+        piv::{tests::MockYubiKey, Piv},
+        Identity, IdentityStub, Recipient, Slot,
+    };
+    use crate::{Callbacks, Identity as _, Recipient as _};
+
+    /// Callbacks that answer PIN requests from a list, and record messages.
+    #[derive(Clone, Default)]
+    struct TestCallbacks {
+        pins: Arc<Mutex<Vec<&'static str>>>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Callbacks for TestCallbacks {
+        fn display_message(&self, message: &str) {
+            self.messages.lock().unwrap().push(message.to_owned());
+        }
+
+        fn request_passphrase(&self, _: &str) -> Option<SecretString> {
+            let mut pins = self.pins.lock().unwrap();
+            (!pins.is_empty()).then(|| SecretString::new(pins.remove(0).to_owned()))
+        }
+    }
+
+    fn open(yubikey: &MockYubiKey, callbacks: TestCallbacks) -> Identity<TestCallbacks> {
+        let yubikey = yubikey.clone();
+        Identity::open_with(
+            Box::new(move |_| {
+                Piv::select(Box::new(yubikey.clone()))
+                    .map(|piv| Some((piv, super::piv::tests::SERIAL)))
+            }),
+            None,
+            Slot::Authentication,
+            callbacks,
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn recipient_encoding() {
+        let encoded = Recipient(MockYubiKey::new(0x02).public_key()).to_string();
+        assert!(encoded.starts_with("age1yubikey1"));
+        let recipient: Recipient = encoded.parse().unwrap();
+        assert_eq!(recipient.to_string(), encoded);
+    }
+
+    #[test]
+    fn slots() {
+        assert_eq!("9a".parse::<Slot>().unwrap(), Slot::Authentication);
+        assert_eq!("9C".parse::<Slot>().unwrap(), Slot::Signature);
+        assert_eq!(Slot::KeyManagement.to_string(), "9d");
+        for invalid in ["", "9", "9b", "82", "09a"] {
+            assert!(invalid.parse::<Slot>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn stub_encoding() {
+        let yubikey = MockYubiKey::new(0x02);
+        let stub = open(&yubikey, TestCallbacks::default()).to_stub();
+        assert_eq!(stub.serial(), super::piv::tests::SERIAL);
+        assert_eq!(stub.slot(), Slot::Authentication);
+
+        let encoded = stub.to_string();
+        assert!(encoded.starts_with("AGE-YUBIKEY-1"));
+        assert_eq!(encoded.parse::<IdentityStub>().unwrap(), stub);
+    }
+
+    #[test]
+    fn round_trip() {
+        let yubikey = MockYubiKey::new(0x02);
+        let callbacks = TestCallbacks::default();
+        callbacks
+            .pins
+            .lock()
+            .unwrap()
+            .extend_from_slice(&["000000", "123456"]);
+        let identity = open(&yubikey, callbacks.clone());
+        let recipient = identity.to_public().unwrap();
+
+        let file_key = FileKey::from([7; 16]);
+        let stanzas = recipient.wrap_file_key(&file_key).unwrap();
+        assert_eq!(stanzas[0].tag, "piv-p256");
+
+        // The user is asked again after entering the wrong PIN.
+        let unwrapped = identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret());
+        assert_eq!(callbacks.messages.lock().unwrap().len(), 1);
+
+        // The PIN is only needed once per session.
+        identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(yubikey.state.lock().unwrap().ecdh_count, 2);
+
+        // Stanzas for other keys don't touch the YubiKey.
+        let other = MockYubiKey::new(0x02);
+        let stanzas = Recipient(other.public_key())
+            .wrap_file_key(&file_key)
+            .unwrap();
+        assert!(identity.unwrap_stanza(&stanzas[0]).is_none());
+        assert_eq!(yubikey.state.lock().unwrap().ecdh_count, 2);
+    }
+
+    #[test]
+    fn pin_always() {
+        let yubikey = MockYubiKey::new(0x03);
+        let callbacks = TestCallbacks::default();
+        callbacks.pins.lock().unwrap().push("123456");
+        let identity = open(&yubikey, callbacks);
+        let recipient = identity.to_public().unwrap();
+
+        let file_key = FileKey::from([7; 16]);
+        let stanzas = recipient.wrap_file_key(&file_key).unwrap();
+
+        // The PIN is entered once, and reused for each use of the key.
+        identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        identity.unwrap_stanza(&stanzas[0]).unwrap().unwrap();
+        assert_eq!(yubikey.state.lock().unwrap().ecdh_count, 2);
+    }
+
+    #[test]
+    fn pin_blocked() {
+        let yubikey = MockYubiKey::new(0x02);
+        let callbacks = TestCallbacks::default();
+        callbacks
+            .pins
+            .lock()
+            .unwrap()
+            .extend_from_slice(&["000000", "000000", "000000"]);
+        let identity = open(&yubikey, callbacks);
+        let stanzas = identity
+            .to_public()
+            .unwrap()
+            .wrap_file_key(&FileKey::from([7; 16]))
+            .unwrap();
+
+        assert!(matches!(
+            identity.unwrap_stanza(&stanzas[0]),
+            Some(Err(crate::DecryptError::Io(_)))
+        ));
+    }
+}
@@ -0,0 +1,468 @@
+//! The parts of the PIV card protocol (NIST SP 800-73-4) that YubiKey identities use,
+//! along with the YubiKey-specific serial number and key metadata extensions.
+
+use p256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey};
+use std::io;
+use zeroize::Zeroize;
+
+use super::Slot;
+
+/// The PIV application identifier.
+const PIV_AID: &[u8] = &[0xa0, 0x00, 0x00, 0x03, 0x08];
+
+const INS_VERIFY: u8 = 0x20;
+const INS_GENERAL_AUTHENTICATE: u8 = 0x87;
+const INS_SELECT: u8 = 0xa4;
+const INS_GET_RESPONSE: u8 = 0xc0;
+const INS_GET_METADATA: u8 = 0xf7;
+const INS_GET_SERIAL: u8 = 0xf8;
+
+/// The PIV algorithm identifier for ECC with P-256.
+const ALGORITHM_ECCP256: u8 = 0x11;
+
+/// The key reference of the PIV Card Application PIN.
+const PIN_REFERENCE: u8 = 0x80;
+/// PINs are padded with 0xff to this length.
+const PIN_BYTES: usize = 8;
+
+const SW_SUCCESS: u16 = 0x9000;
+const SW_AUTH_BLOCKED: u16 = 0x6983;
+
+/// Sends APDUs to a card, and returns its responses (including the status word).
+pub(super) trait Transport: Send {
+    fn transmit(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// When a slot's key requires the PIN to be verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum PinPolicy {
+    Never,
+    Once,
+    Always,
+}
+
+/// When a slot's key requires the YubiKey to be touched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum TouchPolicy {
+    Never,
+    Always,
+    Cached,
+}
+
+/// The metadata of the key in a slot.
+pub(super) struct SlotMetadata {
+    pub(super) pin_policy: PinPolicy,
+    pub(super) touch_policy: TouchPolicy,
+    pub(super) public_key: PublicKey,
+}
+
+/// The result of verifying the PIN.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum PinStatus {
+    Verified,
+    /// The PIN was incorrect, and can be tried this many more times.
+    Incorrect(u8),
+    Blocked,
+}
+
+/// A connection to the PIV application on a card.
+pub(super) struct Piv {
+    transport: Box<dyn Transport>,
+}
+
+impl Piv {
+    /// Selects the PIV application on the card behind `transport`.
+    pub(super) fn select(transport: Box<dyn Transport>) -> io::Result<Self> {
+        let mut piv = Piv { transport };
+        piv.call(INS_SELECT, 0x04, 0x00, PIV_AID)?;
+        Ok(piv)
+    }
+
+    /// Sends a command, and returns its response data and status word, fetching the
+    /// rest of the response if the card has more than fits in one response.
+    fn send(&mut self, ins: u8, p1: u8, p2: u8, data: &[u8]) -> io::Result<(Vec<u8>, u16)> {
+        debug_assert!(data.len() <= 255);
+        let mut apdu = vec![0x00, ins, p1, p2];
+        if !data.is_empty() {
+            apdu.push(data.len() as u8);
+            apdu.extend_from_slice(data);
+        }
+
+        let mut response = vec![];
+        loop {
+            let mut rapdu = self.transport.transmit(&apdu)?;
+            if rapdu.len() < 2 {
+                return Err(invalid_response());
+            }
+            let sw = rapdu.split_off(rapdu.len() - 2);
+            response.extend_from_slice(&rapdu);
+            match (sw[0], sw[1]) {
+                // More data is available.
+                (0x61, _) => apdu = vec![0x00, INS_GET_RESPONSE, 0x00, 0x00, 0x00],
+                _ => return Ok((response, u16::from_be_bytes([sw[0], sw[1]]))),
+            }
+        }
+    }
+
+    /// Sends a command, and returns its response data if it succeeded.
+    fn call(&mut self, ins: u8, p1: u8, p2: u8, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self.send(ins, p1, p2, data)? {
+            (response, SW_SUCCESS) => Ok(response),
+            (_, sw) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("PIV command {:02x} failed with status {:04x}", ins, sw),
+            )),
+        }
+    }
+
+    /// Returns the YubiKey's serial number.
+    pub(super) fn serial(&mut self) -> io::Result<u32> {
+        let response = self.call(INS_GET_SERIAL, 0x00, 0x00, &[])?;
+        response
+            .try_into()
+            .map(u32::from_be_bytes)
+            .map_err(|_| invalid_response())
+    }
+
+    /// Returns the metadata of the P-256 key in `slot`, or `None` if the slot is empty
+    /// or holds another kind of key.
+    pub(super) fn metadata(&mut self, slot: Slot) -> io::Result<Option<SlotMetadata>> {
+        let response = match self.send(INS_GET_METADATA, 0x00, slot.id(), &[])? {
+            (response, SW_SUCCESS) => response,
+            // The slot is empty.
+            (_, 0x6a88) => return Ok(None),
+            (_, sw) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("reading the key's metadata failed with status {:04x}", sw),
+                ))
+            }
+        };
+
+        let mut algorithm = None;
+        let mut policy = None;
+        let mut public_key = None;
+        for (tag, value) in tlvs(&response)? {
+            match tag {
+                0x01 => algorithm = value.first().copied(),
+                0x02 if value.len() == 2 => policy = Some((value[0], value[1])),
+                0x04 => {
+                    public_key = tlvs(value)?
+                        .into_iter()
+                        .find(|(tag, _)| *tag == 0x86)
+                        .and_then(|(_, point)| PublicKey::from_sec1_bytes(point).ok())
+                }
+                _ => (),
+            }
+        }
+
+        if algorithm != Some(ALGORITHM_ECCP256) {
+            return Ok(None);
+        }
+        let (pin_policy, touch_policy) = policy.ok_or_else(invalid_response)?;
+        Ok(Some(SlotMetadata {
+            pin_policy: match pin_policy {
+                0x01 => PinPolicy::Never,
+                0x02 => PinPolicy::Once,
+                _ => PinPolicy::Always,
+            },
+            touch_policy: match touch_policy {
+                0x01 => TouchPolicy::Never,
+                0x03 => TouchPolicy::Cached,
+                _ => TouchPolicy::Always,
+            },
+            public_key: public_key.ok_or_else(invalid_response)?,
+        }))
+    }
+
+    /// Returns whether the PIN has been verified in this session.
+    pub(super) fn pin_verified(&mut self) -> io::Result<bool> {
+        let (_, sw) = self.send(INS_VERIFY, 0x00, PIN_REFERENCE, &[])?;
+        Ok(sw == SW_SUCCESS)
+    }
+
+    /// Verifies the PIN.
+    pub(super) fn verify_pin(&mut self, pin: &[u8]) -> io::Result<PinStatus> {
+        if pin.len() > PIN_BYTES {
+            return self.pin_status();
+        }
+        let mut data = [0xff; PIN_BYTES];
+        data[..pin.len()].copy_from_slice(pin);
+        let (_, sw) = self.send(INS_VERIFY, 0x00, PIN_REFERENCE, &data)?;
+        data.zeroize();
+        Ok(Self::status_to_pin_status(sw))
+    }
+
+    /// Returns the PIN's status without verifying it.
+    fn pin_status(&mut self) -> io::Result<PinStatus> {
+        let (_, sw) = self.send(INS_VERIFY, 0x00, PIN_REFERENCE, &[])?;
+        Ok(match Self::status_to_pin_status(sw) {
+            // An over-long PIN is never correct.
+            PinStatus::Verified => PinStatus::Incorrect(0),
+            status => status,
+        })
+    }
+
+    fn status_to_pin_status(sw: u16) -> PinStatus {
+        match sw {
+            SW_SUCCESS => PinStatus::Verified,
+            SW_AUTH_BLOCKED => PinStatus::Blocked,
+            sw if sw & 0xfff0 == 0x63c0 => match sw & 0x0f {
+                0 => PinStatus::Blocked,
+                retries => PinStatus::Incorrect(retries as u8),
+            },
+            _ => PinStatus::Incorrect(0),
+        }
+    }
+
+    /// Computes the ECDH shared secret between the key in `slot` and `epk`.
+    ///
+    /// This blocks until the YubiKey is touched, if the key's touch policy requires it.
+    pub(super) fn ecdh(&mut self, slot: Slot, epk: &PublicKey) -> io::Result<Vec<u8>> {
+        let epk = epk.to_encoded_point(false);
+        let mut inner = vec![0x82, 0x00, 0x85, epk.len() as u8];
+        inner.extend_from_slice(epk.as_bytes());
+        let mut data = vec![0x7c, inner.len() as u8];
+        data.extend_from_slice(&inner);
+
+        let response = self.call(
+            INS_GENERAL_AUTHENTICATE,
+            ALGORITHM_ECCP256,
+            slot.id(),
+            &data,
+        )?;
+        tlvs(&response)?
+            .into_iter()
+            .find(|(tag, _)| *tag == 0x7c)
+            .map(|(_, template)| tlvs(template))
+            .transpose()?
+            .and_then(|template| template.into_iter().find(|(tag, _)| *tag == 0x82))
+            .map(|(_, shared_secret)| shared_secret.to_vec())
+            .ok_or_else(invalid_response)
+    }
+}
+
+fn invalid_response() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid response from YubiKey")
+}
+
+/// Parses a sequence of BER-TLV values with one-byte tags.
+fn tlvs(mut data: &[u8]) -> io::Result<Vec<(u8, &[u8])>> {
+    let mut tlvs = vec![];
+    while !data.is_empty() {
+        let (tag, len, header) = match data {
+            [tag, len, ..] if *len < 0x80 => (*tag, *len as usize, 2),
+            [tag, 0x81, len, ..] => (*tag, *len as usize, 3),
+            [tag, 0x82, hi, lo, ..] => (*tag, u16::from_be_bytes([*hi, *lo]) as usize, 4),
+            _ => return Err(invalid_response()),
+        };
+        let value = data
+            .get(header..header + len)
+            .ok_or_else(invalid_response)?;
+        tlvs.push((tag, value));
+        data = &data[header + len..];
+    }
+    Ok(tlvs)
+}
+
+/// Connects to YubiKeys through the platform's PC/SC service.
+pub(super) mod pcsc {
+    use std::ffi::CStr;
+    use std::io;
+
+    use super::{Piv, Transport};
+
+    struct Card(::pcsc::Card);
+
+    impl Transport for Card {
+        fn transmit(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>> {
+            let mut buf = [0; ::pcsc::MAX_BUFFER_SIZE];
+            self.0
+                .transmit(apdu, &mut buf)
+                .map(|response| response.to_vec())
+                .map_err(pcsc_error)
+        }
+    }
+
+    fn pcsc_error(e: ::pcsc::Error) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+
+    /// Opens the PIV application on the YubiKey with the given serial number, or on
+    /// the first YubiKey if `serial` is `None`.
+    ///
+    /// Returns `Ok(None)` if no such YubiKey is connected.
+    pub(in crate::yubikey) fn open(serial: Option<u32>) -> io::Result<Option<(Piv, u32)>> {
+        let context = ::pcsc::Context::establish(::pcsc::Scope::User).map_err(pcsc_error)?;
+        let mut readers = match context.list_readers_len() {
+            Ok(len) => vec![0; len],
+            Err(::pcsc::Error::NoReadersAvailable) => return Ok(None),
+            Err(e) => return Err(pcsc_error(e)),
+        };
+        let readers = match context.list_readers(&mut readers) {
+            Ok(readers) => readers.map(CStr::to_owned).collect::<Vec<_>>(),
+            Err(::pcsc::Error::NoReadersAvailable) => return Ok(None),
+            Err(e) => return Err(pcsc_error(e)),
+        };
+
+        for reader in readers {
+            let reader_name = reader.to_string_lossy();
+            if !reader_name.to_lowercase().contains("yubico") {
+                continue;
+            }
+            // The reader might have no card in it, or be in use by another process.
+            let card =
+                match context.connect(&reader, ::pcsc::ShareMode::Shared, ::pcsc::Protocols::ANY) {
+                    Ok(card) => card,
+                    Err(_) => continue,
+                };
+
+            let mut piv = Piv::select(Box::new(Card(card)))?;
+            let card_serial = piv.serial()?;
+            if serial.map_or(true, |serial| serial == card_serial) {
+                return Ok(Some((piv, card_serial)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use p256::{ecdh::diffie_hellman, elliptic_curve::sec1::ToEncodedPoint, PublicKey, SecretKey};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use super::{tlvs, PinStatus, Piv, Transport};
+    use crate::yubikey::Slot;
+
+    pub(crate) const SERIAL: u32 = 12_345_678;
+    pub(crate) const PIN: &[u8] = b"123456";
+
+    /// A software stand-in for the PIV application on a YubiKey, with a P-256 key in
+    /// slot 9a that requires the PIN once per session.
+    #[derive(Clone)]
+    pub(crate) struct MockYubiKey {
+        pub(crate) key: SecretKey,
+        pin_policy: u8,
+        pub(crate) state: Arc<Mutex<MockState>>,
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MockState {
+        pub(crate) pin_verified: bool,
+        pub(crate) pin_retries: u8,
+        pub(crate) ecdh_count: usize,
+    }
+
+    impl MockYubiKey {
+        pub(crate) fn new(pin_policy: u8) -> Self {
+            MockYubiKey {
+                key: SecretKey::random(&mut rand::rngs::OsRng),
+                pin_policy,
+                state: Arc::new(Mutex::new(MockState {
+                    pin_retries: 3,
+                    ..Default::default()
+                })),
+            }
+        }
+
+        pub(crate) fn public_key(&self) -> PublicKey {
+            self.key.public_key()
+        }
+
+        fn respond(&self, apdu: &[u8]) -> Vec<u8> {
+            let mut state = self.state.lock().unwrap();
+            let data = apdu.get(5..).unwrap_or_default();
+            let (mut response, sw): (Vec<u8>, u16) = match (apdu[1], apdu[2], apdu[3]) {
+                (0xa4, 0x04, 0x00) => (vec![], 0x9000),
+                (0xf8, _, _) => (SERIAL.to_be_bytes().to_vec(), 0x9000),
+                (0xf7, _, 0x9a) => {
+                    let point = self.public_key().to_encoded_point(false);
+                    let mut response = vec![0x01, 0x01, 0x11, 0x02, 0x02, self.pin_policy, 0x01];
+                    response.extend_from_slice(&[0x04, point.len() as u8 + 2, 0x86]);
+                    response.push(point.len() as u8);
+                    response.extend_from_slice(point.as_bytes());
+                    (response, 0x9000)
+                }
+                (0xf7, _, _) => (vec![], 0x6a88),
+                (0x20, 0x00, 0x80) if data.is_empty() => match state.pin_verified {
+                    true => (vec![], 0x9000),
+                    false => (vec![], 0x63c0 | state.pin_retries as u16),
+                },
+                (0x20, 0x00, 0x80) => {
+                    let mut pin = [0xff; 8];
+                    pin[..PIN.len()].copy_from_slice(PIN);
+                    if state.pin_retries == 0 {
+                        (vec![], 0x6983)
+                    } else if data == pin {
+                        state.pin_verified = true;
+                        state.pin_retries = 3;
+                        (vec![], 0x9000)
+                    } else {
+                        state.pin_retries -= 1;
+                        (vec![], 0x63c0 | state.pin_retries as u16)
+                    }
+                }
+                (0x87, 0x11, 0x9a) if state.pin_verified || self.pin_policy == 0x01 => {
+                    let template = tlvs(data).unwrap();
+                    let inner = tlvs(template[0].1).unwrap();
+                    let epk = PublicKey::from_sec1_bytes(inner[1].1).unwrap();
+                    let shared_secret =
+                        diffie_hellman(self.key.to_nonzero_scalar(), epk.as_affine());
+                    if self.pin_policy == 0x03 {
+                        state.pin_verified = false;
+                    }
+                    state.ecdh_count += 1;
+
+                    let mut response = vec![0x7c, 0x22, 0x82, 0x20];
+                    response.extend_from_slice(shared_secret.raw_secret_bytes());
+                    (response, 0x9000)
+                }
+                (0x87, _, _) => (vec![], 0x6982),
+                _ => (vec![], 0x6d00),
+            };
+            response.extend_from_slice(&sw.to_be_bytes());
+            response
+        }
+    }
+
+    impl Transport for MockYubiKey {
+        fn transmit(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(self.respond(apdu))
+        }
+    }
+
+    #[test]
+    fn parse_tlvs() {
+        assert_eq!(
+            tlvs(&[0x01, 0x01, 0x11, 0x7c, 0x00]).unwrap(),
+            vec![(0x01, &[0x11][..]), (0x7c, &[][..])]
+        );
+        let mut long = vec![0x53, 0x81, 0x80];
+        long.extend_from_slice(&[7; 0x80]);
+        assert_eq!(tlvs(&long).unwrap(), vec![(0x53, &[7; 0x80][..])]);
+        assert!(tlvs(&[0x01, 0x02, 0x11]).is_err());
+    }
+
+    #[test]
+    fn pin_verification() {
+        let yubikey = MockYubiKey::new(0x02);
+        let mut piv = Piv::select(Box::new(yubikey.clone())).unwrap();
+        assert_eq!(piv.serial().unwrap(), SERIAL);
+        assert!(piv.metadata(Slot::Signature).unwrap().is_none());
+
+        assert!(!piv.pin_verified().unwrap());
+        assert_eq!(piv.verify_pin(b"654321").unwrap(), PinStatus::Incorrect(2));
+        assert_eq!(
+            piv.verify_pin(b"123456789").unwrap(),
+            PinStatus::Incorrect(2)
+        );
+        assert_eq!(piv.verify_pin(PIN).unwrap(), PinStatus::Verified);
+        assert!(piv.pin_verified().unwrap());
+
+        let metadata = piv.metadata(Slot::Authentication).unwrap().unwrap();
+        assert_eq!(metadata.public_key, yubikey.public_key());
+    }
+}
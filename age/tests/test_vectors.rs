@@ -1,6 +1,7 @@
 use age_core::secrecy::SecretString;
 use std::fs;
-use std::io::Read;
+use std::io::{BufReader, Read, Write};
+use test_case::test_case;
 
 #[test]
 #[cfg(feature = "cli-common")]
@@ -20,7 +21,14 @@ fn age_test_vectors() -> Result<(), Box<dyn std::error::Error>> {
         let name = path.file_stem().unwrap().to_str().unwrap();
         let expect_failure = name.starts_with("fail_");
 
-        let res = match age::Decryptor::new(fs::File::open(&path)?)? {
+        let decryptor = match age::Decryptor::new(fs::File::open(&path)?) {
+            Ok(decryptor) => decryptor,
+            // Malformed stanzas are rejected while parsing the header.
+            Err(age::DecryptError::InvalidStanza(_)) if expect_failure => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let res = match decryptor {
             age::Decryptor::Recipients(d) => {
                 let identities = age::cli_common::read_identities(
                     vec![format!(
@@ -59,3 +67,48 @@ fn age_test_vectors() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test_case(2048)]
+#[test_case(3072)]
+#[test_case(4096)]
+fn ssh_rsa_openssh_keys(bits: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let path = format!("./tests/testdata/ssh-rsa/rsa_{}", bits);
+    let plaintext = b"Hello, ssh-rsa!";
+
+    let recipient: age::ssh::Recipient = fs::read_to_string(format!("{}.pub", path))?
+        .trim_end()
+        .parse()
+        .unwrap();
+    let identity =
+        age::ssh::Identity::from_buffer(BufReader::new(fs::File::open(&path)?), Some(path))?;
+    assert!(matches!(identity, age::ssh::Identity::Unencrypted(_)));
+
+    let mut encrypted = vec![];
+    let mut w = age::Encryptor::with_recipients(vec![Box::new(recipient)])
+        .unwrap()
+        .wrap_output(&mut encrypted)?;
+    w.write_all(plaintext)?;
+    w.finish()?;
+
+    let decryptor = match age::Decryptor::new(&encrypted[..])? {
+        age::Decryptor::Recipients(d) => d,
+        _ => panic!("Should have gotten a recipients decryptor"),
+    };
+    let mut decrypted = vec![];
+    decryptor
+        .decrypt(Some(&identity as &dyn age::Identity).into_iter())?
+        .read_to_end(&mut decrypted)?;
+    assert_eq!(decrypted, plaintext);
+
+    Ok(())
+}
+
+#[test_case(1024)]
+#[test_case(8192)]
+fn ssh_rsa_unsupported_key_sizes(bits: usize) {
+    let pk = fs::read_to_string(format!("./tests/testdata/ssh-rsa/rsa_{}.pub", bits)).unwrap();
+    assert_eq!(
+        pk.trim_end().parse::<age::ssh::Recipient>().unwrap_err(),
+        age::ssh::ParseRecipientKeyError::UnsupportedRsaKeySize(bits),
+    );
+}
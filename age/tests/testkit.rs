@@ -5,7 +5,7 @@ use std::{
 };
 
 use age::{
-    armor::{ArmoredReadError, ArmoredReader},
+    armor::{self, ArmoredReadError, ArmoredReader},
     secrecy::SecretString,
     x25519, DecryptError, Decryptor, Identity,
 };
@@ -290,6 +290,54 @@ async fn testkit_async(filename: &str) {
     }
 }
 
+#[test_case("armor")]
+#[test_case("armor_crlf")]
+#[test_case("armor_empty_line_begin")]
+#[test_case("armor_empty_line_end")]
+#[test_case("armor_eol_between_padding")]
+#[test_case("armor_full_last_line")]
+#[test_case("armor_garbage_encoded")]
+#[test_case("armor_garbage_leading")]
+#[test_case("armor_garbage_trailing")]
+#[test_case("armor_header_crlf")]
+#[test_case("armor_headers")]
+#[test_case("armor_invalid_character_header")]
+#[test_case("armor_invalid_character_payload")]
+#[test_case("armor_long_line")]
+#[test_case("armor_lowercase")]
+#[test_case("armor_no_end_line")]
+#[test_case("armor_no_eol")]
+#[test_case("armor_no_match")]
+#[test_case("armor_no_padding")]
+#[test_case("armor_not_canonical")]
+#[test_case("armor_pgp_checksum")]
+#[test_case("armor_short_line")]
+#[test_case("armor_whitespace_begin")]
+#[test_case("armor_whitespace_end")]
+#[test_case("armor_whitespace_eol")]
+#[test_case("armor_whitespace_last_line")]
+#[test_case("armor_whitespace_line_start")]
+#[test_case("armor_whitespace_outside")]
+#[test_case("armor_wrong_type")]
+fn testkit_armor_check(filename: &str) {
+    let testfile = TestFile::parse(filename);
+    assert!(testfile.armored);
+
+    let violation = armor::check(&testfile.age_file[..]).unwrap();
+    if filename == "armor_whitespace_outside" {
+        // We do not support parsing armored files with leading whitespace (due to how
+        // we detect armoring), and the checker matches `ArmoredReader`.
+        assert_eq!(violation.map(|v| (v.line(), v.column())), Some((1, 1)));
+    } else {
+        assert_eq!(
+            violation.is_some(),
+            testfile.expect == Expect::ArmorFailure,
+            "{:?}",
+            violation,
+        );
+    }
+}
+
 fn format_testkit_comment(testfile: &TestFile) -> String {
     testfile
         .comment
@@ -415,18 +463,32 @@ fn check_decrypt_error(filename: &str, testfile: TestFile, e: DecryptError) {
                 assert_eq!(testfile.expect, Expect::HeaderFailure);
             }
         }
-        DecryptError::ExcessiveWork { .. } | DecryptError::UnknownFormat => {
+        DecryptError::ExcessiveWork { .. }
+        | DecryptError::InvalidStanza(_)
+        | DecryptError::UnknownFormat => {
             assert_eq!(testfile.expect, Expect::HeaderFailure)
         }
         DecryptError::InvalidMac => assert_eq!(testfile.expect, Expect::HmacFailure),
-        DecryptError::DecryptionFailed | DecryptError::NoMatchingKeys => {
+        DecryptError::DecryptionFailed
+        | DecryptError::NoMatchingKeys
+        | DecryptError::ThresholdNotMet { .. } => {
             assert_eq!(testfile.expect, Expect::NoMatch)
         }
         DecryptError::KeyDecryptionFailed => todo!(),
+        #[cfg(feature = "kms")]
+        DecryptError::Kms(_) => todo!(),
         #[cfg(feature = "plugin")]
         DecryptError::MissingPlugin { .. } => todo!(),
         #[cfg(feature = "plugin")]
         DecryptError::Plugin(_) => todo!(),
+        #[cfg(feature = "plugin")]
+        DecryptError::PluginNotAllowed { .. } => todo!(),
+        #[cfg(feature = "plugin")]
+        DecryptError::PluginProtocol { .. } => todo!(),
+        #[cfg(feature = "plugin")]
+        DecryptError::PluginTooOld { .. } => todo!(),
+        #[cfg(feature = "plugin")]
+        DecryptError::Timeout { .. } => todo!(),
     }
 }
 
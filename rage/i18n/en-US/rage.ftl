@@ -13,15 +13,40 @@
 
 ## CLI flags
 
+-flag-archive = --archive
 -flag-armor = -a/--armor
+-flag-qr = --qr
 -flag-decrypt = -d/--decrypt
+-flag-verify-only = --verify-only
 -flag-encrypt = -e/--encrypt
 -flag-identity = -i/--identity
+-flag-output = -o/--output
+-flag-output-dir = --output-dir
+-flag-jobs = --jobs
 -flag-recipient = -r/--recipient
 -flag-recipients-file = -R/--recipients-file
+-flag-threshold = --threshold
+-flag-interactive = --interactive
 -flag-passphrase = -p/--passphrase
+-flag-passphrase-fd = --passphrase-fd
+-flag-words = --words
+-flag-wordlist = --wordlist
+-flag-charset = --charset
+-flag-chars = --chars
+-flag-normalize-passphrase = --normalize-passphrase
+-flag-passphrase-to-clipboard = --passphrase-to-clipboard
+-flag-passphrase-sink = --passphrase-sink
 -flag-plugin-name = -j
+-flag-rekey = --rekey
+-flag-check-armor = --check-armor
+-flag-inspect = --inspect
+-flag-json = --json
+-flag-format = --format
+-flag-daemon = --daemon
 -flag-max-work-factor = --max-work-factor
+-flag-mode = --mode
+-flag-force = --force
+-flag-no-default-identities = --no-default-identities
 -flag-unstable = --features unstable
 
 ## Usage
@@ -31,6 +56,7 @@
 -identity = IDENTITY
 -recipient = RECIPIENT
 -recipients-file = PATH
+-env-passphrase = RAGE_PASSPHRASE
 
 usage-header = Usage:
 
@@ -38,23 +64,111 @@ rage-usage =
     {usage-header}
     {"  "}{$usage_a}
     {"  "}{$usage_b}
+    {"  "}{$usage_c}
+    {"  "}{$usage_d}
+    {"  "}{$usage_e}
+    {"  "}{$usage_f}
 
     {$flags}
 
     {-input} defaults to standard input, and {-output} defaults to standard output.
 
+    With several {-input} files, or with {-flag-output-dir} DIR, each file is encrypted
+    or decrypted on its own. Its output is named by adding ".age" to the file's name
+    (or removing it, when decrypting), and is written into DIR, or next to the file.
+    {-flag-jobs} N processes up to N files at once, but no more than there are CPUs.
+
+    With {-flag-verify-only}, each {-input} is decrypted to check that it hasn't been
+    corrupted or tampered with, but the plaintext is discarded instead of written
+    anywhere. {-rage} exits with an error if any part of a file fails to decrypt.
+
+    With {-flag-archive}, {-input} is a directory that is encrypted as a tar archive.
+    When decrypting with {-flag-archive}, the archive is extracted into the directory
+    {-output}, which defaults to the current directory.
+
+    With {-flag-rekey}, the file {-input} is decrypted with {-identity} and re-encrypted
+    in place to {-recipient}, keeping its armor and permissions. The plaintext is never
+    written to disk, and {-input} is only replaced once re-encryption has succeeded.
+
+    With {-flag-check-armor}, {-input} is checked to be well-formed armor, and the
+    first problem is reported with its line and column. No keys are needed, so this
+    can find where a file was mangled (for example, by an email client) before
+    trying to decrypt it.
+
+    With {-flag-inspect}, {-rage} prints what the header of {-input} says about it:
+    its format version, the type and arguments of each recipient stanza, whether it
+    is armored, and the size of its payload and plaintext. No keys are needed, so
+    this can show why a file can't be decrypted (for example, if it was encrypted to
+    a different recipient). {-flag-json} prints the same details as JSON.
+
+    With {-flag-format} json, {-rage} writes a JSON object to standard error when it
+    exits, instead of human-readable messages. It lists the files that were
+    processed, and any warnings. If {-rage} fails, it has a stable "code" for the
+    error, which scripts can check instead of the localized "message".
+
+    {-rage} exits with 0 on success, 2 for invalid flags, 3 if a file can't be read
+    or written, 4 if {-input} is not a valid {-age} file or has been modified, 5 if
+    no identity matches {-input}, 6 for an incorrect passphrase, and 1 for any other
+    error.
+
+    With {-flag-daemon}, {-rage} reads {-identity} and {-recipient} once, and serves
+    encryption and decryption requests from other local processes on the Unix socket
+    SOCKET, so that they can use the identities without reading the key files. SOCKET
+    must be in a directory that is owned by, and only accessible to, the current user.
+
     {-recipient} can be:
     - An {-age} public key, as generated by {$keygen_name} ("age1...").
     - An SSH public key ("ssh-ed25519 AAAA...", "ssh-rsa AAAA...").
+    - An HTTPS URL ("https://...") or GitHub username ("github:NAME") from which to
+    {"  "}fetch SSH public keys, if {-rage} was built with the "http" feature.
 
     {-recipients-file} is a path to a file containing {-age} recipients, one per line
-    (ignoring "#" prefixed comments and empty lines).
+    (ignoring "#" prefixed comments and empty lines). A recipient can be annotated
+    with a "# label:NAME expires:YYYY-MM-DD" comment on the line before it, and
+    {-rage} will refuse to encrypt to it from that date.
+
+    With {-flag-threshold} K, the file can only be decrypted with the identities for
+    any K of the recipients together. Each recipient gets a share of the file key, so
+    this can be used for escrow or break-glass keys held by several people.
+
+    With {-flag-interactive}, {-rage} shows each recipient and where it was read
+    from, and asks for confirmation before encrypting. This can catch a mistyped
+    {-recipient} or an unexpected key in {-recipients-file}.
+
+    With {-flag-passphrase}, leaving the passphrase empty autogenerates one from the
+    wordlist for your language (or the English BIP 39 wordlist), with enough words
+    for 110 bits of entropy. {-flag-words} sets the number of words, and
+    {-flag-wordlist} uses a different wordlist (such as the EFF diceware lists).
+    {-flag-charset} generates {-flag-chars} characters instead, from one of the
+    charsets "alphanumeric", "lowercase", or "printable".
+
+    An autogenerated passphrase is printed to the terminal. To keep it off the
+    screen, {-flag-passphrase-to-clipboard} copies it to the clipboard instead, and
+    {-flag-passphrase-sink} COMMAND writes it to the input of COMMAND (such as a
+    password manager, with "pass insert -m NAME"). Nothing is encrypted if the
+    passphrase can't be stored.
+
+    For scripts, {-flag-passphrase-fd} FD reads the passphrase from the first line of
+    the file descriptor FD (such as 3, with "3< passphrase.txt") instead of the
+    terminal. The passphrase can also be set in the environment variable
+    {-env-passphrase}, but other processes may be able to read it from there.
 
     {-identity} is a path to a file with {-age} identities, one per line
     (ignoring "#" prefixed comments and empty lines), or to an SSH key file.
     Passphrase-encrypted {-age} identity files can be used as identity files.
     Multiple identities may be provided, and any unused ones will be ignored.
 
+    When decrypting without {-flag-identity}, {-rage} uses the identities in these
+    default identity files, if they exist:
+    - {$default_identities}
+    - ~/.ssh/id_ed25519, if {-rage} was built with the "ssh" feature.
+    Pass {-flag-no-default-identities} to disable this.
+
+    A recipient or identity for a plugin ("age1name1...", "AGE-PLUGIN-NAME-1...")
+    requires running the binary age-plugin-NAME. {-rage} asks before running a
+    plugin unless its NAME is listed, one per line, in {$plugins_file}, or it was
+    requested with {-flag-plugin-name}.
+
     Example:
     {"  "}{$example_a}
     {"  "}{tty-pubkey}: {$example_a_output}
@@ -68,26 +182,86 @@ warning-msg = Warning: {$warning}
 ## Keygen messages
 
 tty-pubkey = Public key
-identity-file-created = created
-identity-file-pubkey = public key
+identity-file-derived = derived with label
+recipient-file-keychain = identity stored in keychain as
+keygen-mnemonic = Recovery phrase
+keygen-mnemonic-header =
+    Recovery phrase (write it down and keep it safe; anyone who has it can decrypt
+    your files):
+keygen-mnemonic-prompt = Type the recovery phrase
 
 ## Encryption messages
 
-autogenerated-passphrase = Using an autogenerated passphrase:
+autogenerated-passphrase = Using an autogenerated passphrase ({$bits} bits of entropy):
+autogenerated-passphrase-clipboard =
+    Using an autogenerated passphrase ({$bits} bits of entropy), which was copied to the clipboard.
+autogenerated-passphrase-sink =
+    Using an autogenerated passphrase ({$bits} bits of entropy), which was written to {-flag-passphrase-sink}.
 type-passphrase = Type passphrase
 prompt-passphrase = Passphrase
 
+interactive-recipients = Encrypting to {$count ->
+    [one] 1 recipient:
+   *[other] {$count} recipients:
+}
+interactive-hidden = (a recipient that can't be displayed)
+interactive-from-flag = from {-flag-recipient}
+interactive-from-file = from {$filename}, line {$line}
+interactive-from-file-label = from {$filename}, line {$line} ({$label})
+interactive-from-identity = from identity file {$filename}
+interactive-threshold = Any {$threshold} of them will be needed to decrypt.
+interactive-confirm = Encrypt to these recipients?
+interactive-confirm-yes = Encrypt
+interactive-confirm-no = Cancel
+
 warn-double-encrypting = Encrypting an already-encrypted file
+warn-passphrase-env =
+    Using the passphrase from {-env-passphrase}. Other processes may be able to
+    read environment variables; prefer {-flag-passphrase-fd}.
+
+## Decryption messages
+
+warn-skipped-default-identity = Skipping default identity file '{$filename}': {$err}
 
 ## General errors
 
 err-failed-to-open-output = Failed to open output: {$err}
 err-failed-to-write-output = Failed to write to output: {$err}
+err-failed-to-store-in-keychain = Failed to store identity in keychain: {$err}
+err-failed-to-create-fido2-credential = Failed to create a credential on the security key: {$err}
+err-failed-to-open-yubikey = Failed to open YubiKey: {$err}
+err-fido2-no-security-key = No security key is connected.
+err-keygen-multiple-keys = Only one of --derive, --fido2, --from-mnemonic, --pq, --to-keychain, and --yubikey can be used.
+err-keygen-mnemonic-not-x25519 = --mnemonic can't be used with --fido2, --pq, or --yubikey.
+err-keygen-read-mnemonic = Could not read the recovery phrase: {$err}
+err-keygen-derive-without-master = --derive and --master must be used together.
+err-keygen-master-not-x25519 = '{$filename}' doesn't contain an X25519 identity to derive from.
+err-keygen-read-master = Could not read the master identity file '{$filename}': {$err}
+err-keygen-append-without-output = --append requires -o/--output.
+err-keygen-qr-too-large = The recipient is too long to print as a QR code.
+err-keygen-read-existing = Could not add to the identity file '{$filename}': {$err}
+err-yubikey-no-key = No connected YubiKey has a P-256 key in slot {$slot}.
+rec-yubikey-no-key = Generate one with: ykman piv keys generate -a ECCP256 {$slot} -
 err-identity-ambiguous = {-flag-identity} requires either {-flag-encrypt} or {-flag-decrypt}.
 err-mixed-encrypt-decrypt = {-flag-encrypt} can't be used with {-flag-decrypt}.
+err-passphrase-fd-stdin = {-flag-passphrase-fd} 0 can't be used when {-input} is standard input.
+err-passphrase-fd-unsupported = {-flag-passphrase-fd} is only supported on Unix.
 err-passphrase-timed-out = Timed out waiting for passphrase input.
+err-read-passphrase = Failed to read passphrase: {$err}
 err-same-input-and-output = Input and output are the same file '{$filename}'.
 
+err-unknown-format = Unknown format '{$format}' (expected 'text' or 'json').
+err-completions-shell = Unknown shell '{$shell}' (expected one of: {$shells}).
+
+err-jobs-zero = {-flag-jobs} must be at least 1.
+err-multiple-inputs-flag = Multiple inputs, {-flag-output-dir}, and {-flag-jobs} can't be used with {-flag-archive}, {-flag-rekey}, {-flag-check-armor}, {-flag-inspect}, or {-flag-daemon}.
+err-multiple-inputs-output = {-flag-output} can't be used with multiple inputs.
+rec-multiple-inputs-output = Did you mean to use {-flag-output-dir}?
+err-multiple-inputs-stdin = Multiple inputs and {-flag-output-dir} can't be used with standard input.
+err-unnamed-output = Can't name the output for '{$filename}', because it doesn't end in .age.
+
+rec-plugin-not-allowed = If you trust this plugin, add '{$plugin_name}' to {$plugins_file}.
+
 err-ux-A = Did {-rage} not do what you expected? Could an error be more useful?
 err-ux-B = Tell us
 # Put (len(A) - len(B) - 32) spaces here.
@@ -100,18 +274,53 @@ rec-enc-broken-stdout = Are you piping to a program that isn't reading from stdi
 
 err-enc-broken-file = Could not write to file: {$err}
 
+err-enc-archive-input-not-directory = {-flag-archive} requires {-input} to be a directory.
+
+err-enc-fetch-recipients = Could not fetch recipients from {$url}: {$err}
+
 err-enc-invalid-recipient = Invalid recipient '{$recipient}'
 
 err-enc-missing-recipients = Missing recipients.
 rec-enc-missing-recipients = Did you forget to specify {-flag-recipient}?
 
 err-enc-mixed-identity-passphrase = {-flag-identity} can't be used with {-flag-passphrase}.
+err-enc-mixed-interactive-passphrase = {-flag-interactive} can't be used with {-flag-passphrase}.
 err-enc-mixed-recipient-passphrase = {-flag-recipient} can't be used with {-flag-passphrase}
 err-enc-mixed-recipients-file-passphrase = {-flag-recipients-file} can't be used with {-flag-passphrase}
+err-enc-mixed-threshold-passphrase = {-flag-threshold} can't be used with {-flag-passphrase}.
 err-enc-passphrase-without-file = File to encrypt must be passed as an argument when using {-flag-passphrase}
+err-enc-passphrase-fd-without-passphrase = {-flag-passphrase-fd} requires {-flag-passphrase} when encrypting.
 
 err-enc-plugin-name-flag = {-flag-plugin-name} can't be used with {-flag-encrypt}.
 
+err-enc-threshold-zero = {-flag-threshold} must be at least 1.
+err-enc-invalid-threshold =
+    {-flag-threshold} {$threshold} needs at least {$threshold} and at most 255 recipients, but {$recipients} were given.
+
+err-enc-generator-without-passphrase =
+    {-flag-words}, {-flag-wordlist}, {-flag-charset}, and {-flag-chars} require {-flag-passphrase}.
+err-enc-mixed-words-charset =
+    {-flag-words} and {-flag-wordlist} can't be used with {-flag-charset} or {-flag-chars}.
+err-enc-passphrase-length-zero = A generated passphrase must have at least one word or character.
+err-enc-invalid-wordlist = Wordlist '{$filename}' must contain at least two distinct words.
+err-enc-unknown-charset = Unknown charset '{$charset}'. Expected alphanumeric, lowercase, or printable.
+
+err-enc-generator-not-normalized = {-flag-normalize-passphrase} would weaken this generated passphrase.
+rec-enc-generator-not-normalized = Use {-flag-charset} lowercase, or a wordlist of lowercase words.
+
+err-enc-passphrase-sink-without-passphrase =
+    {-flag-passphrase-to-clipboard} and {-flag-passphrase-sink} require {-flag-passphrase}.
+err-enc-multiple-passphrase-sinks =
+    {-flag-passphrase-to-clipboard} can't be used with {-flag-passphrase-sink}.
+err-enc-empty-passphrase-sink = {-flag-passphrase-sink} requires a command.
+err-enc-passphrase-sink = Could not store the autogenerated passphrase, so nothing was encrypted:
+
+err-enc-interactive-without-terminal = {-flag-interactive} requires a terminal to confirm the recipients.
+err-enc-recipients-declined = The recipients were not confirmed, so nothing was encrypted.
+
+err-enc-qr-too-large = The encrypted file is too large to show as a QR code.
+rec-enc-qr-too-large = A QR code holds up to 1663 bytes of armored data. Use {-flag-armor} instead.
+
 ## Decryption errors
 
 err-detected-powershell-corruption = It looks like this file was corrupted by PowerShell redirection.
@@ -122,6 +331,9 @@ rec-dec-excessive-work = To decrypt, retry with {-flag-max-work-factor} {$wf}
 err-dec-armor-flag = {-flag-armor} can't be used with {-flag-decrypt}.
 rec-dec-armor-flag = Note that armored files are detected automatically.
 
+err-dec-interactive-flag = {-flag-interactive} can't be used with {-flag-decrypt}.
+err-dec-qr-flag = {-flag-qr} can't be used with {-flag-decrypt}.
+
 err-dec-identity-encrypted-without-passphrase =
     Identity file '{$filename}' is encrypted with {-age} but not with a passphrase.
 
@@ -130,6 +342,11 @@ err-dec-identity-not-found = Identity file not found: {$filename}
 err-dec-missing-identities = Missing identities.
 rec-dec-missing-identities = Did you forget to specify {-flag-identity}?
 
+err-dec-exposed-output = Refusing to write the plaintext to '{$filename}', where other users could read it.
+rec-dec-exposed-output =
+    Use {-flag-mode} 600 to make the file private, or {-flag-force} to write it
+    anyway.
+
 err-dec-mixed-identity-passphrase = {-flag-identity} can't be used with passphrase-encrypted files.
 
 err-mixed-identity-and-plugin-name = {-flag-identity} can't be used with {-flag-plugin-name}.
@@ -144,11 +361,71 @@ err-dec-passphrase-without-file-win =
 
 err-dec-recipient-flag = {-flag-recipient} can't be used with {-flag-decrypt}.
 err-dec-recipients-file-flag = {-flag-recipients-file} can't be used with {-flag-decrypt}.
+err-dec-threshold-flag = {-flag-threshold} can't be used with {-flag-decrypt}.
+err-dec-verify-only-output-flags = {-flag-verify-only} doesn't write any output, and can't be used with {-flag-output}, {-flag-output-dir}, or {-flag-archive}.
 rec-dec-recipient-flag = Did you mean to use {-flag-identity} to specify a private key?
 
+err-dec-wrong-passphrase = Incorrect passphrase.
+rec-dec-wrong-passphrase =
+    If the file was encrypted with {-flag-normalize-passphrase}, decrypt it with
+    {-flag-normalize-passphrase} as well.
+
+## Rekey errors
+
+err-rekey-archive-flag = {-flag-archive} can't be used with {-flag-rekey}.
+err-rekey-encrypt-decrypt-flag = {-flag-rekey} can't be used with {-flag-encrypt} or {-flag-decrypt}.
+err-rekey-missing-input = {-flag-rekey} requires {-input} to be a file.
+err-rekey-output-flag = {-flag-output} can't be used with {-flag-rekey}.
+rec-rekey-output-flag = {-flag-rekey} replaces {-input} with the re-encrypted file.
+err-rekey-passphrase-encrypted = {-flag-rekey} can't be used with passphrase-encrypted files.
+err-rekey-passphrase-flag = {-flag-passphrase} and {-flag-passphrase-fd} can't be used with {-flag-rekey}.
+
+## Armor check errors
+
+err-check-armor-invalid = Invalid armor at line {$line}, column {$column}: {$err}
+err-check-armor-other-flags = {-flag-check-armor} only reads {-input}, and can't be used with other flags.
+
+## Inspection messages
+
+inspect-version = Version: {$version}
+inspect-armored = Encoding: armored
+inspect-binary = Encoding: binary
+inspect-header = Header: {$len} bytes
+inspect-recipients = Recipient stanzas: {$count}
+inspect-stanza-label = {"    "}label: {$label}
+inspect-passphrase = The file is encrypted with a passphrase.
+inspect-payload = Payload: {$len} bytes in {$chunks ->
+    [one] 1 chunk
+   *[other] {$chunks} chunks
+}
+inspect-payload-truncated = Payload: {$len} bytes, which is too short to be valid (the file may be truncated)
+inspect-plaintext = Plaintext: {$len} bytes
+inspect-unauthenticated = None of this is authenticated until {-input} is decrypted.
+
+err-inspect-json-flag = {-flag-json} can only be used with {-flag-inspect}.
+err-inspect-other-flags = {-flag-inspect} only reads {-input}, and can only be used with {-flag-json}.
+
+## Daemon messages
+
+info-daemon-listening = Listening on {$socket}
+
+err-daemon-message-too-long = Request is longer than the maximum of {$max} bytes.
+err-daemon-no-identities = This daemon was started without identities, so it can't decrypt.
+err-daemon-no-recipients = This daemon was started without {-flag-recipient}, so it can't encrypt.
+err-daemon-other-flags = {-flag-daemon} can only be used with {-flag-identity}, {-flag-recipient}, {-flag-recipients-file}, {-flag-plugin-name}, and {-flag-armor}.
+err-daemon-passphrase-encrypted = {-flag-daemon} can't decrypt passphrase-encrypted files.
+err-daemon-socket-dir-exposed =
+    {-flag-daemon} won't create a socket in {$dir}, because other users can access it.
+    Use a directory that only you own and can access, such as $XDG_RUNTIME_DIR.
+err-daemon-socket-in-use = Another daemon is already listening on {$socket}.
+err-daemon-unknown-operation = Unknown operation {$op}.
+err-daemon-unsupported = {-flag-daemon} is only supported on Unix.
+
 ## rage-mount strings
 
 -flag-mnt-types = -t/--types
+-flag-mnt-list = --list
+-flag-mnt-extract = --extract
 
 info-decrypting = Decrypting {$filename}
 info-mounting-as-fuse = Mounting as FUSE filesystem
@@ -156,6 +433,8 @@ info-mounting-as-fuse = Mounting as FUSE filesystem
 err-mnt-missing-filename = Missing filename.
 err-mnt-missing-mountpoint = Missing mountpoint.
 err-mnt-missing-types = Missing {-flag-mnt-types}.
+err-mnt-list-extract = {-flag-mnt-list} can't be used with {-flag-mnt-extract}.
+err-mnt-not-in-archive = '{$path}' is not a file in the archive.
 err-mnt-unknown-type = Unknown filesystem type "{$fs_type}"
 
 ## Unstable features
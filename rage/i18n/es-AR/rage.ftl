@@ -65,8 +65,6 @@ rage-usage =
 ## Keygen messages
 
 tty-pubkey = Clave pública
-identity-file-created = creada
-identity-file-pubkey = clave pública
 
 ## Encryption messages
 
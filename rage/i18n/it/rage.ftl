@@ -70,8 +70,6 @@ warning-msg = Attenzione: {$warning}
 ## Keygen messages
 
 tty-pubkey = Chiave pubblica
-identity-file-created = creato
-identity-file-pubkey = chiave pubblica
 
 ## Encryption messages
 
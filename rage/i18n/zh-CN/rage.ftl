@@ -63,8 +63,6 @@ rage-usage =
 ## Keygen messages
 
 tty-pubkey = 公钥
-identity-file-created = 已创建
-identity-file-pubkey = 公钥
 
 ## Encryption messages
 
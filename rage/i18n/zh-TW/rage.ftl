@@ -63,8 +63,6 @@ rage-usage =
 ## Keygen messages
 
 tty-pubkey = 公鑰
-identity-file-created = 已創建
-identity-file-pubkey = 公鑰
 
 ## Encryption messages
 
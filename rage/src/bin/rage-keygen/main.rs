@@ -1,15 +1,27 @@
 #![forbid(unsafe_code)]
 
-use age::{cli_common::file_io, secrecy::ExposeSecret};
+use age::{
+    cli_common::{file_io, read_secret},
+    secrecy::{ExposeSecret, SecretString},
+    IdentityFile, IdentityFileEntry, IdentityMetadata,
+};
 use gumdrop::Options;
 use i18n_embed::{
     fluent::{fluent_language_loader, FluentLanguageLoader},
+    unic_langid::LanguageIdentifier,
     DesktopLanguageRequester,
 };
 use lazy_static::lazy_static;
 use log::error;
 use rust_embed::RustEmbed;
-use std::io::Write;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::process;
+use std::str::FromStr;
+
+#[cfg(feature = "cli-docs")]
+#[path = "../../cli.rs"]
+mod cli;
 
 #[derive(RustEmbed)]
 #[folder = "i18n"]
@@ -17,6 +29,10 @@ struct Translations;
 
 const TRANSLATIONS: Translations = Translations {};
 
+/// The service name under which `--to-keychain` stores identities.
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "rage";
+
 lazy_static! {
     static ref LANGUAGE_LOADER: FluentLanguageLoader = fluent_language_loader!();
 }
@@ -27,16 +43,321 @@ macro_rules! fl {
     }};
 }
 
+/// How `rage-keygen` reports its results and errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+/// An unknown value for `--format`.
+#[derive(Debug)]
+struct UnknownFormat(String);
+
+impl fmt::Display for UnknownFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            i18n_embed_fl::fl!(
+                LANGUAGE_LOADER,
+                "err-unknown-format",
+                format = self.0.as_str()
+            )
+        )
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+/// Exit code for failures that aren't in one of the classes below.
+const EXIT_OTHER: i32 = 1;
+/// Exit code for invalid flags, as for `rage`.
+const EXIT_USAGE: i32 = 2;
+/// Exit code for failures to write the output, as for `rage`.
+const EXIT_IO: i32 = 3;
+
+/// Reports that key generation failed with the error `message`, and exits with
+/// `exit_code`.
+///
+/// With `--format json`, this writes `{"status":"error","error":{...}}` to standard
+/// error, with a stable `code` for the failure.
+fn fail(format: Format, exit_code: i32, code: &str, message: String) -> ! {
+    match format {
+        Format::Text => error!("{}", message),
+        Format::Json => eprintln!(
+            "{}",
+            serde_json::json!({
+                "status": "error",
+                "error": { "code": code, "exit_code": exit_code, "message": message },
+            })
+        ),
+    }
+    process::exit(exit_code)
+}
+
 #[derive(Debug, Options)]
+#[cfg_attr(feature = "cli-docs", derive(clap::Parser))]
+#[cfg_attr(
+    feature = "cli-docs",
+    clap(
+        name = "rage-keygen",
+        about = "Generate age-compatible encryption key pairs",
+        disable_help_flag = true,
+        disable_version_flag = true
+    )
+)]
 struct AgeOptions {
-    #[options(help = "Print this help message and exit.")]
+    /// Print this help message and exit.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     help: bool,
 
-    #[options(help = "Print version info and exit.", short = "V")]
+    /// Print version info and exit.
+    #[options(short = "V")]
+    #[cfg_attr(feature = "cli-docs", clap(short = 'V', long))]
     version: bool,
 
-    #[options(help = "Write the result to the file at path OUTPUT. Defaults to standard output.")]
+    /// Show messages in LANG (such as es-AR) instead of the system language.
+    #[options(meta = "LANG", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "LANG"))]
+    lang: Option<LanguageIdentifier>,
+
+    /// Write the result to the file at path OUTPUT. Defaults to standard output.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     output: Option<String>,
+
+    /// Add the identity to the end of the identity file at OUTPUT, instead of replacing it.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    append: bool,
+
+    /// Create OUTPUT with the Unix permissions MODE (default: 600).
+    #[options(meta = "MODE", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "MODE"))]
+    mode: Option<file_io::Mode>,
+
+    /// Report the result and errors as FORMAT: text (the default) or json.
+    #[options(meta = "FORMAT", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "FORMAT"))]
+    format: Option<Format>,
+
+    /// Also print the identity as a 24-word recovery phrase, for backing it up on paper.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    mnemonic: bool,
+
+    /// Recover the identity from its recovery phrase instead of generating a new key. The phrase is read from standard input, or asked for at the terminal.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    from_mnemonic: bool,
+
+    #[cfg(feature = "qr")]
+    /// Also print the recipient as a QR code, for scanning it with another device.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    qr: bool,
+
+    /// Derive the identity for LABEL from the identity in the file at --master, instead of generating a new key.
+    #[options(no_short, meta = "LABEL")]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "LABEL"))]
+    derive: Option<String>,
+
+    /// Use the identity in the file at PATH as the master secret for --derive.
+    #[options(no_short, meta = "PATH")]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "PATH"))]
+    master: Option<String>,
+
+    #[cfg(feature = "keyring")]
+    /// Store the identity in the platform keychain under NAME, and write its recipient to OUTPUT instead.
+    #[options(no_short, meta = "NAME")]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "NAME"))]
+    to_keychain: Option<String>,
+
+    #[cfg(feature = "fido2")]
+    /// Create a credential on a connected FIDO2 security key, and use it instead of generating a new key.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    fido2: bool,
+
+    #[cfg(feature = "pq")]
+    /// Generate an experimental post-quantum (X25519 + ML-KEM-768) identity. Files encrypted to it can only be decrypted by rage with the pq feature.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    pq: bool,
+
+    #[cfg(feature = "yubikey")]
+    /// Use the P-256 key in PIV slot SLOT (9a, 9c, 9d, or 9e) of a connected YubiKey, instead of generating a new key.
+    #[options(no_short, meta = "SLOT")]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "SLOT"))]
+    yubikey: Option<age::yubikey::Slot>,
+}
+
+impl AgeOptions {
+    /// Returns how many of the options that replace the generated identity (or where it
+    /// is written) are set.
+    #[allow(unused_mut)]
+    fn key_options(&self) -> usize {
+        let mut count = 0;
+        if self.from_mnemonic {
+            count += 1;
+        }
+        if self.derive.is_some() {
+            count += 1;
+        }
+        #[cfg(feature = "fido2")]
+        if self.fido2 {
+            count += 1;
+        }
+        #[cfg(feature = "pq")]
+        if self.pq {
+            count += 1;
+        }
+        #[cfg(feature = "keyring")]
+        if self.to_keychain.is_some() {
+            count += 1;
+        }
+        #[cfg(feature = "yubikey")]
+        if self.yubikey.is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns `true` if one of the options that use a key type other than X25519 is
+    /// set.
+    fn non_x25519(&self) -> bool {
+        #[cfg(feature = "fido2")]
+        if self.fido2 {
+            return true;
+        }
+        #[cfg(feature = "pq")]
+        if self.pq {
+            return true;
+        }
+        #[cfg(feature = "yubikey")]
+        if self.yubikey.is_some() {
+            return true;
+        }
+        false
+    }
+}
+
+/// Reads the first X25519 identity in the identity file at `filename`.
+fn read_master(filename: &str) -> io::Result<Option<age::x25519::Identity>> {
+    Ok(IdentityFile::from_file(filename.to_owned())?
+        .into_identities()
+        .into_iter()
+        .find_map(|entry| match entry {
+            IdentityFileEntry::Native(sk) => Some(sk),
+            _ => None,
+        }))
+}
+
+/// Reads the identity file at `filename` that `--append` adds to, if it exists.
+///
+/// The file is checked to be an identity file, so that `--append` never adds an
+/// identity to some other kind of file.
+fn read_existing(filename: &str, check: bool) -> io::Result<Option<String>> {
+    let contents = match std::fs::read_to_string(filename) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if check {
+        IdentityFile::from_buffer(contents.as_bytes())?;
+    }
+    Ok(Some(contents))
+}
+
+/// Reads a recovery phrase from standard input, or asks for it at the terminal.
+fn read_mnemonic() -> Result<SecretString, String> {
+    let read_error =
+        |err: String| i18n_embed_fl::fl!(LANGUAGE_LOADER, "err-keygen-read-mnemonic", err = err);
+
+    let mut input = file_io::InputReader::new(None).map_err(|e| read_error(e.to_string()))?;
+    if input.is_terminal() {
+        read_secret(
+            &fl!("keygen-mnemonic-prompt"),
+            &fl!("keygen-mnemonic"),
+            None,
+        )
+        .map_err(|e| read_error(e.to_string()))
+    } else {
+        let mut phrase = String::new();
+        input
+            .read_to_string(&mut phrase)
+            .map_err(|e| read_error(e.to_string()))?;
+        Ok(SecretString::new(phrase))
+    }
+}
+
+/// Creates a credential on the first connected FIDO2 security key, and returns its
+/// identity stub and recipient.
+#[cfg(feature = "fido2")]
+fn generate_fido2() -> Result<(SecretString, String), String> {
+    match age::fido2::Identity::generate(age::cli_common::UiCallbacks) {
+        Ok(Some(identity)) => Ok((
+            SecretString::new(identity.to_stub().to_string()),
+            identity.to_recipient().to_string(),
+        )),
+        Ok(None) => Err(fl!("err-fido2-no-security-key")),
+        Err(e) => Err(i18n_embed_fl::fl!(
+            LANGUAGE_LOADER,
+            "err-failed-to-create-fido2-credential",
+            err = e.to_string()
+        )),
+    }
+}
+
+/// Opens the key in `slot` of the first connected YubiKey, and returns its identity
+/// stub and recipient.
+#[cfg(feature = "yubikey")]
+fn open_yubikey(slot: age::yubikey::Slot) -> Result<(SecretString, String), String> {
+    let identity = age::yubikey::Identity::open(None, slot, age::cli_common::UiCallbacks)
+        .and_then(|identity| {
+            identity
+                .map(|identity| identity.to_public().map(|pk| (identity, pk)))
+                .transpose()
+        })
+        .map_err(|e| {
+            i18n_embed_fl::fl!(
+                LANGUAGE_LOADER,
+                "err-failed-to-open-yubikey",
+                err = e.to_string()
+            )
+        })?;
+
+    match identity {
+        Some((identity, pk)) => Ok((
+            SecretString::new(identity.to_stub().to_string()),
+            pk.to_string(),
+        )),
+        None => Err(format!(
+            "{}\n{}",
+            i18n_embed_fl::fl!(
+                LANGUAGE_LOADER,
+                "err-yubikey-no-key",
+                slot = slot.to_string()
+            ),
+            i18n_embed_fl::fl!(
+                LANGUAGE_LOADER,
+                "rec-yubikey-no-key",
+                slot = slot.to_string()
+            ),
+        )),
+    }
 }
 
 fn main() {
@@ -53,53 +374,271 @@ fn main() {
     // Isolation Marks, so we disable them for now.
     LANGUAGE_LOADER.set_use_isolating(false);
 
+    #[cfg(feature = "cli-docs")]
+    if cli::handle::<AgeOptions>(&std::env::args().collect::<Vec<_>>(), EXIT_USAGE) {
+        return;
+    }
+
     let opts = AgeOptions::parse_args_default_or_exit();
 
+    if let Some(language) = &opts.lang {
+        // Override the languages requested by the environment.
+        let languages = [language.clone()];
+        i18n_embed::select(&*LANGUAGE_LOADER, &TRANSLATIONS, &languages).unwrap();
+        age::localizer().select(&languages).unwrap();
+    }
+
     if opts.version {
         println!("rage-keygen {}", env!("CARGO_PKG_VERSION"));
         return;
     }
 
+    let format = opts.format.unwrap_or(Format::Text);
+
+    if opts.key_options() > 1 {
+        fail(
+            format,
+            EXIT_USAGE,
+            "keygen-multiple-keys",
+            fl!("err-keygen-multiple-keys"),
+        );
+    }
+    if opts.derive.is_some() != opts.master.is_some() {
+        fail(
+            format,
+            EXIT_USAGE,
+            "keygen-derive-without-master",
+            fl!("err-keygen-derive-without-master"),
+        );
+    }
+    if opts.append && matches!(opts.output.as_deref(), None | Some("-")) {
+        fail(
+            format,
+            EXIT_USAGE,
+            "keygen-append-without-output",
+            fl!("err-keygen-append-without-output"),
+        );
+    }
+    if opts.mnemonic && opts.non_x25519() {
+        fail(
+            format,
+            EXIT_USAGE,
+            "keygen-mnemonic-not-x25519",
+            fl!("err-keygen-mnemonic-not-x25519"),
+        );
+    }
+
+    // With --to-keychain, OUTPUT is a recipients file rather than an identity file.
+    #[cfg(feature = "keyring")]
+    let check_existing = opts.to_keychain.is_none();
+    #[cfg(not(feature = "keyring"))]
+    let check_existing = true;
+
+    let existing = match opts.output.as_deref() {
+        Some(filename) if opts.append => match read_existing(filename, check_existing) {
+            Ok(existing) => existing,
+            Err(e) => fail(
+                format,
+                EXIT_IO,
+                "keygen-read-existing",
+                i18n_embed_fl::fl!(
+                    LANGUAGE_LOADER,
+                    "err-keygen-read-existing",
+                    filename = filename,
+                    err = e.to_string()
+                ),
+            ),
+        },
+        _ => None,
+    };
+
+    let output_path = opts.output.clone();
+    let mode = opts.mode.map_or(0o600, |mode| mode.0);
     let mut output =
-        match file_io::OutputWriter::new(opts.output, file_io::OutputFormat::Text, 0o600, false) {
+        match file_io::OutputWriter::new(opts.output, file_io::OutputFormat::Text, mode, false) {
             Ok(output) => output,
             Err(e) => {
-                error!(
-                    "{}",
+                fail(
+                    format,
+                    EXIT_IO,
+                    "failed-to-open-output",
                     i18n_embed_fl::fl!(
                         LANGUAGE_LOADER,
                         "err-failed-to-open-output",
                         err = e.to_string()
-                    )
+                    ),
                 );
-                return;
             }
         };
+    // A replaced file may have been readable by others; the new identity should not be.
+    output.use_new_permissions();
 
-    let sk = age::x25519::Identity::generate();
-    let pk = sk.to_public();
+    // Use a hardware key instead, if one was requested.
+    let hardware_key: Option<Result<(SecretString, String), String>> = None;
+    #[cfg(feature = "fido2")]
+    let hardware_key = hardware_key.or_else(|| opts.fido2.then(generate_fido2));
+    #[cfg(feature = "yubikey")]
+    let hardware_key = hardware_key.or_else(|| opts.yubikey.map(open_yubikey));
+    let hardware_key = match hardware_key.transpose() {
+        Ok(hardware_key) => hardware_key,
+        Err(e) => fail(format, EXIT_OTHER, "hardware-key", e),
+    };
+
+    let mut mnemonic = None;
+    let (identity, pk): (SecretString, String) = match hardware_key {
+        Some(hardware_key) => hardware_key,
+        #[cfg(feature = "pq")]
+        None if opts.pq => {
+            let sk = age::pq::Identity::generate();
+            (sk.to_string(), sk.to_public().to_string())
+        }
+        None => {
+            let sk = if let (Some(label), Some(filename)) = (&opts.derive, &opts.master) {
+                match read_master(filename) {
+                    Ok(Some(master)) => master.derive(label),
+                    Ok(None) => fail(
+                        format,
+                        EXIT_OTHER,
+                        "keygen-master-not-x25519",
+                        i18n_embed_fl::fl!(
+                            LANGUAGE_LOADER,
+                            "err-keygen-master-not-x25519",
+                            filename = filename.as_str()
+                        ),
+                    ),
+                    Err(e) => fail(
+                        format,
+                        EXIT_IO,
+                        "keygen-read-master",
+                        i18n_embed_fl::fl!(
+                            LANGUAGE_LOADER,
+                            "err-keygen-read-master",
+                            filename = filename.as_str(),
+                            err = e.to_string()
+                        ),
+                    ),
+                }
+            } else if opts.from_mnemonic {
+                let phrase = match read_mnemonic() {
+                    Ok(phrase) => phrase,
+                    Err(e) => fail(format, EXIT_IO, "keygen-read-mnemonic", e),
+                };
+                match age::x25519::Identity::from_mnemonic(phrase.expose_secret()) {
+                    Ok(sk) => sk,
+                    Err(e) => fail(format, EXIT_OTHER, "keygen-invalid-mnemonic", e.to_string()),
+                }
+            } else {
+                age::x25519::Identity::generate()
+            };
+            if opts.mnemonic {
+                mnemonic = Some(sk.to_mnemonic());
+            }
+
+            #[cfg(feature = "keyring")]
+            if let Some(name) = &opts.to_keychain {
+                if let Err(e) = age::keyring::PlatformKeyring::new(KEYRING_SERVICE).store(name, &sk)
+                {
+                    fail(
+                        format,
+                        EXIT_OTHER,
+                        "failed-to-store-in-keychain",
+                        i18n_embed_fl::fl!(
+                            LANGUAGE_LOADER,
+                            "err-failed-to-store-in-keychain",
+                            err = e.to_string()
+                        ),
+                    );
+                }
+            }
+
+            (sk.to_string(), sk.to_public().to_string())
+        }
+    };
+
+    // The QR code is printed alongside the public key, so it isn't printed with JSON.
+    #[cfg(feature = "qr")]
+    let qr_code = if format == Format::Text && opts.qr {
+        match age::qr::to_terminal(&pk) {
+            Some(code) => Some(code),
+            None => fail(
+                format,
+                EXIT_OTHER,
+                "keygen-qr-too-large",
+                fl!("err-keygen-qr-too-large"),
+            ),
+        }
+    } else {
+        None
+    };
 
     if let Err(e) = (|| {
-        if !output.is_terminal() {
+        if format == Format::Text && !output.is_terminal() {
             eprintln!("{}: {}", fl!("tty-pubkey"), pk);
         }
+        #[cfg(feature = "qr")]
+        if let Some(code) = &qr_code {
+            eprintln!("{}", code);
+        }
+        if let (Format::Text, Some(mnemonic)) = (format, &mnemonic) {
+            eprintln!("{}", fl!("keygen-mnemonic-header"));
+            eprintln!("    {}", mnemonic.expose_secret());
+        }
 
-        writeln!(
-            output,
-            "# {}: {}",
-            fl!("identity-file-created"),
-            chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-        )?;
-        writeln!(output, "# {}: {}", fl!("identity-file-pubkey"), pk)?;
-        writeln!(output, "{}", sk.to_string().expose_secret())
+        // Keep the existing entries, separated from the new one by a blank line.
+        if let Some(existing) = &existing {
+            if !existing.is_empty() {
+                write!(output, "{}", existing)?;
+                if !existing.ends_with('\n') {
+                    writeln!(output)?;
+                }
+                writeln!(output)?;
+            }
+        }
+
+        let metadata = IdentityMetadata::new()
+            .with_created(chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+
+        // The identity is in the keychain, so we write its recipient instead.
+        #[cfg(feature = "keyring")]
+        if let Some(name) = &opts.to_keychain {
+            metadata
+                .with_comment(format!("{}: {}", fl!("recipient-file-keychain"), name))
+                .write_identity(&mut output, &pk)?;
+            return output.commit();
+        }
+
+        let metadata = match &opts.derive {
+            Some(label) => {
+                metadata.with_comment(format!("{}: {}", fl!("identity-file-derived"), label))
+            }
+            None => metadata,
+        };
+        metadata
+            .with_public_key(pk.as_str())
+            .write_identity(&mut output, identity.expose_secret())?;
+        output.commit()
     })() {
-        error!(
-            "{}",
+        fail(
+            format,
+            EXIT_IO,
+            "failed-to-write-output",
             i18n_embed_fl::fl!(
                 LANGUAGE_LOADER,
                 "err-failed-to-write-output",
                 err = e.to_string()
-            )
+            ),
+        );
+    }
+
+    if format == Format::Json {
+        eprintln!(
+            "{}",
+            serde_json::json!({
+                "status": "ok",
+                "output": output_path,
+                "recipient": pk,
+                "mnemonic": mnemonic.as_ref().map(|m| m.expose_secret()),
+            })
         );
     }
 }
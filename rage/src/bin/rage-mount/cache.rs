@@ -0,0 +1,136 @@
+use age::{armor::ArmoredReader, secrecy::zeroize::Zeroize, stream::StreamReader};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+/// The age STREAM chunk size, so that each cached chunk is the plaintext of exactly one
+/// chunk of the age file.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The default size of the cache, in MiB.
+pub const DEFAULT_CACHE_SIZE: usize = 64;
+
+/// The decrypted stream that the mounted filesystems read from.
+pub type Stream = ChunkCache<StreamReader<ArmoredReader<BufReader<File>>>>;
+
+/// A reader that keeps the most recently used chunks of plaintext from its inner
+/// reader in memory.
+///
+/// Filesystem reads are small and frequently revisit the same data (such as archive
+/// headers during directory traversal), which would otherwise decrypt the same chunks
+/// of the age file over and over. Chunks are zeroized when they are evicted, and when
+/// the cache is dropped.
+pub struct ChunkCache<R> {
+    inner: R,
+    pos: u64,
+    len: u64,
+    capacity: usize,
+    /// Cached chunks by index, with the tick at which each was last used.
+    chunks: HashMap<u64, (u64, Vec<u8>)>,
+    tick: u64,
+}
+
+impl<R: Read + Seek> ChunkCache<R> {
+    /// Wraps `inner` with a cache of up to `size_mib` MiB. At least one chunk is always
+    /// cached.
+    pub fn new(mut inner: R, size_mib: usize) -> io::Result<Self> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        Ok(ChunkCache {
+            inner,
+            pos: 0,
+            len,
+            capacity: (size_mib.saturating_mul(1024 * 1024) / CHUNK_SIZE).max(1),
+            chunks: HashMap::new(),
+            tick: 0,
+        })
+    }
+
+    fn chunk(&mut self, index: u64) -> io::Result<&[u8]> {
+        self.tick += 1;
+
+        if !self.chunks.contains_key(&index) {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let mut filled = 0;
+            self.inner
+                .seek(SeekFrom::Start(index * CHUNK_SIZE as u64))?;
+            while filled < CHUNK_SIZE {
+                match self.inner.read(&mut chunk[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => {
+                        chunk.zeroize();
+                        return Err(e);
+                    }
+                }
+            }
+            chunk.truncate(filled);
+
+            if self.chunks.len() >= self.capacity {
+                self.evict();
+            }
+            self.chunks.insert(index, (0, chunk));
+        }
+
+        let (last_used, chunk) = self.chunks.get_mut(&index).expect("inserted above");
+        *last_used = self.tick;
+        Ok(chunk)
+    }
+
+    /// Evicts the least recently used chunk.
+    fn evict(&mut self) {
+        let lru = self
+            .chunks
+            .iter()
+            .min_by_key(|(_, (last_used, _))| *last_used)
+            .map(|(index, _)| *index);
+        if let Some((_, mut chunk)) = lru.and_then(|index| self.chunks.remove(&index)) {
+            chunk.zeroize();
+        }
+    }
+}
+
+impl<R> Drop for ChunkCache<R> {
+    fn drop(&mut self) {
+        for (_, chunk) in self.chunks.values_mut() {
+            chunk.zeroize();
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for ChunkCache<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let index = self.pos / CHUNK_SIZE as u64;
+        let offset = (self.pos % CHUNK_SIZE as u64) as usize;
+        let chunk = self.chunk(index)?;
+
+        let available = chunk.get(offset..).unwrap_or_default();
+        let n = usize::min(buf.len(), available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for ChunkCache<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => self.len as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.pos as i128 + offset as i128,
+        };
+        if target < 0 || target > u64::MAX as i128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        }
+
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
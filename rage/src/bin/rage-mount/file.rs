@@ -0,0 +1,189 @@
+use fuse_mt::*;
+use std::ffi::OsString;
+use std::fs::Metadata;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::cache::Stream;
+
+const TTL: Duration = Duration::from_secs(1);
+
+/// A filesystem containing a single read-only file, which is the plaintext of an age
+/// file.
+///
+/// Reads are served by seeking within the age file, so only the chunks containing the
+/// requested bytes are decrypted.
+pub struct AgeFileFs {
+    inner: Mutex<Stream>,
+    destroy_tx: mpsc::SyncSender<()>,
+    name: OsString,
+    root_attr: FileAttr,
+    file_attr: FileAttr,
+}
+
+impl AgeFileFs {
+    pub fn open(
+        mut stream: Stream,
+        name: OsString,
+        metadata: Metadata,
+        destroy_tx: mpsc::SyncSender<()>,
+    ) -> io::Result<Self> {
+        // Seeking to the end authenticates the length of the plaintext.
+        let size = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(0))?;
+
+        let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let atime = metadata.accessed().unwrap_or(mtime);
+        let ctime = SystemTime::UNIX_EPOCH + Duration::new(metadata.ctime().max(0) as u64, 0);
+        let root_attr = FileAttr {
+            size: 0,
+            blocks: 0,
+            atime,
+            mtime,
+            ctime,
+            crtime: SystemTime::UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o0555,
+            nlink: 2,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            rdev: 0,
+            flags: 0,
+        };
+        let file_attr = FileAttr {
+            size,
+            blocks: (size + 511) / 512,
+            kind: FileType::RegularFile,
+            perm: 0o0444,
+            nlink: 1,
+            ..root_attr
+        };
+
+        Ok(AgeFileFs {
+            inner: Mutex::new(stream),
+            destroy_tx,
+            name,
+            root_attr,
+            file_attr,
+        })
+    }
+
+    fn is_root(path: &Path) -> bool {
+        path == Path::new("/")
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.strip_prefix("/")
+            .map(|name| name == Path::new(&self.name))
+            .unwrap_or(false)
+    }
+}
+
+impl FilesystemMT for AgeFileFs {
+    fn destroy(&self) {
+        self.destroy_tx
+            .send(())
+            .expect("Could not send signal on channel.");
+    }
+
+    fn getattr(&self, _req: RequestInfo, path: &Path, _fh: Option<u64>) -> ResultEntry {
+        if Self::is_root(path) {
+            Ok((TTL, self.root_attr))
+        } else if self.is_file(path) {
+            Ok((TTL, self.file_attr))
+        } else {
+            Err(libc::ENOENT)
+        }
+    }
+
+    fn opendir(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+        if Self::is_root(path) {
+            Ok((0, 0))
+        } else {
+            Err(libc::ENOTDIR)
+        }
+    }
+
+    fn readdir(&self, _req: RequestInfo, path: &Path, _fh: u64) -> ResultReaddir {
+        if Self::is_root(path) {
+            Ok(vec![DirectoryEntry {
+                name: self.name.clone(),
+                kind: FileType::RegularFile,
+            }])
+        } else {
+            Err(libc::ENOTDIR)
+        }
+    }
+
+    fn releasedir(&self, _req: RequestInfo, _path: &Path, _fh: u64, _flags: u32) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn statfs(&self, _req: RequestInfo, _path: &Path) -> ResultStatfs {
+        Ok(Statfs {
+            blocks: self.file_attr.blocks,
+            bfree: 0,
+            bavail: 0,
+            files: 1,
+            ffree: 0,
+            bsize: 512,
+            namelen: u32::max_value(),
+            frsize: 512,
+        })
+    }
+
+    fn open(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+        if self.is_file(path) {
+            Ok((0, 0))
+        } else {
+            Err(libc::ENOENT)
+        }
+    }
+
+    fn read(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        _fh: u64,
+        offset: u64,
+        size: u32,
+        callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult,
+    ) -> CallbackResult {
+        if !self.is_file(path) {
+            return callback(Err(libc::ENOENT));
+        }
+        if offset > self.file_attr.size {
+            return callback(Err(libc::EINVAL));
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        // Skip to offset
+        if inner.seek(SeekFrom::Start(offset)).is_err() {
+            return callback(Err(libc::EIO));
+        }
+
+        // Read bytes
+        let to_read = u64::min(size as u64, self.file_attr.size - offset) as usize;
+        let mut buf = vec![0; to_read];
+        match inner.read_exact(&mut buf) {
+            Ok(_) => callback(Ok(&buf)),
+            Err(_) => callback(Err(libc::EIO)),
+        }
+    }
+
+    fn release(
+        &self,
+        _req: RequestInfo,
+        _path: &Path,
+        _fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+    ) -> ResultEmpty {
+        Ok(())
+    }
+}
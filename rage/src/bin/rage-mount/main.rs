@@ -2,7 +2,10 @@
 
 use age::{
     armor::ArmoredReader,
-    cli_common::{read_identities, read_secret},
+    cli_common::{
+        file_io::{OutputFormat, OutputWriter},
+        read_identities, read_secret,
+    },
     stream::StreamReader,
 };
 use fuse_mt::FilesystemMT;
@@ -10,18 +13,27 @@ use fuser::MountOption;
 use gumdrop::Options;
 use i18n_embed::{
     fluent::{fluent_language_loader, FluentLanguageLoader},
+    unic_langid::LanguageIdentifier,
     DesktopLanguageRequester,
 };
 use lazy_static::lazy_static;
 use log::info;
 use rust_embed::RustEmbed;
 
+use std::ffi::OsString;
 use std::fmt;
-use std::fs::File;
-use std::io;
+use std::fs::{File, Metadata};
+use std::io::{self, Write};
+use std::path::Path;
 use std::sync::mpsc;
 
+mod cache;
+#[cfg(feature = "cli-docs")]
+#[path = "../../cli.rs"]
+mod cli;
+mod file;
 mod tar;
+mod union;
 mod zip;
 
 #[derive(RustEmbed)]
@@ -56,10 +68,12 @@ enum Error {
     Age(age::DecryptError),
     IdentityRead(age::cli_common::ReadError),
     Io(io::Error),
+    ListAndExtract,
     MissingFilename,
     MissingIdentities,
     MissingMountpoint,
     MissingType,
+    NotInArchive(String),
     UnknownType(String),
 }
 
@@ -81,8 +95,39 @@ impl From<io::Error> for Error {
     }
 }
 
-// Rust only supports `fn main() -> Result<(), E: Debug>`, so we implement `Debug`
-// manually to provide the error output we want.
+/// Exit code for invalid flags, as for `rage`.
+const EXIT_USAGE: i32 = 2;
+
+impl Error {
+    /// Returns the exit code for this error, using the same classes of failure as
+    /// `rage`.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::Age(
+                age::DecryptError::DecryptionFailed
+                | age::DecryptError::InvalidHeader
+                | age::DecryptError::InvalidStanza(_)
+                | age::DecryptError::InvalidMac
+                | age::DecryptError::UnknownFormat,
+            ) => 4,
+            Error::Age(age::DecryptError::KeyDecryptionFailed) => 6,
+            Error::Age(
+                age::DecryptError::NoMatchingKeys | age::DecryptError::ThresholdNotMet { .. },
+            ) => 5,
+            Error::Age(age::DecryptError::Io(_)) | Error::Io(_) => 3,
+            Error::ListAndExtract
+            | Error::MissingFilename
+            | Error::MissingIdentities
+            | Error::MissingMountpoint
+            | Error::MissingType
+            | Error::UnknownType(_) => EXIT_USAGE,
+            _ => 1,
+        }
+    }
+}
+
+// `main` prints errors with `Debug` (like `fn main() -> Result<(), E: Debug>` would),
+// so we implement `Debug` manually to provide the error output we want.
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -103,6 +148,7 @@ impl fmt::Debug for Error {
             },
             Error::IdentityRead(e) => write!(f, "{}", e),
             Error::Io(e) => write!(f, "{}", e),
+            Error::ListAndExtract => wfl!(f, "err-mnt-list-extract"),
             Error::MissingFilename => wfl!(f, "err-mnt-missing-filename"),
             Error::MissingIdentities => {
                 wlnfl!(f, "err-dec-missing-identities")?;
@@ -110,6 +156,15 @@ impl fmt::Debug for Error {
             }
             Error::MissingMountpoint => wfl!(f, "err-mnt-missing-mountpoint"),
             Error::MissingType => wfl!(f, "err-mnt-missing-types"),
+            Error::NotInArchive(path) => write!(
+                f,
+                "{}",
+                i18n_embed_fl::fl!(
+                    LANGUAGE_LOADER,
+                    "err-mnt-not-in-archive",
+                    path = path.as_str()
+                )
+            ),
             Error::UnknownType(t) => write!(
                 f,
                 "{}",
@@ -132,42 +187,104 @@ impl fmt::Debug for Error {
 }
 
 #[derive(Debug, Options)]
+#[cfg_attr(feature = "cli-docs", derive(clap::Parser))]
+#[cfg_attr(
+    feature = "cli-docs",
+    clap(
+        name = "rage-mount",
+        about = "Mount an age-encrypted filesystem",
+        disable_help_flag = true,
+        disable_version_flag = true
+    )
+)]
 struct AgeMountOptions {
-    #[options(free, help = "The encrypted filesystem to mount.")]
-    filename: String,
-
-    #[options(free, help = "The directory to mount the filesystem at.")]
-    mountpoint: String,
+    /// The encrypted filesystems to mount, followed by the directory to mount them at.
+    #[options(free)]
+    #[cfg_attr(feature = "cli-docs", clap(value_hint = clap::ValueHint::AnyPath))]
+    paths: Vec<String>,
 
-    #[options(help = "Print this help message and exit.")]
+    /// Print this help message and exit.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     help: bool,
 
-    #[options(help = "Print version info and exit.", short = "V")]
+    /// Print version info and exit.
+    #[options(short = "V")]
+    #[cfg_attr(feature = "cli-docs", clap(short = 'V', long))]
     version: bool,
 
-    #[options(help = "Indicates the filesystem type (one of \"tar\", \"zip\").")]
+    /// Show messages in LANG (such as es-AR) instead of the system language.
+    #[options(meta = "LANG", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "LANG"))]
+    lang: Option<LanguageIdentifier>,
+
+    /// Indicates the filesystem type (one of "tar", "zip", "file").
+    #[cfg_attr(feature = "cli-docs", clap(short, long, required = false))]
     types: String,
 
-    #[options(
-        help = "Maximum work factor to allow for passphrase decryption.",
-        meta = "WF",
-        no_short
-    )]
+    /// Maximum work factor to allow for passphrase decryption.
+    #[options(meta = "WF", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "WF"))]
     max_work_factor: Option<u8>,
 
-    #[options(help = "Use the private key file at IDENTITY. May be repeated.")]
+    /// Use the private key file at IDENTITY. May be repeated.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     identity: Vec<String>,
+
+    /// List the files in each filesystem instead of mounting them.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    list: bool,
+
+    /// Write the file at PATH in the filesystem to OUTPUT instead of mounting it.
+    #[options(meta = "PATH", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "PATH"))]
+    extract: Option<String>,
+
+    /// Write the extracted file to OUTPUT. Defaults to standard output.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
+    output: Option<String>,
+
+    /// Cache up to SIZE MiB of decrypted data from each filesystem (default: 64).
+    #[options(meta = "SIZE", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "SIZE"))]
+    cache_size: Option<usize>,
+}
+
+/// A decrypted age file to mount.
+struct Layer {
+    stream: StreamReader<ArmoredReader<io::BufReader<File>>>,
+    name: OsString,
+    metadata: Metadata,
 }
 
 fn mount_fs<T: FilesystemMT + Send + Sync + 'static, F>(
     open: F,
+    layers: Vec<Layer>,
     mountpoint: String,
     finished: mpsc::Receiver<()>,
 ) -> Result<(), Error>
 where
-    F: FnOnce() -> io::Result<T>,
+    F: FnMut(Layer) -> io::Result<T>,
 {
-    let fs = open().map(|fs| fuse_mt::FuseMT::new(fs, 1))?;
+    let mut layers = layers
+        .into_iter()
+        .map(open)
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Later filesystems shadow earlier ones.
+    if layers.len() == 1 {
+        serve_fs(layers.pop().expect("checked"), mountpoint, finished)
+    } else {
+        serve_fs(union::UnionFs::new(layers), mountpoint, finished)
+    }
+}
+
+fn serve_fs<T: FilesystemMT + Send + Sync + 'static>(
+    fs: T,
+    mountpoint: String,
+    finished: mpsc::Receiver<()>,
+) -> Result<(), Error> {
+    let fs = fuse_mt::FuseMT::new(fs, 1);
     info!("{}", fl!("info-mounting-as-fuse"));
 
     // Mount the filesystem.
@@ -182,26 +299,106 @@ where
     Ok(())
 }
 
-fn mount_stream(
-    stream: StreamReader<ArmoredReader<io::BufReader<File>>>,
+/// Returns the name for the plaintext of the age file at `filename`, when mounted as a
+/// single file.
+fn plaintext_name(filename: &str) -> OsString {
+    let path = Path::new(filename);
+    let name = match path.extension() {
+        Some(ext) if ext == "age" => path.file_stem(),
+        _ => path.file_name(),
+    };
+    name.map(|name| name.to_owned())
+        .unwrap_or_else(|| "plaintext".into())
+}
+
+fn list_layers(layers: Vec<Layer>, cache_size: usize, types: String) -> Result<(), Error> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    for layer in layers {
+        let stream = cache::ChunkCache::new(layer.stream, cache_size)?;
+        match types.as_str() {
+            "tar" => crate::tar::list(stream, &mut out)?,
+            "zip" => crate::zip::list(stream, &mut out)?,
+            "file" => writeln!(out, "{}", Path::new(&layer.name).display())?,
+            _ => return Err(Error::UnknownType(types)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Extracts the file at `path` from the last filesystem that contains it, as if they
+/// were mounted together.
+fn extract_layers(
+    layers: Vec<Layer>,
+    cache_size: usize,
+    types: String,
+    path: String,
+    output: Option<String>,
+) -> Result<(), Error> {
+    let mut output = OutputWriter::new(output, OutputFormat::Unknown, 0o666, false)?;
+
+    for layer in layers.into_iter().rev() {
+        let mut stream = cache::ChunkCache::new(layer.stream, cache_size)?;
+        let found = match types.as_str() {
+            "tar" => crate::tar::extract(stream, &path, &mut output)?,
+            "zip" => crate::zip::extract(stream, &path, &mut output)?,
+            "file" if Path::new(&layer.name) == Path::new(path.trim_start_matches('/')) => {
+                io::copy(&mut stream, &mut output)?;
+                true
+            }
+            "file" => false,
+            _ => return Err(Error::UnknownType(types)),
+        };
+        if found {
+            output.commit()?;
+            return Ok(());
+        }
+    }
+
+    Err(Error::NotInArchive(path))
+}
+
+fn mount_layers(
+    layers: Vec<Layer>,
+    cache_size: usize,
     types: String,
     mountpoint: String,
 ) -> Result<(), Error> {
     // We want to block until either Ctrl-C, or the filesystem is unmounted externally.
-    // Set up a channel for notifying the main thread that we should exit.
-    let (tx, finished) = mpsc::sync_channel(2);
+    // Set up a channel for notifying the main thread that we should exit. Each layer
+    // sends on it when the filesystem is destroyed.
+    let (tx, finished) = mpsc::sync_channel(1 + layers.len());
     let destroy_tx = tx.clone();
     ctrlc::set_handler(move || tx.send(()).expect("Could not send signal on channel."))
         .expect("Error setting Ctrl-C handler");
 
     match types.as_str() {
         "tar" => mount_fs(
-            || crate::tar::AgeTarFs::open(stream, destroy_tx),
+            |layer| {
+                let stream = cache::ChunkCache::new(layer.stream, cache_size)?;
+                crate::tar::AgeTarFs::open(stream, destroy_tx.clone())
+            },
+            layers,
             mountpoint,
             finished,
         ),
         "zip" => mount_fs(
-            || crate::zip::AgeZipFs::open(stream, destroy_tx),
+            |layer| {
+                let stream = cache::ChunkCache::new(layer.stream, cache_size)?;
+                crate::zip::AgeZipFs::open(stream, destroy_tx.clone())
+            },
+            layers,
+            mountpoint,
+            finished,
+        ),
+        "file" => mount_fs(
+            |layer| {
+                let stream = cache::ChunkCache::new(layer.stream, cache_size)?;
+                crate::file::AgeFileFs::open(stream, layer.name, layer.metadata, destroy_tx.clone())
+            },
+            layers,
             mountpoint,
             finished,
         ),
@@ -209,7 +406,14 @@ fn mount_stream(
     }
 }
 
-fn main() -> Result<(), Error> {
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
     use std::env::args;
 
     env_logger::builder()
@@ -227,6 +431,11 @@ fn main() -> Result<(), Error> {
 
     let args = args().collect::<Vec<_>>();
 
+    #[cfg(feature = "cli-docs")]
+    if cli::handle::<AgeMountOptions>(&args, EXIT_USAGE) {
+        return Ok(());
+    }
+
     if console::user_attended() && args.len() == 1 {
         // If gumdrop ever merges that PR, that can be used here
         // instead.
@@ -239,54 +448,83 @@ fn main() -> Result<(), Error> {
 
     let opts = AgeMountOptions::parse_args_default_or_exit();
 
+    if let Some(language) = &opts.lang {
+        // Override the languages requested by the environment.
+        let languages = [language.clone()];
+        i18n_embed::select(&*LANGUAGE_LOADER, &TRANSLATIONS, &languages).unwrap();
+        age::localizer().select(&languages).unwrap();
+    }
+
     if opts.version {
         println!("rage-mount {}", env!("CARGO_PKG_VERSION"));
         return Ok(());
     }
-    if opts.filename.is_empty() {
-        return Err(Error::MissingFilename);
-    }
-    if opts.mountpoint.is_empty() {
-        return Err(Error::MissingMountpoint);
+    if opts.list && opts.extract.is_some() {
+        return Err(Error::ListAndExtract);
     }
+
+    let mut filenames = opts.paths;
+    let mountpoint = match (filenames.len(), opts.list || opts.extract.is_some()) {
+        (0, _) => return Err(Error::MissingFilename),
+        (_, true) => None,
+        (1, false) => return Err(Error::MissingMountpoint),
+        (_, false) => filenames.pop(),
+    };
     if opts.types.is_empty() {
         return Err(Error::MissingType);
     }
 
-    info!(
-        "{}",
-        i18n_embed_fl::fl!(
-            LANGUAGE_LOADER,
-            "info-decrypting",
-            filename = opts.filename.as_str()
-        )
-    );
-    let file = File::open(opts.filename)?;
-
-    let types = opts.types;
-    let mountpoint = opts.mountpoint;
-
-    match age::Decryptor::new(ArmoredReader::new(file))? {
-        age::Decryptor::Passphrase(decryptor) => {
-            match read_secret(&fl!("type-passphrase"), &fl!("prompt-passphrase"), None) {
-                Ok(passphrase) => decryptor
-                    .decrypt(&passphrase, opts.max_work_factor)
-                    .map_err(|e| e.into())
-                    .and_then(|stream| mount_stream(stream, types, mountpoint)),
-                Err(_) => Ok(()),
+    let mut identities = None;
+    let mut layers = Vec::with_capacity(filenames.len());
+    for filename in &filenames {
+        info!(
+            "{}",
+            i18n_embed_fl::fl!(
+                LANGUAGE_LOADER,
+                "info-decrypting",
+                filename = filename.as_str()
+            )
+        );
+        let file = File::open(filename)?;
+        let metadata = file.metadata()?;
+        let name = plaintext_name(filename);
+
+        let stream = match age::Decryptor::new(ArmoredReader::new(file))? {
+            age::Decryptor::Passphrase(decryptor) => {
+                match read_secret(&fl!("type-passphrase"), &fl!("prompt-passphrase"), None) {
+                    Ok(passphrase) => decryptor.decrypt(&passphrase, opts.max_work_factor)?,
+                    Err(_) => return Ok(()),
+                }
             }
-        }
-        age::Decryptor::Recipients(decryptor) => {
-            let identities = read_identities(opts.identity, opts.max_work_factor)?;
+            age::Decryptor::Recipients(decryptor) => {
+                // Read the identities once, for the first file that needs them.
+                if identities.is_none() {
+                    identities = Some(read_identities(
+                        opts.identity.clone(),
+                        opts.max_work_factor,
+                    )?);
+                }
+                let identities = identities.as_ref().expect("set above");
 
-            if identities.is_empty() {
-                return Err(Error::MissingIdentities);
+                if identities.is_empty() {
+                    return Err(Error::MissingIdentities);
+                }
+
+                decryptor.decrypt(identities.iter().map(|i| &**i))?
             }
+        };
 
-            decryptor
-                .decrypt(identities.iter().map(|i| &**i))
-                .map_err(|e| e.into())
-                .and_then(|stream| mount_stream(stream, types, mountpoint))
-        }
+        layers.push(Layer {
+            stream,
+            name,
+            metadata,
+        });
+    }
+
+    let cache_size = opts.cache_size.unwrap_or(cache::DEFAULT_CACHE_SIZE);
+    match (mountpoint, opts.extract) {
+        (Some(mountpoint), _) => mount_layers(layers, cache_size, opts.types, mountpoint),
+        (None, Some(path)) => extract_layers(layers, cache_size, opts.types, path, opts.output),
+        (None, None) => list_layers(layers, cache_size, opts.types),
     }
 }
@@ -1,14 +1,14 @@
-use age::{armor::ArmoredReader, stream::StreamReader};
 use fuse_mt::*;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
-use std::path::{Path, PathBuf};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{mpsc, Mutex};
 use std::time::{Duration, SystemTime};
 
 use tar::{Archive, Entry, EntryType};
 
+use crate::cache::Stream;
+
 fn tar_path(path: &Path) -> &Path {
     path.strip_prefix("/").unwrap()
 }
@@ -102,10 +102,53 @@ fn add_to_dir_map(
         .push(DirectoryEntry { name, kind });
 }
 
+/// Writes the path of each entry in the archive to `out`, one per line.
+///
+/// This seeks past the contents of each entry, so only the chunks of the age file that
+/// contain headers are decrypted.
+pub fn list(stream: Stream, out: &mut impl Write) -> io::Result<()> {
+    let mut archive = Archive::new(stream);
+    for file in archive.entries_with_seek()? {
+        let file = file?;
+        if tar_to_filetype(&file).is_some() {
+            writeln!(out, "{}", file.path()?.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `path` without `.` or root components, so that `./a`, `/a` and `a` compare
+/// equal.
+fn normalized(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir | Component::RootDir))
+        .collect()
+}
+
+/// Writes the contents of the regular file at `path` in the archive to `out`, and
+/// returns whether the archive contains it.
+///
+/// Like [`list`], this seeks past the contents of every other entry.
+pub fn extract(stream: Stream, path: &str, out: &mut impl Write) -> io::Result<bool> {
+    let path = normalized(Path::new(path));
+
+    let mut archive = Archive::new(stream);
+    for file in archive.entries_with_seek()? {
+        let mut file = file?;
+        if matches!(tar_to_filetype(&file), Some(FileType::RegularFile))
+            && normalized(&file.path()?) == path
+        {
+            io::copy(&mut file, out)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 type OpenFile = (PathBuf, u64, u64);
 
 pub struct AgeTarFs {
-    inner: Mutex<StreamReader<ArmoredReader<BufReader<File>>>>,
+    inner: Mutex<Stream>,
     destroy_tx: mpsc::SyncSender<()>,
     dir_map: HashMap<PathBuf, Vec<DirectoryEntry>>,
     file_map: HashMap<PathBuf, (FileAttr, u64)>,
@@ -114,10 +157,7 @@ pub struct AgeTarFs {
 }
 
 impl AgeTarFs {
-    pub fn open(
-        stream: StreamReader<ArmoredReader<BufReader<File>>>,
-        destroy_tx: mpsc::SyncSender<()>,
-    ) -> io::Result<Self> {
+    pub fn open(stream: Stream, destroy_tx: mpsc::SyncSender<()>) -> io::Result<Self> {
         // Build a directory listing for the archive
         let mut dir_map: HashMap<PathBuf, Vec<DirectoryEntry>> = HashMap::new();
         dir_map.insert(PathBuf::new(), vec![]); // the root
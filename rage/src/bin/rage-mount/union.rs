@@ -0,0 +1,170 @@
+use fuse_mt::*;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The open file handles, mapped to the layer that opened each file and the handle that
+/// layer returned, along with the next handle to hand out.
+type OpenFiles = (HashMap<u64, (usize, u64)>, u64);
+
+/// A read-only union of several filesystems, in which each path is served by the last
+/// layer that contains it.
+///
+/// This lets a full backup and the incremental backups taken after it be mounted as a
+/// single tree. Layers can only add or replace files; a later layer can't remove a
+/// file that is present in an earlier one.
+pub struct UnionFs<T> {
+    layers: Vec<T>,
+    open_dirs: Mutex<(HashMap<u64, PathBuf>, u64)>,
+    open_files: Mutex<OpenFiles>,
+}
+
+impl<T: FilesystemMT> UnionFs<T> {
+    pub fn new(layers: Vec<T>) -> Self {
+        UnionFs {
+            layers,
+            open_dirs: Mutex::new((HashMap::new(), 0)),
+            open_files: Mutex::new((HashMap::new(), 0)),
+        }
+    }
+
+    /// Returns the index of the last layer containing `path`.
+    fn top_layer(&self, req: RequestInfo, path: &Path) -> Option<usize> {
+        self.layers
+            .iter()
+            .rposition(|layer| layer.getattr(req, path, None).is_ok())
+    }
+}
+
+impl<T: FilesystemMT> FilesystemMT for UnionFs<T> {
+    fn destroy(&self) {
+        for layer in &self.layers {
+            layer.destroy();
+        }
+    }
+
+    fn getattr(&self, req: RequestInfo, path: &Path, fh: Option<u64>) -> ResultEntry {
+        if let Some(fh) = fh {
+            let open_files = self.open_files.lock().unwrap();
+            if let Some((layer, fh)) = open_files.0.get(&fh) {
+                return self.layers[*layer].getattr(req, path, Some(*fh));
+            } else if !self.open_dirs.lock().unwrap().0.contains_key(&fh) {
+                return Err(libc::EBADF);
+            }
+        }
+
+        let layer = self.top_layer(req, path).ok_or(libc::ENOENT)?;
+        self.layers[layer].getattr(req, path, None)
+    }
+
+    fn opendir(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+        let mut open_dirs = self.open_dirs.lock().unwrap();
+
+        let fh = open_dirs.1;
+
+        open_dirs.0.insert(fh, path.to_path_buf());
+        open_dirs.1 = open_dirs.1.wrapping_add(1);
+
+        Ok((fh, 0))
+    }
+
+    fn readdir(&self, req: RequestInfo, _path: &Path, fh: u64) -> ResultReaddir {
+        let path = match self.open_dirs.lock().unwrap().0.get(&fh) {
+            Some(path) => path.clone(),
+            None => return Err(libc::EBADF),
+        };
+
+        // Merge the listings of every layer in which this is a directory, with later
+        // layers replacing the entries of earlier ones.
+        let mut entries: Vec<DirectoryEntry> = vec![];
+        let mut positions: HashMap<OsString, usize> = HashMap::new();
+        for layer in &self.layers {
+            match layer.getattr(req, &path, None) {
+                Ok((_, attr)) if matches!(attr.kind, FileType::Directory) => (),
+                _ => continue,
+            }
+
+            let (layer_fh, _) = layer.opendir(req, &path, 0)?;
+            let listing = layer.readdir(req, &path, layer_fh);
+            let _ = layer.releasedir(req, &path, layer_fh, 0);
+
+            for entry in listing? {
+                match positions.get(&entry.name) {
+                    Some(&i) => entries[i] = entry,
+                    None => {
+                        positions.insert(entry.name.clone(), entries.len());
+                        entries.push(entry);
+                    }
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn releasedir(&self, _req: RequestInfo, _path: &Path, fh: u64, _flags: u32) -> ResultEmpty {
+        let mut open_dirs = self.open_dirs.lock().unwrap();
+
+        open_dirs.0.remove(&fh).map(|_| ()).ok_or(libc::EBADF)
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &Path) -> ResultStatfs {
+        let mut statfs = self.layers[0].statfs(req, path)?;
+        for layer in &self.layers[1..] {
+            let layer = layer.statfs(req, path)?;
+            statfs.blocks += layer.blocks;
+            statfs.files += layer.files;
+        }
+        Ok(statfs)
+    }
+
+    fn open(&self, req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
+        let layer = self.top_layer(req, path).ok_or(libc::ENOENT)?;
+        let (layer_fh, open_flags) = self.layers[layer].open(req, path, flags)?;
+
+        let mut open_files = self.open_files.lock().unwrap();
+
+        let fh = open_files.1;
+        open_files.0.insert(fh, (layer, layer_fh));
+        open_files.1 = open_files.1.wrapping_add(1);
+
+        Ok((fh, open_flags))
+    }
+
+    fn read(
+        &self,
+        req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult,
+    ) -> CallbackResult {
+        let open_file = self.open_files.lock().unwrap().0.get(&fh).copied();
+
+        match open_file {
+            Some((layer, fh)) => self.layers[layer].read(req, path, fh, offset, size, callback),
+            None => callback(Err(libc::EBADF)),
+        }
+    }
+
+    fn release(
+        &self,
+        req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> ResultEmpty {
+        let open_file = self.open_files.lock().unwrap().0.remove(&fh);
+
+        match open_file {
+            Some((layer, fh)) => {
+                self.layers[layer].release(req, path, fh, flags, lock_owner, flush)
+            }
+            None => Err(libc::EBADF),
+        }
+    }
+}
@@ -1,17 +1,64 @@
-use age::{armor::ArmoredReader, stream::StreamReader};
 use fuse_mt::*;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufReader, Read};
-use std::path::{Path, PathBuf};
-use std::sync::{mpsc, Mutex};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::{Duration, SystemTime};
-use zip::{read::ZipFile, ZipArchive};
+use zip::{read::ZipFile, CompressionMethod, ZipArchive};
+
+use crate::cache::Stream;
+
+/// A handle to the decrypted stream that is shared with the [`ZipArchive`], so that
+/// entries stored without compression can be read by seeking straight to them.
+#[derive(Clone)]
+struct SharedStream(Arc<Mutex<Stream>>);
+
+impl Read for SharedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl Seek for SharedStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.lock().unwrap().seek(pos)
+    }
+}
 
 fn zip_path(path: &Path) -> &Path {
     path.strip_prefix("/").unwrap()
 }
 
+/// Returns the path at which an archive entry is mounted, or `None` if it would be
+/// outside the mountpoint.
+///
+/// This is [`ZipFile::enclosed_name`] with `.` and `..` components resolved, but it
+/// only needs the name from the central directory, so that mounting doesn't have to
+/// read the local header of every entry.
+fn enclosed_path(name: &str) -> Option<PathBuf> {
+    if name.contains('\0') {
+        return None;
+    }
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::ParentDir => {
+                if !path.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => (),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
 fn zipfile_to_filetype(zf: &ZipFile) -> FileType {
     if zf.is_dir() {
         FileType::Directory
@@ -83,42 +130,123 @@ fn add_dir_to_map(
         .push(DirectoryEntry { name, kind });
 }
 
+/// Writes the name of each entry in the archive to `out`, one per line.
+///
+/// The names are read from the central directory, so none of the entries are
+/// decrypted.
+pub fn list(stream: Stream, out: &mut impl Write) -> io::Result<()> {
+    let archive = ZipArchive::new(stream).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut names: Vec<_> = archive.file_names().collect();
+    names.sort_unstable();
+    for name in names {
+        writeln!(out, "{}", name)?;
+    }
+    Ok(())
+}
+
+/// Writes the contents of the file at `path` in the archive to `out`, and returns
+/// whether the archive contains it.
+///
+/// Only the central directory and the chunks of the age file containing the entry are
+/// decrypted.
+pub fn extract(stream: Stream, path: &str, out: &mut impl Write) -> io::Result<bool> {
+    let path = match enclosed_path(path.trim_start_matches('/')) {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+
+    let mut archive =
+        ZipArchive::new(stream).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let name = archive
+        .file_names()
+        .find(|name| {
+            !(name.ends_with('/') || name.ends_with('\\'))
+                && enclosed_path(name).as_ref() == Some(&path)
+        })
+        .map(|name| name.to_owned());
+
+    match name {
+        Some(name) => {
+            let mut zf = archive
+                .by_name(&name)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            io::copy(&mut zf, out)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The name of an open file in the archive, its attributes, and where its data starts
+/// in the decrypted stream if it is stored without compression.
+type OpenFile = (String, FileAttr, Option<u64>);
+
 pub struct AgeZipFs {
-    inner: Mutex<ZipArchive<StreamReader<ArmoredReader<BufReader<File>>>>>,
+    inner: Mutex<ZipArchive<SharedStream>>,
+    stream: SharedStream,
     destroy_tx: mpsc::SyncSender<()>,
     dir_map: HashMap<PathBuf, Vec<DirectoryEntry>>,
+    file_map: HashMap<PathBuf, String>,
+    attrs: Mutex<HashMap<PathBuf, FileAttr>>,
     open_dirs: Mutex<(HashMap<u64, PathBuf>, u64)>,
-    open_files: Mutex<(HashMap<u64, usize>, u64)>,
+    open_files: Mutex<(HashMap<u64, OpenFile>, u64)>,
 }
 
 impl AgeZipFs {
-    pub fn open(
-        stream: StreamReader<ArmoredReader<BufReader<File>>>,
-        destroy_tx: mpsc::SyncSender<()>,
-    ) -> io::Result<Self> {
-        let mut archive =
-            ZipArchive::new(stream).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-        // Build a directory listing for the archive
+    pub fn open(stream: Stream, destroy_tx: mpsc::SyncSender<()>) -> io::Result<Self> {
+        let stream = SharedStream(Arc::new(Mutex::new(stream)));
+
+        // This reads the central directory (including the ZIP64 end of central
+        // directory record, for archives over 4 GiB or with more than 65535 entries),
+        // but not the entries themselves.
+        let archive =
+            ZipArchive::new(stream.clone()).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        // Build a directory listing and a file map for the archive from the names in
+        // the central directory. Entry attributes need the local headers, which are
+        // only read when the entry is first looked at.
         let mut dir_map: HashMap<PathBuf, Vec<DirectoryEntry>> = HashMap::new();
         dir_map.insert(PathBuf::new(), vec![]); // the root
-        for i in 0..archive.len() {
-            let zf = archive
-                .by_index(i)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            if let Some(path) = zf.enclosed_name() {
-                add_dir_to_map(&mut dir_map, path, zipfile_to_filetype(&zf));
+        let mut file_map: HashMap<PathBuf, String> = HashMap::new();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort_unstable();
+        for name in names {
+            if let Some(path) = enclosed_path(name) {
+                if name.ends_with('/') || name.ends_with('\\') {
+                    if !dir_map.contains_key(&path) {
+                        add_dir_to_map(&mut dir_map, &path, FileType::Directory);
+                        dir_map.insert(path, vec![]);
+                    }
+                } else {
+                    add_dir_to_map(&mut dir_map, &path, FileType::RegularFile);
+                    file_map.insert(path, name.to_owned());
+                }
             }
         }
 
         Ok(AgeZipFs {
             inner: Mutex::new(archive),
+            stream,
             destroy_tx,
             dir_map,
+            file_map,
+            attrs: Mutex::new(HashMap::new()),
             open_dirs: Mutex::new((HashMap::new(), 0)),
             open_files: Mutex::new((HashMap::new(), 0)),
         })
     }
+
+    fn attr(&self, inner: &mut ZipArchive<SharedStream>, path: &Path) -> Option<FileAttr> {
+        let mut attrs = self.attrs.lock().unwrap();
+        if let Some(attr) = attrs.get(path) {
+            return Some(*attr);
+        }
+
+        let zf = inner.by_name(self.file_map.get(path)?).ok()?;
+        let attr = zipfile_to_fuse(&zf);
+        attrs.insert(path.to_path_buf(), attr);
+        Some(attr)
+    }
 }
 
 const TTL: Duration = Duration::from_secs(1);
@@ -138,18 +266,17 @@ impl FilesystemMT for AgeZipFs {
         if let Some(fh) = fh {
             if open_dirs.0.contains_key(&fh) {
                 Ok((TTL, DIR_ATTR))
-            } else if let Some(index) = open_files.0.get(&fh) {
-                let zf = inner.by_index(*index).expect("open_files is correct");
-                Ok((TTL, zipfile_to_fuse(&zf)))
+            } else if let Some((_, attr, _)) = open_files.0.get(&fh) {
+                Ok((TTL, *attr))
             } else {
                 Err(libc::EBADF)
             }
         } else if self.dir_map.contains_key(zip_path(path)) {
             Ok((TTL, DIR_ATTR))
         } else {
-            match inner.by_name(zip_path(path).to_str().unwrap()) {
-                Ok(zf) => Ok((TTL, zipfile_to_fuse(&zf))),
-                Err(_) => Err(libc::ENOENT),
+            match self.attr(&mut inner, zip_path(path)) {
+                Some(attr) => Ok((TTL, attr)),
+                None => Err(libc::ENOENT),
             }
         }
     }
@@ -201,16 +328,20 @@ impl FilesystemMT for AgeZipFs {
         let mut inner = self.inner.lock().unwrap();
         let mut open_files = self.open_files.lock().unwrap();
 
-        for i in 0..inner.len() {
-            if inner.by_index(i).unwrap().enclosed_name() == Some(zip_path(path)) {
-                let fh = open_files.1;
-                open_files.0.insert(fh, i);
-                open_files.1 = open_files.1.wrapping_add(1);
-                return Ok((fh, 0));
-            }
-        }
+        let path = zip_path(path);
+        let name = self.file_map.get(path).ok_or(libc::ENOENT)?;
+        let attr = self.attr(&mut inner, path).ok_or(libc::EIO)?;
+        let stored_at = {
+            let zf = inner.by_name(name).map_err(|_| libc::EIO)?;
+            // Looking the entry up has read its local header, so its data start is known.
+            (zf.compression() == CompressionMethod::Stored).then(|| zf.data_start())
+        };
+
+        let fh = open_files.1;
+        open_files.0.insert(fh, (name.clone(), attr, stored_at));
+        open_files.1 = open_files.1.wrapping_add(1);
 
-        Err(libc::ENOENT)
+        Ok((fh, 0))
     }
 
     fn read(
@@ -226,23 +357,33 @@ impl FilesystemMT for AgeZipFs {
         let open_files = self.open_files.lock().unwrap();
 
         match open_files.0.get(&fh) {
-            Some(index) => {
-                let mut zf = inner.by_index(*index).expect("open_files is correct");
-                if offset > zf.size() {
+            Some((name, attr, stored_at)) => {
+                if offset > attr.size {
                     return callback(Err(libc::EINVAL));
                 }
 
-                // Skip to offset
-                let mut buf = vec![];
-                buf.resize(offset as usize, 0);
-                if zf.read_exact(&mut buf).is_err() {
-                    return callback(Err(libc::EIO));
-                }
-
-                // Read bytes
-                let to_read = usize::min(size as usize, (zf.size() - offset) as usize);
-                buf.resize(to_read, 0);
-                match zf.read_exact(&mut buf) {
+                let to_read = u64::min(size as u64, attr.size - offset) as usize;
+                let mut buf = vec![0; to_read];
+
+                let res = match stored_at {
+                    // Seek straight to the data.
+                    Some(pos) => {
+                        let mut stream = self.stream.0.lock().unwrap();
+                        stream
+                            .seek(SeekFrom::Start(pos + offset))
+                            .and_then(|_| stream.read_exact(&mut buf))
+                    }
+                    // Decompress up to offset, and then read bytes.
+                    None => inner
+                        .by_name(name)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                        .and_then(|mut zf| {
+                            io::copy(&mut (&mut zf).take(offset), &mut io::sink())?;
+                            zf.read_exact(&mut buf)
+                        }),
+                };
+
+                match res {
                     Ok(_) => callback(Ok(&buf)),
                     Err(_) => callback(Err(libc::EIO)),
                 }
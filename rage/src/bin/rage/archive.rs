@@ -0,0 +1,36 @@
+//! Encrypting directory trees as tar archives.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Writes the directory tree at `dir` to `output` as a tar archive.
+///
+/// Entries are stored under the directory's name, so extracting the archive recreates
+/// the directory itself rather than spilling its contents into the destination.
+/// Symbolic links are stored as links, not followed.
+pub(crate) fn write<W: Write>(dir: &Path, output: W) -> io::Result<W> {
+    let name = match dir.file_name() {
+        Some(name) => name.to_owned(),
+        // `dir` is something like `.` or `..`; use the name it resolves to.
+        None => dir
+            .canonicalize()?
+            .file_name()
+            .map(|name| name.to_owned())
+            .unwrap_or_else(|| ".".into()),
+    };
+
+    let mut builder = tar::Builder::new(output);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(name, dir)?;
+    builder.into_inner()
+}
+
+/// Extracts the tar archive read from `input` into the directory `dest`, creating it if
+/// necessary.
+///
+/// Entries that would be written outside of `dest` are skipped.
+pub(crate) fn extract<R: Read>(input: R, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    tar::Archive::new(input).unpack(dest)
+}
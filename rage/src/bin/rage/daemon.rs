@@ -0,0 +1,228 @@
+//! `rage --daemon`: encryption and decryption for other local processes.
+//!
+//! The daemon reads its identities and recipients once, and then serves requests on a
+//! Unix domain socket, so that other processes can use them without each one reading
+//! the key files.
+//!
+//! # Protocol
+//!
+//! A message is a 4-byte big-endian length, followed by that many bytes. A client sends
+//! a request as a one-byte operation followed by a message:
+//! - `e`: encrypt the message to the daemon's recipients.
+//! - `d`: decrypt the message (an age file, which may be armored) with the daemon's
+//!   identities.
+//!
+//! The daemon replies with a one-byte status followed by a message: `0` and the output
+//! if the request succeeded, or `1` and a UTF-8 description if it failed. A client can
+//! send any number of requests over one connection, but the daemon serves one client at
+//! a time, and closes a connection that has been idle for [`CLIENT_TIMEOUT`].
+
+use age::{
+    armor::{ArmoredReader, Format},
+    Identity, Recipient,
+};
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{error::DaemonError, SharedRecipients};
+
+const OP_ENCRYPT: u8 = b'e';
+const OP_DECRYPT: u8 = b'd';
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERR: u8 = 1;
+
+/// The maximum length of a request message. Requests and responses are held in
+/// memory, so this limits how much memory a client can make the daemon use.
+const MAX_MESSAGE_LEN: u32 = 64 * 1024 * 1024;
+
+/// How long the daemon waits for a client to send or receive more data. Clients are
+/// served one at a time, so this stops one client from blocking the others.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) struct Daemon {
+    recipients: Option<SharedRecipients>,
+    identities: Vec<Box<dyn Identity>>,
+    armor: bool,
+}
+
+impl Daemon {
+    pub(crate) fn new(
+        recipients: Vec<Box<dyn Recipient + Send>>,
+        identities: Vec<Box<dyn Identity>>,
+        armor: bool,
+    ) -> Self {
+        Daemon {
+            recipients: (!recipients.is_empty()).then(|| SharedRecipients::new(recipients)),
+            identities,
+            armor,
+        }
+    }
+
+    /// Listens on the Unix domain socket at `path`, and serves clients one at a time.
+    ///
+    /// This only returns if the socket can't be created.
+    pub(crate) fn run(&self, path: &str) -> Result<(), DaemonError> {
+        let listener = bind(path)?;
+        eprintln!(
+            "{}",
+            i18n_embed_fl::fl!(
+                crate::LANGUAGE_LOADER,
+                "info-daemon-listening",
+                socket = path
+            )
+        );
+
+        for stream in listener.incoming() {
+            // A failed connection only affects that client.
+            if let Err(e) = stream.and_then(|stream| {
+                stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+                stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+                self.serve(&stream)
+            }) {
+                log::warn!("Connection failed: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serves requests from a client until it closes the connection.
+    fn serve(&self, stream: &UnixStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut writer = BufWriter::new(stream);
+
+        loop {
+            let mut op = [0];
+            match reader.read_exact(&mut op) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let request = read_message(&mut reader)?;
+
+            let response = match op[0] {
+                OP_ENCRYPT => self.encrypt(&request),
+                OP_DECRYPT => self.decrypt(&request),
+                op => Err(DaemonError::UnknownOperation(op)),
+            };
+
+            match response {
+                Ok(output) => write_message(&mut writer, STATUS_OK, &output)?,
+                Err(e) => write_message(&mut writer, STATUS_ERR, e.to_string().as_bytes())?,
+            }
+            writer.flush()?;
+        }
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, DaemonError> {
+        let recipients = self.recipients.clone().ok_or(DaemonError::NoRecipients)?;
+        let format = if self.armor {
+            Format::AsciiArmor
+        } else {
+            Format::Binary
+        };
+        let mut output = age::EncryptorBuilder::new()
+            .recipient(Box::new(recipients))
+            .armor(format)
+            .build()
+            .expect("we provided a recipient")
+            .wrap_armored_output(vec![])
+            .map_err(crate::error::EncryptError::from)?;
+        output.write_all(plaintext)?;
+        Ok(output.finish().and_then(|armor| armor.finish())?)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, DaemonError> {
+        if self.identities.is_empty() {
+            return Err(DaemonError::NoIdentities);
+        }
+
+        let decryptor = match age::Decryptor::new(ArmoredReader::new(ciphertext))
+            .map_err(crate::error::DecryptError::from)?
+        {
+            age::Decryptor::Recipients(decryptor) => decryptor,
+            age::Decryptor::Passphrase(_) => return Err(DaemonError::PassphraseEncrypted),
+        };
+
+        let mut reader = decryptor
+            .decrypt(self.identities.iter().map(|i| i.as_ref() as &dyn Identity))
+            .map_err(crate::error::DecryptError::from)?;
+        let mut plaintext = vec![];
+        reader.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+}
+
+/// Creates the socket at `path`, replacing a stale socket left by a daemon that is no
+/// longer running.
+fn bind(path: &str) -> Result<UnixListener, DaemonError> {
+    check_socket_dir(path)?;
+
+    let listener = match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => match UnixStream::connect(path) {
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            _ => return Err(DaemonError::SocketInUse(path.to_owned())),
+        },
+        res => res?,
+    };
+
+    // Anyone who can connect to the socket can use our identities.
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+/// Checks that the directory that will contain the socket at `path` is owned by us, and
+/// that no other user can access it.
+///
+/// The socket can only be restricted to us after it has been created, so until then it
+/// is only protected by its directory. This also stops other users from replacing it.
+fn check_socket_dir(path: &str) -> Result<(), DaemonError> {
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let metadata = fs::metadata(dir)?;
+    if metadata.uid() != nix::unistd::geteuid().as_raw() || metadata.mode() & 0o077 != 0 {
+        return Err(DaemonError::SocketDirExposed(dir.display().to_string()));
+    }
+
+    Ok(())
+}
+
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            i18n_embed_fl::fl!(
+                crate::LANGUAGE_LOADER,
+                "err-daemon-message-too-long",
+                max = MAX_MESSAGE_LEN
+            ),
+        ));
+    }
+
+    let mut message = vec![0; len as usize];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn write_message<W: Write>(writer: &mut W, status: u8, message: &[u8]) -> io::Result<()> {
+    // Responses are never much longer than MAX_MESSAGE_LEN.
+    let len = u32::try_from(message.len()).expect("response fits in a message");
+    writer.write_all(&[status])?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(message)
+}
@@ -0,0 +1,91 @@
+//! Default locations for identity files and configuration.
+
+use age::plugin::Allowlist;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+
+/// Returns the directory that `rage` reads its configuration from.
+///
+/// This is `$XDG_CONFIG_HOME/rage` (or `~/.config/rage`) on Unix, and `%APPDATA%\rage`
+/// on Windows.
+fn config_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base = env::var_os("APPDATA").map(PathBuf::from);
+
+    #[cfg(not(windows))]
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        // The XDG spec requires this to be an absolute path; ignore it otherwise.
+        .filter(|path| path.is_absolute())
+        .or_else(|| home_dir().map(|home| home.join(".config")));
+
+    base.map(|base| base.join("rage"))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let home = env::var_os("USERPROFILE");
+
+    #[cfg(not(windows))]
+    let home = env::var_os("HOME");
+
+    home.filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+/// A description of where the default identities file is, for the usage text.
+#[cfg(windows)]
+pub(crate) const IDENTITIES_FILE: &str = "%APPDATA%\\rage\\identities";
+#[cfg(not(windows))]
+pub(crate) const IDENTITIES_FILE: &str =
+    "$XDG_CONFIG_HOME/rage/identities (or ~/.config/rage/identities)";
+
+/// Returns the default identity files that exist, in the order they should be tried.
+pub(crate) fn identity_files() -> Vec<String> {
+    let candidates = [
+        config_dir().map(|dir| dir.join("identities")),
+        #[cfg(feature = "ssh")]
+        home_dir().map(|home| home.join(".ssh").join("id_ed25519")),
+    ];
+
+    candidates
+        .into_iter()
+        .flatten()
+        .filter(|path| path.is_file())
+        .filter_map(|path| path.into_os_string().into_string().ok())
+        .collect()
+}
+
+/// A description of where the plugin allowlist is, for the usage text and errors.
+#[cfg(windows)]
+pub(crate) const PLUGINS_FILE: &str = "%APPDATA%\\rage\\plugins";
+#[cfg(not(windows))]
+pub(crate) const PLUGINS_FILE: &str = "$XDG_CONFIG_HOME/rage/plugins (or ~/.config/rage/plugins)";
+
+/// Reads the plugins that may be run without confirmation from the plugin allowlist.
+///
+/// The allowlist contains one plugin name (such as `yubikey` for `age-plugin-yubikey`)
+/// per line, ignoring "#" prefixed comments and empty lines. If it does not exist, no
+/// plugins are allowed.
+pub(crate) fn plugin_allowlist() -> io::Result<Allowlist> {
+    let path = match config_dir() {
+        Some(dir) => dir.join("plugins"),
+        None => return Ok(Allowlist::new()),
+    };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Allowlist::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut allowlist = Allowlist::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let line = line.trim();
+        if !(line.is_empty() || line.starts_with('#')) {
+            allowlist.allow(line);
+        }
+    }
+    Ok(allowlist)
+}
@@ -14,23 +14,170 @@ macro_rules! wlnfl {
     };
 }
 
+/// The classes of failure that `rage` exits with distinct codes for, so that scripts
+/// can tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Failure {
+    /// A failure that isn't in one of the other classes.
+    Other,
+    /// The flags or arguments were invalid.
+    Usage,
+    /// A file or stream could not be read or written.
+    Io,
+    /// The input isn't a valid age file, or has been corrupted or tampered with.
+    Corrupted,
+    /// None of the identities could decrypt the input.
+    NoMatchingIdentity,
+    /// The passphrase for the input (or for an encrypted key) was incorrect.
+    BadPassphrase,
+}
+
+impl Failure {
+    /// Returns the exit code for this class of failure.
+    pub(crate) fn exit_code(self) -> i32 {
+        match self {
+            Failure::Other => 1,
+            Failure::Usage => 2,
+            Failure::Io => 3,
+            Failure::Corrupted => 4,
+            Failure::NoMatchingIdentity => 5,
+            Failure::BadPassphrase => 6,
+        }
+    }
+}
+
+/// Returns the class of an I/O error that occurred while reading an age file.
+///
+/// The age crate reports invalid armor and payloads as I/O errors.
+fn read_io_failure(e: &io::Error) -> Failure {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof => {
+            Failure::Corrupted
+        }
+        _ => Failure::Io,
+    }
+}
+
+/// Returns the class of an error from the age crate.
+fn age_decrypt_failure(e: &age::DecryptError) -> Failure {
+    match e {
+        age::DecryptError::DecryptionFailed
+        | age::DecryptError::InvalidHeader
+        | age::DecryptError::InvalidStanza(_)
+        | age::DecryptError::InvalidMac
+        | age::DecryptError::UnknownFormat => Failure::Corrupted,
+        age::DecryptError::Io(e) => read_io_failure(e),
+        age::DecryptError::KeyDecryptionFailed => Failure::BadPassphrase,
+        age::DecryptError::NoMatchingKeys | age::DecryptError::ThresholdNotMet { .. } => {
+            Failure::NoMatchingIdentity
+        }
+        _ => Failure::Other,
+    }
+}
+
+/// Returns the stable identifier of an error from the age crate, for `--format json`.
+fn age_decrypt_code(e: &age::DecryptError) -> &'static str {
+    match e {
+        age::DecryptError::DecryptionFailed => "decryption-failed",
+        age::DecryptError::ExcessiveWork { .. } => "excessive-work",
+        age::DecryptError::InvalidHeader => "invalid-header",
+        age::DecryptError::InvalidStanza(_) => "invalid-stanza",
+        age::DecryptError::InvalidMac => "invalid-mac",
+        age::DecryptError::Io(_) => "io",
+        age::DecryptError::KeyDecryptionFailed => "key-decryption-failed",
+        age::DecryptError::MissingPlugin { .. } => "missing-plugin",
+        age::DecryptError::NoMatchingKeys => "no-matching-keys",
+        age::DecryptError::Plugin(_) => "plugin",
+        age::DecryptError::PluginNotAllowed { .. } => "plugin-not-allowed",
+        age::DecryptError::PluginProtocol { .. } => "plugin-protocol",
+        age::DecryptError::PluginTooOld { .. } => "plugin-too-old",
+        age::DecryptError::ThresholdNotMet { .. } => "threshold-not-met",
+        age::DecryptError::Timeout { .. } => "plugin-timeout",
+        age::DecryptError::UnknownFormat => "unknown-format",
+        // Variants for features of the age crate that rage doesn't enable.
+        #[allow(unreachable_patterns)]
+        _ => "other",
+    }
+}
+
+/// Returns the stable identifier of an error from the age crate, for `--format json`.
+fn age_encrypt_code(e: &age::EncryptError) -> &'static str {
+    match e {
+        age::EncryptError::EncryptedIdentities(e) => age_decrypt_code(e),
+        age::EncryptError::Io(_) => "io",
+        age::EncryptError::Rng(_) => "rng",
+        age::EncryptError::ThresholdHeader => "threshold-header",
+        age::EncryptError::MissingPlugin { .. } => "missing-plugin",
+        age::EncryptError::Plugin(_) => "plugin",
+        age::EncryptError::PluginNotAllowed { .. } => "plugin-not-allowed",
+        age::EncryptError::PluginProtocol { .. } => "plugin-protocol",
+        age::EncryptError::PluginTooOld { .. } => "plugin-too-old",
+        // Variants for features of the age crate that rage doesn't enable.
+        #[allow(unreachable_patterns)]
+        _ => "other",
+    }
+}
+
+/// Tells the user how to allow a plugin that we refused to run.
+fn write_plugin_not_allowed_hint(f: &mut fmt::Formatter<'_>, binary_name: &str) -> fmt::Result {
+    write!(
+        f,
+        "{}",
+        fl!(
+            crate::LANGUAGE_LOADER,
+            "rec-plugin-not-allowed",
+            plugin_name = binary_name
+                .strip_prefix("age-plugin-")
+                .unwrap_or(binary_name),
+            plugins_file = crate::defaults::PLUGINS_FILE,
+        )
+    )
+}
+
 pub(crate) enum EncryptError {
     Age(age::EncryptError),
+    ArchiveInputNotDirectory,
     BrokenPipe {
         is_stdout: bool,
         source: io::Error,
     },
+    EmptyPassphraseSink,
+    #[cfg(feature = "http")]
+    FetchRecipients(String, io::Error),
+    GeneratorNotNormalized,
     IdentityEncryptedWithoutPassphrase(String),
     IdentityNotFound(String),
+    InputFile(String, Box<EncryptError>),
+    InteractiveWithoutTerminal,
     InvalidRecipient(String),
+    InvalidThreshold {
+        threshold: u8,
+        recipients: usize,
+    },
+    InvalidWordlist(String),
     Io(io::Error),
     MissingRecipients,
     MixedIdentityAndPassphrase,
+    MixedInteractiveAndPassphrase,
     MixedRecipientAndPassphrase,
     MixedRecipientsFileAndPassphrase,
+    MixedThresholdAndPassphrase,
+    MixedWordsAndCharset,
+    MultiplePassphraseSinks,
+    PassphraseFdWithoutPassphrase,
+    PassphraseGeneratorWithoutPassphrase,
+    PassphraseLengthZero,
+    PassphraseSink(io::Error),
+    PassphraseSinkWithoutPassphrase,
     PassphraseTimedOut,
     PassphraseWithoutFileArgument,
     PluginNameFlag,
+    #[cfg(feature = "qr")]
+    QrTooLarge,
+    RecipientsDeclined,
+    RecipientsFile(age::cli_common::recipients_file::RecipientsFileError),
+    ThresholdZero,
+    UnknownCharset(String),
     #[cfg(feature = "ssh")]
     UnsupportedKey(String, age::ssh::UnsupportedKey),
 }
@@ -44,6 +191,15 @@ impl From<age::EncryptError> for EncryptError {
     }
 }
 
+impl From<age::cli_common::recipients_file::RecipientsFileError> for EncryptError {
+    fn from(e: age::cli_common::recipients_file::RecipientsFileError) -> Self {
+        match e {
+            age::cli_common::recipients_file::RecipientsFileError::Io(e) => EncryptError::Io(e),
+            _ => EncryptError::RecipientsFile(e),
+        }
+    }
+}
+
 impl From<io::Error> for EncryptError {
     fn from(e: io::Error) -> Self {
         EncryptError::Io(e)
@@ -53,7 +209,16 @@ impl From<io::Error> for EncryptError {
 impl fmt::Display for EncryptError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            EncryptError::Age(e) => write!(f, "{}", e),
+            EncryptError::Age(e) => match e {
+                age::EncryptError::PluginNotAllowed { binary_name } => {
+                    writeln!(f, "{}", e)?;
+                    write_plugin_not_allowed_hint(f, binary_name)
+                }
+                _ => write!(f, "{}", e),
+            },
+            EncryptError::ArchiveInputNotDirectory => {
+                wfl!(f, "err-enc-archive-input-not-directory")
+            }
             EncryptError::BrokenPipe { is_stdout, source } => {
                 if *is_stdout {
                     writeln!(
@@ -78,6 +243,22 @@ impl fmt::Display for EncryptError {
                     )
                 }
             }
+            EncryptError::EmptyPassphraseSink => wfl!(f, "err-enc-empty-passphrase-sink"),
+            #[cfg(feature = "http")]
+            EncryptError::FetchRecipients(url, e) => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-enc-fetch-recipients",
+                    url = url.as_str(),
+                    err = e.to_string()
+                )
+            ),
+            EncryptError::GeneratorNotNormalized => {
+                wlnfl!(f, "err-enc-generator-not-normalized")?;
+                wfl!(f, "rec-enc-generator-not-normalized")
+            }
             EncryptError::IdentityEncryptedWithoutPassphrase(filename) => {
                 write!(
                     f,
@@ -98,6 +279,10 @@ impl fmt::Display for EncryptError {
                     filename = filename.as_str()
                 )
             ),
+            EncryptError::InputFile(filename, e) => write!(f, "{}: {}", filename, e),
+            EncryptError::InteractiveWithoutTerminal => {
+                wfl!(f, "err-enc-interactive-without-terminal")
+            }
             EncryptError::InvalidRecipient(recipient) => write!(
                 f,
                 "{}",
@@ -107,6 +292,28 @@ impl fmt::Display for EncryptError {
                     recipient = recipient.as_str()
                 )
             ),
+            EncryptError::InvalidThreshold {
+                threshold,
+                recipients,
+            } => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-enc-invalid-threshold",
+                    threshold = threshold,
+                    recipients = recipients,
+                )
+            ),
+            EncryptError::InvalidWordlist(filename) => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-enc-invalid-wordlist",
+                    filename = filename.as_str()
+                )
+            ),
             EncryptError::Io(e) => write!(f, "{}", e),
             EncryptError::MissingRecipients => {
                 wlnfl!(f, "err-enc-missing-recipients")?;
@@ -115,12 +322,34 @@ impl fmt::Display for EncryptError {
             EncryptError::MixedIdentityAndPassphrase => {
                 wfl!(f, "err-enc-mixed-identity-passphrase")
             }
+            EncryptError::MixedInteractiveAndPassphrase => {
+                wfl!(f, "err-enc-mixed-interactive-passphrase")
+            }
             EncryptError::MixedRecipientAndPassphrase => {
                 wfl!(f, "err-enc-mixed-recipient-passphrase")
             }
             EncryptError::MixedRecipientsFileAndPassphrase => {
                 wfl!(f, "err-enc-mixed-recipients-file-passphrase")
             }
+            EncryptError::MixedThresholdAndPassphrase => {
+                wfl!(f, "err-enc-mixed-threshold-passphrase")
+            }
+            EncryptError::MixedWordsAndCharset => wfl!(f, "err-enc-mixed-words-charset"),
+            EncryptError::MultiplePassphraseSinks => wfl!(f, "err-enc-multiple-passphrase-sinks"),
+            EncryptError::PassphraseFdWithoutPassphrase => {
+                wfl!(f, "err-enc-passphrase-fd-without-passphrase")
+            }
+            EncryptError::PassphraseGeneratorWithoutPassphrase => {
+                wfl!(f, "err-enc-generator-without-passphrase")
+            }
+            EncryptError::PassphraseLengthZero => wfl!(f, "err-enc-passphrase-length-zero"),
+            EncryptError::PassphraseSink(e) => {
+                wlnfl!(f, "err-enc-passphrase-sink")?;
+                write!(f, "{}", e)
+            }
+            EncryptError::PassphraseSinkWithoutPassphrase => {
+                wfl!(f, "err-enc-passphrase-sink-without-passphrase")
+            }
             EncryptError::PassphraseTimedOut => wfl!(f, "err-passphrase-timed-out"),
             EncryptError::PassphraseWithoutFileArgument => {
                 wfl!(f, "err-enc-passphrase-without-file")
@@ -128,12 +357,121 @@ impl fmt::Display for EncryptError {
             EncryptError::PluginNameFlag => {
                 wfl!(f, "err-enc-plugin-name-flag")
             }
+            #[cfg(feature = "qr")]
+            EncryptError::QrTooLarge => {
+                wlnfl!(f, "err-enc-qr-too-large")?;
+                wfl!(f, "rec-enc-qr-too-large")
+            }
+            EncryptError::RecipientsDeclined => wfl!(f, "err-enc-recipients-declined"),
+            EncryptError::RecipientsFile(e) => write!(f, "{}", e),
+            EncryptError::ThresholdZero => wfl!(f, "err-enc-threshold-zero"),
+            EncryptError::UnknownCharset(charset) => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-enc-unknown-charset",
+                    charset = charset.as_str()
+                )
+            ),
             #[cfg(feature = "ssh")]
             EncryptError::UnsupportedKey(filename, k) => k.display(f, Some(filename.as_str())),
         }
     }
 }
 
+impl EncryptError {
+    /// Returns a stable identifier for this error, for `--format json`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            EncryptError::Age(e) => age_encrypt_code(e),
+            EncryptError::ArchiveInputNotDirectory => "enc-archive-input-not-directory",
+            EncryptError::BrokenPipe { .. } => "enc-broken-pipe",
+            EncryptError::EmptyPassphraseSink => "enc-empty-passphrase-sink",
+            #[cfg(feature = "http")]
+            EncryptError::FetchRecipients(..) => "enc-fetch-recipients",
+            EncryptError::GeneratorNotNormalized => "enc-generator-not-normalized",
+            EncryptError::IdentityEncryptedWithoutPassphrase(_) => {
+                "identity-encrypted-without-passphrase"
+            }
+            EncryptError::IdentityNotFound(_) => "identity-not-found",
+            EncryptError::InputFile(_, e) => e.code(),
+            EncryptError::InteractiveWithoutTerminal => "enc-interactive-without-terminal",
+            EncryptError::InvalidRecipient(_) => "enc-invalid-recipient",
+            EncryptError::InvalidThreshold { .. } => "enc-invalid-threshold",
+            EncryptError::InvalidWordlist(_) => "enc-invalid-wordlist",
+            EncryptError::Io(_) => "io",
+            EncryptError::MissingRecipients => "enc-missing-recipients",
+            EncryptError::MixedIdentityAndPassphrase => "enc-mixed-identity-passphrase",
+            EncryptError::MixedInteractiveAndPassphrase => "enc-mixed-interactive-passphrase",
+            EncryptError::MixedRecipientAndPassphrase => "enc-mixed-recipient-passphrase",
+            EncryptError::MixedRecipientsFileAndPassphrase => {
+                "enc-mixed-recipients-file-passphrase"
+            }
+            EncryptError::MixedThresholdAndPassphrase => "enc-mixed-threshold-passphrase",
+            EncryptError::MixedWordsAndCharset => "enc-mixed-words-charset",
+            EncryptError::MultiplePassphraseSinks => "enc-multiple-passphrase-sinks",
+            EncryptError::PassphraseFdWithoutPassphrase => "enc-passphrase-fd-without-passphrase",
+            EncryptError::PassphraseGeneratorWithoutPassphrase => {
+                "enc-generator-without-passphrase"
+            }
+            EncryptError::PassphraseLengthZero => "enc-passphrase-length-zero",
+            EncryptError::PassphraseSink(_) => "enc-passphrase-sink",
+            EncryptError::PassphraseSinkWithoutPassphrase => {
+                "enc-passphrase-sink-without-passphrase"
+            }
+            EncryptError::PassphraseTimedOut => "passphrase-timed-out",
+            EncryptError::PassphraseWithoutFileArgument => "enc-passphrase-without-file",
+            EncryptError::PluginNameFlag => "enc-plugin-name-flag",
+            #[cfg(feature = "qr")]
+            EncryptError::QrTooLarge => "enc-qr-too-large",
+            EncryptError::RecipientsDeclined => "enc-recipients-declined",
+            EncryptError::RecipientsFile(_) => "enc-recipients-file",
+            EncryptError::ThresholdZero => "enc-threshold-zero",
+            EncryptError::UnknownCharset(_) => "enc-unknown-charset",
+            #[cfg(feature = "ssh")]
+            EncryptError::UnsupportedKey(..) => "unsupported-key",
+        }
+    }
+
+    /// Returns the class of this error, which determines the exit code.
+    pub(crate) fn failure(&self) -> Failure {
+        match self {
+            EncryptError::Age(age::EncryptError::EncryptedIdentities(e)) => age_decrypt_failure(e),
+            EncryptError::Age(age::EncryptError::Io(_))
+            | EncryptError::BrokenPipe { .. }
+            | EncryptError::IdentityNotFound(_)
+            | EncryptError::Io(_)
+            | EncryptError::PassphraseSink(_) => Failure::Io,
+            #[cfg(feature = "http")]
+            EncryptError::FetchRecipients(..) => Failure::Io,
+            EncryptError::InputFile(_, e) => e.failure(),
+            EncryptError::ArchiveInputNotDirectory
+            | EncryptError::EmptyPassphraseSink
+            | EncryptError::GeneratorNotNormalized
+            | EncryptError::InvalidRecipient(_)
+            | EncryptError::InvalidThreshold { .. }
+            | EncryptError::MissingRecipients
+            | EncryptError::MixedIdentityAndPassphrase
+            | EncryptError::MixedInteractiveAndPassphrase
+            | EncryptError::MixedRecipientAndPassphrase
+            | EncryptError::MixedRecipientsFileAndPassphrase
+            | EncryptError::MixedThresholdAndPassphrase
+            | EncryptError::MixedWordsAndCharset
+            | EncryptError::MultiplePassphraseSinks
+            | EncryptError::PassphraseFdWithoutPassphrase
+            | EncryptError::PassphraseGeneratorWithoutPassphrase
+            | EncryptError::PassphraseLengthZero
+            | EncryptError::PassphraseSinkWithoutPassphrase
+            | EncryptError::PassphraseWithoutFileArgument
+            | EncryptError::PluginNameFlag
+            | EncryptError::ThresholdZero
+            | EncryptError::UnknownCharset(_) => Failure::Usage,
+            _ => Failure::Other,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct DetectedPowerShellCorruptionError;
 
@@ -149,7 +487,10 @@ impl std::error::Error for DetectedPowerShellCorruptionError {}
 pub(crate) enum DecryptError {
     Age(age::DecryptError),
     ArmorFlag,
+    ExposedOutput(String),
     IdentityRead(age::cli_common::ReadError),
+    InputFile(String, Box<DecryptError>),
+    InteractiveFlag,
     Io(io::Error),
     MissingIdentities,
     MixedIdentityAndPassphrase,
@@ -158,8 +499,13 @@ pub(crate) enum DecryptError {
     PassphraseTimedOut,
     #[cfg(not(unix))]
     PassphraseWithoutFileArgument,
+    #[cfg(feature = "qr")]
+    QrFlag,
     RecipientFlag,
     RecipientsFileFlag,
+    ThresholdFlag,
+    VerifyOnlyOutputFlags,
+    WrongPassphrase,
 }
 
 impl From<age::DecryptError> for DecryptError {
@@ -196,13 +542,37 @@ impl fmt::Display for DecryptError {
                         )
                     )
                 }
+                age::DecryptError::PluginNotAllowed { binary_name } => {
+                    writeln!(f, "{}", e)?;
+                    write_plugin_not_allowed_hint(f, binary_name)
+                }
                 _ => write!(f, "{}", e),
             },
             DecryptError::ArmorFlag => {
                 wlnfl!(f, "err-dec-armor-flag")?;
                 wfl!(f, "rec-dec-armor-flag")
             }
-            DecryptError::IdentityRead(e) => write!(f, "{}", e),
+            DecryptError::ExposedOutput(filename) => {
+                writeln!(
+                    f,
+                    "{}",
+                    fl!(
+                        crate::LANGUAGE_LOADER,
+                        "err-dec-exposed-output",
+                        filename = filename.as_str()
+                    )
+                )?;
+                wfl!(f, "rec-dec-exposed-output")
+            }
+            DecryptError::IdentityRead(e) => match e {
+                age::cli_common::ReadError::PluginNotAllowed { binary_name } => {
+                    writeln!(f, "{}", e)?;
+                    write_plugin_not_allowed_hint(f, binary_name)
+                }
+                _ => write!(f, "{}", e),
+            },
+            DecryptError::InputFile(filename, e) => write!(f, "{}: {}", filename, e),
+            DecryptError::InteractiveFlag => wfl!(f, "err-dec-interactive-flag"),
             DecryptError::Io(e) => write!(f, "{}", e),
             DecryptError::MissingIdentities => {
                 wlnfl!(f, "err-dec-missing-identities")?;
@@ -223,6 +593,8 @@ impl fmt::Display for DecryptError {
             DecryptError::PassphraseWithoutFileArgument => {
                 wfl!(f, "err-dec-passphrase-without-file-win")
             }
+            #[cfg(feature = "qr")]
+            DecryptError::QrFlag => wfl!(f, "err-dec-qr-flag"),
             DecryptError::RecipientFlag => {
                 wlnfl!(f, "err-dec-recipient-flag")?;
                 wfl!(f, "rec-dec-recipient-flag")
@@ -231,16 +603,395 @@ impl fmt::Display for DecryptError {
                 wlnfl!(f, "err-dec-recipients-file-flag")?;
                 wfl!(f, "rec-dec-recipient-flag")
             }
+            DecryptError::ThresholdFlag => wfl!(f, "err-dec-threshold-flag"),
+            DecryptError::VerifyOnlyOutputFlags => wfl!(f, "err-dec-verify-only-output-flags"),
+            DecryptError::WrongPassphrase => {
+                wlnfl!(f, "err-dec-wrong-passphrase")?;
+                wfl!(f, "rec-dec-wrong-passphrase")
+            }
+        }
+    }
+}
+
+impl DecryptError {
+    /// Returns a stable identifier for this error, for `--format json`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            DecryptError::Age(e) => age_decrypt_code(e),
+            DecryptError::ArmorFlag => "dec-armor-flag",
+            DecryptError::ExposedOutput(_) => "dec-exposed-output",
+            DecryptError::IdentityRead(_) => "identity-read",
+            DecryptError::InputFile(_, e) => e.code(),
+            DecryptError::InteractiveFlag => "dec-interactive-flag",
+            DecryptError::Io(_) => "io",
+            DecryptError::MissingIdentities => "dec-missing-identities",
+            DecryptError::MixedIdentityAndPassphrase => "dec-mixed-identity-passphrase",
+            DecryptError::MixedIdentityAndPluginName => "mixed-identity-and-plugin-name",
+            DecryptError::PassphraseFlag => "dec-passphrase-flag",
+            DecryptError::PassphraseTimedOut => "passphrase-timed-out",
+            #[cfg(not(unix))]
+            DecryptError::PassphraseWithoutFileArgument => "dec-passphrase-without-file",
+            #[cfg(feature = "qr")]
+            DecryptError::QrFlag => "dec-qr-flag",
+            DecryptError::RecipientFlag => "dec-recipient-flag",
+            DecryptError::RecipientsFileFlag => "dec-recipients-file-flag",
+            DecryptError::ThresholdFlag => "dec-threshold-flag",
+            DecryptError::VerifyOnlyOutputFlags => "dec-verify-only-output-flags",
+            DecryptError::WrongPassphrase => "dec-wrong-passphrase",
+        }
+    }
+
+    /// Returns the class of this error, which determines the exit code.
+    pub(crate) fn failure(&self) -> Failure {
+        match self {
+            DecryptError::Age(e) => age_decrypt_failure(e),
+            DecryptError::IdentityRead(age::cli_common::ReadError::IdentityNotFound(_))
+            | DecryptError::IdentityRead(age::cli_common::ReadError::Io(_)) => Failure::Io,
+            DecryptError::IdentityRead(_) => Failure::Other,
+            DecryptError::InputFile(_, e) => e.failure(),
+            DecryptError::Io(e) => read_io_failure(e),
+            DecryptError::PassphraseTimedOut => Failure::Other,
+            DecryptError::WrongPassphrase => Failure::BadPassphrase,
+            DecryptError::ArmorFlag
+            | DecryptError::ExposedOutput(_)
+            | DecryptError::InteractiveFlag
+            | DecryptError::MissingIdentities
+            | DecryptError::MixedIdentityAndPassphrase
+            | DecryptError::MixedIdentityAndPluginName
+            | DecryptError::PassphraseFlag
+            | DecryptError::RecipientFlag
+            | DecryptError::RecipientsFileFlag
+            | DecryptError::ThresholdFlag
+            | DecryptError::VerifyOnlyOutputFlags => Failure::Usage,
+            #[cfg(not(unix))]
+            DecryptError::PassphraseWithoutFileArgument => Failure::Usage,
+            #[cfg(feature = "qr")]
+            DecryptError::QrFlag => Failure::Usage,
+        }
+    }
+}
+
+pub(crate) enum RekeyError {
+    ArchiveFlag,
+    Decryption(DecryptError),
+    Encryption(EncryptError),
+    EncryptOrDecryptFlag,
+    Io(io::Error),
+    MissingInput,
+    OutputFlag,
+    PassphraseEncrypted,
+    PassphraseFlag,
+}
+
+impl From<DecryptError> for RekeyError {
+    fn from(e: DecryptError) -> Self {
+        RekeyError::Decryption(e)
+    }
+}
+
+impl From<EncryptError> for RekeyError {
+    fn from(e: EncryptError) -> Self {
+        RekeyError::Encryption(e)
+    }
+}
+
+impl From<io::Error> for RekeyError {
+    fn from(e: io::Error) -> Self {
+        RekeyError::Io(e)
+    }
+}
+
+impl fmt::Display for RekeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RekeyError::ArchiveFlag => wfl!(f, "err-rekey-archive-flag"),
+            RekeyError::Decryption(e) => write!(f, "{}", e),
+            RekeyError::Encryption(e) => write!(f, "{}", e),
+            RekeyError::EncryptOrDecryptFlag => wfl!(f, "err-rekey-encrypt-decrypt-flag"),
+            RekeyError::Io(e) => write!(f, "{}", e),
+            RekeyError::MissingInput => wfl!(f, "err-rekey-missing-input"),
+            RekeyError::OutputFlag => {
+                wlnfl!(f, "err-rekey-output-flag")?;
+                wfl!(f, "rec-rekey-output-flag")
+            }
+            RekeyError::PassphraseEncrypted => wfl!(f, "err-rekey-passphrase-encrypted"),
+            RekeyError::PassphraseFlag => wfl!(f, "err-rekey-passphrase-flag"),
+        }
+    }
+}
+
+impl RekeyError {
+    /// Returns a stable identifier for this error, for `--format json`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            RekeyError::ArchiveFlag => "rekey-archive-flag",
+            RekeyError::Decryption(e) => e.code(),
+            RekeyError::Encryption(e) => e.code(),
+            RekeyError::EncryptOrDecryptFlag => "rekey-encrypt-decrypt-flag",
+            RekeyError::Io(_) => "io",
+            RekeyError::MissingInput => "rekey-missing-input",
+            RekeyError::OutputFlag => "rekey-output-flag",
+            RekeyError::PassphraseEncrypted => "rekey-passphrase-encrypted",
+            RekeyError::PassphraseFlag => "rekey-passphrase-flag",
+        }
+    }
+
+    /// Returns the class of this error, which determines the exit code.
+    pub(crate) fn failure(&self) -> Failure {
+        match self {
+            RekeyError::Decryption(e) => e.failure(),
+            RekeyError::Encryption(e) => e.failure(),
+            RekeyError::Io(_) => Failure::Io,
+            RekeyError::PassphraseEncrypted => Failure::Other,
+            RekeyError::ArchiveFlag
+            | RekeyError::EncryptOrDecryptFlag
+            | RekeyError::MissingInput
+            | RekeyError::OutputFlag
+            | RekeyError::PassphraseFlag => Failure::Usage,
+        }
+    }
+}
+
+pub(crate) enum CheckArmorError {
+    Invalid(age::armor::ArmorViolation),
+    Io(io::Error),
+    OtherFlags,
+}
+
+impl From<io::Error> for CheckArmorError {
+    fn from(e: io::Error) -> Self {
+        CheckArmorError::Io(e)
+    }
+}
+
+impl fmt::Display for CheckArmorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckArmorError::Invalid(violation) => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-check-armor-invalid",
+                    line = violation.line(),
+                    column = violation.column(),
+                    err = violation.error().to_string(),
+                )
+            ),
+            CheckArmorError::Io(e) => write!(f, "{}", e),
+            CheckArmorError::OtherFlags => wfl!(f, "err-check-armor-other-flags"),
+        }
+    }
+}
+
+impl CheckArmorError {
+    /// Returns a stable identifier for this error, for `--format json`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            CheckArmorError::Invalid(_) => "check-armor-invalid",
+            CheckArmorError::Io(_) => "io",
+            CheckArmorError::OtherFlags => "check-armor-other-flags",
+        }
+    }
+
+    /// Returns the class of this error, which determines the exit code.
+    pub(crate) fn failure(&self) -> Failure {
+        match self {
+            CheckArmorError::Invalid(_) => Failure::Corrupted,
+            CheckArmorError::Io(_) => Failure::Io,
+            CheckArmorError::OtherFlags => Failure::Usage,
+        }
+    }
+}
+
+pub(crate) enum InspectError {
+    Age(age::DecryptError),
+    Io(io::Error),
+    OtherFlags,
+}
+
+impl From<age::DecryptError> for InspectError {
+    fn from(e: age::DecryptError) -> Self {
+        match e {
+            age::DecryptError::Io(e) => InspectError::Io(e),
+            _ => InspectError::Age(e),
+        }
+    }
+}
+
+impl From<io::Error> for InspectError {
+    fn from(e: io::Error) -> Self {
+        InspectError::Io(e)
+    }
+}
+
+impl fmt::Display for InspectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InspectError::Age(e) => write!(f, "{}", e),
+            InspectError::Io(e) => write!(f, "{}", e),
+            InspectError::OtherFlags => wfl!(f, "err-inspect-other-flags"),
+        }
+    }
+}
+
+impl InspectError {
+    /// Returns a stable identifier for this error, for `--format json`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            InspectError::Age(e) => age_decrypt_code(e),
+            InspectError::Io(_) => "io",
+            InspectError::OtherFlags => "inspect-other-flags",
+        }
+    }
+
+    /// Returns the class of this error, which determines the exit code.
+    pub(crate) fn failure(&self) -> Failure {
+        match self {
+            InspectError::Age(e) => age_decrypt_failure(e),
+            InspectError::Io(e) => read_io_failure(e),
+            InspectError::OtherFlags => Failure::Usage,
+        }
+    }
+}
+
+pub(crate) enum DaemonError {
+    Decryption(DecryptError),
+    Encryption(EncryptError),
+    Io(io::Error),
+    NoIdentities,
+    NoRecipients,
+    OtherFlags,
+    PassphraseEncrypted,
+    SocketDirExposed(String),
+    SocketInUse(String),
+    UnknownOperation(u8),
+    #[cfg(not(unix))]
+    Unsupported,
+}
+
+impl From<DecryptError> for DaemonError {
+    fn from(e: DecryptError) -> Self {
+        DaemonError::Decryption(e)
+    }
+}
+
+impl From<EncryptError> for DaemonError {
+    fn from(e: EncryptError) -> Self {
+        DaemonError::Encryption(e)
+    }
+}
+
+impl From<io::Error> for DaemonError {
+    fn from(e: io::Error) -> Self {
+        DaemonError::Io(e)
+    }
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaemonError::Decryption(e) => write!(f, "{}", e),
+            DaemonError::Encryption(e) => write!(f, "{}", e),
+            DaemonError::Io(e) => write!(f, "{}", e),
+            DaemonError::NoIdentities => wfl!(f, "err-daemon-no-identities"),
+            DaemonError::NoRecipients => wfl!(f, "err-daemon-no-recipients"),
+            DaemonError::OtherFlags => wfl!(f, "err-daemon-other-flags"),
+            DaemonError::PassphraseEncrypted => wfl!(f, "err-daemon-passphrase-encrypted"),
+            DaemonError::SocketDirExposed(dir) => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-daemon-socket-dir-exposed",
+                    dir = dir.as_str()
+                )
+            ),
+            DaemonError::SocketInUse(socket) => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-daemon-socket-in-use",
+                    socket = socket.as_str()
+                )
+            ),
+            DaemonError::UnknownOperation(op) => write!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-daemon-unknown-operation",
+                    op = format!("{:#04x}", op)
+                )
+            ),
+            #[cfg(not(unix))]
+            DaemonError::Unsupported => wfl!(f, "err-daemon-unsupported"),
+        }
+    }
+}
+
+impl DaemonError {
+    /// Returns a stable identifier for this error, for `--format json`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            DaemonError::Decryption(e) => e.code(),
+            DaemonError::Encryption(e) => e.code(),
+            DaemonError::Io(_) => "io",
+            DaemonError::NoIdentities => "daemon-no-identities",
+            DaemonError::NoRecipients => "daemon-no-recipients",
+            DaemonError::OtherFlags => "daemon-other-flags",
+            DaemonError::PassphraseEncrypted => "daemon-passphrase-encrypted",
+            DaemonError::SocketDirExposed(_) => "daemon-socket-dir-exposed",
+            DaemonError::SocketInUse(_) => "daemon-socket-in-use",
+            DaemonError::UnknownOperation(_) => "daemon-unknown-operation",
+            #[cfg(not(unix))]
+            DaemonError::Unsupported => "daemon-unsupported",
+        }
+    }
+
+    /// Returns the class of this error, which determines the exit code.
+    pub(crate) fn failure(&self) -> Failure {
+        match self {
+            DaemonError::Decryption(e) => e.failure(),
+            DaemonError::Encryption(e) => e.failure(),
+            DaemonError::Io(_) => Failure::Io,
+            DaemonError::OtherFlags => Failure::Usage,
+            _ => Failure::Other,
         }
     }
 }
 
 pub(crate) enum Error {
+    CheckArmor(CheckArmorError),
+    Daemon(DaemonError),
     Decryption(DecryptError),
     Encryption(EncryptError),
     IdentityFlagAmbiguous,
+    Inspect(InspectError),
+    JobsZero,
+    JsonWithoutInspect,
     MixedEncryptAndDecrypt,
+    MultipleInputsFlag,
+    MultipleInputsStdin,
+    OutputWithMultipleInputs,
+    PassphraseFdIsStdin,
+    ReadPassphrase(io::Error),
+    Rekey(RekeyError),
     SameInputAndOutput(String),
+    UnnamedOutput(String),
+}
+
+impl From<CheckArmorError> for Error {
+    fn from(e: CheckArmorError) -> Self {
+        Error::CheckArmor(e)
+    }
+}
+
+impl From<DaemonError> for Error {
+    fn from(e: DaemonError) -> Self {
+        Error::Daemon(e)
+    }
 }
 
 impl From<DecryptError> for Error {
@@ -255,15 +1006,95 @@ impl From<EncryptError> for Error {
     }
 }
 
-// Rust only supports `fn main() -> Result<(), E: Debug>`, so we implement `Debug`
-// manually to provide the error output we want.
-impl fmt::Debug for Error {
+impl From<InspectError> for Error {
+    fn from(e: InspectError) -> Self {
+        Error::Inspect(e)
+    }
+}
+
+impl From<RekeyError> for Error {
+    fn from(e: RekeyError) -> Self {
+        Error::Rekey(e)
+    }
+}
+
+impl Error {
+    /// Returns a stable identifier for this error, for `--format json`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Error::CheckArmor(e) => e.code(),
+            Error::Daemon(e) => e.code(),
+            Error::Decryption(e) => e.code(),
+            Error::Encryption(e) => e.code(),
+            Error::IdentityFlagAmbiguous => "identity-ambiguous",
+            Error::Inspect(e) => e.code(),
+            Error::JobsZero => "jobs-zero",
+            Error::JsonWithoutInspect => "inspect-json-flag",
+            Error::MixedEncryptAndDecrypt => "mixed-encrypt-decrypt",
+            Error::MultipleInputsFlag => "multiple-inputs-flag",
+            Error::MultipleInputsStdin => "multiple-inputs-stdin",
+            Error::OutputWithMultipleInputs => "multiple-inputs-output",
+            Error::PassphraseFdIsStdin => "passphrase-fd-stdin",
+            Error::ReadPassphrase(_) => "read-passphrase",
+            Error::Rekey(e) => e.code(),
+            Error::SameInputAndOutput(_) => "same-input-and-output",
+            Error::UnnamedOutput(_) => "unnamed-output",
+        }
+    }
+
+    /// Returns the class of this error, which determines the exit code.
+    pub(crate) fn failure(&self) -> Failure {
+        match self {
+            Error::CheckArmor(e) => e.failure(),
+            Error::Daemon(e) => e.failure(),
+            Error::Decryption(e) => e.failure(),
+            Error::Encryption(e) => e.failure(),
+            Error::Inspect(e) => e.failure(),
+            Error::ReadPassphrase(_) => Failure::Io,
+            Error::Rekey(e) => e.failure(),
+            Error::IdentityFlagAmbiguous
+            | Error::JobsZero
+            | Error::JsonWithoutInspect
+            | Error::MixedEncryptAndDecrypt
+            | Error::MultipleInputsFlag
+            | Error::MultipleInputsStdin
+            | Error::OutputWithMultipleInputs
+            | Error::PassphraseFdIsStdin
+            | Error::SameInputAndOutput(_)
+            | Error::UnnamedOutput(_) => Failure::Usage,
+        }
+    }
+}
+
+impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Error::CheckArmor(e) => writeln!(f, "{}", e)?,
+            Error::Daemon(e) => writeln!(f, "{}", e)?,
             Error::Decryption(e) => writeln!(f, "{}", e)?,
             Error::Encryption(e) => writeln!(f, "{}", e)?,
             Error::IdentityFlagAmbiguous => wlnfl!(f, "err-identity-ambiguous")?,
+            Error::Inspect(e) => writeln!(f, "{}", e)?,
+            Error::JobsZero => wlnfl!(f, "err-jobs-zero")?,
+            Error::JsonWithoutInspect => wlnfl!(f, "err-inspect-json-flag")?,
             Error::MixedEncryptAndDecrypt => wlnfl!(f, "err-mixed-encrypt-decrypt")?,
+            Error::MultipleInputsFlag => wlnfl!(f, "err-multiple-inputs-flag")?,
+            Error::MultipleInputsStdin => wlnfl!(f, "err-multiple-inputs-stdin")?,
+            Error::OutputWithMultipleInputs => {
+                wlnfl!(f, "err-multiple-inputs-output")?;
+                wlnfl!(f, "rec-multiple-inputs-output")?
+            }
+            Error::PassphraseFdIsStdin => wlnfl!(f, "err-passphrase-fd-stdin")?,
+            Error::ReadPassphrase(e) => writeln!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-read-passphrase",
+                    err = e.to_string()
+                )
+            )?,
+            Error::Rekey(e) => writeln!(f, "{}", e)?,
             Error::SameInputAndOutput(filename) => writeln!(
                 f,
                 "{}",
@@ -273,8 +1104,25 @@ impl fmt::Debug for Error {
                     filename = filename.as_str()
                 )
             )?,
+            Error::UnnamedOutput(filename) => writeln!(
+                f,
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "err-unnamed-output",
+                    filename = filename.as_str()
+                )
+            )?,
         }
-        writeln!(f)?;
+        Ok(())
+    }
+}
+
+// `main` prints errors with `Debug` (like `fn main() -> Result<(), E: Debug>` would),
+// so we implement `Debug` manually to provide the error output we want.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self)?;
         writeln!(f, "[ {} ]", crate::fl!("err-ux-A"))?;
         write!(
             f,
@@ -0,0 +1,160 @@
+//! Printing the details of age file headers.
+
+use age::{
+    armor::ArmoredReader,
+    decryptor::{FileMetadata, HeaderParser, Progress},
+};
+use i18n_embed_fl::fl;
+use std::io::{self, Read};
+
+use crate::error::InspectError;
+
+/// The details of an age file that `rage --inspect` prints.
+pub(crate) struct Inspection {
+    /// Whether the file is armored.
+    armored: bool,
+    /// The length of the encrypted payload, after the header and nonce.
+    payload_len: u64,
+    /// What the header says about the file.
+    metadata: FileMetadata,
+}
+
+impl Inspection {
+    /// Reads the age file from `input`, which may be armored.
+    ///
+    /// The payload is read to find its length, but is not decrypted.
+    pub(crate) fn read<R: Read>(input: R) -> Result<Self, InspectError> {
+        let mut input = ArmoredReader::new(input);
+        let mut parser = HeaderParser::new();
+        let mut payload_len = 0;
+
+        let mut buf = [0; 8192];
+        loop {
+            let n = match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            // Once the header is complete, the parser consumes nothing.
+            if let Progress::Done { consumed } = parser.feed(&buf[..n])? {
+                payload_len += (n - consumed) as u64;
+            }
+        }
+
+        let decryptor = parser.finish(io::empty())?;
+        let file_len = (decryptor.peek_header_len() + 16) as u64 + payload_len;
+        Ok(Inspection {
+            armored: input.is_armored().unwrap_or(false),
+            payload_len,
+            metadata: decryptor.with_expected_len(file_len).peek_metadata(),
+        })
+    }
+
+    /// Prints the details as localized text.
+    pub(crate) fn print(&self) {
+        println!(
+            "{}",
+            fl!(
+                crate::LANGUAGE_LOADER,
+                "inspect-version",
+                version = self.metadata.version.as_str()
+            )
+        );
+        println!(
+            "{}",
+            if self.armored {
+                fl!(crate::LANGUAGE_LOADER, "inspect-armored")
+            } else {
+                fl!(crate::LANGUAGE_LOADER, "inspect-binary")
+            }
+        );
+        println!(
+            "{}",
+            fl!(
+                crate::LANGUAGE_LOADER,
+                "inspect-header",
+                len = self.metadata.header_len
+            )
+        );
+
+        println!(
+            "{}",
+            fl!(
+                crate::LANGUAGE_LOADER,
+                "inspect-recipients",
+                count = self.metadata.recipients.len()
+            )
+        );
+        for stanza in &self.metadata.recipients {
+            // Print each stanza as it appears in the header, without its body.
+            let mut line = format!("  -> {}", stanza.tag);
+            for arg in &stanza.args {
+                line.push(' ');
+                line.push_str(arg);
+            }
+            println!("{}", line);
+            if let Some(label) = &stanza.label {
+                println!(
+                    "{}",
+                    fl!(
+                        crate::LANGUAGE_LOADER,
+                        "inspect-stanza-label",
+                        label = label.as_str()
+                    )
+                );
+            }
+        }
+        if self.metadata.passphrase {
+            println!("{}", fl!(crate::LANGUAGE_LOADER, "inspect-passphrase"));
+        }
+
+        match (
+            self.metadata.chunk_count_hint,
+            self.metadata.plaintext_len_hint,
+        ) {
+            (Some(chunks), Some(plaintext_len)) => {
+                println!(
+                    "{}",
+                    fl!(
+                        crate::LANGUAGE_LOADER,
+                        "inspect-payload",
+                        len = self.payload_len,
+                        chunks = chunks
+                    )
+                );
+                println!(
+                    "{}",
+                    fl!(
+                        crate::LANGUAGE_LOADER,
+                        "inspect-plaintext",
+                        len = plaintext_len
+                    )
+                );
+            }
+            _ => println!(
+                "{}",
+                fl!(
+                    crate::LANGUAGE_LOADER,
+                    "inspect-payload-truncated",
+                    len = self.payload_len
+                )
+            ),
+        }
+
+        println!();
+        println!("{}", fl!(crate::LANGUAGE_LOADER, "inspect-unauthenticated"));
+    }
+
+    /// Prints the details as JSON.
+    ///
+    /// This is the serialization of [`FileMetadata`], with the additional fields
+    /// `armored` and `payload_len`.
+    pub(crate) fn print_json(&self) {
+        let mut value =
+            serde_json::to_value(&self.metadata).expect("FileMetadata can be serialized");
+        value["armored"] = self.armored.into();
+        value["payload_len"] = self.payload_len.into();
+        println!("{:#}", value);
+    }
+}
@@ -0,0 +1,107 @@
+//! Confirming the recipients with the user before encrypting (`--interactive`).
+
+use age::{cli_common::UiCallbacks, Callbacks};
+use i18n_embed_fl::fl;
+
+use crate::error::EncryptError;
+
+/// Where a recipient was read from.
+pub(crate) enum Source {
+    /// A `-r/--recipient` argument.
+    Flag,
+    /// A line of a recipients file, or of the keys fetched from a URL.
+    File {
+        filename: String,
+        line_number: usize,
+        label: Option<String>,
+    },
+    /// An identity file given with `-i/--identity`.
+    Identity(String),
+}
+
+/// A recipient that will be shown to the user before encrypting.
+pub(crate) struct ListedRecipient {
+    /// The recipient, or `None` if it can't be shown (such as for plugin identities,
+    /// which may contain secrets).
+    pub(crate) recipient: Option<String>,
+    pub(crate) source: Source,
+}
+
+impl ListedRecipient {
+    fn print(&self) {
+        match &self.recipient {
+            Some(recipient) => eprintln!("  {}", recipient),
+            None => eprintln!("  {}", fl!(crate::LANGUAGE_LOADER, "interactive-hidden")),
+        }
+        let source = match &self.source {
+            Source::Flag => fl!(crate::LANGUAGE_LOADER, "interactive-from-flag"),
+            Source::File {
+                filename,
+                line_number,
+                label: None,
+            } => fl!(
+                crate::LANGUAGE_LOADER,
+                "interactive-from-file",
+                filename = filename.as_str(),
+                line = (*line_number)
+            ),
+            Source::File {
+                filename,
+                line_number,
+                label: Some(label),
+            } => fl!(
+                crate::LANGUAGE_LOADER,
+                "interactive-from-file-label",
+                filename = filename.as_str(),
+                line = (*line_number),
+                label = label.as_str()
+            ),
+            Source::Identity(filename) => fl!(
+                crate::LANGUAGE_LOADER,
+                "interactive-from-identity",
+                filename = filename.as_str()
+            ),
+        };
+        eprintln!("    {}", source);
+    }
+}
+
+/// Prints `recipients`, and asks the user whether to encrypt to them.
+///
+/// Returns an error if the user declines, or if there is no terminal to ask on.
+pub(crate) fn confirm(
+    recipients: &[ListedRecipient],
+    threshold: Option<u8>,
+) -> Result<(), EncryptError> {
+    eprintln!(
+        "{}",
+        fl!(
+            crate::LANGUAGE_LOADER,
+            "interactive-recipients",
+            count = recipients.len()
+        )
+    );
+    for recipient in recipients {
+        recipient.print();
+    }
+    if let Some(threshold) = threshold {
+        eprintln!(
+            "{}",
+            fl!(
+                crate::LANGUAGE_LOADER,
+                "interactive-threshold",
+                threshold = threshold
+            )
+        );
+    }
+
+    match UiCallbacks.confirm(
+        &fl!(crate::LANGUAGE_LOADER, "interactive-confirm"),
+        &fl!(crate::LANGUAGE_LOADER, "interactive-confirm-yes"),
+        Some(&fl!(crate::LANGUAGE_LOADER, "interactive-confirm-no")),
+    ) {
+        Some(true) => Ok(()),
+        Some(false) => Err(EncryptError::RecipientsDeclined),
+        None => Err(EncryptError::InteractiveWithoutTerminal),
+    }
+}
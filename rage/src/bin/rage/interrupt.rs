@@ -0,0 +1,90 @@
+//! Ctrl-C handling.
+
+use lazy_static::lazy_static;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+/// The conventional exit code for a process terminated by SIGINT (128 + 2).
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+lazy_static! {
+    /// The temporary files that output is being written to.
+    static ref UNCOMMITTED: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+}
+
+/// Records the temporary file that an output is being written to, so that it is removed
+/// if we are interrupted before the output is committed.
+///
+/// Committing an output renames its temporary file away, and dropping it removes the
+/// file, so paths are never unregistered: removing them again is harmless.
+pub(crate) fn track_uncommitted(tmp_path: &Path) {
+    if let Ok(mut paths) = UNCOMMITTED.lock() {
+        paths.push(tmp_path.to_owned());
+    }
+}
+
+fn remove_uncommitted() {
+    if let Ok(paths) = UNCOMMITTED.lock() {
+        for path in paths.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Installs a Ctrl-C handler that cleans up after `rage` before exiting.
+///
+/// On interrupt, the handler:
+/// - restores the terminal to its state at startup (an interrupted passphrase prompt
+///   otherwise leaves echo disabled);
+/// - removes any partially-written output file;
+/// - exits with code 130.
+pub(crate) fn install_handler() {
+    #[cfg(unix)]
+    let saved_tty = tty::SavedState::capture();
+
+    let res = ctrlc::set_handler(move || {
+        #[cfg(unix)]
+        if let Some(tty) = &saved_tty {
+            tty.restore();
+        }
+
+        remove_uncommitted();
+
+        // Move past any prompt that was being displayed.
+        eprintln!();
+        process::exit(INTERRUPTED_EXIT_CODE);
+    });
+
+    if let Err(e) = res {
+        // Without the handler, Ctrl-C still terminates us; we just can't clean up.
+        log::warn!("Could not set Ctrl-C handler: {}", e);
+    }
+}
+
+#[cfg(unix)]
+mod tty {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+    use termios::{tcsetattr, Termios, TCSANOW};
+
+    /// The state of the controlling terminal, which passphrase prompts read from.
+    pub(super) struct SavedState {
+        tty: File,
+        termios: Termios,
+    }
+
+    impl SavedState {
+        /// Captures the current state of the controlling terminal, if there is one.
+        pub(super) fn capture() -> Option<Self> {
+            let tty = File::open("/dev/tty").ok()?;
+            let termios = Termios::from_fd(tty.as_raw_fd()).ok()?;
+            Some(SavedState { tty, termios })
+        }
+
+        pub(super) fn restore(&self) {
+            let _ = tcsetattr(self.tty.as_raw_fd(), TCSANOW, &self.termios);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Processing several files at once with `--jobs N`.
+
+use std::panic;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc, Mutex,
+};
+use std::thread;
+
+/// Runs `work` on each of `tasks`, on up to `jobs` threads (or on the calling thread if
+/// `jobs` is 1). No more threads are started than there are tasks, or than the system
+/// can run in parallel.
+///
+/// The tasks are produced on the calling thread, so that anything they need from the
+/// user (such as a passphrase) is only asked for there. Once a task fails, no more
+/// tasks are started; the tasks that were already started are finished, and then the
+/// first error is returned.
+pub(crate) fn run<T, E, I, F>(jobs: usize, tasks: I, work: F) -> Result<(), E>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+    I: IntoIterator<Item = Result<T, E>>,
+    F: Fn(T) -> Result<(), E> + Send + Sync + 'static,
+{
+    let jobs = match thread::available_parallelism() {
+        Ok(parallelism) => jobs.min(parallelism.get()),
+        Err(_) => jobs,
+    };
+
+    if jobs <= 1 {
+        for task in tasks {
+            work(task?)?;
+        }
+        return Ok(());
+    }
+
+    let work = Arc::new(work);
+    let failed = Arc::new(AtomicBool::new(false));
+    // Tasks are handed straight to an idle worker, so none are left queued when one
+    // fails.
+    let (task_tx, task_rx) = mpsc::sync_channel::<T>(0);
+    let mut task_rx = Some(Arc::new(Mutex::new(task_rx)));
+    let (err_tx, err_rx) = mpsc::channel();
+
+    let mut workers = vec![];
+    let mut result = Ok(());
+    for task in tasks {
+        if failed.load(Ordering::SeqCst) {
+            break;
+        }
+        let task = match task {
+            Ok(task) => task,
+            Err(e) => {
+                failed.store(true, Ordering::SeqCst);
+                result = Err(e);
+                break;
+            }
+        };
+
+        // Start a worker for each task, until there are `jobs` of them. We then stop
+        // holding the receiver, so that sending fails if every worker has panicked.
+        if let Some(rx) = &task_rx {
+            let work = work.clone();
+            let failed = failed.clone();
+            let rx = rx.clone();
+            let err_tx = err_tx.clone();
+            workers.push(thread::spawn(move || loop {
+                // Release the lock before working on the task.
+                let task = rx.lock().expect("not poisoned").recv();
+                match task {
+                    // Skip any task that was sent before another one failed.
+                    Ok(_) if failed.load(Ordering::SeqCst) => (),
+                    Ok(task) => {
+                        if let Err(e) = work(task) {
+                            failed.store(true, Ordering::SeqCst);
+                            let _ = err_tx.send(e);
+                        }
+                    }
+                    // All tasks have been sent.
+                    Err(_) => break,
+                }
+            }));
+            if workers.len() == jobs {
+                task_rx = None;
+            }
+        }
+
+        if task_tx.send(task).is_err() {
+            // Every worker has panicked; we propagate that below.
+            break;
+        }
+    }
+    drop(task_tx);
+    drop(err_tx);
+
+    for worker in workers {
+        if let Err(payload) = worker.join() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    result.and_then(|()| match err_rx.try_recv() {
+        Ok(e) => Err(e),
+        Err(_) => Ok(()),
+    })
+}
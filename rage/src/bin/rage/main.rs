@@ -3,24 +3,48 @@
 use age::{
     armor::{ArmoredReader, ArmoredWriter, Format},
     cli_common::{
-        file_io, read_identities, read_or_generate_passphrase, read_secret, Passphrase, UiCallbacks,
+        file_io, normalize_passphrase, read_identities_with_allowlist,
+        read_or_generate_passphrase_with, read_passphrase_from_env, read_secret,
+        recipients_file::read_recipients_file,
+        secret_sink::{Clipboard, CommandSink, SecretSink},
+        BatchCallbacks, Passphrase, PassphraseGenerator, UiCallbacks, Wordlist,
+        CHARSET_ALPHANUMERIC, CHARSET_LOWERCASE_ALPHANUMERIC, CHARSET_PRINTABLE,
+        DEFAULT_PASSPHRASE_ENTROPY_BITS,
     },
     plugin,
-    secrecy::ExposeSecret,
-    Identity, IdentityFile, IdentityFileEntry, Recipient,
+    secrecy::{ExposeSecret, SecretString},
+    Callbacks, EncryptorBuilder, Identity, IdentityFile, IdentityFileEntry, Recipient,
 };
+use age_core::format::{FileKey, Stanza};
 use gumdrop::{Options, ParsingStyle};
 use i18n_embed::{
     fluent::{fluent_language_loader, FluentLanguageLoader},
+    unic_langid::LanguageIdentifier,
     DesktopLanguageRequester,
 };
 use lazy_static::lazy_static;
 use rust_embed::RustEmbed;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-
+use std::sync::{Arc, Mutex};
+
+mod archive;
+#[cfg(feature = "cli-docs")]
+#[path = "../../cli.rs"]
+mod cli;
+#[cfg(unix)]
+mod daemon;
+mod defaults;
 mod error;
+mod inspect;
+mod interactive;
+mod interrupt;
+mod jobs;
+mod report;
+
+#[cfg(feature = "http")]
+mod remote;
 
 #[derive(RustEmbed)]
 #[folder = "i18n"]
@@ -41,18 +65,13 @@ macro_rules! fl {
 
 macro_rules! warning {
     ($warning_id:literal) => {{
-        eprintln!(
-            "{}",
-            i18n_embed_fl::fl!(
-                $crate::LANGUAGE_LOADER,
-                "warning-msg",
-                warning = fl!($warning_id)
-            )
-        );
+        $crate::report::warning($warning_id, fl!($warning_id));
     }};
 }
 
 /// Parses a recipient from a string.
+// With some feature flags disabled, several branches reduce to `None`.
+#[allow(clippy::ifs_same_cond)]
 fn parse_recipient(
     filename: &str,
     s: String,
@@ -72,6 +91,18 @@ fn parse_recipient(
                         age::ssh::UnsupportedKey::Type(key_type),
                     ))
                 }
+                Err(age::ssh::ParseRecipientKeyError::UnsupportedRsaKeySize(bits)) => {
+                    return Err(error::EncryptError::UnsupportedKey(
+                        filename.to_string(),
+                        age::ssh::UnsupportedKey::RsaKeySize(bits),
+                    ))
+                }
+                Err(age::ssh::ParseRecipientKeyError::Dsa) => {
+                    return Err(error::EncryptError::UnsupportedKey(
+                        filename.to_string(),
+                        age::ssh::UnsupportedKey::Dsa,
+                    ))
+                }
                 _ => None,
             }
         }
@@ -80,6 +111,36 @@ fn parse_recipient(
         None
     } {
         recipients.push(pk);
+    } else if let Some(pk) = {
+        #[cfg(feature = "pq")]
+        {
+            s.parse::<age::pq::Recipient>().ok()
+        }
+
+        #[cfg(not(feature = "pq"))]
+        None::<age::x25519::Recipient>
+    } {
+        recipients.push(Box::new(pk));
+    } else if let Some(pk) = {
+        #[cfg(feature = "yubikey")]
+        {
+            s.parse::<age::yubikey::Recipient>().ok()
+        }
+
+        #[cfg(not(feature = "yubikey"))]
+        None::<age::x25519::Recipient>
+    } {
+        recipients.push(Box::new(pk));
+    } else if let Some(pk) = {
+        #[cfg(feature = "fido2")]
+        {
+            age::fido2::Recipient::new(&s, UiCallbacks).ok()
+        }
+
+        #[cfg(not(feature = "fido2"))]
+        None::<age::x25519::Recipient>
+    } {
+        recipients.push(Box::new(pk));
     } else if let Ok(recipient) = s.parse::<plugin::Recipient>() {
         plugin_recipients.push(recipient);
     } else {
@@ -95,17 +156,27 @@ fn read_recipients_list<R: BufRead>(
     buf: R,
     recipients: &mut Vec<Box<dyn Recipient + Send>>,
     plugin_recipients: &mut Vec<plugin::Recipient>,
-) -> io::Result<()> {
-    for (line_number, line) in buf.lines().enumerate() {
-        let line = line?;
-
-        // Skip empty lines and comments
-        if line.is_empty() || line.find('#') == Some(0) {
-            continue;
-        } else if let Err(e) = parse_recipient(filename, line, recipients, plugin_recipients) {
+    listing: &mut Vec<interactive::ListedRecipient>,
+) -> Result<(), error::EncryptError> {
+    for line in read_recipients_file(filename, buf)? {
+        let line_number = line.line_number();
+        listing.push(interactive::ListedRecipient {
+            recipient: Some(line.recipient().to_owned()),
+            source: interactive::Source::File {
+                filename: filename.to_owned(),
+                line_number,
+                label: line.label().map(|label| label.to_owned()),
+            },
+        });
+        if let Err(e) = parse_recipient(
+            filename,
+            line.recipient().to_owned(),
+            recipients,
+            plugin_recipients,
+        ) {
             #[cfg(feature = "ssh")]
             if matches!(e, error::EncryptError::UnsupportedKey(_, _)) {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()).into());
             }
 
             // Return a line number in place of the line, so we don't leak the file
@@ -114,10 +185,10 @@ fn read_recipients_list<R: BufRead>(
                 io::ErrorKind::InvalidData,
                 format!(
                     "recipients file {} contains non-recipient data on line {}",
-                    filename,
-                    line_number + 1
+                    filename, line_number
                 ),
-            ));
+            )
+            .into());
         }
     }
 
@@ -125,27 +196,54 @@ fn read_recipients_list<R: BufRead>(
 }
 
 /// Reads recipients from the provided arguments.
+///
+/// If `separate_plugins` is set, each plugin recipient or identity gets its own
+/// [`plugin::RecipientPluginV1`], so that every returned recipient is a single key.
+///
+/// Each recipient that is read is also added to `listing`, along with where it was
+/// read from, so that it can be shown to the user.
 fn read_recipients(
     recipient_strings: Vec<String>,
     recipients_file_strings: Vec<String>,
     identity_strings: Vec<String>,
     max_work_factor: Option<u8>,
+    separate_plugins: bool,
+    listing: &mut Vec<interactive::ListedRecipient>,
 ) -> Result<Vec<Box<dyn Recipient + Send>>, error::EncryptError> {
     let mut recipients: Vec<Box<dyn Recipient + Send>> = vec![];
     let mut plugin_recipients: Vec<plugin::Recipient> = vec![];
     let mut plugin_identities: Vec<plugin::Identity> = vec![];
 
     for arg in recipient_strings {
+        #[cfg(feature = "http")]
+        if let Some(url) = remote::recipients_url(&arg) {
+            let url = url?;
+            let buf = remote::fetch(&url)?;
+            read_recipients_list(&url, buf, &mut recipients, &mut plugin_recipients, listing)?;
+            continue;
+        }
+
+        listing.push(interactive::ListedRecipient {
+            recipient: Some(arg.clone()),
+            source: interactive::Source::Flag,
+        });
         parse_recipient("", arg, &mut recipients, &mut plugin_recipients)?;
     }
 
     for arg in recipients_file_strings {
         let f = File::open(&arg)?;
         let buf = BufReader::new(f);
-        read_recipients_list(&arg, buf, &mut recipients, &mut plugin_recipients)?;
+        read_recipients_list(&arg, buf, &mut recipients, &mut plugin_recipients, listing)?;
     }
 
     for filename in identity_strings {
+        let mut list = |recipient: Option<String>| {
+            listing.push(interactive::ListedRecipient {
+                recipient,
+                source: interactive::Source::Identity(filename.clone()),
+            })
+        };
+
         // Try parsing as an encrypted age identity.
         if let Ok(identity) = age::encrypted::Identity::from_buffer(
             ArmoredReader::new(BufReader::new(File::open(&filename)?)),
@@ -154,7 +252,12 @@ fn read_recipients(
             max_work_factor,
         ) {
             if let Some(identity) = identity {
-                recipients.extend(identity.recipients()?);
+                let identity = identity.with_plugin_allowlist(defaults::plugin_allowlist()?);
+                // The recipients of an encrypted identity can't be displayed.
+                for recipient in identity.recipients()? {
+                    list(None);
+                    recipients.push(recipient);
+                }
                 continue;
             } else {
                 return Err(error::EncryptError::IdentityEncryptedWithoutPassphrase(
@@ -172,25 +275,60 @@ fn read_recipients(
             Ok(age::ssh::Identity::Unsupported(k)) => {
                 return Err(error::EncryptError::UnsupportedKey(filename, k))
             }
-            Ok(identity) => {
-                if let Ok(recipient) = age::ssh::Recipient::try_from(identity) {
+            Ok(identity) => match age::ssh::Recipient::try_from(identity) {
+                Ok(recipient) => {
+                    list(Some(recipient.to_string()));
                     recipients.push(Box::new(recipient));
                     continue;
                 }
-            }
+                Err(age::ssh::ParseRecipientKeyError::UnsupportedRsaKeySize(bits)) => {
+                    return Err(error::EncryptError::UnsupportedKey(
+                        filename,
+                        age::ssh::UnsupportedKey::RsaKeySize(bits),
+                    ))
+                }
+                Err(_) => (),
+            },
             Err(_) => (),
         }
 
         // Try parsing as multiple single-line age identities.
         let identity_file =
             IdentityFile::from_file(filename.clone()).map_err(|e| match e.kind() {
-                io::ErrorKind::NotFound => error::EncryptError::IdentityNotFound(filename),
+                io::ErrorKind::NotFound => error::EncryptError::IdentityNotFound(filename.clone()),
                 _ => e.into(),
             })?;
         for entry in identity_file.into_identities() {
             match entry {
-                IdentityFileEntry::Native(i) => recipients.push(Box::new(i.to_public())),
-                IdentityFileEntry::Plugin(i) => plugin_identities.push(i),
+                IdentityFileEntry::Native(i) => {
+                    let recipient = i.to_public();
+                    list(Some(recipient.to_string()));
+                    recipients.push(Box::new(recipient));
+                }
+                IdentityFileEntry::Plugin(i) => {
+                    list(None);
+                    plugin_identities.push(i);
+                }
+                #[cfg(feature = "pq")]
+                IdentityFileEntry::Pq(i) => {
+                    let recipient = i.to_public();
+                    list(Some(recipient.to_string()));
+                    recipients.push(Box::new(recipient));
+                }
+                #[cfg(feature = "fido2")]
+                IdentityFileEntry::Fido2(stub) => {
+                    list(None);
+                    recipients.push(Box::new(
+                        age::fido2::Identity::from_stub(stub, UiCallbacks).to_recipient(),
+                    ));
+                }
+                #[cfg(feature = "yubikey")]
+                IdentityFileEntry::YubiKey(stub) => {
+                    let recipient =
+                        age::yubikey::Identity::from_stub(stub, UiCallbacks).to_public()?;
+                    list(Some(recipient.to_string()));
+                    recipients.push(Box::new(recipient));
+                }
             }
         }
     }
@@ -204,87 +342,395 @@ fn read_recipients(
     plugin_names.sort_unstable();
     plugin_names.dedup();
 
-    // Find the required plugins.
-    for plugin_name in plugin_names {
-        recipients.push(Box::new(plugin::RecipientPluginV1::new(
-            plugin_name,
-            &plugin_recipients,
-            &plugin_identities,
-            UiCallbacks,
-        )?))
+    // Find the required plugins. Recipients can come from untrusted sources, so only
+    // run plugins that the user has allowed.
+    if !plugin_names.is_empty() {
+        let allowlist = defaults::plugin_allowlist()?;
+        if separate_plugins {
+            for recipient in &plugin_recipients {
+                recipients.push(Box::new(plugin::RecipientPluginV1::with_allowlist(
+                    recipient.plugin(),
+                    std::slice::from_ref(recipient),
+                    &[],
+                    UiCallbacks,
+                    &allowlist,
+                )?))
+            }
+            for identity in &plugin_identities {
+                recipients.push(Box::new(plugin::RecipientPluginV1::with_allowlist(
+                    identity.plugin(),
+                    &[],
+                    std::slice::from_ref(identity),
+                    UiCallbacks,
+                    &allowlist,
+                )?))
+            }
+        } else {
+            for plugin_name in plugin_names {
+                recipients.push(Box::new(plugin::RecipientPluginV1::with_allowlist(
+                    plugin_name,
+                    &plugin_recipients,
+                    &plugin_identities,
+                    UiCallbacks,
+                    &allowlist,
+                )?))
+            }
+        }
     }
 
     Ok(recipients)
 }
 
+/// Recipients that can be shared by several encryptors.
+#[derive(Clone)]
+struct SharedRecipients(Arc<Mutex<Vec<Box<dyn Recipient + Send>>>>);
+
+impl SharedRecipients {
+    fn new(recipients: Vec<Box<dyn Recipient + Send>>) -> Self {
+        SharedRecipients(Arc::new(Mutex::new(recipients)))
+    }
+}
+
+impl Recipient for SharedRecipients {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, age::EncryptError> {
+        let recipients = self.0.lock().expect("not poisoned");
+        let mut stanzas = vec![];
+        for recipient in recipients.iter() {
+            stanzas.append(&mut recipient.wrap_file_key(file_key)?);
+        }
+        Ok(stanzas)
+    }
+}
+
 #[derive(Debug, Options)]
+#[cfg_attr(feature = "cli-docs", derive(clap::Parser))]
+#[cfg_attr(
+    feature = "cli-docs",
+    clap(
+        name = "rage",
+        about = "A simple, secure, and modern encryption tool",
+        disable_help_flag = true,
+        disable_version_flag = true
+    )
+)]
 struct AgeOptions {
-    #[options(free, help = "Path to a file to read from.")]
+    /// Path to a file to read from.
+    #[options(free)]
+    #[cfg_attr(feature = "cli-docs", clap(value_hint = clap::ValueHint::AnyPath))]
     input: Option<String>,
 
-    #[options(help = "Print this help message and exit.")]
+    /// Paths to further files to read from.
+    #[options(free)]
+    #[cfg_attr(feature = "cli-docs", clap(value_hint = clap::ValueHint::AnyPath))]
+    more_inputs: Vec<String>,
+
+    /// Print this help message and exit.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     help: bool,
 
-    #[options(help = "Print version info and exit.", short = "V")]
+    /// Print version info and exit.
+    #[options(short = "V")]
+    #[cfg_attr(feature = "cli-docs", clap(short = 'V', long))]
     version: bool,
 
-    #[options(help = "Encrypt the input (the default).")]
+    /// Show messages in LANG (such as es-AR) instead of the system language.
+    #[options(meta = "LANG", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "LANG"))]
+    lang: Option<LanguageIdentifier>,
+
+    /// Encrypt the input (the default).
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     encrypt: bool,
 
-    #[options(help = "Decrypt the input.")]
+    /// Decrypt the input.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     decrypt: bool,
 
-    #[options(help = "Encrypt with a passphrase instead of recipients.")]
+    /// Decrypt the input to check that it is intact, without writing the plaintext.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    verify_only: bool,
+
+    /// Encrypt with a passphrase instead of recipients.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     passphrase: bool,
 
-    #[options(
-        help = "Maximum work factor to allow for passphrase decryption.",
-        meta = "WF",
-        no_short
-    )]
+    /// Read the passphrase from file descriptor FD instead of the terminal.
+    #[options(meta = "FD", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "FD"))]
+    passphrase_fd: Option<u32>,
+
+    /// Maximum work factor to allow for passphrase decryption.
+    #[options(meta = "WF", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "WF"))]
     max_work_factor: Option<u8>,
 
-    #[options(help = "Encrypt to a PEM encoded format.")]
+    /// Normalize the passphrase (Unicode NFKC, lowercase, single spaces) before use.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    normalize_passphrase: bool,
+
+    /// Generate a passphrase of N words (default: enough for 110 bits of entropy).
+    #[options(meta = "N", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "N"))]
+    words: Option<usize>,
+
+    /// Generate a passphrase from the wordlist at PATH instead of your language's.
+    #[options(meta = "PATH", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "PATH"))]
+    wordlist: Option<String>,
+
+    /// Generate a passphrase of characters from CHARSET instead of words.
+    #[options(meta = "CHARSET", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "CHARSET"))]
+    charset: Option<String>,
+
+    /// Generate a passphrase of N characters from CHARSET.
+    #[options(meta = "N", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "N"))]
+    chars: Option<usize>,
+
+    /// Copy an autogenerated passphrase to the clipboard instead of printing it.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    passphrase_to_clipboard: bool,
+
+    /// Write an autogenerated passphrase to the input of COMMAND instead of printing it.
+    #[options(meta = "COMMAND", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "COMMAND"))]
+    passphrase_sink: Option<String>,
+
+    /// Encrypt to a PEM encoded format.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     armor: bool,
 
-    #[options(help = "Encrypt to the specified RECIPIENT. May be repeated.")]
+    #[cfg(feature = "qr")]
+    /// Encrypt to a PEM encoded format, and write it as a QR code for the terminal.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    qr: bool,
+
+    /// Encrypt to the specified RECIPIENT. May be repeated.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     recipient: Vec<String>,
 
-    #[options(
-        help = "Encrypt to the recipients listed at PATH. May be repeated.",
-        meta = "PATH"
-    )]
+    /// Encrypt to the recipients listed at PATH. May be repeated.
+    #[options(short = "R", meta = "PATH")]
+    #[cfg_attr(feature = "cli-docs", clap(short = 'R', long, value_name = "PATH"))]
     recipients_file: Vec<String>,
 
-    #[options(help = "Use the identity file at IDENTITY. May be repeated.")]
+    /// Require any K of the recipients to decrypt together.
+    #[options(meta = "K", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "K"))]
+    threshold: Option<u8>,
+
+    /// Show the recipients and ask for confirmation before encrypting.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    interactive: bool,
+
+    /// Use the identity file at IDENTITY. May be repeated.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     identity: Vec<String>,
 
-    #[options(
-        help = "Use age-plugin-PLUGIN-NAME in its default mode as an identity.",
-        no_long,
-        short = "j"
+    /// Don't use the default identity files when decrypting.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    no_default_identities: bool,
+
+    /// Use age-plugin-PLUGIN-NAME in its default mode as an identity.
+    #[options(no_long, short = "j", meta = "PLUGIN-NAME")]
+    #[cfg_attr(
+        feature = "cli-docs",
+        clap(short = 'j', value_name = "PLUGIN-NAME", required = false)
     )]
     plugin_name: String,
 
-    #[options(help = "Write the result to the file at path OUTPUT.")]
+    /// Write the result to the file at path OUTPUT.
+    #[cfg_attr(feature = "cli-docs", clap(short, long))]
     output: Option<String>,
+
+    /// Create output files with the Unix permissions MODE (such as 600).
+    #[options(meta = "MODE", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "MODE"))]
+    mode: Option<file_io::Mode>,
+
+    /// Write decrypted output even where other users could read it.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    force: bool,
+
+    /// Write the result for each INPUT into the directory DIR.
+    #[options(meta = "DIR", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "DIR"))]
+    output_dir: Option<String>,
+
+    /// Encrypt or decrypt up to N of the INPUT files at once.
+    #[options(meta = "N", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "N"))]
+    jobs: Option<usize>,
+
+    /// Encrypt the directory INPUT as a tar archive, or extract one into OUTPUT.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    archive: bool,
+
+    /// Re-encrypt the file INPUT in place to new recipients.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    rekey: bool,
+
+    /// Check that INPUT is well-formed armor, without decrypting it.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    check_armor: bool,
+
+    /// Print the details of the header of INPUT, without decrypting it.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    inspect: bool,
+
+    /// Print the details from --inspect as JSON.
+    #[options(no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long))]
+    json: bool,
+
+    /// Report results and errors as FORMAT: text (the default) or json.
+    #[options(meta = "FORMAT", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "FORMAT"))]
+    format: Option<report::Format>,
+
+    /// Serve encryption and decryption requests on the Unix socket at SOCKET.
+    #[options(meta = "SOCKET", no_short)]
+    #[cfg_attr(feature = "cli-docs", clap(long, value_name = "SOCKET"))]
+    daemon: Option<String>,
 }
 
 fn set_up_io(
     input: Option<String>,
     output: Option<String>,
     output_format: file_io::OutputFormat,
+    mode: Option<file_io::Mode>,
 ) -> io::Result<(file_io::InputReader, file_io::OutputWriter)> {
     let input = file_io::InputReader::new(input)?;
 
     // Create an output to the user-requested location.
-    let output = file_io::OutputWriter::new(output, output_format, 0o666, input.is_terminal())?;
+    let output = open_output(output, output_format, mode, input.is_terminal())?;
 
     Ok((input, output))
 }
 
-type ReadCheckerMatchCase = (&'static [u8], Box<dyn FnOnce() -> io::Result<()>>);
-type ReadCheckerMatcher = Option<(&'static [u8], usize, Box<dyn FnOnce() -> io::Result<()>>)>;
+/// Opens an output, creating files with the permissions given by `--mode`.
+///
+/// Without `--mode`, new files get the default permissions (subject to the umask), and
+/// replaced files keep their own.
+fn open_output(
+    output: Option<String>,
+    output_format: file_io::OutputFormat,
+    mode: Option<file_io::Mode>,
+    input_is_tty: bool,
+) -> io::Result<file_io::OutputWriter> {
+    let mut output = file_io::OutputWriter::new(
+        output,
+        output_format,
+        mode.map_or(0o666, |mode| mode.0),
+        input_is_tty,
+    )?;
+    output.on_tmp_file(interrupt::track_uncommitted);
+    if mode.is_some() {
+        output.use_new_permissions();
+    }
+    Ok(output)
+}
+
+/// The inputs to encrypt or decrypt, and the output to write for each of them. `None`
+/// means standard input or output.
+type IoPaths = Vec<(Option<String>, Option<String>)>;
+
+/// Returns the [`IoPaths`] for `rage --encrypt` or `rage --decrypt`.
+///
+/// With several inputs, or with `--output-dir`, each output is named after its input:
+/// `.age` is appended when encrypting, and removed when decrypting. Otherwise this is
+/// just `INPUT` and `OUTPUT`. With `--verify-only`, the outputs are unused.
+fn io_paths(opts: &AgeOptions, decrypt: bool) -> Result<IoPaths, error::Error> {
+    // Nothing is written with `--verify-only`, so the inputs don't need outputs.
+    if opts.verify_only {
+        if opts.more_inputs.is_empty() {
+            return Ok(vec![(opts.input.clone(), None)]);
+        }
+        let inputs = opts.input.iter().chain(opts.more_inputs.iter());
+        if inputs.clone().any(|input| input == "-") {
+            return Err(error::Error::MultipleInputsStdin);
+        }
+        return Ok(inputs.map(|input| (Some(input.clone()), None)).collect());
+    }
+
+    if opts.more_inputs.is_empty() && opts.output_dir.is_none() {
+        return Ok(vec![(opts.input.clone(), opts.output.clone())]);
+    }
+    if opts.output.is_some() {
+        return Err(error::Error::OutputWithMultipleInputs);
+    }
+
+    let inputs = opts
+        .input
+        .iter()
+        .chain(opts.more_inputs.iter())
+        .collect::<Vec<_>>();
+    if inputs.is_empty() || inputs.iter().any(|input| *input == "-") {
+        return Err(error::Error::MultipleInputsStdin);
+    }
+    let canonical_inputs = inputs
+        .iter()
+        .filter_map(|input| Path::new(input).canonicalize().ok())
+        .collect::<Vec<_>>();
+
+    inputs
+        .into_iter()
+        .map(|input| {
+            let path = Path::new(input);
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| {
+                    if decrypt {
+                        name.strip_suffix(".age")
+                            .filter(|name| !name.is_empty())
+                            .map(|name| name.to_owned())
+                    } else {
+                        Some(format!("{}.age", name))
+                    }
+                })
+                .ok_or_else(|| error::Error::UnnamedOutput(input.clone()))?;
+
+            let output = match &opts.output_dir {
+                Some(dir) => Path::new(dir).join(file_name),
+                None => path.with_file_name(file_name),
+            };
+
+            // Don't overwrite one of the inputs before we read it.
+            if let Ok(canonical) = output.canonicalize() {
+                if canonical_inputs.contains(&canonical) {
+                    return Err(error::Error::SameInputAndOutput(
+                        output.to_string_lossy().into_owned(),
+                    ));
+                }
+            }
+
+            Ok((
+                Some(input.clone()),
+                Some(output.to_string_lossy().into_owned()),
+            ))
+        })
+        .collect()
+}
+
+type ReadCheckerMatchCase = (&'static [u8], Box<dyn FnOnce() -> io::Result<()> + Send>);
+type ReadCheckerMatcher = Option<(
+    &'static [u8],
+    usize,
+    Box<dyn FnOnce() -> io::Result<()> + Send>,
+)>;
 
 /// A wrapper around a reader that checks it for various prefixes.
 struct ReadChecker<R: io::Read, const N: usize> {
@@ -326,12 +772,168 @@ impl<R: io::Read, const N: usize> io::Read for ReadChecker<R, N> {
     }
 }
 
-fn encrypt(opts: AgeOptions) -> Result<(), error::EncryptError> {
+/// The environment variable from which a passphrase can be read for non-interactive use.
+const PASSPHRASE_ENV_VAR: &str = "RAGE_PASSPHRASE";
+
+/// Reads the passphrase provided for non-interactive use with `--passphrase-fd` or
+/// `$RAGE_PASSPHRASE`, if any.
+///
+/// This always removes `$RAGE_PASSPHRASE` from our environment, so that plugins don't
+/// inherit it.
+fn batch_callbacks(passphrase_fd: Option<u32>) -> io::Result<Option<BatchCallbacks>> {
+    let from_env = read_passphrase_from_env(PASSPHRASE_ENV_VAR)?;
+
+    let passphrase = match passphrase_fd {
+        #[cfg(unix)]
+        Some(fd) => Some(age::cli_common::read_passphrase_from_fd(fd)?),
+        #[cfg(not(unix))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                fl!("err-passphrase-fd-unsupported"),
+            ))
+        }
+        None => {
+            if from_env.is_some() {
+                warning!("warn-passphrase-env");
+            }
+            from_env
+        }
+    };
+
+    Ok(passphrase.map(BatchCallbacks::new))
+}
+
+/// Returns where an autogenerated passphrase should be stored instead of being printed,
+/// if anywhere.
+fn passphrase_sink(opts: &AgeOptions) -> Result<Option<Box<dyn SecretSink>>, error::EncryptError> {
+    match (opts.passphrase_to_clipboard, &opts.passphrase_sink) {
+        (true, Some(_)) => Err(error::EncryptError::MultiplePassphraseSinks),
+        (true, None) => Ok(Some(Box::new(Clipboard))),
+        (false, Some(command)) => CommandSink::new(command)
+            .map(|sink| Some(Box::new(sink) as Box<dyn SecretSink>))
+            .ok_or(error::EncryptError::EmptyPassphraseSink),
+        (false, None) => Ok(None),
+    }
+}
+
+/// Builds the generator for autogenerated passphrases from the command-line flags.
+fn passphrase_generator(opts: &AgeOptions) -> Result<PassphraseGenerator, error::EncryptError> {
+    let generator = if opts.charset.is_some() || opts.chars.is_some() {
+        if opts.words.is_some() || opts.wordlist.is_some() {
+            return Err(error::EncryptError::MixedWordsAndCharset);
+        }
+
+        let charset = match opts.charset.as_deref().unwrap_or("alphanumeric") {
+            "alphanumeric" => CHARSET_ALPHANUMERIC,
+            "lowercase" => CHARSET_LOWERCASE_ALPHANUMERIC,
+            "printable" => CHARSET_PRINTABLE,
+            charset => return Err(error::EncryptError::UnknownCharset(charset.into())),
+        };
+        let count = match opts.chars {
+            Some(0) => return Err(error::EncryptError::PassphraseLengthZero),
+            Some(count) => count,
+            // Match the strength of the default generator.
+            None => {
+                let bits_per_char = (charset.chars().count() as f64).log2();
+                (DEFAULT_PASSPHRASE_ENTROPY_BITS / bits_per_char).ceil() as usize
+            }
+        };
+        PassphraseGenerator::chars(charset, count).expect("charset is valid")
+    } else {
+        let wordlist = match &opts.wordlist {
+            Some(path) => Wordlist::from_lines(&fs::read_to_string(path)?)
+                .ok_or_else(|| error::EncryptError::InvalidWordlist(path.clone()))?,
+            None => Wordlist::localized(),
+        };
+        match opts.words {
+            Some(0) => return Err(error::EncryptError::PassphraseLengthZero),
+            Some(count) => PassphraseGenerator::words(wordlist, count),
+            // Match the strength of the default generator, whatever the wordlist.
+            None => {
+                PassphraseGenerator::words_with_entropy(wordlist, DEFAULT_PASSPHRASE_ENTROPY_BITS)
+            }
+        }
+    };
+
+    if opts.normalize_passphrase && !generator.is_normalized() {
+        return Err(error::EncryptError::GeneratorNotNormalized);
+    }
+
+    Ok(generator)
+}
+
+/// The passphrase or recipients that `rage --encrypt` encrypts each input to.
+enum EncryptTo {
+    Passphrase(SecretString),
+    Recipients(SharedRecipients),
+    /// Any `threshold` of the recipients, each of which is a single key.
+    Threshold(u8, Vec<SharedRecipients>),
+}
+
+impl EncryptTo {
+    fn encryptor(&self, armor: bool) -> age::Encryptor {
+        let builder = match self {
+            EncryptTo::Passphrase(passphrase) => {
+                EncryptorBuilder::new().passphrase(passphrase.clone())
+            }
+            EncryptTo::Recipients(recipients) => {
+                EncryptorBuilder::new().recipient(Box::new(recipients.clone()))
+            }
+            EncryptTo::Threshold(threshold, recipients) => EncryptorBuilder::new()
+                .recipients(
+                    recipients
+                        .iter()
+                        .map(|r| Box::new(r.clone()) as Box<dyn Recipient + Send>),
+                )
+                .threshold(*threshold),
+        };
+        builder
+            .armor(if armor {
+                Format::AsciiArmor
+            } else {
+                Format::Binary
+            })
+            .build()
+            .expect("recipients and threshold were checked")
+    }
+}
+
+/// Checks that `--threshold` can be used with this many recipients.
+fn check_threshold(threshold: u8, recipients: usize) -> Result<(), error::EncryptError> {
+    if threshold == 0 {
+        Err(error::EncryptError::ThresholdZero)
+    } else if usize::from(threshold) > recipients || recipients > 255 {
+        Err(error::EncryptError::InvalidThreshold {
+            threshold,
+            recipients,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn encrypt(
+    opts: AgeOptions,
+    paths: IoPaths,
+    batch: Option<BatchCallbacks>,
+) -> Result<(), error::EncryptError> {
     if !opts.plugin_name.is_empty() {
         return Err(error::EncryptError::PluginNameFlag);
     }
+    if opts.passphrase_fd.is_some() && !opts.passphrase {
+        return Err(error::EncryptError::PassphraseFdWithoutPassphrase);
+    }
+    if opts.archive
+        && !opts
+            .input
+            .as_deref()
+            .map_or(false, |p| Path::new(p).is_dir())
+    {
+        return Err(error::EncryptError::ArchiveInputNotDirectory);
+    }
 
-    let encryptor = if opts.passphrase {
+    let encrypt_to = if opts.passphrase {
         if !opts.identity.is_empty() {
             return Err(error::EncryptError::MixedIdentityAndPassphrase);
         }
@@ -341,17 +943,80 @@ fn encrypt(opts: AgeOptions) -> Result<(), error::EncryptError> {
         if !opts.recipients_file.is_empty() {
             return Err(error::EncryptError::MixedRecipientsFileAndPassphrase);
         }
+        if opts.threshold.is_some() {
+            return Err(error::EncryptError::MixedThresholdAndPassphrase);
+        }
+        if opts.interactive {
+            return Err(error::EncryptError::MixedInteractiveAndPassphrase);
+        }
 
-        if opts.input.is_none() {
+        // A passphrase typed at the terminal can't share it with piped input, but a
+        // non-interactive passphrase doesn't use the terminal.
+        if opts.input.is_none() && batch.is_none() {
             return Err(error::EncryptError::PassphraseWithoutFileArgument);
         }
 
-        match read_or_generate_passphrase() {
-            Ok(Passphrase::Typed(passphrase)) => age::Encryptor::with_user_passphrase(passphrase),
+        let generator = passphrase_generator(&opts)?;
+        let sink = passphrase_sink(&opts)?;
+
+        let normalize = |passphrase| {
+            if opts.normalize_passphrase {
+                normalize_passphrase(&passphrase)
+            } else {
+                passphrase
+            }
+        };
+
+        let passphrase = match &batch {
+            Some(callbacks) => callbacks
+                .request_passphrase(&fl!("type-passphrase"))
+                .map(Passphrase::Typed)
+                .ok_or(pinentry::Error::Cancelled),
+            None => read_or_generate_passphrase_with(&generator),
+        };
+
+        report::set("passphrase", true);
+        match passphrase {
+            Ok(Passphrase::Typed(passphrase)) => EncryptTo::Passphrase(normalize(passphrase)),
             Ok(Passphrase::Generated(new_passphrase)) => {
-                eprintln!("{}", fl!("autogenerated-passphrase"));
-                eprintln!("    {}", new_passphrase.expose_secret());
-                age::Encryptor::with_user_passphrase(new_passphrase)
+                let new_passphrase = normalize(new_passphrase);
+                let bits = generator.entropy_bits().floor() as u64;
+                match sink {
+                    Some(sink) => {
+                        // Store the passphrase before encrypting anything with it, so
+                        // that a failure can't leave behind a file that nobody can open.
+                        sink.store(&new_passphrase)
+                            .map_err(error::EncryptError::PassphraseSink)?;
+                        eprintln!(
+                            "{}",
+                            if opts.passphrase_to_clipboard {
+                                i18n_embed_fl::fl!(
+                                    LANGUAGE_LOADER,
+                                    "autogenerated-passphrase-clipboard",
+                                    bits = bits,
+                                )
+                            } else {
+                                i18n_embed_fl::fl!(
+                                    LANGUAGE_LOADER,
+                                    "autogenerated-passphrase-sink",
+                                    bits = bits,
+                                )
+                            }
+                        );
+                    }
+                    None => {
+                        eprintln!(
+                            "{}",
+                            i18n_embed_fl::fl!(
+                                LANGUAGE_LOADER,
+                                "autogenerated-passphrase",
+                                bits = bits,
+                            )
+                        );
+                        eprintln!("    {}", new_passphrase.expose_secret());
+                    }
+                }
+                EncryptTo::Passphrase(new_passphrase)
             }
             Err(pinentry::Error::Cancelled) => return Ok(()),
             Err(pinentry::Error::Timeout) => return Err(error::EncryptError::PassphraseTimedOut),
@@ -372,36 +1037,167 @@ fn encrypt(opts: AgeOptions) -> Result<(), error::EncryptError> {
             Err(pinentry::Error::Io(e)) => return Err(error::EncryptError::Io(e)),
         }
     } else {
+        if opts.words.is_some()
+            || opts.wordlist.is_some()
+            || opts.charset.is_some()
+            || opts.chars.is_some()
+        {
+            return Err(error::EncryptError::PassphraseGeneratorWithoutPassphrase);
+        }
+        if opts.passphrase_to_clipboard || opts.passphrase_sink.is_some() {
+            return Err(error::EncryptError::PassphraseSinkWithoutPassphrase);
+        }
         if opts.recipient.is_empty() && opts.recipients_file.is_empty() && opts.identity.is_empty()
         {
             return Err(error::EncryptError::MissingRecipients);
         }
 
-        match age::Encryptor::with_recipients(read_recipients(
+        report::set("recipients", opts.recipient.clone());
+        report::set("recipients_files", opts.recipients_file.clone());
+        report::set("identities", opts.identity.clone());
+        if let Some(threshold) = opts.threshold {
+            report::set("threshold", threshold);
+        }
+
+        let mut listing = vec![];
+        let recipients = read_recipients(
             opts.recipient,
             opts.recipients_file,
             opts.identity,
             opts.max_work_factor,
-        )?) {
-            Some(encryptor) => encryptor,
-            None => return Err(error::EncryptError::MissingRecipients),
+            opts.threshold.is_some(),
+            &mut listing,
+        )?;
+        if recipients.is_empty() {
+            return Err(error::EncryptError::MissingRecipients);
+        }
+        if let Some(threshold) = opts.threshold {
+            check_threshold(threshold, recipients.len())?;
+        }
+        if opts.interactive {
+            interactive::confirm(&listing, opts.threshold)?;
+        }
+        match opts.threshold {
+            Some(threshold) => EncryptTo::Threshold(
+                threshold,
+                recipients
+                    .into_iter()
+                    .map(|r| SharedRecipients::new(vec![r]))
+                    .collect(),
+            ),
+            None => EncryptTo::Recipients(SharedRecipients::new(recipients)),
         }
     };
 
-    let (format, output_format) = if opts.armor {
-        (Format::AsciiArmor, file_io::OutputFormat::Text)
+    #[cfg(feature = "qr")]
+    let qr = opts.qr;
+    #[cfg(not(feature = "qr"))]
+    let qr = false;
+
+    let multiple = paths.len() > 1;
+    let (archive, armor) = (opts.archive, opts.armor || qr);
+    let mode = opts.mode;
+    report::set("armor", armor);
+    jobs::run(
+        opts.jobs.unwrap_or(1),
+        paths.into_iter().map(Ok),
+        move |(input, output)| {
+            encrypt_file(
+                encrypt_to.encryptor(armor),
+                input.clone(),
+                output,
+                archive,
+                armor,
+                qr,
+                mode,
+            )
+            .map_err(|e| match input {
+                Some(filename) if multiple => error::EncryptError::InputFile(filename, Box::new(e)),
+                _ => e,
+            })
+        },
+    )
+}
+
+/// Where `rage --encrypt` writes the ciphertext.
+enum EncryptOutput {
+    /// A file, or standard output.
+    Writer(file_io::OutputWriter),
+    /// A QR code of the armored ciphertext, written to a file or standard output once
+    /// the whole ciphertext is known.
+    #[cfg(feature = "qr")]
+    Qr {
+        armored: Vec<u8>,
+        output: file_io::OutputWriter,
+    },
+}
+
+impl Write for EncryptOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EncryptOutput::Writer(output) => output.write(buf),
+            #[cfg(feature = "qr")]
+            EncryptOutput::Qr { armored, .. } => armored.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EncryptOutput::Writer(output) => output.flush(),
+            #[cfg(feature = "qr")]
+            EncryptOutput::Qr { .. } => Ok(()),
+        }
+    }
+}
+
+/// Encrypts `input` to `output`, or standard input and output if they are `None`.
+///
+/// With `qr`, the armored ciphertext is written as a QR code instead.
+fn encrypt_file(
+    encryptor: age::Encryptor,
+    input: Option<String>,
+    output: Option<String>,
+    archive: bool,
+    armor: bool,
+    qr: bool,
+    mode: Option<file_io::Mode>,
+) -> Result<(), error::EncryptError> {
+    let output_format = if armor {
+        file_io::OutputFormat::Text
     } else {
-        (Format::Binary, file_io::OutputFormat::Binary)
+        file_io::OutputFormat::Binary
     };
 
-    let (input, output) = set_up_io(opts.input, opts.output, output_format)?;
+    let output_path = output.clone();
+    let (reader, output) = if archive {
+        // The input is a directory, which we read from in `archive::write`.
+        (None, open_output(output, output_format, mode, false)?)
+    } else {
+        let (reader, output) = set_up_io(input.clone(), output, output_format, mode)?;
+        (Some(reader), output)
+    };
 
     let is_stdout = match output {
         file_io::OutputWriter::File(..) => false,
         file_io::OutputWriter::Stdout(..) => true,
     };
 
-    let mut output = encryptor.wrap_output(ArmoredWriter::wrap_output(output, format)?)?;
+    #[cfg(feature = "qr")]
+    let output = if qr {
+        EncryptOutput::Qr {
+            armored: vec![],
+            output,
+        }
+    } else {
+        EncryptOutput::Writer(output)
+    };
+    #[cfg(not(feature = "qr"))]
+    let output = {
+        debug_assert!(!qr);
+        EncryptOutput::Writer(output)
+    };
+
+    let mut output = encryptor.wrap_armored_output(output)?;
 
     // Give more useful errors specifically when writing to the output.
     let map_io_errors = |e: io::Error| match e.kind() {
@@ -419,35 +1215,146 @@ fn encrypt(opts: AgeOptions) -> Result<(), error::EncryptError> {
         Ok(())
     });
 
-    io::copy(
-        &mut ReadChecker::new(
-            input,
-            [
-                (AGE_MAGIC, warn_double_encrypting.clone()),
-                (ARMORED_BEGIN_MARKER, warn_double_encrypting),
-            ],
-        ),
-        &mut output,
-    )
-    .map_err(map_io_errors)?;
-    output
+    let output = match reader {
+        Some(reader) => {
+            io::copy(
+                &mut ReadChecker::new(
+                    reader,
+                    [
+                        (AGE_MAGIC, warn_double_encrypting.clone()),
+                        (ARMORED_BEGIN_MARKER, warn_double_encrypting),
+                    ],
+                ),
+                &mut output,
+            )
+            .map_err(map_io_errors)?;
+            output
+        }
+        None => {
+            let dir = input.as_deref().expect("checked above");
+            archive::write(Path::new(dir), output).map_err(map_io_errors)?
+        }
+    };
+
+    let output = output
         .finish()
         .and_then(|armor| armor.finish())
         .map_err(map_io_errors)?;
+    match output {
+        EncryptOutput::Writer(output) => output.commit().map_err(map_io_errors)?,
+        #[cfg(feature = "qr")]
+        EncryptOutput::Qr {
+            armored,
+            mut output,
+        } => {
+            let armored = std::str::from_utf8(&armored).expect("armor is ASCII");
+            let code = age::qr::to_terminal(armored).ok_or(error::EncryptError::QrTooLarge)?;
+            writeln!(output, "{}", code)
+                .and_then(|()| output.commit())
+                .map_err(map_io_errors)?;
+        }
+    }
 
+    report::file(input.as_deref(), output_path.as_deref());
     Ok(())
 }
 
-fn write_output<R: io::Read, W: io::Write>(
+/// Where `rage --decrypt` writes the plaintext.
+enum DecryptOutput {
+    /// A file, or standard output.
+    Writer(file_io::OutputWriter),
+    /// A directory that the plaintext is extracted into as a tar archive.
+    Archive(String),
+    /// Nowhere: the plaintext is only read to authenticate it.
+    Discard,
+}
+
+fn write_output<R: io::Read>(
     mut input: R,
-    mut output: W,
+    output: DecryptOutput,
 ) -> Result<(), error::DecryptError> {
-    io::copy(&mut input, &mut output)?;
+    match output {
+        DecryptOutput::Writer(mut output) => {
+            io::copy(&mut input, &mut output)?;
+            output.commit()?;
+        }
+        DecryptOutput::Archive(dir) => archive::extract(input, Path::new(&dir))?,
+        DecryptOutput::Discard => {
+            // Reading to the end checks the tag of every chunk, and that the last chunk
+            // is present.
+            io::copy(&mut input, &mut io::sink())?;
+        }
+    }
 
     Ok(())
 }
 
-fn decrypt(opts: AgeOptions) -> Result<(), error::DecryptError> {
+/// Reads the identities in the default identity files that exist.
+///
+/// Unlike identity files passed with `-i`, a default identity file that can't be read
+/// is skipped with a warning, so that an unrelated broken file does not prevent
+/// decryption with the others.
+fn read_default_identities(
+    max_work_factor: Option<u8>,
+    allowlist: &plugin::Allowlist,
+) -> Vec<Box<dyn Identity>> {
+    let mut identities = vec![];
+    for filename in defaults::identity_files() {
+        match read_identities_with_allowlist(vec![filename.clone()], max_work_factor, allowlist) {
+            Ok(mut file_identities) => identities.append(&mut file_identities),
+            Err(e) => report::warning(
+                "warn-skipped-default-identity",
+                i18n_embed_fl::fl!(
+                    LANGUAGE_LOADER,
+                    "warn-skipped-default-identity",
+                    filename = filename,
+                    err = e.to_string(),
+                ),
+            ),
+        }
+    }
+    identities
+}
+
+/// Reads the identities to decrypt with, from `-j`, `-i`, or the default identity files.
+fn read_decryption_identities(
+    plugin_name: &str,
+    identity_strings: Vec<String>,
+    no_default_identities: bool,
+    max_work_factor: Option<u8>,
+) -> Result<Vec<Box<dyn Identity>>, error::DecryptError> {
+    let identities = if !plugin_name.is_empty() {
+        // Construct the default plugin. The user named it with -j, so don't ask before
+        // running it.
+        vec![Box::new(plugin::IdentityPluginV1::new_unchecked(
+            plugin_name,
+            &[plugin::Identity::default_for_plugin(plugin_name)],
+            UiCallbacks,
+        )?) as Box<dyn Identity>]
+    } else if !identity_strings.is_empty() {
+        read_identities_with_allowlist(
+            identity_strings,
+            max_work_factor,
+            &defaults::plugin_allowlist()?,
+        )?
+    } else if !no_default_identities {
+        read_default_identities(max_work_factor, &defaults::plugin_allowlist()?)
+    } else {
+        vec![]
+    };
+
+    if identities.is_empty() {
+        Err(error::DecryptError::MissingIdentities)
+    } else {
+        Ok(identities)
+    }
+}
+
+fn decrypt(
+    opts: AgeOptions,
+    paths: IoPaths,
+    batch: Option<BatchCallbacks>,
+) -> Result<(), error::DecryptError> {
     if opts.armor {
         return Err(error::DecryptError::ArmorFlag);
     }
@@ -461,15 +1368,140 @@ fn decrypt(opts: AgeOptions) -> Result<(), error::DecryptError> {
     if !opts.recipients_file.is_empty() {
         return Err(error::DecryptError::RecipientsFileFlag);
     }
+    if opts.threshold.is_some() {
+        return Err(error::DecryptError::ThresholdFlag);
+    }
+    if opts.interactive {
+        return Err(error::DecryptError::InteractiveFlag);
+    }
+    #[cfg(feature = "qr")]
+    if opts.qr {
+        return Err(error::DecryptError::QrFlag);
+    }
 
     if !(opts.identity.is_empty() || opts.plugin_name.is_empty()) {
         return Err(error::DecryptError::MixedIdentityAndPluginName);
     }
 
-    #[cfg(not(unix))]
-    let has_file_argument = opts.input.is_some();
+    if opts.verify_only && (opts.output.is_some() || opts.output_dir.is_some() || opts.archive) {
+        return Err(error::DecryptError::VerifyOnlyOutputFlags);
+    }
+
+    let multiple = paths.len() > 1;
+    let in_file = move |input: Option<String>, e| match input {
+        Some(filename) if multiple => error::DecryptError::InputFile(filename, Box::new(e)),
+        _ => e,
+    };
+
+    // Headers are read here, so that identities and passphrases are only read once and
+    // prompts don't overlap. The payloads are then decrypted by the workers.
+    let mut secrets = DecryptSecrets::default();
+    let tasks = paths
+        .into_iter()
+        .map(|(input, output)| {
+            start_decryption(&opts, batch.as_ref(), &mut secrets, input.clone(), output)
+                .map_err(|e| in_file(input, e))
+                .transpose()
+        })
+        // Stop if the user cancelled the passphrase prompt.
+        .map_while(|task| task);
+
+    jobs::run(opts.jobs.unwrap_or(1), tasks, move |task| {
+        let input = task.input.clone();
+        task.finish().map_err(|e| in_file(input, e))
+    })
+}
+
+/// The identities or passphrase that `rage --decrypt` decrypts with.
+///
+/// Each is only read once a file needs it, and is then reused for the other files.
+#[derive(Default)]
+struct DecryptSecrets {
+    identities: Option<Vec<Box<dyn Identity>>>,
+    passphrase: Option<SecretString>,
+}
+
+/// The input that `rage --decrypt` reads an age file from.
+type DecryptInput = ArmoredReader<BufReader<ReadChecker<file_io::InputReader, 2>>>;
+
+/// A file that `rage --decrypt` has read the header of.
+struct DecryptTask {
+    input: Option<String>,
+    decryptor: TaskDecryptor,
+    output: DecryptOutput,
+    /// The path that `output` writes to, for `--format json`.
+    output_path: Option<String>,
+}
+
+enum TaskDecryptor {
+    /// A passphrase-encrypted file, with the passphrase and maximum work factor to
+    /// decrypt it with.
+    Passphrase(
+        age::decryptor::PassphraseDecryptor<DecryptInput>,
+        SecretString,
+        Option<u8>,
+    ),
+    /// A file encrypted to recipients, whose file key has been unwrapped.
+    Recipients(age::stream::StreamReader<DecryptInput>),
+}
+
+impl DecryptTask {
+    /// Decrypts the file's payload into its output.
+    fn finish(self) -> Result<(), error::DecryptError> {
+        let reader = match self.decryptor {
+            TaskDecryptor::Passphrase(decryptor, passphrase, max_work_factor) => decryptor
+                .decrypt(&passphrase, max_work_factor)
+                .map_err(|e| match e {
+                    // The scrypt stanza is only authenticated by the passphrase, so this
+                    // is most likely a typo.
+                    age::DecryptError::DecryptionFailed => error::DecryptError::WrongPassphrase,
+                    e => e.into(),
+                })?,
+            TaskDecryptor::Recipients(reader) => reader,
+        };
+        write_output(reader, self.output)?;
+        report::file(self.input.as_deref(), self.output_path.as_deref());
+        Ok(())
+    }
+}
 
-    let (input, output) = set_up_io(opts.input, opts.output, file_io::OutputFormat::Unknown)?;
+/// Reads the header of `input` (or standard input if `None`), and prepares to decrypt
+/// it to `output` (or standard output if `None`).
+///
+/// Returns `None` if the user cancelled the passphrase prompt.
+fn start_decryption(
+    opts: &AgeOptions,
+    batch: Option<&BatchCallbacks>,
+    secrets: &mut DecryptSecrets,
+    input: Option<String>,
+    output: Option<String>,
+) -> Result<Option<DecryptTask>, error::DecryptError> {
+    #[cfg(not(unix))]
+    let has_file_argument = input.is_some();
+
+    let (reader, output, output_path) = if opts.verify_only {
+        let reader = file_io::InputReader::new(input.clone())?;
+        (reader, DecryptOutput::Discard, None)
+    } else if opts.archive {
+        let reader = file_io::InputReader::new(input.clone())?;
+        let dir = output.unwrap_or_else(|| ".".into());
+        (reader, DecryptOutput::Archive(dir.clone()), Some(dir))
+    } else {
+        let (reader, mut writer) = set_up_io(
+            input.clone(),
+            output.clone(),
+            file_io::OutputFormat::Unknown,
+            opts.mode,
+        )?;
+        // Check the permissions the output was actually created with, which depend on
+        // the umask and on any file being replaced, as well as on --mode.
+        if let Some(filename) = &output {
+            if !opts.force && writer.is_exposed()? {
+                return Err(error::DecryptError::ExposedOutput(filename.clone()));
+            }
+        }
+        (reader, DecryptOutput::Writer(writer), output)
+    };
 
     // CRLF_MANGLED_INTRO and UTF16_MANGLED_INTRO are the intro lines of the age format after
     // mangling by various versions of PowerShell redirection, truncated to the length of the
@@ -484,15 +1516,15 @@ fn decrypt(opts: AgeOptions) -> Result<(), error::DecryptError> {
         ))
     });
 
-    let input = ReadChecker::new(
-        input,
+    let reader = ReadChecker::new(
+        reader,
         [
             (CRLF_MANGLED_INTRO, err_powershell_corruption.clone()),
             (UTF16_MANGLED_INTRO, err_powershell_corruption),
         ],
     );
 
-    match age::Decryptor::new(ArmoredReader::new(input))? {
+    let decryptor = match age::Decryptor::new(ArmoredReader::new(reader))? {
         age::Decryptor::Passphrase(decryptor) => {
             if !opts.identity.is_empty() {
                 return Err(error::DecryptError::MixedIdentityAndPassphrase);
@@ -502,60 +1534,294 @@ fn decrypt(opts: AgeOptions) -> Result<(), error::DecryptError> {
             // any conflict with stdin.
             #[cfg(not(unix))]
             {
-                if !has_file_argument {
+                if !has_file_argument && batch.is_none() {
                     return Err(error::DecryptError::PassphraseWithoutFileArgument);
                 }
             }
 
-            match read_secret(&fl!("type-passphrase"), &fl!("prompt-passphrase"), None) {
-                Ok(passphrase) => decryptor
-                    .decrypt(&passphrase, opts.max_work_factor)
-                    .map_err(|e| e.into())
-                    .and_then(|input| write_output(input, output)),
-                Err(pinentry::Error::Cancelled) => Ok(()),
-                Err(pinentry::Error::Timeout) => Err(error::DecryptError::PassphraseTimedOut),
-                Err(pinentry::Error::Encoding(e)) => {
-                    // Pretend it is an I/O error
-                    Err(error::DecryptError::Io(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        e,
-                    )))
+            if secrets.passphrase.is_none() {
+                let passphrase = match batch {
+                    Some(callbacks) => callbacks
+                        .request_passphrase(&fl!("type-passphrase"))
+                        .ok_or(pinentry::Error::Cancelled),
+                    None => read_secret(&fl!("type-passphrase"), &fl!("prompt-passphrase"), None),
                 }
-                Err(pinentry::Error::Gpg(e)) => {
-                    // Pretend it is an I/O error
-                    Err(error::DecryptError::Io(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("{}", e),
-                    )))
+                .map(|passphrase| {
+                    if opts.normalize_passphrase {
+                        normalize_passphrase(&passphrase)
+                    } else {
+                        passphrase
+                    }
+                });
+
+                match passphrase {
+                    Ok(passphrase) => secrets.passphrase = Some(passphrase),
+                    Err(pinentry::Error::Cancelled) => return Ok(None),
+                    Err(pinentry::Error::Timeout) => {
+                        return Err(error::DecryptError::PassphraseTimedOut)
+                    }
+                    Err(pinentry::Error::Encoding(e)) => {
+                        // Pretend it is an I/O error
+                        return Err(error::DecryptError::Io(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            e,
+                        )));
+                    }
+                    Err(pinentry::Error::Gpg(e)) => {
+                        // Pretend it is an I/O error
+                        return Err(error::DecryptError::Io(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("{}", e),
+                        )));
+                    }
+                    Err(pinentry::Error::Io(e)) => return Err(error::DecryptError::Io(e)),
                 }
-                Err(pinentry::Error::Io(e)) => Err(error::DecryptError::Io(e)),
             }
+            let passphrase = secrets.passphrase.clone().expect("set above");
+
+            TaskDecryptor::Passphrase(decryptor, passphrase, opts.max_work_factor)
         }
         age::Decryptor::Recipients(decryptor) => {
-            let identities = if opts.plugin_name.is_empty() {
-                read_identities(opts.identity, opts.max_work_factor)?
-            } else {
-                // Construct the default plugin.
-                vec![Box::new(plugin::IdentityPluginV1::new(
+            if secrets.identities.is_none() {
+                secrets.identities = Some(read_decryption_identities(
                     &opts.plugin_name,
-                    &[plugin::Identity::default_for_plugin(&opts.plugin_name)],
-                    UiCallbacks,
-                )?) as Box<dyn Identity>]
-            };
-
-            if identities.is_empty() {
-                return Err(error::DecryptError::MissingIdentities);
+                    opts.identity.clone(),
+                    opts.no_default_identities,
+                    opts.max_work_factor,
+                )?);
             }
+            let identities = secrets.identities.as_ref().expect("set above");
 
-            decryptor
-                .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
-                .map_err(|e| e.into())
-                .and_then(|input| write_output(input, output))
+            TaskDecryptor::Recipients(
+                decryptor.decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))?,
+            )
         }
+    };
+
+    Ok(Some(DecryptTask {
+        input,
+        decryptor,
+        output,
+        output_path,
+    }))
+}
+
+fn rekey(opts: AgeOptions) -> Result<(), error::RekeyError> {
+    if opts.encrypt || opts.decrypt || opts.verify_only {
+        return Err(error::RekeyError::EncryptOrDecryptFlag);
+    }
+    if opts.passphrase || opts.passphrase_fd.is_some() {
+        return Err(error::RekeyError::PassphraseFlag);
+    }
+    if opts.output.is_some() {
+        return Err(error::RekeyError::OutputFlag);
     }
+    if opts.archive {
+        return Err(error::RekeyError::ArchiveFlag);
+    }
+    if !(opts.identity.is_empty() || opts.plugin_name.is_empty()) {
+        return Err(error::DecryptError::MixedIdentityAndPluginName.into());
+    }
+    if opts.recipient.is_empty() && opts.recipients_file.is_empty() {
+        return Err(error::EncryptError::MissingRecipients.into());
+    }
+
+    let path = match opts.input {
+        Some(path) if Path::new(&path).is_file() => path,
+        _ => return Err(error::RekeyError::MissingInput),
+    };
+
+    // Read the new recipients first, so that we don't decrypt the file only to find
+    // that we can't re-encrypt it.
+    let mut listing = vec![];
+    let recipients = read_recipients(
+        opts.recipient,
+        opts.recipients_file,
+        vec![],
+        opts.max_work_factor,
+        opts.threshold.is_some(),
+        &mut listing,
+    )?;
+    if let Some(threshold) = opts.threshold {
+        check_threshold(threshold, recipients.len())?;
+    }
+    if opts.interactive && !recipients.is_empty() {
+        interactive::confirm(&listing, opts.threshold)?;
+    }
+    let builder = EncryptorBuilder::new().recipients(recipients);
+    let encryptor = match opts.threshold {
+        Some(threshold) => builder.threshold(threshold),
+        None => builder,
+    }
+    .build()
+    .ok_or(error::EncryptError::MissingRecipients)?;
+
+    let mut input = File::open(&path)?;
+
+    // Preserve the armor of the existing file.
+    const ARMORED_BEGIN_MARKER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+    let mut marker = [0; ARMORED_BEGIN_MARKER.len()];
+    let is_armored = input.read_exact(&mut marker).is_ok() && marker == ARMORED_BEGIN_MARKER;
+    input.seek(SeekFrom::Start(0))?;
+    let format = if is_armored || opts.armor {
+        Format::AsciiArmor
+    } else {
+        Format::Binary
+    };
+
+    let decryptor = match age::Decryptor::new(ArmoredReader::new(BufReader::new(input)))
+        .map_err(error::DecryptError::from)?
+    {
+        age::Decryptor::Recipients(decryptor) => decryptor,
+        age::Decryptor::Passphrase(_) => return Err(error::RekeyError::PassphraseEncrypted),
+    };
+    let identities = read_decryption_identities(
+        &opts.plugin_name,
+        opts.identity,
+        opts.no_default_identities,
+        opts.max_work_factor,
+    )?;
+    let reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
+        .map_err(error::DecryptError::from)?;
+
+    // The new file is written next to the existing one, and only renamed over the top
+    // once it is complete, and keeps the existing file's permissions. If we fail or are
+    // interrupted before then, it is removed.
+    let output = age::fs::AtomicOutput::create(&path)?;
+    if let Some(tmp_path) = output.tmp_path() {
+        interrupt::track_uncommitted(tmp_path);
+    }
+
+    let output = age::rekey(
+        reader,
+        encryptor,
+        ArmoredWriter::wrap_output(output, format)?,
+        |_| (),
+    )
+    .map_err(error::EncryptError::from)?;
+    output.finish()?.commit()?;
+
+    report::file(Some(&path), Some(&path));
+    Ok(())
+}
+
+fn check_armor(opts: AgeOptions) -> Result<(), error::CheckArmorError> {
+    if opts.encrypt
+        || opts.decrypt
+        || opts.verify_only
+        || opts.rekey
+        || opts.inspect
+        || opts.daemon.is_some()
+        || opts.passphrase
+        || opts.passphrase_fd.is_some()
+        || opts.archive
+        || opts.armor
+        || opts.output.is_some()
+        || !opts.recipient.is_empty()
+        || !opts.recipients_file.is_empty()
+        || opts.threshold.is_some()
+        || opts.interactive
+        || !opts.plugin_name.is_empty()
+    {
+        return Err(error::CheckArmorError::OtherFlags);
+    }
+
+    let input = file_io::InputReader::new(opts.input)?;
+    match age::armor::check(BufReader::new(input))? {
+        None => Ok(()),
+        Some(violation) => Err(error::CheckArmorError::Invalid(violation)),
+    }
+}
+
+fn inspect(opts: AgeOptions) -> Result<(), error::InspectError> {
+    if opts.encrypt
+        || opts.decrypt
+        || opts.verify_only
+        || opts.rekey
+        || opts.daemon.is_some()
+        || opts.passphrase
+        || opts.passphrase_fd.is_some()
+        || opts.archive
+        || opts.armor
+        || opts.output.is_some()
+        || !opts.recipient.is_empty()
+        || !opts.recipients_file.is_empty()
+        || opts.threshold.is_some()
+        || opts.interactive
+        || !opts.plugin_name.is_empty()
+    {
+        return Err(error::InspectError::OtherFlags);
+    }
+
+    let input = file_io::InputReader::new(opts.input)?;
+    let inspection = inspect::Inspection::read(input)?;
+    if opts.json || opts.format == Some(report::Format::Json) {
+        inspection.print_json();
+    } else {
+        inspection.print();
+    }
+    Ok(())
 }
 
-fn main() -> Result<(), error::Error> {
+fn daemon(opts: AgeOptions) -> Result<(), error::DaemonError> {
+    if opts.encrypt
+        || opts.decrypt
+        || opts.verify_only
+        || opts.rekey
+        || opts.inspect
+        || opts.passphrase
+        || opts.passphrase_fd.is_some()
+        || opts.archive
+        || opts.output.is_some()
+        || opts.input.is_some()
+        || opts.threshold.is_some()
+        || opts.interactive
+    {
+        return Err(error::DaemonError::OtherFlags);
+    }
+    if !(opts.identity.is_empty() || opts.plugin_name.is_empty()) {
+        return Err(error::DecryptError::MixedIdentityAndPluginName.into());
+    }
+
+    let recipients = if opts.recipient.is_empty() && opts.recipients_file.is_empty() {
+        vec![]
+    } else {
+        read_recipients(
+            opts.recipient,
+            opts.recipients_file,
+            vec![],
+            opts.max_work_factor,
+            false,
+            &mut vec![],
+        )?
+    };
+
+    // The daemon only needs identities if it will decrypt.
+    let identities = match read_decryption_identities(
+        &opts.plugin_name,
+        opts.identity,
+        opts.no_default_identities,
+        opts.max_work_factor,
+    ) {
+        Err(error::DecryptError::MissingIdentities) if !recipients.is_empty() => vec![],
+        res => res?,
+    };
+
+    #[cfg(unix)]
+    {
+        let socket = opts.daemon.expect("only called with --daemon");
+        daemon::Daemon::new(recipients, identities, opts.armor).run(&socket)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (recipients, identities);
+        Err(error::DaemonError::Unsupported)
+    }
+}
+
+fn main() {
     use std::env::args;
 
     env_logger::builder()
@@ -573,24 +1839,46 @@ fn main() -> Result<(), error::Error> {
 
     let args = args().collect::<Vec<_>>();
 
+    #[cfg(feature = "cli-docs")]
+    if cli::handle::<AgeOptions>(&args, error::Failure::Usage.exit_code()) {
+        return;
+    }
+
     let opts = AgeOptions::parse_args(&args[1..], ParsingStyle::default()).unwrap_or_else(|e| {
         eprintln!("{}: {}", args[0], e);
-        std::process::exit(2);
+        std::process::exit(error::Failure::Usage.exit_code());
     });
 
+    if let Some(language) = &opts.lang {
+        // Override the languages requested by the environment.
+        let languages = [language.clone()];
+        i18n_embed::select(&*LANGUAGE_LOADER, &TRANSLATIONS, &languages).unwrap();
+        age::localizer().select(&languages).unwrap();
+    }
+
     // If you are piping input with no other args, this will not allow
     // it.
     if (console::user_attended() && args.len() == 1) || opts.help_requested() {
         let binary_name = args[0].as_str();
         let keygen_name = format!("{}-keygen", binary_name);
         let usage_a = format!(
-            "{} [--encrypt] -r RECIPIENT [-i IDENTITY] [-a] [-o OUTPUT] [INPUT]",
+            "{} [--encrypt] -r RECIPIENT [-i IDENTITY] [-a] [-o OUTPUT] [INPUT...]",
             binary_name
         );
         let usage_b = format!(
-            "{} --decrypt [-i IDENTITY] [-o OUTPUT] [INPUT]",
+            "{} --decrypt [-i IDENTITY] [-o OUTPUT | --verify-only] [INPUT...]",
             binary_name
         );
+        let usage_c = format!(
+            "{} --rekey [-i IDENTITY] -r RECIPIENT [-a] INPUT",
+            binary_name
+        );
+        let usage_d = format!("{} --check-armor [INPUT]", binary_name);
+        let usage_e = format!(
+            "{} --daemon SOCKET [-i IDENTITY] [-r RECIPIENT] [-a]",
+            binary_name
+        );
+        let usage_f = format!("{} --inspect [--json] [INPUT]", binary_name);
         let example_a = format!("$ {} -o key.txt", keygen_name);
         let example_a_output = "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p";
         let example_b = format!(
@@ -609,8 +1897,14 @@ fn main() -> Result<(), error::Error> {
                 "rage-usage",
                 usage_a = usage_a,
                 usage_b = usage_b,
+                usage_c = usage_c,
+                usage_d = usage_d,
+                usage_e = usage_e,
+                usage_f = usage_f,
                 flags = AgeOptions::usage(),
                 keygen_name = keygen_name,
+                default_identities = defaults::IDENTITIES_FILE,
+                plugins_file = defaults::PLUGINS_FILE,
                 example_a = example_a,
                 example_a_output = example_a_output,
                 example_b = example_b,
@@ -618,36 +1912,93 @@ fn main() -> Result<(), error::Error> {
             )
         );
 
-        return Ok(());
+        return;
     }
 
     if opts.version {
         println!("rage {}", env!("CARGO_PKG_VERSION"));
-        Ok(())
-    } else {
-        if opts.encrypt && opts.decrypt {
-            return Err(error::Error::MixedEncryptAndDecrypt);
-        }
-        if !(opts.identity.is_empty() || opts.encrypt || opts.decrypt) {
-            return Err(error::Error::IdentityFlagAmbiguous);
-        }
+        return;
+    }
 
-        if let (Some(in_file), Some(out_file)) = (&opts.input, &opts.output) {
-            // Check that the given filenames do not correspond to the same file.
-            let in_path = Path::new(&in_file);
-            let out_path = Path::new(&out_file);
-            match (in_path.canonicalize(), out_path.canonicalize()) {
-                (Ok(in_abs), Ok(out_abs)) if in_abs == out_abs => {
-                    return Err(error::Error::SameInputAndOutput(out_file.clone()));
-                }
-                _ => (),
+    report::set_format(opts.format.unwrap_or(report::Format::Text));
+    report::finish(run(opts));
+}
+
+/// Checks the flags in `opts`, and then runs the requested operation.
+fn run(opts: AgeOptions) -> Result<(), error::Error> {
+    if opts.encrypt && (opts.decrypt || opts.verify_only) {
+        return Err(error::Error::MixedEncryptAndDecrypt);
+    }
+    if !(opts.identity.is_empty()
+        || opts.encrypt
+        || opts.decrypt
+        || opts.verify_only
+        || opts.rekey
+        || opts.daemon.is_some())
+    {
+        return Err(error::Error::IdentityFlagAmbiguous);
+    }
+
+    if let (Some(in_file), Some(out_file)) = (&opts.input, &opts.output) {
+        // Check that the given filenames do not correspond to the same file.
+        let in_path = Path::new(&in_file);
+        let out_path = Path::new(&out_file);
+        match (in_path.canonicalize(), out_path.canonicalize()) {
+            (Ok(in_abs), Ok(out_abs)) if in_abs == out_abs => {
+                return Err(error::Error::SameInputAndOutput(out_file.clone()));
             }
+            _ => (),
         }
+    }
 
-        if opts.decrypt {
-            decrypt(opts).map_err(error::Error::from)
-        } else {
-            encrypt(opts).map_err(error::Error::from)
-        }
+    if (!opts.more_inputs.is_empty() || opts.output_dir.is_some() || opts.jobs.is_some())
+        && (opts.archive || opts.rekey || opts.check_armor || opts.inspect || opts.daemon.is_some())
+    {
+        return Err(error::Error::MultipleInputsFlag);
+    }
+    if opts.json && !opts.inspect {
+        return Err(error::Error::JsonWithoutInspect);
+    }
+    if opts.jobs == Some(0) {
+        return Err(error::Error::JobsZero);
+    }
+
+    if opts.passphrase_fd == Some(0) && opts.input.is_none() {
+        return Err(error::Error::PassphraseFdIsStdin);
+    }
+
+    // Read any passphrase provided for non-interactive use before we start any
+    // plugins, so that they don't inherit it.
+    let batch = batch_callbacks(opts.passphrase_fd).map_err(error::Error::ReadPassphrase)?;
+
+    interrupt::install_handler();
+
+    if opts.check_armor {
+        report::set("operation", "check-armor");
+        check_armor(opts).map_err(error::Error::from)
+    } else if opts.inspect {
+        report::set("operation", "inspect");
+        inspect(opts).map_err(error::Error::from)
+    } else if opts.daemon.is_some() {
+        report::set("operation", "daemon");
+        daemon(opts).map_err(error::Error::from)
+    } else if opts.rekey {
+        report::set("operation", "rekey");
+        rekey(opts).map_err(error::Error::from)
+    } else if opts.decrypt || opts.verify_only {
+        report::set(
+            "operation",
+            if opts.verify_only {
+                "verify"
+            } else {
+                "decrypt"
+            },
+        );
+        let paths = io_paths(&opts, true)?;
+        decrypt(opts, paths, batch).map_err(error::Error::from)
+    } else {
+        report::set("operation", "encrypt");
+        let paths = io_paths(&opts, false)?;
+        encrypt(opts, paths, batch).map_err(error::Error::from)
     }
 }
@@ -0,0 +1,80 @@
+//! Fetching recipients over HTTPS.
+
+use std::io::{self, BufRead, Cursor, Read};
+
+use crate::error;
+
+const GITHUB_PREFIX: &str = "github:";
+const HTTPS_PREFIX: &str = "https://";
+
+/// The maximum number of bytes we will read from a remote recipients list.
+const MAX_RESPONSE_BYTES: u64 = 1024 * 1024;
+
+/// Returns the URL that recipients should be fetched from, if `arg` refers to a remote
+/// recipients list.
+pub(crate) fn recipients_url(arg: &str) -> Option<Result<String, error::EncryptError>> {
+    if let Some(username) = arg.strip_prefix(GITHUB_PREFIX) {
+        // GitHub usernames are alphanumeric with single hyphens; rejecting anything else
+        // ensures the username can't alter the path we fetch.
+        Some(
+            if !username.is_empty()
+                && username
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            {
+                Ok(format!("https://github.com/{}.keys", username))
+            } else {
+                Err(error::EncryptError::InvalidRecipient(arg.to_owned()))
+            },
+        )
+    } else if arg.starts_with(HTTPS_PREFIX) {
+        Some(Ok(arg.to_owned()))
+    } else {
+        None
+    }
+}
+
+/// Fetches a recipients list from the given HTTPS URL.
+pub(crate) fn fetch(url: &str) -> Result<impl BufRead, error::EncryptError> {
+    let response = ureq::get(url).call().map_err(|e| {
+        // ureq's own error messages include the URL, which we already show.
+        let message = match e {
+            ureq::Error::Status(code, response) => {
+                format!("HTTP {} {}", code, response.status_text())
+            }
+            ureq::Error::Transport(t) => {
+                let mut message = t.kind().to_string();
+                if let Some(source) = std::error::Error::source(&t) {
+                    message = format!("{}: {}", message, source);
+                } else if let Some(detail) = t.message() {
+                    message = format!("{}: {}", message, detail);
+                }
+                message
+            }
+        };
+        error::EncryptError::FetchRecipients(
+            url.to_owned(),
+            io::Error::new(io::ErrorKind::Other, message),
+        )
+    })?;
+
+    // Read one byte past the limit, so that we can reject an oversized response instead
+    // of silently truncating it (which could drop recipients).
+    let mut body = vec![];
+    response
+        .into_reader()
+        .take(MAX_RESPONSE_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| error::EncryptError::FetchRecipients(url.to_owned(), e))?;
+    if body.len() as u64 > MAX_RESPONSE_BYTES {
+        return Err(error::EncryptError::FetchRecipients(
+            url.to_owned(),
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("response is larger than {} bytes", MAX_RESPONSE_BYTES),
+            ),
+        ));
+    }
+
+    Ok(Cursor::new(body))
+}
@@ -0,0 +1,172 @@
+//! Machine-readable reports for `--format json`.
+//!
+//! With `--format json`, `rage` writes a single JSON object to standard error when it
+//! exits, in place of its human-readable messages:
+//!
+//! ```text
+//! {
+//!   "status": "ok",
+//!   "operation": "encrypt",
+//!   "recipients": ["age1..."],
+//!   "files": [{ "input": "photo.jpg", "output": "photo.jpg.age" }],
+//!   "warnings": []
+//! }
+//! ```
+//!
+//! `input` and `output` are `null` for standard input and output. If `rage` fails,
+//! `status` is `"error"`, and `error` has a stable `code` for the failure, the
+//! `exit_code`, and a localized `message`. The files that were completed before the
+//! failure are still listed. Warnings have `code` and `message` fields.
+//!
+//! Passphrase prompts and autogenerated passphrases are still written to the terminal.
+
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::error;
+
+/// How `rage` reports its results and errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// Localized messages for people.
+    Text,
+    /// A JSON object for scripts.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+/// An unknown value for `--format`.
+#[derive(Debug)]
+pub(crate) struct UnknownFormat(String);
+
+impl fmt::Display for UnknownFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            i18n_embed_fl::fl!(
+                crate::LANGUAGE_LOADER,
+                "err-unknown-format",
+                format = self.0.as_str()
+            )
+        )
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref REPORT: Mutex<Report> = Mutex::new(Report::default());
+}
+
+#[derive(Default)]
+struct Report {
+    fields: Map<String, Value>,
+    files: Vec<Value>,
+    warnings: Vec<Value>,
+}
+
+/// Sets how results and errors are reported for the rest of this run.
+pub(crate) fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+/// Returns `true` if results and errors are reported as JSON.
+pub(crate) fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Sets a field of the JSON report, such as the operation that was performed.
+pub(crate) fn set(key: &str, value: impl Into<Value>) {
+    if is_json() {
+        let mut report = REPORT.lock().expect("not poisoned");
+        report.fields.insert(key.to_owned(), value.into());
+    }
+}
+
+/// Records that `input` was processed into `output` (`None` for standard input or
+/// output).
+pub(crate) fn file(input: Option<&str>, output: Option<&str>) {
+    if is_json() {
+        let mut report = REPORT.lock().expect("not poisoned");
+        report
+            .files
+            .push(json!({ "input": input, "output": output }));
+    }
+}
+
+/// Prints the warning `message`, or adds it to the JSON report.
+///
+/// `id` is the warning's message ID, such as `warn-double-encrypting`.
+pub(crate) fn warning(id: &str, message: String) {
+    if is_json() {
+        let mut report = REPORT.lock().expect("not poisoned");
+        report.warnings.push(json!({
+            "code": id.trim_start_matches("warn-"),
+            "message": message,
+        }));
+    } else {
+        eprintln!(
+            "{}",
+            i18n_embed_fl::fl!(crate::LANGUAGE_LOADER, "warning-msg", warning = message)
+        );
+    }
+}
+
+/// Finishes this run with `result`.
+///
+/// If `result` is an error, this prints it and exits with the code for its class of
+/// [`error::Failure`]. With `--format json`, this also writes the JSON report to
+/// standard error.
+pub(crate) fn finish(result: Result<(), error::Error>) {
+    if !is_json() {
+        if let Err(e) = result {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(e.failure().exit_code());
+        }
+        return;
+    }
+
+    let report = std::mem::take(&mut *REPORT.lock().expect("not poisoned"));
+    let mut value = report.fields;
+    match &result {
+        Ok(()) => {
+            value.insert("status".into(), "ok".into());
+        }
+        Err(e) => {
+            value.insert("status".into(), "error".into());
+            value.insert(
+                "error".into(),
+                json!({
+                    "code": e.code(),
+                    "exit_code": e.failure().exit_code(),
+                    "message": e.to_string().trim_end(),
+                }),
+            );
+        }
+    }
+    value.insert("files".into(), report.files.into());
+    value.insert("warnings".into(), report.warnings.into());
+    eprintln!("{}", Value::Object(value));
+
+    if let Err(e) = result {
+        std::process::exit(e.failure().exit_code());
+    }
+}
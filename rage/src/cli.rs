@@ -0,0 +1,122 @@
+//! Shell completions and manual pages for the rage binaries.
+//!
+//! Each binary defines its options once, as a struct that derives `gumdrop::Options` to
+//! parse the command line and print `--help`. With the `cli-docs` feature flag, the same
+//! struct also derives `clap::Parser`, and the completions and manual page are generated
+//! from the resulting `clap::Command`. Both derives take each option's help text from the
+//! doc comment on its field, so the parser, `--help`, completions, and manual pages can't
+//! drift apart.
+
+use clap::{Command, CommandFactory};
+use clap_complete::{generate, Shell};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// The hidden subcommand that prints completions: `<binary> completions <shell>`.
+const COMPLETIONS: &str = "completions";
+
+/// The hidden flag that prints a manual page: `<binary> --generate-man`.
+const GENERATE_MAN: &str = "--generate-man";
+
+/// The shells that completions can be generated for.
+const SHELLS: &str = "bash, elvish, fish, powershell, zsh";
+
+/// Handles the hidden `completions <shell>` and `--generate-man` invocations, printing
+/// the result to standard output. Returns whether `args` was one of them.
+///
+/// Exits with `usage_exit_code` if the requested shell is not supported.
+pub(crate) fn handle<O: CommandFactory>(args: &[String], usage_exit_code: i32) -> bool {
+    match args {
+        [_, flag] if flag == GENERATE_MAN => {
+            let page = manual(&command::<O>());
+            let _ = io::stdout().write_all(page.as_bytes());
+            true
+        }
+        [_, subcommand, shell] if subcommand == COMPLETIONS => match Shell::from_str(shell) {
+            Ok(shell) => {
+                let mut cmd = command::<O>();
+                let name = match shell {
+                    // PowerShell on Windows registers completers against the executable.
+                    Shell::PowerShell if cfg!(windows) => format!("{}.exe", cmd.get_name()),
+                    _ => cmd.get_name().to_owned(),
+                };
+                generate(shell, &mut cmd, name, &mut io::stdout());
+                true
+            }
+            Err(_) => {
+                eprintln!(
+                    "{}",
+                    i18n_embed_fl::fl!(
+                        crate::LANGUAGE_LOADER,
+                        "err-completions-shell",
+                        shell = shell.as_str(),
+                        shells = SHELLS
+                    )
+                );
+                std::process::exit(usage_exit_code);
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Returns the [`Command`] derived from the options `O`.
+fn command<O: CommandFactory>() -> Command<'static> {
+    let mut cmd = O::command();
+    cmd.build();
+    cmd
+}
+
+/// Renders a manual page in roff format.
+fn manual(cmd: &Command) -> String {
+    use man::prelude::{Arg, Author, Flag, Manual, Opt};
+
+    let mut manual =
+        Manual::new(cmd.get_name()).author(Author::new("Jack Grigg").email("thestr4d@gmail.com"));
+    if let Some(about) = cmd.get_about() {
+        manual = manual.about(about);
+    }
+
+    for arg in cmd.get_arguments() {
+        let value_name = arg
+            .get_value_names()
+            .and_then(|names| names.first().copied())
+            .map(str::to_owned)
+            .unwrap_or_else(|| arg.get_id().to_uppercase());
+
+        if arg.is_positional() {
+            manual = manual.arg(Arg::new(&value_name));
+            continue;
+        }
+
+        let short = arg.get_short().map(|short| format!("-{}", short));
+        let long = arg.get_long().map(|long| format!("--{}", long));
+        manual = if arg.is_takes_value_set() {
+            let mut option = Opt::new(&value_name);
+            if let Some(short) = &short {
+                option = option.short(short);
+            }
+            if let Some(long) = &long {
+                option = option.long(long);
+            }
+            if let Some(help) = arg.get_help() {
+                option = option.help(help);
+            }
+            manual.option(option)
+        } else {
+            let mut flag = Flag::new();
+            if let Some(short) = &short {
+                flag = flag.short(short);
+            }
+            if let Some(long) = &long {
+                flag = flag.long(long);
+            }
+            if let Some(help) = arg.get_help() {
+                flag = flag.help(help);
+            }
+            manual.flag(flag)
+        };
+    }
+
+    manual.render()
+}
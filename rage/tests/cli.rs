@@ -0,0 +1,1097 @@
+//! End-to-end tests that run the rage binaries.
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const IDENTITY: &str = "tests/fixtures/identity.txt";
+const RECIPIENT: &str = "age1t7rxyev2z3rw82stdlrrepyc39nvn86l5078zqkf5uasdy86jp6svpy7pa";
+const SSH_IDENTITY: &str = "tests/fixtures/ssh_ed25519";
+const SSH_RECIPIENTS: &str = "tests/fixtures/ssh_ed25519.pub";
+const PLAINTEXT: &str = "tests/fixtures/hello.txt";
+
+/// Exit code for errors reported by rage that aren't in one of the classes below.
+const ERROR: i32 = 1;
+/// Exit code for invalid command-line arguments.
+const USAGE_ERROR: i32 = 2;
+/// Exit code for inputs that aren't valid age files, or have been corrupted.
+const CORRUPTED: i32 = 4;
+/// Exit code for inputs that none of the identities can decrypt.
+const NO_MATCHING_IDENTITY: i32 = 5;
+/// Exit code for incorrect passphrases.
+const BAD_PASSPHRASE: i32 = 6;
+
+/// Returns a command for one of the rage binaries, isolated from the user's
+/// configuration.
+fn cmd(bin: &str, config_dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin(bin).unwrap();
+    cmd.env("HOME", config_dir)
+        .env("XDG_CONFIG_HOME", config_dir)
+        .env("LANG", "en_US.UTF-8")
+        .env_remove("LANGUAGE")
+        .env_remove("RAGE_PASSPHRASE")
+        .env_remove("AGEDEBUG");
+    cmd
+}
+
+fn rage(config_dir: &Path) -> Command {
+    cmd("rage", config_dir)
+}
+
+fn plaintext() -> Vec<u8> {
+    fs::read(PLAINTEXT).unwrap()
+}
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new("tests/fixtures").join(name)
+}
+
+#[test]
+fn encrypt_decrypt_pipe() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let encrypted = rage(dir.path())
+        .args(["-r", RECIPIENT])
+        .write_stdin(plaintext())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(encrypted.starts_with(b"age-encryption.org/v1\n"));
+
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY])
+        .write_stdin(encrypted)
+        .assert()
+        .success()
+        .stdout(plaintext());
+}
+
+#[test]
+fn encrypt_decrypt_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let encrypted = dir.path().join("hello.age");
+    let decrypted = dir.path().join("hello.txt");
+
+    rage(dir.path())
+        .args(["-R", SSH_RECIPIENTS, "-o"])
+        .arg(&encrypted)
+        .arg(PLAINTEXT)
+        .assert()
+        .success()
+        .stdout("");
+
+    rage(dir.path())
+        .args(["-d", "-i", SSH_IDENTITY, "-o"])
+        .arg(&decrypted)
+        .arg(&encrypted)
+        .assert()
+        .success();
+    assert_eq!(fs::read(&decrypted).unwrap(), plaintext());
+
+    // Refuse to overwrite the input.
+    rage(dir.path())
+        .args(["-r", RECIPIENT, "-o"])
+        .arg(&encrypted)
+        .arg(&encrypted)
+        .assert()
+        .code(USAGE_ERROR);
+}
+
+#[test]
+fn multiple_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.txt");
+    let b = dir.path().join("b.txt");
+    fs::copy(PLAINTEXT, &a).unwrap();
+    fs::write(&b, "Goodbye!\n").unwrap();
+
+    // Outputs are written next to the inputs by default.
+    rage(dir.path())
+        .args(["-r", RECIPIENT])
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("");
+    assert!(dir.path().join("a.txt.age").exists());
+    assert!(dir.path().join("b.txt.age").exists());
+
+    let out = dir.path().join("out");
+    fs::create_dir(&out).unwrap();
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY, "--output-dir"])
+        .arg(&out)
+        .arg(dir.path().join("a.txt.age"))
+        .arg(dir.path().join("b.txt.age"))
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), plaintext());
+    assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"Goodbye!\n");
+
+    // The same, with several files at once.
+    let jobs_out = dir.path().join("jobs");
+    fs::create_dir(&jobs_out).unwrap();
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY, "--jobs", "2", "--output-dir"])
+        .arg(&jobs_out)
+        .arg(dir.path().join("a.txt.age"))
+        .arg(dir.path().join("b.txt.age"))
+        .arg(fixture("hello.age"))
+        .assert()
+        .success();
+    assert_eq!(fs::read(jobs_out.join("a.txt")).unwrap(), plaintext());
+    assert_eq!(fs::read(jobs_out.join("b.txt")).unwrap(), b"Goodbye!\n");
+    assert_eq!(fs::read(jobs_out.join("hello")).unwrap(), plaintext());
+
+    rage(dir.path())
+        .args(["-r", RECIPIENT, "--jobs", "0"])
+        .arg(&a)
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("--jobs must be at least 1."));
+
+    // Errors name the file that failed.
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY, "--output-dir"])
+        .arg(&out)
+        .arg(dir.path().join("a.txt.age"))
+        .arg(fixture("passphrase.age"))
+        .env("RAGE_PASSPHRASE", "battery-staple")
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("passphrase.age: "));
+
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY])
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("doesn't end in .age"));
+
+    rage(dir.path())
+        .args(["-r", RECIPIENT, "-o", "out.age"])
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("--output-dir"));
+
+    rage(dir.path())
+        .args(["-r", RECIPIENT, "--output-dir"])
+        .arg(&out)
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("standard input"));
+}
+
+#[test]
+fn armor() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let encrypted = rage(dir.path())
+        .args(["-a", "-r", RECIPIENT])
+        .write_stdin(plaintext())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "-----BEGIN AGE ENCRYPTED FILE-----\n",
+        ))
+        .get_output()
+        .stdout
+        .clone();
+
+    // Armor is detected automatically.
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY])
+        .write_stdin(encrypted.clone())
+        .assert()
+        .success()
+        .stdout(plaintext());
+
+    rage(dir.path())
+        .args(["--check-armor"])
+        .write_stdin(encrypted.clone())
+        .assert()
+        .success();
+
+    // Mangle the armor, as an email client might.
+    let mangled = String::from_utf8(encrypted)
+        .unwrap()
+        .replacen('\n', "\n ", 2);
+    rage(dir.path())
+        .args(["--check-armor"])
+        .write_stdin(mangled)
+        .assert()
+        .code(CORRUPTED)
+        .stderr(predicate::str::contains("Invalid armor at line 2"));
+
+    // Armor can't be requested when decrypting.
+    rage(dir.path())
+        .args(["-d", "-a", "-i", IDENTITY, "-o", "-"])
+        .arg(fixture("hello.age.txt"))
+        .assert()
+        .code(USAGE_ERROR);
+}
+
+#[test]
+fn inspect() {
+    let dir = tempfile::tempdir().unwrap();
+
+    for (file, encoding) in [("hello.age", "binary"), ("hello.age.txt", "armored")] {
+        rage(dir.path())
+            .arg("--inspect")
+            .arg(fixture(file))
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!("Encoding: {}", encoding)))
+            .stdout(predicate::str::contains("  -> X25519 "))
+            .stdout(predicate::str::contains("Payload: 30 bytes in 1 chunk\n"))
+            .stdout(predicate::str::contains("Plaintext: 14 bytes"));
+    }
+
+    let output = rage(dir.path())
+        .args(["--inspect", "--json"])
+        .arg(fixture("hello.age.txt"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let details: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(details["version"], "v1");
+    assert_eq!(details["recipients"][0]["tag"], "X25519");
+    assert_eq!(details["passphrase"], false);
+    assert_eq!(details["armored"], true);
+    assert_eq!(details["chunk_count_hint"], 1);
+    assert_eq!(details["plaintext_len_hint"], 14);
+
+    // Inspecting doesn't decrypt, so it can't be combined with keys.
+    rage(dir.path())
+        .args(["--inspect", "-i", IDENTITY])
+        .arg(fixture("hello.age"))
+        .assert()
+        .code(USAGE_ERROR);
+    rage(dir.path())
+        .args(["--json", "-d", "-i", IDENTITY])
+        .arg(fixture("hello.age"))
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains(
+            "--json can only be used with --inspect",
+        ));
+}
+
+#[test]
+fn decrypt_fixtures() {
+    let dir = tempfile::tempdir().unwrap();
+
+    for (file, identity) in [
+        ("hello.age", IDENTITY),
+        ("hello.age", SSH_IDENTITY),
+        ("hello.age.txt", IDENTITY),
+    ] {
+        rage(dir.path())
+            .args(["-d", "-i", identity])
+            .arg(fixture(file))
+            .assert()
+            .success()
+            .stdout(plaintext());
+    }
+
+    // hello.age.txt was not encrypted to the SSH key.
+    rage(dir.path())
+        .args(["-d", "-i", SSH_IDENTITY])
+        .arg(fixture("hello.age.txt"))
+        .assert()
+        .code(NO_MATCHING_IDENTITY)
+        .stderr(predicate::str::contains("No matching keys found"));
+}
+
+#[test]
+fn verify_only() {
+    let dir = tempfile::tempdir().unwrap();
+
+    rage(dir.path())
+        .args(["--verify-only", "-i", IDENTITY])
+        .arg(fixture("hello.age"))
+        .arg(fixture("hello.age.txt"))
+        .assert()
+        .success()
+        .stdout("");
+
+    // Flip a bit in the last byte of the payload.
+    let mut corrupted = fs::read(fixture("hello.age")).unwrap();
+    *corrupted.last_mut().unwrap() ^= 1;
+    rage(dir.path())
+        .args(["-d", "--verify-only", "-i", IDENTITY])
+        .write_stdin(corrupted)
+        .assert()
+        .code(CORRUPTED)
+        .stdout("");
+
+    let out = dir.path().join("out.txt");
+    rage(dir.path())
+        .args(["--verify-only", "-i", IDENTITY, "-o"])
+        .arg(&out)
+        .arg(fixture("hello.age"))
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("doesn't write any output"));
+    assert!(!out.exists());
+}
+
+#[test]
+fn weak_ssh_keys() {
+    let dir = tempfile::tempdir().unwrap();
+
+    for args in [
+        ["-R", "tests/fixtures/ssh_dss.pub"],
+        ["-i", "tests/fixtures/ssh_dss"],
+    ] {
+        rage(dir.path())
+            .arg("-e")
+            .args(args)
+            .write_stdin(plaintext())
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Unsupported SSH DSA Key"));
+    }
+}
+
+#[test]
+fn default_identities() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("rage");
+    fs::create_dir(&config).unwrap();
+    fs::copy(IDENTITY, config.join("identities")).unwrap();
+
+    rage(dir.path())
+        .arg("-d")
+        .arg(fixture("hello.age"))
+        .assert()
+        .success()
+        .stdout(plaintext());
+
+    rage(dir.path())
+        .args(["-d", "--no-default-identities"])
+        .arg(fixture("hello.age"))
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("Missing identities."));
+}
+
+#[test]
+fn passphrase() {
+    let dir = tempfile::tempdir().unwrap();
+
+    rage(dir.path())
+        .arg("-d")
+        .arg(fixture("passphrase.age"))
+        .env("RAGE_PASSPHRASE", "correct-horse")
+        .assert()
+        .success()
+        .stdout(plaintext())
+        .stderr(predicate::str::contains("RAGE_PASSPHRASE"));
+
+    rage(dir.path())
+        .arg("-d")
+        .arg(fixture("passphrase.age"))
+        .env("RAGE_PASSPHRASE", "battery-staple")
+        .assert()
+        .code(BAD_PASSPHRASE)
+        .stderr(predicate::str::contains("Incorrect passphrase."));
+
+    // Encrypting standard input with a passphrase.
+    let encrypted = rage(dir.path())
+        .arg("-p")
+        .env("RAGE_PASSPHRASE", "correct-horse")
+        .write_stdin(plaintext())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    rage(dir.path())
+        .arg("-d")
+        .env("RAGE_PASSPHRASE", "correct-horse")
+        .write_stdin(encrypted)
+        .assert()
+        .success()
+        .stdout(plaintext());
+}
+
+#[test]
+fn usage_errors() {
+    let dir = tempfile::tempdir().unwrap();
+
+    rage(dir.path())
+        .arg("--not-a-flag")
+        .assert()
+        .code(USAGE_ERROR);
+
+    rage(dir.path())
+        .args(["-e", "-d"])
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("can't be used with"));
+
+    rage(dir.path())
+        .args(["-i", IDENTITY])
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR);
+
+    rage(dir.path())
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("Missing recipients."));
+
+    rage(dir.path())
+        .args(["-r", "age1invalid"])
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR);
+
+    rage(dir.path())
+        .args(["-r", RECIPIENT, "--passphrase-to-clipboard"])
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("require -p/--passphrase"));
+
+    rage(dir.path())
+        .args([
+            "-p",
+            "--passphrase-to-clipboard",
+            "--passphrase-sink",
+            "cat",
+        ])
+        .env("RAGE_PASSPHRASE", "correct-horse")
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR);
+
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY])
+        .write_stdin("not an age file")
+        .assert()
+        .code(CORRUPTED);
+}
+
+#[test]
+fn interactive() {
+    let dir = tempfile::tempdir().unwrap();
+
+    rage(dir.path())
+        .args(["-d", "--interactive", "-i", IDENTITY])
+        .arg(fixture("hello.age.txt"))
+        .assert()
+        .code(USAGE_ERROR);
+
+    // Without a terminal (or pinentry) to confirm on, nothing is encrypted.
+    rage(dir.path())
+        .env_remove("PATH")
+        .args(["--interactive", "-r", RECIPIENT])
+        .write_stdin(plaintext())
+        .assert()
+        .code(ERROR)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains(RECIPIENT))
+        .stderr(predicate::str::contains("from -r/--recipient"))
+        .stderr(predicate::str::contains("requires a terminal"));
+}
+
+#[test]
+fn rekey() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("hello.age");
+    fs::copy(fixture("hello.age.txt"), &file).unwrap();
+
+    rage(dir.path())
+        .args(["--rekey", "-i", IDENTITY, "-R", SSH_RECIPIENTS])
+        .arg(&file)
+        .assert()
+        .success();
+
+    // The armor is kept, and the file is now encrypted to the new recipient.
+    assert!(fs::read_to_string(&file)
+        .unwrap()
+        .starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+    rage(dir.path())
+        .args(["-d", "-i", SSH_IDENTITY])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout(plaintext());
+
+    // Passphrase-encrypted files can't be rekeyed.
+    let file = dir.path().join("passphrase.age");
+    fs::copy(fixture("passphrase.age"), &file).unwrap();
+    rage(dir.path())
+        .args(["--rekey", "-i", IDENTITY, "-R", SSH_RECIPIENTS])
+        .arg(&file)
+        .assert()
+        .code(ERROR)
+        .stderr(predicate::str::contains("passphrase-encrypted"));
+}
+
+#[test]
+fn keygen() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key.txt");
+
+    cmd("rage-keygen", dir.path())
+        .arg("-o")
+        .arg(&key)
+        .assert()
+        .success()
+        .stderr(predicate::str::starts_with("Public key: age1"));
+
+    let contents = fs::read_to_string(&key).unwrap();
+    let recipient = contents
+        .lines()
+        .find_map(|line| line.strip_prefix("# public key: "))
+        .unwrap()
+        .to_owned();
+
+    let encrypted = rage(dir.path())
+        .args(["-r", &recipient])
+        .write_stdin(plaintext())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    rage(dir.path())
+        .arg("-d")
+        .arg("-i")
+        .arg(&key)
+        .write_stdin(encrypted)
+        .assert()
+        .success()
+        .stdout(plaintext());
+}
+
+#[test]
+fn keygen_append() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("keys.txt");
+
+    for _ in 0..2 {
+        cmd("rage-keygen", dir.path())
+            .arg("--append")
+            .arg("-o")
+            .arg(&key)
+            .assert()
+            .success();
+    }
+
+    let contents = fs::read_to_string(&key).unwrap();
+    let recipients: Vec<_> = contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("# public key: "))
+        .collect();
+    assert_eq!(recipients.len(), 2);
+    assert_ne!(recipients[0], recipients[1]);
+
+    // Either identity in the file can decrypt.
+    for recipient in recipients {
+        let encrypted = rage(dir.path())
+            .args(["-r", recipient])
+            .write_stdin(plaintext())
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        rage(dir.path())
+            .arg("-d")
+            .arg("-i")
+            .arg(&key)
+            .write_stdin(encrypted)
+            .assert()
+            .success()
+            .stdout(plaintext());
+    }
+
+    cmd("rage-keygen", dir.path())
+        .arg("--append")
+        .assert()
+        .code(USAGE_ERROR);
+
+    // Only identity files can be appended to.
+    let other = dir.path().join("hello.txt");
+    fs::copy(fixture("hello.txt"), &other).unwrap();
+    cmd("rage-keygen", dir.path())
+        .arg("--append")
+        .arg("-o")
+        .arg(&other)
+        .assert()
+        .failure();
+    assert_eq!(fs::read(&other).unwrap(), plaintext());
+}
+
+#[cfg(unix)]
+#[test]
+fn output_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key.txt");
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    cmd("rage-keygen", dir.path())
+        .arg("-o")
+        .arg(&key)
+        .assert()
+        .success();
+    assert_eq!(mode(&key), 0o600);
+
+    // Replacing an existing file applies the mode to the new file.
+    cmd("rage-keygen", dir.path())
+        .args(["--mode", "400", "-o"])
+        .arg(&key)
+        .assert()
+        .success();
+    assert_eq!(mode(&key), 0o400);
+
+    let encrypted = dir.path().join("hello.txt.age");
+    rage(dir.path())
+        .args(["--mode", "0o640", "-r", RECIPIENT, "-o"])
+        .arg(&encrypted)
+        .write_stdin(plaintext())
+        .assert()
+        .success();
+    assert_eq!(mode(&encrypted), 0o640);
+
+    rage(dir.path())
+        .args(["--mode", "rw", "-r", RECIPIENT])
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("Invalid file mode"));
+
+    // No temporary files are left behind.
+    for entry in fs::read_dir(dir.path()).unwrap() {
+        let name = entry.unwrap().file_name();
+        assert!(!name.to_string_lossy().ends_with(".tmp"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn exposed_secrets() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let chmod = |path: &Path, mode| {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    };
+
+    // Identity files that other users can read are warned about.
+    let identity = dir.path().join("identity.txt");
+    fs::copy(IDENTITY, &identity).unwrap();
+    chmod(&identity, 0o644);
+    rage(dir.path())
+        .arg("-d")
+        .arg("-i")
+        .arg(&identity)
+        .arg(fixture("hello.age"))
+        .assert()
+        .success()
+        .stdout(plaintext())
+        .stderr(predicate::str::contains("can be read by other users"));
+
+    chmod(&identity, 0o600);
+    rage(dir.path())
+        .arg("-d")
+        .arg("-i")
+        .arg(&identity)
+        .arg(fixture("hello.age"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("can be read by other users").not());
+
+    // Plaintext isn't written where other users could read it without --force.
+    let shared = dir.path().join("shared");
+    fs::create_dir(&shared).unwrap();
+    chmod(&shared, 0o777);
+    let decrypted = shared.join("hello.txt");
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY, "--mode", "644", "-o"])
+        .arg(&decrypted)
+        .arg(fixture("hello.age"))
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("other users could read it"));
+    assert!(!decrypted.exists());
+
+    // Without --mode, the output is checked with the permissions it actually gets,
+    // which for a replaced file are its own.
+    fs::write(&decrypted, b"").unwrap();
+    chmod(&decrypted, 0o644);
+    rage(dir.path())
+        .args(["-d", "-i", IDENTITY, "-o"])
+        .arg(&decrypted)
+        .arg(fixture("hello.age"))
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("other users could read it"));
+    assert!(fs::read(&decrypted).unwrap().is_empty());
+
+    for flags in [
+        &["--mode", "600"][..],
+        &[][..],
+        &["--mode", "644", "--force"][..],
+    ] {
+        rage(dir.path())
+            .args(["-d", "-i", IDENTITY, "-o"])
+            .arg(&decrypted)
+            .args(flags)
+            .arg(fixture("hello.age"))
+            .assert()
+            .success();
+        assert_eq!(fs::read(&decrypted).unwrap(), plaintext());
+    }
+}
+
+#[test]
+fn keygen_mnemonic() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key.txt");
+    let recovered = dir.path().join("recovered.txt");
+
+    let stderr = cmd("rage-keygen", dir.path())
+        .arg("--mnemonic")
+        .arg("-o")
+        .arg(&key)
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(stderr).unwrap();
+    let phrase = stderr.lines().last().unwrap().trim();
+    assert_eq!(phrase.split(' ').count(), 24);
+
+    cmd("rage-keygen", dir.path())
+        .arg("--from-mnemonic")
+        .arg("-o")
+        .arg(&recovered)
+        .write_stdin(phrase)
+        .assert()
+        .success();
+
+    let identity = |path: &Path| {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+            .unwrap()
+            .to_owned()
+    };
+    assert_eq!(identity(&recovered), identity(&key));
+
+    cmd("rage-keygen", dir.path())
+        .args(["--from-mnemonic", "--format", "json"])
+        .write_stdin("legal winner thank")
+        .assert()
+        .code(ERROR)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains(
+            r#""code":"keygen-invalid-mnemonic""#,
+        ));
+}
+
+#[test]
+fn keygen_derive() {
+    let dir = tempfile::tempdir().unwrap();
+    let master = dir.path().join("master.txt");
+
+    cmd("rage-keygen", dir.path())
+        .arg("-o")
+        .arg(&master)
+        .assert()
+        .success();
+
+    let derive = |label: &str| {
+        let output = cmd("rage-keygen", dir.path())
+            .args(["--derive", label, "--master"])
+            .arg(&master)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("# created: "))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let backups = derive("backups-2024");
+    assert!(backups.contains("# derived with label: backups-2024"));
+    assert_eq!(derive("backups-2024"), backups);
+    assert_ne!(derive("work"), backups.replace("backups-2024", "work"));
+
+    cmd("rage-keygen", dir.path())
+        .args(["--derive", "backups-2024"])
+        .assert()
+        .code(USAGE_ERROR);
+}
+
+#[test]
+fn format_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let encrypted = dir.path().join("hello.age");
+    let report = |output: &assert_cmd::assert::Assert| -> serde_json::Value {
+        serde_json::from_slice(&output.get_output().stderr).unwrap()
+    };
+
+    let output = rage(dir.path())
+        .args(["--format", "json", "-r", RECIPIENT, "-o"])
+        .arg(&encrypted)
+        .arg(PLAINTEXT)
+        .assert()
+        .success();
+    let details = report(&output);
+    assert_eq!(details["status"], "ok");
+    assert_eq!(details["operation"], "encrypt");
+    assert_eq!(details["recipients"][0], RECIPIENT);
+    assert_eq!(details["files"][0]["input"], PLAINTEXT);
+    assert_eq!(details["files"][0]["output"], encrypted.to_str().unwrap());
+
+    // Errors have a stable code.
+    let output = rage(dir.path())
+        .args(["--format", "json", "-d", "--no-default-identities"])
+        .arg(&encrypted)
+        .assert()
+        .code(USAGE_ERROR);
+    let details = report(&output);
+    assert_eq!(details["status"], "error");
+    assert_eq!(details["error"]["code"], "dec-missing-identities");
+
+    let output = cmd("rage-keygen", dir.path())
+        .args(["--format", "json"])
+        .assert()
+        .success();
+    let details = report(&output);
+    assert_eq!(details["status"], "ok");
+    assert_eq!(details["output"], serde_json::Value::Null);
+    assert!(details["recipient"].as_str().unwrap().starts_with("age1"));
+
+    rage(dir.path())
+        .args(["--format", "yaml", "-r", RECIPIENT])
+        .arg(PLAINTEXT)
+        .assert()
+        .code(USAGE_ERROR);
+}
+
+#[test]
+fn lang_override() {
+    let dir = tempfile::tempdir().unwrap();
+
+    rage(dir.path())
+        .args(["--lang", "es-AR"])
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("No se encontraron destinatarios."));
+
+    rage(dir.path())
+        .args(["--lang", "not a language", "-r", RECIPIENT])
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR);
+}
+
+#[cfg(feature = "qr")]
+#[test]
+fn qr_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let is_qr = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| matches!(c, ' ' | '\u{2580}' | '\u{2584}' | '\u{2588}' | '\n'))
+    };
+
+    let code = rage(dir.path())
+        .args(["--qr", "-r", RECIPIENT])
+        .write_stdin(plaintext())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(is_qr(&String::from_utf8(code).unwrap()));
+
+    rage(dir.path())
+        .args(["--qr", "-r", RECIPIENT])
+        .write_stdin(vec![42; 4096])
+        .assert()
+        .code(ERROR)
+        .stderr(predicate::str::contains("too large to show as a QR code"));
+
+    rage(dir.path())
+        .args(["-d", "--qr", "-i", IDENTITY])
+        .write_stdin(plaintext())
+        .assert()
+        .code(USAGE_ERROR);
+
+    let stderr = cmd("rage-keygen", dir.path())
+        .arg("--qr")
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(stderr).unwrap();
+    let (pubkey, code) = stderr.split_once('\n').unwrap();
+    assert!(pubkey.starts_with("Public key: age1"));
+    assert!(is_qr(code));
+}
+
+#[cfg(feature = "mount")]
+#[test]
+fn mount_usage_errors() {
+    let dir = tempfile::tempdir().unwrap();
+
+    cmd("rage-mount", dir.path())
+        .args(["-t", "tar"])
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("Missing filename."));
+
+    cmd("rage-mount", dir.path())
+        .args(["-t", "tar", "archive.age"])
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("Missing mountpoint."));
+}
+
+#[cfg(feature = "cli-docs")]
+#[test]
+fn completions() {
+    let dir = tempfile::tempdir().unwrap();
+
+    for shell in ["bash", "elvish", "fish", "powershell", "zsh"] {
+        rage(dir.path())
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("recipients-file"));
+
+        cmd("rage-keygen", dir.path())
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("output"));
+    }
+
+    rage(dir.path())
+        .args(["completions", "tcsh"])
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("Unknown shell 'tcsh'"));
+}
+
+#[cfg(feature = "cli-docs")]
+#[test]
+fn generate_man() {
+    let dir = tempfile::tempdir().unwrap();
+
+    rage(dir.path())
+        .arg("--generate-man")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "A simple, secure, and modern encryption tool",
+        ))
+        .stdout(predicate::str::contains("RECIPIENT"))
+        .stdout(predicate::str::contains("May be repeated"));
+
+    cmd("rage-keygen", dir.path())
+        .arg("--generate-man")
+        .assert()
+        .success()
+        // roff escapes hyphens.
+        .stdout(predicate::str::contains(
+            r"Generate age\-compatible encryption key pairs",
+        ));
+}
+
+#[cfg(feature = "mount")]
+#[test]
+fn mount_list() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("sub").join("hello.txt"), plaintext()).unwrap();
+    let archive = dir.path().join("tree.tar.age");
+
+    rage(dir.path())
+        .args(["--archive", "-r", RECIPIENT, "-o"])
+        .arg(&archive)
+        .arg(&tree)
+        .assert()
+        .success();
+
+    cmd("rage-mount", dir.path())
+        .args(["--list", "-t", "tar", "-i", IDENTITY])
+        .arg(&archive)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hello.txt"));
+
+    cmd("rage-mount", dir.path())
+        .args(["--extract", "tree/sub/hello.txt"])
+        .args(["-t", "tar", "-i", IDENTITY])
+        .arg(&archive)
+        .assert()
+        .success()
+        .stdout(plaintext());
+
+    cmd("rage-mount", dir.path())
+        .args(["--extract", "missing.txt", "-t", "tar", "-i", IDENTITY])
+        .arg(&archive)
+        .assert()
+        .code(ERROR)
+        .stderr(predicate::str::contains("is not a file in the archive"));
+
+    cmd("rage-mount", dir.path())
+        .args(["--list", "-t", "tar"])
+        .assert()
+        .code(USAGE_ERROR)
+        .stderr(predicate::str::contains("Missing filename."));
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon_socket_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let shared = dir.path().join("shared");
+    fs::create_dir(&shared).unwrap();
+    fs::set_permissions(&shared, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // The socket isn't created where other users could reach it before it is chmodded.
+    let socket = shared.join("rage.sock");
+    rage(dir.path())
+        .arg("--daemon")
+        .arg(&socket)
+        .args(["-r", RECIPIENT])
+        .assert()
+        .code(ERROR)
+        .stderr(predicate::str::contains("other users can access it"));
+    assert!(!socket.exists());
+}